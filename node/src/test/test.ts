@@ -163,6 +163,30 @@ describe('LanceDB client', function () {
       assert.equal(await table.countRows(), 4)
     })
 
+    it('rejects vectors of different lengths', async function () {
+      const dir = await track().mkdir('lancejs')
+      const con = await lancedb.connect(dir)
+
+      const data = [
+        { id: 1, vector: [0.1, 0.2], price: 10 },
+        { id: 2, vector: [1.1, 1.2], price: 50 }
+      ]
+      const table = await con.createTable('vectors', data)
+
+      const ragged = [
+        { id: 3, vector: [2.1, 2.2], price: 10 },
+        { id: 4, vector: [3.1, 3.2, 3.3], price: 50 }
+      ]
+      await expect(con.createTable('ragged', ragged)).to.be.rejectedWith(Error, 'vector 1 has 3 dimensions, expected 2')
+
+      const wider = [
+        { id: 3, vector: [2.1, 2.2, 2.3], price: 10 },
+        { id: 4, vector: [3.1, 3.2, 3.3], price: 50 }
+      ]
+      await expect(table.add(wider)).to.be.rejectedWith(Error, 'has 2 dimensions, got 3')
+      assert.equal(await table.countRows(), 2)
+    })
+
     it('overwrite all records in a table', async function () {
       const uri = await createTestDB()
       const con = await lancedb.connect(uri)
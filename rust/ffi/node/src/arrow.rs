@@ -21,14 +21,26 @@ use arrow_array::{Array, FixedSizeListArray, RecordBatch};
 use arrow_ipc::reader::FileReader;
 use arrow_schema::{DataType, Field, Schema};
 use lance::arrow::{FixedSizeListArrayExt, RecordBatchExt};
+use vectordb::error::{Error, Result};
 
-pub(crate) fn convert_record_batch(record_batch: RecordBatch) -> RecordBatch {
+pub(crate) fn convert_record_batch(record_batch: RecordBatch) -> Result<RecordBatch> {
     let column = record_batch
         .column_by_name("vector")
         .expect("vector column is missing");
     let arr = as_list_array(column.deref());
     let list_size = arr.values().len() / record_batch.num_rows();
-    let r = FixedSizeListArray::try_new(arr.values(), list_size as i32).unwrap();
+    // Rows are converted from JS arrays, so every vector needs the width of the
+    // first; the width of the table's column is checked by the add.
+    let expected = arr.value_length(0);
+    if let Some(i) = (0..arr.len()).find(|&i| arr.value_length(i) != expected) {
+        return Err(Error::InvalidInput {
+            message: format!(
+                "vector {i} has {} dimensions, expected {expected}",
+                arr.value_length(i)
+            ),
+        });
+    }
+    let r = FixedSizeListArray::try_new(arr.values(), list_size as i32)?;
 
     let schema = Arc::new(Schema::new(vec![Field::new(
         "vector",
@@ -45,16 +57,49 @@ pub(crate) fn convert_record_batch(record_batch: RecordBatch) -> RecordBatch {
         let rb = record_batch.drop_column("vector").unwrap();
         new_batch = new_batch.merge(&rb).unwrap();
     }
-    new_batch
+    Ok(new_batch)
 }
 
-pub(crate) fn arrow_buffer_to_record_batch(slice: &[u8]) -> Vec<RecordBatch> {
+pub(crate) fn arrow_buffer_to_record_batch(slice: &[u8]) -> Result<Vec<RecordBatch>> {
     let mut batches: Vec<RecordBatch> = Vec::new();
     let fr = FileReader::try_new(Cursor::new(slice), None);
     let file_reader = fr.unwrap();
     for b in file_reader {
-        let record_batch = convert_record_batch(b.unwrap());
+        let record_batch = convert_record_batch(b.unwrap())?;
         batches.push(record_batch);
     }
-    batches
+    Ok(batches)
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow_array::types::Float32Type;
+    use arrow_array::{ArrayRef, ListArray};
+
+    use super::*;
+
+    fn vectors(rows: Vec<Vec<f32>>) -> RecordBatch {
+        let list = ListArray::from_iter_primitive::<Float32Type, _, _>(
+            rows.into_iter()
+                .map(|row| Some(row.into_iter().map(Some).collect::<Vec<_>>())),
+        );
+        RecordBatch::try_from_iter(vec![("vector", Arc::new(list) as ArrayRef)]).unwrap()
+    }
+
+    #[test]
+    fn test_convert_record_batch() {
+        let batch = convert_record_batch(vectors(vec![vec![0.1, 0.2], vec![1.1, 1.2]])).unwrap();
+        assert_eq!(
+            batch.schema().field(0).data_type(),
+            &DataType::FixedSizeList(Arc::new(Field::new("item", DataType::Float32, true)), 2)
+        );
+
+        let ragged = vectors(vec![vec![0.1, 0.2], vec![1.1, 1.2, 1.3]]);
+        let err = convert_record_batch(ragged).unwrap_err();
+        assert!(
+            matches!(err, Error::InvalidInput { ref message } if message == "vector 1 has 3 dimensions, expected 2"),
+            "{}",
+            err
+        );
+    }
 }
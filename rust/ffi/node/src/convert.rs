@@ -15,7 +15,7 @@
 use neon::prelude::*;
 
 pub(crate) fn vec_str_to_array<'a, C: Context<'a>>(
-    vec: &Vec<String>,
+    vec: &[String],
     cx: &mut C,
 ) -> JsResult<'a, JsArray> {
     let a = JsArray::new(cx, vec.len() as u32);
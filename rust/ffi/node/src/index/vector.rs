@@ -36,11 +36,7 @@ pub(crate) fn table_create_vector_index(mut cx: FunctionContext) -> JsResult<JsP
     let table = js_table.table.clone();
 
     rt.block_on(async move {
-        let add_result = table
-            .lock()
-            .unwrap()
-            .create_index(&index_params_builder)
-            .await;
+        let add_result = table.create_index(&index_params_builder).await;
 
         deferred.settle_with(&channel, move |mut cx| {
             add_result
@@ -65,22 +61,28 @@ fn get_index_params_builder(
             let mut index_builder: IvfPQIndexBuilder = IvfPQIndexBuilder::new();
             let mut pq_params = PQBuildParams::default();
 
-            obj.get_opt::<JsString, _, _>(cx, "column")
+            if let Some(s) = obj
+                .get_opt::<JsString, _, _>(cx, "column")
                 .map_err(|t| t.to_string())?
-                .map(|s| index_builder.column(s.value(cx)));
+            {
+                index_builder = index_builder.column(s.value(cx));
+            }
 
-            obj.get_opt::<JsString, _, _>(cx, "index_name")
+            if let Some(s) = obj
+                .get_opt::<JsString, _, _>(cx, "index_name")
                 .map_err(|t| t.to_string())?
-                .map(|s| index_builder.index_name(s.value(cx)));
+            {
+                index_builder = index_builder.index_name(s.value(cx));
+            }
 
-            obj.get_opt::<JsString, _, _>(cx, "metric_type")
+            if let Some(s) = obj
+                .get_opt::<JsString, _, _>(cx, "metric_type")
                 .map_err(|t| t.to_string())?
-                .map(|s| MetricType::try_from(s.value(cx).as_str()))
-                .map(|mt| {
-                    let metric_type = mt.unwrap();
-                    index_builder.metric_type(metric_type);
-                    pq_params.metric_type = metric_type;
-                });
+            {
+                let metric_type = MetricType::try_from(s.value(cx).as_str()).unwrap();
+                index_builder = index_builder.metric_type(metric_type);
+                pq_params.metric_type = metric_type;
+            }
 
             let num_partitions = obj
                 .get_opt::<JsNumber, _, _>(cx, "num_partitions")
@@ -92,39 +94,57 @@ fn get_index_params_builder(
                 .map_err(|t| t.to_string())?
                 .map(|s| s.value(cx) as usize);
 
-            num_partitions.map(|np| {
+            if let Some(np) = num_partitions {
                 let max_iters = max_iters.unwrap_or(50);
                 let ivf_params = IvfBuildParams {
                     num_partitions: np,
                     max_iters,
                     centroids: None,
                 };
-                index_builder.ivf_params(ivf_params)
-            });
+                index_builder = index_builder.ivf_params(ivf_params);
+            }
 
-            obj.get_opt::<JsBoolean, _, _>(cx, "use_opq")
+            if let Some(s) = obj
+                .get_opt::<JsBoolean, _, _>(cx, "use_opq")
                 .map_err(|t| t.to_string())?
-                .map(|s| pq_params.use_opq = s.value(cx));
+            {
+                pq_params.use_opq = s.value(cx);
+            }
 
-            obj.get_opt::<JsNumber, _, _>(cx, "num_sub_vectors")
+            if let Some(s) = obj
+                .get_opt::<JsNumber, _, _>(cx, "num_sub_vectors")
                 .map_err(|t| t.to_string())?
-                .map(|s| pq_params.num_sub_vectors = s.value(cx) as usize);
+            {
+                pq_params.num_sub_vectors = s.value(cx) as usize;
+            }
 
-            obj.get_opt::<JsNumber, _, _>(cx, "num_bits")
+            if let Some(s) = obj
+                .get_opt::<JsNumber, _, _>(cx, "num_bits")
                 .map_err(|t| t.to_string())?
-                .map(|s| pq_params.num_bits = s.value(cx) as usize);
+            {
+                pq_params.num_bits = s.value(cx) as usize;
+            }
 
-            obj.get_opt::<JsNumber, _, _>(cx, "max_iters")
+            if let Some(s) = obj
+                .get_opt::<JsNumber, _, _>(cx, "max_iters")
                 .map_err(|t| t.to_string())?
-                .map(|s| pq_params.max_iters = s.value(cx) as usize);
+            {
+                pq_params.max_iters = s.value(cx) as usize;
+            }
 
-            obj.get_opt::<JsNumber, _, _>(cx, "max_opq_iters")
+            if let Some(s) = obj
+                .get_opt::<JsNumber, _, _>(cx, "max_opq_iters")
                 .map_err(|t| t.to_string())?
-                .map(|s| pq_params.max_opq_iters = s.value(cx) as usize);
+            {
+                pq_params.max_opq_iters = s.value(cx) as usize;
+            }
 
-            obj.get_opt::<JsBoolean, _, _>(cx, "replace")
+            if let Some(s) = obj
+                .get_opt::<JsBoolean, _, _>(cx, "replace")
                 .map_err(|t| t.to_string())?
-                .map(|s| index_builder.replace(s.value(cx)));
+            {
+                index_builder = index_builder.replace(s.value(cx));
+            }
 
             Ok(index_builder)
         }
@@ -15,7 +15,7 @@
 use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::ops::Deref;
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
 
 use arrow_array::{Float32Array, RecordBatchReader};
 use arrow_ipc::writer::FileWriter;
@@ -28,9 +28,9 @@ use neon::types::buffer::TypedArray;
 use once_cell::sync::OnceCell;
 use tokio::runtime::Runtime;
 
-use vectordb::database::Database;
+use vectordb::database::{connect, Database};
 use vectordb::error::Error;
-use vectordb::table::Table;
+use vectordb::table::TableRef;
 
 use crate::arrow::arrow_buffer_to_record_batch;
 
@@ -45,7 +45,7 @@ struct JsDatabase {
 impl Finalize for JsDatabase {}
 
 struct JsTable {
-    table: Arc<Mutex<Table>>,
+    table: TableRef,
 }
 
 impl Finalize for JsTable {}
@@ -64,7 +64,7 @@ fn database_new(mut cx: FunctionContext) -> JsResult<JsPromise> {
     let (deferred, promise) = cx.promise();
 
     rt.spawn(async move {
-        let database = Database::connect(&path).await;
+        let database = connect(&path).create_dir(true).execute().await;
 
         deferred.settle_with(&channel, move |mut cx| {
             let db = JsDatabase {
@@ -113,9 +113,7 @@ fn database_open_table(mut cx: FunctionContext) -> JsResult<JsPromise> {
         let table_rst = database.open_table(&table_name).await;
 
         deferred.settle_with(&channel, move |mut cx| {
-            let table = Arc::new(Mutex::new(
-                table_rst.or_else(|err| cx.throw_error(err.to_string()))?,
-            ));
+            let table = table_rst.or_else(|err| cx.throw_error(err.to_string()))?;
             Ok(cx.boxed(JsTable { table }))
         });
     });
@@ -186,9 +184,7 @@ fn table_search(mut cx: FunctionContext) -> JsResult<JsPromise> {
 
     rt.spawn(async move {
         let builder = table
-            .lock()
-            .unwrap()
-            .search(Float32Array::from(query))
+            .search(Float32Array::from(query).into())
             .limit(limit as usize)
             .refine_factor(refine_factor)
             .nprobes(nprobes)
@@ -208,7 +204,7 @@ fn table_search(mut cx: FunctionContext) -> JsResult<JsPromise> {
                 return cx.buffer(0);
             }
 
-            let schema = results.get(0).unwrap().schema();
+            let schema = results.first().unwrap().schema();
             let mut fr = FileWriter::try_new(vector, schema.deref())
                 .or_else(|err| cx.throw_error(err.to_string()))?;
 
@@ -232,17 +228,20 @@ fn table_create(mut cx: FunctionContext) -> JsResult<JsPromise> {
         .downcast_or_throw::<JsBox<JsDatabase>, _>(&mut cx)?;
     let table_name = cx.argument::<JsString>(0)?.value(&mut cx);
     let buffer = cx.argument::<JsBuffer>(1)?;
-    let batches = arrow_buffer_to_record_batch(buffer.as_slice(&mut cx));
+    let batches = arrow_buffer_to_record_batch(buffer.as_slice(&cx))
+        .or_else(|err| cx.throw_error(err.to_string()))?;
 
     // Write mode
     let mode = match cx.argument::<JsString>(2)?.value(&mut cx).as_str() {
         "overwrite" => WriteMode::Overwrite,
         "append" => WriteMode::Append,
         "create" => WriteMode::Create,
-        _ => return cx.throw_error("Table::create only supports 'overwrite' and 'create' modes")
+        _ => return cx.throw_error("Table::create only supports 'overwrite' and 'create' modes"),
+    };
+    let params = WriteParams {
+        mode,
+        ..WriteParams::default()
     };
-    let mut params = WriteParams::default();
-    params.mode = mode;
 
     let rt = runtime(&mut cx)?;
     let channel = cx.channel();
@@ -252,12 +251,14 @@ fn table_create(mut cx: FunctionContext) -> JsResult<JsPromise> {
 
     rt.block_on(async move {
         let batch_reader: Box<dyn RecordBatchReader> = Box::new(RecordBatchBuffer::new(batches));
-        let table_rst = database.create_table(&table_name, batch_reader, Some(params)).await;
+        let table_rst = database
+            .create_table(&table_name, batch_reader)
+            .write_params(params)
+            .execute()
+            .await;
 
         deferred.settle_with(&channel, move |mut cx| {
-            let table = Arc::new(Mutex::new(
-                table_rst.or_else(|err| cx.throw_error(err.to_string()))?,
-            ));
+            let table = table_rst.or_else(|err| cx.throw_error(err.to_string()))?;
             Ok(cx.boxed(JsTable { table }))
         });
     });
@@ -274,7 +275,8 @@ fn table_add(mut cx: FunctionContext) -> JsResult<JsPromise> {
     let js_table = cx.this().downcast_or_throw::<JsBox<JsTable>, _>(&mut cx)?;
     let buffer = cx.argument::<JsBuffer>(0)?;
     let write_mode = cx.argument::<JsString>(1)?.value(&mut cx);
-    let batches = arrow_buffer_to_record_batch(buffer.as_slice(&mut cx));
+    let batches = arrow_buffer_to_record_batch(buffer.as_slice(&cx))
+        .or_else(|err| cx.throw_error(err.to_string()))?;
 
     let rt = runtime(&mut cx)?;
     let channel = cx.channel();
@@ -284,8 +286,9 @@ fn table_add(mut cx: FunctionContext) -> JsResult<JsPromise> {
     let write_mode = write_mode_map.get(write_mode.as_str()).cloned();
 
     rt.block_on(async move {
-        let batch_reader: Box<dyn RecordBatchReader> = Box::new(RecordBatchBuffer::new(batches));
-        let add_result = table.lock().unwrap().add(batch_reader, write_mode).await;
+        let batch_reader: Box<dyn RecordBatchReader + Send> =
+            Box::new(RecordBatchBuffer::new(batches));
+        let add_result = table.add(batch_reader, write_mode).await;
 
         deferred.settle_with(&channel, move |mut cx| {
             let added = add_result.or_else(|err| cx.throw_error(err.to_string()))?;
@@ -304,7 +307,7 @@ fn table_count_rows(mut cx: FunctionContext) -> JsResult<JsPromise> {
     let table = js_table.table.clone();
 
     rt.block_on(async move {
-        let num_rows_result = table.lock().unwrap().count_rows().await;
+        let num_rows_result = table.count_rows().await;
 
         deferred.settle_with(&channel, move |mut cx| {
             let num_rows = num_rows_result.or_else(|err| cx.throw_error(err.to_string()))?;
@@ -324,7 +327,7 @@ fn table_delete(mut cx: FunctionContext) -> JsResult<JsPromise> {
 
     let predicate = cx.argument::<JsString>(0)?.value(&mut cx);
 
-    let delete_result = rt.block_on(async move { table.lock().unwrap().delete(&predicate).await });
+    let delete_result = rt.block_on(async move { table.delete(&predicate).await });
 
     deferred.settle_with(&channel, move |mut cx| {
         delete_result.or_else(|err| cx.throw_error(err.to_string()))?;
@@ -0,0 +1,1036 @@
+// Copyright 2023 Lance Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Conversion of Rust values to Arrow data.
+//!
+//! Rows are Rust structs implementing [Serialize]. Each field becomes a column:
+//!
+//! * integers, floats, `bool` and strings map to the Arrow type of the same width,
+//! * `Vec<f32>` maps to a `FixedSizeList` of `Float32`, as vector columns are stored,
+//!   and other `Vec`s to a `List`,
+//! * nested structs map to a `Struct`,
+//! * `Option<T>` maps to a nullable column of the type of `T`.
+//!
+//! Half-precision vectors, of [f16] values, are built with
+//! [vec_to_fixed_size_list_f16] and [RecordBatchBuilder::vector_column_f16], and
+//! multivector columns, of any number of vectors per row, with [vec_to_multivector]
+//! and [RecordBatchBuilder::multivector_column].
+//!
+//! With the `ndarray` feature, `ndarray_to_fixed_size_list` converts the rows
+//! of a matrix to vectors.
+//!
+//! Metadata without a fixed schema is stored as JSON documents in a string
+//! column, built from [serde_json::Value]s with [json_array] and
+//! [RecordBatchBuilder::json_column], and read back with [parse_json]. Filters
+//! select rows by the values in the documents with `json_extract`, see
+//! [crate::query::Query::filter].
+//!
+//! When the rows are written to a table, the types of the table's columns are
+//! used instead, and the values are checked against them.
+//!
+//! [batch_to_rows] reads rows back, from the batches of a table or of a search,
+//! as [serde::de::DeserializeOwned] structs.
+
+mod json;
+mod rows;
+mod value;
+
+use std::sync::Arc;
+
+use arrow_array::types::{
+    Float16Type, Float32Type, Float64Type, Int16Type, Int32Type, Int64Type, Int8Type, UInt16Type,
+    UInt32Type, UInt64Type, UInt8Type,
+};
+use arrow_array::{
+    make_array, Array, ArrayRef, ArrowPrimitiveType, BooleanArray, FixedSizeListArray,
+    LargeStringArray, ListArray, NullArray, PrimitiveArray, RecordBatch, RecordBatchIterator,
+    RecordBatchReader, StringArray,
+};
+use arrow_buffer::Buffer;
+use arrow_data::{ArrayData, ArrayDataBuilder};
+use arrow_schema::{DataType, Field, Fields, Schema, SchemaRef};
+use half::f16;
+use serde::Serialize;
+
+pub(crate) use self::json::{documents, JsonPath};
+pub use self::json::{json_array, parse_json};
+pub use self::rows::batch_to_rows;
+use self::value::{to_value, Value};
+use crate::error::{Error, Result};
+
+/// Data that can be written to a table.
+pub trait IntoArrow {
+    /// Convert to record batches.
+    ///
+    /// `schema` is the schema of the table the data is written to, if there is
+    /// one. Data that is converted from Rust values takes the column types from
+    /// it; the columns of `schema` that the data does not have are left out.
+    fn into_arrow(self, schema: Option<SchemaRef>) -> Result<Box<dyn RecordBatchReader>>;
+}
+
+impl IntoArrow for Box<dyn RecordBatchReader> {
+    fn into_arrow(self, _schema: Option<SchemaRef>) -> Result<Box<dyn RecordBatchReader>> {
+        Ok(self)
+    }
+}
+
+impl IntoArrow for RecordBatch {
+    fn into_arrow(self, _schema: Option<SchemaRef>) -> Result<Box<dyn RecordBatchReader>> {
+        let schema = self.schema();
+        Ok(Box::new(RecordBatchIterator::new(vec![Ok(self)], schema)))
+    }
+}
+
+impl<T: Serialize> IntoArrow for Vec<T> {
+    fn into_arrow(self, schema: Option<SchemaRef>) -> Result<Box<dyn RecordBatchReader>> {
+        rows_to_batch(self, schema.as_deref())?.into_arrow(None)
+    }
+}
+
+/// Convert `rows` to a record batch, see the [module documentation](self).
+///
+/// If `schema` is given the columns of the batch have its types, and the
+/// columns of `schema` that the rows do not have are left out. Values that do
+/// not fit `schema` fail with an [Error::Schema] listing every such column.
+pub fn rows_to_batch<T: Serialize>(
+    rows: impl IntoIterator<Item = T>,
+    schema: Option<&Schema>,
+) -> Result<RecordBatch> {
+    let mut columns: Vec<String> = Vec::new();
+    let mut values = Vec::new();
+    for row in rows {
+        let row = to_value(&row).map_err(|e| Error::InvalidInput { message: e.0 })?;
+        let Value::Struct(fields) = row else {
+            return Err(Error::InvalidInput {
+                message: format!("rows must be structs, got a {} value", row.kind()),
+            });
+        };
+        for (name, _) in fields.iter() {
+            if !columns.contains(name) {
+                columns.push(name.clone());
+            }
+        }
+        values.push(fields);
+    }
+    let column = |name: &str| -> Vec<Option<&Value>> {
+        values
+            .iter()
+            .map(|row| row.iter().find(|(n, _)| n == name).map(|(_, v)| v))
+            .collect()
+    };
+
+    let schema = match schema {
+        Some(schema) => {
+            let mut errors: Vec<String> = columns
+                .iter()
+                .filter(|c| schema.field_with_name(c).is_err())
+                .map(|c| format!("the table has no column '{c}'"))
+                .collect();
+            let fields = schema
+                .fields()
+                .iter()
+                .filter(|f| columns.contains(f.name()))
+                .cloned()
+                .collect::<Vec<_>>();
+            let mut arrays = Vec::new();
+            for field in fields.iter() {
+                match build_array(field.name(), &column(field.name()), field) {
+                    Ok(array) => arrays.push(array),
+                    Err(Error::Schema { message }) => errors.push(message),
+                    Err(e) => return Err(e),
+                }
+            }
+            if !errors.is_empty() {
+                return Err(Error::Schema {
+                    message: errors.join("; "),
+                });
+            }
+            return batch(Schema::new(fields), arrays);
+        }
+        None => {
+            if values.is_empty() {
+                return Err(Error::InvalidInput {
+                    message: "cannot infer a schema without rows".to_string(),
+                });
+            }
+            Schema::new(
+                columns
+                    .iter()
+                    .map(|name| infer_field(name, &column(name)))
+                    .collect::<Result<Vec<_>>>()?,
+            )
+        }
+    };
+    let arrays = schema
+        .fields()
+        .iter()
+        .map(|field| build_array(field.name(), &column(field.name()), field))
+        .collect::<Result<Vec<_>>>()?;
+    batch(schema, arrays)
+}
+
+fn batch(schema: Schema, arrays: Vec<ArrayRef>) -> Result<RecordBatch> {
+    Ok(RecordBatch::try_new(Arc::new(schema), arrays).map_err(lance::Error::from)?)
+}
+
+fn schema_error(message: String) -> Error {
+    Error::Schema { message }
+}
+
+/// The field of column `name` from its `values`, `None` where a row has no such field.
+fn infer_field(name: &str, values: &[Option<&Value>]) -> Result<Field> {
+    let nullable = values.iter().any(|v| v.is_none_or(Value::is_optional));
+    let present: Vec<&Value> = values
+        .iter()
+        .filter_map(|v| v.and_then(Value::get))
+        .collect();
+    let Some(first) = present.first() else {
+        return Err(schema_error(format!(
+            "cannot infer the type of column '{name}', it has no values"
+        )));
+    };
+    if let Some(other) = present
+        .iter()
+        .find(|v| std::mem::discriminant(**v) != std::mem::discriminant(*first))
+    {
+        return Err(schema_error(format!(
+            "column '{name}' has values of different types, {} and {}",
+            first.kind(),
+            other.kind()
+        )));
+    }
+    let data_type = match first {
+        Value::Bool(_) => DataType::Boolean,
+        Value::Int(_, data_type) => data_type.clone(),
+        Value::Float32(_) => DataType::Float32,
+        Value::Float64(_) => DataType::Float64,
+        Value::Str(_) => DataType::Utf8,
+        Value::List(first_items) => {
+            let items: Vec<Option<&Value>> = present
+                .iter()
+                .flat_map(|v| match v {
+                    Value::List(items) => items.iter().map(Some).collect(),
+                    _ => Vec::new(),
+                })
+                .collect();
+            let item = infer_field("item", &items).map_err(|_| {
+                schema_error(format!("cannot infer the item type of column '{name}'"))
+            })?;
+            if item.data_type() == &DataType::Float32 {
+                DataType::FixedSizeList(
+                    Arc::new(Field::new("item", DataType::Float32, true)),
+                    first_items.len() as i32,
+                )
+            } else {
+                DataType::List(Arc::new(item))
+            }
+        }
+        Value::Struct(_) => {
+            let mut names: Vec<&str> = Vec::new();
+            for value in present.iter() {
+                if let Value::Struct(fields) = value {
+                    for (n, _) in fields.iter() {
+                        if !names.contains(&n.as_str()) {
+                            names.push(n);
+                        }
+                    }
+                }
+            }
+            let children = names
+                .iter()
+                .map(|child| infer_field(child, &struct_field(&present_all(values), child)))
+                .collect::<Result<Vec<_>>>()?;
+            DataType::Struct(Fields::from(children))
+        }
+        Value::Null | Value::Opt(_) => unreachable!("null values are filtered out"),
+    };
+    Ok(Field::new(name, data_type, nullable))
+}
+
+/// The non-null values of `values`, `None` for the others.
+fn present_all<'a>(values: &[Option<&'a Value>]) -> Vec<Option<&'a Value>> {
+    values.iter().map(|v| v.and_then(Value::get)).collect()
+}
+
+/// The values of the field `name` of struct `values`.
+fn struct_field<'a>(values: &[Option<&'a Value>], name: &str) -> Vec<Option<&'a Value>> {
+    values
+        .iter()
+        .map(|v| match v {
+            Some(Value::Struct(fields)) => fields.iter().find(|(n, _)| n == name).map(|(_, v)| v),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Build the array of column `name`, of the type of `field`, from `values`.
+fn build_array(name: &str, values: &[Option<&Value>], field: &Field) -> Result<ArrayRef> {
+    let values = present_all(values);
+    if !field.is_nullable() && values.iter().any(Option::is_none) {
+        return Err(schema_error(format!(
+            "column '{name}' is not nullable, but has null values"
+        )));
+    }
+    let data_type = field.data_type();
+    let mismatch = |value: &Value| {
+        schema_error(format!(
+            "column '{name}' has type {data_type}, got a {} value",
+            value.kind()
+        ))
+    };
+    let validity = || values.iter().map(Option::is_some).collect::<Buffer>();
+    let array: ArrayRef = match data_type {
+        DataType::Null => Arc::new(NullArray::new(values.len())),
+        DataType::Boolean => Arc::new(BooleanArray::from(
+            values
+                .iter()
+                .map(|v| {
+                    v.map(|v| match v {
+                        Value::Bool(b) => Ok(*b),
+                        v => Err(mismatch(v)),
+                    })
+                    .transpose()
+                })
+                .collect::<Result<Vec<_>>>()?,
+        )),
+        DataType::Int8 => int_array::<Int8Type>(name, &values, data_type, mismatch)?,
+        DataType::Int16 => int_array::<Int16Type>(name, &values, data_type, mismatch)?,
+        DataType::Int32 => int_array::<Int32Type>(name, &values, data_type, mismatch)?,
+        DataType::Int64 => int_array::<Int64Type>(name, &values, data_type, mismatch)?,
+        DataType::UInt8 => int_array::<UInt8Type>(name, &values, data_type, mismatch)?,
+        DataType::UInt16 => int_array::<UInt16Type>(name, &values, data_type, mismatch)?,
+        DataType::UInt32 => int_array::<UInt32Type>(name, &values, data_type, mismatch)?,
+        DataType::UInt64 => int_array::<UInt64Type>(name, &values, data_type, mismatch)?,
+        DataType::Float32 => {
+            primitive_array::<Float32Type>(&values, |v| float(v).map(|f| f as f32), mismatch)?
+        }
+        DataType::Float16 => {
+            primitive_array::<Float16Type>(&values, |v| float(v).map(f16::from_f64), mismatch)?
+        }
+        DataType::Float64 => primitive_array::<Float64Type>(&values, float, mismatch)?,
+        DataType::Utf8 => Arc::new(StringArray::from(strings(&values, mismatch)?)),
+        DataType::LargeUtf8 => Arc::new(LargeStringArray::from(strings(&values, mismatch)?)),
+        DataType::FixedSizeList(item, width) => {
+            let width = *width as usize;
+            let mut children = Vec::with_capacity(values.len() * width);
+            for value in values.iter() {
+                match value {
+                    None => children.extend(std::iter::repeat_n(None, width)),
+                    Some(Value::List(items)) if items.len() == width => {
+                        children.extend(items.iter().map(Some))
+                    }
+                    Some(Value::List(items)) => {
+                        return Err(Error::EmbeddingDimensionMismatch {
+                            column: name.to_string(),
+                            expected: width,
+                            got: items.len(),
+                        })
+                    }
+                    Some(v) => return Err(mismatch(v)),
+                }
+            }
+            let child = build_array(name, &children, item)?;
+            nested_array(
+                ArrayDataBuilder::new(data_type.clone())
+                    .len(values.len())
+                    .add_child_data(child.to_data())
+                    .null_bit_buffer(Some(validity())),
+            )?
+        }
+        DataType::List(item) => {
+            let mut offsets = vec![0_i32];
+            let mut children = Vec::new();
+            for value in values.iter() {
+                match value {
+                    None => {}
+                    Some(Value::List(items)) => children.extend(items.iter().map(Some)),
+                    Some(v) => return Err(mismatch(v)),
+                }
+                offsets.push(children.len() as i32);
+            }
+            let child = build_array(name, &children, item)?;
+            nested_array(
+                ArrayDataBuilder::new(data_type.clone())
+                    .len(values.len())
+                    .add_buffer(Buffer::from_vec(offsets))
+                    .add_child_data(child.to_data())
+                    .null_bit_buffer(Some(validity())),
+            )?
+        }
+        DataType::Struct(fields) => {
+            if let Some(v) = values
+                .iter()
+                .flatten()
+                .find(|v| !matches!(v, Value::Struct(_)))
+            {
+                return Err(mismatch(v));
+            }
+            let children = fields
+                .iter()
+                .map(|field| {
+                    let child = format!("{name}.{}", field.name());
+                    let child_values: Vec<Option<&Value>> = struct_field(&values, field.name())
+                        .into_iter()
+                        .zip(values.iter())
+                        // The fields of null structs are null, whatever their type.
+                        .map(|(v, parent)| {
+                            if parent.is_some() {
+                                v
+                            } else {
+                                Some(&Value::Null)
+                            }
+                        })
+                        .collect();
+                    build_array(&child, &child_values, field).map(|a| a.to_data())
+                })
+                .collect::<Result<Vec<ArrayData>>>()?;
+            nested_array(
+                ArrayDataBuilder::new(data_type.clone())
+                    .len(values.len())
+                    .child_data(children)
+                    .null_bit_buffer(Some(validity())),
+            )?
+        }
+        data_type => {
+            return Err(schema_error(format!(
+                "column '{name}' has the unsupported type {data_type}"
+            )))
+        }
+    };
+    Ok(array)
+}
+
+fn nested_array(builder: ArrayDataBuilder) -> Result<ArrayRef> {
+    Ok(make_array(builder.build().map_err(lance::Error::from)?))
+}
+
+fn float(value: &Value) -> Option<f64> {
+    match value {
+        Value::Float32(f) => Some(*f as f64),
+        Value::Float64(f) => Some(*f),
+        Value::Int(i, _) => Some(*i as f64),
+        _ => None,
+    }
+}
+
+fn strings<'a>(
+    values: &[Option<&'a Value>],
+    mismatch: impl Fn(&Value) -> Error,
+) -> Result<Vec<Option<&'a str>>> {
+    values
+        .iter()
+        .map(|v| {
+            v.map(|v| match v {
+                Value::Str(s) => Ok(s.as_str()),
+                v => Err(mismatch(v)),
+            })
+            .transpose()
+        })
+        .collect()
+}
+
+fn primitive_array<T: ArrowPrimitiveType>(
+    values: &[Option<&Value>],
+    convert: impl Fn(&Value) -> Option<T::Native>,
+    mismatch: impl Fn(&Value) -> Error,
+) -> Result<ArrayRef> {
+    let values = values
+        .iter()
+        .map(|v| v.map(|v| convert(v).ok_or_else(|| mismatch(v))).transpose())
+        .collect::<Result<Vec<_>>>()?;
+    Ok(Arc::new(values.into_iter().collect::<PrimitiveArray<T>>()))
+}
+
+fn int_array<T: ArrowPrimitiveType>(
+    name: &str,
+    values: &[Option<&Value>],
+    data_type: &DataType,
+    mismatch: impl Fn(&Value) -> Error,
+) -> Result<ArrayRef>
+where
+    T::Native: TryFrom<i128>,
+{
+    if let Some(Value::Int(i, _)) = values
+        .iter()
+        .flatten()
+        .find(|v| matches!(v, Value::Int(i, _) if T::Native::try_from(*i).is_err()))
+    {
+        return Err(schema_error(format!(
+            "column '{name}' has type {data_type}, got the out of range value {i}"
+        )));
+    }
+    primitive_array::<T>(
+        values,
+        |v| match v {
+            Value::Int(i, _) => T::Native::try_from(*i).ok(),
+            _ => None,
+        },
+        mismatch,
+    )
+}
+
+/// An item of the vectors passed to [vec_to_fixed_size_list]: a vector of `T`
+/// values, or an [Option] that is `None` for a null vector.
+pub trait VectorLike<T = f32> {
+    fn as_vector(&self) -> Option<&[T]>;
+}
+
+impl<T> VectorLike<T> for Vec<T> {
+    fn as_vector(&self) -> Option<&[T]> {
+        Some(self)
+    }
+}
+
+impl<T> VectorLike<T> for &[T] {
+    fn as_vector(&self) -> Option<&[T]> {
+        Some(self)
+    }
+}
+
+impl<T, const N: usize> VectorLike<T> for [T; N] {
+    fn as_vector(&self) -> Option<&[T]> {
+        Some(self)
+    }
+}
+
+impl<T, V: VectorLike<T>> VectorLike<T> for Option<V> {
+    fn as_vector(&self) -> Option<&[T]> {
+        self.as_ref().and_then(V::as_vector)
+    }
+}
+
+/// Convert `vectors`, of `dims` values each, to a vector column. `None` items
+/// are null vectors.
+///
+/// ```
+/// # use vectordb::arrow::vec_to_fixed_size_list;
+/// let vectors = vec_to_fixed_size_list(2, vec![Some(vec![1.0, 2.0]), None]).unwrap();
+/// assert_eq!(vectors.value_length(), 2);
+/// ```
+pub fn vec_to_fixed_size_list(
+    dims: i32,
+    vectors: impl IntoIterator<Item = impl VectorLike>,
+) -> Result<FixedSizeListArray> {
+    fixed_size_list::<Float32Type>(None, dims, vectors)
+}
+
+/// Convert half-precision `vectors` to a vector column of `Float16` values,
+/// see [vec_to_fixed_size_list].
+pub fn vec_to_fixed_size_list_f16(
+    dims: i32,
+    vectors: impl IntoIterator<Item = impl VectorLike<f16>>,
+) -> Result<FixedSizeListArray> {
+    fixed_size_list::<Float16Type>(None, dims, vectors)
+}
+
+/// Convert the rows of `matrix` to a vector column of as many dimensions as
+/// the matrix has columns. The values are copied once, in one piece if the
+/// matrix is in row-major order.
+///
+/// ```
+/// # use arrow_array::Array;
+/// # use vectordb::arrow::ndarray_to_fixed_size_list;
+/// let matrix = ndarray::array![[1.0, 2.0], [3.0, 4.0], [5.0, 6.0]];
+/// let vectors = ndarray_to_fixed_size_list(matrix.view()).unwrap();
+/// assert_eq!((vectors.len(), vectors.value_length()), (3, 2));
+/// ```
+#[cfg(feature = "ndarray")]
+pub fn ndarray_to_fixed_size_list(matrix: ndarray::ArrayView2<f32>) -> Result<FixedSizeListArray> {
+    let dims = matrix.ncols();
+    if dims == 0 {
+        return Err(Error::InvalidInput {
+            message: "vectors must have at least one dimension, got 0".to_string(),
+        });
+    }
+    let values = match matrix.as_slice() {
+        Some(values) => PrimitiveArray::<Float32Type>::from(values.to_vec()),
+        None => PrimitiveArray::<Float32Type>::from_iter_values(matrix.iter().copied()),
+    };
+    let data = ArrayDataBuilder::new(DataType::FixedSizeList(
+        Arc::new(Field::new("item", DataType::Float32, true)),
+        dims as i32,
+    ))
+    .len(matrix.nrows())
+    .add_child_data(values.into_data())
+    .build()
+    .map_err(lance::Error::from)?;
+    Ok(FixedSizeListArray::from(data))
+}
+
+/// Convert `rows` of any number of vectors, of `dims` values each, to a
+/// multivector column: a `List` of the vectors of each row.
+///
+/// ```
+/// # use vectordb::arrow::vec_to_multivector;
+/// let rows = vec_to_multivector(2, vec![vec![vec![1.0, 2.0], vec![3.0, 4.0]], vec![]]).unwrap();
+/// assert_eq!(rows.value_length(0), 2);
+/// assert_eq!(rows.value_length(1), 0);
+/// ```
+pub fn vec_to_multivector<V: VectorLike>(
+    dims: i32,
+    rows: impl IntoIterator<Item = impl AsRef<[V]>>,
+) -> Result<ListArray> {
+    multivector(None, dims, rows)
+}
+
+/// The multivector column `column` of `rows`, see [vec_to_multivector].
+fn multivector<V: VectorLike>(
+    column: Option<&str>,
+    dims: i32,
+    rows: impl IntoIterator<Item = impl AsRef<[V]>>,
+) -> Result<ListArray> {
+    let rows = rows.into_iter().collect::<Vec<_>>();
+    let mut offsets = vec![0_i32];
+    let mut vectors = Vec::new();
+    for (row, vectors_of_row) in rows.iter().enumerate() {
+        for vector in vectors_of_row.as_ref() {
+            let vector = vector.as_vector();
+            match (vector, column) {
+                (Some(vector), None) if dims > 0 && vector.len() != dims as usize => {
+                    return Err(Error::InvalidInput {
+                        message: format!(
+                            "a vector of row {row} has {} dimensions, expected {dims}",
+                            vector.len()
+                        ),
+                    })
+                }
+                _ => vectors.push(vector),
+            }
+        }
+        offsets.push(vectors.len() as i32);
+    }
+    let vectors = fixed_size_list::<Float32Type>(column, dims, vectors)?;
+    let data = ArrayDataBuilder::new(DataType::List(Arc::new(Field::new(
+        "item",
+        vectors.data_type().clone(),
+        true,
+    ))))
+    .len(rows.len())
+    .add_buffer(Buffer::from_vec(offsets))
+    .add_child_data(vectors.into_data())
+    .build()
+    .map_err(lance::Error::from)?;
+    Ok(ListArray::from(data))
+}
+
+/// The vector column `column` of `vectors`, see [vec_to_fixed_size_list].
+fn fixed_size_list<T: ArrowPrimitiveType>(
+    column: Option<&str>,
+    dims: i32,
+    vectors: impl IntoIterator<Item = impl VectorLike<T::Native>>,
+) -> Result<FixedSizeListArray> {
+    if dims <= 0 {
+        return Err(Error::InvalidInput {
+            message: format!("vectors must have at least one dimension, got {dims}"),
+        });
+    }
+    let width = dims as usize;
+    let mut values = Vec::new();
+    let mut validity = Vec::new();
+    for (row, vector) in vectors.into_iter().enumerate() {
+        let vector = vector.as_vector();
+        match vector {
+            Some(vector) if vector.len() != width => {
+                return Err(match column {
+                    Some(column) => Error::EmbeddingDimensionMismatch {
+                        column: column.to_string(),
+                        expected: width,
+                        got: vector.len(),
+                    },
+                    None => Error::InvalidInput {
+                        message: format!(
+                            "vector {row} has {} dimensions, expected {dims}",
+                            vector.len()
+                        ),
+                    },
+                })
+            }
+            Some(vector) => values.extend_from_slice(vector),
+            None => values.extend(std::iter::repeat_n(T::Native::default(), width)),
+        }
+        validity.push(vector.is_some());
+    }
+    let nulls = validity.contains(&false);
+    let data = ArrayDataBuilder::new(DataType::FixedSizeList(
+        Arc::new(Field::new("item", T::DATA_TYPE, true)),
+        dims,
+    ))
+    .len(validity.len())
+    .add_child_data(PrimitiveArray::<T>::from_iter_values(values).into_data())
+    .null_bit_buffer(nulls.then(|| validity.into_iter().collect::<Buffer>()))
+    .build()
+    .map_err(lance::Error::from)?;
+    Ok(FixedSizeListArray::from(data))
+}
+
+/// Builds a [RecordBatch] column by column.
+///
+/// ```
+/// # use std::sync::Arc;
+/// # use arrow_array::Int32Array;
+/// # use vectordb::arrow::RecordBatchBuilder;
+/// let batch = RecordBatchBuilder::new()
+///     .column("id", Arc::new(Int32Array::from(vec![1, 2])))
+///     .vector_column("embedding", 3, vec![vec![1.0, 2.0, 3.0], vec![4.0, 5.0, 6.0]])
+///     .build()
+///     .unwrap();
+/// assert_eq!(batch.num_rows(), 2);
+/// ```
+#[derive(Default)]
+pub struct RecordBatchBuilder {
+    fields: Vec<Field>,
+    columns: Vec<ArrayRef>,
+    error: Option<Error>,
+}
+
+impl RecordBatchBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the nullable column `name`.
+    pub fn column(mut self, name: &str, array: ArrayRef) -> Self {
+        self.fields
+            .push(Field::new(name, array.data_type().clone(), true));
+        self.columns.push(array);
+        self
+    }
+
+    /// Add the nullable vector column `name` of `vectors`, see [vec_to_fixed_size_list].
+    ///
+    /// [RecordBatchBuilder::build] fails with [Error::EmbeddingDimensionMismatch]
+    /// if a vector is not `dims` long.
+    pub fn vector_column(
+        self,
+        name: &str,
+        dims: i32,
+        vectors: impl IntoIterator<Item = impl VectorLike>,
+    ) -> Self {
+        match fixed_size_list::<Float32Type>(Some(name), dims, vectors) {
+            Ok(array) => self.column(name, Arc::new(array)),
+            Err(e) => self.fail(e),
+        }
+    }
+
+    /// Add the multivector column `name` of `rows`, see [vec_to_multivector].
+    ///
+    /// [RecordBatchBuilder::build] fails with [Error::EmbeddingDimensionMismatch]
+    /// if a vector is not `dims` long.
+    pub fn multivector_column<V: VectorLike>(
+        self,
+        name: &str,
+        dims: i32,
+        rows: impl IntoIterator<Item = impl AsRef<[V]>>,
+    ) -> Self {
+        match multivector(Some(name), dims, rows) {
+            Ok(array) => self.column(name, Arc::new(array)),
+            Err(e) => self.fail(e),
+        }
+    }
+
+    /// Add the nullable vector column `name` of half-precision `vectors`, see
+    /// [RecordBatchBuilder::vector_column].
+    pub fn vector_column_f16(
+        self,
+        name: &str,
+        dims: i32,
+        vectors: impl IntoIterator<Item = impl VectorLike<f16>>,
+    ) -> Self {
+        match fixed_size_list::<Float16Type>(Some(name), dims, vectors) {
+            Ok(array) => self.column(name, Arc::new(array)),
+            Err(e) => self.fail(e),
+        }
+    }
+
+    /// Add the nullable string column `name` of the JSON documents `values`,
+    /// see [json_array].
+    pub fn json_column(
+        self,
+        name: &str,
+        values: impl IntoIterator<Item = serde_json::Value>,
+    ) -> Self {
+        self.column(name, Arc::new(json_array(values)))
+    }
+
+    fn fail(mut self, error: Error) -> Self {
+        self.error.get_or_insert(error);
+        self
+    }
+
+    /// Build the batch, making sure that all columns have the same number of rows.
+    pub fn build(self) -> Result<RecordBatch> {
+        if let Some(error) = self.error {
+            return Err(error);
+        }
+        let Some(rows) = self.columns.first().map(|c| c.len()) else {
+            return Err(Error::InvalidInput {
+                message: "a batch needs at least one column".to_string(),
+            });
+        };
+        for (field, column) in self.fields.iter().zip(self.columns.iter()) {
+            if column.len() != rows {
+                return Err(Error::InvalidInput {
+                    message: format!(
+                        "column '{}' has {} rows, expected {rows}",
+                        field.name(),
+                        column.len()
+                    ),
+                });
+            }
+        }
+        batch(Schema::new(self.fields), self.columns)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow_array::cast::AsArray;
+    use arrow_array::{Array, FixedSizeListArray, Float32Array, Int32Array, StructArray};
+    use serde::Serialize;
+
+    use super::*;
+
+    #[derive(Serialize, Clone)]
+    struct Meta {
+        source: Option<String>,
+        page: u32,
+    }
+
+    #[derive(Serialize, Clone)]
+    struct Doc {
+        id: i32,
+        text: String,
+        score: Option<f64>,
+        embedding: Vec<f32>,
+        meta: Option<Meta>,
+    }
+
+    fn docs() -> Vec<Doc> {
+        vec![
+            Doc {
+                id: 1,
+                text: "a".to_string(),
+                score: Some(0.5),
+                embedding: vec![1.0, 2.0],
+                meta: Some(Meta {
+                    source: Some("web".to_string()),
+                    page: 3,
+                }),
+            },
+            Doc {
+                id: 2,
+                text: "b".to_string(),
+                score: None,
+                embedding: vec![3.0, 4.0],
+                meta: Some(Meta {
+                    source: None,
+                    page: 4,
+                }),
+            },
+            Doc {
+                id: 3,
+                text: "c".to_string(),
+                score: None,
+                embedding: vec![5.0, 6.0],
+                meta: None,
+            },
+        ]
+    }
+
+    #[test]
+    fn test_rows_to_batch() {
+        let batch = rows_to_batch(docs(), None).unwrap();
+        let schema = batch.schema();
+        let names: Vec<&str> = schema.fields().iter().map(|f| f.name().as_str()).collect();
+        assert_eq!(names, vec!["id", "text", "score", "embedding", "meta"]);
+        assert_eq!(schema.field(0), &Field::new("id", DataType::Int32, false));
+        assert_eq!(
+            schema.field(2),
+            &Field::new("score", DataType::Float64, true)
+        );
+        assert_eq!(
+            schema.field(3).data_type(),
+            &DataType::FixedSizeList(Arc::new(Field::new("item", DataType::Float32, true)), 2)
+        );
+        assert!(schema.field(4).is_nullable());
+
+        assert_eq!(
+            batch
+                .column(0)
+                .as_any()
+                .downcast_ref::<Int32Array>()
+                .unwrap(),
+            &Int32Array::from(vec![1, 2, 3])
+        );
+        let scores = batch.column(2).as_primitive::<Float64Type>();
+        assert_eq!(
+            scores.iter().collect::<Vec<_>>(),
+            vec![Some(0.5), None, None]
+        );
+        let vectors = batch
+            .column(3)
+            .as_any()
+            .downcast_ref::<FixedSizeListArray>()
+            .unwrap();
+        let vector = vectors.value(2);
+        assert_eq!(
+            vector.as_any().downcast_ref::<Float32Array>().unwrap(),
+            &Float32Array::from(vec![5.0, 6.0])
+        );
+
+        let meta = batch
+            .column(4)
+            .as_any()
+            .downcast_ref::<StructArray>()
+            .unwrap();
+        assert_eq!(meta.null_count(), 1);
+        let sources = meta.column_by_name("source").unwrap().as_string::<i32>();
+        assert_eq!(
+            sources.iter().collect::<Vec<_>>(),
+            vec![Some("web"), None, None]
+        );
+        let pages = meta
+            .column_by_name("page")
+            .unwrap()
+            .as_primitive::<UInt32Type>();
+        assert_eq!(pages.value(1), 4);
+    }
+
+    #[test]
+    fn test_rows_to_batch_with_schema() {
+        let schema = Schema::new(vec![
+            Field::new("id", DataType::Int64, false),
+            Field::new("score", DataType::Float32, true),
+            Field::new("other", DataType::Utf8, true),
+        ]);
+
+        #[derive(Serialize)]
+        struct Row {
+            id: u8,
+            score: Option<f64>,
+        }
+        let rows = vec![
+            Row {
+                id: 1,
+                score: Some(1.5),
+            },
+            Row { id: 2, score: None },
+        ];
+        let batch = rows_to_batch(rows, Some(&schema)).unwrap();
+        assert_eq!(batch.schema().fields().len(), 2);
+        assert_eq!(batch.column(0).data_type(), &DataType::Int64);
+        assert_eq!(batch.column(1).data_type(), &DataType::Float32);
+
+        let schema = Schema::new(vec![
+            Field::new("id", DataType::Int8, false),
+            Field::new("text", DataType::Int32, false),
+            Field::new("embedding", DataType::Utf8, false),
+        ]);
+        let err = rows_to_batch(docs(), Some(&schema)).unwrap_err();
+        let Error::Schema { message } = err else {
+            panic!("unexpected error {err}");
+        };
+        for column in ["'text'", "'embedding'", "'score'", "'meta'"] {
+            assert!(message.contains(column), "{message}");
+        }
+        assert!(!message.contains("'id'"), "{message}");
+    }
+
+    #[test]
+    fn test_vector_width() {
+        let mut rows = docs();
+        rows[1].embedding = vec![1.0; 3];
+        assert!(matches!(
+            rows_to_batch(rows.clone(), None).unwrap_err(),
+            Error::EmbeddingDimensionMismatch {
+                expected: 2,
+                got: 3,
+                ..
+            }
+        ));
+
+        let schema = Schema::new(vec![Field::new(
+            "embedding",
+            DataType::FixedSizeList(Arc::new(Field::new("item", DataType::Float32, true)), 3),
+            true,
+        )]);
+        let err = rows_to_batch(docs(), Some(&schema)).unwrap_err();
+        assert!(
+            matches!(
+                err,
+                Error::EmbeddingDimensionMismatch {
+                    expected: 3,
+                    got: 2,
+                    ..
+                }
+            ),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn test_vec_to_fixed_size_list() {
+        let vectors =
+            vec_to_fixed_size_list(2, vec![Some(vec![1.0, 2.0]), None, Some(vec![3.0, 4.0])])
+                .unwrap();
+        assert_eq!(vectors.len(), 3);
+        assert!(vectors.is_null(1));
+        assert_eq!(
+            vectors.value(2).as_primitive::<Float32Type>(),
+            &Float32Array::from(vec![3.0, 4.0])
+        );
+
+        let err = vec_to_fixed_size_list(2, vec![vec![1.0, 2.0], vec![3.0]]).unwrap_err();
+        assert!(
+            matches!(&err, Error::InvalidInput { message } if message.contains("vector 1")),
+            "{err}"
+        );
+        assert!(vec_to_fixed_size_list(0, Vec::<Vec<f32>>::new()).is_err());
+    }
+
+    #[test]
+    fn test_record_batch_builder() {
+        let batch = RecordBatchBuilder::new()
+            .column("id", Arc::new(Int32Array::from(vec![1, 2])))
+            .vector_column("embedding", 2, vec![Some([1.0, 2.0]), None])
+            .build()
+            .unwrap();
+        assert_eq!(batch.num_rows(), 2);
+        assert_eq!(
+            batch.schema().field(1).data_type(),
+            &DataType::FixedSizeList(Arc::new(Field::new("item", DataType::Float32, true)), 2)
+        );
+        assert_eq!(batch.column(1).null_count(), 1);
+
+        let err = RecordBatchBuilder::new()
+            .vector_column("embedding", 2, vec![vec![1.0, 2.0], vec![1.0, 2.0, 3.0]])
+            .build()
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            Error::EmbeddingDimensionMismatch { ref column, expected: 2, got: 3 }
+                if column == "embedding"
+        ));
+
+        let err = RecordBatchBuilder::new()
+            .column("id", Arc::new(Int32Array::from(vec![1, 2, 3])))
+            .vector_column("embedding", 2, vec![vec![1.0, 2.0]])
+            .build()
+            .unwrap_err();
+        assert!(
+            matches!(&err, Error::InvalidInput { message } if message.contains("'embedding'")),
+            "{err}"
+        );
+    }
+}
@@ -0,0 +1,270 @@
+// Copyright 2023 LanceDB Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! JSON documents stored in string or binary columns.
+//!
+//! A path into a document starts at its root `$`, followed by the keys of
+//! objects, `.lang` or `["page title"]`, and the indices of arrays, `[0]`.
+
+use std::fmt;
+use std::str::FromStr;
+
+use arrow_array::cast::AsArray;
+use arrow_array::{Array, StringArray};
+use arrow_schema::DataType;
+use serde_json::Value;
+
+use crate::error::{Error, Result};
+
+/// The string column of the JSON documents `values`, [Value::Null] stored as a
+/// null.
+pub fn json_array(values: impl IntoIterator<Item = Value>) -> StringArray {
+    values
+        .into_iter()
+        .map(|value| (!value.is_null()).then(|| value.to_string()))
+        .collect()
+}
+
+/// The documents of `array`, a string or binary column of JSON documents.
+///
+/// Null rows are `None`, and a row that is not a JSON document fails with an
+/// [Error::InvalidInput].
+pub fn parse_json(array: &dyn Array) -> Result<Vec<Option<Value>>> {
+    documents(array)?
+        .into_iter()
+        .enumerate()
+        .map(|(row, document)| {
+            document
+                .map(|bytes| {
+                    serde_json::from_slice(bytes).map_err(|e| Error::InvalidInput {
+                        message: format!("the row {row} is not a JSON document: {e}"),
+                    })
+                })
+                .transpose()
+        })
+        .collect()
+}
+
+/// The bytes of the documents of `array`, a string or binary column.
+pub(crate) fn documents(array: &dyn Array) -> Result<Vec<Option<&[u8]>>> {
+    Ok(match array.data_type() {
+        DataType::Utf8 => array.as_string::<i32>().iter().map(bytes).collect(),
+        DataType::LargeUtf8 => array.as_string::<i64>().iter().map(bytes).collect(),
+        DataType::Binary => array.as_binary::<i32>().iter().collect(),
+        DataType::LargeBinary => array.as_binary::<i64>().iter().collect(),
+        data_type => {
+            return Err(Error::InvalidInput {
+                message: format!(
+                    "JSON documents are stored in string or binary columns, got {data_type}"
+                ),
+            })
+        }
+    })
+}
+
+fn bytes(text: Option<&str>) -> Option<&[u8]> {
+    text.map(str::as_bytes)
+}
+
+/// A step of a [JsonPath].
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Step {
+    Key(String),
+    Index(usize),
+}
+
+/// A path into JSON documents, see the [module documentation](self).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct JsonPath {
+    text: String,
+    steps: Vec<Step>,
+}
+
+impl JsonPath {
+    /// The value at this path of `document`, `None` if it has none.
+    pub(crate) fn get<'a>(&self, document: &'a Value) -> Option<&'a Value> {
+        self.steps
+            .iter()
+            .try_fold(document, |value, step| match step {
+                Step::Key(key) => value.get(key),
+                Step::Index(index) => value.get(index),
+            })
+    }
+
+    /// The values at this path of the documents of `array`, as text: strings
+    /// as they are, and other values as JSON.
+    ///
+    /// JSON nulls, missing values and rows that are not JSON documents are
+    /// null.
+    pub(crate) fn extract(&self, array: &dyn Array) -> Result<StringArray> {
+        Ok(documents(array)?
+            .into_iter()
+            .map(|document| self.extract_one(document?))
+            .collect())
+    }
+
+    /// The value at this path of `document` as text, see [JsonPath::extract].
+    pub(crate) fn extract_one(&self, document: &[u8]) -> Option<String> {
+        let document: Value = serde_json::from_slice(document).ok()?;
+        match self.get(&document)? {
+            Value::Null => None,
+            Value::String(text) => Some(text.clone()),
+            value => Some(value.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for JsonPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+impl FromStr for JsonPath {
+    type Err = Error;
+
+    fn from_str(path: &str) -> Result<Self> {
+        let invalid = |reason: &str| Error::InvalidInput {
+            message: format!("invalid JSON path '{path}': {reason}"),
+        };
+        let Some(mut rest) = path.strip_prefix('$') else {
+            return Err(invalid("a path starts with '$'"));
+        };
+        let mut steps = Vec::new();
+        while !rest.is_empty() {
+            if let Some(after) = rest.strip_prefix('.') {
+                let end = after.find(['.', '[']).unwrap_or(after.len());
+                if end == 0 {
+                    return Err(invalid("a '.' is followed by a key"));
+                }
+                steps.push(Step::Key(after[..end].to_string()));
+                rest = &after[end..];
+            } else if let Some(after) = rest.strip_prefix('[') {
+                let Some(end) = after.find(']') else {
+                    return Err(invalid("a '[' is closed by a ']'"));
+                };
+                let inner = after[..end].trim();
+                let quoted = ['"', '\''].iter().find_map(|q| {
+                    inner
+                        .strip_prefix(*q)
+                        .and_then(|key| key.strip_suffix(*q))
+                        .filter(|_| inner.len() >= 2)
+                });
+                let step =
+                    match quoted {
+                        Some(key) => Step::Key(key.to_string()),
+                        None => Step::Index(inner.parse().map_err(|_| {
+                            invalid("brackets hold an array index or a quoted key")
+                        })?),
+                    };
+                steps.push(step);
+                rest = &after[end + 1..];
+            } else {
+                return Err(invalid("steps start with '.' or '['"));
+            }
+        }
+        Ok(Self {
+            text: path.to_string(),
+            steps,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow_array::BinaryArray;
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_json_path() {
+        let document = json!({
+            "lang": "en",
+            "page title": "Intro",
+            "tags": ["a", {"name": "b"}],
+            "meta": {"year": 2021, "draft": false, "editor": null},
+        });
+        let cases = [
+            ("$", Some(document.clone())),
+            ("$.lang", Some(json!("en"))),
+            ("$[\"page title\"]", Some(json!("Intro"))),
+            ("$['meta'].year", Some(json!(2021))),
+            ("$.tags[1].name", Some(json!("b"))),
+            ("$.tags[2]", None),
+            ("$.lang.nested", None),
+            ("$.missing", None),
+        ];
+        for (path, expected) in cases {
+            let parsed = path.parse::<JsonPath>().unwrap();
+            assert_eq!(parsed.get(&document), expected.as_ref(), "{path}");
+            assert_eq!(parsed.to_string(), path);
+        }
+        for path in ["lang", "$.", "$[0", "$[x]", "$lang"] {
+            let err = path.parse::<JsonPath>().unwrap_err();
+            assert!(matches!(err, Error::InvalidInput { .. }), "{path}: {err}");
+        }
+    }
+
+    #[test]
+    fn test_extract() {
+        let array = json_array([
+            json!({"meta": {"year": 2021, "lang": "en", "draft": false}}),
+            json!({"meta": {"year": null}}),
+            Value::Null,
+            json!([1, 2]),
+        ]);
+        assert_eq!(array.null_count(), 1);
+        let extract = |path: &str| {
+            let path = path.parse::<JsonPath>().unwrap();
+            path.extract(&array)
+                .unwrap()
+                .iter()
+                .map(|v| v.map(String::from))
+                .collect::<Vec<_>>()
+        };
+        let text = |v: &str| Some(v.to_string());
+        assert_eq!(extract("$.meta.lang"), vec![text("en"), None, None, None]);
+        assert_eq!(extract("$.meta.year"), vec![text("2021"), None, None, None]);
+        assert_eq!(
+            extract("$.meta.draft"),
+            vec![text("false"), None, None, None]
+        );
+        assert_eq!(
+            extract("$.meta"),
+            vec![
+                text(r#"{"draft":false,"lang":"en","year":2021}"#),
+                text(r#"{"year":null}"#),
+                None,
+                None
+            ]
+        );
+        assert_eq!(extract("$[1]"), vec![None, None, None, text("2")]);
+
+        let binary = BinaryArray::from(vec![&b"{\"a\": 1}"[..], b"not json"]);
+        let path = "$.a".parse::<JsonPath>().unwrap();
+        let values = path.extract(&binary).unwrap();
+        assert_eq!((values.value(0), values.is_null(1)), ("1", true));
+        assert!(parse_json(&binary).is_err());
+        assert_eq!(
+            parse_json(&array).unwrap(),
+            vec![
+                Some(json!({"meta": {"year": 2021, "lang": "en", "draft": false}})),
+                Some(json!({"meta": {"year": null}})),
+                None,
+                Some(json!([1, 2])),
+            ]
+        );
+    }
+}
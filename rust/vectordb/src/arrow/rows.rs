@@ -0,0 +1,185 @@
+// Copyright 2023 Lance Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Conversion of Arrow data back to Rust values, through [serde_json::Value]s.
+
+use arrow_array::cast::{
+    as_boolean_array, as_large_list_array, as_largestring_array, as_list_array, as_primitive_array,
+    as_string_array, as_struct_array,
+};
+use arrow_array::types::{
+    Float16Type, Float32Type, Float64Type, Int16Type, Int32Type, Int64Type, Int8Type, UInt16Type,
+    UInt32Type, UInt64Type, UInt8Type,
+};
+use arrow_array::{Array, FixedSizeListArray, RecordBatch};
+use arrow_schema::DataType;
+use datafusion::arrow::util::display::array_value_to_string;
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Number, Value};
+
+use crate::error::{Error, Result};
+
+fn float(value: f64) -> Value {
+    Number::from_f64(value).map_or(Value::Null, Value::Number)
+}
+
+fn items(values: &dyn Array) -> Result<Value> {
+    (0..values.len())
+        .map(|i| json_value(values, i))
+        .collect::<Result<Vec<_>>>()
+        .map(Value::Array)
+}
+
+/// The value of `row` of `array`. NaN and infinite floats are null, and the
+/// types without a JSON counterpart, such as timestamps, are their display
+/// string.
+fn json_value(array: &dyn Array, row: usize) -> Result<Value> {
+    if array.is_null(row) {
+        return Ok(Value::Null);
+    }
+    Ok(match array.data_type() {
+        DataType::Null => Value::Null,
+        DataType::Boolean => Value::Bool(as_boolean_array(array).value(row)),
+        DataType::Int8 => as_primitive_array::<Int8Type>(array).value(row).into(),
+        DataType::Int16 => as_primitive_array::<Int16Type>(array).value(row).into(),
+        DataType::Int32 => as_primitive_array::<Int32Type>(array).value(row).into(),
+        DataType::Int64 => as_primitive_array::<Int64Type>(array).value(row).into(),
+        DataType::UInt8 => as_primitive_array::<UInt8Type>(array).value(row).into(),
+        DataType::UInt16 => as_primitive_array::<UInt16Type>(array).value(row).into(),
+        DataType::UInt32 => as_primitive_array::<UInt32Type>(array).value(row).into(),
+        DataType::UInt64 => as_primitive_array::<UInt64Type>(array).value(row).into(),
+        DataType::Float16 => float(as_primitive_array::<Float16Type>(array).value(row).to_f64()),
+        DataType::Float32 => float(as_primitive_array::<Float32Type>(array).value(row) as f64),
+        DataType::Float64 => float(as_primitive_array::<Float64Type>(array).value(row)),
+        DataType::Utf8 => as_string_array(array).value(row).into(),
+        DataType::LargeUtf8 => as_largestring_array(array).value(row).into(),
+        DataType::FixedSizeList(_, _) => {
+            let list = array.as_any().downcast_ref::<FixedSizeListArray>().unwrap();
+            items(list.value(row).as_ref())?
+        }
+        DataType::List(_) => items(as_list_array(array).value(row).as_ref())?,
+        DataType::LargeList(_) => items(as_large_list_array(array).value(row).as_ref())?,
+        DataType::Struct(fields) => {
+            let array = as_struct_array(array);
+            let mut object = Map::with_capacity(fields.len());
+            for (field, column) in fields.iter().zip(array.columns()) {
+                object.insert(field.name().clone(), json_value(column.as_ref(), row)?);
+            }
+            Value::Object(object)
+        }
+        _ => array_value_to_string(array, row)
+            .map_err(lance::Error::from)?
+            .into(),
+    })
+}
+
+/// Convert the rows of `batch` to `T`s, the reverse of [super::rows_to_batch].
+///
+/// Each row is a struct of the columns of the batch, so that `T` may leave
+/// columns out. Rows that do not fit `T` fail with an [Error::InvalidInput].
+pub fn batch_to_rows<T: DeserializeOwned>(batch: &RecordBatch) -> Result<Vec<T>> {
+    let schema = batch.schema();
+    (0..batch.num_rows())
+        .map(|row| {
+            let mut object = Map::with_capacity(batch.num_columns());
+            for (field, column) in schema.fields().iter().zip(batch.columns()) {
+                object.insert(field.name().clone(), json_value(column.as_ref(), row)?);
+            }
+            serde_json::from_value(Value::Object(object)).map_err(|e| Error::InvalidInput {
+                message: format!("row {row} does not fit the row type: {e}"),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_array::{Float32Array, Int32Array, StringArray};
+    use serde::{Deserialize, Serialize};
+
+    use super::*;
+    use crate::arrow::{rows_to_batch, RecordBatchBuilder};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Inner {
+        score: f64,
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Row {
+        id: i32,
+        name: Option<String>,
+        vector: Vec<f32>,
+        tags: Vec<String>,
+        inner: Inner,
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let rows = vec![
+            Row {
+                id: 1,
+                name: Some("a".to_string()),
+                vector: vec![1.0, 2.0],
+                tags: vec!["x".to_string()],
+                inner: Inner { score: 0.5 },
+            },
+            Row {
+                id: 2,
+                name: None,
+                vector: vec![3.0, 4.5],
+                tags: vec![],
+                inner: Inner { score: 1.5 },
+            },
+        ];
+        let batch = rows_to_batch(&rows, None).unwrap();
+        assert_eq!(batch_to_rows::<Row>(&batch).unwrap(), rows);
+    }
+
+    #[test]
+    fn test_left_out_columns() {
+        #[derive(Debug, PartialEq, Deserialize)]
+        struct Id {
+            id: i32,
+            score: Option<f32>,
+        }
+        let batch = RecordBatchBuilder::new()
+            .column("id", Arc::new(Int32Array::from(vec![1, 2])))
+            .column("score", Arc::new(Float32Array::from(vec![f32::NAN, 2.0])))
+            .column("text", Arc::new(StringArray::from(vec!["a", "b"])))
+            .build()
+            .unwrap();
+        let ids = batch_to_rows::<Id>(&batch).unwrap();
+        assert_eq!(
+            ids,
+            vec![
+                Id { id: 1, score: None },
+                Id {
+                    id: 2,
+                    score: Some(2.0)
+                }
+            ]
+        );
+
+        #[derive(Debug, Deserialize)]
+        #[allow(dead_code)]
+        struct Wrong {
+            id: String,
+        }
+        let err = batch_to_rows::<Wrong>(&batch).unwrap_err();
+        assert!(matches!(err, Error::InvalidInput { .. }), "{err}");
+    }
+}
@@ -0,0 +1,422 @@
+// Copyright 2023 Lance Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A blocking API for applications that do not run an async runtime.
+//!
+//! A [Database] starts its own tokio runtime, which the tables opened from it
+//! and their queries share. The blocking API cannot be used from within an
+//! async runtime, where it fails with [Error::Runtime]; use the async API there.
+//!
+//! ```no_run
+//! # use vectordb::blocking::Database;
+//! # fn example() -> vectordb::Result<()> {
+//! let db = Database::connect("data/sample-lancedb")?;
+//! let table = db.open_table("my_table")?;
+//! let results = table.search(vec![0.1, 0.2]).limit(10).execute()?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::future::Future;
+use std::sync::Arc;
+
+use arrow_array::{RecordBatch, RecordBatchReader};
+use futures::TryStreamExt;
+use lance::dataset::{WriteMode, WriteParams};
+use lance::index::vector::MetricType;
+use tokio::runtime::Runtime;
+
+use crate::database::{self, ConnectBuilder, CreateTableMode};
+use crate::embeddings::EmbeddingFunction;
+use crate::error::{Error, Result};
+use crate::index::vector::VectorIndexBuilder;
+use crate::query::{self, QueryMetrics, QueryVector};
+use crate::table::{HealthReport, TableRef};
+
+fn check_no_runtime() -> Result<()> {
+    if tokio::runtime::Handle::try_current().is_ok() {
+        return Err(Error::Runtime {
+            message: "the blocking API cannot be used from within an async runtime".to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// Run `future` to completion on `runtime`, unless called from within an async runtime.
+fn block_on<F: Future>(runtime: &Runtime, future: F) -> Result<F::Output> {
+    check_no_runtime()?;
+    Ok(runtime.block_on(future))
+}
+
+/// A blocking connection to LanceDB, see [database::Database].
+///
+/// Dropping it, or a table or query created from it, within an async runtime panics.
+pub struct Database {
+    inner: database::Database,
+    runtime: Arc<Runtime>,
+}
+
+impl Database {
+    /// Connects to the database at `uri`, see [database::Database::connect].
+    pub fn connect(uri: &str) -> Result<Self> {
+        Self::connect_with(database::connect(uri))
+    }
+
+    /// Connects with the options set on `builder`.
+    pub fn connect_with(builder: ConnectBuilder) -> Result<Self> {
+        check_no_runtime()?;
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+            .map_err(|e| Error::Runtime {
+                message: e.to_string(),
+            })?;
+        let inner = block_on(&runtime, builder.execute())??;
+        Ok(Self {
+            inner,
+            runtime: Arc::new(runtime),
+        })
+    }
+
+    /// The async connection this one wraps.
+    pub fn inner(&self) -> &database::Database {
+        &self.inner
+    }
+
+    /// Check that the storage of the database can be reached, see
+    /// [database::Database::health_check].
+    pub fn health_check(&self) -> Result<HealthReport> {
+        block_on(&self.runtime, self.inner.health_check())?
+    }
+
+    /// Get the names of all tables in the database.
+    pub fn table_names(&self) -> Result<Vec<String>> {
+        block_on(&self.runtime, self.inner.table_names())?
+    }
+
+    /// Create a new table from `batches`, see [database::Database::create_table].
+    pub fn create_table(
+        &self,
+        name: &str,
+        batches: Box<dyn RecordBatchReader>,
+    ) -> CreateTableBuilder<'_> {
+        CreateTableBuilder {
+            inner: self.inner.create_table(name, batches),
+            runtime: &self.runtime,
+        }
+    }
+
+    /// Create a new table of the data returned by `data_fn`, only called if the
+    /// table is created, see [database::Database::create_table_lazy].
+    pub fn create_table_lazy(
+        &self,
+        name: &str,
+        data_fn: impl FnOnce() -> Result<Box<dyn RecordBatchReader>> + 'static,
+    ) -> CreateTableBuilder<'_> {
+        CreateTableBuilder {
+            inner: self.inner.create_table_lazy(name, data_fn),
+            runtime: &self.runtime,
+        }
+    }
+
+    /// Open a table in the database.
+    pub fn open_table(&self, name: &str) -> Result<Table> {
+        let inner = block_on(&self.runtime, self.inner.open_table(name))??;
+        Ok(Table::new(inner, self.runtime.clone()))
+    }
+
+    /// Drop a table in the database.
+    pub fn drop_table(&self, name: &str) -> Result<()> {
+        block_on(&self.runtime, self.inner.drop_table(name))?
+    }
+}
+
+/// A blocking [database::CreateTableBuilder].
+pub struct CreateTableBuilder<'a> {
+    inner: database::CreateTableBuilder<'a>,
+    runtime: &'a Arc<Runtime>,
+}
+
+impl CreateTableBuilder<'_> {
+    /// Set the [WriteParams] used to write the initial data.
+    pub fn write_params(mut self, params: WriteParams) -> Self {
+        self.inner = self.inner.write_params(params);
+        self
+    }
+
+    /// Set what to do if the table already exists, see [CreateTableMode].
+    pub fn mode(mut self, mode: CreateTableMode) -> Self {
+        self.inner = self.inner.mode(mode);
+        self
+    }
+
+    /// Compute `vector_column` from `source_column` with `function`.
+    pub fn embedding(
+        mut self,
+        source_column: &str,
+        vector_column: &str,
+        function: Arc<dyn EmbeddingFunction>,
+    ) -> Self {
+        self.inner = self.inner.embedding(source_column, vector_column, function);
+        self
+    }
+
+    /// Create the table.
+    pub fn execute(self) -> Result<Table> {
+        let inner = block_on(self.runtime, self.inner.execute())??;
+        Ok(Table::new(inner, self.runtime.clone()))
+    }
+}
+
+/// A blocking handle to a local or remote table, see [crate::table::TableLike].
+#[derive(Clone)]
+pub struct Table {
+    inner: TableRef,
+    runtime: Arc<Runtime>,
+}
+
+impl Table {
+    fn new(inner: TableRef, runtime: Arc<Runtime>) -> Self {
+        Self { inner, runtime }
+    }
+
+    /// The async table this one wraps.
+    pub fn inner(&self) -> &TableRef {
+        &self.inner
+    }
+
+    /// The name of the table.
+    pub fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    /// Returns the number of rows in this table.
+    pub fn count_rows(&self) -> Result<usize> {
+        block_on(&self.runtime, self.inner.count_rows())?
+    }
+
+    /// Insert records into this table, appending them unless `write_mode` says otherwise.
+    pub fn add(
+        &self,
+        batches: Box<dyn RecordBatchReader + Send>,
+        write_mode: Option<WriteMode>,
+    ) -> Result<usize> {
+        block_on(&self.runtime, self.inner.add(batches, write_mode))?
+    }
+
+    /// Create index on the table.
+    pub fn create_index(&self, index_builder: &(dyn VectorIndexBuilder + Sync)) -> Result<()> {
+        block_on(&self.runtime, self.inner.create_index(index_builder))?
+    }
+
+    /// Delete the rows matching `predicate`, a SQL WHERE clause.
+    pub fn delete(&self, predicate: &str) -> Result<()> {
+        block_on(&self.runtime, self.inner.delete(predicate))?
+    }
+
+    /// Creates a new Query object that can be executed.
+    ///
+    /// `query` is a vector, or a text embedded with the table's embedding function.
+    pub fn search(&self, query: impl Into<QueryVector>) -> Query {
+        Query {
+            inner: self.inner.search(query.into()),
+            runtime: self.runtime.clone(),
+        }
+    }
+}
+
+/// A blocking [query::Query].
+pub struct Query {
+    inner: query::Query,
+    runtime: Arc<Runtime>,
+}
+
+impl Query {
+    /// Execute the query and collect its results.
+    pub fn execute(&self) -> Result<Vec<RecordBatch>> {
+        block_on(&self.runtime, async {
+            let stream = self.inner.execute().await?;
+            Ok(stream.try_collect::<Vec<_>>().await?)
+        })?
+    }
+
+    /// Execute the query and collect its results with [QueryMetrics].
+    pub fn execute_with_metrics(&self) -> Result<(Vec<RecordBatch>, QueryMetrics)> {
+        block_on(&self.runtime, self.inner.execute_with_metrics())?
+    }
+
+    /// Set the maximum number of results to return.
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.inner = self.inner.limit(limit);
+        self
+    }
+
+    /// Search for `query`, see [query::Query::nearest_to].
+    pub fn nearest_to(mut self, query: impl Into<QueryVector>) -> Self {
+        self.inner = self.inner.nearest_to(query);
+        self
+    }
+
+    /// Search the vectors of `column` instead of the `vector` column.
+    pub fn column(mut self, column: &str) -> Self {
+        self.inner = self.inner.column(column);
+        self
+    }
+
+    /// Set the number of probes to use.
+    pub fn nprobes(mut self, nprobes: usize) -> Self {
+        self.inner = self.inner.nprobes(nprobes);
+        self
+    }
+
+    /// Set the refine factor to use.
+    pub fn refine_factor(mut self, refine_factor: Option<u32>) -> Self {
+        self.inner = self.inner.refine_factor(refine_factor);
+        self
+    }
+
+    /// Set the distance metric to use.
+    pub fn metric_type(mut self, metric_type: Option<MetricType>) -> Self {
+        self.inner = self.inner.metric_type(metric_type);
+        self
+    }
+
+    /// Whether to use an ANN index if available.
+    pub fn use_index(mut self, use_index: bool) -> Self {
+        self.inner = self.inner.use_index(use_index);
+        self
+    }
+
+    /// A filter statement to be applied to this query.
+    pub fn filter(mut self, filter: Option<String>) -> Self {
+        self.inner = self.inner.filter(filter);
+        self
+    }
+
+    /// A filter built from a [query::FilterExpr], see [query::Query::filter_expr].
+    pub fn filter_expr(mut self, filter: query::FilterExpr) -> Self {
+        self.inner = self.inner.filter_expr(filter);
+        self
+    }
+
+    /// Whether the results have a `_rowid` column, see [query::Query::with_row_id].
+    pub fn with_row_id(mut self, with_row_id: bool) -> Self {
+        self.inner = self.inner.with_row_id(with_row_id);
+        self
+    }
+
+    /// Keep the rows of NaN or infinite distances, see
+    /// [query::Query::allow_nonfinite].
+    pub fn allow_nonfinite(mut self, allow: bool) -> Self {
+        self.inner = self.inner.allow_nonfinite(allow);
+        self
+    }
+
+    /// Cap the estimated size of the results, see
+    /// [query::Query::max_result_bytes].
+    pub fn max_result_bytes(mut self, bytes: usize) -> Self {
+        self.inner = self.inner.max_result_bytes(bytes);
+        self
+    }
+
+    /// Order the rows of the same distance by row id, see [query::Query::ordered].
+    pub fn ordered(mut self, ordered: bool) -> Self {
+        self.inner = self.inner.ordered(ordered);
+        self
+    }
+
+    /// Return the nearest row of each value of `column`, see [query::Query::distinct_on].
+    pub fn distinct_on(mut self, column: &str) -> Self {
+        self.inner = self.inner.distinct_on(column);
+        self
+    }
+
+    /// Set how many rows a distinct query searches for, see
+    /// [query::Query::distinct_overfetch].
+    pub fn distinct_overfetch(mut self, factor: usize) -> Self {
+        self.inner = self.inner.distinct_overfetch(factor);
+        self
+    }
+
+    /// Return only the specified columns.
+    pub fn select(mut self, columns: Option<Vec<String>>) -> Self {
+        self.inner = self.inner.select(columns);
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::iter::repeat_with;
+
+    use arrow_array::Int32Array;
+    use lance::arrow::RecordBatchBuffer;
+    use lance::index::vector::ivf::IvfBuildParams;
+    use lance::index::vector::pq::PQBuildParams;
+    use rand::Rng;
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::arrow::RecordBatchBuilder;
+    use crate::index::vector::IvfPQIndexBuilder;
+
+    fn make_batches(ids: std::ops::Range<i32>) -> Box<dyn RecordBatchReader + Send> {
+        let mut rng = rand::thread_rng();
+        let vectors: Vec<Vec<f32>> = repeat_with(|| repeat_with(|| rng.gen()).take(16).collect())
+            .take(ids.len())
+            .collect();
+        let batch = RecordBatchBuilder::new()
+            .column("id", Arc::new(Int32Array::from_iter_values(ids)))
+            .vector_column("vector", 16, vectors)
+            .build()
+            .unwrap();
+        Box::new(RecordBatchBuffer::new(vec![batch]))
+    }
+
+    #[test]
+    fn test_blocking_flow() {
+        let tmp_dir = tempdir().unwrap();
+        let db = Database::connect(tmp_dir.path().to_str().unwrap()).unwrap();
+
+        let table = db
+            .create_table("vectors", make_batches(0..256))
+            .execute()
+            .unwrap();
+        table.add(make_batches(256..512), None).unwrap();
+        assert_eq!(table.count_rows().unwrap(), 512);
+        assert_eq!(db.table_names().unwrap(), vec!["vectors"]);
+
+        let builder = IvfPQIndexBuilder::new()
+            .column("vector")
+            .ivf_params(IvfBuildParams::new(2))
+            .pq_params(PQBuildParams::default());
+        table.create_index(&builder).unwrap();
+
+        let table = db.open_table("vectors").unwrap();
+        let results = table.search(vec![0.5; 16]).limit(5).execute().unwrap();
+        assert_eq!(results.iter().map(|b| b.num_rows()).sum::<usize>(), 5);
+
+        db.drop_table("vectors").unwrap();
+        assert!(db.table_names().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_nested_runtime_rejected() {
+        let tmp_dir = tempdir().unwrap();
+        let err = Database::connect(tmp_dir.path().to_str().unwrap())
+            .err()
+            .unwrap();
+        assert!(matches!(err, Error::Runtime { .. }), "{err}");
+    }
+}
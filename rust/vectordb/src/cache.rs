@@ -0,0 +1,421 @@
+// Copyright 2023 LanceDB Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use arrow_array::RecordBatch;
+use arrow_schema::SchemaRef;
+use lance::dataset::Dataset;
+
+use crate::query::QueryKey;
+
+/// Hit / miss counters of the caches shared by a connection.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Number of table opens served from the metadata cache.
+    pub metadata_hits: u64,
+    /// Number of table opens that had to read the manifest from storage.
+    pub metadata_misses: u64,
+    /// Number of tables currently held in the metadata cache.
+    pub metadata_entries: usize,
+    /// Estimated size in bytes of the cached metadata.
+    pub metadata_size_bytes: usize,
+    /// Number of tables kept open by [crate::Database::table] handles.
+    pub open_tables: usize,
+    /// Number of tables opened by [crate::Database::table] handles, counting
+    /// the tables opened again after they were closed.
+    pub table_opens: u64,
+}
+
+struct CacheEntry {
+    dataset: Arc<Dataset>,
+    size_bytes: usize,
+    loaded_at: Instant,
+    last_used: u64,
+}
+
+#[derive(Default)]
+struct CacheState {
+    entries: HashMap<String, CacheEntry>,
+    size_bytes: usize,
+    clock: u64,
+}
+
+/// A byte-bounded LRU cache of opened table metadata, keyed by table URI.
+///
+/// Shared by all tables opened from the same connection so they do not each
+/// re-read their manifest.
+pub(crate) struct MetadataCache {
+    capacity_bytes: usize,
+    state: Mutex<CacheState>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl MetadataCache {
+    pub(crate) fn new(capacity_bytes: usize) -> Self {
+        Self {
+            capacity_bytes,
+            state: Mutex::new(CacheState::default()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Get the cached dataset for `uri`, unless it was loaded more than `max_age` ago.
+    pub(crate) fn get(&self, uri: &str, max_age: Option<Duration>) -> Option<Arc<Dataset>> {
+        let mut state = self.state.lock().unwrap();
+        state.clock += 1;
+        let clock = state.clock;
+        let dataset = state.entries.get_mut(uri).and_then(|entry| {
+            if max_age.is_some_and(|age| entry.loaded_at.elapsed() > age) {
+                return None;
+            }
+            entry.last_used = clock;
+            Some(entry.dataset.clone())
+        });
+        match dataset {
+            Some(_) => self.hits.fetch_add(1, Ordering::Relaxed),
+            None => self.misses.fetch_add(1, Ordering::Relaxed),
+        };
+        dataset
+    }
+
+    /// Insert or replace the dataset cached for `uri`, evicting the least recently
+    /// used entries to stay within the byte budget.
+    pub(crate) fn insert(&self, uri: &str, dataset: Arc<Dataset>) {
+        let size_bytes = estimated_size(&dataset);
+        let mut state = self.state.lock().unwrap();
+        if let Some(old) = state.entries.remove(uri) {
+            state.size_bytes -= old.size_bytes;
+        }
+        if size_bytes > self.capacity_bytes {
+            return;
+        }
+        while state.size_bytes + size_bytes > self.capacity_bytes {
+            let lru = state
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| key.clone());
+            match lru.and_then(|key| state.entries.remove(&key)) {
+                Some(evicted) => state.size_bytes -= evicted.size_bytes,
+                None => break,
+            }
+        }
+        state.clock += 1;
+        let last_used = state.clock;
+        state.size_bytes += size_bytes;
+        state.entries.insert(
+            uri.to_string(),
+            CacheEntry {
+                dataset,
+                size_bytes,
+                loaded_at: Instant::now(),
+                last_used,
+            },
+        );
+    }
+
+    /// Drop the entry cached for `uri`.
+    pub(crate) fn invalidate(&self, uri: &str) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(old) = state.entries.remove(uri) {
+            state.size_bytes -= old.size_bytes;
+        }
+    }
+
+    pub(crate) fn stats(&self) -> CacheStats {
+        let state = self.state.lock().unwrap();
+        CacheStats {
+            metadata_hits: self.hits.load(Ordering::Relaxed),
+            metadata_misses: self.misses.load(Ordering::Relaxed),
+            metadata_entries: state.entries.len(),
+            metadata_size_bytes: state.size_bytes,
+            ..Default::default()
+        }
+    }
+}
+
+/// Configuration of the query cache of a table, see
+/// [crate::NativeTable::with_query_cache].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheConfig {
+    /// The most query results kept, the least recently used go first.
+    pub max_entries: usize,
+    /// How long results are served after they were computed. `None` serves
+    /// them until the table changes.
+    pub ttl: Option<Duration>,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            max_entries: 1024,
+            ttl: None,
+        }
+    }
+}
+
+/// Hit / miss counters of the query cache of a table.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct QueryCacheStats {
+    /// Number of queries answered from the cache.
+    pub hits: u64,
+    /// Number of queries that had to be run.
+    pub misses: u64,
+    /// Number of query results currently cached.
+    pub entries: usize,
+}
+
+/// The results of a query, as cached.
+pub(crate) type CachedResults = (SchemaRef, Vec<RecordBatch>);
+
+struct QueryEntry {
+    results: CachedResults,
+    computed_at: Instant,
+    last_used: u64,
+}
+
+struct QueryCacheState<K> {
+    entries: HashMap<K, QueryEntry>,
+    /// The version of the table the entries were computed from.
+    version: u64,
+    clock: u64,
+}
+
+impl<K> Default for QueryCacheState<K> {
+    fn default() -> Self {
+        Self {
+            entries: HashMap::new(),
+            version: 0,
+            clock: 0,
+        }
+    }
+}
+
+/// An LRU cache of query results of one table version, keyed by the query
+/// parameters that change its results, see [QueryKey]. The keys are compared
+/// in full on a hit.
+pub(crate) struct QueryCache<K = QueryKey> {
+    config: CacheConfig,
+    state: Mutex<QueryCacheState<K>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl<K: Eq + Hash + Clone> std::fmt::Debug for QueryCache<K> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("QueryCache")
+            .field("config", &self.config)
+            .field("stats", &self.stats())
+            .finish()
+    }
+}
+
+impl<K: Eq + Hash + Clone> QueryCache<K> {
+    pub(crate) fn new(config: CacheConfig) -> Self {
+        Self {
+            config,
+            state: Mutex::new(QueryCacheState::default()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Get the results cached for `key`, which holds the version of the table.
+    pub(crate) fn get(&self, key: &K) -> Option<CachedResults> {
+        let mut guard = self.state.lock().unwrap();
+        let state = &mut *guard;
+        state.clock += 1;
+        let clock = state.clock;
+        let ttl = self.config.ttl;
+        let results = match state.entries.get_mut(key) {
+            Some(entry) if ttl.is_some_and(|ttl| entry.computed_at.elapsed() > ttl) => {
+                state.entries.remove(key);
+                None
+            }
+            Some(entry) => {
+                entry.last_used = clock;
+                Some(entry.results.clone())
+            }
+            None => None,
+        };
+        match results {
+            Some(_) => self.hits.fetch_add(1, Ordering::Relaxed),
+            None => self.misses.fetch_add(1, Ordering::Relaxed),
+        };
+        results
+    }
+
+    /// Cache the results of `key`, computed from `version` of the table,
+    /// dropping the results of other versions.
+    pub(crate) fn insert(&self, key: K, version: u64, results: CachedResults) {
+        let mut state = self.state.lock().unwrap();
+        if state.version != version {
+            state.entries.clear();
+            state.version = version;
+        }
+        while state.entries.len() >= self.config.max_entries && !state.entries.is_empty() {
+            let lru = state
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| key.clone());
+            if let Some(key) = lru {
+                state.entries.remove(&key);
+            }
+        }
+        if self.config.max_entries == 0 {
+            return;
+        }
+        state.clock += 1;
+        let last_used = state.clock;
+        state.entries.insert(
+            key,
+            QueryEntry {
+                results,
+                computed_at: Instant::now(),
+                last_used,
+            },
+        );
+    }
+
+    pub(crate) fn stats(&self) -> QueryCacheStats {
+        QueryCacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            entries: self.state.lock().unwrap().entries.len(),
+        }
+    }
+}
+
+/// Rough in-memory footprint of a dataset's manifest: schema plus fragment list.
+fn estimated_size(dataset: &Dataset) -> usize {
+    const FIELD_OVERHEAD: usize = 64;
+    const FRAGMENT_OVERHEAD: usize = 32;
+
+    let schema_size: usize = dataset
+        .schema()
+        .fields
+        .iter()
+        .map(|f| FIELD_OVERHEAD + f.name.len())
+        .sum();
+    let fragments_size: usize = dataset
+        .get_fragments()
+        .iter()
+        .map(|f| {
+            FRAGMENT_OVERHEAD
+                + f.metadata()
+                    .files
+                    .iter()
+                    .map(|file| file.path.len() + file.fields.len() * 4)
+                    .sum::<usize>()
+        })
+        .sum();
+    schema_size + fragments_size
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_array::{Int32Array, RecordBatch, RecordBatchReader};
+    use arrow_schema::{DataType, Field, Schema};
+    use lance::arrow::RecordBatchBuffer;
+    use lance::dataset::Dataset;
+
+    use super::*;
+
+    async fn make_dataset() -> Arc<Dataset> {
+        let schema = Arc::new(Schema::new(vec![Field::new("i", DataType::Int32, false)]));
+        let mut batches: Box<dyn RecordBatchReader> =
+            Box::new(RecordBatchBuffer::new(vec![RecordBatch::try_new(
+                schema,
+                vec![Arc::new(Int32Array::from_iter_values(0..10))],
+            )
+            .unwrap()]));
+        Arc::new(
+            Dataset::write(&mut batches, "memory://cache", None)
+                .await
+                .unwrap(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_lru_eviction() {
+        let dataset = make_dataset().await;
+        let size = estimated_size(&dataset);
+        let cache = MetadataCache::new(size * 2);
+
+        cache.insert("a", dataset.clone());
+        cache.insert("b", dataset.clone());
+        assert!(cache.get("a", None).is_some());
+        cache.insert("c", dataset.clone());
+
+        assert!(cache.get("b", None).is_none());
+        assert!(cache.get("a", None).is_some());
+        assert!(cache.get("c", None).is_some());
+        let stats = cache.stats();
+        assert_eq!(stats.metadata_entries, 2);
+        assert!(stats.metadata_size_bytes <= size * 2);
+        assert_eq!(stats.metadata_hits, 3);
+        assert_eq!(stats.metadata_misses, 1);
+    }
+
+    #[test]
+    fn test_query_cache() {
+        let results = || (Arc::new(Schema::empty()), vec![]);
+        let cache = QueryCache::new(CacheConfig {
+            max_entries: 2,
+            ttl: None,
+        });
+        cache.insert(1, 5, results());
+        cache.insert(2, 5, results());
+        assert!(cache.get(&1).is_some());
+        cache.insert(3, 5, results());
+        assert!(cache.get(&2).is_none());
+        assert!(cache.get(&3).is_some());
+        // A new version of the table drops the results of the previous one.
+        cache.insert(4, 6, results());
+        assert!(cache.get(&1).is_none());
+        assert_eq!(
+            cache.stats(),
+            QueryCacheStats {
+                hits: 2,
+                misses: 2,
+                entries: 1
+            }
+        );
+
+        let cache = QueryCache::new(CacheConfig {
+            max_entries: 2,
+            ttl: Some(Duration::ZERO),
+        });
+        cache.insert(1, 5, results());
+        assert!(cache.get(&1).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_max_age() {
+        let cache = MetadataCache::new(1024 * 1024);
+        cache.insert("a", make_dataset().await);
+        assert!(cache.get("a", Some(Duration::from_secs(60))).is_some());
+        assert!(cache.get("a", Some(Duration::ZERO)).is_none());
+    }
+}
@@ -12,83 +12,795 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::collections::HashMap;
 use std::fs::create_dir_all;
-use std::path::Path;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
 
 use arrow_array::RecordBatchReader;
-use lance::dataset::WriteParams;
-use lance::io::object_store::ObjectStore;
-use snafu::prelude::*;
+use arrow_schema::{Schema, SchemaRef};
+use lance::dataset::{Dataset, ReadParams, WriteMode, WriteParams};
+use lance::io::object_store::{ObjectStore, ObjectStoreParams, WrappingObjectStore};
+use lance::session::Session;
+use tracing::{field, info_span};
 
-use crate::error::{CreateDirSnafu, Result};
-use crate::table::{OpenTableParams, Table};
+use crate::cache::{CacheStats, MetadataCache};
+use crate::embeddings::{
+    embed_batches, read_configs, write_configs, EmbeddingConfig, EmbeddingDefinition,
+    EmbeddingFunction, EmbeddingRegistry,
+};
+use crate::error::{Error, Result};
+use crate::io::auto_id::{with_auto_ids, with_versions};
+use crate::io::mirror::{cache_scope, MirrorCache, MirrorWrapper};
+use crate::io::object_store::{build_object_store, memory_object_store};
+use crate::io::uri::DatabaseUri;
+use crate::query::{ScanParams, SlowQueryCallback, SlowQueryHook};
+#[cfg(feature = "remote")]
+use crate::remote::{RemoteDatabase, RestfulClient};
+use crate::spans::timed;
+use crate::table::{
+    check_partition_column, AutoId, CommitLock, HealthCheckKind, HealthReport, NativeTable,
+    OpenTableParams, Partitioning, TableProperties, TableRef, WriteOptions,
+    DEFAULT_MAX_COMMIT_RETRIES, LAST_MODIFIED_VERSION_COLUMN,
+};
+
+mod multi_search;
+mod registry;
+mod transaction;
+
+pub use multi_search::{
+    MultiSearch, MultiSearchResults, OnTableError, TableSearchFailure, TABLE_NAME_COLUMN,
+};
+pub(crate) use registry::TableRegistry;
+pub use registry::{TableHandle, DEFAULT_MAX_OPEN_TABLES};
+pub use transaction::{RollbackFailure, Transaction};
+
+/// Default number of vector indices kept open by a connection.
+const DEFAULT_INDEX_CACHE_ENTRIES: usize = 256;
+
+/// Default byte budget of the local mirror of a connection.
+const DEFAULT_MIRROR_CACHE_SIZE_BYTES: usize = 1024 * 1024 * 1024;
 
 pub struct Database {
     object_store: ObjectStore,
+    store_params: ObjectStoreParams,
+    session: Arc<Session>,
+    metadata_cache: Option<Arc<MetadataCache>>,
+    read_consistency_interval: Option<Duration>,
+    max_commit_retries: usize,
+    slow_query: Option<SlowQueryHook>,
+    scan_params: ScanParams,
+    max_limit: Option<usize>,
+    write_options: WriteOptions,
+    index_build_threads: Option<usize>,
+    embedding_registry: Arc<EmbeddingRegistry>,
+    /// The tables opened by the [TableHandle]s of the connection.
+    tables: TableRegistry,
+    /// The server of a `db://` database.
+    #[cfg(feature = "remote")]
+    remote: Option<RemoteDatabase>,
 
     pub(crate) uri: String,
 }
 
 const LANCE_EXTENSION: &str = "lance";
+const MEMORY_SCHEME: &str = "memory://";
+const LATEST_MANIFEST: &str = "_latest.manifest";
 
-/// A connection to LanceDB
-impl Database {
-    /// Connects to LanceDB
+/// Connects to LanceDB
+///
+/// # Arguments
+///
+/// * `uri` - URI where the database is located, can be a local file or a supported remote cloud storage
+///
+/// # Returns
+///
+/// * A [ConnectBuilder] to configure and open the connection.
+pub fn connect(uri: &str) -> ConnectBuilder {
+    ConnectBuilder::new(uri)
+}
+
+/// A builder for a connection to LanceDB.
+pub struct ConnectBuilder {
+    uri: String,
+    storage_options: HashMap<String, String>,
+    strict_storage_options: bool,
+    read_consistency_interval: Option<Duration>,
+    index_cache_entries: usize,
+    metadata_cache_size_bytes: usize,
+    max_open_tables: usize,
+    max_commit_retries: usize,
+    create_dir: bool,
+    mirror: Option<PathBuf>,
+    mirror_cache_size_bytes: usize,
+    region: Option<String>,
+    slow_query: Option<SlowQueryHook>,
+    scan_params: ScanParams,
+    max_limit: Option<usize>,
+    write_options: WriteOptions,
+    index_build_threads: Option<usize>,
+    #[cfg(feature = "remote")]
+    api_key: Option<String>,
+    #[cfg(feature = "remote")]
+    host_override: Option<String>,
+}
+
+impl ConnectBuilder {
+    fn new(uri: &str) -> Self {
+        ConnectBuilder {
+            uri: uri.to_string(),
+            storage_options: HashMap::new(),
+            strict_storage_options: false,
+            read_consistency_interval: None,
+            index_cache_entries: DEFAULT_INDEX_CACHE_ENTRIES,
+            metadata_cache_size_bytes: 0,
+            max_open_tables: DEFAULT_MAX_OPEN_TABLES,
+            max_commit_retries: DEFAULT_MAX_COMMIT_RETRIES,
+            create_dir: false,
+            mirror: None,
+            mirror_cache_size_bytes: DEFAULT_MIRROR_CACHE_SIZE_BYTES,
+            region: None,
+            slow_query: None,
+            scan_params: ScanParams::default(),
+            max_limit: None,
+            write_options: WriteOptions::default(),
+            index_build_threads: None,
+            #[cfg(feature = "remote")]
+            api_key: None,
+            #[cfg(feature = "remote")]
+            host_override: None,
+        }
+    }
+
+    /// Set an option on the underlying object store.
+    ///
+    /// Keys follow the `object_store` configuration names, e.g. `aws_access_key_id`,
+    /// `google_service_account` or `azure_storage_account_name`. Explicit options
+    /// take precedence over environment variables.
+    ///
+    /// The options are used for the connection itself and for reading and
+    /// writing the tables opened through it.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The option name.
+    /// * `value` - The option value.
+    pub fn storage_option(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.storage_options.insert(key.into(), value.into());
+        self
+    }
+
+    /// Set several options on the underlying object store.
+    ///
+    /// See [ConnectBuilder::storage_option].
+    pub fn storage_options(
+        mut self,
+        options: impl IntoIterator<Item = (impl Into<String>, impl Into<String>)>,
+    ) -> Self {
+        for (key, value) in options {
+            self.storage_options.insert(key.into(), value.into());
+        }
+        self
+    }
+
+    /// Set the AWS access key id.
+    pub fn aws_access_key_id(self, access_key_id: impl Into<String>) -> Self {
+        self.storage_option("aws_access_key_id", access_key_id)
+    }
+
+    /// Set the AWS secret access key.
+    pub fn aws_secret_access_key(self, secret_access_key: impl Into<String>) -> Self {
+        self.storage_option("aws_secret_access_key", secret_access_key)
+    }
+
+    /// Set the AWS session token.
+    pub fn aws_session_token(self, session_token: impl Into<String>) -> Self {
+        self.storage_option("aws_session_token", session_token)
+    }
+
+    /// Set the region of the bucket, or of the LanceDB server for `db://` URIs.
+    pub fn region(mut self, region: impl Into<String>) -> Self {
+        let region = region.into();
+        self.region = Some(region.clone());
+        self.storage_option("aws_region", region)
+    }
+
+    /// Set the API key used to authenticate with a LanceDB server.
+    #[cfg(feature = "remote")]
+    pub fn api_key(mut self, api_key: impl Into<String>) -> Self {
+        self.api_key = Some(api_key.into());
+        self
+    }
+
+    /// Send the requests for a `db://` database to `host` instead of the hosted
+    /// LanceDB endpoint, e.g. for a self-hosted server.
+    #[cfg(feature = "remote")]
+    pub fn host_override(mut self, host: impl Into<String>) -> Self {
+        self.host_override = Some(host.into());
+        self
+    }
+
+    /// Set a custom endpoint, e.g. for an S3 compatible store.
+    pub fn endpoint(self, endpoint: impl Into<String>) -> Self {
+        self.storage_option("aws_endpoint", endpoint)
+    }
+
+    /// Whether unknown storage option keys are an error.
+    ///
+    /// By default unknown keys are logged and ignored.
+    pub fn strict_storage_options(mut self, strict: bool) -> Self {
+        self.strict_storage_options = strict;
+        self
+    }
+
+    /// How often tables opened from this connection check for newer versions
+    /// written by other processes.
+    ///
+    /// With `None` (the default) tables only see their own writes until reopened.
+    /// `Duration::ZERO` checks before every read.
+    pub fn read_consistency_interval(mut self, interval: Duration) -> Self {
+        self.read_consistency_interval = Some(interval);
+        self
+    }
+
+    /// Set the number of vector indices kept open in the cache shared by all tables
+    /// of this connection. Set to zero to disable the index cache. Default: 256.
+    ///
+    /// lance bounds the cache by its number of entries whatever their size, and
+    /// reports no hits or misses of it, see [Self::metadata_cache_size_bytes] for
+    /// the byte-bounded cache counted by [Database::cache_stats].
+    pub fn index_cache_entries(mut self, entries: usize) -> Self {
+        self.index_cache_entries = entries;
+        self
+    }
+
+    /// Set the byte budget of the table metadata cache shared by all tables of
+    /// this connection.
+    ///
+    /// Cached metadata lets tables be reopened without reading their manifest
+    /// again; entries expire after the `read_consistency_interval`. The cache is
+    /// disabled by default.
+    pub fn metadata_cache_size_bytes(mut self, size_bytes: usize) -> Self {
+        self.metadata_cache_size_bytes = size_bytes;
+        self
+    }
+
+    /// Set the number of tables the [Database::table] handles of this
+    /// connection keep open. The least recently used table is closed to open
+    /// another, and opened again when its handle is next used. Defaults to 128.
+    pub fn max_open_tables(mut self, max_open_tables: usize) -> Self {
+        self.max_open_tables = max_open_tables;
+        self
+    }
+
+    /// Set how many times a write to a table of this connection waits for another
+    /// writer to finish committing before failing with [Error::CommitConflict].
+    pub fn max_commit_retries(mut self, max_retries: usize) -> Self {
+        self.max_commit_retries = max_retries;
+        self
+    }
+
+    /// Call `callback` for every query of the tables of this connection that takes
+    /// longer than `threshold`.
+    ///
+    /// The callback runs on a blocking thread of the runtime, so a slow callback
+    /// does not hold up the results of the query.
+    pub fn on_slow_query(mut self, threshold: Duration, callback: SlowQueryCallback) -> Self {
+        self.slow_query = Some(SlowQueryHook::new(threshold, callback));
+        self
+    }
+
+    /// Set how many fragments the queries of the tables of this connection read
+    /// at the same time, see [crate::Query::io_parallelism].
+    pub fn io_parallelism(mut self, io_parallelism: usize) -> Self {
+        self.scan_params.io_parallelism = Some(io_parallelism);
+        self
+    }
+
+    /// Set how many batches the queries of the tables of this connection read
+    /// ahead, see [crate::Query::batch_readahead].
+    pub fn batch_readahead(mut self, batch_readahead: usize) -> Self {
+        self.scan_params.batch_readahead = Some(batch_readahead);
+        self
+    }
+
+    /// Set the size of the blocks the queries of the tables of this connection
+    /// read from object stores, see [crate::Query::io_coalesce_bytes].
+    pub fn io_coalesce_bytes(mut self, io_coalesce_bytes: usize) -> Self {
+        self.scan_params.io_coalesce_bytes = Some(io_coalesce_bytes);
+        self
+    }
+
+    /// Set the largest request the queries of the tables of this connection
+    /// issue to object stores, see [crate::Query::io_max_request_size].
+    pub fn io_max_request_size(mut self, io_max_request_size: usize) -> Self {
+        self.scan_params.io_max_request_size = Some(io_max_request_size);
+        self
+    }
+
+    /// Set how many requests to object stores each query of the tables of
+    /// this connection runs at the same time, see [crate::Query::io_concurrency].
+    pub fn io_concurrency(mut self, io_concurrency: usize) -> Self {
+        self.scan_params.io_concurrency = Some(io_concurrency);
+        self
+    }
+
+    /// Reject the queries of the tables of this connection whose limit is above
+    /// `max_limit` with an [Error::InvalidInput], to keep a large limit from
+    /// reading most of a table. By default limits are not capped.
+    pub fn max_limit(mut self, max_limit: usize) -> Self {
+        self.max_limit = Some(max_limit);
+        self
+    }
+
+    /// Set how the batches written to the tables of this connection are split
+    /// and checked, see [WriteOptions].
+    pub fn write_options(mut self, options: WriteOptions) -> Self {
+        self.write_options = options;
+        self
+    }
+
+    /// Set how many fragments the index builds of the tables of this
+    /// connection assign to partitions at the same time, see
+    /// [crate::index::vector::IvfPQIndexBuilder::num_threads]. Defaults to the
+    /// available parallelism.
+    pub fn index_build_threads(mut self, num_threads: usize) -> Self {
+        self.index_build_threads = Some(num_threads);
+        self
+    }
+
+    /// Whether to create the database directory if it does not exist.
+    ///
+    /// Only applies to local paths. By default connecting to a missing directory
+    /// fails with [Error::DatabaseNotFound].
+    pub fn create_dir(mut self, create_dir: bool) -> Self {
+        self.create_dir = create_dir;
+        self
+    }
+
+    /// Cache the immutable files of the tables, data and index files, in a local
+    /// directory.
+    ///
+    /// Writes go straight to the primary store and files are copied to the cache
+    /// the first time they are read. Manifests are always read from the primary,
+    /// so other writers are seen as usual. Only applies to object store URIs,
+    /// local tables are read in place. The files of each bucket and endpoint
+    /// are cached in a directory of their own, a cache directory can be shared
+    /// by connections to different stores.
     ///
     /// # Arguments
     ///
-    /// * `path` - URI where the database is located, can be a local file or a supported remote cloud storage
+    /// * `local_path` - The cache directory, created if it does not exist.
+    pub fn mirror(mut self, local_path: impl Into<PathBuf>) -> Self {
+        self.mirror = Some(local_path.into());
+        self
+    }
+
+    /// Set the byte budget of the [ConnectBuilder::mirror] cache. The least
+    /// recently read files are removed to stay within it. Defaults to 1 GiB.
+    pub fn mirror_cache_size_bytes(mut self, size_bytes: usize) -> Self {
+        self.mirror_cache_size_bytes = size_bytes;
+        self
+    }
+
+    /// Open the connection.
     ///
     /// # Returns
     ///
     /// * A [Database] object.
-    pub async fn connect(uri: &str) -> Result<Database> {
-        let (object_store, _) = ObjectStore::from_uri(uri).await?;
-        if object_store.is_local() {
-            Self::try_create_dir(uri).context(CreateDirSnafu { path: uri })?;
+    pub async fn execute(self) -> Result<Database> {
+        self.scan_params.validate()?;
+        self.write_options.validate()?;
+        if self.max_limit == Some(0) {
+            return Err(Error::InvalidInput {
+                message: "max_limit must be at least 1".to_string(),
+            });
+        }
+        if self.index_build_threads == Some(0) {
+            return Err(Error::InvalidInput {
+                message: "index_build_threads must be at least 1".to_string(),
+            });
+        }
+        if self.max_open_tables == 0 {
+            return Err(Error::InvalidInput {
+                message: "max_open_tables must be at least 1".to_string(),
+            });
+        }
+        let uri = DatabaseUri::parse(&self.uri)?;
+        match &uri {
+            DatabaseUri::Local(path) => Database::check_local_dir(path, self.create_dir)?,
+            DatabaseUri::Remote { db_name, .. } => return self.connect_remote(&uri, db_name),
+            DatabaseUri::ObjectStore(_) => {}
+        }
+        let uri = uri.as_str();
+        let mut object_store_wrapper =
+            build_object_store(uri, &self.storage_options, self.strict_storage_options)?
+                .map(|store| Arc::new(store) as Arc<dyn WrappingObjectStore>);
+        if uri.starts_with(MEMORY_SCHEME) {
+            object_store_wrapper = Some(Arc::new(memory_object_store()));
         }
+        if let Some(mirror) = self
+            .mirror
+            .as_ref()
+            .filter(|_| !uri.starts_with(MEMORY_SCHEME))
+        {
+            let cache =
+                MirrorCache::try_new(mirror, self.mirror_cache_size_bytes).map_err(|source| {
+                    Error::CreateDir {
+                        path: mirror.to_string_lossy().to_string(),
+                        source,
+                    }
+                })?;
+            object_store_wrapper = Some(Arc::new(MirrorWrapper::new(
+                Arc::new(cache),
+                cache_scope(uri, &self.storage_options),
+                object_store_wrapper,
+            )));
+        }
+        let store_params = ObjectStoreParams {
+            object_store_wrapper,
+        };
+        let (object_store, _) = ObjectStore::from_uri_and_params(uri, store_params.clone()).await?;
+        let metadata_cache = (self.metadata_cache_size_bytes > 0)
+            .then(|| Arc::new(MetadataCache::new(self.metadata_cache_size_bytes)));
         Ok(Database {
             uri: uri.to_string(),
             object_store,
+            store_params,
+            session: Arc::new(Session::new(self.index_cache_entries)),
+            metadata_cache,
+            read_consistency_interval: self.read_consistency_interval,
+            max_commit_retries: self.max_commit_retries,
+            slow_query: self.slow_query.clone(),
+            scan_params: self.scan_params,
+            max_limit: self.max_limit,
+            write_options: self.write_options.clone(),
+            index_build_threads: self.index_build_threads,
+            embedding_registry: Arc::new(EmbeddingRegistry::default()),
+            tables: TableRegistry::new(self.max_open_tables),
+            #[cfg(feature = "remote")]
+            remote: None,
+        })
+    }
+}
+
+impl ConnectBuilder {
+    #[cfg(feature = "remote")]
+    fn connect_remote(&self, uri: &DatabaseUri, db_name: &str) -> Result<Database> {
+        let client = RestfulClient::try_new(
+            db_name,
+            self.api_key.as_deref(),
+            self.region.as_deref(),
+            self.host_override.as_deref(),
+        )?;
+        Ok(Database {
+            uri: uri.as_str().to_string(),
+            // Remote databases never use the object store.
+            object_store: ObjectStore::memory(),
+            store_params: ObjectStoreParams::default(),
+            session: Arc::new(Session::new(0)),
+            metadata_cache: None,
+            read_consistency_interval: None,
+            max_commit_retries: self.max_commit_retries,
+            slow_query: self.slow_query.clone(),
+            scan_params: self.scan_params,
+            max_limit: self.max_limit,
+            write_options: self.write_options.clone(),
+            index_build_threads: self.index_build_threads,
+            embedding_registry: Arc::new(EmbeddingRegistry::default()),
+            tables: TableRegistry::new(self.max_open_tables),
+            remote: Some(RemoteDatabase::new(
+                client,
+                self.slow_query.clone(),
+                self.max_limit,
+            )),
+        })
+    }
+
+    #[cfg(not(feature = "remote"))]
+    fn connect_remote(&self, uri: &DatabaseUri, _db_name: &str) -> Result<Database> {
+        Err(Error::InvalidUri {
+            uri: uri.as_str().to_string(),
+            reason: "db:// URIs need the `remote` feature".to_string(),
         })
     }
+}
+
+/// Customizes the [OpenTableParams] of a table, see [CreateTableMode::ExistOk].
+pub type OpenTableCallback = Box<dyn Fn(OpenTableParams) -> OpenTableParams + Send + Sync>;
+
+/// What [CreateTableBuilder::execute] does if the table already exists.
+#[derive(Default)]
+pub enum CreateTableMode {
+    /// Fail with [Error::TableAlreadyExists].
+    #[default]
+    Create,
+    /// Replace the existing table.
+    Overwrite,
+    /// Open the existing table with the [OpenTableParams] returned by the
+    /// callback, without writing the data. A local table must have a column of
+    /// the same type for each column of the data, or this fails with [Error::Schema].
+    ///
+    /// The creators of a table in the same directory, or of a table in object
+    /// storage in the same process, take turns: the first creates the table,
+    /// and the others open it.
+    ExistOk(OpenTableCallback),
+}
+
+impl CreateTableMode {
+    /// [CreateTableMode::ExistOk] with the given callback.
+    pub fn exist_ok(
+        callback: impl Fn(OpenTableParams) -> OpenTableParams + Send + Sync + 'static,
+    ) -> Self {
+        Self::ExistOk(Box::new(callback))
+    }
+}
+
+/// Returns the initial data of a table, see [Database::create_table_lazy].
+pub type TableDataCallback = Box<dyn FnOnce() -> Result<Box<dyn RecordBatchReader>>>;
+
+/// The initial data of a [CreateTableBuilder].
+enum TableData {
+    Batches(Box<dyn RecordBatchReader>),
+    /// Only called if the table is created.
+    Lazy(TableDataCallback),
+}
+
+impl TableData {
+    /// The schema of the data, `None` if it is not read yet.
+    fn schema(&self) -> Option<SchemaRef> {
+        match self {
+            Self::Batches(batches) => Some(batches.schema()),
+            Self::Lazy(_) => None,
+        }
+    }
+
+    fn read(self) -> Result<Box<dyn RecordBatchReader>> {
+        match self {
+            Self::Batches(batches) => Ok(batches),
+            Self::Lazy(callback) => callback(),
+        }
+    }
+}
+
+/// A builder for a new table, see [Database::create_table].
+pub struct CreateTableBuilder<'a> {
+    db: &'a Database,
+    name: String,
+    data: TableData,
+    params: Option<WriteParams>,
+    mode: Option<CreateTableMode>,
+    embeddings: Vec<EmbeddingDefinition>,
+    stable_row_ids: bool,
+    auto_id: Option<String>,
+    partition_by: Option<String>,
+    track_modifications: bool,
+}
+
+impl CreateTableBuilder<'_> {
+    /// Set the [WriteParams] used to write the initial data.
+    pub fn write_params(mut self, params: WriteParams) -> Self {
+        self.params = Some(params);
+        self
+    }
+
+    /// Set what to do if the table already exists, this replaces the mode of
+    /// the [WriteParams]. Default: [CreateTableMode::Create].
+    pub fn mode(mut self, mode: CreateTableMode) -> Self {
+        self.mode = Some(mode);
+        self
+    }
+
+    /// Compute `vector_column` from `source_column` with `function`.
+    ///
+    /// The vectors are computed for the initial data, and for data added to the
+    /// table later on whenever it does not carry the vector column itself.
+    pub fn embedding(
+        mut self,
+        source_column: &str,
+        vector_column: &str,
+        function: Arc<dyn EmbeddingFunction>,
+    ) -> Self {
+        self.embeddings.push(EmbeddingDefinition::new(
+            source_column,
+            vector_column,
+            function,
+        ));
+        self
+    }
+
+    /// Whether the rows of the table keep their `_rowid`, see
+    /// [crate::Query::with_row_id], so that ids kept outside of the table stay
+    /// valid. The compaction of [NativeTable::optimize] moves rows, so it
+    /// leaves these tables as they are. Default: `false`.
+    pub fn enable_stable_row_ids(mut self, enable: bool) -> Self {
+        self.stable_row_ids = enable;
+        self
+    }
+
+    /// Add the column `column` of `UInt64` ids, assigned in increasing order to
+    /// the rows of the initial data and of every later add.
+    ///
+    /// The written data must not have the column. The id of the next row is
+    /// recorded with the table and read while holding its commit lock, so the
+    /// ids of deleted rows are not reused and concurrent appends get distinct
+    /// ids. The rows dropped by [crate::table::OnBadVectors::Drop] leave gaps.
+    /// The ids can be made the primary key of the table, see
+    /// [NativeTable::ensure_primary_key], and used as the key of
+    /// [NativeTable::merge].
+    pub fn auto_id(mut self, column: &str) -> Self {
+        self.auto_id = Some(column.to_string());
+        self
+    }
+
+    /// Write the rows of each value of `column` to fragments of their own, in
+    /// the initial data and in every later add.
+    ///
+    /// The column must be of an integer or string type. A flat search whose
+    /// filter requires values of the column with `=` or `IN` only reads the
+    /// fragments of those values, see [crate::query::QueryMetrics::fragments_skipped].
+    /// Searches of an index read the index whatever the filter, and the
+    /// fragments merged by [NativeTable::optimize] are read by every search.
+    /// Not supported by remote databases.
+    pub fn partition_by(mut self, column: &str) -> Self {
+        self.partition_by = Some(column.to_string());
+        self
+    }
+
+    /// Add the `UInt64` column [LAST_MODIFIED_VERSION_COLUMN], set to the
+    /// version of the table each row is written in, by the initial data and
+    /// by every later add, so that [NativeTable::changed_since] finds the rows
+    /// written after a version.
+    ///
+    /// The written data must not have the column. The columns added by
+    /// [NativeTable::merge] do not change the versions of the rows. Not
+    /// supported by remote databases. Default: `false`.
+    pub fn track_modifications(mut self, track: bool) -> Self {
+        self.track_modifications = track;
+        self
+    }
+
+    /// Create the table.
+    ///
+    /// # Returns
+    ///
+    /// * A [TableRef] to the new table.
+    pub async fn execute(self) -> Result<TableRef> {
+        let span = info_span!(
+            "create_table",
+            table = %self.name,
+            rows = field::Empty,
+            elapsed_ms = field::Empty
+        );
+        timed(span, self.db.create_table_from(self)).await
+    }
+}
+
+/// A connection to LanceDB
+impl Database {
+    /// Connects to LanceDB
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - URI where the database is located, can be a local file or a supported remote cloud storage.
+    ///   A local directory must already exist, see [ConnectBuilder::create_dir].
+    ///
+    /// # Returns
+    ///
+    /// * A [Database] object.
+    pub async fn connect(uri: &str) -> Result<Database> {
+        connect(uri).execute().await
+    }
+
+    /// Check that the local database directory exists and is readable, creating
+    /// it first if `create_dir` is set.
+    fn check_local_dir(path: &str, create_dir: bool) -> Result<()> {
+        let io_error = |source: std::io::Error| match source.kind() {
+            ErrorKind::PermissionDenied => Error::PermissionDenied {
+                uri: path.to_string(),
+                source,
+            },
+            _ => Error::CreateDir {
+                path: path.to_string(),
+                source,
+            },
+        };
+        let dir = Path::new(path);
+        if !dir.try_exists().map_err(io_error)? {
+            if !create_dir {
+                return Err(Error::DatabaseNotFound {
+                    uri: path.to_string(),
+                });
+            }
+            create_dir_all(dir).map_err(io_error)?;
+        }
+        if !dir.is_dir() {
+            return Err(Error::InvalidUri {
+                uri: path.to_string(),
+                reason: "not a directory".to_string(),
+            });
+        }
+        std::fs::read_dir(dir).map_err(io_error)?;
+        Ok(())
+    }
+
+    /// Check that the storage of the database can be reached, for readiness
+    /// probes: stat and list its local directory, list the root of its object
+    /// store, or list the tables of its server.
+    ///
+    /// Fails with an [Error::DatabaseNotFound] or [Error::PermissionDenied] if
+    /// the local directory is gone or cannot be read, an [Error::ObjectStore]
+    /// if the object store cannot be listed, and an [Error::Timeout] if the
+    /// check takes longer than [crate::table::HEALTH_CHECK_TIMEOUT].
+    pub async fn health_check(&self) -> Result<HealthReport> {
+        let mut report = HealthReport::new(&self.uri);
+        #[cfg(feature = "remote")]
+        if let Some(remote) = self.remote.as_ref() {
+            report
+                .run(HealthCheckKind::Storage, remote.table_names())
+                .await?;
+            return Ok(report);
+        }
+        report
+            .run(HealthCheckKind::Storage, self.list_root())
+            .await?;
+        Ok(report)
+    }
 
-    /// Try to create a local directory to store the lancedb dataset
-    fn try_create_dir(path: &str) -> core::result::Result<(), std::io::Error> {
-        let path = Path::new(path);
-        if !path.try_exists()? {
-            create_dir_all(&path)?;
+    /// List the root of the database, without reading the tables in it.
+    async fn list_root(&self) -> Result<()> {
+        if let DatabaseUri::Local(path) = DatabaseUri::parse(&self.uri)? {
+            return Database::check_local_dir(&path, false);
         }
+        let path = self.object_path(&self.uri)?;
+        self.object_store
+            .inner
+            .list_with_delimiter(Some(&path))
+            .await
+            .map_err(|source| Error::ObjectStore {
+                uri: self.uri.clone(),
+                source,
+            })?;
         Ok(())
     }
 
     /// Get the names of all tables in the database.
     ///
+    /// Directories without a manifest, e.g. left by a failed creation, are not
+    /// tables.
+    ///
     /// # Returns
     ///
     /// * A [Vec<String>] with all table names.
     pub async fn table_names(&self) -> Result<Vec<String>> {
+        #[cfg(feature = "remote")]
+        if let Some(remote) = self.remote.as_ref() {
+            return remote.table_names().await;
+        }
         let f = self
             .object_store
-            .read_dir(self.uri.as_str())
+            .read_dir(self.object_path(&self.uri)?)
             .await?
             .iter()
-            .map(|fname| Path::new(fname))
+            .map(Path::new)
             .filter(|path| {
                 let is_lance = path
                     .extension()
-                    .map(|e| e.to_str().map(|e| e == LANCE_EXTENSION))
-                    .flatten();
+                    .and_then(|e| e.to_str().map(|e| e == LANCE_EXTENSION));
                 is_lance.unwrap_or(false)
             })
-            .map(|p| {
-                p.file_stem()
-                    .map(|s| s.to_str().map(|s| String::from(s)))
-                    .flatten()
-            })
-            .flatten()
-            .collect();
-        Ok(f)
+            .filter_map(|p| p.file_stem().and_then(|s| s.to_str().map(String::from)))
+            .collect::<Vec<_>>();
+        let mut tables = Vec::with_capacity(f.len());
+        for name in f {
+            if self.is_dataset(&name).await? {
+                tables.push(name);
+            }
+        }
+        Ok(tables)
     }
 
     /// Create a new table in the database.
@@ -96,14 +808,309 @@ impl Database {
     /// # Arguments
     /// * `name` - The name of the table.
     /// * `batches` - The initial data to write to the table.
-    /// * `params` - Optional [`WriteParams`] to create the table.
-    pub async fn create_table(
+    ///
+    /// # Returns
+    ///
+    /// * A [CreateTableBuilder] to configure and create the table.
+    pub fn create_table(
+        &self,
+        name: &str,
+        batches: Box<dyn RecordBatchReader>,
+    ) -> CreateTableBuilder<'_> {
+        self.create_table_of(name, TableData::Batches(batches))
+    }
+
+    /// Create a new table in the database, of the initial data returned by
+    /// `data_fn`, which is only called if the table is created.
+    ///
+    /// With [CreateTableMode::ExistOk], a table that exists, or that another
+    /// writer creates first, is opened without calling `data_fn`, and without
+    /// checking the columns of the data against those of the table.
+    pub fn create_table_lazy(
+        &self,
+        name: &str,
+        data_fn: impl FnOnce() -> Result<Box<dyn RecordBatchReader>> + 'static,
+    ) -> CreateTableBuilder<'_> {
+        self.create_table_of(name, TableData::Lazy(Box::new(data_fn)))
+    }
+
+    fn create_table_of(&self, name: &str, data: TableData) -> CreateTableBuilder<'_> {
+        CreateTableBuilder {
+            db: self,
+            name: name.to_string(),
+            data,
+            params: None,
+            mode: None,
+            embeddings: Vec::new(),
+            stable_row_ids: false,
+            auto_id: None,
+            partition_by: None,
+            track_modifications: false,
+        }
+    }
+
+    async fn create_table_from(&self, builder: CreateTableBuilder<'_>) -> Result<TableRef> {
+        let CreateTableBuilder {
+            name,
+            data,
+            params,
+            mode,
+            embeddings,
+            stable_row_ids,
+            auto_id,
+            partition_by,
+            track_modifications,
+            ..
+        } = builder;
+        let properties = TableProperties {
+            stable_row_ids,
+            auto_id: auto_id.map(|column| AutoId { column, next: 0 }),
+            partition_by: partition_by.map(|column| Partitioning::new(&column)),
+            track_modifications,
+            ..Default::default()
+        };
+        let params = match mode {
+            None => params,
+            Some(CreateTableMode::Overwrite) => Some(WriteParams {
+                mode: WriteMode::Overwrite,
+                ..params.unwrap_or_default()
+            }),
+            Some(_) => Some(WriteParams {
+                mode: WriteMode::Create,
+                ..params.unwrap_or_default()
+            }),
+        };
+        let Some(CreateTableMode::ExistOk(callback)) = mode else {
+            return self
+                .write_table(&name, data.read()?, params, embeddings, properties)
+                .await;
+        };
+        let schema = data.schema();
+        let open = || {
+            let params = callback(OpenTableParams::default());
+            self.open_existing(&name, params, schema.as_deref())
+        };
+        match open().await {
+            Err(Error::TableNotFound { .. }) => {}
+            result => return result,
+        }
+        // The server of a remote database creates the table once.
+        let _lock = match self.is_remote() {
+            true => None,
+            false => {
+                let uri = NativeTable::table_uri(&self.uri, &name)?;
+                Some(CommitLock::acquire_create(&uri).await?)
+            }
+        };
+        // Another creator may have created the table while the lock was held.
+        match open().await {
+            Err(Error::TableNotFound { .. }) => {}
+            result => return result,
+        }
+        let written = self.write_table(&name, data.read()?, params, embeddings, properties);
+        match written.await {
+            // Another writer created the table in the meantime.
+            Err(Error::TableAlreadyExists { .. }) => open().await,
+            result => result,
+        }
+    }
+
+    /// Open `name` for [CreateTableMode::ExistOk], checking that data of
+    /// `schema` can be written to it if it is known.
+    async fn open_existing(
+        &self,
+        name: &str,
+        params: OpenTableParams,
+        schema: Option<&Schema>,
+    ) -> Result<TableRef> {
+        #[cfg(feature = "remote")]
+        if let Some(remote) = self.remote.as_ref() {
+            return remote.open_table(name).await;
+        }
+        let table = self.open_native_table(name, params).await?;
+        let Some(schema) = schema else {
+            return Ok(Arc::new(table));
+        };
+        let table_schema = table.schema().await?;
+        for field in schema.fields() {
+            match table_schema.field_with_name(field.name()) {
+                Ok(existing) if existing.data_type() == field.data_type() => {}
+                Ok(existing) => {
+                    return Err(Error::Schema {
+                        message: format!(
+                            "column '{}' of table '{name}' has type {}, the data has type {}",
+                            field.name(),
+                            existing.data_type(),
+                            field.data_type()
+                        ),
+                    })
+                }
+                Err(_) => {
+                    return Err(Error::Schema {
+                        message: format!("table '{name}' has no column '{}'", field.name()),
+                    })
+                }
+            }
+        }
+        Ok(Arc::new(table))
+    }
+
+    async fn write_table(
         &self,
         name: &str,
         batches: Box<dyn RecordBatchReader>,
         params: Option<WriteParams>,
-    ) -> Result<Table> {
-        Table::create(&self.uri, name, batches, params).await
+        embeddings: Vec<EmbeddingDefinition>,
+        properties: TableProperties,
+    ) -> Result<TableRef> {
+        #[cfg(feature = "remote")]
+        if let Some(remote) = self.remote.as_ref() {
+            if !embeddings.is_empty() {
+                return Err(Error::InvalidInput {
+                    message: "remote tables do not support embedding functions".to_string(),
+                });
+            }
+            if properties.stable_row_ids {
+                return Err(Error::InvalidInput {
+                    message: "remote tables do not support stable row ids".to_string(),
+                });
+            }
+            if properties.auto_id.is_some() {
+                return Err(Error::InvalidInput {
+                    message: "remote tables do not support auto ids".to_string(),
+                });
+            }
+            if properties.partition_by.is_some() {
+                return Err(Error::InvalidInput {
+                    message: "remote tables do not support partitioning".to_string(),
+                });
+            }
+            if properties.track_modifications {
+                return Err(Error::InvalidInput {
+                    message: "remote tables do not support modification tracking".to_string(),
+                });
+            }
+            return remote.create_table(name, batches, params).await;
+        }
+        let partition_by = properties.partition_by.as_ref().map(|p| p.column.clone());
+        let overwrite = params
+            .as_ref()
+            .is_some_and(|p| matches!(p.mode, WriteMode::Overwrite));
+        // A replaced table is opened again by its handles.
+        if overwrite {
+            self.tables.forget(name);
+        }
+        let mut batches = embed_batches(&embeddings, batches).await?;
+        let next_id = Arc::new(AtomicU64::new(0));
+        if let Some(auto_id) = properties.auto_id.as_ref() {
+            if params
+                .as_ref()
+                .is_some_and(|p| matches!(p.mode, WriteMode::Append))
+            {
+                return Err(Error::InvalidInput {
+                    message:
+                        "an auto id column is added when the table is created, not appended to"
+                            .to_string(),
+                });
+            }
+            batches = with_auto_ids(batches, &auto_id.column, next_id.clone())?;
+        }
+        if properties.track_modifications {
+            // The version the write commits, after that of a replaced table.
+            let replaced = params
+                .as_ref()
+                .is_some_and(|p| !matches!(p.mode, WriteMode::Create));
+            let uri = NativeTable::table_uri(&self.uri, name)?;
+            let read_params = ReadParams {
+                store_options: Some(self.store_params.clone()),
+                ..Default::default()
+            };
+            let version = match Dataset::open_with_params(&uri, &read_params).await {
+                Ok(existing) if replaced => existing.version().version + 1,
+                _ => 1,
+            };
+            let version = Arc::new(AtomicU64::new(version));
+            batches = with_versions(batches, LAST_MODIFIED_VERSION_COLUMN, version)?;
+        }
+        if let Some(column) = partition_by.as_deref() {
+            check_partition_column(&batches.schema(), column)?;
+        }
+        // The id after those of the initial data, once it is written.
+        let written = |properties: TableProperties| TableProperties {
+            auto_id: properties.auto_id.map(|auto_id| AutoId {
+                next: next_id.load(Ordering::Relaxed),
+                ..auto_id
+            }),
+            ..properties
+        };
+        let table = NativeTable::create_with_cache(
+            &self.uri,
+            name,
+            batches,
+            params,
+            self.open_table_params(OpenTableParams::default()),
+            self.metadata_cache.clone(),
+            partition_by.as_deref(),
+        )
+        .await?;
+        // The properties of a replaced table do not hold for the new rows.
+        let mut properties = written(properties);
+        // The values of the written fragments and the metadata of the
+        // written schema, recorded by the table.
+        let recorded = table.properties().await?;
+        if partition_by.is_some() {
+            properties.partition_by = recorded.partition_by;
+        }
+        properties.schema_metadata = recorded.schema_metadata;
+        properties.field_metadata = recorded.field_metadata;
+        if properties != TableProperties::default() || overwrite {
+            table.set_properties(properties).await?;
+        }
+        // The embeddings of a replaced table are kept for its versions, the
+        // new ones start from the written version.
+        if !embeddings.is_empty() || overwrite {
+            self.embedding_registry.register_missing(&embeddings);
+            let base = self.object_path(table.uri())?;
+            let configs = embeddings
+                .iter()
+                .map(EmbeddingConfig::from)
+                .collect::<Vec<_>>();
+            write_configs(&self.object_store, &base, table.version(), &configs).await?;
+        }
+        Ok(Arc::new(table.with_embeddings(embeddings)))
+    }
+
+    /// Create the table `name` from the rows of the Polars DataFrame `df`.
+    ///
+    /// Polars has no fixed size lists, so the vector columns are given with
+    /// their dimensions in `vector_columns`: their `List` values become
+    /// `FixedSizeList<Float32>` vectors, and a list of another length fails
+    /// with the row it is in. Categorical columns are written as strings.
+    #[cfg(feature = "polars")]
+    pub async fn create_table_from_polars(
+        &self,
+        name: &str,
+        df: &polars::prelude::DataFrame,
+        vector_columns: &[(&str, i32)],
+    ) -> Result<TableRef> {
+        let vector_columns = vector_columns
+            .iter()
+            .map(|(column, dims)| (column.to_string(), *dims))
+            .collect();
+        let batches = crate::io::dataframe::dataframe_to_batches(df, &vector_columns)?;
+        self.create_table(name, batches).execute().await
+    }
+
+    /// Create the table `name` from the Arrow IPC file at `path`, reading one
+    /// batch at a time.
+    #[cfg(feature = "ipc")]
+    pub async fn create_table_from_ipc(
+        &self,
+        name: &str,
+        path: impl AsRef<Path>,
+    ) -> Result<TableRef> {
+        let batches = crate::io::ipc::read_ipc_file(path.as_ref())?;
+        self.create_table(name, batches).execute().await
     }
 
     /// Open a table in the database.
@@ -113,8 +1120,8 @@ impl Database {
     ///
     /// # Returns
     ///
-    /// * A [Table] object.
-    pub async fn open_table(&self, name: &str) -> Result<Table> {
+    /// * A [TableRef] to the table.
+    pub async fn open_table(&self, name: &str) -> Result<TableRef> {
         self.open_table_with_params(name, OpenTableParams::default())
             .await
     }
@@ -127,60 +1134,305 @@ impl Database {
     ///
     /// # Returns
     ///
-    /// * A [Table] object.
+    /// * A [TableRef] to the table.
     pub async fn open_table_with_params(
         &self,
         name: &str,
         params: OpenTableParams,
-    ) -> Result<Table> {
-        Table::open_with_params(&self.uri, name, params).await
+    ) -> Result<TableRef> {
+        #[cfg(feature = "remote")]
+        if let Some(remote) = self.remote.as_ref() {
+            return remote.open_table(name).await;
+        }
+        Ok(Arc::new(self.open_native_table(name, params).await?))
     }
 
-    /// Drop a table in the database.
+    /// A handle to the table `name`, which opens the table when it is used
+    /// and keeps it open while it is among the
+    /// [ConnectBuilder::max_open_tables] used last.
     ///
-    /// # Arguments
-    /// * `name` - The name of the table.
-    pub async fn drop_table(&self, name: &str) -> Result<()> {
-        let dir_name = format!("{}/{}.{}", self.uri, name, LANCE_EXTENSION);
-        self.object_store.remove_dir_all(dir_name).await?;
-        Ok(())
+    /// Nothing is read until the handle is used, see [TableHandle::table].
+    pub fn table(&self, name: &str) -> TableHandle<'_> {
+        TableHandle::new(self, name)
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use std::fs::create_dir_all;
-    use tempfile::tempdir;
+    /// Start a [Transaction], writes to several tables of this database that
+    /// are rolled back together when one of them fails.
+    ///
+    /// Not supported by remote databases.
+    pub fn transaction(&self) -> Transaction<'_> {
+        Transaction::new(self)
+    }
 
-    use crate::database::Database;
+    async fn open_native_table(&self, name: &str, params: OpenTableParams) -> Result<NativeTable> {
+        let table = NativeTable::open_with_cache(
+            &self.uri,
+            name,
+            self.open_table_params(params),
+            self.metadata_cache.clone(),
+        )
+        .await?;
+        let embeddings = self.read_embeddings(&table).await?;
+        Ok(table.with_embeddings(embeddings))
+    }
 
-    #[tokio::test]
-    async fn test_connect() {
-        let tmp_dir = tempdir().unwrap();
-        let uri = tmp_dir.path().to_str().unwrap();
-        let db = Database::connect(uri).await.unwrap();
+    /// Restore the embeddings persisted with the version of `table`.
+    async fn read_embeddings(&self, table: &NativeTable) -> Result<Vec<EmbeddingDefinition>> {
+        let base = self.object_path(table.uri())?;
+        let configs = read_configs(&self.object_store, &base, table.version()).await?;
+        self.embedding_registry.rehydrate(configs)
+    }
 
-        assert_eq!(db.uri, uri);
+    /// The path of `uri` within the object store of the connection.
+    fn object_path(&self, uri: &str) -> Result<object_store::path::Path> {
+        match url::Url::parse(uri) {
+            Ok(url) if url.scheme().len() > 1 => Ok(object_store::path::Path::parse(url.path())?),
+            _ => Ok(object_store::path::Path::parse(uri)?),
+        }
     }
 
-    #[tokio::test]
-    async fn test_table_names() {
-        let tmp_dir = tempdir().unwrap();
-        create_dir_all(tmp_dir.path().join("table1.lance")).unwrap();
-        create_dir_all(tmp_dir.path().join("table2.lance")).unwrap();
-        create_dir_all(tmp_dir.path().join("invalidlance")).unwrap();
+    /// Fill in the connection-level defaults that `params` leaves unset.
+    fn open_table_params(&self, params: OpenTableParams) -> OpenTableParams {
+        let ReadParams {
+            block_size,
+            index_cache_size,
+            session,
+            store_options,
+        } = params.open_table_params;
+        OpenTableParams {
+            open_table_params: ReadParams {
+                block_size,
+                index_cache_size,
+                session: session.or_else(|| Some(self.session.clone())),
+                store_options: store_options.or_else(|| Some(self.store_params.clone())),
+            },
+            read_consistency_interval: params
+                .read_consistency_interval
+                .or(self.read_consistency_interval),
+            max_commit_retries: params.max_commit_retries.or(Some(self.max_commit_retries)),
+            slow_query: params.slow_query.or_else(|| self.slow_query.clone()),
+            scan_params: params.scan_params.or(self.scan_params),
+            max_limit: params.max_limit.or(self.max_limit),
+            write_options: params
+                .write_options
+                .or_else(|| Some(self.write_options.clone())),
+            index_build_threads: params.index_build_threads.or(self.index_build_threads),
+        }
+    }
 
-        let uri = tmp_dir.path().to_str().unwrap();
-        let db = Database::connect(uri).await.unwrap();
-        let tables = db.table_names().await.unwrap();
-        assert_eq!(tables.len(), 2);
-        assert!(tables.contains(&String::from("table1")));
-        assert!(tables.contains(&String::from("table2")));
+    /// The embedding functions of this connection.
+    pub fn embedding_registry(&self) -> &EmbeddingRegistry {
+        &self.embedding_registry
     }
 
-    #[tokio::test]
-    async fn test_connect_s3() {
-        // let db = Database::connect("s3://bucket/path/to/database").await.unwrap();
+    /// Hit / miss statistics of the caches shared by tables of this connection.
+    ///
+    /// The index cache of lance, see [ConnectBuilder::index_cache_entries], is
+    /// not counted.
+    pub fn cache_stats(&self) -> CacheStats {
+        let stats = self
+            .metadata_cache
+            .as_ref()
+            .map(|cache| cache.stats())
+            .unwrap_or_default();
+        CacheStats {
+            open_tables: self.tables.open_tables(),
+            table_opens: self.tables.opens(),
+            ..stats
+        }
+    }
+
+    /// Drop every table of the database.
+    ///
+    /// Only the [Self::table_names] are removed, anything else under the
+    /// database root is left alone.
+    ///
+    /// # Arguments
+    /// * `force` - Must be set to drop the tables of a database at the root of a
+    ///   bucket, which is likely shared with other data.
+    ///
+    /// # Returns
+    ///
+    /// * The names of the dropped tables.
+    pub async fn drop_all_tables(&self, force: bool) -> Result<Vec<String>> {
+        self.check_drop_allowed(force)?;
+        let mut dropped = Vec::new();
+        for name in self.table_names().await? {
+            self.drop_table(&name).await?;
+            dropped.push(name);
+        }
+        dropped.sort();
+        Ok(dropped)
+    }
+
+    /// Drop every table of the database and remove the database root itself,
+    /// unless it holds anything else, which is left alone.
+    ///
+    /// The connection is consumed, as there is nothing left to connect to.
+    ///
+    /// # Arguments
+    /// * `force` - Must be set to drop a database at the root of a bucket.
+    ///
+    /// # Returns
+    ///
+    /// * The names of the dropped tables.
+    pub async fn drop_database(self, force: bool) -> Result<Vec<String>> {
+        let dropped = self.drop_all_tables(force).await?;
+        #[cfg(feature = "remote")]
+        if self.remote.is_some() {
+            return Ok(dropped);
+        }
+        let root = self.object_path(&self.uri)?;
+        if self.object_store.read_dir(root.clone()).await?.is_empty() {
+            self.object_store.remove_dir_all(root).await?;
+        }
+        Ok(dropped)
+    }
+
+    fn check_drop_allowed(&self, force: bool) -> Result<()> {
+        if !force && DatabaseUri::parse(&self.uri)?.is_storage_root() {
+            return Err(Error::InvalidInput {
+                message: format!(
+                    "refusing to drop the tables at the storage root {} without force",
+                    self.uri
+                ),
+            });
+        }
+        Ok(())
+    }
+
+    /// Whether this is a connection to a `db://` database.
+    fn is_remote(&self) -> bool {
+        #[cfg(feature = "remote")]
+        if self.remote.is_some() {
+            return true;
+        }
+        false
+    }
+
+    /// Whether the table directory `name` holds a lance dataset.
+    async fn is_dataset(&self, name: &str) -> Result<bool> {
+        #[cfg(feature = "remote")]
+        if self.remote.is_some() {
+            return Ok(true);
+        }
+        let manifest = self
+            .object_path(&NativeTable::table_uri(&self.uri, name)?)?
+            .child(LATEST_MANIFEST);
+        Ok(self.object_store.exists(&manifest).await?)
+    }
+
+    /// Drop a table in the database.
+    ///
+    /// # Arguments
+    /// * `name` - The name of the table.
+    pub async fn drop_table(&self, name: &str) -> Result<()> {
+        #[cfg(feature = "remote")]
+        if let Some(remote) = self.remote.as_ref() {
+            return remote.drop_table(name).await;
+        }
+        self.tables.forget(name);
+        let table_uri = NativeTable::table_uri(&self.uri, name)?;
+        let create_lock = self.object_path(&CommitLock::create_lock_path(&table_uri))?;
+        self.object_store
+            .remove_dir_all(self.object_path(&table_uri)?)
+            .await?;
+        match self.object_store.inner.delete(&create_lock).await {
+            Ok(()) | Err(object_store::Error::NotFound { .. }) => {}
+            Err(e) => return Err(e.into()),
+        }
+        if let Some(cache) = self.metadata_cache.as_ref() {
+            cache.invalidate(&table_uri);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::create_dir_all;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::{mpsc, Arc};
+    use std::time::Duration;
+
+    use arrow_array::{
+        Array, ArrayRef, FixedSizeListArray, Float32Array, Int32Array, RecordBatch,
+        RecordBatchReader, StringArray,
+    };
+    use arrow_schema::{DataType, Field, Schema};
+    use futures::TryStreamExt;
+    use lance::arrow::{FixedSizeListArrayExt, RecordBatchBuffer};
+    use lance::dataset::{Dataset, WriteMode};
+    use lance::index::vector::ivf::IvfBuildParams;
+    use lance::index::vector::pq::PQBuildParams;
+    use tempfile::tempdir;
+
+    use crate::arrow::{IntoArrow, RecordBatchBuilder};
+    use crate::database::{connect, CreateTableMode, Database};
+    use crate::embeddings::tests::{make_text_batches, MockEmbedding};
+    use crate::embeddings::EmbeddingFunction;
+    use crate::error::{Error, Result};
+    use crate::index::vector::IvfPQIndexBuilder;
+    use crate::query::{Query, SlowQueryCallback};
+    use crate::table::{Constraint, HealthCheckKind, IndexHealth, OpenTableParams, WriteOptions};
+    use crate::testing::TestTableBuilder;
+
+    #[tokio::test]
+    async fn test_connect() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        let db = Database::connect(uri).await.unwrap();
+
+        assert_eq!(db.uri, uri);
+    }
+
+    #[tokio::test]
+    async fn test_connect_storage_options() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+
+        let db = connect(uri)
+            .storage_option("unknown_key", "value")
+            .execute()
+            .await
+            .unwrap();
+        assert_eq!(db.uri, uri);
+
+        let result = connect(uri)
+            .storage_option("unknown_key", "value")
+            .strict_storage_options(true)
+            .execute()
+            .await;
+        assert!(matches!(
+            result.err().unwrap(),
+            Error::UnknownStorageOption { .. }
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_table_names() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        let db = Database::connect(uri).await.unwrap();
+        for name in ["table1", "table2"] {
+            db.create_table(name, make_batches(0..10))
+                .execute()
+                .await
+                .unwrap();
+        }
+        create_dir_all(tmp_dir.path().join("invalidlance")).unwrap();
+        // A directory without a manifest is not a table.
+        create_dir_all(tmp_dir.path().join("ghost.lance")).unwrap();
+
+        let tables = db.table_names().await.unwrap();
+        assert_eq!(tables.len(), 2);
+        assert!(tables.contains(&String::from("table1")));
+        assert!(tables.contains(&String::from("table2")));
+    }
+
+    #[tokio::test]
+    async fn test_connect_s3() {
+        // let db = Database::connect("s3://bucket/path/to/database").await.unwrap();
     }
 
     #[tokio::test]
@@ -194,5 +1446,1039 @@ mod tests {
 
         let tables = db.table_names().await.unwrap();
         assert_eq!(tables.len(), 0);
+        assert!(!tmp_dir.path().join("table1.lance").exists());
+    }
+
+    #[tokio::test]
+    async fn test_drop_all_tables() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        let db = Database::connect(uri).await.unwrap();
+        db.create_table("a", make_batches(0..10))
+            .execute()
+            .await
+            .unwrap();
+        db.create_table("b", make_batches(0..10))
+            .execute()
+            .await
+            .unwrap();
+        create_dir_all(tmp_dir.path().join("not_a_table.lance")).unwrap();
+        std::fs::write(tmp_dir.path().join("notes.txt"), b"keep").unwrap();
+
+        assert_eq!(db.drop_all_tables(false).await.unwrap(), vec!["a", "b"]);
+        assert!(db.table_names().await.unwrap().is_empty());
+        assert!(tmp_dir.path().join("not_a_table.lance").exists());
+        assert!(tmp_dir.path().join("notes.txt").exists());
+
+        db.create_table("c", make_batches(0..10))
+            .execute()
+            .await
+            .unwrap();
+        assert_eq!(db.drop_database(false).await.unwrap(), vec!["c"]);
+        // The root is kept, with what is not a table.
+        assert!(tmp_dir.path().join("not_a_table.lance").exists());
+        assert!(tmp_dir.path().join("notes.txt").exists());
+        assert!(!tmp_dir.path().join("c.lance").exists());
+
+        std::fs::remove_dir(tmp_dir.path().join("not_a_table.lance")).unwrap();
+        std::fs::remove_file(tmp_dir.path().join("notes.txt")).unwrap();
+        let db = Database::connect(uri).await.unwrap();
+        db.create_table("d", make_batches(0..10))
+            .execute()
+            .await
+            .unwrap();
+        assert_eq!(db.drop_database(false).await.unwrap(), vec!["d"]);
+        assert!(!tmp_dir.path().exists());
+    }
+
+    #[tokio::test]
+    async fn test_drop_storage_root() {
+        // The root of a bucket stands in for a database of a temporary directory,
+        // so that nothing of the real storage is touched if the check fails.
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        let db = Database::connect(uri).await.unwrap();
+        db.create_table("t", make_batches(0..10))
+            .execute()
+            .await
+            .unwrap();
+        let mut root = Database::connect(uri).await.unwrap();
+        root.uri = "s3://bucket".to_string();
+        assert!(matches!(
+            root.drop_all_tables(false).await.err().unwrap(),
+            Error::InvalidInput { .. }
+        ));
+        assert!(matches!(
+            root.drop_database(false).await.err().unwrap(),
+            Error::InvalidInput { .. }
+        ));
+        assert_eq!(db.table_names().await.unwrap(), vec!["t"]);
+    }
+
+    #[tokio::test]
+    async fn test_shared_metadata_cache() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        let db = connect(uri)
+            .metadata_cache_size_bytes(1024 * 1024)
+            .execute()
+            .await
+            .unwrap();
+        db.create_table("t", make_batches(0..10))
+            .execute()
+            .await
+            .unwrap();
+
+        let t1 = db.open_table("t").await.unwrap();
+        let t2 = db.open_table("t").await.unwrap();
+        assert_eq!(t1.count_rows().await.unwrap(), 10);
+        assert_eq!(t2.count_rows().await.unwrap(), 10);
+
+        let stats = db.cache_stats();
+        assert_eq!(stats.metadata_hits, 2);
+        assert_eq!(stats.metadata_misses, 0);
+        assert_eq!(stats.metadata_entries, 1);
+    }
+
+    #[tokio::test]
+    async fn test_metadata_cache_byte_bound() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        let db = connect(uri)
+            .metadata_cache_size_bytes(16)
+            .execute()
+            .await
+            .unwrap();
+        db.create_table("t", make_batches(0..10))
+            .execute()
+            .await
+            .unwrap();
+        db.open_table("t").await.unwrap();
+
+        let stats = db.cache_stats();
+        assert_eq!(stats.metadata_entries, 0);
+        assert!(stats.metadata_size_bytes <= 16);
+        assert_eq!(stats.metadata_misses, 1);
+    }
+
+    #[tokio::test]
+    async fn test_read_consistency_interval() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        let writer_db = Database::connect(uri).await.unwrap();
+        let writer = writer_db
+            .create_table("t", make_batches(0..10))
+            .execute()
+            .await
+            .unwrap();
+
+        let lazy = Database::connect(uri).await.unwrap();
+        let lazy_table = lazy.open_table("t").await.unwrap();
+        let strong = connect(uri)
+            .read_consistency_interval(Duration::ZERO)
+            .execute()
+            .await
+            .unwrap();
+        let strong_table = strong.open_table("t").await.unwrap();
+
+        writer
+            .add(make_batches(10..20), Some(WriteMode::Append))
+            .await
+            .unwrap();
+        assert_eq!(lazy_table.count_rows().await.unwrap(), 10);
+        assert_eq!(strong_table.count_rows().await.unwrap(), 20);
+    }
+
+    #[tokio::test]
+    async fn test_connect_missing_dir() {
+        let tmp_dir = tempdir().unwrap();
+        let path = tmp_dir.path().join("missing");
+        let uri = path.to_str().unwrap();
+
+        let err = Database::connect(uri).await.err().unwrap();
+        assert!(matches!(err, Error::DatabaseNotFound { .. }));
+        assert!(err.to_string().contains(uri));
+        assert!(!path.exists());
+
+        let db = connect(uri).create_dir(true).execute().await.unwrap();
+        assert_eq!(db.uri, uri);
+        assert!(path.is_dir());
+    }
+
+    #[tokio::test]
+    async fn test_health_check() {
+        let tmp_dir = tempdir().unwrap();
+        let path = tmp_dir.path().join("db");
+        let uri = path.to_str().unwrap();
+        let db = connect(uri).create_dir(true).execute().await.unwrap();
+        let report = db.health_check().await.unwrap();
+        assert_eq!(report.uri, uri);
+        assert_eq!(report.version, None);
+        assert!(report.latency(HealthCheckKind::Storage).is_some());
+
+        Database::connect("memory://")
+            .await
+            .unwrap()
+            .health_check()
+            .await
+            .unwrap();
+
+        std::fs::remove_dir_all(&path).unwrap();
+        let err = db.health_check().await.unwrap_err();
+        assert!(matches!(err, Error::DatabaseNotFound { .. }), "{err}");
+    }
+
+    #[tokio::test]
+    async fn test_connect_invalid_uri() {
+        let tmp_dir = tempdir().unwrap();
+        let file = tmp_dir.path().join("file");
+        std::fs::write(&file, b"").unwrap();
+        assert!(matches!(
+            Database::connect(file.to_str().unwrap())
+                .await
+                .err()
+                .unwrap(),
+            Error::InvalidUri { .. }
+        ));
+        assert!(matches!(
+            Database::connect("s4://bucket/db").await.err().unwrap(),
+            Error::InvalidUri { .. }
+        ));
+
+        let file_uri = format!("file://{}", tmp_dir.path().to_str().unwrap());
+        let db = Database::connect(&file_uri).await.unwrap();
+        assert_eq!(db.uri, tmp_dir.path().to_str().unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_memory_database() {
+        let db = Database::connect("memory://").await.unwrap();
+        let table = db
+            .create_table("vectors", make_vector_batches(512))
+            .execute()
+            .await
+            .unwrap();
+        table
+            .add(make_vector_batches(256), Some(WriteMode::Append))
+            .await
+            .unwrap();
+        assert_eq!(table.count_rows().await.unwrap(), 768);
+
+        let builder = IvfPQIndexBuilder::new()
+            .column("vector")
+            .ivf_params(IvfBuildParams::new(4));
+        table.create_index(&builder).await.unwrap();
+
+        let opened = db.open_table("vectors").await.unwrap();
+        assert_eq!(opened.count_rows().await.unwrap(), 768);
+        let results = opened
+            .search(Float32Array::from(vec![0.5; 16]).into())
+            .limit(10)
+            .execute()
+            .await
+            .unwrap()
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        assert_eq!(results.iter().map(|b| b.num_rows()).sum::<usize>(), 10);
+
+        // The appends and the index are versions of their own.
+        let native = db
+            .open_native_table("vectors", OpenTableParams::default())
+            .await
+            .unwrap();
+        assert_eq!(native.version(), 3);
+        assert_eq!(
+            native.index_stats("vector_idx").await.unwrap().indexed_rows,
+            768
+        );
+        let first = native.checkout(1).await.unwrap();
+        assert_eq!(first.count_rows().await.unwrap(), 512);
+        native.restore(1).await.unwrap();
+        assert_eq!(native.count_rows().await.unwrap(), 512);
+
+        assert!(matches!(
+            db.create_table("vectors", make_vector_batches(1))
+                .execute()
+                .await
+                .err()
+                .unwrap(),
+            Error::TableAlreadyExists { .. }
+        ));
+        assert_eq!(db.table_names().await.unwrap(), vec!["vectors"]);
+
+        db.drop_table("vectors").await.unwrap();
+        assert!(db.table_names().await.unwrap().is_empty());
+        assert!(matches!(
+            db.open_table("vectors").await.err().unwrap(),
+            Error::TableNotFound { .. }
+        ));
+
+        let other = Database::connect("memory://").await.unwrap();
+        assert!(other.table_names().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_memory_schema_evolution() {
+        let db = connect("memory://")
+            .write_options(WriteOptions::default().schema_evolution(true))
+            .execute()
+            .await
+            .unwrap();
+        let table = db
+            .create_table("vectors", make_vector_batches(8))
+            .execute()
+            .await
+            .unwrap();
+        let batch = make_vector_batches(4).next().unwrap().unwrap();
+        let tags = Arc::new(StringArray::from(vec!["a"; 4])) as ArrayRef;
+        let mut columns = vec![(String::from("tag"), tags, true)];
+        for (field, column) in batch.schema().fields().iter().zip(batch.columns()) {
+            columns.push((field.name().clone(), column.clone(), field.is_nullable()));
+        }
+        let batch = RecordBatch::try_from_iter_with_nullable(columns).unwrap();
+        let rows = Box::new(RecordBatchBuffer::new(vec![batch]));
+        table.add(rows, None).await.unwrap();
+
+        assert_eq!(table.count_rows().await.unwrap(), 12);
+        let schema = table.schema().await.unwrap();
+        assert_eq!(schema.fields().last().unwrap().name(), "tag");
+    }
+
+    #[tokio::test]
+    async fn test_memory_track_modifications() {
+        let db = Database::connect("memory://").await.unwrap();
+        db.create_table("t", make_batches(0..4))
+            .track_modifications(true)
+            .execute()
+            .await
+            .unwrap();
+        let table = db
+            .open_native_table("t", OpenTableParams::default())
+            .await
+            .unwrap();
+        table.add(make_batches(4..6), None).await.unwrap();
+        let changed = table
+            .changed_since(1)
+            .await
+            .unwrap()
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        assert_eq!(changed.iter().map(|b| b.num_rows()).sum::<usize>(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_create_table_modes() {
+        let tmp_dir = tempdir().unwrap();
+        let db = Database::connect(tmp_dir.path().to_str().unwrap())
+            .await
+            .unwrap();
+
+        let table = db
+            .create_table("t", make_batches(0..10))
+            .mode(CreateTableMode::Create)
+            .execute()
+            .await
+            .unwrap();
+        assert_eq!(table.count_rows().await.unwrap(), 10);
+        let err = db
+            .create_table("t", make_batches(0..5))
+            .mode(CreateTableMode::Create)
+            .execute()
+            .await
+            .err()
+            .unwrap();
+        assert!(matches!(err, Error::TableAlreadyExists { .. }), "{err}");
+
+        let table = db
+            .create_table("t", make_batches(0..5))
+            .mode(CreateTableMode::Overwrite)
+            .execute()
+            .await
+            .unwrap();
+        assert_eq!(table.count_rows().await.unwrap(), 5);
+
+        let opened = Arc::new(AtomicBool::new(false));
+        let exist_ok = |opened: Arc<AtomicBool>| {
+            CreateTableMode::exist_ok(move |params| {
+                opened.store(true, Ordering::SeqCst);
+                params
+            })
+        };
+        let table = db
+            .create_table("t", make_batches(0..20))
+            .mode(exist_ok(opened.clone()))
+            .execute()
+            .await
+            .unwrap();
+        assert!(opened.load(Ordering::SeqCst));
+        assert_eq!(table.count_rows().await.unwrap(), 5);
+
+        let table = db
+            .create_table("new", make_batches(0..20))
+            .mode(exist_ok(opened))
+            .execute()
+            .await
+            .unwrap();
+        assert_eq!(table.count_rows().await.unwrap(), 20);
+    }
+
+    #[tokio::test]
+    async fn test_create_table_exist_ok_schema_mismatch() {
+        let db = Database::connect("memory://").await.unwrap();
+        db.create_table("t", make_batches(0..10))
+            .execute()
+            .await
+            .unwrap();
+
+        let err = db
+            .create_table("t", make_text_batches(&["a"]))
+            .mode(CreateTableMode::exist_ok(|params| params))
+            .execute()
+            .await
+            .err()
+            .unwrap();
+        assert!(matches!(err, Error::Schema { .. }), "{err}");
+
+        let schema = Arc::new(Schema::new(vec![Field::new("i", DataType::Utf8, false)]));
+        let batch =
+            RecordBatch::try_new(schema.clone(), vec![Arc::new(StringArray::from(vec!["a"]))])
+                .unwrap();
+        let err = db
+            .create_table("t", Box::new(RecordBatchBuffer::new(vec![batch])))
+            .mode(CreateTableMode::exist_ok(|params| params))
+            .execute()
+            .await
+            .err()
+            .unwrap();
+        assert!(matches!(err, Error::Schema { .. }), "{err}");
+    }
+
+    #[tokio::test]
+    async fn test_create_table_lazy() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        let db = Database::connect(uri).await.unwrap();
+        let exist_ok = || CreateTableMode::exist_ok(|params| params);
+        let calls = Arc::new(AtomicUsize::new(0));
+        let data = |calls: &Arc<AtomicUsize>, rows: i32| {
+            let calls = calls.clone();
+            move || {
+                calls.fetch_add(1, Ordering::SeqCst);
+                Ok(make_batches(0..rows) as Box<dyn RecordBatchReader>)
+            }
+        };
+
+        let table = db
+            .create_table_lazy("t", data(&calls, 10))
+            .execute()
+            .await
+            .unwrap();
+        assert_eq!(table.count_rows().await.unwrap(), 10);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // The data of an existing table is not read.
+        let table = db
+            .create_table_lazy("t", || -> Result<Box<dyn RecordBatchReader>> {
+                panic!("the data of an existing table is read")
+            })
+            .mode(exist_ok())
+            .execute()
+            .await
+            .unwrap();
+        assert_eq!(table.count_rows().await.unwrap(), 10);
+
+        // Concurrent creators: one writes the table, the other opens it.
+        let calls = Arc::new(AtomicUsize::new(0));
+        let (first, second) = tokio::join!(
+            db.create_table_lazy("race", data(&calls, 10))
+                .mode(exist_ok())
+                .execute(),
+            db.create_table_lazy("race", data(&calls, 20))
+                .mode(exist_ok())
+                .execute(),
+        );
+        let (first, second) = (first.unwrap(), second.unwrap());
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        let rows = first.count_rows().await.unwrap();
+        assert!(rows == 10 || rows == 20, "{rows}");
+        assert_eq!(second.count_rows().await.unwrap(), rows);
+        let dataset = Dataset::open(&format!("{uri}/race.lance")).await.unwrap();
+        assert_eq!(dataset.versions().await.unwrap().len(), 1);
+
+        // Both handles write to the same table.
+        first
+            .add(make_batches(0..1), Some(WriteMode::Append))
+            .await
+            .unwrap();
+        second
+            .add(make_batches(0..1), Some(WriteMode::Append))
+            .await
+            .unwrap();
+        // The second write commits on top of the first.
+        assert_eq!(second.count_rows().await.unwrap(), rows + 2);
+        assert_eq!(db.table_names().await.unwrap().len(), 2);
+
+        // An error of the callback fails the creation.
+        let err = db
+            .create_table_lazy("failed", || {
+                Err(Error::InvalidInput {
+                    message: "no data".to_string(),
+                })
+            })
+            .mode(exist_ok())
+            .execute()
+            .await
+            .err()
+            .unwrap();
+        assert!(matches!(err, Error::InvalidInput { .. }), "{err}");
+        // The failed creation leaves no directory behind, only the file of its
+        // lock, which is removed with the table.
+        assert!(!tmp_dir.path().join("failed.lance").exists());
+        assert!(matches!(
+            db.open_table("failed").await,
+            Err(Error::TableNotFound { .. })
+        ));
+        assert!(!tmp_dir.path().join("failed.lance").exists());
+        assert_eq!(db.table_names().await.unwrap().len(), 2);
+        assert!(tmp_dir.path().join("race.lance.create.lock").exists());
+        db.drop_table("race").await.unwrap();
+        assert!(!tmp_dir.path().join("race.lance.create.lock").exists());
+    }
+
+    #[tokio::test]
+    async fn test_create_table_with_embedding() {
+        let tmp_dir = tempdir().unwrap();
+        let db = Database::connect(tmp_dir.path().to_str().unwrap())
+            .await
+            .unwrap();
+        let table = db
+            .create_table("docs", make_text_batches(&["a", "bb"]))
+            .embedding("text", "vector", Arc::new(MockEmbedding { dims: 4 }))
+            .execute()
+            .await
+            .unwrap();
+        table
+            .add(make_text_batches(&["ccc"]), Some(WriteMode::Append))
+            .await
+            .unwrap();
+        assert_eq!(table.count_rows().await.unwrap(), 3);
+
+        let table = db.open_table("docs").await.unwrap();
+        table
+            .add(make_text_batches(&["dddd"]), Some(WriteMode::Append))
+            .await
+            .unwrap();
+        let results = table
+            .search(Float32Array::from_iter_values([4.0, b'd' as f32, 0.0, 0.0]).into())
+            .limit(1)
+            .execute()
+            .await
+            .unwrap()
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        let texts = results[0]
+            .column_by_name("text")
+            .unwrap()
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .unwrap();
+        assert_eq!(texts.value(0), "dddd");
+
+        assert!(matches!(
+            db.create_table("other", make_batches(0..10))
+                .embedding("text", "vector", Arc::new(MockEmbedding { dims: 4 }))
+                .execute()
+                .await
+                .err()
+                .unwrap(),
+            Error::InvalidInput { .. }
+        ));
+    }
+
+    /// Embeds integers as `[i, 0]`.
+    #[derive(Debug)]
+    struct IntEmbedding;
+
+    #[async_trait::async_trait]
+    impl EmbeddingFunction for IntEmbedding {
+        fn name(&self) -> String {
+            "int".to_string()
+        }
+
+        fn source_type(&self) -> DataType {
+            DataType::Int32
+        }
+
+        fn dims(&self) -> usize {
+            2
+        }
+
+        async fn embed(&self, input: ArrayRef) -> Result<ArrayRef> {
+            let ints = input.as_any().downcast_ref::<Int32Array>().unwrap();
+            let values =
+                Float32Array::from_iter_values(ints.values().iter().flat_map(|i| [*i as f32, 0.0]));
+            Ok(Arc::new(FixedSizeListArray::try_new(values, 2)?))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_search_text() {
+        let tmp_dir = tempdir().unwrap();
+        let db = Database::connect(tmp_dir.path().to_str().unwrap())
+            .await
+            .unwrap();
+        let embedding = Arc::new(MockEmbedding { dims: 4 });
+        let table = db
+            .create_table("docs", make_text_batches(&["a", "bb", "ccc", "bbbb"]))
+            .embedding("text", "vector", embedding.clone())
+            .execute()
+            .await
+            .unwrap();
+
+        let collect = |query: Query| async move {
+            query
+                .limit(2)
+                .execute()
+                .await
+                .unwrap()
+                .try_collect::<Vec<_>>()
+                .await
+                .unwrap()
+        };
+        let by_text = collect(table.search("bbb".into())).await;
+        let vectors = embedding
+            .embed(Arc::new(StringArray::from(vec!["bbb"])))
+            .await
+            .unwrap();
+        let vector = vectors
+            .as_any()
+            .downcast_ref::<FixedSizeListArray>()
+            .unwrap()
+            .value(0);
+        let vector = vector.as_any().downcast_ref::<Float32Array>().unwrap();
+        let by_vector = collect(table.search(vector.clone().into())).await;
+        assert_eq!(by_text, by_vector);
+        let explicit = collect(
+            table
+                .search(Float32Array::from(vec![0.0; 4]).into())
+                .nearest_to_text("bbb"),
+        )
+        .await;
+        assert_eq!(explicit, by_vector);
+
+        let plain = db
+            .create_table("plain", make_vector_batches(10))
+            .execute()
+            .await
+            .unwrap();
+        assert!(matches!(
+            plain.search("bbb".into()).execute().await.err().unwrap(),
+            Error::InvalidInput { .. }
+        ));
+
+        let ints = db
+            .create_table("ints", make_batches(0..10))
+            .embedding("i", "vector", Arc::new(IntEmbedding))
+            .execute()
+            .await
+            .unwrap();
+        assert!(matches!(
+            ints.search("bbb".into()).execute().await.err().unwrap(),
+            Error::InvalidInput { .. }
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_embeddings_persisted() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        let db = Database::connect(uri).await.unwrap();
+        db.create_table("docs", make_text_batches(&["a", "bb", "ccc"]))
+            .embedding("text", "vector", Arc::new(MockEmbedding { dims: 4 }))
+            .execute()
+            .await
+            .unwrap();
+
+        let other = Database::connect(uri).await.unwrap();
+        other
+            .embedding_registry()
+            .register(Arc::new(MockEmbedding { dims: 4 }));
+        let table = other.open_table("docs").await.unwrap();
+        let results = table
+            .search("by".into())
+            .limit(1)
+            .execute()
+            .await
+            .unwrap()
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        let texts = results[0]
+            .column_by_name("text")
+            .unwrap()
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .unwrap();
+        assert_eq!(texts.value(0), "bb");
+
+        let unregistered = Database::connect(uri).await.unwrap();
+        assert!(matches!(
+            unregistered.open_table("docs").await.err().unwrap(),
+            Error::MissingEmbeddingProvider { name } if name == "mock"
+        ));
+
+        let mismatched = Database::connect(uri).await.unwrap();
+        mismatched
+            .embedding_registry()
+            .register(Arc::new(MockEmbedding { dims: 8 }));
+        assert!(matches!(
+            mismatched.open_table("docs").await.err().unwrap(),
+            Error::InvalidInput { .. }
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_embedding_factory() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        let db = Database::connect(uri).await.unwrap();
+        for (name, dims) in [("small", 2), ("large", 8)] {
+            db.create_table(name, make_text_batches(&["a", "bb"]))
+                .embedding("text", "vector", Arc::new(MockEmbedding { dims }))
+                .execute()
+                .await
+                .unwrap();
+        }
+        // The new version of a replaced table has the embeddings it was
+        // replaced with.
+        db.create_table("large", make_text_batches(&["a"]))
+            .mode(CreateTableMode::Overwrite)
+            .embedding("text", "vector", Arc::new(MockEmbedding { dims: 4 }))
+            .execute()
+            .await
+            .unwrap();
+
+        // Both parameters of the provider are built by one factory.
+        let other = Database::connect(uri).await.unwrap();
+        other
+            .embedding_registry()
+            .register_factory("mock", |params| {
+                let dims = params["dims"].parse().unwrap();
+                Ok(Arc::new(MockEmbedding { dims }) as Arc<dyn EmbeddingFunction>)
+            });
+        let small = other
+            .open_native_table("small", OpenTableParams::default())
+            .await
+            .unwrap();
+        assert_eq!(small.embeddings()[0].function.dims(), 2);
+        let large = other
+            .open_native_table("large", OpenTableParams::default())
+            .await
+            .unwrap();
+        assert_eq!(large.embeddings()[0].function.dims(), 4);
+        assert_eq!(other.embedding_registry().names(), vec!["mock"]);
+    }
+
+    #[tokio::test]
+    async fn test_rename_embedded_column() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        let db = Database::connect(uri).await.unwrap();
+        // Distinct texts, embedded as their length and first letter.
+        let texts = (0..300)
+            .map(|i| format!("{}{}", (b'a' + (i % 26) as u8) as char, "x".repeat(i / 26)))
+            .collect::<Vec<_>>();
+        let texts = texts.iter().map(String::as_str).collect::<Vec<_>>();
+        db.create_table("docs", make_text_batches(&texts))
+            .embedding("text", "vector", Arc::new(MockEmbedding { dims: 2 }))
+            .execute()
+            .await
+            .unwrap();
+        let table = db
+            .open_native_table("docs", OpenTableParams::default())
+            .await
+            .unwrap();
+        let builder = IvfPQIndexBuilder::new()
+            .column("vector")
+            .ivf_params(IvfBuildParams::new(2))
+            .pq_params(PQBuildParams {
+                num_sub_vectors: 1,
+                ..Default::default()
+            });
+        table.create_index(&builder).await.unwrap();
+
+        table
+            .set_constraints(vec![Constraint::new("norm", "vector_norm(vector) > 0")])
+            .await
+            .unwrap();
+        let err = table
+            .rename_column("vector", "embedding")
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(&err, Error::InvalidInput { message } if message.contains("'norm'")),
+            "{err}"
+        );
+        table.set_constraints(Vec::new()).await.unwrap();
+        for (from, to) in [("missing", "other"), ("vector", "text"), ("vector", "")] {
+            let err = table.rename_column(from, to).await.unwrap_err();
+            assert!(matches!(err, Error::InvalidInput { .. }), "{err}");
+        }
+
+        table.rename_column("vector", "embedding").await.unwrap();
+        assert_eq!(table.embeddings()[0].vector_column, "embedding");
+        let stats = table.index_stats("vector_idx").await.unwrap();
+        assert_eq!(stats.column, "embedding");
+        assert_eq!(stats.health, IndexHealth::Healthy);
+        // Added rows are embedded into the renamed column.
+        table.add(make_text_batches(&["by"]), None).await.unwrap();
+
+        // A later connection finds the embedding of the renamed column.
+        let other = Database::connect(uri).await.unwrap();
+        other
+            .embedding_registry()
+            .register(Arc::new(MockEmbedding { dims: 2 }));
+        let table = other.open_table("docs").await.unwrap();
+        let results = table
+            .search("by".into())
+            .column("embedding")
+            .use_index(true)
+            .nprobes(2)
+            .refine_factor(Some(50))
+            .limit(2)
+            .execute()
+            .await
+            .unwrap()
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        let texts = results[0]
+            .column_by_name("text")
+            .unwrap()
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .unwrap();
+        let mut found = texts.iter().flatten().collect::<Vec<_>>();
+        found.sort();
+        assert_eq!(found, vec!["bx", "by"]);
+    }
+
+    #[tokio::test]
+    async fn test_on_slow_query() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        let (sender, receiver) = mpsc::channel();
+        let callback: SlowQueryCallback = Arc::new(move |event| sender.send(event).unwrap());
+        let db = connect(uri)
+            .on_slow_query(Duration::ZERO, callback)
+            .execute()
+            .await
+            .unwrap();
+        let batch = RecordBatchBuilder::new()
+            .column("i", Arc::new(Int32Array::from_iter_values(0..10)))
+            .vector_column("vector", 2, (0..10).map(|i| vec![i as f32, 0.25]))
+            .build()
+            .unwrap();
+        let table = db
+            .create_table("slow", batch.into_arrow(None).unwrap())
+            .execute()
+            .await
+            .unwrap();
+        let query = || {
+            table
+                .search(vec![0.5, 0.75].into())
+                .filter(Some("i < 5".to_string()))
+                .limit(3)
+        };
+
+        query().execute_with_metrics().await.unwrap();
+        let event = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(event.table, "slow");
+        assert!(event.query.contains("i < 5"));
+        assert!(!event.query.contains("0.75"));
+        assert_eq!(event.metrics.rows_after_filter, 3);
+
+        let results = query()
+            .execute()
+            .await
+            .unwrap()
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        assert_eq!(results.iter().map(|b| b.num_rows()).sum::<usize>(), 3);
+        drop(results);
+        let event = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(event.metrics.rows_after_filter, 3);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(receiver.try_recv().is_err());
+
+        let (sender, receiver) = mpsc::channel();
+        let callback: SlowQueryCallback = Arc::new(move |event| sender.send(event).unwrap());
+        let db = connect(uri)
+            .on_slow_query(Duration::from_secs(3600), callback)
+            .execute()
+            .await
+            .unwrap();
+        let table = db.open_table("slow").await.unwrap();
+        table
+            .search(vec![0.5, 0.75].into())
+            .execute_with_metrics()
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(receiver.try_recv().is_err());
+    }
+
+    #[cfg(feature = "polars")]
+    #[tokio::test]
+    async fn test_polars_round_trip() {
+        use arrow_array::{Int64Array, StringArray};
+        use polars::prelude::{DataFrame, DataType as PolarsType, NamedFrom, Series};
+
+        use crate::table::NativeTable;
+
+        let vectors = |values: &[[f32; 2]]| {
+            let vectors = values
+                .iter()
+                .map(|v| Series::new("", v.as_slice()))
+                .collect::<Vec<_>>();
+            Series::new("vector", vectors)
+        };
+        let frame = |ids: &[i64], categories: &[&str], values: &[[f32; 2]]| {
+            let category = Series::new("category", categories)
+                .cast(&PolarsType::Categorical(None))
+                .unwrap();
+            DataFrame::new(vec![Series::new("id", ids), category, vectors(values)]).unwrap()
+        };
+
+        // The categories of the frames stacked below share their strings.
+        polars::enable_string_cache(true);
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        let db = Database::connect(uri).await.unwrap();
+        let mut df = frame(&[1, 2], &["a", "b"], &[[0.0, 0.0], [3.0, 4.0]]);
+        // The rows of a DataFrame of two chunks.
+        df.vstack_mut(&frame(&[3], &["a"], &[[9.0, 9.0]])).unwrap();
+        let table = db
+            .create_table_from_polars("frames", &df, &[("vector", 2)])
+            .await
+            .unwrap();
+        let schema = table.schema().await.unwrap();
+        assert!(matches!(
+            schema.field_with_name("vector").unwrap().data_type(),
+            DataType::FixedSizeList(_, 2)
+        ));
+        assert_eq!(
+            schema.field_with_name("category").unwrap().data_type(),
+            &DataType::Utf8
+        );
+
+        let results = table
+            .search(vec![3.0, 4.0].into())
+            .limit(1)
+            .execute()
+            .await
+            .unwrap()
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        let column = |name| results[0].column_by_name(name).unwrap().as_any();
+        let ids = column("id").downcast_ref::<Int64Array>().unwrap();
+        let categories = column("category").downcast_ref::<StringArray>().unwrap();
+        assert_eq!((ids.value(0), categories.value(0)), (2, "b"));
+
+        let table = NativeTable::open(uri, "frames").await.unwrap();
+        let more = frame(&[4], &["c"], &[[1.0, 1.0]]);
+        assert_eq!(table.add_polars(&more).await.unwrap(), 1);
+        assert_eq!(table.count_rows().await.unwrap(), 4);
+
+        let mismatched = DataFrame::new(vec![
+            Series::new("id", &[5_i64, 6]),
+            Series::new(
+                "vector",
+                vec![
+                    Series::new("", &[1.0_f32, 2.0]),
+                    Series::new("", &[1.0_f32]),
+                ],
+            ),
+        ])
+        .unwrap();
+        let err = table.add_polars(&mismatched).await.unwrap_err();
+        assert!(
+            matches!(err, Error::InvalidInput { ref message } if message.contains("row 1 ")),
+            "{err}"
+        );
+        assert_eq!(table.count_rows().await.unwrap(), 4);
+    }
+
+    #[cfg(feature = "ipc")]
+    #[tokio::test]
+    async fn test_ipc_round_trip() {
+        use arrow_ipc::reader::FileReader;
+
+        use crate::io::ipc::{ExportIpcParams, IpcCompression};
+        use crate::table::NativeTable;
+
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        let db = Database::connect(uri).await.unwrap();
+        let batch = RecordBatchBuilder::new()
+            .column("id", Arc::new(Int32Array::from_iter_values(0..20_000)))
+            .vector_column("vector", 4, (0..20_000).map(|i| vec![i as f32; 4]))
+            .build()
+            .unwrap();
+        db.create_table("source", batch.into_arrow(None).unwrap())
+            .execute()
+            .await
+            .unwrap();
+        let source = NativeTable::open(uri, "source").await.unwrap();
+        let schema = source.schema().await.unwrap();
+
+        let path = tmp_dir.path().join("source.arrow");
+        let params = ExportIpcParams {
+            compression: Some(IpcCompression::Zstd),
+            ..Default::default()
+        };
+        assert_eq!(source.export_ipc(&path, params).await.unwrap(), 20_000);
+        let reader = FileReader::try_new(std::fs::File::open(&path).unwrap(), None).unwrap();
+        // The batches are written as they are scanned, not collected first.
+        assert!(reader.num_batches() > 1);
+        assert_eq!(reader.schema(), schema);
+
+        let copy = db.create_table_from_ipc("copy", &path).await.unwrap();
+        assert_eq!(copy.schema().await.unwrap(), schema);
+        assert_eq!(copy.count_rows().await.unwrap(), 20_000);
+
+        let filtered = tmp_dir.path().join("filtered.arrow");
+        let params = ExportIpcParams {
+            columns: Some(vec!["id".to_string(), "vector".to_string()]),
+            filter: Some("id < 10".to_string()),
+            compression: Some(IpcCompression::Lz4),
+        };
+        assert_eq!(source.export_ipc(&filtered, params).await.unwrap(), 10);
+        let copy = NativeTable::open(uri, "copy").await.unwrap();
+        assert_eq!(copy.add_ipc(&filtered).await.unwrap(), 10);
+        assert_eq!(copy.count_rows().await.unwrap(), 20_010);
+
+        assert!(matches!(
+            copy.add_ipc(tmp_dir.path().join("missing.arrow")).await,
+            Err(Error::Io { .. })
+        ));
+    }
+
+    fn make_vector_batches(num_rows: usize) -> Box<dyn RecordBatchReader + Send> {
+        TestTableBuilder::new(num_rows).reader().unwrap()
+    }
+
+    fn make_batches(range: std::ops::Range<i32>) -> Box<dyn RecordBatchReader + Send> {
+        let schema = Arc::new(Schema::new(vec![Field::new("i", DataType::Int32, false)]));
+        Box::new(RecordBatchBuffer::new(vec![RecordBatch::try_new(
+            schema,
+            vec![Arc::new(Int32Array::from_iter_values(range))],
+        )
+        .unwrap()]))
     }
 }
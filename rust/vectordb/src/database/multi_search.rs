@@ -0,0 +1,410 @@
+// Copyright 2023 LanceDB Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Searches of several tables of a database merged into one result, see
+//! [Database::multi_search].
+//!
+//! The searches of the tables run concurrently in the future of
+//! [MultiSearch::execute], without spawned tasks: dropping the future cancels
+//! them all, and a failing search cancels the others unless the failures are
+//! reported with [OnTableError::Report]. Each table is searched for the
+//! global limit, so that the nearest rows of all tables are among the rows
+//! merged.
+
+use std::sync::Arc;
+
+use arrow_array::{Array, ArrayRef, Float32Array, RecordBatch, StringArray, UInt32Array};
+use arrow_schema::{DataType, Field, Schema as ArrowSchema};
+use datafusion::arrow::compute::{concat_batches, take};
+use futures::future::{join_all, try_join_all};
+use futures::TryStreamExt;
+use tracing::{field, info_span};
+
+use super::Database;
+use crate::error::{Error, Result};
+use crate::query::{Query, QueryVector, DEFAULT_QUERY_LIMIT, DISTANCE_COLUMN};
+use crate::spans::timed;
+
+/// The column of the table of each row in the results of
+/// [Database::multi_search].
+pub const TABLE_NAME_COLUMN: &str = "_table";
+
+/// What [MultiSearch::execute] does when the search of a table fails.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OnTableError {
+    /// Cancel the other searches and fail with the error of the table.
+    #[default]
+    FailFast,
+    /// Merge the results of the other tables, and report the failure in
+    /// [MultiSearchResults::failures].
+    Report,
+}
+
+/// A table whose search failed, see [OnTableError::Report].
+#[derive(Debug)]
+pub struct TableSearchFailure {
+    /// The name of the table.
+    pub table: String,
+    /// Why the search failed.
+    pub error: Error,
+}
+
+/// The merged results of a [MultiSearch].
+#[derive(Debug, Default)]
+pub struct MultiSearchResults {
+    /// The nearest rows of all tables searched, by distance, with the
+    /// [TABLE_NAME_COLUMN] of each. Empty if no table has results.
+    pub batches: Vec<RecordBatch>,
+    /// The tables whose search failed, in the order they were given.
+    pub failures: Vec<TableSearchFailure>,
+}
+
+/// Searches of several tables of a database, see [Database::multi_search].
+pub struct MultiSearch<'a> {
+    db: &'a Database,
+    tables: Vec<String>,
+    build: Box<dyn Fn(Query) -> Query + Send + Sync + 'a>,
+    limit: usize,
+    on_table_error: OnTableError,
+}
+
+impl<'a> MultiSearch<'a> {
+    pub(super) fn new(
+        db: &'a Database,
+        tables: &[&str],
+        build: impl Fn(Query) -> Query + Send + Sync + 'a,
+    ) -> Self {
+        Self {
+            db,
+            tables: tables.iter().map(|t| t.to_string()).collect(),
+            build: Box::new(build),
+            limit: DEFAULT_QUERY_LIMIT,
+            on_table_error: OnTableError::default(),
+        }
+    }
+
+    /// Return the `limit` nearest rows of all tables, [DEFAULT_QUERY_LIMIT] by
+    /// default. The query of each table is built with this limit.
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    /// What to do when the search of a table fails, see [OnTableError].
+    pub fn on_table_error(mut self, on_table_error: OnTableError) -> Self {
+        self.on_table_error = on_table_error;
+        self
+    }
+
+    /// Search the tables, and merge their results.
+    ///
+    /// Fails with an [Error::InvalidInput] if a table is given twice, or if
+    /// the results of two tables have different columns: select the same
+    /// columns in all of them with [Query::select].
+    pub async fn execute(self) -> Result<MultiSearchResults> {
+        let span = info_span!(
+            "multi_search",
+            tables = self.tables.len(),
+            elapsed_ms = field::Empty
+        );
+        timed(span, self.run()).await
+    }
+
+    async fn run(self) -> Result<MultiSearchResults> {
+        for (i, table) in self.tables.iter().enumerate() {
+            if self.tables[..i].contains(table) {
+                return Err(Error::InvalidInput {
+                    message: format!("the table '{table}' is searched twice"),
+                });
+            }
+        }
+        let searches = self.tables.iter().map(|table| self.search(table));
+        let mut failures = Vec::new();
+        let results = match self.on_table_error {
+            OnTableError::FailFast => try_join_all(searches).await?,
+            OnTableError::Report => join_all(searches)
+                .await
+                .into_iter()
+                .zip(&self.tables)
+                .filter_map(|(result, table)| match result {
+                    Ok(batches) => Some(batches),
+                    Err(error) => {
+                        failures.push(TableSearchFailure {
+                            table: table.clone(),
+                            error,
+                        });
+                        None
+                    }
+                })
+                .collect(),
+        };
+        let batches = merge(results.into_iter().flatten().collect(), self.limit)?;
+        Ok(MultiSearchResults { batches, failures })
+    }
+
+    /// The results of the search of `table`, with its [TABLE_NAME_COLUMN].
+    async fn search(&self, table: &str) -> Result<Vec<RecordBatch>> {
+        let opened = self.db.open_table(table).await?;
+        let query = opened
+            .search(QueryVector::Vector(Float32Array::from(Vec::<f32>::new())))
+            .limit(self.limit);
+        let batches = (self.build)(query)
+            .execute()
+            .await?
+            .try_collect::<Vec<_>>()
+            .await?;
+        batches
+            .iter()
+            .map(|batch| with_table_name(batch, table))
+            .collect()
+    }
+}
+
+impl Database {
+    /// Search the tables `tables` of this database with the queries built by
+    /// `build`, and merge their results by distance.
+    ///
+    /// `build` is given the query of each table, and sets its vector with
+    /// [Query::nearest_to] and its other parameters. It can set them by table,
+    /// for example the [Query::nprobes] of the tables with larger indices,
+    /// from [Query::table_name]. The tables are searched concurrently, see
+    /// [MultiSearch::execute].
+    pub fn multi_search<'a>(
+        &'a self,
+        tables: &[&str],
+        build: impl Fn(Query) -> Query + Send + Sync + 'a,
+    ) -> MultiSearch<'a> {
+        MultiSearch::new(self, tables, build)
+    }
+}
+
+/// `batch` with the [TABLE_NAME_COLUMN] `table`.
+fn with_table_name(batch: &RecordBatch, table: &str) -> Result<RecordBatch> {
+    let schema = batch.schema();
+    let mut fields = schema.fields().iter().cloned().collect::<Vec<_>>();
+    fields.push(Arc::new(Field::new(
+        TABLE_NAME_COLUMN,
+        DataType::Utf8,
+        false,
+    )));
+    let mut columns = batch.columns().to_vec();
+    let names: ArrayRef = Arc::new(StringArray::from_iter_values(std::iter::repeat_n(
+        table,
+        batch.num_rows(),
+    )));
+    columns.push(names);
+    let schema = ArrowSchema::new_with_metadata(fields, schema.metadata().clone());
+    Ok(RecordBatch::try_new(Arc::new(schema), columns).map_err(lance::Error::from)?)
+}
+
+/// The `limit` rows of `batches` of the smallest distances, in one batch.
+///
+/// Rows of the same distance keep the order of their tables, and of their
+/// rows in the results of each table.
+fn merge(batches: Vec<RecordBatch>, limit: usize) -> Result<Vec<RecordBatch>> {
+    let Some(first) = batches.first() else {
+        return Ok(Vec::new());
+    };
+    let schema = first.schema();
+    for batch in &batches {
+        if batch.schema().fields() != schema.fields() {
+            let table = |batch: &RecordBatch| {
+                let names = batch[TABLE_NAME_COLUMN].as_any();
+                let names = names.downcast_ref::<StringArray>().unwrap();
+                names.value(0).to_string()
+            };
+            return Err(Error::InvalidInput {
+                message: format!(
+                    "the results of the tables '{}' and '{}' have different columns",
+                    table(first),
+                    table(batch)
+                ),
+            });
+        }
+    }
+    let batch = concat_batches(&schema, &batches).map_err(lance::Error::from)?;
+    let scores = batch
+        .column_by_name(DISTANCE_COLUMN)
+        .and_then(|scores| scores.as_any().downcast_ref::<Float32Array>())
+        .ok_or_else(|| Error::InvalidInput {
+            message: format!("the results of a search have no '{DISTANCE_COLUMN}' column"),
+        })?;
+    let score = |row: usize| scores.is_valid(row).then(|| scores.value(row));
+    let mut rows = (0..batch.num_rows()).collect::<Vec<_>>();
+    rows.sort_by(|a, b| match (score(*a), score(*b)) {
+        (Some(a), Some(b)) => a.total_cmp(&b),
+        (a, b) => a.is_none().cmp(&b.is_none()),
+    });
+    rows.truncate(limit);
+    let indices = UInt32Array::from_iter_values(rows.into_iter().map(|row| row as u32));
+    let columns = batch
+        .columns()
+        .iter()
+        .map(|column| take(column.as_ref(), &indices, None))
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .map_err(lance::Error::from)?;
+    Ok(vec![
+        RecordBatch::try_new(schema, columns).map_err(lance::Error::from)?
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow_array::{Int32Array, RecordBatchReader};
+    use lance::arrow::RecordBatchBuffer;
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::database::connect;
+
+    /// The rows `ids`, each with a pseudo-random vector.
+    fn rows(ids: std::ops::Range<i32>) -> Box<dyn RecordBatchReader> {
+        let vectors = ids
+            .clone()
+            .map(|id| {
+                let x = (id as u64 + 1).wrapping_mul(6364136223846793005) >> 24;
+                [
+                    (x % 100_000) as f32 / 100.0,
+                    (x >> 20 & 0xfffff) as f32 / 1000.0,
+                ]
+            })
+            .collect::<Vec<_>>();
+        let batch = crate::arrow::RecordBatchBuilder::new()
+            .column("id", Arc::new(Int32Array::from_iter_values(ids)))
+            .vector_column("vector", 2, vectors)
+            .build()
+            .unwrap();
+        Box::new(RecordBatchBuffer::new(vec![batch]))
+    }
+
+    /// The ids and tables of `batches`, in order.
+    fn ids(batches: &[RecordBatch]) -> Vec<(i32, String)> {
+        batches
+            .iter()
+            .flat_map(|b| {
+                let ids = b["id"].as_any().downcast_ref::<Int32Array>().unwrap();
+                let tables = b[TABLE_NAME_COLUMN]
+                    .as_any()
+                    .downcast_ref::<StringArray>()
+                    .unwrap();
+                (0..b.num_rows())
+                    .map(|i| (ids.value(i), tables.value(i).to_string()))
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_multi_search() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        let db = connect(uri).execute().await.unwrap();
+        let tables = ["en", "fr", "de"];
+        for (i, table) in tables.iter().enumerate() {
+            let start = i as i32 * 100;
+            db.create_table(table, rows(start..start + 100))
+                .execute()
+                .await
+                .unwrap();
+        }
+        let vector = vec![500.3, 499.7];
+
+        // The results of the tables merged by hand.
+        let mut expected = Vec::new();
+        for table in tables {
+            let results = db
+                .open_table(table)
+                .await
+                .unwrap()
+                .search(vector.clone().into())
+                .limit(100)
+                .execute()
+                .await
+                .unwrap()
+                .try_collect::<Vec<_>>()
+                .await
+                .unwrap();
+            for batch in &results {
+                let ids = batch["id"].as_any().downcast_ref::<Int32Array>().unwrap();
+                let scores = batch[DISTANCE_COLUMN]
+                    .as_any()
+                    .downcast_ref::<Float32Array>()
+                    .unwrap();
+                for row in 0..batch.num_rows() {
+                    expected.push((scores.value(row), ids.value(row), table.to_string()));
+                }
+            }
+        }
+        expected.sort_by(|a, b| a.0.total_cmp(&b.0));
+        let expected = expected
+            .into_iter()
+            .take(15)
+            .map(|(_, id, table)| (id, table))
+            .collect::<Vec<_>>();
+
+        let results = db
+            .multi_search(&tables, |query| query.nearest_to(vector.clone()))
+            .limit(15)
+            .execute()
+            .await
+            .unwrap();
+        assert!(results.failures.is_empty());
+        assert_eq!(ids(&results.batches), expected);
+        // The rows of all tables are among the nearest.
+        for table in tables {
+            assert!(expected.iter().any(|(_, t)| t == table));
+        }
+
+        // The queries are built by table.
+        let results = db
+            .multi_search(&tables, |query| {
+                let query = query.nearest_to(vector.clone());
+                match query.table_name() {
+                    Some("fr") => query.filter(Some("id < 0".to_string())),
+                    _ => query.nprobes(5),
+                }
+            })
+            .limit(15)
+            .execute()
+            .await
+            .unwrap();
+        let got = ids(&results.batches);
+        assert_eq!(got.len(), 15);
+        assert!(got.iter().all(|(_, t)| t != "fr"));
+
+        // A missing table fails the search, or is reported.
+        let with_missing = ["en", "missing", "de"];
+        let search = || db.multi_search(&with_missing, |query| query.nearest_to(vector.clone()));
+        let err = search().execute().await.unwrap_err();
+        assert!(matches!(err, Error::TableNotFound { .. }), "{err}");
+        let results = search()
+            .on_table_error(OnTableError::Report)
+            .limit(15)
+            .execute()
+            .await
+            .unwrap();
+        assert_eq!(results.failures.len(), 1);
+        assert_eq!(results.failures[0].table, "missing");
+        let got = ids(&results.batches);
+        assert_eq!(got.len(), 15);
+        assert!(got.iter().all(|(_, t)| t == "en" || t == "de"));
+
+        let err = db
+            .multi_search(&["en", "en"], |query| query.nearest_to(vector.clone()))
+            .execute()
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::InvalidInput { .. }), "{err}");
+    }
+}
@@ -0,0 +1,277 @@
+// Copyright 2023 LanceDB Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Handles to the tables of a database that open them when they are used, see
+//! [Database::table].
+//!
+//! A database of thousands of tables cannot keep all of them open. The
+//! connection keeps the tables used last open, up to
+//! [crate::ConnectBuilder::max_open_tables], and closes the least recently
+//! used one to open another. The handle of a closed table opens it again, at
+//! its latest version, the next time it is used.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use super::Database;
+use crate::error::Result;
+use crate::table::{OpenTableParams, TableRef};
+
+/// Default number of tables the [Database::table] handles of a connection keep
+/// open.
+pub const DEFAULT_MAX_OPEN_TABLES: usize = 128;
+
+struct OpenTable {
+    table: TableRef,
+    last_used: u64,
+}
+
+#[derive(Default)]
+struct RegistryState {
+    open: HashMap<String, OpenTable>,
+    clock: u64,
+}
+
+/// The tables opened by the [TableHandle]s of a connection, the least recently
+/// used closed first.
+pub(crate) struct TableRegistry {
+    capacity: usize,
+    state: Mutex<RegistryState>,
+    opens: AtomicU64,
+}
+
+impl TableRegistry {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            state: Mutex::new(RegistryState::default()),
+            opens: AtomicU64::new(0),
+        }
+    }
+
+    /// The open table `name`, now the most recently used.
+    fn get(&self, name: &str) -> Option<TableRef> {
+        let mut state = self.state.lock().unwrap();
+        state.clock += 1;
+        let clock = state.clock;
+        state.open.get_mut(name).map(|open| {
+            open.last_used = clock;
+            open.table.clone()
+        })
+    }
+
+    /// Keep `table`, opened for `name`, open, closing the least recently used
+    /// tables over the capacity. If another handle opened the table meanwhile,
+    /// its table is kept and returned, so that both share one view of it.
+    fn insert(&self, name: &str, table: TableRef) -> TableRef {
+        self.opens.fetch_add(1, Ordering::Relaxed);
+        let mut state = self.state.lock().unwrap();
+        state.clock += 1;
+        let clock = state.clock;
+        if let Some(open) = state.open.get_mut(name) {
+            open.last_used = clock;
+            return open.table.clone();
+        }
+        while state.open.len() >= self.capacity {
+            let lru = state
+                .open
+                .iter()
+                .min_by_key(|(_, open)| open.last_used)
+                .map(|(name, _)| name.clone());
+            match lru {
+                Some(lru) => state.open.remove(&lru),
+                None => break,
+            };
+        }
+        state.open.insert(
+            name.to_string(),
+            OpenTable {
+                table: table.clone(),
+                last_used: clock,
+            },
+        );
+        table
+    }
+
+    /// Close the table `name`, dropped or replaced.
+    pub(crate) fn forget(&self, name: &str) {
+        self.state.lock().unwrap().open.remove(name);
+    }
+
+    /// The number of open tables.
+    pub(crate) fn open_tables(&self) -> usize {
+        self.state.lock().unwrap().open.len()
+    }
+
+    /// The number of times a table was opened, counting the reopens of closed
+    /// tables.
+    pub(crate) fn opens(&self) -> u64 {
+        self.opens.load(Ordering::Relaxed)
+    }
+}
+
+/// A table of a database, opened when it is used, see [Database::table].
+pub struct TableHandle<'a> {
+    db: &'a Database,
+    name: String,
+}
+
+impl<'a> TableHandle<'a> {
+    pub(super) fn new(db: &'a Database, name: &str) -> Self {
+        Self {
+            db,
+            name: name.to_string(),
+        }
+    }
+
+    /// The name of the table.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The table, opened at its latest version if it is not open.
+    ///
+    /// The table stays usable when the connection closes it, so a query or
+    /// write running on it is not interrupted. Get the table again for each
+    /// use rather than holding it, a table held stays open.
+    ///
+    /// Fails with an [crate::Error::TableNotFound] if the table does not exist.
+    pub async fn table(&self) -> Result<TableRef> {
+        #[cfg(feature = "remote")]
+        if self.db.remote.is_some() {
+            return self.db.open_table(&self.name).await;
+        }
+        if let Some(table) = self.db.tables.get(&self.name) {
+            return Ok(table);
+        }
+        let table = self
+            .db
+            .open_native_table(&self.name, OpenTableParams::default())
+            .await?;
+        Ok(self.db.tables.insert(&self.name, Arc::new(table)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow_array::{Int32Array, RecordBatchReader};
+    use futures::TryStreamExt;
+    use lance::arrow::RecordBatchBuffer;
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::arrow::RecordBatchBuilder;
+    use crate::connect;
+    use crate::error::Error;
+
+    fn rows(ids: std::ops::Range<i32>) -> Box<dyn RecordBatchReader + Send> {
+        let vectors = ids.clone().map(|i| [i as f32, 1.0]).collect::<Vec<_>>();
+        let batch = RecordBatchBuilder::new()
+            .column("id", Arc::new(Int32Array::from_iter_values(ids)))
+            .vector_column("vector", 2, vectors)
+            .build()
+            .unwrap();
+        Box::new(RecordBatchBuffer::new(vec![batch]))
+    }
+
+    #[tokio::test]
+    async fn test_table_handles() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        let db = connect(uri).max_open_tables(2).execute().await.unwrap();
+        for (i, name) in ["a", "b", "c"].into_iter().enumerate() {
+            let ids = 0..(i as i32 + 1) * 10;
+            db.create_table(name, rows(ids)).execute().await.unwrap();
+        }
+        let handles = ["a", "b", "c"].map(|name| db.table(name));
+        // Handles open their tables when they are used.
+        assert_eq!(db.cache_stats().open_tables, 0);
+
+        for _ in 0..3 {
+            for (i, handle) in handles.iter().enumerate() {
+                let table = handle.table().await.unwrap();
+                assert_eq!(table.count_rows().await.unwrap(), (i + 1) * 10);
+            }
+        }
+        let stats = db.cache_stats();
+        assert_eq!(stats.open_tables, 2);
+        // Used in turn, two at most open, every table is reopened each time.
+        assert_eq!(stats.table_opens, 9);
+
+        // The most recently used tables stay open.
+        handles[2].table().await.unwrap();
+        handles[1].table().await.unwrap();
+        assert_eq!(db.cache_stats().table_opens, 9);
+
+        // A closed table is reopened at its latest version.
+        db.open_table("a")
+            .await
+            .unwrap()
+            .add(rows(100..105), None)
+            .await
+            .unwrap();
+        let a = handles[0].table().await.unwrap();
+        assert_eq!(a.count_rows().await.unwrap(), 15);
+        assert_eq!(db.cache_stats().table_opens, 10);
+
+        let err = db.table("missing").table().await.err().unwrap();
+        assert!(matches!(err, Error::TableNotFound { .. }), "{err}");
+        let err = connect(uri)
+            .max_open_tables(0)
+            .execute()
+            .await
+            .err()
+            .unwrap();
+        assert!(matches!(err, Error::InvalidInput { .. }), "{err}");
+    }
+
+    #[tokio::test]
+    async fn test_close_running_query() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        let db = connect(uri).max_open_tables(1).execute().await.unwrap();
+        db.create_table("a", rows(0..100)).execute().await.unwrap();
+        db.create_table("b", rows(0..10)).execute().await.unwrap();
+
+        let a = db.table("a");
+        let stream = a
+            .table()
+            .await
+            .unwrap()
+            .search(vec![50.0, 1.0].into())
+            .limit(3)
+            .execute()
+            .await
+            .unwrap();
+        // Opening `b` closes `a` while its query runs.
+        db.table("b").table().await.unwrap();
+        assert_eq!(db.cache_stats().open_tables, 1);
+        let batches = stream.try_collect::<Vec<_>>().await.unwrap();
+        let ids = batches[0]["id"].as_any().downcast_ref::<Int32Array>();
+        let mut ids = ids.unwrap().values().to_vec();
+        ids.sort();
+        assert_eq!(ids, vec![49, 50, 51]);
+
+        // Writes through a handle are seen by the reopened table.
+        a.table().await.unwrap().delete("id >= 50").await.unwrap();
+        db.table("b").table().await.unwrap();
+        assert_eq!(a.table().await.unwrap().count_rows().await.unwrap(), 50);
+
+        // A dropped table is closed.
+        db.drop_table("a").await.unwrap();
+        let err = a.table().await.err().unwrap();
+        assert!(matches!(err, Error::TableNotFound { .. }), "{err}");
+    }
+}
@@ -0,0 +1,362 @@
+// Copyright 2023 LanceDB Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Writes to several tables that are undone together when one of them fails.
+//!
+//! lance commits each table on its own, so a transaction is not atomic: its
+//! writes are committed one after the other, and readers see the tables written
+//! so far before it finishes. When a write fails, the tables written before it
+//! are restored to their versions from before the transaction, by committing
+//! those versions again with [NativeTable::restore], unless another client
+//! wrote to them since.
+
+use arrow_array::RecordBatchReader;
+use tracing::{field, info_span, warn};
+
+use super::Database;
+use crate::error::{Error, Result};
+use crate::spans::timed;
+use crate::table::{NativeTable, OpenTableParams, TableLike};
+
+/// A write of a [Transaction].
+enum Operation {
+    Add {
+        table: String,
+        batches: Box<dyn RecordBatchReader>,
+    },
+    Delete {
+        table: String,
+        predicate: String,
+    },
+}
+
+impl Operation {
+    fn table(&self) -> &str {
+        match self {
+            Self::Add { table, .. } | Self::Delete { table, .. } => table,
+        }
+    }
+}
+
+/// A table that a failed [Transaction] could not restore.
+#[derive(Debug)]
+pub struct RollbackFailure {
+    /// The name of the table.
+    pub table: String,
+    /// The version of the table before the transaction.
+    pub version: u64,
+    /// Why the version could not be restored.
+    pub error: Error,
+}
+
+/// Writes to several tables of a database, see [Database::transaction].
+///
+/// The writes run in the order they were added when the transaction is
+/// executed. If one fails, the tables written before it are rolled back and
+/// [Error::TransactionFailed] reports the failure and the tables that could not
+/// be rolled back. A table another client committed to after the transaction
+/// wrote it is not rolled back, which would undo that write, and is reported
+/// with an [Error::CommitConflict].
+pub struct Transaction<'a> {
+    db: &'a Database,
+    operations: Vec<Operation>,
+}
+
+impl<'a> Transaction<'a> {
+    pub(super) fn new(db: &'a Database) -> Self {
+        Self {
+            db,
+            operations: Vec::new(),
+        }
+    }
+
+    /// Append `batches` to `table`.
+    pub fn add(mut self, table: &str, batches: Box<dyn RecordBatchReader>) -> Self {
+        self.operations.push(Operation::Add {
+            table: table.to_string(),
+            batches,
+        });
+        self
+    }
+
+    /// Delete the rows of `table` matching `predicate`, a SQL WHERE clause.
+    pub fn delete(mut self, table: &str, predicate: &str) -> Self {
+        self.operations.push(Operation::Delete {
+            table: table.to_string(),
+            predicate: predicate.to_string(),
+        });
+        self
+    }
+
+    /// Run the writes of the transaction.
+    ///
+    /// All tables are opened first, a missing table fails the transaction
+    /// before anything is written.
+    pub async fn execute(self) -> Result<()> {
+        let span = info_span!(
+            "transaction",
+            operations = self.operations.len(),
+            elapsed_ms = field::Empty
+        );
+        timed(span, self.run()).await
+    }
+
+    async fn run(self) -> Result<()> {
+        #[cfg(feature = "remote")]
+        if self.db.remote.is_some() {
+            return Err(Error::InvalidInput {
+                message: "transactions need a local database".to_string(),
+            });
+        }
+        // The tables of the transaction, with their latest versions before it
+        // and the last versions it committed.
+        let mut tables: Vec<(NativeTable, u64, u64)> = Vec::new();
+        for operation in &self.operations {
+            if tables.iter().any(|(t, _, _)| t.name() == operation.table()) {
+                continue;
+            }
+            let table = self
+                .db
+                .open_native_table(operation.table(), OpenTableParams::default())
+                .await?;
+            let version = latest_version(&table).await?;
+            tables.push((table, version, version));
+        }
+
+        let mut written = Vec::new();
+        for operation in self.operations {
+            let position = tables
+                .iter()
+                .position(|(t, _, _)| t.name() == operation.table())
+                .unwrap();
+            if !written.contains(&position) {
+                written.push(position);
+            }
+            let table = &tables[position].0;
+            let result = match operation {
+                Operation::Add { batches, .. } => table.add(batches, None).await.map(|_| ()),
+                Operation::Delete { predicate, .. } => table.delete(&predicate).await,
+            };
+            if let Err(e) = result {
+                let written = written.iter().map(|i| &tables[*i]).collect::<Vec<_>>();
+                return Err(Error::TransactionFailed {
+                    source: Box::new(e),
+                    not_rolled_back: roll_back(&written).await,
+                });
+            }
+            tables[position].2 = tables[position].0.version();
+        }
+        Ok(())
+    }
+}
+
+/// The latest version of `table` in storage.
+async fn latest_version(table: &NativeTable) -> Result<u64> {
+    let versions = table.versions().await?;
+    Ok(versions.iter().map(|v| v.version).max().unwrap_or_default())
+}
+
+/// Restore `tables` to their versions, in reverse order, returning those that
+/// could not be restored. A failure does not stop the others.
+///
+/// A table is only restored while its latest version is the last one the
+/// transaction committed.
+async fn roll_back(tables: &[&(NativeTable, u64, u64)]) -> Vec<RollbackFailure> {
+    let mut failures = Vec::new();
+    for (table, version, committed) in tables.iter().rev() {
+        let result = match latest_version(table).await {
+            Ok(latest) if latest == *version => Ok(()),
+            Ok(_) => table.restore_from(*version, Some(*committed)).await,
+            Err(e) => Err(e),
+        };
+        if let Err(error) = result {
+            warn!(table = %table.name(), version, %error, "the table was not rolled back");
+            failures.push(RollbackFailure {
+                table: table.name().to_string(),
+                version: *version,
+                error,
+            });
+        }
+    }
+    failures
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_array::{Int32Array, RecordBatch};
+    use arrow_schema::{DataType, Field, Schema};
+    use lance::arrow::RecordBatchBuffer;
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::database::connect;
+
+    fn ids(column: &str, ids: Vec<i32>) -> Box<dyn RecordBatchReader> {
+        let schema = Arc::new(Schema::new(vec![Field::new(
+            column,
+            DataType::Int32,
+            false,
+        )]));
+        let batch = RecordBatch::try_new(schema, vec![Arc::new(Int32Array::from(ids))]).unwrap();
+        Box::new(RecordBatchBuffer::new(vec![batch]))
+    }
+
+    #[tokio::test]
+    async fn test_transaction() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        let db = connect(uri).execute().await.unwrap();
+        db.create_table("documents", ids("id", vec![1, 2]))
+            .execute()
+            .await
+            .unwrap();
+        db.create_table("chunks", ids("document", vec![1, 1, 2]))
+            .execute()
+            .await
+            .unwrap();
+        let open = |name| async move { NativeTable::open(uri, name).await.unwrap() };
+
+        db.transaction()
+            .add("documents", ids("id", vec![3]))
+            .add("chunks", ids("document", vec![3, 3]))
+            .delete("chunks", "document = 1")
+            .execute()
+            .await
+            .unwrap();
+        assert_eq!(open("documents").await.count_rows().await.unwrap(), 3);
+        assert_eq!(open("chunks").await.count_rows().await.unwrap(), 3);
+
+        // The second table rejects the rows, the first is restored.
+        let err = db
+            .transaction()
+            .add("documents", ids("id", vec![4]))
+            .delete("documents", "id = 1")
+            .add("chunks", ids("id", vec![4]))
+            .execute()
+            .await
+            .unwrap_err();
+        let Error::TransactionFailed {
+            source,
+            not_rolled_back,
+        } = err
+        else {
+            panic!("expected a failed transaction, got {err}");
+        };
+        assert!(matches!(*source, Error::Schema { .. }), "{source}");
+        assert!(not_rolled_back.is_empty());
+        let documents = open("documents").await;
+        assert_eq!(documents.count_rows().await.unwrap(), 3);
+        assert_eq!(documents.versions().await.unwrap().len(), 5);
+        let chunks = open("chunks").await;
+        assert_eq!(chunks.count_rows().await.unwrap(), 3);
+        assert_eq!(chunks.versions().await.unwrap().len(), 3);
+
+        // A missing table fails before any write.
+        let err = db
+            .transaction()
+            .add("documents", ids("id", vec![5]))
+            .add("missing", ids("id", vec![5]))
+            .execute()
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::TableNotFound { .. }), "{err}");
+        assert_eq!(documents.versions().await.unwrap().len(), 5);
+    }
+
+    #[tokio::test]
+    async fn test_memory_transaction() {
+        let db = connect("memory://").execute().await.unwrap();
+        db.create_table("documents", ids("id", vec![1, 2]))
+            .execute()
+            .await
+            .unwrap();
+        db.create_table("chunks", ids("document", vec![1, 1, 2]))
+            .execute()
+            .await
+            .unwrap();
+
+        let err = db
+            .transaction()
+            .add("documents", ids("id", vec![3]))
+            .add("chunks", ids("id", vec![3]))
+            .execute()
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::TransactionFailed { .. }), "{err}");
+        let documents = db
+            .open_native_table("documents", OpenTableParams::default())
+            .await
+            .unwrap();
+        assert_eq!(documents.count_rows().await.unwrap(), 2);
+        assert_eq!(documents.versions().await.unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_roll_back_partial_failure() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        let a = NativeTable::create(uri, "a", ids("id", vec![1]), None)
+            .await
+            .unwrap();
+        let b = NativeTable::create(uri, "b", ids("id", vec![1]), None)
+            .await
+            .unwrap();
+        a.add(ids("id", vec![2]), None).await.unwrap();
+        b.add(ids("id", vec![2]), None).await.unwrap();
+
+        // The version of `b` does not exist, `a` is still restored.
+        let failures = roll_back(&[&(a.clone(), 1, 2), &(b.clone(), 7, 2)]).await;
+        assert_eq!(failures.len(), 1);
+        assert_eq!((failures[0].table.as_str(), failures[0].version), ("b", 7));
+        assert_eq!(a.count_rows().await.unwrap(), 1);
+        assert_eq!(b.count_rows().await.unwrap(), 2);
+
+        let err = Error::TransactionFailed {
+            source: Box::new(Error::InvalidInput {
+                message: "bad rows".to_string(),
+            }),
+            not_rolled_back: failures,
+        };
+        assert!(
+            err.to_string()
+                .starts_with("LanceDBError: Transaction failed: LanceDBError: Invalid input: bad rows, the tables were not rolled back: 'b' to version 7 ("),
+            "{err}"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_roll_back_after_other_writer() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        let table = NativeTable::create(uri, "a", ids("id", vec![1]), None)
+            .await
+            .unwrap();
+        table.add(ids("id", vec![2]), None).await.unwrap();
+        // Another client appends after the write of the transaction, version 2.
+        let other = NativeTable::open(uri, "a").await.unwrap();
+        other.add(ids("id", vec![3]), None).await.unwrap();
+
+        let failures = roll_back(&[&(table.clone(), 1, 2)]).await;
+        assert_eq!(failures.len(), 1);
+        assert!(
+            matches!(failures[0].error, Error::CommitConflict { .. }),
+            "{}",
+            failures[0].error
+        );
+        assert_eq!(other.count_rows().await.unwrap(), 3);
+        assert_eq!(latest_version(&other).await.unwrap(), 3);
+    }
+}
@@ -0,0 +1,662 @@
+// Copyright 2023 LanceDB Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Embedding functions turn a source column, e.g. text, into a vector column.
+//!
+//! A table created with an [EmbeddingDefinition] computes the vector column
+//! itself whenever it is missing from the data being added.
+
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::sync::{Arc, RwLock};
+
+use arrow_array::cast::AsArray;
+use arrow_array::types::Float32Type;
+use arrow_array::{
+    Array, ArrayRef, FixedSizeListArray, Float32Array, RecordBatch, RecordBatchIterator,
+    RecordBatchReader, StringArray,
+};
+use arrow_schema::{DataType, Field, Schema, SchemaRef};
+use async_trait::async_trait;
+use lance::io::object_store::ObjectStore;
+use object_store::path::Path;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+
+#[cfg(feature = "openai")]
+pub mod openai;
+#[cfg(feature = "sentence-transformers")]
+pub mod sentence_transformers;
+
+/// A function that embeds the values of a column into vectors.
+#[async_trait]
+pub trait EmbeddingFunction: Debug + Send + Sync {
+    /// The name of the provider of this function, e.g. `openai`.
+    ///
+    /// Tables persist the name, so that a later connection can look the function
+    /// up again in its [EmbeddingRegistry].
+    fn name(&self) -> String;
+
+    /// The parameters of this function, e.g. the model, persisted with the table.
+    ///
+    /// Must not contain secrets such as API keys.
+    fn params(&self) -> HashMap<String, String> {
+        HashMap::new()
+    }
+
+    /// The type of the column this function embeds, e.g. [DataType::Utf8].
+    fn source_type(&self) -> DataType;
+
+    /// The number of dimensions of the produced vectors.
+    fn dims(&self) -> usize;
+
+    /// Embed `input`, an array of [EmbeddingFunction::source_type].
+    ///
+    /// # Returns
+    ///
+    /// * A `FixedSizeList<Float32>` array of width [EmbeddingFunction::dims],
+    ///   with one vector per input value.
+    async fn embed(&self, input: ArrayRef) -> Result<ArrayRef>;
+}
+
+/// Links the source column of a table to the vector column computed from it.
+#[derive(Debug, Clone)]
+pub struct EmbeddingDefinition {
+    /// The column holding the values to embed.
+    pub source_column: String,
+    /// The column the vectors are written to.
+    pub vector_column: String,
+    /// The function computing the vectors.
+    pub function: Arc<dyn EmbeddingFunction>,
+}
+
+impl EmbeddingDefinition {
+    pub fn new(
+        source_column: impl Into<String>,
+        vector_column: impl Into<String>,
+        function: Arc<dyn EmbeddingFunction>,
+    ) -> Self {
+        Self {
+            source_column: source_column.into(),
+            vector_column: vector_column.into(),
+            function,
+        }
+    }
+
+    /// Rename the column `from` to `to` in the columns of this embedding.
+    pub(crate) fn rename_column(&mut self, from: &str, to: &str) {
+        for column in [&mut self.source_column, &mut self.vector_column] {
+            if *column == from {
+                *column = to.to_string();
+            }
+        }
+    }
+
+    /// The field of the vector column.
+    fn vector_field(&self) -> Field {
+        Field::new(
+            &self.vector_column,
+            DataType::FixedSizeList(
+                Arc::new(Field::new("item", DataType::Float32, true)),
+                self.function.dims() as i32,
+            ),
+            true,
+        )
+    }
+
+    /// Embed the source column of `batch`.
+    async fn embed(&self, batch: &RecordBatch) -> Result<ArrayRef> {
+        let source =
+            batch
+                .column_by_name(&self.source_column)
+                .ok_or_else(|| Error::InvalidInput {
+                    message: format!(
+                        "the source column '{}' of the embedding is missing",
+                        self.source_column
+                    ),
+                })?;
+        self.embed_array(source.clone()).await
+    }
+
+    /// Embed a query text.
+    pub(crate) async fn embed_text(&self, text: &str) -> Result<Float32Array> {
+        if self.function.source_type() != DataType::Utf8 {
+            return Err(Error::InvalidInput {
+                message: format!(
+                    "cannot search for text, the embedding function of '{}' embeds {}",
+                    self.vector_column,
+                    self.function.source_type()
+                ),
+            });
+        }
+        let vectors = self
+            .embed_array(Arc::new(StringArray::from(vec![text])))
+            .await?;
+        // The type was checked by embed_array.
+        let vectors = vectors
+            .as_any()
+            .downcast_ref::<FixedSizeListArray>()
+            .expect("embedding returned a FixedSizeListArray");
+        Ok(vectors.value(0).as_primitive::<Float32Type>().clone())
+    }
+
+    /// Embed `source`, checking the shape of the vectors produced.
+    async fn embed_array(&self, source: ArrayRef) -> Result<ArrayRef> {
+        let len = source.len();
+        let vectors = self.function.embed(source).await?;
+        let field = self.vector_field();
+        if let DataType::FixedSizeList(_, got) = vectors.data_type() {
+            if *got as usize != self.function.dims() {
+                return Err(Error::EmbeddingDimensionMismatch {
+                    column: self.vector_column.clone(),
+                    expected: self.function.dims(),
+                    got: *got as usize,
+                });
+            }
+        }
+        if vectors.data_type() != field.data_type() || vectors.len() != len {
+            return Err(Error::InvalidInput {
+                message: format!(
+                    "the embedding of '{}' returned {} values of type {}, expected {} values of type {}",
+                    self.source_column,
+                    vectors.len(),
+                    vectors.data_type(),
+                    len,
+                    field.data_type()
+                ),
+            });
+        }
+        Ok(vectors)
+    }
+}
+
+/// Builds the embedding function of a provider from the parameters persisted
+/// with a table, see [EmbeddingRegistry::register_factory].
+pub type EmbeddingFactory =
+    Arc<dyn Fn(&HashMap<String, String>) -> Result<Arc<dyn EmbeddingFunction>> + Send + Sync>;
+
+/// The embedding functions of a connection.
+///
+/// Tables created with an embedding function persist its name and parameters;
+/// when such a table is opened, the factory registered under that name builds
+/// the function from the parameters.
+#[derive(Default)]
+pub struct EmbeddingRegistry {
+    factories: RwLock<HashMap<String, EmbeddingFactory>>,
+}
+
+impl Debug for EmbeddingRegistry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EmbeddingRegistry")
+            .field("providers", &self.names())
+            .finish()
+    }
+}
+
+/// A factory building `function` for its own parameters only.
+fn fixed_factory(function: Arc<dyn EmbeddingFunction>) -> EmbeddingFactory {
+    Arc::new(move |params| {
+        if &function.params() != params {
+            return Err(Error::InvalidInput {
+                message: format!(
+                    "the embedding provider '{}' is registered with parameters {:?}, the table was created with {:?}",
+                    function.name(),
+                    function.params(),
+                    params
+                ),
+            });
+        }
+        Ok(function.clone())
+    })
+}
+
+impl EmbeddingRegistry {
+    /// Register `factory` under the provider `name`, replacing any provider of
+    /// the same name. The tables created with the provider get the function
+    /// the factory builds from their parameters.
+    pub fn register_factory(
+        &self,
+        name: impl Into<String>,
+        factory: impl Fn(&HashMap<String, String>) -> Result<Arc<dyn EmbeddingFunction>>
+            + Send
+            + Sync
+            + 'static,
+    ) {
+        self.factories
+            .write()
+            .unwrap()
+            .insert(name.into(), Arc::new(factory));
+    }
+
+    /// Register `function` under its [EmbeddingFunction::name], replacing any
+    /// provider of the same name.
+    ///
+    /// Only the tables created with the [EmbeddingFunction::params] of
+    /// `function` can be opened, [Self::register_factory] registers a provider
+    /// for all of them.
+    pub fn register(&self, function: Arc<dyn EmbeddingFunction>) {
+        self.factories
+            .write()
+            .unwrap()
+            .insert(function.name(), fixed_factory(function));
+    }
+
+    /// Build the function of the provider `name` with `params`.
+    ///
+    /// Fails with [Error::MissingEmbeddingProvider] if no provider is registered
+    /// under `name`, or with the error of its factory.
+    pub fn create(
+        &self,
+        name: &str,
+        params: &HashMap<String, String>,
+    ) -> Result<Arc<dyn EmbeddingFunction>> {
+        let factory = self
+            .factories
+            .read()
+            .unwrap()
+            .get(name)
+            .cloned()
+            .ok_or_else(|| Error::MissingEmbeddingProvider {
+                name: name.to_string(),
+            })?;
+        factory(params)
+    }
+
+    /// The names of the registered providers.
+    pub fn names(&self) -> Vec<String> {
+        self.factories.read().unwrap().keys().cloned().collect()
+    }
+
+    /// Register the functions of `embeddings` whose providers are not
+    /// registered yet.
+    pub(crate) fn register_missing(&self, embeddings: &[EmbeddingDefinition]) {
+        let mut factories = self.factories.write().unwrap();
+        for embedding in embeddings {
+            factories
+                .entry(embedding.function.name())
+                .or_insert_with(|| fixed_factory(embedding.function.clone()));
+        }
+    }
+
+    /// Restore the embeddings persisted with a table.
+    pub(crate) fn rehydrate(
+        &self,
+        configs: Vec<EmbeddingConfig>,
+    ) -> Result<Vec<EmbeddingDefinition>> {
+        configs
+            .into_iter()
+            .map(|config| {
+                let function = self.create(&config.provider, &config.params)?;
+                Ok(EmbeddingDefinition {
+                    source_column: config.source_column,
+                    vector_column: config.vector_column,
+                    function,
+                })
+            })
+            .collect()
+    }
+}
+
+/// The persisted form of an [EmbeddingDefinition].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct EmbeddingConfig {
+    source_column: String,
+    vector_column: String,
+    provider: String,
+    params: HashMap<String, String>,
+}
+
+impl EmbeddingConfig {
+    /// Rename the column `from` to `to` in the columns of this embedding.
+    pub(crate) fn rename_column(&mut self, from: &str, to: &str) {
+        for column in [&mut self.source_column, &mut self.vector_column] {
+            if *column == from {
+                *column = to.to_string();
+            }
+        }
+    }
+}
+
+impl From<&EmbeddingDefinition> for EmbeddingConfig {
+    fn from(embedding: &EmbeddingDefinition) -> Self {
+        Self {
+            source_column: embedding.source_column.clone(),
+            vector_column: embedding.vector_column.clone(),
+            provider: embedding.function.name(),
+            params: embedding.function.params(),
+        }
+    }
+}
+
+/// lance 0.5 does not persist schema metadata, so the embeddings of a table are
+/// kept in this directory of the table directory instead, in a file for each
+/// version that changed them, named after the version.
+pub(crate) const EMBEDDINGS_DIR: &str = "_embeddings";
+
+/// The file of the embeddings of the tables written before they were kept by
+/// version, for all their versions.
+const LEGACY_EMBEDDINGS_FILE: &str = "_embeddings.json";
+
+fn encode_configs(configs: &[EmbeddingConfig]) -> Result<Vec<u8>> {
+    serde_json::to_vec(configs).map_err(|e| Error::InvalidInput {
+        message: format!("cannot encode the embedding configuration: {e}"),
+    })
+}
+
+fn decode_configs(path: &Path, bytes: &[u8]) -> Result<Vec<EmbeddingConfig>> {
+    serde_json::from_slice(bytes).map_err(|e| Error::InvalidMetadata {
+        path: path.to_string(),
+        message: format!("invalid embedding configuration: {e}"),
+    })
+}
+
+/// The embeddings of the version `version` of the table at `base`, those
+/// written by the latest version up to it.
+pub(crate) async fn read_configs(
+    store: &ObjectStore,
+    base: &Path,
+    version: u64,
+) -> Result<Vec<EmbeddingConfig>> {
+    let dir = base.child(EMBEDDINGS_DIR);
+    let written = match store.inner.list_with_delimiter(Some(&dir)).await {
+        Ok(list) => list.objects,
+        Err(object_store::Error::NotFound { .. }) => Vec::new(),
+        Err(e) => return Err(e.into()),
+    };
+    let path = written
+        .iter()
+        .filter_map(|object| {
+            let name = object.location.filename()?;
+            let written = name.strip_suffix(".json")?.parse::<u64>().ok()?;
+            (written <= version).then_some((written, &object.location))
+        })
+        .max_by_key(|(written, _)| *written)
+        .map_or_else(
+            || base.child(LEGACY_EMBEDDINGS_FILE),
+            |(_, path)| path.clone(),
+        );
+    match store.inner.get(&path).await {
+        Ok(result) => decode_configs(&path, &result.bytes().await?),
+        Err(object_store::Error::NotFound { .. }) => Ok(Vec::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Record `configs` as the embeddings of the version `version` and the later
+/// ones of the table at `base`.
+pub(crate) async fn write_configs(
+    store: &ObjectStore,
+    base: &Path,
+    version: u64,
+    configs: &[EmbeddingConfig],
+) -> Result<()> {
+    let path = base.child(EMBEDDINGS_DIR).child(format!("{version}.json"));
+    store
+        .inner
+        .put(&path, encode_configs(configs)?.into())
+        .await?;
+    Ok(())
+}
+
+/// Check that the embeddings can be computed for data of `schema`.
+pub(crate) fn validate(embeddings: &[EmbeddingDefinition], schema: &Schema) -> Result<()> {
+    for embedding in embeddings {
+        let field = schema
+            .field_with_name(&embedding.source_column)
+            .map_err(|_| Error::InvalidInput {
+                message: format!(
+                    "the source column '{}' of the embedding is missing",
+                    embedding.source_column
+                ),
+            })?;
+        if field.data_type() != &embedding.function.source_type() {
+            return Err(Error::InvalidInput {
+                message: format!(
+                    "the embedding of '{}' expects {}, the column is {}",
+                    embedding.source_column,
+                    embedding.function.source_type(),
+                    field.data_type()
+                ),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Add the vector columns missing from `batches`.
+///
+/// Vector columns already present are kept as they are, so callers can supply
+/// precomputed vectors.
+pub(crate) async fn embed_batches(
+    embeddings: &[EmbeddingDefinition],
+    batches: Box<dyn RecordBatchReader>,
+) -> Result<Box<dyn RecordBatchReader>> {
+    let input_schema = batches.schema();
+    let missing: Vec<_> = embeddings
+        .iter()
+        .filter(|e| input_schema.field_with_name(&e.vector_column).is_err())
+        .cloned()
+        .collect();
+    if missing.is_empty() {
+        return Ok(batches);
+    }
+    validate(&missing, &input_schema)?;
+
+    let mut fields: Vec<Field> = input_schema
+        .fields()
+        .iter()
+        .map(|f| f.as_ref().clone())
+        .collect();
+    fields.extend(missing.iter().map(|e| e.vector_field()));
+    let schema: SchemaRef = Arc::new(Schema::new_with_metadata(
+        fields,
+        input_schema.metadata().clone(),
+    ));
+
+    let mut output = Vec::new();
+    for batch in batches {
+        let batch = batch.map_err(lance::Error::from)?;
+        let mut columns = batch.columns().to_vec();
+        for embedding in &missing {
+            columns.push(embedding.embed(&batch).await?);
+        }
+        output.push(RecordBatch::try_new(schema.clone(), columns).map_err(lance::Error::from)?);
+    }
+    Ok(Box::new(RecordBatchIterator::new(
+        output.into_iter().map(Ok),
+        schema,
+    )))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use lance::arrow::FixedSizeListArrayExt;
+
+    use super::*;
+
+    /// Embeds a string as `[len, first byte, 0, ...]`.
+    #[derive(Debug)]
+    pub(crate) struct MockEmbedding {
+        pub(crate) dims: usize,
+    }
+
+    #[async_trait]
+    impl EmbeddingFunction for MockEmbedding {
+        fn name(&self) -> String {
+            "mock".to_string()
+        }
+
+        fn params(&self) -> HashMap<String, String> {
+            HashMap::from([("dims".to_string(), self.dims.to_string())])
+        }
+
+        fn source_type(&self) -> DataType {
+            DataType::Utf8
+        }
+
+        fn dims(&self) -> usize {
+            self.dims
+        }
+
+        async fn embed(&self, input: ArrayRef) -> Result<ArrayRef> {
+            let strings = input
+                .as_any()
+                .downcast_ref::<StringArray>()
+                .ok_or_else(|| Error::InvalidInput {
+                    message: "expected strings".to_string(),
+                })?;
+            let values = Float32Array::from_iter_values(strings.iter().flat_map(|s| {
+                let s = s.unwrap_or_default();
+                let mut vector = vec![0.0; self.dims];
+                vector[0] = s.len() as f32;
+                vector[1] = s.bytes().next().unwrap_or_default() as f32;
+                vector
+            }));
+            Ok(Arc::new(FixedSizeListArray::try_new(
+                values,
+                self.dims as i32,
+            )?))
+        }
+    }
+
+    /// Always produces vectors of the wrong width.
+    #[derive(Debug)]
+    struct WrongDims;
+
+    #[async_trait]
+    impl EmbeddingFunction for WrongDims {
+        fn name(&self) -> String {
+            "wrong-dims".to_string()
+        }
+
+        fn source_type(&self) -> DataType {
+            DataType::Utf8
+        }
+
+        fn dims(&self) -> usize {
+            4
+        }
+
+        async fn embed(&self, input: ArrayRef) -> Result<ArrayRef> {
+            MockEmbedding { dims: 8 }.embed(input).await
+        }
+    }
+
+    pub(crate) fn make_text_batches(texts: &[&str]) -> Box<dyn RecordBatchReader + Send> {
+        let schema = Arc::new(Schema::new(vec![Field::new("text", DataType::Utf8, false)]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![Arc::new(StringArray::from_iter_values(texts))],
+        )
+        .unwrap();
+        Box::new(RecordBatchIterator::new(vec![Ok(batch)], schema))
+    }
+
+    #[tokio::test]
+    async fn test_embed_batches() {
+        let embeddings = vec![EmbeddingDefinition::new(
+            "text",
+            "vector",
+            Arc::new(MockEmbedding { dims: 4 }),
+        )];
+        let reader = embed_batches(&embeddings, make_text_batches(&["ab", "xyz"]))
+            .await
+            .unwrap();
+        let batches: Vec<_> = reader.map(|b| b.unwrap()).collect();
+        let vectors = batches[0]
+            .column_by_name("vector")
+            .unwrap()
+            .as_any()
+            .downcast_ref::<FixedSizeListArray>()
+            .unwrap();
+        assert_eq!(vectors.value_length(), 4);
+        let first = vectors.value(1);
+        let first = first.as_any().downcast_ref::<Float32Array>().unwrap();
+        assert_eq!(first.values(), &[3.0, b'x' as f32, 0.0, 0.0]);
+    }
+
+    #[tokio::test]
+    async fn test_dims_validated() {
+        let embeddings = vec![EmbeddingDefinition::new(
+            "text",
+            "vector",
+            Arc::new(WrongDims),
+        )];
+        let err = embed_batches(&embeddings, make_text_batches(&["ab"]))
+            .await
+            .err()
+            .unwrap();
+        assert!(
+            matches!(
+                err,
+                Error::EmbeddingDimensionMismatch {
+                    ref column,
+                    expected: 4,
+                    got: 8,
+                } if column == "vector"
+            ),
+            "{err}"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_configs_by_version() {
+        let (store, base) = ObjectStore::from_uri("memory:///table").await.unwrap();
+        assert_eq!(read_configs(&store, &base, 1).await.unwrap(), vec![]);
+        let config = |dims| {
+            EmbeddingConfig::from(&EmbeddingDefinition::new(
+                "text",
+                "vector",
+                Arc::new(MockEmbedding { dims }),
+            ))
+        };
+        // The embeddings written before they were kept by version.
+        let legacy = encode_configs(&[config(2)]).unwrap();
+        store
+            .inner
+            .put(&base.child(LEGACY_EMBEDDINGS_FILE), legacy.into())
+            .await
+            .unwrap();
+        write_configs(&store, &base, 2, &[config(4)]).await.unwrap();
+        write_configs(&store, &base, 10, &[]).await.unwrap();
+
+        for (version, expected) in [
+            (1, vec![config(2)]),
+            (2, vec![config(4)]),
+            (9, vec![config(4)]),
+            (10, vec![]),
+            (12, vec![]),
+        ] {
+            assert_eq!(
+                read_configs(&store, &base, version).await.unwrap(),
+                expected
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_source_type_validated() {
+        let embeddings = vec![EmbeddingDefinition::new(
+            "text",
+            "vector",
+            Arc::new(MockEmbedding { dims: 4 }),
+        )];
+        let schema = Schema::new(vec![Field::new("text", DataType::Int32, false)]);
+        assert!(matches!(
+            validate(&embeddings, &schema).unwrap_err(),
+            Error::InvalidInput { .. }
+        ));
+    }
+}
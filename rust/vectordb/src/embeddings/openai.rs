@@ -0,0 +1,443 @@
+// Copyright 2023 LanceDB Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Embeddings computed by the OpenAI embeddings API.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use arrow_array::{Array, ArrayRef, FixedSizeListArray, Float32Array, StringArray};
+use arrow_schema::DataType;
+use async_trait::async_trait;
+use lance::arrow::FixedSizeListArrayExt;
+use reqwest::StatusCode;
+use serde::Deserialize;
+use serde_json::json;
+
+use super::EmbeddingFunction;
+use crate::error::{Error, Result};
+
+const DEFAULT_BASE_URL: &str = "https://api.openai.com/v1";
+const API_KEY_ENV: &str = "OPENAI_API_KEY";
+/// The most inputs the API accepts in one request.
+const MAX_BATCH_SIZE: usize = 2048;
+const DEFAULT_MAX_RETRIES: usize = 5;
+const DEFAULT_RETRY_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_RETRY_BACKOFF: Duration = Duration::from_secs(30);
+
+/// The number of dimensions of the vectors produced by the known models.
+fn model_dims(model: &str) -> Option<usize> {
+    match model {
+        "text-embedding-ada-002" | "text-embedding-3-small" => Some(1536),
+        "text-embedding-3-large" => Some(3072),
+        _ => None,
+    }
+}
+
+#[derive(Deserialize)]
+struct EmbeddingResponse {
+    data: Vec<EmbeddingData>,
+}
+
+#[derive(Deserialize)]
+struct EmbeddingData {
+    index: usize,
+    embedding: Vec<f32>,
+}
+
+/// Embeds text with an OpenAI embedding model.
+#[derive(Debug, Clone)]
+pub struct OpenAIEmbeddingFunction {
+    client: reqwest::Client,
+    model: String,
+    dims: usize,
+    api_key: Option<String>,
+    base_url: String,
+    batch_size: usize,
+    max_retries: usize,
+    retry_backoff: Duration,
+}
+
+impl OpenAIEmbeddingFunction {
+    /// Embed with `model`, one of `text-embedding-ada-002`,
+    /// `text-embedding-3-small` or `text-embedding-3-large`.
+    ///
+    /// Use [OpenAIEmbeddingFunction::new_with_dims] for other models.
+    pub fn new(model: &str) -> Result<Self> {
+        let dims = model_dims(model).ok_or_else(|| Error::InvalidInput {
+            message: format!(
+                "the dimensions of the OpenAI model '{model}' are unknown, use new_with_dims"
+            ),
+        })?;
+        Ok(Self::new_with_dims(model, dims))
+    }
+
+    /// Embed with `model`, which produces vectors of `dims` dimensions.
+    pub fn new_with_dims(model: &str, dims: usize) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            model: model.to_string(),
+            dims,
+            api_key: None,
+            base_url: DEFAULT_BASE_URL.to_string(),
+            batch_size: MAX_BATCH_SIZE,
+            max_retries: DEFAULT_MAX_RETRIES,
+            retry_backoff: DEFAULT_RETRY_BACKOFF,
+        }
+    }
+
+    /// Embed with the model of `params`, the [EmbeddingFunction::params] of a
+    /// function, e.g. in a factory of
+    /// [EmbeddingRegistry::register_factory](super::EmbeddingRegistry::register_factory).
+    pub fn from_params(params: &HashMap<String, String>) -> Result<Self> {
+        let model = params.get("model").ok_or_else(|| Error::InvalidInput {
+            message: "the OpenAI embedding parameters have no model".to_string(),
+        })?;
+        match params.get("dims") {
+            Some(dims) => {
+                let dims = dims.parse().map_err(|_| Error::InvalidInput {
+                    message: format!("invalid dimensions of the OpenAI model '{model}': {dims}"),
+                })?;
+                Ok(Self::new_with_dims(model, dims))
+            }
+            None => Self::new(model),
+        }
+    }
+
+    /// Set the API key. By default it is read from `OPENAI_API_KEY`.
+    pub fn api_key(mut self, api_key: impl Into<String>) -> Self {
+        self.api_key = Some(api_key.into());
+        self
+    }
+
+    /// Send requests to `base_url` instead of `https://api.openai.com/v1`.
+    pub fn base_url(mut self, base_url: &str) -> Self {
+        self.base_url = base_url.trim_end_matches('/').to_string();
+        self
+    }
+
+    /// Set the most inputs sent in one request, at most 2048.
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.clamp(1, MAX_BATCH_SIZE);
+        self
+    }
+
+    /// Set how many times a rate limited request is retried, and the wait
+    /// before the first retry. The wait doubles on every retry.
+    pub fn retries(mut self, max_retries: usize, backoff: Duration) -> Self {
+        self.max_retries = max_retries;
+        self.retry_backoff = backoff;
+        self
+    }
+
+    /// The model used to embed.
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    fn resolve_api_key(&self) -> Result<String> {
+        self.api_key
+            .clone()
+            .or_else(|| std::env::var(API_KEY_ENV).ok())
+            .ok_or_else(|| Error::InvalidInput {
+                message: format!("an OpenAI API key is required, set {API_KEY_ENV}"),
+            })
+    }
+
+    /// Embed one batch of texts, retrying while rate limited.
+    async fn embed_batch(&self, api_key: &str, texts: &[&str]) -> Result<Vec<Vec<f32>>> {
+        let body = json!({ "model": self.model, "input": texts });
+        let mut backoff = self.retry_backoff;
+        let mut attempt = 0;
+        let response = loop {
+            let response = self
+                .client
+                .post(format!("{}/embeddings", self.base_url))
+                .bearer_auth(api_key)
+                .json(&body)
+                .send()
+                .await?;
+            if response.status() != StatusCode::TOO_MANY_REQUESTS || attempt >= self.max_retries {
+                break response;
+            }
+            attempt += 1;
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(MAX_RETRY_BACKOFF);
+        };
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(Error::Http {
+                status: Some(status.as_u16()),
+                message: format!("{status}: {body}"),
+            });
+        }
+        let mut data = response.json::<EmbeddingResponse>().await?.data;
+        data.sort_by_key(|d| d.index);
+        if data.len() != texts.len() {
+            return Err(Error::Http {
+                status: Some(status.as_u16()),
+                message: format!(
+                    "OpenAI returned {} embeddings for {} inputs",
+                    data.len(),
+                    texts.len()
+                ),
+            });
+        }
+        Ok(data.into_iter().map(|d| d.embedding).collect())
+    }
+}
+
+#[async_trait]
+impl EmbeddingFunction for OpenAIEmbeddingFunction {
+    fn name(&self) -> String {
+        "openai".to_string()
+    }
+
+    fn params(&self) -> HashMap<String, String> {
+        HashMap::from([
+            ("model".to_string(), self.model.clone()),
+            ("dims".to_string(), self.dims.to_string()),
+        ])
+    }
+
+    fn source_type(&self) -> DataType {
+        DataType::Utf8
+    }
+
+    fn dims(&self) -> usize {
+        self.dims
+    }
+
+    async fn embed(&self, input: ArrayRef) -> Result<ArrayRef> {
+        let texts = input
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .ok_or_else(|| Error::InvalidInput {
+                message: format!(
+                    "OpenAI embeddings expect strings, got {}",
+                    input.data_type()
+                ),
+            })?;
+        if texts.null_count() > 0 {
+            return Err(Error::InvalidInput {
+                message: "OpenAI embeddings cannot embed null strings".to_string(),
+            });
+        }
+        let api_key = self.resolve_api_key()?;
+        let texts: Vec<&str> = texts.iter().flatten().collect();
+        let mut values = Vec::with_capacity(texts.len() * self.dims);
+        for chunk in texts.chunks(self.batch_size) {
+            for embedding in self.embed_batch(&api_key, chunk).await? {
+                if embedding.len() != self.dims {
+                    return Err(Error::InvalidInput {
+                        message: format!(
+                            "the OpenAI model '{}' returned {} dimensions, expected {}",
+                            self.model,
+                            embedding.len(),
+                            self.dims
+                        ),
+                    });
+                }
+                values.extend(embedding);
+            }
+        }
+        let vectors = FixedSizeListArray::try_new(Float32Array::from(values), self.dims as i32)?;
+        Ok(Arc::new(vectors))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::test_util::{mock_server, MockRequest, MockResponse};
+
+    /// Answers with `[index, len(text)]` for every input.
+    fn embeddings_response(request: &MockRequest) -> MockResponse {
+        let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+        let data: Vec<_> = body["input"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .enumerate()
+            .map(|(index, text)| {
+                json!({
+                    "index": index,
+                    "embedding": [index as f32, text.as_str().unwrap().len() as f32],
+                })
+            })
+            .rev()
+            .collect();
+        MockResponse::ok(
+            "application/json",
+            serde_json::to_vec(&json!({ "data": data })).unwrap(),
+        )
+    }
+
+    fn embedding_function(host: &str) -> OpenAIEmbeddingFunction {
+        OpenAIEmbeddingFunction::new_with_dims("test-model", 2)
+            .api_key("secret")
+            .base_url(host)
+            .retries(3, Duration::from_millis(1))
+    }
+
+    fn values(vectors: &ArrayRef) -> Vec<f32> {
+        let vectors = vectors
+            .as_any()
+            .downcast_ref::<FixedSizeListArray>()
+            .unwrap();
+        vectors
+            .values()
+            .as_any()
+            .downcast_ref::<Float32Array>()
+            .unwrap()
+            .values()
+            .to_vec()
+    }
+
+    #[test]
+    fn test_model_dims() {
+        assert_eq!(
+            OpenAIEmbeddingFunction::new("text-embedding-3-large")
+                .unwrap()
+                .dims(),
+            3072
+        );
+        assert!(OpenAIEmbeddingFunction::new("unknown").is_err());
+    }
+
+    #[test]
+    fn test_from_params() {
+        let function = OpenAIEmbeddingFunction::new_with_dims("custom", 8);
+        let built = OpenAIEmbeddingFunction::from_params(&function.params()).unwrap();
+        assert_eq!((built.model(), built.dims()), ("custom", 8));
+        let model = HashMap::from([("model".to_string(), "text-embedding-3-small".to_string())]);
+        assert_eq!(
+            OpenAIEmbeddingFunction::from_params(&model).unwrap().dims(),
+            1536
+        );
+        assert!(OpenAIEmbeddingFunction::from_params(&HashMap::new()).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_embed_batches() {
+        let (host, requests) = mock_server(embeddings_response).await;
+        let function = embedding_function(&host).batch_size(2);
+
+        let input = Arc::new(StringArray::from(vec!["a", "bb", "ccc"]));
+        let vectors = function.embed(input).await.unwrap();
+        assert_eq!(values(&vectors), vec![0.0, 1.0, 1.0, 2.0, 0.0, 3.0]);
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].method, "POST");
+        assert_eq!(requests[0].path, "/embeddings");
+        assert_eq!(requests[0].headers["authorization"], "Bearer secret");
+        let body: serde_json::Value = serde_json::from_slice(&requests[0].body).unwrap();
+        assert_eq!(body["model"], "test-model");
+        assert_eq!(body["input"], json!(["a", "bb"]));
+    }
+
+    #[tokio::test]
+    async fn test_retry_rate_limited() {
+        let calls = AtomicUsize::new(0);
+        let (host, requests) = mock_server(move |request| {
+            if calls.fetch_add(1, Ordering::Relaxed) < 2 {
+                MockResponse {
+                    status: 429,
+                    content_type: "application/json",
+                    body: b"{}".to_vec(),
+                }
+            } else {
+                embeddings_response(request)
+            }
+        })
+        .await;
+
+        let input = Arc::new(StringArray::from(vec!["a"]));
+        let vectors = embedding_function(&host).embed(input).await.unwrap();
+        assert_eq!(values(&vectors), vec![0.0, 1.0]);
+        assert_eq!(requests.lock().unwrap().len(), 3);
+
+        let function = embedding_function(&host).retries(0, Duration::ZERO);
+        let calls = AtomicUsize::new(0);
+        let (host, _) = mock_server(move |_| {
+            calls.fetch_add(1, Ordering::Relaxed);
+            MockResponse {
+                status: 429,
+                content_type: "application/json",
+                body: b"{}".to_vec(),
+            }
+        })
+        .await;
+        let err = function
+            .base_url(&host)
+            .embed(Arc::new(StringArray::from(vec!["a"])))
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(
+                err,
+                Error::Http {
+                    status: Some(429),
+                    ..
+                }
+            ),
+            "{err}"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_text_search() {
+        use futures::TryStreamExt;
+
+        let (host, _) = mock_server(embeddings_response).await;
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let db = crate::Database::connect(tmp_dir.path().to_str().unwrap())
+            .await
+            .unwrap();
+        let table = db
+            .create_table(
+                "docs",
+                crate::embeddings::tests::make_text_batches(&["a", "bb", "ccc"]),
+            )
+            .embedding(
+                "text",
+                "vector",
+                Arc::new(embedding_function(&host).batch_size(1)),
+            )
+            .execute()
+            .await
+            .unwrap();
+        let results = table
+            .search("zz".into())
+            .limit(1)
+            .execute()
+            .await
+            .unwrap()
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        let texts = results[0]
+            .column_by_name("text")
+            .unwrap()
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .unwrap();
+        assert_eq!(texts.value(0), "bb");
+    }
+}
@@ -0,0 +1,465 @@
+// Copyright 2023 LanceDB Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Embeddings computed in-process by a local BERT model with candle, as
+//! sentence-transformers computes them: the mean of the token embeddings of
+//! the last layer.
+
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use arrow_array::{Array, ArrayRef, FixedSizeListArray, Float32Array, StringArray};
+use arrow_schema::DataType;
+use async_trait::async_trait;
+use candle_core::{DType, Tensor};
+use candle_nn::VarBuilder;
+use candle_transformers::models::bert::{BertModel, Config};
+use lance::arrow::FixedSizeListArrayExt;
+use serde::Deserialize;
+use tokenizers::{PaddingParams, PaddingStrategy, Tokenizer, TruncationParams};
+
+use super::EmbeddingFunction;
+use crate::error::{Error, Result};
+
+const CONFIG_FILE: &str = "config.json";
+const TOKENIZER_FILE: &str = "tokenizer.json";
+const WEIGHTS_FILE: &str = "model.safetensors";
+const DEFAULT_BATCH_SIZE: usize = 32;
+
+/// The device a [SentenceTransformersEmbeddings] model runs on.
+///
+/// CUDA and Metal devices need the `cuda` or `metal` feature of this crate.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Device {
+    #[default]
+    Cpu,
+    /// The CUDA GPU of this ordinal.
+    Cuda(usize),
+    /// The Metal GPU of this ordinal.
+    Metal(usize),
+}
+
+impl Device {
+    fn open(self) -> Result<candle_core::Device> {
+        Ok(match self {
+            Self::Cpu => candle_core::Device::Cpu,
+            Self::Cuda(ordinal) => candle_core::Device::new_cuda(ordinal)?,
+            Self::Metal(ordinal) => candle_core::Device::new_metal(ordinal)?,
+        })
+    }
+}
+
+/// The fields of the model config read besides those of [Config].
+#[derive(Deserialize)]
+struct ModelShape {
+    hidden_size: usize,
+    max_position_embeddings: usize,
+}
+
+/// The model and tokenizer, shared with the blocking tasks embedding batches.
+struct Model {
+    bert: BertModel,
+    tokenizer: Tokenizer,
+    device: candle_core::Device,
+}
+
+impl Model {
+    /// The mean token embeddings of `texts`, normalized if `normalize`, one
+    /// vector after the other.
+    fn embed(&self, texts: Vec<String>, normalize: bool) -> Result<Vec<f32>> {
+        let encodings = self
+            .tokenizer
+            .encode_batch(texts, true)
+            .map_err(|e| Error::Runtime {
+                message: format!("cannot tokenize the texts to embed: {e}"),
+            })?;
+        let tokens = encodings.first().map_or(0, |e| e.get_ids().len());
+        let tensor =
+            |values: Vec<u32>| Tensor::from_vec(values, (encodings.len(), tokens), &self.device);
+        let ids = tensor(
+            encodings
+                .iter()
+                .flat_map(|e| e.get_ids().to_vec())
+                .collect(),
+        )?;
+        let type_ids = tensor(
+            encodings
+                .iter()
+                .flat_map(|e| e.get_type_ids().to_vec())
+                .collect(),
+        )?;
+        let mask = tensor(
+            encodings
+                .iter()
+                .flat_map(|e| e.get_attention_mask().to_vec())
+                .collect(),
+        )?;
+
+        let hidden = self.bert.forward(&ids, &type_ids, Some(&mask))?;
+        // The padding tokens are left out of the mean.
+        let mask = mask.to_dtype(DType::F32)?.unsqueeze(2)?;
+        let sum = hidden.broadcast_mul(&mask)?.sum(1)?;
+        let mut pooled = sum.broadcast_div(&mask.sum(1)?)?;
+        if normalize {
+            let norms = pooled
+                .sqr()?
+                .sum_keepdim(1)?
+                .sqrt()?
+                .clamp(1e-12, f32::MAX)?;
+            pooled = pooled.broadcast_div(&norms)?;
+        }
+        Ok(pooled.flatten_all()?.to_vec1::<f32>()?)
+    }
+}
+
+/// Embeds text with a local BERT model in the layout of sentence-transformers,
+/// for deployments without access to a hosted embedding API.
+///
+/// The model directory holds `config.json`, `tokenizer.json` and
+/// `model.safetensors`. The vectors are computed on blocking threads, a batch
+/// of texts at a time.
+#[derive(Clone)]
+pub struct SentenceTransformersEmbeddings {
+    model: Arc<Model>,
+    model_dir: PathBuf,
+    device: Device,
+    dims: usize,
+    batch_size: usize,
+    normalize: bool,
+}
+
+impl Debug for SentenceTransformersEmbeddings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SentenceTransformersEmbeddings")
+            .field("model_dir", &self.model_dir)
+            .field("device", &self.device)
+            .field("dims", &self.dims)
+            .field("batch_size", &self.batch_size)
+            .field("normalize", &self.normalize)
+            .finish()
+    }
+}
+
+fn read_file(path: &Path) -> Result<String> {
+    std::fs::read_to_string(path).map_err(|e| invalid_model(path, e))
+}
+
+fn invalid_model(path: &Path, e: impl std::fmt::Display) -> Error {
+    Error::InvalidInput {
+        message: format!("invalid embedding model file {}: {e}", path.display()),
+    }
+}
+
+impl SentenceTransformersEmbeddings {
+    /// Load the model of `model_dir` on `device`.
+    ///
+    /// The vectors have the `hidden_size` of the model config as dimensions.
+    pub fn new(model_dir: impl AsRef<Path>, device: Device) -> Result<Self> {
+        let model_dir = model_dir.as_ref().to_path_buf();
+        let config_path = model_dir.join(CONFIG_FILE);
+        let config_json = read_file(&config_path)?;
+        let config: Config =
+            serde_json::from_str(&config_json).map_err(|e| invalid_model(&config_path, e))?;
+        let shape: ModelShape =
+            serde_json::from_str(&config_json).map_err(|e| invalid_model(&config_path, e))?;
+
+        let tokenizer_path = model_dir.join(TOKENIZER_FILE);
+        let mut tokenizer =
+            Tokenizer::from_file(&tokenizer_path).map_err(|e| invalid_model(&tokenizer_path, e))?;
+        tokenizer.with_padding(Some(PaddingParams {
+            strategy: PaddingStrategy::BatchLongest,
+            ..Default::default()
+        }));
+        tokenizer
+            .with_truncation(Some(TruncationParams {
+                max_length: shape.max_position_embeddings,
+                ..Default::default()
+            }))
+            .map_err(|e| invalid_model(&tokenizer_path, e))?;
+
+        let candle_device = device.open()?;
+        let weights_path = model_dir.join(WEIGHTS_FILE);
+        if !weights_path.is_file() {
+            return Err(invalid_model(&weights_path, "the file is missing"));
+        }
+        // SAFETY: the weights file is not modified while the model is loaded.
+        let vb = unsafe {
+            VarBuilder::from_mmaped_safetensors(&[&weights_path], DType::F32, &candle_device)?
+        };
+        let bert = BertModel::load(vb, &config).map_err(|e| invalid_model(&weights_path, e))?;
+
+        Ok(Self {
+            model: Arc::new(Model {
+                bert,
+                tokenizer,
+                device: candle_device,
+            }),
+            model_dir,
+            device,
+            dims: shape.hidden_size,
+            batch_size: DEFAULT_BATCH_SIZE,
+            normalize: false,
+        })
+    }
+
+    /// Load the model of `params`, the [EmbeddingFunction::params] of a
+    /// function, on `device`, e.g. in a factory of
+    /// [EmbeddingRegistry::register_factory](super::EmbeddingRegistry::register_factory).
+    pub fn from_params(params: &HashMap<String, String>, device: Device) -> Result<Self> {
+        let model_dir = params.get("model_dir").ok_or_else(|| Error::InvalidInput {
+            message: "the sentence-transformers embedding parameters have no model_dir".to_string(),
+        })?;
+        let normalize = match params.get("normalize").map(String::as_str) {
+            Some("true") => true,
+            Some("false") | None => false,
+            Some(other) => {
+                return Err(Error::InvalidInput {
+                    message: format!("invalid sentence-transformers normalize parameter: {other}"),
+                })
+            }
+        };
+        Ok(Self::new(model_dir, device)?.normalize(normalize))
+    }
+
+    /// Set the most texts embedded at once, 32 by default.
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Scale the vectors to unit length, for indices of the cosine metric.
+    pub fn normalize(mut self, normalize: bool) -> Self {
+        self.normalize = normalize;
+        self
+    }
+
+    /// The directory the model was loaded from.
+    pub fn model_dir(&self) -> &Path {
+        &self.model_dir
+    }
+}
+
+#[async_trait]
+impl EmbeddingFunction for SentenceTransformersEmbeddings {
+    fn name(&self) -> String {
+        "sentence-transformers".to_string()
+    }
+
+    fn params(&self) -> HashMap<String, String> {
+        HashMap::from([
+            (
+                "model_dir".to_string(),
+                self.model_dir.display().to_string(),
+            ),
+            ("normalize".to_string(), self.normalize.to_string()),
+        ])
+    }
+
+    fn source_type(&self) -> DataType {
+        DataType::Utf8
+    }
+
+    fn dims(&self) -> usize {
+        self.dims
+    }
+
+    async fn embed(&self, input: ArrayRef) -> Result<ArrayRef> {
+        let texts = input
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .ok_or_else(|| Error::InvalidInput {
+                message: format!(
+                    "sentence-transformers embeddings expect strings, got {}",
+                    input.data_type()
+                ),
+            })?;
+        if texts.null_count() > 0 {
+            return Err(Error::InvalidInput {
+                message: "sentence-transformers embeddings cannot embed null strings".to_string(),
+            });
+        }
+        let texts: Vec<String> = texts.iter().flatten().map(String::from).collect();
+        let mut values = Vec::with_capacity(texts.len() * self.dims);
+        for chunk in texts.chunks(self.batch_size) {
+            let model = self.model.clone();
+            let (chunk, normalize) = (chunk.to_vec(), self.normalize);
+            let embedded = tokio::task::spawn_blocking(move || model.embed(chunk, normalize))
+                .await
+                .map_err(|e| Error::Runtime {
+                    message: format!("the embedding task failed: {e}"),
+                })??;
+            values.extend(embedded);
+        }
+        let vectors = FixedSizeListArray::try_new(Float32Array::from(values), self.dims as i32)?;
+        Ok(Arc::new(vectors))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use candle_nn::VarMap;
+    use serde_json::json;
+
+    use super::*;
+
+    const HIDDEN_SIZE: usize = 8;
+
+    /// Write a BERT model of random weights and a word level tokenizer to
+    /// `dir`, to exercise the plumbing rather than the quality of vectors.
+    fn write_tiny_model(dir: &Path) {
+        let config = json!({
+            "vocab_size": 6,
+            "hidden_size": HIDDEN_SIZE,
+            "num_hidden_layers": 1,
+            "num_attention_heads": 2,
+            "intermediate_size": 16,
+            "hidden_act": "gelu",
+            "hidden_dropout_prob": 0.0,
+            "max_position_embeddings": 16,
+            "type_vocab_size": 1,
+            "initializer_range": 0.02,
+            "layer_norm_eps": 1e-12,
+            "pad_token_id": 0,
+            "position_embedding_type": "absolute",
+            "use_cache": false,
+            "classifier_dropout": null,
+            "model_type": "bert",
+        });
+        std::fs::write(dir.join(CONFIG_FILE), config.to_string()).unwrap();
+
+        let tokenizer = json!({
+            "version": "1.0",
+            "truncation": null,
+            "padding": null,
+            "added_tokens": [],
+            "normalizer": null,
+            "pre_tokenizer": { "type": "Whitespace" },
+            "post_processor": null,
+            "decoder": null,
+            "model": {
+                "type": "WordLevel",
+                "vocab": { "[PAD]": 0, "[UNK]": 1, "red": 2, "green": 3, "blue": 4, "sky": 5 },
+                "unk_token": "[UNK]",
+            },
+        });
+        std::fs::write(dir.join(TOKENIZER_FILE), tokenizer.to_string()).unwrap();
+
+        // Loading the model from a VarMap creates its weights.
+        let config: Config = serde_json::from_value(config).unwrap();
+        let varmap = VarMap::new();
+        let device = candle_core::Device::Cpu;
+        BertModel::load(
+            VarBuilder::from_varmap(&varmap, DType::F32, &device),
+            &config,
+        )
+        .unwrap();
+        for (name, var) in varmap.data().lock().unwrap().iter() {
+            if name.ends_with("LayerNorm.weight") {
+                var.set(&var.ones_like().unwrap()).unwrap();
+            }
+        }
+        varmap.save(dir.join(WEIGHTS_FILE)).unwrap();
+    }
+
+    fn vectors(array: &ArrayRef) -> Vec<Vec<f32>> {
+        let vectors = array.as_any().downcast_ref::<FixedSizeListArray>().unwrap();
+        let values = vectors
+            .values()
+            .as_any()
+            .downcast_ref::<Float32Array>()
+            .unwrap();
+        values
+            .values()
+            .chunks(vectors.value_length() as usize)
+            .map(<[f32]>::to_vec)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_embed_shapes() {
+        let dir = tempfile::tempdir().unwrap();
+        write_tiny_model(dir.path());
+        let function = SentenceTransformersEmbeddings::new(dir.path(), Device::Cpu).unwrap();
+        assert_eq!(function.dims(), HIDDEN_SIZE);
+
+        let texts = ["red", "green blue sky", "sky", "blue red"];
+        let embedded = function
+            .embed(Arc::new(StringArray::from(texts.to_vec())))
+            .await
+            .unwrap();
+        assert_eq!(embedded.len(), texts.len());
+        assert_eq!(
+            embedded.data_type(),
+            &DataType::FixedSizeList(
+                Arc::new(arrow_schema::Field::new("item", DataType::Float32, true)),
+                HIDDEN_SIZE as i32
+            )
+        );
+
+        // The padding of the longer texts of a batch leaves the vectors of
+        // the shorter ones as they are embedded alone.
+        let batched = function.clone().batch_size(1);
+        let one_by_one = batched
+            .embed(Arc::new(StringArray::from(texts.to_vec())))
+            .await
+            .unwrap();
+        for (a, b) in vectors(&embedded).iter().zip(vectors(&one_by_one).iter()) {
+            for (a, b) in a.iter().zip(b) {
+                assert!((a - b).abs() < 1e-4, "{a} != {b}");
+            }
+        }
+
+        let nulls = Arc::new(StringArray::from(vec![Some("red"), None]));
+        assert!(function.embed(nulls).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_normalize() {
+        let dir = tempfile::tempdir().unwrap();
+        write_tiny_model(dir.path());
+        let function = SentenceTransformersEmbeddings::new(dir.path(), Device::Cpu)
+            .unwrap()
+            .normalize(true);
+        let embedded = function
+            .embed(Arc::new(StringArray::from(vec!["red", "green blue"])))
+            .await
+            .unwrap();
+        for vector in vectors(&embedded) {
+            let norm = vector.iter().map(|v| v * v).sum::<f32>().sqrt();
+            assert!((norm - 1.0).abs() < 1e-4, "{norm}");
+        }
+    }
+
+    #[test]
+    fn test_from_params() {
+        let dir = tempfile::tempdir().unwrap();
+        write_tiny_model(dir.path());
+        let function = SentenceTransformersEmbeddings::new(dir.path(), Device::Cpu)
+            .unwrap()
+            .normalize(true);
+        let built =
+            SentenceTransformersEmbeddings::from_params(&function.params(), Device::Cpu).unwrap();
+        assert_eq!(built.params(), function.params());
+        assert_eq!(built.dims(), HIDDEN_SIZE);
+
+        assert!(SentenceTransformersEmbeddings::from_params(&HashMap::new(), Device::Cpu).is_err());
+        let missing = tempfile::tempdir().unwrap();
+        assert!(matches!(
+            SentenceTransformersEmbeddings::new(missing.path(), Device::Cpu).unwrap_err(),
+            Error::InvalidInput { .. }
+        ));
+    }
+}
@@ -12,7 +12,12 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use snafu::Snafu;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use snafu::{Backtrace, GenerateImplicitData, Snafu};
+
+use crate::database::RollbackFailure;
 
 #[derive(Debug, Snafu)]
 #[snafu(visibility(pub(crate)))]
@@ -23,39 +28,240 @@ pub enum Error {
     TableNotFound { name: String },
     #[snafu(display("LanceDBError: Table '{name}' already exists"))]
     TableAlreadyExists { name: String },
+    #[snafu(display("LanceDBError: Invalid URI {uri}: {reason}"))]
+    InvalidUri { uri: String, reason: String },
+    #[snafu(display("LanceDBError: Database {uri} does not exist"))]
+    DatabaseNotFound { uri: String },
+    #[snafu(display("LanceDBError: Permission denied accessing {uri}: {source}"))]
+    PermissionDenied { uri: String, source: std::io::Error },
+    #[snafu(display("LanceDBError: Object store error for {uri}: {source}"))]
+    ObjectStore {
+        uri: String,
+        source: object_store::Error,
+    },
     #[snafu(display("LanceDBError: Unable to created lance dataset at {path}: {source}"))]
     CreateDir {
         path: String,
         source: std::io::Error,
     },
-    #[snafu(display("LanceDBError: {message}"))]
-    Store { message: String },
-    #[snafu(display("LanceDBError: {message}"))]
-    Lance { message: String },
+    #[snafu(display("LanceDBError: I/O error on {path}: {source}"))]
+    Io {
+        path: String,
+        source: std::io::Error,
+    },
+    /// A file lancedb keeps next to the dataset of a table, as the embeddings
+    /// or the index parameters of the table, cannot be decoded.
+    #[snafu(display("LanceDBError: Invalid metadata in {path}: {message}"))]
+    InvalidMetadata { path: String, message: String },
+    #[snafu(display("LanceDBError: Timed out after {timeout:?} accessing {uri}"))]
+    Timeout { uri: String, timeout: Duration },
+    #[snafu(display("LanceDBError: Commit conflict on {uri}: {reason}"))]
+    CommitConflict { uri: String, reason: String },
+    #[snafu(display("LanceDBError: Unknown storage option '{key}' for {uri}"))]
+    UnknownStorageOption { key: String, uri: String },
+    #[snafu(display("LanceDBError: Runtime error: {message}"))]
+    Runtime { message: String },
+    #[snafu(display("LanceDBError: Embedding provider '{name}' is not registered"))]
+    MissingEmbeddingProvider { name: String },
+    #[snafu(display(
+        "LanceDBError: Vector column '{column}' has {expected} dimensions, got {got}"
+    ))]
+    EmbeddingDimensionMismatch {
+        column: String,
+        expected: usize,
+        got: usize,
+    },
+    #[snafu(display("LanceDBError: Invalid input: {message}"))]
+    InvalidInput { message: String },
+    #[snafu(display("LanceDBError: Http error: {message}"))]
+    Http {
+        status: Option<u16>,
+        message: String,
+    },
+    #[snafu(display("LanceDBError: The table has no version committed at or before {timestamp}"))]
+    NoVersionBefore { timestamp: DateTime<Utc> },
+    #[snafu(display("LanceDBError: No row has the key {key} in the column '{column}'"))]
+    RowNotFound { column: String, key: String },
+    #[snafu(display("LanceDBError: The delete matches more than {max_rows} rows"))]
+    DeleteLimitExceeded { max_rows: usize },
+    /// The results of a query take more than its `max_result_bytes`, see
+    /// [crate::Query::max_result_bytes]. `estimated` is the size of the
+    /// results read until then.
+    #[snafu(display(
+        "LanceDBError: The results of the query take an estimated {estimated} bytes, more than the cap of {cap} bytes; select fewer columns with Query::select, or lower the limit"
+    ))]
+    ResultTooLarge { estimated: usize, cap: usize },
+    #[snafu(display("LanceDBError: Index '{name}' was not found"))]
+    IndexNotFound { name: String },
+    /// The column of the index has vectors of another dimension than the
+    /// index, which can be dropped or created again with `replace`.
+    #[snafu(display(
+        "LanceDBError: Index '{index}' has vectors of {expected_dim} dimensions, its column has {column_dim}, drop it or create it again"
+    ))]
+    StaleIndex {
+        index: String,
+        expected_dim: usize,
+        column_dim: usize,
+    },
+    #[snafu(display("LanceDBError: Schema error: {message}"))]
+    Schema { message: String },
+    #[snafu(display(
+        "LanceDBError: The key column '{column}' has {count} duplicated values, such as {}",
+        examples.join(", ")
+    ))]
+    DuplicateKeys {
+        column: String,
+        count: usize,
+        examples: Vec<String>,
+    },
+    #[snafu(display(
+        "LanceDBError: Transaction failed: {source}{}",
+        describe_rollback_failures(not_rolled_back)
+    ))]
+    TransactionFailed {
+        source: Box<Error>,
+        not_rolled_back: Vec<RollbackFailure>,
+    },
+    #[snafu(display("LanceDBError: {source}"))]
+    Store { source: object_store::Error },
+    #[snafu(display("LanceDBError: {source}"))]
+    Lance {
+        source: Box<lance::Error>,
+        backtrace: Backtrace,
+    },
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+fn describe_rollback_failures(failures: &[RollbackFailure]) -> String {
+    if failures.is_empty() {
+        return String::new();
+    }
+    let tables = failures
+        .iter()
+        .map(|f| format!("'{}' to version {} ({})", f.table, f.version, f.error))
+        .collect::<Vec<_>>();
+    format!(", the tables were not rolled back: {}", tables.join(", "))
+}
+
+/// Errors of lance are mapped to the variant describing them best, and kept
+/// as the source of [Error::Lance] otherwise.
 impl From<lance::Error> for Error {
     fn from(e: lance::Error) -> Self {
-        Self::Lance {
-            message: e.to_string(),
+        match e {
+            lance::Error::InvalidInput { source } => Self::InvalidInput {
+                message: source.to_string(),
+            },
+            lance::Error::Schema { message } => Self::Schema { message },
+            lance::Error::SchemaMismatch { .. } => Self::Schema {
+                message: e.to_string(),
+            },
+            e => Self::Lance {
+                source: Box::new(e),
+                backtrace: Backtrace::generate(),
+            },
         }
     }
 }
 
 impl From<object_store::Error> for Error {
-    fn from(e: object_store::Error) -> Self {
+    fn from(source: object_store::Error) -> Self {
+        Self::Store { source }
+    }
+}
+
+impl From<object_store::path::Error> for Error {
+    fn from(source: object_store::path::Error) -> Self {
         Self::Store {
+            source: object_store::Error::InvalidPath { source },
+        }
+    }
+}
+
+#[cfg(any(feature = "remote", feature = "openai"))]
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Self {
+        Self::Http {
+            status: e.status().map(|s| s.as_u16()),
             message: e.to_string(),
         }
     }
 }
 
-impl From<object_store::path::Error> for Error {
-    fn from(e: object_store::path::Error) -> Self {
-        Self::Store {
+#[cfg(feature = "polars")]
+impl From<polars::prelude::PolarsError> for Error {
+    fn from(e: polars::prelude::PolarsError) -> Self {
+        Self::InvalidInput {
             message: e.to_string(),
         }
     }
 }
+
+#[cfg(feature = "sentence-transformers")]
+impl From<candle_core::Error> for Error {
+    fn from(e: candle_core::Error) -> Self {
+        Self::Runtime {
+            message: e.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use snafu::ErrorCompat;
+
+    use super::*;
+
+    #[test]
+    fn test_lance_error_mapping() {
+        let err = Error::from(lance::Error::invalid_input("bad vector"));
+        assert!(matches!(err, Error::InvalidInput { ref message } if message == "bad vector"));
+
+        let err = Error::from(lance::Error::Schema {
+            message: "no such column".to_string(),
+        });
+        assert!(matches!(err, Error::Schema { ref message } if message == "no such column"));
+
+        let schema = |name: &str| {
+            let field = arrow_schema::Field::new(name, arrow_schema::DataType::Int32, false);
+            lance::datatypes::Schema::try_from(&arrow_schema::Schema::new(vec![field])).unwrap()
+        };
+        let err = Error::from(lance::Error::SchemaMismatch {
+            original: schema("a"),
+            new: schema("b"),
+        });
+        assert!(matches!(err, Error::Schema { .. }));
+
+        let err = Error::from(lance::Error::Index {
+            message: "corrupt index".to_string(),
+        });
+        assert!(
+            matches!(err, Error::Lance { ref source, .. } if matches!(**source, lance::Error::Index { .. }))
+        );
+        assert!(err.to_string().contains("corrupt index"));
+        assert!(std::error::Error::source(&err).is_some());
+        let _ = err.backtrace();
+    }
+
+    #[test]
+    fn test_store_error_mapping() {
+        let err = Error::from(object_store::Error::NotFound {
+            path: "db/table.lance".to_string(),
+            source: "missing".into(),
+        });
+        assert!(matches!(
+            err,
+            Error::Store {
+                source: object_store::Error::NotFound { .. }
+            }
+        ));
+
+        let path_err = object_store::path::Path::parse("a//b").unwrap_err();
+        assert!(matches!(
+            Error::from(path_err),
+            Error::Store {
+                source: object_store::Error::InvalidPath { .. }
+            }
+        ));
+    }
+}
@@ -12,18 +12,103 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use arrow_schema::{DataType, Field};
 use lance::index::vector::ivf::IvfBuildParams;
 use lance::index::vector::pq::PQBuildParams;
-use lance::index::vector::{MetricType, VectorIndexParams};
+use lance::index::vector::{MetricType, StageParams, VectorIndexParams};
+use lance::index::IndexType;
+use object_store::path::Path;
+use serde::{Deserialize, Serialize};
 
+use crate::error::{Error, Result};
+use crate::query::flat::{is_f16_vector, is_multivector};
+
+/// The training vectors sampled for each centroid of an IVF_PQ index, by
+/// default. The centroids are the IVF partitions or the 256 PQ centroids,
+/// whichever are more.
+pub const DEFAULT_SAMPLE_RATE: usize = 256;
+
+/// The bytes of PQ codes and row ids an IVF_PQ index build keeps in memory
+/// by default, see [IvfPQIndexBuilder::max_memory_bytes].
+pub const DEFAULT_INDEX_BUILD_MEMORY_BYTES: usize = 1024 * 1024 * 1024;
+
+/// The parameters of a vector index, given to [crate::TableLike::create_index].
+///
+/// The trait is object safe, tables take the builders as
+/// `&dyn VectorIndexBuilder`. The accessors borrow from the builder, and are
+/// named apart from the setters of the builders, as those of
+/// [IvfPQIndexBuilder::column] and [IvfPQIndexBuilder::index_name].
 pub trait VectorIndexBuilder {
-    fn get_column(&self) -> Option<String>;
-    fn get_index_name(&self) -> Option<String>;
+    /// The indexed column, `None` for [crate::table::VECTOR_COLUMN_NAME].
+    fn column_name(&self) -> Option<&str>;
+
+    /// The name of the index, `None` for `<column>_idx`.
+    fn name(&self) -> Option<&str>;
+
+    /// The type of the index.
+    fn index_type(&self) -> IndexType {
+        IndexType::Vector
+    }
+
+    /// Check that `field`, the indexed column, can be indexed by the index,
+    /// failing with an [Error::InvalidInput] if it cannot. Any column can by
+    /// default.
+    fn validate(&self, _field: &Field) -> Result<()> {
+        Ok(())
+    }
+
+    #[deprecated(note = "use VectorIndexBuilder::column_name, which does not clone the name")]
+    fn get_column(&self) -> Option<String> {
+        self.column_name().map(str::to_string)
+    }
+
+    #[deprecated(note = "use VectorIndexBuilder::name, which does not clone the name")]
+    fn get_index_name(&self) -> Option<String> {
+        self.name().map(str::to_string)
+    }
+
     fn build(&self) -> VectorIndexParams;
 
     fn get_replace(&self) -> bool;
+
+    /// The training vectors sampled for each centroid, `None` for
+    /// [DEFAULT_SAMPLE_RATE].
+    fn get_sample_rate(&self) -> Option<usize> {
+        None
+    }
+
+    /// The bytes of PQ codes and row ids kept in memory while the rows are
+    /// assigned to partitions, `None` for [DEFAULT_INDEX_BUILD_MEMORY_BYTES].
+    fn get_max_memory_bytes(&self) -> Option<usize> {
+        None
+    }
+
+    /// The tasks assigning the fragments of the table to partitions at once,
+    /// `None` for the default of the connection.
+    fn get_num_threads(&self) -> Option<usize> {
+        None
+    }
+
+    /// The filter of the rows indexed, `None` for every row.
+    fn filter(&self) -> Option<&str> {
+        None
+    }
 }
 
+/// A builder of IVF_PQ index parameters.
+///
+/// The setters take and return the builder, so that an index can be built in
+/// one expression:
+///
+/// ```
+/// use vectordb::index::vector::IvfPQIndexBuilder;
+/// use vectordb::lance::index::vector::MetricType;
+///
+/// let builder = IvfPQIndexBuilder::new()
+///     .column("vector")
+///     .metric_type(MetricType::Cosine);
+/// ```
+#[derive(Clone)]
 pub struct IvfPQIndexBuilder {
     column: Option<String>,
     index_name: Option<String>,
@@ -31,6 +116,10 @@ pub struct IvfPQIndexBuilder {
     ivf_params: Option<IvfBuildParams>,
     pq_params: Option<PQBuildParams>,
     replace: bool,
+    sample_rate: Option<usize>,
+    max_memory_bytes: Option<usize>,
+    num_threads: Option<usize>,
+    row_filter: Option<String>,
 }
 
 impl IvfPQIndexBuilder {
@@ -42,54 +131,145 @@ impl IvfPQIndexBuilder {
             ivf_params: None,
             pq_params: None,
             replace: true,
+            sample_rate: None,
+            max_memory_bytes: None,
+            num_threads: None,
+            row_filter: None,
         }
     }
 }
 
+impl Default for IvfPQIndexBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl IvfPQIndexBuilder {
-    pub fn column(&mut self, column: String) -> &mut IvfPQIndexBuilder {
-        self.column = Some(column);
+    pub fn column(mut self, column: impl Into<String>) -> Self {
+        self.column = Some(column.into());
         self
     }
 
-    pub fn index_name(&mut self, index_name: String) -> &mut IvfPQIndexBuilder {
-        self.index_name = Some(index_name);
+    pub fn index_name(mut self, index_name: impl Into<String>) -> Self {
+        self.index_name = Some(index_name.into());
         self
     }
 
-    pub fn metric_type(&mut self, metric_type: MetricType) -> &mut IvfPQIndexBuilder {
+    pub fn metric_type(mut self, metric_type: MetricType) -> Self {
         self.metric_type = Some(metric_type);
         self
     }
 
-    pub fn ivf_params(&mut self, ivf_params: IvfBuildParams) -> &mut IvfPQIndexBuilder {
+    pub fn ivf_params(mut self, ivf_params: IvfBuildParams) -> Self {
         self.ivf_params = Some(ivf_params);
         self
     }
 
-    pub fn pq_params(&mut self, pq_params: PQBuildParams) -> &mut IvfPQIndexBuilder {
+    pub fn pq_params(mut self, pq_params: PQBuildParams) -> Self {
         self.pq_params = Some(pq_params);
         self
     }
 
-    pub fn replace(&mut self, replace: bool) -> &mut IvfPQIndexBuilder {
+    pub fn replace(mut self, replace: bool) -> Self {
         self.replace = replace;
         self
     }
+
+    /// Set the training vectors sampled for each centroid. Defaults to
+    /// [DEFAULT_SAMPLE_RATE].
+    ///
+    /// The centroids and the PQ codebook are trained on the sampled vectors
+    /// only, the other rows are read once, to be assigned to partitions.
+    pub fn sample_rate(mut self, sample_rate: usize) -> Self {
+        self.sample_rate = Some(sample_rate);
+        self
+    }
+
+    /// Set the bytes of PQ codes and row ids kept in memory while the rows
+    /// are assigned to partitions. Over it, they are spilled to files in the
+    /// temporary directory until the index file is written. Defaults to
+    /// [DEFAULT_INDEX_BUILD_MEMORY_BYTES].
+    ///
+    /// Indices with OPQ are built by lance, which keeps the codes and row ids
+    /// of every row in memory.
+    pub fn max_memory_bytes(mut self, max_memory_bytes: usize) -> Self {
+        self.max_memory_bytes = Some(max_memory_bytes);
+        self
+    }
+
+    /// Set how many fragments of the table are assigned to partitions and
+    /// encoded at the same time, each by a task. Defaults to the
+    /// [crate::database::ConnectBuilder::index_build_threads] of the
+    /// connection, or to the available parallelism. The index is the same for
+    /// any number of threads.
+    ///
+    /// The memory used grows with the threads: each holds the batches of its
+    /// fragment it has read but not yet buffered.
+    pub fn num_threads(mut self, num_threads: usize) -> Self {
+        self.num_threads = Some(num_threads);
+        self
+    }
+
+    /// Train and index only the rows matching `filter`, a SQL WHERE clause,
+    /// for tables searched only with it: `status = 'active'`.
+    ///
+    /// Searches use the index when their filter implies this one, when every
+    /// condition joined by `AND` in `filter` is also one of theirs, and are
+    /// flat otherwise, see [crate::Query::require_covering_index].
+    /// [crate::table::IndexStats::row_filter] reports the filter.
+    ///
+    /// The rows matching the filter are read to sample the training vectors.
+    /// Indices with OPQ cannot have a filter.
+    pub fn row_filter(mut self, filter: impl Into<String>) -> Self {
+        self.row_filter = Some(filter.into());
+        self
+    }
 }
 
 impl VectorIndexBuilder for IvfPQIndexBuilder {
-    fn get_column(&self) -> Option<String> {
-        self.column.clone()
+    fn column_name(&self) -> Option<&str> {
+        self.column.as_deref()
     }
 
-    fn get_index_name(&self) -> Option<String> {
-        self.index_name.clone()
+    fn name(&self) -> Option<&str> {
+        self.index_name.as_deref()
+    }
+
+    /// An IVF_PQ index is of a vector column of `Float32` values.
+    fn validate(&self, field: &Field) -> Result<()> {
+        let column = field.name();
+        if is_f16_vector(field) {
+            return Err(Error::InvalidInput {
+                message: format!(
+                    "cannot index the float16 vector column '{column}', vector indexes do not support float16 vectors yet"
+                ),
+            });
+        }
+        if is_multivector(field) {
+            return Err(Error::InvalidInput {
+                message: format!(
+                    "cannot index the multivector column '{column}', it is searched without an index"
+                ),
+            });
+        }
+        match field.data_type() {
+            DataType::FixedSizeList(item, _)
+                if item.data_type() == &DataType::Float32 =>
+            {
+                Ok(())
+            }
+            data_type => Err(Error::InvalidInput {
+                message: format!(
+                    "cannot index the column '{column}' of {data_type}, an IVF_PQ index is of a column of float32 vectors"
+                ),
+            }),
+        }
     }
 
     fn build(&self) -> VectorIndexParams {
-        let ivf_params = self.ivf_params.clone().unwrap_or(IvfBuildParams::default());
-        let pq_params = self.pq_params.clone().unwrap_or(PQBuildParams::default());
+        let ivf_params = self.ivf_params.clone().unwrap_or_default();
+        let pq_params = self.pq_params.clone().unwrap_or_default();
 
         VectorIndexParams::with_ivf_pq_params(pq_params.metric_type, ivf_params, pq_params)
     }
@@ -97,25 +277,140 @@ impl VectorIndexBuilder for IvfPQIndexBuilder {
     fn get_replace(&self) -> bool {
         self.replace
     }
+
+    fn get_sample_rate(&self) -> Option<usize> {
+        self.sample_rate
+    }
+
+    fn get_max_memory_bytes(&self) -> Option<usize> {
+        self.max_memory_bytes
+    }
+
+    fn get_num_threads(&self) -> Option<usize> {
+        self.num_threads
+    }
+
+    fn filter(&self) -> Option<&str> {
+        self.row_filter.as_deref()
+    }
+}
+
+/// The file in the table directory where the parameters of its indices are
+/// recorded, so that they can be rebuilt with the same parameters.
+pub(crate) const INDICES_FILE: &str = "_indices.json";
+
+/// The build parameters of an IVF_PQ index, as recorded in [INDICES_FILE].
+///
+/// User-provided IVF centroids and PQ codebooks are not recorded, a rebuilt
+/// index trains its own.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct IndexConfig {
+    pub name: String,
+    pub column: String,
+    pub metric_type: String,
+    pub num_partitions: usize,
+    pub ivf_max_iters: usize,
+    pub num_sub_vectors: usize,
+    pub num_bits: usize,
+    pub use_opq: bool,
+    pub pq_max_iters: usize,
+    pub max_opq_iters: usize,
+    /// The filter of the rows indexed, see [IvfPQIndexBuilder::row_filter].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub row_filter: Option<String>,
+}
+
+impl IndexConfig {
+    /// The configuration of the index `name` on `column` built with `params`,
+    /// `None` unless they are IVF_PQ parameters.
+    pub(crate) fn new(name: String, column: String, params: &VectorIndexParams) -> Option<Self> {
+        let [StageParams::Ivf(ivf), StageParams::PQ(pq)] = params.stages.as_slice() else {
+            return None;
+        };
+        Some(Self {
+            name,
+            column,
+            metric_type: params.metric_type.to_string(),
+            num_partitions: ivf.num_partitions,
+            ivf_max_iters: ivf.max_iters,
+            num_sub_vectors: pq.num_sub_vectors,
+            num_bits: pq.num_bits,
+            use_opq: pq.use_opq,
+            pq_max_iters: pq.max_iters,
+            max_opq_iters: pq.max_opq_iters,
+            row_filter: None,
+        })
+    }
+
+    /// A builder of the same index, replacing it.
+    pub(crate) fn builder(&self) -> Result<IvfPQIndexBuilder> {
+        let metric_type = MetricType::try_from(self.metric_type.as_str())?;
+        let builder = IvfPQIndexBuilder::new()
+            .column(self.column.clone())
+            .index_name(self.name.clone())
+            .metric_type(metric_type)
+            .ivf_params(IvfBuildParams {
+                max_iters: self.ivf_max_iters,
+                ..IvfBuildParams::new(self.num_partitions)
+            })
+            .pq_params(PQBuildParams {
+                num_sub_vectors: self.num_sub_vectors,
+                num_bits: self.num_bits,
+                metric_type,
+                use_opq: self.use_opq,
+                max_iters: self.pq_max_iters,
+                max_opq_iters: self.max_opq_iters,
+                ..PQBuildParams::default()
+            })
+            .replace(true);
+        Ok(match self.row_filter.as_ref() {
+            Some(filter) => builder.row_filter(filter.clone()),
+            None => builder,
+        })
+    }
+}
+
+pub(crate) fn encode_index_configs(configs: &[IndexConfig]) -> Result<Vec<u8>> {
+    serde_json::to_vec(configs).map_err(|e| Error::InvalidInput {
+        message: format!("cannot encode the index configuration: {e}"),
+    })
+}
+
+/// The index configurations of `bytes`, read from `path`.
+pub(crate) fn decode_index_configs(path: &Path, bytes: &[u8]) -> Result<Vec<IndexConfig>> {
+    serde_json::from_slice(bytes).map_err(|e| Error::InvalidMetadata {
+        path: path.to_string(),
+        message: format!("invalid index configuration: {e}"),
+    })
 }
 
 #[cfg(test)]
 mod tests {
+    use std::sync::Arc;
+
+    use arrow_schema::{DataType, Field};
     use lance::index::vector::ivf::IvfBuildParams;
     use lance::index::vector::pq::PQBuildParams;
     use lance::index::vector::{MetricType, StageParams};
+    use lance::index::IndexType;
+    use object_store::path::Path;
 
-    use crate::index::vector::{IvfPQIndexBuilder, VectorIndexBuilder};
+    use crate::error::Error;
+    use crate::index::vector::{
+        decode_index_configs, encode_index_configs, IndexConfig, IvfPQIndexBuilder,
+        VectorIndexBuilder, INDICES_FILE,
+    };
 
     #[test]
     fn test_builder_no_params() {
         let index_builder = IvfPQIndexBuilder::new();
-        assert!(index_builder.get_column().is_none());
-        assert!(index_builder.get_index_name().is_none());
+        assert!(index_builder.column_name().is_none());
+        assert!(index_builder.name().is_none());
+        assert!(matches!(index_builder.index_type(), IndexType::Vector));
 
         let index_params = index_builder.build();
         assert_eq!(index_params.stages.len(), 2);
-        if let StageParams::Ivf(ivf_params) = index_params.stages.get(0).unwrap() {
+        if let StageParams::Ivf(ivf_params) = index_params.stages.first().unwrap() {
             let default = IvfBuildParams::default();
             assert_eq!(ivf_params.num_partitions, default.num_partitions);
             assert_eq!(ivf_params.max_iters, default.max_iters);
@@ -124,53 +419,124 @@ mod tests {
         }
 
         if let StageParams::PQ(pq_params) = index_params.stages.get(1).unwrap() {
-            assert_eq!(pq_params.use_opq, false);
+            assert!(!pq_params.use_opq);
         } else {
             panic!("Expected second stage to be pq")
         }
     }
 
     #[test]
-    fn test_builder_all_params() {
-        let mut index_builder = IvfPQIndexBuilder::new();
+    fn test_index_config_round_trip() {
+        let index_builder = IvfPQIndexBuilder::new()
+            .ivf_params(IvfBuildParams::new(4))
+            .pq_params(PQBuildParams {
+                num_sub_vectors: 2,
+                metric_type: MetricType::Cosine,
+                ..PQBuildParams::default()
+            });
+        let params = index_builder.build();
+        let config = IndexConfig::new("idx".into(), "vector".into(), &params).unwrap();
+        let path = Path::from(INDICES_FILE);
+        let decoded = decode_index_configs(
+            &path,
+            &encode_index_configs(std::slice::from_ref(&config)).unwrap(),
+        );
+        assert_eq!(decoded.unwrap(), vec![config.clone()]);
+
+        let rebuilt = config.builder().unwrap();
+        assert_eq!(rebuilt.column_name(), Some("vector"));
+        assert_eq!(rebuilt.name(), Some("idx"));
+        let rebuilt = IndexConfig::new("idx".into(), "vector".into(), &rebuilt.build());
+        assert_eq!(rebuilt.unwrap(), config);
 
-        index_builder
-            .column("c".to_owned())
+        let config = IndexConfig {
+            row_filter: Some("status = 'active'".to_string()),
+            ..config
+        };
+        let decoded = decode_index_configs(
+            &path,
+            &encode_index_configs(std::slice::from_ref(&config)).unwrap(),
+        );
+        assert_eq!(decoded.unwrap(), vec![config.clone()]);
+        let rebuilt = config.builder().unwrap();
+        assert_eq!(rebuilt.filter().unwrap(), "status = 'active'");
+
+        let err = decode_index_configs(&path, b"[{").unwrap_err();
+        assert!(
+            matches!(err, Error::InvalidMetadata { ref path, .. } if path == INDICES_FILE),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn test_builder_all_params() {
+        let index_builder = IvfPQIndexBuilder::new()
+            .column("c")
             .metric_type(MetricType::Cosine)
-            .index_name("index".to_owned());
+            .index_name("index");
 
         assert_eq!(index_builder.column.clone().unwrap(), "c");
         assert_eq!(index_builder.metric_type.unwrap(), MetricType::Cosine);
         assert_eq!(index_builder.index_name.clone().unwrap(), "index");
+        assert_eq!(index_builder.column_name(), Some("c"));
+        assert_eq!(index_builder.name(), Some("index"));
+        let builder: &dyn VectorIndexBuilder = &index_builder;
+        assert_eq!(builder.column_name(), Some("c"));
+        assert_eq!(builder.name(), Some("index"));
+        #[allow(deprecated)]
+        {
+            assert_eq!(builder.get_column().unwrap(), "c");
+            assert_eq!(builder.get_index_name().unwrap(), "index");
+        }
 
         let ivf_params = IvfBuildParams::new(500);
-        let mut pq_params = PQBuildParams::default();
-        pq_params.use_opq = true;
-        pq_params.max_iters = 1;
-        pq_params.num_bits = 8;
-        pq_params.num_sub_vectors = 50;
-        pq_params.metric_type = MetricType::Cosine;
-        pq_params.max_opq_iters = 2;
-        index_builder.ivf_params(ivf_params);
-        index_builder.pq_params(pq_params);
+        let pq_params = PQBuildParams {
+            use_opq: true,
+            max_iters: 1,
+            num_bits: 8,
+            num_sub_vectors: 50,
+            metric_type: MetricType::Cosine,
+            max_opq_iters: 2,
+            ..PQBuildParams::default()
+        };
+        let index_builder = index_builder.ivf_params(ivf_params).pq_params(pq_params);
 
         let index_params = index_builder.build();
         assert_eq!(index_params.stages.len(), 2);
-        if let StageParams::Ivf(ivf_params) = index_params.stages.get(0).unwrap() {
+        if let StageParams::Ivf(ivf_params) = index_params.stages.first().unwrap() {
             assert_eq!(ivf_params.num_partitions, 500);
         } else {
-            assert!(false, "Expected first stage to be ivf")
+            panic!("Expected first stage to be ivf")
         }
 
         if let StageParams::PQ(pq_params) = index_params.stages.get(1).unwrap() {
-            assert_eq!(pq_params.use_opq, true);
+            assert!(pq_params.use_opq);
             assert_eq!(pq_params.max_iters, 1);
             assert_eq!(pq_params.num_bits, 8);
             assert_eq!(pq_params.num_sub_vectors, 50);
             assert_eq!(pq_params.metric_type, MetricType::Cosine);
             assert_eq!(pq_params.max_opq_iters, 2);
         } else {
-            assert!(false, "Expected second stage to be pq")
+            panic!("Expected second stage to be pq")
         }
     }
+
+    #[test]
+    fn test_validate() {
+        let vector = |item: DataType| {
+            let item = Arc::new(Field::new("item", item, true));
+            Field::new("vector", DataType::FixedSizeList(item, 8), true)
+        };
+        let builder: Box<dyn VectorIndexBuilder> = Box::new(IvfPQIndexBuilder::new());
+        builder.validate(&vector(DataType::Float32)).unwrap();
+        let message = |field: Field| match builder.validate(&field).unwrap_err() {
+            Error::InvalidInput { message } => message,
+            e => panic!("unexpected error {e}"),
+        };
+        assert!(message(vector(DataType::Float16)).contains("float16"));
+        let multivector = DataType::List(Arc::new(vector(DataType::Float32)));
+        assert!(message(Field::new("tokens", multivector, true)).contains("multivector"));
+        let text = message(Field::new("text", DataType::Utf8, true));
+        assert!(text.contains("'text' of Utf8"), "{text}");
+    }
 }
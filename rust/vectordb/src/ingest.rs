@@ -0,0 +1,510 @@
+// Copyright 2023 LanceDB Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Ingestion of documents split into chunks, see [Chunker].
+//!
+//! A document is split into chunks of up to `chunk_size` characters, or tokens
+//! of a [Tokenizer], each starting [Chunker::overlap] of them before the end
+//! of the chunk before it. A row is written per chunk, with the id of its
+//! document, its index in the document, its byte offset in the document and
+//! its text, and the table computes its vector with the embedding function of
+//! the text column, see [crate::database::CreateTableBuilder::embedding].
+
+use std::collections::HashSet;
+use std::fmt::Debug;
+use std::ops::Range;
+use std::sync::Arc;
+
+use arrow_array::{
+    RecordBatch, RecordBatchIterator, RecordBatchReader, StringArray, UInt32Array, UInt64Array,
+};
+use arrow_schema::{DataType, Field, Schema, SchemaRef};
+
+use crate::error::{Error, Result};
+use crate::query::{col, lit};
+use crate::table::TableLike;
+
+/// The column of the ids of the documents of the chunks.
+pub const DOC_ID_COLUMN: &str = "doc_id";
+/// The column of the indices of the chunks in their documents, from 0.
+pub const CHUNK_INDEX_COLUMN: &str = "chunk_index";
+/// The column of the byte offsets of the chunks in their documents.
+pub const OFFSET_COLUMN: &str = "offset";
+/// The default column of the texts of the chunks, see [Chunker::text_column].
+pub const TEXT_COLUMN: &str = "text";
+
+/// Splits texts into the tokens a [Chunker] counts.
+pub trait Tokenizer: Debug + Send + Sync {
+    /// The byte ranges of the tokens of `text`, in order and not overlapping.
+    fn tokenize(&self, text: &str) -> Vec<Range<usize>>;
+}
+
+/// A [Tokenizer] of the runs of non-whitespace characters.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WhitespaceTokenizer;
+
+impl Tokenizer for WhitespaceTokenizer {
+    fn tokenize(&self, text: &str) -> Vec<Range<usize>> {
+        let mut tokens = Vec::new();
+        let mut start = None;
+        for (i, c) in text.char_indices() {
+            match (c.is_whitespace(), start) {
+                (true, Some(token)) => {
+                    tokens.push(token..i);
+                    start = None;
+                }
+                (false, None) => start = Some(i),
+                _ => {}
+            }
+        }
+        if let Some(token) = start {
+            tokens.push(token..text.len());
+        }
+        tokens
+    }
+}
+
+/// A document to ingest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Document {
+    /// The id of the document, written to [DOC_ID_COLUMN].
+    pub id: String,
+    /// The text split into chunks.
+    pub text: String,
+}
+
+impl Document {
+    pub fn new(id: impl Into<String>, text: impl Into<String>) -> Self {
+        Self {
+            id: id.into(),
+            text: text.into(),
+        }
+    }
+}
+
+/// A chunk of a document, see [Chunker::split].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chunk {
+    /// The index of the chunk in its document, from 0.
+    pub index: usize,
+    /// The byte offset of the chunk in its document.
+    pub offset: usize,
+    /// The text of the chunk, that of the document from its offset.
+    pub text: String,
+}
+
+/// The documents of a [Chunker::ingest].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IngestReport {
+    /// The ids of the documents written, in order.
+    pub written: Vec<String>,
+    /// The ids of the documents left out, which the table already had or which
+    /// came earlier in the documents ingested.
+    pub skipped: Vec<String>,
+    /// The number of chunks written.
+    pub chunks: usize,
+}
+
+/// Splits documents into chunks, and writes them to a table, see
+/// [Chunker::ingest].
+#[derive(Debug, Clone)]
+pub struct Chunker {
+    chunk_size: usize,
+    overlap: usize,
+    tokenizer: Option<Arc<dyn Tokenizer>>,
+    text_column: String,
+}
+
+impl Chunker {
+    /// A chunker of chunks of up to `chunk_size` characters, or tokens of
+    /// [Chunker::tokenizer].
+    pub fn new(chunk_size: usize) -> Self {
+        Self {
+            chunk_size,
+            overlap: 0,
+            tokenizer: None,
+            text_column: TEXT_COLUMN.to_string(),
+        }
+    }
+
+    /// The characters, or tokens, each chunk shares with the chunk before it.
+    /// Must be less than the chunk size. Default: 0.
+    pub fn overlap(mut self, overlap: usize) -> Self {
+        self.overlap = overlap;
+        self
+    }
+
+    /// Count the chunk size and the overlap in the tokens of `tokenizer`
+    /// instead of characters. A chunk runs from the start of its first token
+    /// to the end of its last one.
+    pub fn tokenizer(mut self, tokenizer: Arc<dyn Tokenizer>) -> Self {
+        self.tokenizer = Some(tokenizer);
+        self
+    }
+
+    /// The column the texts of the chunks are written to, the source column
+    /// of the embedding function of the table. Default: [TEXT_COLUMN].
+    pub fn text_column(mut self, column: impl Into<String>) -> Self {
+        self.text_column = column.into();
+        self
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.chunk_size == 0 {
+            return Err(Error::InvalidInput {
+                message: "a chunker needs a chunk size of at least 1".to_string(),
+            });
+        }
+        if self.overlap >= self.chunk_size {
+            return Err(Error::InvalidInput {
+                message: format!(
+                    "the overlap of the chunks, {}, must be less than their size, {}",
+                    self.overlap, self.chunk_size
+                ),
+            });
+        }
+        Ok(())
+    }
+
+    /// The chunks of `text`, none if it is empty. Fails with an
+    /// [Error::InvalidInput] if the chunk size is 0 or not more than the
+    /// overlap.
+    pub fn split(&self, text: &str) -> Result<Vec<Chunk>> {
+        self.validate()?;
+        let units = match self.tokenizer.as_ref() {
+            Some(tokenizer) => tokenizer.tokenize(text),
+            None => text
+                .char_indices()
+                .map(|(i, c)| i..i + c.len_utf8())
+                .collect(),
+        };
+        let step = self.chunk_size - self.overlap;
+        let mut chunks = Vec::new();
+        let mut start = 0;
+        while start < units.len() {
+            let end = (start + self.chunk_size).min(units.len());
+            let bytes = units[start].start..units[end - 1].end;
+            chunks.push(Chunk {
+                index: chunks.len(),
+                offset: bytes.start,
+                text: text[bytes].to_string(),
+            });
+            if end == units.len() {
+                break;
+            }
+            start += step;
+        }
+        Ok(chunks)
+    }
+
+    /// The schema of the rows of the chunks, without their vectors.
+    pub fn schema(&self) -> SchemaRef {
+        Arc::new(Schema::new(vec![
+            Field::new(DOC_ID_COLUMN, DataType::Utf8, false),
+            Field::new(CHUNK_INDEX_COLUMN, DataType::UInt32, false),
+            Field::new(OFFSET_COLUMN, DataType::UInt64, false),
+            Field::new(&self.text_column, DataType::Utf8, false),
+        ]))
+    }
+
+    /// The rows of the chunks of `documents`, for example the initial data of
+    /// a table the documents are ingested in.
+    pub fn to_batches(&self, documents: &[Document]) -> Result<Box<dyn RecordBatchReader + Send>> {
+        let mut ids = Vec::new();
+        let mut chunks = Vec::new();
+        for document in documents {
+            for chunk in self.split(&document.text)? {
+                ids.push(document.id.as_str());
+                chunks.push(chunk);
+            }
+        }
+        let schema = self.schema();
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(StringArray::from(ids)),
+                Arc::new(UInt32Array::from_iter_values(
+                    chunks.iter().map(|c| c.index as u32),
+                )),
+                Arc::new(UInt64Array::from_iter_values(
+                    chunks.iter().map(|c| c.offset as u64),
+                )),
+                Arc::new(StringArray::from_iter_values(
+                    chunks.iter().map(|c| c.text.as_str()),
+                )),
+            ],
+        )
+        .map_err(lance::Error::from)?;
+        Ok(Box::new(RecordBatchIterator::new(vec![Ok(batch)], schema)))
+    }
+
+    /// Write the chunks of `documents` to `table`, with [TableLike::add].
+    ///
+    /// Each document is written by its own add, so that it is written with
+    /// all its chunks or not at all, and the documents the table already has
+    /// chunks of are skipped. An ingestion that failed, whose error is
+    /// returned, is resumed by ingesting the same documents again. The
+    /// documents without chunks are written without adding any row.
+    pub async fn ingest(
+        &self,
+        table: &dyn TableLike,
+        documents: &[Document],
+    ) -> Result<IngestReport> {
+        self.validate()?;
+        let mut report = IngestReport::default();
+        if documents.is_empty() {
+            return Ok(report);
+        }
+        let ids = documents.iter().map(|d| d.id.as_str());
+        let filter = col(DOC_ID_COLUMN).in_list(ids).to_string();
+        let resumed = table.count_rows_filtered(&filter).await? > 0;
+        let mut seen = HashSet::new();
+        for document in documents {
+            if !seen.insert(document.id.as_str())
+                || (resumed && self.is_stored(table, &document.id).await?)
+            {
+                report.skipped.push(document.id.clone());
+                continue;
+            }
+            let chunks = self.split(&document.text)?.len();
+            if chunks > 0 {
+                table
+                    .add(self.to_batches(std::slice::from_ref(document))?, None)
+                    .await?;
+            }
+            report.written.push(document.id.clone());
+            report.chunks += chunks;
+        }
+        Ok(report)
+    }
+
+    /// Whether `table` has chunks of the document `id`.
+    async fn is_stored(&self, table: &dyn TableLike, id: &str) -> Result<bool> {
+        let filter = col(DOC_ID_COLUMN).eq(lit(id)).to_string();
+        Ok(table.count_rows_filtered(&filter).await? > 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use arrow_array::{Array, ArrayRef, FixedSizeListArray, Float32Array};
+    use async_trait::async_trait;
+    use futures::TryStreamExt;
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::embeddings::tests::MockEmbedding;
+    use crate::embeddings::EmbeddingFunction;
+    use crate::{Database, TableRef};
+
+    const TEXT: &str = "the quick brown fox jumps over the lazy dog";
+
+    fn texts(chunks: &[Chunk]) -> Vec<&str> {
+        chunks.iter().map(|c| c.text.as_str()).collect()
+    }
+
+    #[test]
+    fn test_split() {
+        let chunks = Chunker::new(16).overlap(4).split(TEXT).unwrap();
+        assert_eq!(
+            texts(&chunks),
+            vec![
+                "the quick brown ",
+                "own fox jumps ov",
+                "s over the lazy ",
+                "azy dog"
+            ]
+        );
+        assert_eq!(
+            chunks
+                .iter()
+                .map(|c| (c.index, c.offset))
+                .collect::<Vec<_>>(),
+            vec![(0, 0), (1, 12), (2, 24), (3, 36)]
+        );
+        for chunk in &chunks {
+            assert!(TEXT[chunk.offset..].starts_with(&chunk.text));
+        }
+
+        // The characters are counted, not the bytes.
+        let chunks = Chunker::new(2).split("héllo").unwrap();
+        assert_eq!(texts(&chunks), vec!["hé", "ll", "o"]);
+        assert_eq!(chunks[1].offset, 3);
+        assert!(Chunker::new(4).split("").unwrap().is_empty());
+
+        let tokens = Chunker::new(4)
+            .overlap(1)
+            .tokenizer(Arc::new(WhitespaceTokenizer));
+        let chunks = tokens.split(TEXT).unwrap();
+        assert_eq!(
+            texts(&chunks),
+            vec!["the quick brown fox", "fox jumps over the", "the lazy dog"]
+        );
+        // A text of fewer tokens than a chunk is one chunk.
+        let chunks = tokens.split("  lazy  dog ").unwrap();
+        assert_eq!(texts(&chunks), vec!["lazy  dog"]);
+        assert_eq!(chunks[0].offset, 2);
+
+        for chunker in [Chunker::new(0), Chunker::new(4).overlap(4)] {
+            let err = chunker.split(TEXT).unwrap_err();
+            assert!(matches!(err, Error::InvalidInput { .. }), "{err}");
+        }
+    }
+
+    /// [MockEmbedding], failing while `fail` is set for the texts containing
+    /// `"fail"`.
+    #[derive(Debug)]
+    struct FlakyEmbedding {
+        fail: AtomicBool,
+    }
+
+    #[async_trait]
+    impl EmbeddingFunction for FlakyEmbedding {
+        fn name(&self) -> String {
+            "flaky".to_string()
+        }
+
+        fn source_type(&self) -> DataType {
+            DataType::Utf8
+        }
+
+        fn dims(&self) -> usize {
+            4
+        }
+
+        async fn embed(&self, input: ArrayRef) -> Result<ArrayRef> {
+            let strings = input.as_any().downcast_ref::<StringArray>().unwrap();
+            if self.fail.load(Ordering::SeqCst)
+                && strings.iter().flatten().any(|s| s.contains("fail"))
+            {
+                return Err(Error::Runtime {
+                    message: "the embedding service is unavailable".to_string(),
+                });
+            }
+            MockEmbedding { dims: 4 }.embed(input).await
+        }
+    }
+
+    /// The `(doc_id, chunk_index, text, vector)` of the rows of `table`,
+    /// sorted.
+    async fn rows(table: &TableRef) -> Vec<(String, u32, String, Vec<f32>)> {
+        let batches = table
+            .search(Float32Array::from(vec![0.0; 4]).into())
+            .limit(100)
+            .execute()
+            .await
+            .unwrap()
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        let mut rows = Vec::new();
+        for batch in batches {
+            let ids = batch[DOC_ID_COLUMN]
+                .as_any()
+                .downcast_ref::<StringArray>()
+                .unwrap();
+            let indices = batch[CHUNK_INDEX_COLUMN]
+                .as_any()
+                .downcast_ref::<UInt32Array>()
+                .unwrap();
+            let texts = batch[TEXT_COLUMN]
+                .as_any()
+                .downcast_ref::<StringArray>()
+                .unwrap();
+            let vectors = batch["vector"]
+                .as_any()
+                .downcast_ref::<FixedSizeListArray>()
+                .unwrap();
+            for row in 0..batch.num_rows() {
+                let vector = vectors.value(row);
+                let vector = vector.as_any().downcast_ref::<Float32Array>().unwrap();
+                rows.push((
+                    ids.value(row).to_string(),
+                    indices.value(row),
+                    texts.value(row).to_string(),
+                    vector.values().to_vec(),
+                ));
+            }
+        }
+        rows.sort_by(|a, b| (&a.0, a.1).cmp(&(&b.0, b.1)));
+        rows
+    }
+
+    #[tokio::test]
+    async fn test_ingest() {
+        let tmp_dir = tempdir().unwrap();
+        let db = Database::connect(tmp_dir.path().to_str().unwrap())
+            .await
+            .unwrap();
+        let chunker = Chunker::new(4).tokenizer(Arc::new(WhitespaceTokenizer));
+        let first = Document::new("a", "one two three four five");
+        let embedding = Arc::new(FlakyEmbedding {
+            fail: AtomicBool::new(true),
+        });
+        let table = db
+            .create_table(
+                "docs",
+                chunker.to_batches(std::slice::from_ref(&first)).unwrap(),
+            )
+            .embedding(TEXT_COLUMN, "vector", embedding.clone())
+            .execute()
+            .await
+            .unwrap();
+
+        let documents = vec![
+            first,
+            Document::new("b", "six seven"),
+            Document::new("c", "eight fail nine"),
+            Document::new("d", "ten"),
+        ];
+        let err = chunker
+            .ingest(table.as_ref(), &documents)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::Runtime { .. }), "{err}");
+        // The documents before the failure are written.
+        assert_eq!(table.count_rows().await.unwrap(), 3);
+
+        embedding.fail.store(false, Ordering::SeqCst);
+        let report = chunker.ingest(table.as_ref(), &documents).await.unwrap();
+        assert_eq!(
+            report,
+            IngestReport {
+                written: vec!["c".to_string(), "d".to_string()],
+                skipped: vec!["a".to_string(), "b".to_string()],
+                chunks: 2,
+            }
+        );
+        let vector = |text: &str| vec![text.len() as f32, text.as_bytes()[0] as f32, 0.0, 0.0];
+        let row =
+            |id: &str, index, text: &str| (id.to_string(), index, text.to_string(), vector(text));
+        assert_eq!(
+            rows(&table).await,
+            vec![
+                row("a", 0, "one two three four"),
+                row("a", 1, "five"),
+                row("b", 0, "six seven"),
+                row("c", 0, "eight fail nine"),
+                row("d", 0, "ten"),
+            ]
+        );
+
+        // Ingesting them again writes nothing.
+        let report = chunker.ingest(table.as_ref(), &documents).await.unwrap();
+        assert!(report.written.is_empty());
+        assert_eq!(table.count_rows().await.unwrap(), 5);
+    }
+}
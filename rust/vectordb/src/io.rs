@@ -0,0 +1,31 @@
+// Copyright 2023 Lance Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+pub(crate) mod auto_id;
+pub(crate) mod bad_vectors;
+pub(crate) mod coalesce;
+pub(crate) mod conform;
+pub(crate) mod constraints;
+pub(crate) mod content_hash;
+#[cfg(feature = "polars")]
+pub(crate) mod dataframe;
+#[cfg(feature = "ipc")]
+pub mod ipc;
+pub(crate) mod metered;
+pub(crate) mod mirror;
+pub(crate) mod norms;
+pub mod object_store;
+pub mod progress;
+pub(crate) mod split;
+pub(crate) mod uri;
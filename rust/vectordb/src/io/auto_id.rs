@@ -0,0 +1,183 @@
+// Copyright 2023 LanceDB Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The columns a table assigns to the written rows: the ids of
+//! [crate::database::CreateTableBuilder::auto_id], and the versions of
+//! [crate::database::CreateTableBuilder::track_modifications].
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use arrow_array::{ArrayRef, RecordBatch, RecordBatchReader, UInt64Array};
+use arrow_schema::{ArrowError, DataType, Field, Schema, SchemaRef};
+
+use crate::error::{Error, Result};
+
+/// A reader of the batches of `inner` with the column `column` of ids, from
+/// the value of `next` when the first batch is read. `next` is left at the id
+/// after the last one assigned.
+///
+/// Fails with an [Error::InvalidInput] if `inner` already has the column.
+pub(crate) fn with_auto_ids(
+    inner: Box<dyn RecordBatchReader>,
+    column: &str,
+    next: Arc<AtomicU64>,
+) -> Result<Box<dyn RecordBatchReader>> {
+    if inner.schema().field_with_name(column).is_ok() {
+        return Err(Error::InvalidInput {
+            message: format!("the ids of '{column}' are assigned by the table, leave it out"),
+        });
+    }
+    Ok(assigned(inner, column, next, true))
+}
+
+/// A reader of the batches of `inner` with the column `column` of the value of
+/// `version` when each batch is read, the version the rows are written in.
+///
+/// Fails with an [Error::InvalidInput] if `inner` already has the column.
+pub(crate) fn with_versions(
+    inner: Box<dyn RecordBatchReader>,
+    column: &str,
+    version: Arc<AtomicU64>,
+) -> Result<Box<dyn RecordBatchReader>> {
+    if inner.schema().field_with_name(column).is_ok() {
+        return Err(Error::InvalidInput {
+            message: format!("the versions of '{column}' are set by the table, leave it out"),
+        });
+    }
+    Ok(assigned(inner, column, version, false))
+}
+
+/// The batches of `inner` with the `UInt64` column `column` read from `next`,
+/// incremented for each row if `increment`.
+fn assigned(
+    inner: Box<dyn RecordBatchReader>,
+    column: &str,
+    next: Arc<AtomicU64>,
+    increment: bool,
+) -> Box<dyn RecordBatchReader> {
+    let schema = inner.schema();
+    let mut fields = schema.fields().iter().cloned().collect::<Vec<_>>();
+    fields.push(Arc::new(Field::new(column, DataType::UInt64, false)));
+    let schema = Arc::new(Schema::new_with_metadata(fields, schema.metadata().clone()));
+    Box::new(AssignedReader {
+        inner,
+        schema,
+        next,
+        increment,
+    })
+}
+
+struct AssignedReader {
+    inner: Box<dyn RecordBatchReader>,
+    schema: SchemaRef,
+    next: Arc<AtomicU64>,
+    increment: bool,
+}
+
+impl Iterator for AssignedReader {
+    type Item = std::result::Result<RecordBatch, ArrowError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let batch = match self.inner.next()? {
+            Ok(batch) => batch,
+            Err(e) => return Some(Err(e)),
+        };
+        let rows = batch.num_rows() as u64;
+        let values: ArrayRef = if self.increment {
+            let start = self.next.fetch_add(rows, Ordering::Relaxed);
+            Arc::new(UInt64Array::from_iter_values(start..start + rows))
+        } else {
+            let value = self.next.load(Ordering::Relaxed);
+            Arc::new(UInt64Array::from_value(value, rows as usize))
+        };
+        let mut columns = batch.columns().to_vec();
+        columns.push(values);
+        Some(RecordBatch::try_new(self.schema.clone(), columns))
+    }
+}
+
+impl RecordBatchReader for AssignedReader {
+    fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow_array::Int32Array;
+    use lance::arrow::RecordBatchBuffer;
+
+    use super::*;
+    use crate::arrow::RecordBatchBuilder;
+
+    #[test]
+    fn test_auto_ids() {
+        let batch = |values: Vec<i32>| {
+            RecordBatchBuilder::new()
+                .column("value", Arc::new(Int32Array::from(values)))
+                .build()
+                .unwrap()
+        };
+        let inner = RecordBatchBuffer::new(vec![batch(vec![1, 2, 3]), batch(vec![4, 5])]);
+        let next = Arc::new(AtomicU64::new(0));
+        let reader = with_auto_ids(Box::new(inner), "id", next.clone()).unwrap();
+        // The ids start from the value of `next` when the rows are read.
+        next.store(10, Ordering::Relaxed);
+        assert_eq!(reader.schema().field(1).data_type(), &DataType::UInt64);
+        let ids = reader
+            .map(|batch| {
+                let batch = batch.unwrap();
+                let ids = batch["id"].as_any().downcast_ref::<UInt64Array>().unwrap();
+                ids.values().to_vec()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(ids, vec![vec![10, 11, 12], vec![13, 14]]);
+        assert_eq!(next.load(Ordering::Relaxed), 15);
+
+        let inner = RecordBatchBuffer::new(vec![batch(vec![1])]);
+        let err = with_auto_ids(Box::new(inner), "value", next).err().unwrap();
+        assert!(matches!(err, Error::InvalidInput { .. }), "{err}");
+    }
+
+    #[test]
+    fn test_versions() {
+        let batch = |values: Vec<i32>| {
+            RecordBatchBuilder::new()
+                .column("value", Arc::new(Int32Array::from(values)))
+                .build()
+                .unwrap()
+        };
+        let inner = RecordBatchBuffer::new(vec![batch(vec![1, 2, 3]), batch(vec![4, 5])]);
+        let version = Arc::new(AtomicU64::new(0));
+        let reader = with_versions(Box::new(inner), "version", version.clone()).unwrap();
+        version.store(7, Ordering::Relaxed);
+        let versions = reader
+            .map(|batch| {
+                let batch = batch.unwrap();
+                let versions = batch["version"].as_any();
+                let versions = versions.downcast_ref::<UInt64Array>().unwrap();
+                versions.values().to_vec()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(versions, vec![vec![7, 7, 7], vec![7, 7]]);
+        assert_eq!(version.load(Ordering::Relaxed), 7);
+
+        let inner = RecordBatchBuffer::new(vec![batch(vec![1])]);
+        let err = with_versions(Box::new(inner), "value", version)
+            .err()
+            .unwrap();
+        assert!(matches!(err, Error::InvalidInput { .. }), "{err}");
+    }
+}
@@ -0,0 +1,506 @@
+// Copyright 2023 LanceDB Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The [OnBadVectors] policy, applied to the vector columns of written batches.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use arrow_array::cast::as_primitive_array;
+use arrow_array::types::{Float16Type, Float32Type};
+use arrow_array::{
+    Array, ArrayRef, ArrowPrimitiveType, BooleanArray, FixedSizeListArray, PrimitiveArray,
+    RecordBatch, RecordBatchReader,
+};
+use arrow_data::ArrayDataBuilder;
+use arrow_schema::{ArrowError, DataType, Field, Schema, SchemaRef};
+use datafusion::arrow::compute::filter_record_batch;
+use half::f16;
+
+use crate::error::Error;
+use crate::table::{OnBadVectors, WriteOptions};
+
+/// The rows dropped and filled by an [OnBadVectors] policy.
+#[derive(Debug, Default)]
+pub(crate) struct BadVectorCounts {
+    pub dropped: AtomicUsize,
+    pub filled: AtomicUsize,
+    /// The error of [OnBadVectors::Error], which lance would otherwise report
+    /// as an opaque arrow error.
+    pub error: Mutex<Option<Error>>,
+}
+
+impl BadVectorCounts {
+    /// `error`, or the error that made the reader stop if there was one.
+    pub(crate) fn take_error(&self, error: Error) -> Error {
+        self.error.lock().unwrap().take().unwrap_or(error)
+    }
+}
+
+/// A reader of the batches of `inner` with the vector columns fixed by the
+/// [OnBadVectors] policy of `options`.
+///
+/// With [WriteOptions::allow_null_vectors] the null vectors are kept, as vectors
+/// of zeros marked in the columns named by [null_vector_column], which are added
+/// to the batches.
+pub(crate) fn check_vectors(
+    inner: Box<dyn RecordBatchReader>,
+    options: &WriteOptions,
+) -> (Box<dyn RecordBatchReader>, Arc<BadVectorCounts>) {
+    let counts = Arc::new(BadVectorCounts::default());
+    let mut schema = inner.schema();
+    if options.allow_null_vectors {
+        schema = with_null_vector_fields(&schema);
+    }
+    let reader = BadVectorReader {
+        inner,
+        schema,
+        policy: options.on_bad_vectors,
+        allow_null_vectors: options.allow_null_vectors,
+        counts: counts.clone(),
+        offset: 0,
+    };
+    (Box::new(reader), counts)
+}
+
+struct BadVectorReader {
+    inner: Box<dyn RecordBatchReader>,
+    /// The schema of `inner`, with the marker columns of null vectors.
+    schema: SchemaRef,
+    policy: OnBadVectors,
+    allow_null_vectors: bool,
+    counts: Arc<BadVectorCounts>,
+    /// The number of rows read before the current batch, to report bad rows.
+    offset: usize,
+}
+
+/// The name of the column marking the null vectors of the vector column
+/// `column`.
+///
+/// lance does not store the validity of vectors, so the null vectors kept by
+/// [WriteOptions::allow_null_vectors] are stored as vectors of zeros, `true` in
+/// this column. The vectors of the rows where it is `false` or null are not null.
+pub fn null_vector_column(column: &str) -> String {
+    format!("_{column}_is_null")
+}
+
+/// The field of the column marking the null vectors of `column`.
+pub(crate) fn null_vector_field(column: &str) -> Field {
+    Field::new(null_vector_column(column), DataType::Boolean, true)
+}
+
+/// The column marking the null vectors of `column`, if `schema` has one.
+pub(crate) fn marker_column_of(schema: &Schema, column: &str) -> Option<String> {
+    let marker = null_vector_column(column);
+    schema.field_with_name(&marker).is_ok().then_some(marker)
+}
+
+/// `batch` with the vectors of `column` that its marker column marks null as
+/// nulls, and without the marker column unless `keep_marker`.
+pub(crate) fn mark_null_vectors(
+    batch: &RecordBatch,
+    column: &str,
+    keep_marker: bool,
+) -> std::result::Result<RecordBatch, ArrowError> {
+    let schema = batch.schema();
+    let marker = null_vector_column(column);
+    let (Ok(i), Ok(m)) = (schema.index_of(column), schema.index_of(&marker)) else {
+        return Ok(batch.clone());
+    };
+    let mut fields = schema.fields().iter().cloned().collect::<Vec<_>>();
+    let mut arrays = batch.columns().to_vec();
+    let vectors = arrays[i].as_any().downcast_ref::<FixedSizeListArray>();
+    let marks = arrays[m].as_any().downcast_ref::<BooleanArray>();
+    if let (Some(vectors), Some(marks)) = (vectors, marks) {
+        if marks.true_count() > 0 {
+            let valid = (0..vectors.len())
+                .map(|row| vectors.is_valid(row) && !(marks.is_valid(row) && marks.value(row)))
+                .collect::<Vec<_>>();
+            arrays[i] = with_validity(vectors, &valid)?;
+        }
+    }
+    if !keep_marker {
+        fields.remove(m);
+        arrays.remove(m);
+    }
+    let schema = Schema::new_with_metadata(fields, schema.metadata().clone());
+    RecordBatch::try_new(Arc::new(schema), arrays)
+}
+
+/// `vectors` with the rows that are not `valid` null.
+fn with_validity(
+    vectors: &FixedSizeListArray,
+    valid: &[bool],
+) -> std::result::Result<ArrayRef, ArrowError> {
+    let dims = vectors.value_length() as usize;
+    let offset = vectors.value_offset(0) as usize;
+    let values = vectors.values().slice(offset, vectors.len() * dims);
+    let nulls = BooleanArray::from(valid.to_vec());
+    let data = ArrayDataBuilder::new(vectors.data_type().clone())
+        .len(vectors.len())
+        .add_child_data(values.to_data())
+        .null_bit_buffer(Some(nulls.values().inner().clone()))
+        .build()?;
+    Ok(Arc::new(FixedSizeListArray::from(data)))
+}
+
+/// `vectors` with its null vectors replaced by vectors of zeros.
+fn zero_null_vectors<T: ArrowPrimitiveType>(
+    vectors: &FixedSizeListArray,
+) -> std::result::Result<ArrayRef, ArrowError> {
+    let dims = vectors.value_length() as usize;
+    let values = vectors.values();
+    let values = as_primitive_array::<T>(values.as_ref());
+    let offset = vectors.value_offset(0) as usize;
+    // The nulls of the values are kept, for the policy to find.
+    let zeroed = (0..vectors.len() * dims)
+        .map(|i| {
+            if vectors.is_null(i / dims) {
+                Some(T::Native::default())
+            } else {
+                values
+                    .is_valid(offset + i)
+                    .then(|| values.value(offset + i))
+            }
+        })
+        .collect::<PrimitiveArray<T>>();
+    let data = ArrayDataBuilder::new(vectors.data_type().clone())
+        .len(vectors.len())
+        .add_child_data(zeroed.into_data())
+        .build()?;
+    Ok(Arc::new(FixedSizeListArray::from(data)))
+}
+
+/// Whether `field` is a vector column: a `FixedSizeList` column of floats.
+fn is_vector_field(field: &Field) -> bool {
+    matches!(field.data_type(), DataType::FixedSizeList(item, _)
+        if matches!(item.data_type(), DataType::Float32 | DataType::Float16))
+}
+
+/// The names of the vector columns of `schema`.
+pub(crate) fn vector_column_names(schema: &Schema) -> impl Iterator<Item = &str> {
+    schema
+        .fields()
+        .iter()
+        .filter(|f| is_vector_field(f))
+        .map(|f| f.name().as_str())
+}
+
+/// The vector columns of `batch`.
+fn vector_columns(batch: &RecordBatch) -> Vec<(usize, &FixedSizeListArray)> {
+    let schema = batch.schema();
+    let columns = schema.fields().iter().enumerate();
+    columns
+        .filter(|(_, field)| is_vector_field(field))
+        .filter_map(|(i, _)| {
+            let column = batch
+                .column(i)
+                .as_any()
+                .downcast_ref::<FixedSizeListArray>();
+            column.map(|column| (i, column))
+        })
+        .collect()
+}
+
+/// Why the vector `row` of `vectors` is bad, `None` if it is not.
+fn bad_vector(vectors: &FixedSizeListArray, row: usize) -> Option<&'static str> {
+    if vectors.is_null(row) {
+        return Some("it is null");
+    }
+    let vector = vectors.value(row);
+    if vector.null_count() > 0 {
+        return Some("it has null values");
+    }
+    let finite = match vector.data_type() {
+        DataType::Float16 => as_primitive_array::<Float16Type>(vector.as_ref())
+            .values()
+            .iter()
+            .all(|v| v.is_finite()),
+        _ => as_primitive_array::<Float32Type>(vector.as_ref())
+            .values()
+            .iter()
+            .all(|v| v.is_finite()),
+    };
+    (!finite).then_some("it has NaN or infinite values")
+}
+
+/// `vectors` with every value of the vectors of `rows` set to `value`.
+fn fill<T: ArrowPrimitiveType>(
+    vectors: &FixedSizeListArray,
+    rows: &[bool],
+    value: T::Native,
+) -> std::result::Result<ArrayRef, ArrowError> {
+    let dims = vectors.value_length() as usize;
+    let values = vectors.values();
+    let values = as_primitive_array::<T>(values.as_ref());
+    let offset = vectors.value_offset(0) as usize;
+    let filled = (0..vectors.len() * dims)
+        .map(|i| {
+            if rows[i / dims] {
+                value
+            } else {
+                values.value(offset + i)
+            }
+        })
+        .collect::<Vec<_>>();
+    // Filled vectors are not null, and the others are not bad, so no row is null.
+    let data = ArrayDataBuilder::new(vectors.data_type().clone())
+        .len(vectors.len())
+        .add_child_data(PrimitiveArray::<T>::from_iter_values(filled).into_data())
+        .build()?;
+    Ok(Arc::new(FixedSizeListArray::from(data)))
+}
+
+/// `schema` with a column marking the null vectors of each of its vector
+/// columns that has none.
+pub(crate) fn with_null_vector_fields(schema: &SchemaRef) -> SchemaRef {
+    let mut fields = schema.fields().iter().cloned().collect::<Vec<_>>();
+    for field in schema.fields().iter().filter(|f| is_vector_field(f)) {
+        if schema
+            .field_with_name(&null_vector_column(field.name()))
+            .is_err()
+        {
+            fields.push(Arc::new(null_vector_field(field.name())));
+        }
+    }
+    Arc::new(Schema::new_with_metadata(fields, schema.metadata().clone()))
+}
+
+/// `batch` with its null vectors as vectors of zeros, marked in the marker
+/// columns of `schema`, the schema of `batch` by [with_null_vector_fields].
+pub(crate) fn zero_null_vector_columns(
+    batch: RecordBatch,
+    schema: &SchemaRef,
+) -> std::result::Result<RecordBatch, ArrowError> {
+    let mut arrays = batch.columns().to_vec();
+    let mut markers = HashMap::new();
+    for (i, vectors) in vector_columns(&batch) {
+        let marker = null_vector_column(batch.schema().field(i).name());
+        // The rows the written data already marks stay marked.
+        let marks = batch
+            .column_by_name(&marker)
+            .and_then(|c| c.as_any().downcast_ref::<BooleanArray>());
+        let marks = (0..vectors.len())
+            .map(|row| {
+                let marked = marks.is_some_and(|m| m.is_valid(row) && m.value(row));
+                Some(marked || vectors.is_null(row))
+            })
+            .collect::<BooleanArray>();
+        markers.insert(marker, Arc::new(marks) as ArrayRef);
+        if vectors.null_count() > 0 {
+            arrays[i] = match vectors.value_type() {
+                DataType::Float16 => zero_null_vectors::<Float16Type>(vectors)?,
+                _ => zero_null_vectors::<Float32Type>(vectors)?,
+            };
+        }
+    }
+    let columns = schema
+        .fields()
+        .iter()
+        .map(|field| match markers.remove(field.name()) {
+            Some(marks) => Ok(marks),
+            None => batch
+                .schema()
+                .index_of(field.name())
+                .map(|i| arrays[i].clone()),
+        })
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+    RecordBatch::try_new(schema.clone(), columns)
+}
+
+impl BadVectorReader {
+    fn apply(&mut self, batch: RecordBatch) -> std::result::Result<RecordBatch, ArrowError> {
+        let batch = if self.allow_null_vectors {
+            zero_null_vector_columns(batch, &self.schema)?
+        } else {
+            batch
+        };
+        let mut bad = vec![false; batch.num_rows()];
+        let columns = vector_columns(&batch);
+        for (i, vectors) in &columns {
+            for (row, bad) in bad.iter_mut().enumerate() {
+                let Some(reason) = bad_vector(vectors, row) else {
+                    continue;
+                };
+                if self.policy == OnBadVectors::Error {
+                    let message = format!(
+                        "the vector of row {} of the column '{}' is bad, {reason}",
+                        self.offset + row,
+                        batch.schema().field(*i).name()
+                    );
+                    *self.counts.error.lock().unwrap() = Some(Error::InvalidInput {
+                        message: message.clone(),
+                    });
+                    return Err(ArrowError::InvalidArgumentError(message));
+                }
+                *bad = true;
+            }
+        }
+        self.offset += batch.num_rows();
+        let bad_rows = bad.iter().filter(|b| **b).count();
+        if bad_rows == 0 {
+            return Ok(batch);
+        }
+        match self.policy {
+            OnBadVectors::Error => unreachable!("the first bad vector fails the write"),
+            OnBadVectors::Drop => {
+                self.counts.dropped.fetch_add(bad_rows, Ordering::Relaxed);
+                let keep = bad.iter().map(|b| Some(!b)).collect::<BooleanArray>();
+                filter_record_batch(&batch, &keep)
+            }
+            OnBadVectors::Fill(value) => {
+                self.counts.filled.fetch_add(bad_rows, Ordering::Relaxed);
+                let mut arrays = batch.columns().to_vec();
+                for (i, vectors) in columns {
+                    let column_bad = (0..vectors.len())
+                        .map(|row| bad[row] && bad_vector(vectors, row).is_some())
+                        .collect::<Vec<_>>();
+                    arrays[i] = match vectors.value_type() {
+                        DataType::Float16 => {
+                            fill::<Float16Type>(vectors, &column_bad, f16::from_f32(value))?
+                        }
+                        _ => fill::<Float32Type>(vectors, &column_bad, value)?,
+                    };
+                }
+                RecordBatch::try_new(batch.schema(), arrays)
+            }
+        }
+    }
+}
+
+impl Iterator for BadVectorReader {
+    type Item = std::result::Result<RecordBatch, ArrowError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.inner.next()? {
+            Ok(batch) => Some(self.apply(batch)),
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+impl RecordBatchReader for BadVectorReader {
+    fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow_array::{Float32Array, Int32Array};
+    use lance::arrow::RecordBatchBuffer;
+
+    use super::*;
+    use crate::arrow::RecordBatchBuilder;
+    use crate::error::Result;
+
+    fn batch() -> RecordBatch {
+        RecordBatchBuilder::new()
+            .column("i", Arc::new(Int32Array::from_iter_values(0..3)))
+            .vector_column(
+                "vector",
+                2,
+                vec![[0.0, 1.0], [f32::NAN, 1.0], [2.0, f32::INFINITY]],
+            )
+            .build()
+            .unwrap()
+    }
+
+    fn read(policy: OnBadVectors) -> (Result<Vec<RecordBatch>>, Arc<BadVectorCounts>) {
+        let reader = Box::new(RecordBatchBuffer::new(vec![batch()]));
+        let options = WriteOptions::default().on_bad_vectors(policy);
+        let (reader, counts) = check_vectors(reader, &options);
+        let batches = reader
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .map_err(|e| counts.take_error(lance::Error::from(e).into()));
+        (batches, counts)
+    }
+
+    fn vectors(batch: &RecordBatch) -> Vec<f32> {
+        let vectors = batch.column(1);
+        let vectors = vectors.as_any().downcast_ref::<FixedSizeListArray>();
+        let values = vectors.unwrap().values();
+        let values = values.as_any().downcast_ref::<Float32Array>().unwrap();
+        values.values().to_vec()
+    }
+
+    #[test]
+    fn test_error() {
+        let (batches, _) = read(OnBadVectors::Error);
+        let Err(Error::InvalidInput { message }) = batches else {
+            panic!("expected an invalid input error");
+        };
+        assert_eq!(
+            message,
+            "the vector of row 1 of the column 'vector' is bad, it has NaN or infinite values"
+        );
+    }
+
+    #[test]
+    fn test_drop() {
+        let (batches, counts) = read(OnBadVectors::Drop);
+        let batches = batches.unwrap();
+        assert_eq!(batches[0].num_rows(), 1);
+        assert_eq!(vectors(&batches[0]), vec![0.0, 1.0]);
+        assert_eq!(counts.dropped.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn test_fill() {
+        let (batches, counts) = read(OnBadVectors::Fill(0.5));
+        let batches = batches.unwrap();
+        assert_eq!(vectors(&batches[0]), vec![0.0, 1.0, 0.5, 0.5, 0.5, 0.5]);
+        assert_eq!(counts.filled.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn test_null_vectors() {
+        let batch = RecordBatchBuilder::new()
+            .vector_column(
+                "vector",
+                2,
+                vec![Some(vec![1.0, 2.0]), None, Some(vec![0.0, 0.0])],
+            )
+            .build()
+            .unwrap();
+        let read = |options: WriteOptions| {
+            let reader = Box::new(RecordBatchBuffer::new(vec![batch.clone()]));
+            let (reader, _) = check_vectors(reader, &options);
+            reader.collect::<std::result::Result<Vec<_>, _>>()
+        };
+        assert!(read(WriteOptions::default()).is_err());
+        let batches = read(WriteOptions::default().allow_null_vectors(true)).unwrap();
+        let vectors = batches[0].column(0);
+        let vectors = vectors
+            .as_any()
+            .downcast_ref::<FixedSizeListArray>()
+            .unwrap();
+        assert_eq!(vectors.null_count(), 0);
+        let values = vectors.values();
+        let values = values.as_any().downcast_ref::<Float32Array>().unwrap();
+        assert_eq!(values.values(), &[1.0, 2.0, 0.0, 0.0, 0.0, 0.0]);
+        let marks = batches[0]["_vector_is_null"]
+            .as_any()
+            .downcast_ref::<BooleanArray>()
+            .unwrap();
+        assert_eq!(marks, &BooleanArray::from(vec![false, true, false]));
+
+        // Read back, the marked vector is null and the vector of zeros is not.
+        let marked = mark_null_vectors(&batches[0], "vector", false).unwrap();
+        assert_eq!(marked.num_columns(), 1);
+        let nulls = (0..3).map(|row| marked.column(0).is_null(row));
+        assert_eq!(nulls.collect::<Vec<_>>(), vec![false, true, false]);
+    }
+}
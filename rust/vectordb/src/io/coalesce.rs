@@ -0,0 +1,340 @@
+// Copyright 2023 LanceDB Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! An object store shaping the range requests of the reads of a query, see
+//! [crate::query::ScanParams].
+//!
+//! lance reads a page of a data file, or a partition of an index, with a range
+//! request of its own, thousands of tiny requests for a scan of an object
+//! store. The small reads of the immutable files are served from blocks of
+//! `io_coalesce_bytes` fetched once, large reads are split in requests of at
+//! most `io_max_request_size`, and at most `io_concurrency` requests run at
+//! the same time.
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt::{Debug, Display, Formatter};
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use bytes::{Bytes, BytesMut};
+use futures::future::try_join_all;
+use futures::stream::BoxStream;
+use lance::io::object_store::WrappingObjectStore;
+use object_store::path::Path;
+use object_store::{
+    GetOptions, GetResult, ListResult, MultipartId, ObjectMeta, ObjectStore as OSObjectStore,
+};
+use tokio::io::AsyncWrite;
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+use crate::query::ScanParams;
+
+/// File extensions of the lance files that are never modified once written,
+/// whose blocks can be kept: data files and index files.
+const IMMUTABLE_EXTENSIONS: &[&str] = &["lance", "idx"];
+
+/// The blocks a store keeps, the most recently fetched.
+const MAX_CACHED_BLOCKS: usize = 16;
+
+fn is_immutable(location: &Path) -> bool {
+    location
+        .as_ref()
+        .rsplit_once('.')
+        .is_some_and(|(_, ext)| IMMUTABLE_EXTENSIONS.contains(&ext))
+}
+
+/// Wraps stores in a [CoalescingObjectStore], after the wrapper of the table if
+/// it has one.
+pub(crate) struct CoalescingWrapper {
+    params: ScanParams,
+    inner: Option<Arc<dyn WrappingObjectStore>>,
+}
+
+impl CoalescingWrapper {
+    pub(crate) fn new(params: ScanParams, inner: Option<Arc<dyn WrappingObjectStore>>) -> Self {
+        Self { params, inner }
+    }
+}
+
+impl WrappingObjectStore for CoalescingWrapper {
+    fn wrap(&self, original: Arc<dyn OSObjectStore>) -> Arc<dyn OSObjectStore> {
+        let inner = match &self.inner {
+            Some(wrapper) => wrapper.wrap(original),
+            None => original,
+        };
+        Arc::new(CoalescingObjectStore {
+            inner,
+            coalesce_bytes: self.params.io_coalesce_bytes.unwrap_or(0),
+            max_request_size: self.params.io_max_request_size,
+            permits: self.params.io_concurrency.map(Semaphore::new),
+            sizes: Mutex::default(),
+            blocks: Mutex::default(),
+        })
+    }
+}
+
+/// An object store serving the small reads of immutable files from blocks of
+/// `coalesce_bytes`, splitting the reads above `max_request_size`, and
+/// running at most as many requests at the same time as `permits` has.
+pub(crate) struct CoalescingObjectStore {
+    inner: Arc<dyn OSObjectStore>,
+    coalesce_bytes: usize,
+    max_request_size: Option<usize>,
+    permits: Option<Semaphore>,
+    /// The sizes of the immutable files, read once.
+    sizes: Mutex<HashMap<Path, usize>>,
+    /// The file, offset and bytes of the blocks fetched, oldest first.
+    blocks: Mutex<VecDeque<(Path, usize, Bytes)>>,
+}
+
+impl CoalescingObjectStore {
+    async fn permit(&self) -> Option<SemaphorePermit<'_>> {
+        match &self.permits {
+            // The semaphore is never closed.
+            Some(permits) => Some(permits.acquire().await.unwrap()),
+            None => None,
+        }
+    }
+
+    async fn size(&self, location: &Path) -> object_store::Result<usize> {
+        if let Some(size) = self.sizes.lock().unwrap().get(location) {
+            return Ok(*size);
+        }
+        Ok(self.head(location).await?.size)
+    }
+
+    /// Read `range` of `location` in requests of at most `max_request_size`.
+    async fn fetch(&self, location: &Path, range: Range<usize>) -> object_store::Result<Bytes> {
+        let max = self.max_request_size.unwrap_or(usize::MAX);
+        if range.len() <= max {
+            let _permit = self.permit().await;
+            return self.inner.get_range(location, range).await;
+        }
+        let range_end = range.end;
+        let requests = range.clone().step_by(max).map(|start| async move {
+            let _permit = self.permit().await;
+            let end = (start + max).min(range_end);
+            self.inner.get_range(location, start..end).await
+        });
+        let mut bytes = BytesMut::with_capacity(range.len());
+        for chunk in try_join_all(requests).await? {
+            bytes.extend_from_slice(&chunk);
+        }
+        Ok(bytes.freeze())
+    }
+
+    /// The block of `location` at `offset`, fetching it if it is not kept.
+    async fn block(&self, location: &Path, offset: usize) -> object_store::Result<Bytes> {
+        let cached = self
+            .blocks
+            .lock()
+            .unwrap()
+            .iter()
+            .find_map(|(path, start, bytes)| {
+                (path == location && *start == offset).then(|| bytes.clone())
+            });
+        if let Some(bytes) = cached {
+            return Ok(bytes);
+        }
+        let size = self.size(location).await?;
+        let end = (offset + self.coalesce_bytes).min(size);
+        let bytes = self.fetch(location, offset..end).await?;
+        let mut blocks = self.blocks.lock().unwrap();
+        if blocks.len() == MAX_CACHED_BLOCKS {
+            blocks.pop_front();
+        }
+        blocks.push_back((location.clone(), offset, bytes.clone()));
+        Ok(bytes)
+    }
+}
+
+impl Debug for CoalescingObjectStore {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CoalescingObjectStore")
+            .field("inner", &self.inner)
+            .field("coalesce_bytes", &self.coalesce_bytes)
+            .field("max_request_size", &self.max_request_size)
+            .finish()
+    }
+}
+
+impl Display for CoalescingObjectStore {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Coalescing({})", self.inner)
+    }
+}
+
+#[async_trait]
+impl OSObjectStore for CoalescingObjectStore {
+    async fn put(&self, location: &Path, bytes: Bytes) -> object_store::Result<()> {
+        self.inner.put(location, bytes).await
+    }
+
+    async fn put_multipart(
+        &self,
+        location: &Path,
+    ) -> object_store::Result<(MultipartId, Box<dyn AsyncWrite + Unpin + Send>)> {
+        self.inner.put_multipart(location).await
+    }
+
+    async fn abort_multipart(
+        &self,
+        location: &Path,
+        multipart_id: &MultipartId,
+    ) -> object_store::Result<()> {
+        self.inner.abort_multipart(location, multipart_id).await
+    }
+
+    async fn get_opts(
+        &self,
+        location: &Path,
+        options: GetOptions,
+    ) -> object_store::Result<GetResult> {
+        let _permit = self.permit().await;
+        self.inner.get_opts(location, options).await
+    }
+
+    async fn get_range(&self, location: &Path, range: Range<usize>) -> object_store::Result<Bytes> {
+        let block_size = self.coalesce_bytes;
+        if block_size == 0 || range.len() > block_size || !is_immutable(location) {
+            return self.fetch(location, range).await;
+        }
+        // The range is in at most two blocks.
+        let first = range.start / block_size * block_size;
+        let last = range.end.saturating_sub(1) / block_size * block_size;
+        let mut bytes = self.block(location, first).await?;
+        if last != first {
+            let mut joined = BytesMut::from(bytes.as_ref());
+            joined.extend_from_slice(&self.block(location, last).await?);
+            bytes = joined.freeze();
+        }
+        let end = (range.end - first).min(bytes.len());
+        Ok(bytes.slice((range.start - first).min(end)..end))
+    }
+
+    async fn get_ranges(
+        &self,
+        location: &Path,
+        ranges: &[Range<usize>],
+    ) -> object_store::Result<Vec<Bytes>> {
+        try_join_all(ranges.iter().map(|r| self.get_range(location, r.clone()))).await
+    }
+
+    async fn head(&self, location: &Path) -> object_store::Result<ObjectMeta> {
+        let meta = {
+            let _permit = self.permit().await;
+            self.inner.head(location).await?
+        };
+        if is_immutable(location) {
+            let mut sizes = self.sizes.lock().unwrap();
+            sizes.insert(location.clone(), meta.size);
+        }
+        Ok(meta)
+    }
+
+    async fn delete(&self, location: &Path) -> object_store::Result<()> {
+        self.inner.delete(location).await
+    }
+
+    // async-trait names the lifetime of `self`, which the lint sees as inconsistent.
+    #[allow(mismatched_lifetime_syntaxes)]
+    async fn list(
+        &self,
+        prefix: Option<&Path>,
+    ) -> object_store::Result<BoxStream<'_, object_store::Result<ObjectMeta>>> {
+        self.inner.list(prefix).await
+    }
+
+    async fn list_with_delimiter(&self, prefix: Option<&Path>) -> object_store::Result<ListResult> {
+        self.inner.list_with_delimiter(prefix).await
+    }
+
+    async fn copy(&self, from: &Path, to: &Path) -> object_store::Result<()> {
+        self.inner.copy(from, to).await
+    }
+
+    async fn copy_if_not_exists(&self, from: &Path, to: &Path) -> object_store::Result<()> {
+        self.inner.copy_if_not_exists(from, to).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering;
+
+    use object_store::memory::InMemory;
+
+    use super::*;
+    use crate::io::metered::MeteredWrapper;
+
+    /// The bytes of the reads of 100 bytes of a file of 64 KiB, one after the
+    /// other, through a store of `params`, and the requests they issued.
+    async fn read_pages(params: ScanParams) -> (Vec<Bytes>, u64) {
+        let store: Arc<dyn OSObjectStore> = Arc::new(InMemory::new());
+        let path = Path::from("t.lance/data/0.lance");
+        let data = (0..64 * 1024).map(|i| i as u8).collect::<Vec<_>>();
+        store.put(&path, Bytes::from(data)).await.unwrap();
+
+        let metered = MeteredWrapper::new(None);
+        let counters = metered.counters();
+        let store = CoalescingWrapper::new(params, Some(Arc::new(metered))).wrap(store);
+        let mut pages = Vec::new();
+        for start in (0..20_000).step_by(100) {
+            pages.push(store.get_range(&path, start..start + 100).await.unwrap());
+        }
+        (pages, counters.requests.load(Ordering::Relaxed))
+    }
+
+    #[tokio::test]
+    async fn test_coalesce() {
+        let (expected, requests) = read_pages(ScanParams::default()).await;
+        assert_eq!(requests, 200);
+
+        let params = ScanParams {
+            io_coalesce_bytes: Some(8 * 1024),
+            ..Default::default()
+        };
+        let (pages, requests) = read_pages(params).await;
+        assert_eq!(pages, expected);
+        // The size of the file, and 3 blocks.
+        assert_eq!(requests, 4);
+
+        // The blocks are fetched in requests of at most 1 KiB.
+        let params = ScanParams {
+            io_coalesce_bytes: Some(8 * 1024),
+            io_max_request_size: Some(1024),
+            io_concurrency: Some(2),
+            ..Default::default()
+        };
+        let (pages, requests) = read_pages(params).await;
+        assert_eq!(pages, expected);
+        assert_eq!(requests, 1 + 3 * 8);
+    }
+
+    #[tokio::test]
+    async fn test_mutable_files_not_coalesced() {
+        let store: Arc<dyn OSObjectStore> = Arc::new(InMemory::new());
+        let path = Path::from("t.lance/_latest.manifest");
+        store.put(&path, Bytes::from_static(b"v1")).await.unwrap();
+        let params = ScanParams {
+            io_coalesce_bytes: Some(1024),
+            ..Default::default()
+        };
+        let store = CoalescingWrapper::new(params, None).wrap(store);
+        assert_eq!(store.get_range(&path, 0..2).await.unwrap(), "v1");
+        store.put(&path, Bytes::from_static(b"v2")).await.unwrap();
+        assert_eq!(store.get_range(&path, 0..2).await.unwrap(), "v2");
+    }
+}
@@ -0,0 +1,219 @@
+// Copyright 2023 LanceDB Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Written batches conformed to the schema of a table, see [crate::SchemaDelta].
+
+use std::sync::Arc;
+
+use arrow_array::{
+    new_null_array, Array, ArrayRef, FixedSizeListArray, RecordBatch, RecordBatchReader,
+};
+use arrow_data::ArrayDataBuilder;
+use arrow_schema::{ArrowError, DataType, Field, Schema, SchemaRef};
+use datafusion::arrow::compute::cast;
+
+/// A reader of the batches of `inner` with the columns of `schema`, in its
+/// order and of its types, and nulls for the columns `inner` does not have.
+pub(crate) fn conform(
+    inner: Box<dyn RecordBatchReader>,
+    schema: SchemaRef,
+) -> Box<dyn RecordBatchReader> {
+    Box::new(ConformReader { inner, schema })
+}
+
+/// A reader of the batches of `inner` with their dictionary-encoded columns
+/// decoded to the values. lance cannot append to the dictionary-encoded columns
+/// of a table, so tables are only created with them when asked to, see
+/// [encode_dictionaries].
+pub(crate) fn decode_dictionaries(inner: Box<dyn RecordBatchReader>) -> Box<dyn RecordBatchReader> {
+    let schema = inner.schema();
+    let is_dictionary = |f: &Field| matches!(f.data_type(), DataType::Dictionary(_, _));
+    if !schema.fields().iter().any(|f| is_dictionary(f)) {
+        return inner;
+    }
+    let fields = schema
+        .fields()
+        .iter()
+        .map(|f| match f.data_type() {
+            DataType::Dictionary(_, value) => {
+                Field::new(f.name(), value.as_ref().clone(), f.is_nullable())
+                    .with_metadata(f.metadata().clone())
+            }
+            _ => f.as_ref().clone(),
+        })
+        .collect::<Vec<_>>();
+    let schema = Schema::new_with_metadata(fields, schema.metadata().clone());
+    conform(inner, Arc::new(schema))
+}
+
+/// A reader of the batches of `inner` with the string `columns` encoded as
+/// dictionaries, see [crate::Encoding::Dictionary].
+pub(crate) fn encode_dictionaries(
+    inner: Box<dyn RecordBatchReader>,
+    columns: &[String],
+) -> Box<dyn RecordBatchReader> {
+    if columns.is_empty() {
+        return inner;
+    }
+    let schema = inner.schema();
+    let fields = schema
+        .fields()
+        .iter()
+        .map(|f| {
+            if !columns.contains(f.name()) {
+                return f.as_ref().clone();
+            }
+            let data_type =
+                DataType::Dictionary(Box::new(DataType::Int32), Box::new(f.data_type().clone()));
+            Field::new(f.name(), data_type, f.is_nullable()).with_metadata(f.metadata().clone())
+        })
+        .collect::<Vec<_>>();
+    let schema = Schema::new_with_metadata(fields, schema.metadata().clone());
+    conform(inner, Arc::new(schema))
+}
+
+struct ConformReader {
+    inner: Box<dyn RecordBatchReader>,
+    schema: SchemaRef,
+}
+
+/// `array` cast to `to`. arrow does not cast between `FixedSizeList`s, so the
+/// values of vectors are cast on their own.
+fn cast_column(array: &ArrayRef, to: &DataType) -> Result<ArrayRef, ArrowError> {
+    if array.data_type() == to {
+        return Ok(array.clone());
+    }
+    let (DataType::FixedSizeList(_, _), DataType::FixedSizeList(item, width)) =
+        (array.data_type(), to)
+    else {
+        return cast(array, to);
+    };
+    let vectors = array
+        .as_any()
+        .downcast_ref::<FixedSizeListArray>()
+        .expect("a FixedSizeList column");
+    let width = *width as usize;
+    let offset = vectors.value_offset(0) as usize;
+    let values = vectors.values().slice(offset, vectors.len() * width);
+    let values = cast(&values, item.data_type())?;
+    let data = ArrayDataBuilder::new(to.clone())
+        .len(vectors.len())
+        .nulls(vectors.nulls().cloned())
+        .add_child_data(values.into_data())
+        .build()?;
+    Ok(Arc::new(FixedSizeListArray::from(data)))
+}
+
+impl ConformReader {
+    fn apply(&self, batch: RecordBatch) -> Result<RecordBatch, ArrowError> {
+        let columns = self
+            .schema
+            .fields()
+            .iter()
+            .map(|field| match batch.column_by_name(field.name()) {
+                Some(column) => cast_column(column, field.data_type()),
+                None => Ok(new_null_array(field.data_type(), batch.num_rows())),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        RecordBatch::try_new(self.schema.clone(), columns)
+    }
+}
+
+impl Iterator for ConformReader {
+    type Item = Result<RecordBatch, ArrowError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.inner.next()? {
+            Ok(batch) => Some(self.apply(batch)),
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+impl RecordBatchReader for ConformReader {
+    fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow_array::types::Int32Type;
+    use arrow_array::{
+        DictionaryArray, Float32Array, Float64Array, Int32Array, Int64Array, StringArray,
+    };
+    use lance::arrow::RecordBatchBuffer;
+
+    use super::*;
+
+    #[test]
+    fn test_conform() {
+        let item = Arc::new(Field::new("item", DataType::Float64, true));
+        let values = Float64Array::from(vec![0.0, 1.0, 2.0, 3.0]);
+        let vectors = ArrayDataBuilder::new(DataType::FixedSizeList(item, 2))
+            .len(2)
+            .add_child_data(values.into_data())
+            .build()
+            .unwrap();
+        let incoming = RecordBatch::try_from_iter(vec![
+            (
+                "vector",
+                Arc::new(FixedSizeListArray::from(vectors)) as ArrayRef,
+            ),
+            ("id", Arc::new(Int32Array::from(vec![1, 2])) as ArrayRef),
+        ])
+        .unwrap();
+
+        let item = Arc::new(Field::new("item", DataType::Float32, true));
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("id", DataType::Int64, false),
+            Field::new("name", DataType::Utf8, true),
+            Field::new("vector", DataType::FixedSizeList(item, 2), true),
+        ]));
+        let reader = Box::new(RecordBatchBuffer::new(vec![incoming]));
+        let batches = conform(reader, schema.clone())
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(batches[0].schema(), schema);
+
+        let ids = batches[0].column(0).as_any().downcast_ref::<Int64Array>();
+        assert_eq!(ids.unwrap().values(), &[1, 2]);
+        let names = batches[0].column(1).as_any().downcast_ref::<StringArray>();
+        assert_eq!(names.unwrap().null_count(), 2);
+        let vectors = batches[0]
+            .column(2)
+            .as_any()
+            .downcast_ref::<FixedSizeListArray>();
+        let values = vectors.unwrap().values();
+        let values = values.as_any().downcast_ref::<Float32Array>().unwrap();
+        assert_eq!(values.values(), &[0.0, 1.0, 2.0, 3.0]);
+    }
+
+    #[test]
+    fn test_decode_dictionaries() {
+        let categories: DictionaryArray<Int32Type> = vec!["a", "b", "a"].into_iter().collect();
+        let batch = RecordBatch::try_from_iter(vec![
+            ("id", Arc::new(Int32Array::from(vec![1, 2, 3])) as ArrayRef),
+            ("category", Arc::new(categories) as ArrayRef),
+        ])
+        .unwrap();
+        let reader = Box::new(RecordBatchBuffer::new(vec![batch.clone()]));
+        let batches = decode_dictionaries(reader)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(batches[0].column(0), batch.column(0));
+        let categories = batches[0].column(1).as_any().downcast_ref::<StringArray>();
+        assert_eq!(categories.unwrap(), &StringArray::from(vec!["a", "b", "a"]));
+    }
+}
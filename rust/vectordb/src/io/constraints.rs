@@ -0,0 +1,294 @@
+// Copyright 2023 LanceDB Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The [Constraint]s of a table, checked on the rows of written batches.
+
+use std::sync::{Arc, Mutex};
+
+use arrow_array::cast::as_primitive_array;
+use arrow_array::types::Float32Type;
+use arrow_array::{
+    Array, BooleanArray, FixedSizeListArray, Float64Array, RecordBatch, RecordBatchReader,
+};
+use arrow_schema::{ArrowError, DataType, Schema as ArrowSchema, SchemaRef};
+use datafusion::arrow::compute::{cast, filter_record_batch};
+use datafusion::error::DataFusionError;
+use datafusion::logical_expr::{
+    ColumnarValue, ReturnTypeFunction, ScalarFunctionImplementation, ScalarUDF, Signature,
+    Volatility,
+};
+use datafusion::physical_expr::PhysicalExpr;
+
+use crate::error::{Error, Result};
+use crate::query::JsonFilter;
+use crate::table::{Constraint, OnConstraintViolation, RejectedRow};
+
+/// The function of constraints returning the L2 norm of the vectors of a
+/// column, null for null vectors: `vector_norm(vector) > 0`.
+pub const VECTOR_NORM: &str = "vector_norm";
+
+/// The `vector_norm` function of datafusion, see [VECTOR_NORM].
+fn vector_norm() -> ScalarUDF {
+    let signature = Signature::any(1, Volatility::Immutable);
+    let return_type: ReturnTypeFunction = Arc::new(|_| Ok(Arc::new(DataType::Float64)));
+    let fun: ScalarFunctionImplementation = Arc::new(|args| {
+        let vectors = args[0].clone().into_array(1);
+        let Some(vectors) = vectors.as_any().downcast_ref::<FixedSizeListArray>() else {
+            return Err(DataFusionError::Execution(format!(
+                "{VECTOR_NORM} takes a vector column, got {}",
+                vectors.data_type()
+            )));
+        };
+        let values = cast(vectors.values(), &DataType::Float32)?;
+        let values = as_primitive_array::<Float32Type>(values.as_ref());
+        let dims = vectors.value_length() as usize;
+        let offset = vectors.value_offset(0) as usize;
+        let norms = (0..vectors.len())
+            .map(|row| {
+                let start = offset + row * dims;
+                let vector = &values.values()[start..start + dims];
+                vectors.is_valid(row).then(|| {
+                    vector
+                        .iter()
+                        .map(|v| (*v as f64).powi(2))
+                        .sum::<f64>()
+                        .sqrt()
+                })
+            })
+            .collect::<Float64Array>();
+        Ok(ColumnarValue::Array(Arc::new(norms)))
+    });
+    ScalarUDF::new(VECTOR_NORM, &signature, &return_type, &fun)
+}
+
+/// The columns of `schema` that `constraint` reads.
+pub(crate) fn constraint_columns(
+    constraint: &Constraint,
+    schema: &ArrowSchema,
+) -> Result<Vec<String>> {
+    let functions = vec![Arc::new(vector_norm())];
+    Ok(JsonFilter::plan_with(&constraint.expr, schema, functions)?.columns)
+}
+
+/// A [Constraint] planned for the batches of a schema.
+struct PlannedConstraint {
+    constraint: Constraint,
+    expr: Arc<dyn PhysicalExpr>,
+}
+
+impl PlannedConstraint {
+    /// `constraint` planned for the batches of `schema`, failing with an
+    /// [Error::InvalidInput] if it is not a boolean expression of its columns.
+    fn plan(constraint: &Constraint, schema: &SchemaRef) -> Result<Self> {
+        let invalid = |e: &dyn std::fmt::Display| Error::InvalidInput {
+            message: format!("invalid constraint '{}': {e}", constraint.name),
+        };
+        let filter = JsonFilter::plan_with(
+            &constraint.expr,
+            schema.as_ref(),
+            vec![Arc::new(vector_norm())],
+        )
+        .map_err(|e| invalid(&e))?;
+        let expr = filter.physical(schema).map_err(|e| invalid(&e))?;
+        let data_type = expr.data_type(schema).map_err(lance::Error::from)?;
+        if data_type != DataType::Boolean {
+            return Err(invalid(&format!("it is a {data_type}, not a boolean")));
+        }
+        Ok(Self {
+            constraint: constraint.clone(),
+            expr,
+        })
+    }
+
+    /// Whether each row of `batch` violates the constraint: rows where it is
+    /// false. As for the `CHECK` constraints of SQL, null is not a violation.
+    fn violations(&self, batch: &RecordBatch) -> Result<Vec<bool>> {
+        let value = self
+            .expr
+            .evaluate(batch)
+            .map_err(lance::Error::from)?
+            .into_array(batch.num_rows());
+        let value = value.as_any().downcast_ref::<BooleanArray>().unwrap();
+        Ok((0..value.len())
+            .map(|row| value.is_valid(row) && !value.value(row))
+            .collect())
+    }
+}
+
+/// `constraints` planned for the batches of `schema`.
+fn plan_constraints(
+    constraints: &[Constraint],
+    schema: &SchemaRef,
+) -> Result<Vec<PlannedConstraint>> {
+    constraints
+        .iter()
+        .map(|constraint| PlannedConstraint::plan(constraint, schema))
+        .collect()
+}
+
+/// The rows of `batch` that violate one of `constraints`, the first one at
+/// `offset`, and the names of the constraints each violates.
+fn violations(
+    constraints: &[PlannedConstraint],
+    batch: &RecordBatch,
+    offset: usize,
+) -> Result<Vec<RejectedRow>> {
+    let mut rejected: Vec<RejectedRow> = Vec::new();
+    let mut rows = vec![None; batch.num_rows()];
+    for planned in constraints {
+        for (row, violated) in planned.violations(batch)?.into_iter().enumerate() {
+            if !violated {
+                continue;
+            }
+            let index = *rows[row].get_or_insert_with(|| {
+                rejected.push(RejectedRow {
+                    row: offset + row,
+                    constraints: Vec::new(),
+                });
+                rejected.len() - 1
+            });
+            rejected[index]
+                .constraints
+                .push(planned.constraint.name.clone());
+        }
+    }
+    rejected.sort_by_key(|r| r.row);
+    Ok(rejected)
+}
+
+/// The error of the first row of `rejected`, a violation of `constraints`.
+fn violation_error(constraints: &[PlannedConstraint], rejected: &RejectedRow) -> Error {
+    let name = &rejected.constraints[0];
+    let expr = constraints
+        .iter()
+        .find(|c| &c.constraint.name == name)
+        .map(|c| c.constraint.expr.as_str())
+        .unwrap_or_default();
+    Error::InvalidInput {
+        message: format!(
+            "row {} violates the constraint '{name}', {expr}",
+            rejected.row
+        ),
+    }
+}
+
+/// The rows rejected by [OnConstraintViolation::Reject].
+#[derive(Debug, Default)]
+pub(crate) struct ConstraintViolations {
+    pub rejected: Mutex<Vec<RejectedRow>>,
+    /// The error of [OnConstraintViolation::Error], which lance would
+    /// otherwise report as an opaque arrow error.
+    pub error: Mutex<Option<Error>>,
+}
+
+impl ConstraintViolations {
+    /// `error`, or the error that made the reader stop if there was one.
+    pub(crate) fn take_error(&self, error: Error) -> Error {
+        self.error.lock().unwrap().take().unwrap_or(error)
+    }
+}
+
+/// A reader of the batches of `inner` checked against `constraints`, failing
+/// on the first violation or leaving the violating rows out by `policy`.
+pub(crate) fn check_constraints(
+    inner: Box<dyn RecordBatchReader>,
+    constraints: &[Constraint],
+    policy: OnConstraintViolation,
+) -> Result<(Box<dyn RecordBatchReader>, Arc<ConstraintViolations>)> {
+    let violations = Arc::new(ConstraintViolations::default());
+    if constraints.is_empty() {
+        return Ok((inner, violations));
+    }
+    let constraints = plan_constraints(constraints, &inner.schema())?;
+    let reader = ConstraintReader {
+        inner,
+        constraints,
+        policy,
+        violations: violations.clone(),
+        offset: 0,
+    };
+    Ok((Box::new(reader), violations))
+}
+
+struct ConstraintReader {
+    inner: Box<dyn RecordBatchReader>,
+    constraints: Vec<PlannedConstraint>,
+    policy: OnConstraintViolation,
+    violations: Arc<ConstraintViolations>,
+    /// The number of rows read before the current batch, to report rows.
+    offset: usize,
+}
+
+impl ConstraintReader {
+    fn apply(&mut self, batch: RecordBatch) -> std::result::Result<RecordBatch, ArrowError> {
+        let fail = |violations: &ConstraintViolations, error: Error| {
+            let message = error.to_string();
+            *violations.error.lock().unwrap() = Some(error);
+            ArrowError::InvalidArgumentError(message)
+        };
+        let rejected = violations(&self.constraints, &batch, self.offset)
+            .map_err(|e| fail(&self.violations, e))?;
+        self.offset += batch.num_rows();
+        if rejected.is_empty() {
+            return Ok(batch);
+        }
+        if self.policy == OnConstraintViolation::Error {
+            let error = violation_error(&self.constraints, &rejected[0]);
+            return Err(fail(&self.violations, error));
+        }
+        let first = self.offset - batch.num_rows();
+        let mut keep = vec![true; batch.num_rows()];
+        for row in &rejected {
+            keep[row.row - first] = false;
+        }
+        self.violations.rejected.lock().unwrap().extend(rejected);
+        filter_record_batch(&batch, &BooleanArray::from(keep))
+    }
+}
+
+impl Iterator for ConstraintReader {
+    type Item = std::result::Result<RecordBatch, ArrowError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.inner.next()? {
+            Ok(batch) => Some(self.apply(batch)),
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+impl RecordBatchReader for ConstraintReader {
+    fn schema(&self) -> SchemaRef {
+        self.inner.schema()
+    }
+}
+
+/// Make sure each of `constraints` has a distinct name and is a boolean
+/// expression of the columns of `schema`.
+pub(crate) fn validate_constraints(constraints: &[Constraint], schema: &SchemaRef) -> Result<()> {
+    for (i, constraint) in constraints.iter().enumerate() {
+        if constraint.name.is_empty() {
+            return Err(Error::InvalidInput {
+                message: format!("the constraint '{}' has no name", constraint.expr),
+            });
+        }
+        if constraints[..i].iter().any(|c| c.name == constraint.name) {
+            return Err(Error::InvalidInput {
+                message: format!("several constraints are named '{}'", constraint.name),
+            });
+        }
+        PlannedConstraint::plan(constraint, schema)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,326 @@
+// Copyright 2023 LanceDB Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The hashes of the written rows of [crate::table::WriteOptions::dedupe_on_hash],
+//! skipping the rows whose content is already in the table.
+//!
+//! A row is hashed by the bytes of the values of the deduplicated columns in
+//! the arrow row format. The hashes are stored in the table, so they are
+//! computed with FNV-1a, which unlike [std::collections::hash_map::DefaultHasher]
+//! does not change between releases. lance has no scalar index yet, so the
+//! stored hashes are scanned by every deduplicated write.
+
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use arrow_array::{Array, ArrayRef, BooleanArray, RecordBatch, RecordBatchReader, UInt64Array};
+use arrow_schema::{ArrowError, DataType, Field, Schema, SchemaRef};
+use datafusion::arrow::compute::filter_record_batch;
+use datafusion::arrow::row::{RowConverter, SortField};
+use futures::TryStreamExt;
+use lance::dataset::{Dataset, ROW_ID};
+
+use crate::error::{Error, Result};
+
+/// The column of the hashes of the deduplicated columns of each row, null (read
+/// back as zero) for the rows written without
+/// [crate::table::WriteOptions::dedupe_on_hash].
+pub const CONTENT_HASH_COLUMN: &str = "_content_hash";
+
+/// The field of [CONTENT_HASH_COLUMN].
+pub(crate) fn content_hash_field() -> Field {
+    Field::new(CONTENT_HASH_COLUMN, DataType::UInt64, true)
+}
+
+/// Fail with an [Error::InvalidInput] if the written data has the column of
+/// the hashes, which the table computes.
+pub(crate) fn check_content_hash_left_out(schema: &Schema) -> Result<()> {
+    if schema.field_with_name(CONTENT_HASH_COLUMN).is_ok() {
+        return Err(Error::InvalidInput {
+            message: format!(
+                "the hashes of '{CONTENT_HASH_COLUMN}' are computed by the table, leave it out"
+            ),
+        });
+    }
+    Ok(())
+}
+
+/// The 64-bit FNV-1a hash of `bytes`.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0100_0000_01b3;
+    bytes.iter().fold(OFFSET_BASIS, |hash, b| {
+        (hash ^ *b as u64).wrapping_mul(PRIME)
+    })
+}
+
+/// A converter of the values of `columns` of `schema` to rows.
+fn converter(schema: &Schema, columns: &[String]) -> Result<RowConverter> {
+    let mut fields = Vec::with_capacity(columns.len());
+    for column in columns {
+        let field = schema
+            .field_with_name(column)
+            .map_err(|_| Error::InvalidInput {
+                message: format!("the deduplicated column '{column}' is not in the written data"),
+            })?;
+        fields.push(SortField::new(field.data_type().clone()));
+    }
+    RowConverter::new(fields).map_err(|e| Error::InvalidInput {
+        message: format!(
+            "the columns '{}' cannot be hashed: {e}",
+            columns.join("', '")
+        ),
+    })
+}
+
+/// The hash of the values of `columns` of each row of `batch`.
+fn row_hashes(
+    converter: &mut RowConverter,
+    batch: &RecordBatch,
+    columns: &[String],
+) -> std::result::Result<Vec<u64>, ArrowError> {
+    let arrays = columns
+        .iter()
+        .map(|c| {
+            batch.column_by_name(c).cloned().ok_or_else(|| {
+                ArrowError::SchemaError(format!("the column '{c}' is not in the batch"))
+            })
+        })
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+    let rows = converter.convert_columns(&arrays)?;
+    // A zero hash is a hash that was not stored, see [table_hashes].
+    Ok(rows.iter().map(|row| fnv1a(row.as_ref()).max(1)).collect())
+}
+
+/// The hashes of the rows of `dataset`, computed from `columns` for the rows
+/// written without them.
+///
+/// lance 0.5 reads the nulls of a column back as zeros, so the rows written
+/// without hashes are told apart by their fragment: the fragments written
+/// before the column existed have it in a data file added after theirs. The
+/// rows appended without [crate::table::WriteOptions::dedupe_on_hash] since
+/// read a hash of zero, which [row_hashes] never gives.
+pub(crate) async fn table_hashes(dataset: &Dataset, columns: &[String]) -> Result<HashSet<u64>> {
+    let mut hashes = HashSet::new();
+    let Some(column) = dataset.schema().field(CONTENT_HASH_COLUMN) else {
+        return Ok(hashes);
+    };
+    let unhashed = dataset
+        .get_fragments()
+        .iter()
+        .map(|fragment| fragment.metadata())
+        .filter(|fragment| {
+            fragment
+                .files
+                .first()
+                .is_none_or(|file| !file.fields.contains(&column.id))
+        })
+        .map(|fragment| fragment.id)
+        .collect::<HashSet<_>>();
+    let mut projection = columns.to_vec();
+    projection.push(column.name.clone());
+    let mut scanner = dataset.scan();
+    scanner.project(&projection)?;
+    scanner.with_row_id();
+    let mut stream = scanner.try_into_stream().await?;
+    let mut converter = None;
+    while let Some(batch) = stream.try_next().await? {
+        let stored = batch[CONTENT_HASH_COLUMN].clone();
+        let stored = stored.as_any().downcast_ref::<UInt64Array>().unwrap();
+        let row_ids = batch[ROW_ID].clone();
+        let row_ids = row_ids.as_any().downcast_ref::<UInt64Array>().unwrap();
+        let is_hashed = |row: usize| {
+            stored.is_valid(row)
+                && stored.value(row) != 0
+                && !unhashed.contains(&(row_ids.value(row) >> 32))
+        };
+        if (0..batch.num_rows()).all(is_hashed) {
+            hashes.extend(stored.values().iter().copied());
+            continue;
+        }
+        let converter = match converter.as_mut() {
+            Some(converter) => converter,
+            None => converter.insert(self::converter(&batch.schema(), columns)?),
+        };
+        let computed = row_hashes(converter, &batch, columns).map_err(lance::Error::from)?;
+        for (row, hash) in computed.into_iter().enumerate() {
+            hashes.insert(if is_hashed(row) {
+                stored.value(row)
+            } else {
+                hash
+            });
+        }
+    }
+    Ok(hashes)
+}
+
+/// A reader of the batches of `inner` with the hashes of the values of
+/// `columns` in [CONTENT_HASH_COLUMN], without the rows whose hash is in
+/// `seen`. The hashes of the rows kept are added to `seen`, so that the
+/// duplicated rows of `inner` are written once, and the rows left out are
+/// counted in `skipped`.
+///
+/// The column of the hashes replaces the one of `inner`, or is added after
+/// its columns. Fails with an [Error::InvalidInput] if `inner` does not have
+/// all of `columns`, or if their types cannot be hashed.
+pub(crate) fn with_content_hashes(
+    inner: Box<dyn RecordBatchReader>,
+    columns: &[String],
+    seen: Arc<Mutex<HashSet<u64>>>,
+    skipped: Arc<AtomicUsize>,
+) -> Result<Box<dyn RecordBatchReader>> {
+    let schema = inner.schema();
+    let converter = converter(&schema, columns)?;
+    let position = schema.index_of(CONTENT_HASH_COLUMN).ok();
+    let mut fields = schema.fields().iter().cloned().collect::<Vec<_>>();
+    match position {
+        Some(position) => fields[position] = Arc::new(content_hash_field()),
+        None => fields.push(Arc::new(content_hash_field())),
+    }
+    let schema = Arc::new(Schema::new_with_metadata(fields, schema.metadata().clone()));
+    Ok(Box::new(ContentHashReader {
+        inner,
+        schema,
+        columns: columns.to_vec(),
+        converter,
+        position,
+        seen,
+        skipped,
+    }))
+}
+
+struct ContentHashReader {
+    inner: Box<dyn RecordBatchReader>,
+    schema: SchemaRef,
+    columns: Vec<String>,
+    converter: RowConverter,
+    /// The position of the column of the hashes in `inner`, if it has one.
+    position: Option<usize>,
+    seen: Arc<Mutex<HashSet<u64>>>,
+    skipped: Arc<AtomicUsize>,
+}
+
+impl ContentHashReader {
+    fn dedupe(&mut self, batch: RecordBatch) -> std::result::Result<RecordBatch, ArrowError> {
+        let hashes = row_hashes(&mut self.converter, &batch, &self.columns)?;
+        let mut seen = self.seen.lock().unwrap();
+        let keep = hashes
+            .iter()
+            .map(|hash| seen.insert(*hash))
+            .collect::<Vec<_>>();
+        drop(seen);
+        let kept = hashes
+            .iter()
+            .zip(&keep)
+            .filter_map(|(hash, keep)| keep.then_some(*hash))
+            .collect::<Vec<_>>();
+        let batch = filter_record_batch(&batch, &BooleanArray::from(keep))?;
+        self.skipped
+            .fetch_add(hashes.len() - kept.len(), Ordering::Relaxed);
+        let hashes: ArrayRef = Arc::new(UInt64Array::from(kept));
+        let mut columns = batch.columns().to_vec();
+        match self.position {
+            Some(position) => columns[position] = hashes,
+            None => columns.push(hashes),
+        }
+        RecordBatch::try_new(self.schema.clone(), columns)
+    }
+}
+
+impl Iterator for ContentHashReader {
+    type Item = std::result::Result<RecordBatch, ArrowError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.inner.next()? {
+            Ok(batch) => Some(self.dedupe(batch)),
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+impl RecordBatchReader for ContentHashReader {
+    fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow_array::{Int32Array, StringArray};
+    use lance::arrow::RecordBatchBuffer;
+
+    use super::*;
+    use crate::arrow::RecordBatchBuilder;
+
+    fn batch(ids: Vec<i32>, texts: Vec<&str>) -> RecordBatch {
+        RecordBatchBuilder::new()
+            .column("id", Arc::new(Int32Array::from(ids)))
+            .column("text", Arc::new(StringArray::from(texts)))
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_content_hashes() {
+        let inner = RecordBatchBuffer::new(vec![
+            batch(vec![1, 2, 3], vec!["a", "b", "a"]),
+            batch(vec![4, 5], vec!["c", "b"]),
+        ]);
+        let seen = Arc::new(Mutex::new(HashSet::new()));
+        let skipped = Arc::new(AtomicUsize::new(0));
+        let columns = ["text".to_string()];
+        let reader =
+            with_content_hashes(Box::new(inner), &columns, seen.clone(), skipped.clone()).unwrap();
+        let batches = reader.collect::<std::result::Result<Vec<_>, _>>().unwrap();
+        let ids = batches
+            .iter()
+            .flat_map(|b| {
+                let ids = b["id"].as_any().downcast_ref::<Int32Array>().unwrap();
+                ids.values().to_vec()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(ids, vec![1, 2, 4]);
+        assert_eq!(skipped.load(Ordering::Relaxed), 2);
+        assert_eq!(seen.lock().unwrap().len(), 3);
+        assert_eq!(batches[0].schema().field(2), &content_hash_field());
+
+        // The hashes do not depend on the other columns, nor on the batch.
+        let inner = RecordBatchBuffer::new(vec![batch(vec![9, 10], vec!["c", "d"])]);
+        let reader = with_content_hashes(Box::new(inner), &columns, seen, skipped).unwrap();
+        let batches = reader.collect::<std::result::Result<Vec<_>, _>>().unwrap();
+        assert_eq!(batches[0].num_rows(), 1);
+        let hashes = batches[0][CONTENT_HASH_COLUMN].clone();
+        let hashes = hashes.as_any().downcast_ref::<UInt64Array>().unwrap();
+        let mut converter = converter(&batches[0].schema(), &columns).unwrap();
+        let expected = row_hashes(&mut converter, &batches[0], &columns).unwrap();
+        assert_eq!(hashes.values().to_vec(), expected);
+    }
+
+    #[test]
+    fn test_missing_column() {
+        let inner = RecordBatchBuffer::new(vec![batch(vec![1], vec!["a"])]);
+        let err = with_content_hashes(
+            Box::new(inner),
+            &["missing".to_string()],
+            Default::default(),
+            Default::default(),
+        )
+        .err()
+        .unwrap();
+        assert!(matches!(err, Error::InvalidInput { .. }), "{err}");
+        let schema = batch(vec![1], vec!["a"]).schema();
+        assert!(check_content_hash_left_out(&schema).is_ok());
+    }
+}
@@ -0,0 +1,300 @@
+// Copyright 2023 LanceDB Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Polars DataFrames written to tables, see
+//! [Database::create_table_from_polars](crate::Database::create_table_from_polars).
+//!
+//! Polars keeps its own Arrow arrays, so the columns are converted a value at a
+//! time, whatever chunks a series is made of. Polars has no fixed size lists:
+//! the `List` columns of vectors become `FixedSizeList<Float32>` columns of the
+//! dimensions they are declared with, and categorical columns become strings.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use arrow_array::{
+    ArrayRef, BooleanArray, Date32Array, FixedSizeListArray, Float32Array, Float64Array,
+    Int16Array, Int32Array, Int64Array, Int8Array, RecordBatch, RecordBatchIterator,
+    RecordBatchReader, StringArray, TimestampMicrosecondArray, TimestampMillisecondArray,
+    TimestampNanosecondArray, UInt16Array, UInt32Array, UInt64Array, UInt8Array,
+};
+use arrow_schema::{Field, Schema};
+use polars::prelude::{DataFrame, DataType as PolarsType, Series, TimeUnit};
+
+use crate::arrow::vec_to_fixed_size_list;
+use crate::error::{Error, Result};
+
+/// The rows of a DataFrame converted to a record batch at a time.
+const BATCH_ROWS: usize = 64 * 1024;
+
+/// The batches of the rows of `df`, its vector columns of the dimensions of
+/// `vector_columns`.
+///
+/// The whole DataFrame is converted before it is returned, so that invalid
+/// data fails before anything is written.
+pub(crate) fn dataframe_to_batches(
+    df: &DataFrame,
+    vector_columns: &HashMap<String, i32>,
+) -> Result<Box<dyn RecordBatchReader>> {
+    for column in vector_columns.keys() {
+        if df.column(column).is_err() {
+            return Err(Error::InvalidInput {
+                message: format!("the vector column '{column}' is missing from the DataFrame"),
+            });
+        }
+    }
+    let mut schema = None;
+    let mut batches = Vec::new();
+    for offset in (0..df.height()).step_by(BATCH_ROWS) {
+        let slice = df.slice(offset as i64, BATCH_ROWS);
+        let (fields, columns): (Vec<_>, Vec<_>) = slice
+            .get_columns()
+            .iter()
+            .map(|series| {
+                convert_series(series, offset, vector_columns.get(series.name()).copied())
+            })
+            .collect::<Result<Vec<_>>>()?
+            .into_iter()
+            .unzip();
+        let batch_schema = schema
+            .get_or_insert_with(|| Arc::new(Schema::new(fields)))
+            .clone();
+        batches.push(RecordBatch::try_new(batch_schema, columns).map_err(lance::Error::from)?);
+    }
+    let schema = match schema {
+        Some(schema) => schema,
+        // An empty DataFrame still has the columns of its schema.
+        None => Arc::new(Schema::new(
+            df.get_columns()
+                .iter()
+                .map(|series| {
+                    convert_series(series, 0, vector_columns.get(series.name()).copied())
+                        .map(|(field, _)| field)
+                })
+                .collect::<Result<Vec<_>>>()?,
+        )),
+    };
+    Ok(Box::new(RecordBatchIterator::new(
+        batches.into_iter().map(Ok),
+        schema,
+    )))
+}
+
+/// The column of `series`, whose first row is the row `offset` of its
+/// DataFrame, a vector column of `dims` dimensions if given.
+fn convert_series(series: &Series, offset: usize, dims: Option<i32>) -> Result<(Field, ArrayRef)> {
+    let array: ArrayRef = match (series.dtype(), dims) {
+        (_, Some(dims)) => Arc::new(vectors(series, offset, dims)?),
+        (PolarsType::Boolean, _) => Arc::new(series.bool()?.into_iter().collect::<BooleanArray>()),
+        (PolarsType::Int8, _) => Arc::new(series.i8()?.into_iter().collect::<Int8Array>()),
+        (PolarsType::Int16, _) => Arc::new(series.i16()?.into_iter().collect::<Int16Array>()),
+        (PolarsType::Int32, _) => Arc::new(series.i32()?.into_iter().collect::<Int32Array>()),
+        (PolarsType::Int64, _) => Arc::new(series.i64()?.into_iter().collect::<Int64Array>()),
+        (PolarsType::UInt8, _) => Arc::new(series.u8()?.into_iter().collect::<UInt8Array>()),
+        (PolarsType::UInt16, _) => Arc::new(series.u16()?.into_iter().collect::<UInt16Array>()),
+        (PolarsType::UInt32, _) => Arc::new(series.u32()?.into_iter().collect::<UInt32Array>()),
+        (PolarsType::UInt64, _) => Arc::new(series.u64()?.into_iter().collect::<UInt64Array>()),
+        (PolarsType::Float32, _) => Arc::new(series.f32()?.into_iter().collect::<Float32Array>()),
+        (PolarsType::Float64, _) => Arc::new(series.f64()?.into_iter().collect::<Float64Array>()),
+        (PolarsType::Utf8, _) => Arc::new(series.utf8()?.into_iter().collect::<StringArray>()),
+        (PolarsType::Categorical(_), _) => {
+            let strings = series.cast(&PolarsType::Utf8)?;
+            let strings = strings.utf8()?.into_iter().collect::<StringArray>();
+            Arc::new(strings)
+        }
+        (PolarsType::Date, _) => {
+            let days = series.cast(&PolarsType::Int32)?;
+            Arc::new(days.i32()?.into_iter().collect::<Date32Array>())
+        }
+        (PolarsType::Datetime(unit, tz), _) => {
+            let ticks = series.cast(&PolarsType::Int64)?;
+            let ticks = ticks.i64()?.into_iter();
+            let tz = tz.clone();
+            match unit {
+                TimeUnit::Nanoseconds => Arc::new(
+                    ticks
+                        .collect::<TimestampNanosecondArray>()
+                        .with_timezone_opt(tz),
+                ),
+                TimeUnit::Microseconds => Arc::new(
+                    ticks
+                        .collect::<TimestampMicrosecondArray>()
+                        .with_timezone_opt(tz),
+                ),
+                TimeUnit::Milliseconds => Arc::new(
+                    ticks
+                        .collect::<TimestampMillisecondArray>()
+                        .with_timezone_opt(tz),
+                ),
+            }
+        }
+        (PolarsType::List(_), None) => {
+            return Err(Error::InvalidInput {
+                message: format!(
+                    "the list column '{}' is not a vector column, give its dimensions",
+                    series.name()
+                ),
+            })
+        }
+        (other, None) => {
+            return Err(Error::InvalidInput {
+                message: format!(
+                    "the column '{}' of the DataFrame is of the unsupported type {other}",
+                    series.name()
+                ),
+            })
+        }
+    };
+    let field = Field::new(series.name(), array.data_type().clone(), true);
+    Ok((field, array))
+}
+
+/// The vectors of the list column `series`, of `dims` values each.
+fn vectors(series: &Series, offset: usize, dims: i32) -> Result<FixedSizeListArray> {
+    let name = series.name();
+    let lists = series.list().map_err(|_| Error::InvalidInput {
+        message: format!(
+            "the vector column '{name}' is of type {}, not a list",
+            series.dtype()
+        ),
+    })?;
+    let mut vectors = Vec::with_capacity(series.len());
+    for (row, list) in lists.into_iter().enumerate() {
+        let Some(list) = list else {
+            vectors.push(None);
+            continue;
+        };
+        if list.len() != dims as usize {
+            return Err(Error::InvalidInput {
+                message: format!(
+                    "row {} of the vector column '{name}' has {} values, expected {dims}",
+                    offset + row,
+                    list.len()
+                ),
+            });
+        }
+        let values = list.cast(&PolarsType::Float32)?;
+        let vector = values
+            .f32()?
+            .into_iter()
+            .collect::<Option<Vec<f32>>>()
+            .ok_or_else(|| Error::InvalidInput {
+                message: format!(
+                    "row {} of the vector column '{name}' has null values",
+                    offset + row
+                ),
+            })?;
+        vectors.push(Some(vector));
+    }
+    vec_to_fixed_size_list(dims, vectors)
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow_array::Array;
+    use arrow_schema::DataType;
+    use polars::prelude::{df, NamedFrom};
+
+    use super::*;
+
+    fn collect(reader: Box<dyn RecordBatchReader>) -> Vec<RecordBatch> {
+        reader.collect::<std::result::Result<_, _>>().unwrap()
+    }
+
+    #[test]
+    fn test_vectors_of_chunked_series() {
+        let mut vectors = Series::new(
+            "vector",
+            &[
+                Series::new("", &[1.0_f32, 2.0]),
+                Series::new("", &[3.0_f32, 4.0]),
+            ],
+        );
+        // A series of two chunks.
+        vectors
+            .append(&Series::new("vector", &[Series::new("", &[5.0_f32, 6.0])]))
+            .unwrap();
+        assert_eq!(vectors.n_chunks(), 2);
+        let df = DataFrame::new(vec![Series::new("id", &[1_i64, 2, 3]), vectors]).unwrap();
+
+        let columns = HashMap::from([("vector".to_string(), 2)]);
+        let batches = collect(dataframe_to_batches(&df, &columns).unwrap());
+        assert_eq!(batches.len(), 1);
+        let vectors = batches[0]
+            .column_by_name("vector")
+            .unwrap()
+            .as_any()
+            .downcast_ref::<FixedSizeListArray>()
+            .unwrap();
+        assert_eq!(vectors.value_length(), 2);
+        let values = vectors.values();
+        let values = values.as_any().downcast_ref::<Float32Array>().unwrap();
+        assert_eq!(values.values(), &[1.0, 2.0, 3.0, 4.0, 5.0, 6.0]);
+
+        // Vectors of f64 values are converted to f32.
+        let vectors = Series::new("vector", &[Series::new("", &[7.0_f64, 8.0])]);
+        let df = DataFrame::new(vec![vectors]).unwrap();
+        let batches = collect(dataframe_to_batches(&df, &columns).unwrap());
+        let vectors = batches[0]
+            .column(0)
+            .as_any()
+            .downcast_ref::<FixedSizeListArray>()
+            .unwrap();
+        let values = vectors.values();
+        let values = values.as_any().downcast_ref::<Float32Array>().unwrap();
+        assert_eq!(values.values(), &[7.0, 8.0]);
+    }
+
+    #[test]
+    fn test_mismatched_vector_length() {
+        let vectors = Series::new(
+            "vector",
+            &[
+                Series::new("", &[1.0_f32, 2.0]),
+                Series::new("", &[3.0_f32]),
+            ],
+        );
+        let df = DataFrame::new(vec![vectors]).unwrap();
+        let columns = HashMap::from([("vector".to_string(), 2)]);
+        let err = dataframe_to_batches(&df, &columns).err().unwrap();
+        assert!(
+            matches!(err, Error::InvalidInput { ref message } if message.contains("row 1 ")),
+            "{err}"
+        );
+
+        // A list column without dimensions.
+        assert!(dataframe_to_batches(&df, &HashMap::new()).is_err());
+        let missing = HashMap::from([("missing".to_string(), 2)]);
+        assert!(dataframe_to_batches(&df, &missing).is_err());
+    }
+
+    #[test]
+    fn test_categorical_to_utf8() {
+        let df = df!("color" => &["red", "blue", "red"]).unwrap();
+        let color = df
+            .column("color")
+            .unwrap()
+            .cast(&PolarsType::Categorical(None))
+            .unwrap();
+        let df = DataFrame::new(vec![color]).unwrap();
+        let batches = collect(dataframe_to_batches(&df, &HashMap::new()).unwrap());
+        let colors = batches[0].column(0);
+        assert_eq!(colors.data_type(), &DataType::Utf8);
+        let colors = colors.as_any().downcast_ref::<StringArray>().unwrap();
+        assert_eq!(
+            colors.iter().collect::<Vec<_>>(),
+            vec![Some("red"), Some("blue"), Some("red")]
+        );
+    }
+}
@@ -0,0 +1,98 @@
+// Copyright 2023 LanceDB Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Arrow IPC (Feather v2) files, to move tables between environments.
+//!
+//! Unlike Parquet, IPC files keep the Arrow types of the columns exactly, so
+//! vector columns are read back as the same `FixedSizeList`.
+
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+use arrow_array::{RecordBatch, RecordBatchReader};
+use arrow_ipc::reader::FileReader;
+use arrow_ipc::writer::{FileWriter, IpcWriteOptions};
+use arrow_ipc::CompressionType;
+use arrow_schema::Schema;
+
+use crate::error::{Error, Result};
+
+/// How the record batches of an exported IPC file are compressed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IpcCompression {
+    Lz4,
+    Zstd,
+}
+
+/// Parameters of [crate::NativeTable::export_ipc].
+#[derive(Debug, Clone, Default)]
+pub struct ExportIpcParams {
+    /// The columns to export. `None` exports all columns.
+    pub columns: Option<Vec<String>>,
+
+    /// Only export the rows matching this SQL WHERE clause.
+    pub filter: Option<String>,
+
+    /// `None` writes uncompressed batches.
+    pub compression: Option<IpcCompression>,
+}
+
+fn io_error(path: &Path) -> impl FnOnce(std::io::Error) -> Error + '_ {
+    move |source| Error::Io {
+        path: path.display().to_string(),
+        source,
+    }
+}
+
+/// A reader of the batches of the IPC file at `path`, read one at a time.
+pub(crate) fn read_ipc_file(path: &Path) -> Result<Box<dyn RecordBatchReader>> {
+    let file = File::open(path).map_err(io_error(path))?;
+    let reader = FileReader::try_new(file, None).map_err(lance::Error::from)?;
+    Ok(Box::new(reader))
+}
+
+/// Writes batches to an IPC file as they come.
+pub(crate) struct IpcFileWriter {
+    writer: FileWriter<BufWriter<File>>,
+}
+
+impl IpcFileWriter {
+    pub(crate) fn try_new(
+        path: &Path,
+        schema: &Schema,
+        compression: Option<IpcCompression>,
+    ) -> Result<Self> {
+        let compression = compression.map(|c| match c {
+            IpcCompression::Lz4 => CompressionType::LZ4_FRAME,
+            IpcCompression::Zstd => CompressionType::ZSTD,
+        });
+        let options = IpcWriteOptions::default()
+            .try_with_compression(compression)
+            .map_err(lance::Error::from)?;
+        let file = File::create(path).map_err(io_error(path))?;
+        let writer = FileWriter::try_new_with_options(BufWriter::new(file), schema, options)
+            .map_err(lance::Error::from)?;
+        Ok(Self { writer })
+    }
+
+    pub(crate) fn write(&mut self, batch: &RecordBatch) -> Result<()> {
+        Ok(self.writer.write(batch).map_err(lance::Error::from)?)
+    }
+
+    /// Write the footer of the file.
+    pub(crate) fn finish(mut self) -> Result<()> {
+        Ok(self.writer.finish().map_err(lance::Error::from)?)
+    }
+}
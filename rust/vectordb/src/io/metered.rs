@@ -0,0 +1,237 @@
+// Copyright 2023 LanceDB Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! An object store counting the bytes read and the requests issued through it,
+//! for query metrics.
+
+use std::fmt::{Debug, Display, Formatter};
+use std::ops::Range;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::stream::BoxStream;
+use futures::StreamExt;
+use lance::io::object_store::WrappingObjectStore;
+use object_store::path::Path;
+use object_store::{
+    GetOptions, GetResult, ListResult, MultipartId, ObjectMeta, ObjectStore as OSObjectStore,
+};
+use tokio::io::AsyncWrite;
+
+/// The bytes read and the read requests issued through the stores of a
+/// [MeteredWrapper] so far.
+#[derive(Debug, Default)]
+pub(crate) struct IoCounters {
+    pub(crate) bytes_read: AtomicU64,
+    /// The gets, range gets and heads, a get of several ranges counting for
+    /// one request of each range.
+    pub(crate) requests: AtomicU64,
+}
+
+/// Wraps stores in a [MeteredObjectStore], after the wrapper of the table if it has one.
+pub(crate) struct MeteredWrapper {
+    counters: Arc<IoCounters>,
+    inner: Option<Arc<dyn WrappingObjectStore>>,
+}
+
+impl MeteredWrapper {
+    pub(crate) fn new(inner: Option<Arc<dyn WrappingObjectStore>>) -> Self {
+        Self {
+            counters: Arc::default(),
+            inner,
+        }
+    }
+
+    /// The counters of the stores of this wrapper.
+    pub(crate) fn counters(&self) -> Arc<IoCounters> {
+        self.counters.clone()
+    }
+}
+
+impl WrappingObjectStore for MeteredWrapper {
+    fn wrap(&self, original: Arc<dyn OSObjectStore>) -> Arc<dyn OSObjectStore> {
+        let inner = match &self.inner {
+            Some(wrapper) => wrapper.wrap(original),
+            None => original,
+        };
+        Arc::new(MeteredObjectStore {
+            inner,
+            counters: self.counters.clone(),
+        })
+    }
+}
+
+pub(crate) struct MeteredObjectStore {
+    inner: Arc<dyn OSObjectStore>,
+    counters: Arc<IoCounters>,
+}
+
+impl MeteredObjectStore {
+    fn count(&self, bytes: usize) {
+        self.counters
+            .bytes_read
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    fn count_requests(&self, requests: usize) {
+        self.counters
+            .requests
+            .fetch_add(requests as u64, Ordering::Relaxed);
+    }
+}
+
+impl Debug for MeteredObjectStore {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MeteredObjectStore")
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
+
+impl Display for MeteredObjectStore {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Metered({})", self.inner)
+    }
+}
+
+#[async_trait]
+impl OSObjectStore for MeteredObjectStore {
+    async fn put(&self, location: &Path, bytes: Bytes) -> object_store::Result<()> {
+        self.inner.put(location, bytes).await
+    }
+
+    async fn put_multipart(
+        &self,
+        location: &Path,
+    ) -> object_store::Result<(MultipartId, Box<dyn AsyncWrite + Unpin + Send>)> {
+        self.inner.put_multipart(location).await
+    }
+
+    async fn abort_multipart(
+        &self,
+        location: &Path,
+        multipart_id: &MultipartId,
+    ) -> object_store::Result<()> {
+        self.inner.abort_multipart(location, multipart_id).await
+    }
+
+    async fn get_opts(
+        &self,
+        location: &Path,
+        options: GetOptions,
+    ) -> object_store::Result<GetResult> {
+        self.count_requests(1);
+        let counters = self.counters.clone();
+        let stream = match self.inner.get_opts(location, options).await? {
+            GetResult::File(file, path) => {
+                if let Ok(metadata) = file.metadata() {
+                    self.count(metadata.len() as usize);
+                }
+                return Ok(GetResult::File(file, path));
+            }
+            GetResult::Stream(stream) => stream,
+        };
+        Ok(GetResult::Stream(
+            stream
+                .inspect(move |chunk| {
+                    if let Ok(chunk) = chunk {
+                        counters
+                            .bytes_read
+                            .fetch_add(chunk.len() as u64, Ordering::Relaxed);
+                    }
+                })
+                .boxed(),
+        ))
+    }
+
+    async fn get_range(&self, location: &Path, range: Range<usize>) -> object_store::Result<Bytes> {
+        self.count_requests(1);
+        let bytes = self.inner.get_range(location, range).await?;
+        self.count(bytes.len());
+        Ok(bytes)
+    }
+
+    async fn get_ranges(
+        &self,
+        location: &Path,
+        ranges: &[Range<usize>],
+    ) -> object_store::Result<Vec<Bytes>> {
+        self.count_requests(ranges.len());
+        let bytes = self.inner.get_ranges(location, ranges).await?;
+        self.count(bytes.iter().map(Bytes::len).sum());
+        Ok(bytes)
+    }
+
+    async fn head(&self, location: &Path) -> object_store::Result<ObjectMeta> {
+        self.count_requests(1);
+        self.inner.head(location).await
+    }
+
+    async fn delete(&self, location: &Path) -> object_store::Result<()> {
+        self.inner.delete(location).await
+    }
+
+    // async-trait names the lifetime of `self`, which the lint sees as inconsistent.
+    #[allow(mismatched_lifetime_syntaxes)]
+    async fn list(
+        &self,
+        prefix: Option<&Path>,
+    ) -> object_store::Result<BoxStream<'_, object_store::Result<ObjectMeta>>> {
+        self.inner.list(prefix).await
+    }
+
+    async fn list_with_delimiter(&self, prefix: Option<&Path>) -> object_store::Result<ListResult> {
+        self.inner.list_with_delimiter(prefix).await
+    }
+
+    async fn copy(&self, from: &Path, to: &Path) -> object_store::Result<()> {
+        self.inner.copy(from, to).await
+    }
+
+    async fn copy_if_not_exists(&self, from: &Path, to: &Path) -> object_store::Result<()> {
+        self.inner.copy_if_not_exists(from, to).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use object_store::memory::InMemory;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_bytes_read() {
+        let store: Arc<dyn OSObjectStore> = Arc::new(InMemory::new());
+        let path = Path::from("data.lance");
+        store
+            .put(&path, Bytes::from(vec![0_u8; 100]))
+            .await
+            .unwrap();
+
+        let wrapper = MeteredWrapper::new(None);
+        let metered = wrapper.wrap(store);
+        let counters = wrapper.counters();
+        let bytes_read = &counters.bytes_read;
+        metered.get_range(&path, 10..30).await.unwrap();
+        metered.get_ranges(&path, &[0..5, 50..60]).await.unwrap();
+        assert_eq!(bytes_read.load(Ordering::Relaxed), 35);
+        metered.get(&path).await.unwrap().bytes().await.unwrap();
+        assert_eq!(bytes_read.load(Ordering::Relaxed), 135);
+        metered.head(&path).await.unwrap();
+        assert_eq!(bytes_read.load(Ordering::Relaxed), 135);
+        assert_eq!(counters.requests.load(Ordering::Relaxed), 5);
+    }
+}
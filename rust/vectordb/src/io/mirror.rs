@@ -0,0 +1,799 @@
+// Copyright 2023 LanceDB Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! An object store that serves immutable files from a local disk cache.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Debug, Display, Formatter};
+use std::fs;
+use std::io::{Read, Seek, SeekFrom};
+use std::ops::Range;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::stream::BoxStream;
+use lance::io::object_store::WrappingObjectStore;
+use object_store::path::Path;
+use object_store::{
+    GetOptions, GetResult, ListResult, MultipartId, ObjectMeta, ObjectStore as OSObjectStore,
+};
+use tokio::io::AsyncWrite;
+use uuid::Uuid;
+
+use crate::error::Error;
+
+/// File extensions of the lance files that are never modified once written:
+/// data files and index files. Manifests are always read from the primary.
+const IMMUTABLE_EXTENSIONS: &[&str] = &["lance", "idx"];
+
+/// The extension of the copies being written to the cache.
+const TMP_EXTENSION: &str = ".tmp";
+
+struct CachedFile {
+    size_bytes: usize,
+    last_used: u64,
+}
+
+#[derive(Default)]
+struct MirrorState {
+    files: HashMap<String, CachedFile>,
+    /// The files found larger than the byte budget, which are never cached.
+    too_large: HashSet<String>,
+    size_bytes: usize,
+    clock: u64,
+}
+
+/// A byte-bounded LRU cache of immutable files in a local directory.
+pub(crate) struct MirrorCache {
+    root: PathBuf,
+    capacity_bytes: usize,
+    state: Mutex<MirrorState>,
+}
+
+impl MirrorCache {
+    /// Open the cache at `root`, creating the directory if needed.
+    ///
+    /// Files left by a previous process are kept and count as least recently used.
+    pub(crate) fn try_new(
+        root: impl Into<PathBuf>,
+        capacity_bytes: usize,
+    ) -> std::io::Result<Self> {
+        let root = root.into();
+        fs::create_dir_all(&root)?;
+        let cache = Self {
+            root,
+            capacity_bytes,
+            state: Mutex::new(MirrorState::default()),
+        };
+        cache.scan(&cache.root.clone(), "")?;
+        Ok(cache)
+    }
+
+    fn scan(&self, dir: &std::path::Path, prefix: &str) -> std::io::Result<()> {
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            let key = if prefix.is_empty() {
+                name
+            } else {
+                format!("{prefix}/{name}")
+            };
+            let metadata = entry.metadata()?;
+            if metadata.is_dir() {
+                self.scan(&entry.path(), &key)?;
+            } else if key.ends_with(TMP_EXTENSION) {
+                // A copy a previous process did not finish.
+                let _ = fs::remove_file(entry.path());
+            } else if is_immutable(&key) {
+                let mut state = self.state.lock().unwrap();
+                state.size_bytes += metadata.len() as usize;
+                state.files.insert(
+                    key,
+                    CachedFile {
+                        size_bytes: metadata.len() as usize,
+                        last_used: 0,
+                    },
+                );
+            }
+        }
+        self.evict(0);
+        Ok(())
+    }
+
+    fn local_path(&self, location: &Path) -> PathBuf {
+        self.root.join(location.as_ref())
+    }
+
+    /// Read `range` of the cached copy of `location`, or the whole file if `None`.
+    ///
+    /// A copy whose length is not the length it was cached with is removed,
+    /// and read again from the primary store.
+    fn read(&self, location: &Path, range: Option<Range<usize>>) -> Option<Bytes> {
+        let size_bytes = {
+            let mut state = self.state.lock().unwrap();
+            state.clock += 1;
+            let clock = state.clock;
+            let file = state.files.get_mut(location.as_ref())?;
+            file.last_used = clock;
+            file.size_bytes
+        };
+        let bytes = self.read_file(location, size_bytes, range);
+        if bytes.is_none() {
+            self.remove(location);
+        }
+        bytes
+    }
+
+    fn read_file(
+        &self,
+        location: &Path,
+        size_bytes: usize,
+        range: Option<Range<usize>>,
+    ) -> Option<Bytes> {
+        let mut file = fs::File::open(self.local_path(location)).ok()?;
+        if file.metadata().ok()?.len() != size_bytes as u64 {
+            return None;
+        }
+        let mut buf = Vec::new();
+        match range {
+            Some(range) => {
+                if range.start > range.end || range.end > size_bytes {
+                    return None;
+                }
+                file.seek(SeekFrom::Start(range.start as u64)).ok()?;
+                buf.resize(range.end - range.start, 0);
+                file.read_exact(&mut buf).ok()?;
+            }
+            None => {
+                file.read_to_end(&mut buf).ok()?;
+                if buf.len() != size_bytes {
+                    return None;
+                }
+            }
+        }
+        Some(buf.into())
+    }
+
+    /// Store a copy of `location`, evicting the least recently used files to stay
+    /// within the byte budget.
+    fn insert(&self, location: &Path, bytes: &Bytes) -> std::io::Result<()> {
+        if bytes.len() > self.capacity_bytes {
+            return Ok(());
+        }
+        self.evict(bytes.len());
+        let path = self.local_path(location);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        // Write to a temporary file of this copy first, so that readers and
+        // concurrent copies of the file never see a partial copy.
+        let tmp_path = path.with_extension(format!("{}{TMP_EXTENSION}", Uuid::new_v4()));
+        if let Err(e) = fs::write(&tmp_path, bytes).and_then(|_| fs::rename(&tmp_path, &path)) {
+            let _ = fs::remove_file(&tmp_path);
+            return Err(e);
+        }
+
+        let mut state = self.state.lock().unwrap();
+        state.clock += 1;
+        let last_used = state.clock;
+        if let Some(old) = state.files.insert(
+            location.to_string(),
+            CachedFile {
+                size_bytes: bytes.len(),
+                last_used,
+            },
+        ) {
+            state.size_bytes -= old.size_bytes;
+        }
+        state.size_bytes += bytes.len();
+        Ok(())
+    }
+
+    /// Whether `location` was found larger than the byte budget by [Self::fits].
+    fn too_large(&self, location: &Path) -> bool {
+        self.state
+            .lock()
+            .unwrap()
+            .too_large
+            .contains(location.as_ref())
+    }
+
+    /// Whether a file of `size_bytes` fits in the byte budget, remembering
+    /// `location` as too large if it does not.
+    fn fits(&self, location: &Path, size_bytes: usize) -> bool {
+        if size_bytes <= self.capacity_bytes {
+            return true;
+        }
+        let mut state = self.state.lock().unwrap();
+        state.too_large.insert(location.to_string());
+        false
+    }
+
+    /// Make room for `incoming` more bytes.
+    fn evict(&self, incoming: usize) {
+        let mut state = self.state.lock().unwrap();
+        while state.size_bytes + incoming > self.capacity_bytes {
+            let lru = state
+                .files
+                .iter()
+                .min_by_key(|(_, file)| file.last_used)
+                .map(|(key, _)| key.clone());
+            let Some(key) = lru else {
+                break;
+            };
+            if let Some(evicted) = state.files.remove(&key) {
+                state.size_bytes -= evicted.size_bytes;
+                let _ = fs::remove_file(self.root.join(&key));
+            }
+        }
+    }
+
+    fn remove(&self, location: &Path) {
+        let mut state = self.state.lock().unwrap();
+        state.too_large.remove(location.as_ref());
+        if let Some(old) = state.files.remove(location.as_ref()) {
+            state.size_bytes -= old.size_bytes;
+            let _ = fs::remove_file(self.local_path(location));
+        }
+    }
+
+    #[cfg(test)]
+    fn size_bytes(&self) -> usize {
+        self.state.lock().unwrap().size_bytes
+    }
+}
+
+fn is_immutable(location: &str) -> bool {
+    location
+        .rsplit_once('.')
+        .is_some_and(|(_, ext)| IMMUTABLE_EXTENSIONS.contains(&ext))
+}
+
+/// The directory of the cache holding the files of the store at `uri`, apart
+/// from those of other stores: the scheme and the bucket or host of `uri`,
+/// after the endpoints of `storage_options`.
+pub(crate) fn cache_scope(uri: &str, storage_options: &HashMap<String, String>) -> String {
+    let sanitize = |part: &str| {
+        part.chars()
+            .map(|c| match c {
+                'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '.' => c,
+                _ => '_',
+            })
+            .collect::<String>()
+    };
+    let Ok(url) = url::Url::parse(uri) else {
+        return String::new();
+    };
+    let mut endpoints = storage_options
+        .iter()
+        .filter(|(key, _)| key.to_ascii_lowercase().contains("endpoint"))
+        .map(|(_, value)| value.as_str())
+        .collect::<Vec<_>>();
+    endpoints.sort();
+    let mut parts = vec![url.scheme()];
+    parts.extend(endpoints);
+    parts.extend(url.host_str());
+    parts
+        .into_iter()
+        .map(sanitize)
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Wraps the primary object store of a connection with a [MirrorCache].
+///
+/// Applied after the wrapper that configures the primary store, if any.
+pub(crate) struct MirrorWrapper {
+    cache: Arc<MirrorCache>,
+    scope: String,
+    primary: Option<Arc<dyn WrappingObjectStore>>,
+}
+
+impl MirrorWrapper {
+    /// Cache the files of the store under `scope`, see [cache_scope].
+    pub(crate) fn new(
+        cache: Arc<MirrorCache>,
+        scope: String,
+        primary: Option<Arc<dyn WrappingObjectStore>>,
+    ) -> Self {
+        Self {
+            cache,
+            scope,
+            primary,
+        }
+    }
+}
+
+impl WrappingObjectStore for MirrorWrapper {
+    fn wrap(&self, original: Arc<dyn OSObjectStore>) -> Arc<dyn OSObjectStore> {
+        let primary = match &self.primary {
+            Some(wrapper) => wrapper.wrap(original),
+            None => original,
+        };
+        Arc::new(MirroredObjectStore {
+            primary,
+            cache: self.cache.clone(),
+            scope: self.scope.clone(),
+        })
+    }
+}
+
+/// An object store that writes through to `primary` and serves reads of
+/// immutable files from a local cache, filled on first read.
+///
+/// The disk is read and written on blocking threads.
+pub(crate) struct MirroredObjectStore {
+    primary: Arc<dyn OSObjectStore>,
+    cache: Arc<MirrorCache>,
+    scope: String,
+}
+
+impl MirroredObjectStore {
+    /// The key of `location` in the cache.
+    fn key(&self, location: &Path) -> Path {
+        if self.scope.is_empty() {
+            return location.clone();
+        }
+        Path::from(format!("{}/{location}", self.scope))
+    }
+
+    /// The error of the store for `source`, an error of the local cache.
+    fn cache_error(&self, source: std::io::Error) -> object_store::Error {
+        object_store::Error::Generic {
+            store: "MirroredObjectStore",
+            source: Box::new(Error::Io {
+                path: self.cache.root.to_string_lossy().to_string(),
+                source,
+            }),
+        }
+    }
+
+    /// Run `f` with the cache on a blocking thread.
+    async fn with_cache<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&MirrorCache) -> T + Send + 'static,
+    ) -> object_store::Result<T> {
+        let cache = self.cache.clone();
+        tokio::task::spawn_blocking(move || f(&cache))
+            .await
+            .map_err(|e| self.cache_error(std::io::Error::other(e)))
+    }
+
+    /// Remove the cached copy of `location`, whose file is replaced.
+    async fn invalidate(&self, location: &Path) -> object_store::Result<()> {
+        let key = self.key(location);
+        self.with_cache(move |cache| cache.remove(&key)).await
+    }
+
+    /// Read `range` of `location`, fetching and caching the whole file on a miss.
+    ///
+    /// `None` for a file larger than the cache, whose reads go to the primary
+    /// store: fetching it whole for each of them would read it many times over.
+    async fn read_cached(
+        &self,
+        location: &Path,
+        range: Option<Range<usize>>,
+    ) -> object_store::Result<Option<Bytes>> {
+        let key = self.key(location);
+        let (read_key, read_range) = (key.clone(), range.clone());
+        let cached = self
+            .with_cache(move |cache| cache.read(&read_key, read_range))
+            .await?;
+        if cached.is_some() {
+            return Ok(cached);
+        }
+        if self.cache.too_large(&key)
+            || !self
+                .cache
+                .fits(&key, self.primary.head(location).await?.size)
+        {
+            return Ok(None);
+        }
+        let bytes = self.primary.get(location).await?.bytes().await?;
+        let copy = bytes.clone();
+        self.with_cache(move |cache| cache.insert(&key, &copy))
+            .await?
+            .map_err(|e| self.cache_error(e))?;
+        Ok(Some(match range {
+            Some(range) => bytes.slice(range),
+            None => bytes,
+        }))
+    }
+}
+
+impl Debug for MirroredObjectStore {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MirroredObjectStore")
+            .field("primary", &self.primary)
+            .field("cache", &self.cache.root)
+            .finish()
+    }
+}
+
+impl Display for MirroredObjectStore {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Mirrored({}, {})",
+            self.primary,
+            self.cache.root.display()
+        )
+    }
+}
+
+#[async_trait]
+impl OSObjectStore for MirroredObjectStore {
+    async fn put(&self, location: &Path, bytes: Bytes) -> object_store::Result<()> {
+        self.invalidate(location).await?;
+        self.primary.put(location, bytes).await
+    }
+
+    async fn put_multipart(
+        &self,
+        location: &Path,
+    ) -> object_store::Result<(MultipartId, Box<dyn AsyncWrite + Unpin + Send>)> {
+        self.invalidate(location).await?;
+        self.primary.put_multipart(location).await
+    }
+
+    async fn abort_multipart(
+        &self,
+        location: &Path,
+        multipart_id: &MultipartId,
+    ) -> object_store::Result<()> {
+        self.primary.abort_multipart(location, multipart_id).await
+    }
+
+    async fn get_opts(
+        &self,
+        location: &Path,
+        options: GetOptions,
+    ) -> object_store::Result<GetResult> {
+        let conditional = options.if_match.is_some()
+            || options.if_none_match.is_some()
+            || options.if_modified_since.is_some()
+            || options.if_unmodified_since.is_some();
+        if conditional || !is_immutable(location.as_ref()) {
+            return self.primary.get_opts(location, options).await;
+        }
+        match self.read_cached(location, options.range.clone()).await? {
+            Some(bytes) => Ok(GetResult::Stream(Box::pin(futures::stream::once(
+                async move { Ok(bytes) },
+            )))),
+            None => self.primary.get_opts(location, options).await,
+        }
+    }
+
+    async fn get_range(&self, location: &Path, range: Range<usize>) -> object_store::Result<Bytes> {
+        if !is_immutable(location.as_ref()) {
+            return self.primary.get_range(location, range).await;
+        }
+        match self.read_cached(location, Some(range.clone())).await? {
+            Some(bytes) => Ok(bytes),
+            None => self.primary.get_range(location, range).await,
+        }
+    }
+
+    async fn head(&self, location: &Path) -> object_store::Result<ObjectMeta> {
+        self.primary.head(location).await
+    }
+
+    async fn delete(&self, location: &Path) -> object_store::Result<()> {
+        self.invalidate(location).await?;
+        self.primary.delete(location).await
+    }
+
+    // async-trait names the lifetime of `self`, which the lint sees as inconsistent.
+    #[allow(mismatched_lifetime_syntaxes)]
+    async fn list(
+        &self,
+        prefix: Option<&Path>,
+    ) -> object_store::Result<BoxStream<'_, object_store::Result<ObjectMeta>>> {
+        self.primary.list(prefix).await
+    }
+
+    async fn list_with_delimiter(&self, prefix: Option<&Path>) -> object_store::Result<ListResult> {
+        self.primary.list_with_delimiter(prefix).await
+    }
+
+    async fn copy(&self, from: &Path, to: &Path) -> object_store::Result<()> {
+        self.invalidate(to).await?;
+        self.primary.copy(from, to).await
+    }
+
+    async fn copy_if_not_exists(&self, from: &Path, to: &Path) -> object_store::Result<()> {
+        self.primary.copy_if_not_exists(from, to).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use object_store::memory::InMemory;
+    use tempfile::tempdir;
+
+    use super::*;
+
+    /// Counts the reads that reach the wrapped store, and the bytes they read.
+    #[derive(Debug, Default)]
+    struct CountingObjectStore {
+        inner: InMemory,
+        reads: AtomicUsize,
+        bytes_read: AtomicUsize,
+    }
+
+    impl CountingObjectStore {
+        fn reads(&self) -> usize {
+            self.reads.load(Ordering::Relaxed)
+        }
+
+        fn bytes_read(&self) -> usize {
+            self.bytes_read.load(Ordering::Relaxed)
+        }
+    }
+
+    impl Display for CountingObjectStore {
+        fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+            write!(f, "Counting({})", self.inner)
+        }
+    }
+
+    #[async_trait]
+    impl OSObjectStore for CountingObjectStore {
+        async fn put(&self, location: &Path, bytes: Bytes) -> object_store::Result<()> {
+            self.inner.put(location, bytes).await
+        }
+
+        async fn put_multipart(
+            &self,
+            location: &Path,
+        ) -> object_store::Result<(MultipartId, Box<dyn AsyncWrite + Unpin + Send>)> {
+            self.inner.put_multipart(location).await
+        }
+
+        async fn abort_multipart(
+            &self,
+            location: &Path,
+            multipart_id: &MultipartId,
+        ) -> object_store::Result<()> {
+            self.inner.abort_multipart(location, multipart_id).await
+        }
+
+        async fn get_opts(
+            &self,
+            location: &Path,
+            options: GetOptions,
+        ) -> object_store::Result<GetResult> {
+            self.reads.fetch_add(1, Ordering::Relaxed);
+            let size = match &options.range {
+                Some(range) => range.len(),
+                None => self.inner.head(location).await?.size,
+            };
+            self.bytes_read.fetch_add(size, Ordering::Relaxed);
+            self.inner.get_opts(location, options).await
+        }
+
+        async fn get_range(
+            &self,
+            location: &Path,
+            range: Range<usize>,
+        ) -> object_store::Result<Bytes> {
+            self.reads.fetch_add(1, Ordering::Relaxed);
+            self.bytes_read.fetch_add(range.len(), Ordering::Relaxed);
+            self.inner.get_range(location, range).await
+        }
+
+        async fn head(&self, location: &Path) -> object_store::Result<ObjectMeta> {
+            self.inner.head(location).await
+        }
+
+        async fn delete(&self, location: &Path) -> object_store::Result<()> {
+            self.inner.delete(location).await
+        }
+
+        #[allow(mismatched_lifetime_syntaxes)]
+        async fn list(
+            &self,
+            prefix: Option<&Path>,
+        ) -> object_store::Result<BoxStream<'_, object_store::Result<ObjectMeta>>> {
+            self.inner.list(prefix).await
+        }
+
+        async fn list_with_delimiter(
+            &self,
+            prefix: Option<&Path>,
+        ) -> object_store::Result<ListResult> {
+            self.inner.list_with_delimiter(prefix).await
+        }
+
+        async fn copy(&self, from: &Path, to: &Path) -> object_store::Result<()> {
+            self.inner.copy(from, to).await
+        }
+
+        async fn copy_if_not_exists(&self, from: &Path, to: &Path) -> object_store::Result<()> {
+            self.inner.copy_if_not_exists(from, to).await
+        }
+    }
+
+    fn mirrored(
+        primary: Arc<CountingObjectStore>,
+        capacity_bytes: usize,
+    ) -> (tempfile::TempDir, Arc<dyn OSObjectStore>) {
+        let tmp_dir = tempdir().unwrap();
+        let cache = Arc::new(MirrorCache::try_new(tmp_dir.path(), capacity_bytes).unwrap());
+        let store = MirrorWrapper::new(cache, "s3/bucket".to_string(), None).wrap(primary);
+        (tmp_dir, store)
+    }
+
+    #[tokio::test]
+    async fn test_second_read_hits_cache() {
+        let primary = Arc::new(CountingObjectStore::default());
+        let (_tmp_dir, store) = mirrored(primary.clone(), 1024);
+
+        let data = Path::from("db/t.lance/data/0.lance");
+        store
+            .put(&data, Bytes::from_static(b"0123456789"))
+            .await
+            .unwrap();
+        assert_eq!(store.get_range(&data, 2..5).await.unwrap(), "234");
+        assert_eq!(store.get_range(&data, 5..8).await.unwrap(), "567");
+        let all = store.get(&data).await.unwrap().bytes().await.unwrap();
+        assert_eq!(all, "0123456789");
+        assert_eq!(primary.reads(), 1);
+
+        let manifest = Path::from("db/t.lance/_latest.manifest");
+        store
+            .put(&manifest, Bytes::from_static(b"v1"))
+            .await
+            .unwrap();
+        store.get(&manifest).await.unwrap();
+        store
+            .put(&manifest, Bytes::from_static(b"v2"))
+            .await
+            .unwrap();
+        let latest = store.get(&manifest).await.unwrap().bytes().await.unwrap();
+        assert_eq!(latest, "v2");
+        assert_eq!(primary.reads(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_lru_budget() {
+        let primary = Arc::new(CountingObjectStore::default());
+        let (_tmp_dir, store) = mirrored(primary.clone(), 20);
+
+        let paths: Vec<_> = (0..3)
+            .map(|i| Path::from(format!("t.lance/data/{i}.lance")))
+            .collect();
+        for path in &paths {
+            store.put(path, Bytes::from(vec![0u8; 10])).await.unwrap();
+            store.get_range(path, 0..1).await.unwrap();
+        }
+        assert_eq!(primary.reads(), 3);
+
+        // The first file was evicted to make room for the third.
+        store.get_range(&paths[2], 0..1).await.unwrap();
+        assert_eq!(primary.reads(), 3);
+        store.get_range(&paths[0], 0..1).await.unwrap();
+        assert_eq!(primary.reads(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_file_larger_than_budget() {
+        let primary = Arc::new(CountingObjectStore::default());
+        let (tmp_dir, store) = mirrored(primary.clone(), 8);
+
+        let data = Path::from("t.lance/data/0.lance");
+        store
+            .put(&data, Bytes::from_static(b"0123456789"))
+            .await
+            .unwrap();
+        // Each range read fetches its range only, and nothing is cached.
+        assert_eq!(store.get_range(&data, 2..5).await.unwrap(), "234");
+        assert_eq!(store.get_range(&data, 5..8).await.unwrap(), "567");
+        assert_eq!((primary.reads(), primary.bytes_read()), (2, 6));
+        let all = store.get(&data).await.unwrap().bytes().await.unwrap();
+        assert_eq!(all, "0123456789");
+        assert_eq!((primary.reads(), primary.bytes_read()), (3, 16));
+        assert!(!tmp_dir
+            .path()
+            .join("s3/bucket")
+            .join(data.as_ref())
+            .exists());
+    }
+
+    #[test]
+    fn test_cache_scope() {
+        let options = HashMap::from([(
+            "aws_endpoint".to_string(),
+            "http://localhost:9000".to_string(),
+        )]);
+        assert_eq!(cache_scope("s3://bucket/db", &HashMap::new()), "s3/bucket");
+        assert_eq!(
+            cache_scope("s3://bucket/db", &options),
+            "s3/http___localhost_9000/bucket"
+        );
+        assert_eq!(
+            cache_scope("gs://other/db", &options),
+            "gs/http___localhost_9000/other"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_stores_cached_apart() {
+        let tmp_dir = tempdir().unwrap();
+        let cache = Arc::new(MirrorCache::try_new(tmp_dir.path(), 1024).unwrap());
+        let path = Path::from("db/t.lance/data/0.lance");
+        let mut stores = vec![];
+        for (scope, contents) in [("s3/a", "from a"), ("s3/b", "from b")] {
+            let primary = Arc::new(CountingObjectStore::default());
+            let wrapper = MirrorWrapper::new(cache.clone(), scope.to_string(), None);
+            let store = wrapper.wrap(primary.clone());
+            store.put(&path, Bytes::from(contents)).await.unwrap();
+            stores.push((store, primary, contents));
+        }
+        for (store, primary, contents) in &stores {
+            for _ in 0..2 {
+                let bytes = store.get(&path).await.unwrap().bytes().await.unwrap();
+                assert_eq!(bytes, *contents);
+            }
+            assert_eq!(primary.reads(), 1);
+        }
+        assert!(tmp_dir.path().join("s3/a").join(path.as_ref()).exists());
+    }
+
+    #[tokio::test]
+    async fn test_invalid_copy_read_again() {
+        let primary = Arc::new(CountingObjectStore::default());
+        let (tmp_dir, store) = mirrored(primary.clone(), 1024);
+        let path = Path::from("t.lance/data/0.lance");
+        store
+            .put(&path, Bytes::from_static(b"0123456789"))
+            .await
+            .unwrap();
+        store.get_range(&path, 0..2).await.unwrap();
+
+        // A truncated copy is not served.
+        let local = tmp_dir.path().join("s3/bucket").join(path.as_ref());
+        std::fs::write(&local, b"0123").unwrap();
+        assert_eq!(store.get_range(&path, 6..9).await.unwrap(), "678");
+        assert_eq!(primary.reads(), 2);
+        assert_eq!(std::fs::read(&local).unwrap(), b"0123456789");
+
+        // A file written again is not served from its old copy.
+        store
+            .put(&path, Bytes::from_static(b"abcdefghij"))
+            .await
+            .unwrap();
+        assert_eq!(store.get_range(&path, 0..3).await.unwrap(), "abc");
+        assert_eq!(primary.reads(), 3);
+        let entries = std::fs::read_dir(local.parent().unwrap()).unwrap();
+        assert_eq!(entries.count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_reopen_keeps_files() {
+        let tmp_dir = tempdir().unwrap();
+        let path = Path::from("t.lance/data/0.lance");
+        let cache = MirrorCache::try_new(tmp_dir.path(), 1024).unwrap();
+        cache.insert(&path, &Bytes::from_static(b"abc")).unwrap();
+        drop(cache);
+
+        let cache = MirrorCache::try_new(tmp_dir.path(), 1024).unwrap();
+        assert_eq!(cache.size_bytes(), 3);
+        assert_eq!(cache.read(&path, Some(1..3)).unwrap(), "bc");
+    }
+}
@@ -0,0 +1,174 @@
+// Copyright 2023 LanceDB Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The L2 norms of the vectors of the written rows, stored by the tables of
+//! [crate::NativeTable::store_vector_norms].
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use arrow_array::cast::as_primitive_array;
+use arrow_array::types::Float32Type;
+use arrow_array::{
+    Array, ArrayRef, FixedSizeListArray, Float32Array, RecordBatch, RecordBatchReader,
+};
+use arrow_schema::{ArrowError, DataType, Field, Schema, SchemaRef};
+use datafusion::arrow::compute::cast;
+
+/// The L2 norms of `vectors`, null for the null vectors.
+pub(crate) fn vector_norms(vectors: &FixedSizeListArray) -> Result<Float32Array, ArrowError> {
+    let values = cast(vectors.values(), &DataType::Float32)?;
+    let values = as_primitive_array::<Float32Type>(values.as_ref()).values();
+    let dims = vectors.value_length() as usize;
+    let offset = vectors.value_offset(0) as usize;
+    Ok((0..vectors.len())
+        .map(|row| {
+            let start = offset + row * dims;
+            let vector = &values[start..start + dims];
+            vectors
+                .is_valid(row)
+                .then(|| vector.iter().map(|v| v * v).sum::<f32>().sqrt())
+        })
+        .collect())
+}
+
+/// A reader of the batches of `inner` with the norms of their vector columns,
+/// `norms` mapping each vector column to its column of norms.
+///
+/// The column of norms is added if `inner` does not have it, and its values
+/// are computed again if it does, so that they always are the norms of the
+/// written vectors. The batches without a vector column are left as they are.
+pub(crate) fn with_norms(
+    inner: Box<dyn RecordBatchReader>,
+    norms: &HashMap<String, String>,
+) -> Box<dyn RecordBatchReader> {
+    let schema = inner.schema();
+    let mut fields = schema.fields().iter().cloned().collect::<Vec<_>>();
+    let mut columns = Vec::new();
+    for (column, norm_column) in norms {
+        let Some(vectors) = schema.index_of(column).ok() else {
+            continue;
+        };
+        let field = Arc::new(Field::new(norm_column, DataType::Float32, true));
+        let index = match schema.index_of(norm_column) {
+            Ok(index) => {
+                fields[index] = field;
+                index
+            }
+            Err(_) => {
+                fields.push(field);
+                fields.len() - 1
+            }
+        };
+        columns.push((vectors, index));
+    }
+    if columns.is_empty() {
+        return inner;
+    }
+    let schema = Arc::new(Schema::new_with_metadata(fields, schema.metadata().clone()));
+    Box::new(NormsReader {
+        inner,
+        schema,
+        columns,
+    })
+}
+
+struct NormsReader {
+    inner: Box<dyn RecordBatchReader>,
+    schema: SchemaRef,
+    /// The index of each vector column, and that of its norms in [Self::schema].
+    columns: Vec<(usize, usize)>,
+}
+
+impl NormsReader {
+    fn apply(&self, batch: RecordBatch) -> Result<RecordBatch, ArrowError> {
+        let mut arrays = batch.columns().to_vec();
+        for (vectors, norms) in &self.columns {
+            let vectors = arrays[*vectors]
+                .as_any()
+                .downcast_ref::<FixedSizeListArray>()
+                .ok_or_else(|| {
+                    ArrowError::InvalidArgumentError(format!(
+                        "the norms of '{}' are stored, it must be a vector column",
+                        batch.schema().field(*vectors).name()
+                    ))
+                })?;
+            let values: ArrayRef = Arc::new(vector_norms(vectors)?);
+            if *norms < arrays.len() {
+                arrays[*norms] = values;
+            } else {
+                arrays.push(values);
+            }
+        }
+        RecordBatch::try_new(self.schema.clone(), arrays)
+    }
+}
+
+impl Iterator for NormsReader {
+    type Item = Result<RecordBatch, ArrowError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let batch = match self.inner.next()? {
+            Ok(batch) => batch,
+            Err(e) => return Some(Err(e)),
+        };
+        Some(self.apply(batch))
+    }
+}
+
+impl RecordBatchReader for NormsReader {
+    fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lance::arrow::RecordBatchBuffer;
+
+    use super::*;
+    use crate::arrow::RecordBatchBuilder;
+
+    #[test]
+    fn test_with_norms() {
+        let batch = RecordBatchBuilder::new()
+            .vector_column("vector", 2, vec![[3.0, 4.0], [0.0, 2.0]])
+            .build()
+            .unwrap();
+        let norms = HashMap::from([("vector".to_string(), "_vector_norm".to_string())]);
+        let inner = RecordBatchBuffer::new(vec![batch]);
+        let mut reader = with_norms(Box::new(inner), &norms);
+        let batch = reader.next().unwrap().unwrap();
+        let values = batch["_vector_norm"]
+            .as_any()
+            .downcast_ref::<Float32Array>()
+            .unwrap();
+        assert_eq!(values.values(), &[5.0, 2.0]);
+
+        // Stale norms are computed again.
+        let stale = RecordBatchBuilder::new()
+            .column("_vector_norm", Arc::new(Float32Array::from(vec![1.0, 1.0])))
+            .vector_column("vector", 2, vec![[3.0, 4.0], [0.0, 2.0]])
+            .build()
+            .unwrap();
+        let mut reader = with_norms(Box::new(RecordBatchBuffer::new(vec![stale])), &norms);
+        let batch = reader.next().unwrap().unwrap();
+        assert_eq!(batch.num_columns(), 2);
+        let values = batch["_vector_norm"]
+            .as_any()
+            .downcast_ref::<Float32Array>()
+            .unwrap();
+        assert_eq!(values.values(), &[5.0, 2.0]);
+    }
+}
@@ -0,0 +1,218 @@
+// Copyright 2023 LanceDB Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use lance::io::object_store::WrappingObjectStore;
+use log::warn;
+use object_store::aws::{AmazonS3Builder, AmazonS3ConfigKey};
+use object_store::azure::{AzureConfigKey, MicrosoftAzureBuilder};
+use object_store::gcp::{GoogleCloudStorageBuilder, GoogleConfigKey};
+use object_store::memory::InMemory;
+use object_store::ObjectStore as OSObjectStore;
+
+use snafu::ResultExt;
+
+use crate::error::{Error, ObjectStoreSnafu, Result};
+
+/// Serves a pre-built object store in place of the one lance builds from the URI.
+///
+/// This is how explicit storage options reach lance, which otherwise only
+/// configures cloud stores from the environment. The tables write through it
+/// too, see [crate::table::NativeTable::add].
+pub(crate) struct ConfiguredObjectStore {
+    store: Arc<dyn OSObjectStore>,
+}
+
+impl ConfiguredObjectStore {
+    pub(crate) fn new(store: Arc<dyn OSObjectStore>) -> Self {
+        Self { store }
+    }
+}
+
+impl WrappingObjectStore for ConfiguredObjectStore {
+    fn wrap(&self, _original: Arc<dyn OSObjectStore>) -> Arc<dyn OSObjectStore> {
+        self.store.clone()
+    }
+}
+
+/// A new, empty in-memory store, served for every `memory://` URI opened
+/// through it.
+///
+/// lance builds another empty store each time it opens a `memory://` URI, so
+/// the datasets a connection writes would not be found again. The connection
+/// keeps one store for all of its tables instead, which lives as long as the
+/// connection and its tables.
+pub(crate) fn memory_object_store() -> ConfiguredObjectStore {
+    ConfiguredObjectStore::new(Arc::new(InMemory::new()))
+}
+
+/// Build an object store for `uri` configured with `options`.
+///
+/// Options are layered on top of the environment, so explicit values win over
+/// environment variables: each backend builder starts from the environment and
+/// is then given the options. Keys the backend does not recognize are logged, or
+/// rejected when `strict` is set.
+///
+/// # Returns
+///
+/// * `None` if no options were given, or the URI does not point to a cloud store.
+pub(crate) fn build_object_store(
+    uri: &str,
+    options: &HashMap<String, String>,
+    strict: bool,
+) -> Result<Option<ConfiguredObjectStore>> {
+    if options.is_empty() {
+        return Ok(None);
+    }
+    let store: Arc<dyn OSObjectStore> = match scheme(uri) {
+        Some("s3") => Arc::new(
+            s3_builder(AmazonS3Builder::from_env(), uri, options, strict)?
+                .build()
+                .context(ObjectStoreSnafu { uri })?,
+        ),
+        Some("gs") => Arc::new(
+            gcs_builder(GoogleCloudStorageBuilder::from_env(), uri, options, strict)?
+                .build()
+                .context(ObjectStoreSnafu { uri })?,
+        ),
+        Some("az") => Arc::new(
+            azure_builder(MicrosoftAzureBuilder::from_env(), uri, options, strict)?
+                .build()
+                .context(ObjectStoreSnafu { uri })?,
+        ),
+        _ => {
+            for key in options.keys() {
+                unknown_option(uri, key, strict)?;
+            }
+            return Ok(None);
+        }
+    };
+    Ok(Some(ConfiguredObjectStore::new(store)))
+}
+
+fn scheme(uri: &str) -> Option<&str> {
+    uri.split_once("://").map(|(scheme, _)| scheme)
+}
+
+fn unknown_option(uri: &str, key: &str, strict: bool) -> Result<()> {
+    if strict {
+        return Err(Error::UnknownStorageOption {
+            key: key.to_string(),
+            uri: uri.to_string(),
+        });
+    }
+    warn!("Ignoring unknown storage option '{key}' for {uri}");
+    Ok(())
+}
+
+fn s3_builder(
+    builder: AmazonS3Builder,
+    uri: &str,
+    options: &HashMap<String, String>,
+    strict: bool,
+) -> Result<AmazonS3Builder> {
+    let mut builder = builder.with_url(uri);
+    for (key, value) in options {
+        match key.to_ascii_lowercase().parse::<AmazonS3ConfigKey>() {
+            Ok(config_key) => builder = builder.with_config(config_key, value),
+            Err(_) => unknown_option(uri, key, strict)?,
+        }
+    }
+    Ok(builder)
+}
+
+fn gcs_builder(
+    builder: GoogleCloudStorageBuilder,
+    uri: &str,
+    options: &HashMap<String, String>,
+    strict: bool,
+) -> Result<GoogleCloudStorageBuilder> {
+    let mut builder = builder.with_url(uri);
+    for (key, value) in options {
+        match key.to_ascii_lowercase().parse::<GoogleConfigKey>() {
+            Ok(config_key) => builder = builder.with_config(config_key, value),
+            Err(_) => unknown_option(uri, key, strict)?,
+        }
+    }
+    Ok(builder)
+}
+
+fn azure_builder(
+    builder: MicrosoftAzureBuilder,
+    uri: &str,
+    options: &HashMap<String, String>,
+    strict: bool,
+) -> Result<MicrosoftAzureBuilder> {
+    let mut builder = builder.with_url(uri);
+    for (key, value) in options {
+        match key.to_ascii_lowercase().parse::<AzureConfigKey>() {
+            Ok(config_key) => builder = builder.with_config(config_key, value),
+            Err(_) => unknown_option(uri, key, strict)?,
+        }
+    }
+    Ok(builder)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use object_store::aws::AmazonS3ConfigKey;
+
+    use super::*;
+
+    #[test]
+    fn test_explicit_options_override_env() {
+        // The builder as read from an environment with both variables set.
+        let env = AmazonS3Builder::new()
+            .with_config(AmazonS3ConfigKey::AccessKeyId, "from-env")
+            .with_config(AmazonS3ConfigKey::Token, "token-from-env");
+
+        let options = HashMap::from([("aws_access_key_id".to_string(), "explicit".to_string())]);
+        let builder = s3_builder(env, "s3://bucket/db", &options, true).unwrap();
+        assert_eq!(
+            builder.get_config_value(&AmazonS3ConfigKey::AccessKeyId),
+            Some("explicit".to_string())
+        );
+        assert_eq!(
+            builder.get_config_value(&AmazonS3ConfigKey::Token),
+            Some("token-from-env".to_string())
+        );
+    }
+
+    #[test]
+    fn test_unknown_option() {
+        let options = HashMap::from([("not_a_key".to_string(), "value".to_string())]);
+        let builder = AmazonS3Builder::new;
+        assert!(s3_builder(builder(), "s3://bucket/db", &options, false).is_ok());
+        assert!(matches!(
+            s3_builder(builder(), "s3://bucket/db", &options, true).unwrap_err(),
+            Error::UnknownStorageOption { .. }
+        ));
+        assert!(matches!(
+            build_object_store("/tmp/db", &options, true).err().unwrap(),
+            Error::UnknownStorageOption { .. }
+        ));
+    }
+
+    #[test]
+    fn test_no_options() {
+        let options = HashMap::new();
+        assert!(build_object_store("s3://bucket/db", &options, true)
+            .unwrap()
+            .is_none());
+    }
+}
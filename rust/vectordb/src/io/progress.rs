@@ -0,0 +1,167 @@
+// Copyright 2023 LanceDB Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Progress of the writes of [crate::table::NativeTable::add_with_progress].
+//!
+//! lance pulls the batches of a write one at a time and writes them to the data
+//! file of the current fragment, starting a new fragment after a batch brings
+//! the file to `max_rows_per_file` rows. The progress is reported as each batch
+//! is handed to lance, so it runs ahead of the data written by at most one row
+//! group.
+
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::{Arc, Mutex};
+
+use arrow_array::{Array, RecordBatch, RecordBatchReader};
+use arrow_schema::{ArrowError, SchemaRef};
+use tracing::warn;
+
+/// The rows of a write handed to lance so far.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WriteProgress {
+    /// The rows written.
+    pub rows: usize,
+    /// The in-memory size of the rows written, in bytes. The files are usually
+    /// smaller, lance encodes and compresses the columns.
+    pub bytes: usize,
+    /// The fragment of the write the last rows went to, counting from 0.
+    pub fragment: usize,
+}
+
+/// Called with the [WriteProgress] of a write after each batch.
+///
+/// The callback runs on the thread writing the table, so it should return
+/// quickly. It cannot stop the write: a panic of the callback is logged and
+/// the write goes on.
+pub type WriteProgressCallback = Arc<dyn Fn(WriteProgress) + Send + Sync>;
+
+/// A reader of the batches of `inner`, counting them as lance reads them and
+/// reporting the counts to `callback`.
+pub(crate) fn track_progress(
+    inner: Box<dyn RecordBatchReader>,
+    max_rows_per_file: usize,
+    callback: Option<WriteProgressCallback>,
+) -> (Box<dyn RecordBatchReader>, Arc<Mutex<WriteProgress>>) {
+    let progress = Arc::new(Mutex::new(WriteProgress::default()));
+    let reader = ProgressReader {
+        inner,
+        max_rows_per_file: max_rows_per_file.max(1),
+        callback,
+        progress: progress.clone(),
+        file_rows: 0,
+    };
+    (Box::new(reader), progress)
+}
+
+struct ProgressReader {
+    inner: Box<dyn RecordBatchReader>,
+    max_rows_per_file: usize,
+    callback: Option<WriteProgressCallback>,
+    progress: Arc<Mutex<WriteProgress>>,
+    /// The rows in the file of the current fragment.
+    file_rows: usize,
+}
+
+/// The memory of the rows of `batch`, without the rest of the buffers of a
+/// sliced batch.
+fn batch_bytes(batch: &RecordBatch) -> usize {
+    batch
+        .columns()
+        .iter()
+        .map(|c| {
+            c.to_data()
+                .get_slice_memory_size()
+                .unwrap_or_else(|_| c.get_array_memory_size())
+        })
+        .sum()
+}
+
+impl Iterator for ProgressReader {
+    type Item = Result<RecordBatch, ArrowError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let batch = self.inner.next();
+        if let Some(Ok(batch)) = batch.as_ref() {
+            let progress = {
+                let mut progress = self.progress.lock().unwrap();
+                if self.file_rows >= self.max_rows_per_file {
+                    progress.fragment += 1;
+                    self.file_rows = 0;
+                }
+                self.file_rows += batch.num_rows();
+                progress.rows += batch.num_rows();
+                progress.bytes += batch_bytes(batch);
+                *progress
+            };
+            if let Some(callback) = self.callback.as_ref() {
+                if catch_unwind(AssertUnwindSafe(|| callback(progress))).is_err() {
+                    warn!(rows = progress.rows, "the write progress callback panicked");
+                }
+            }
+        }
+        batch
+    }
+}
+
+impl RecordBatchReader for ProgressReader {
+    fn schema(&self) -> SchemaRef {
+        self.inner.schema()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow_array::Int32Array;
+    use arrow_schema::{DataType, Field, Schema};
+    use lance::arrow::RecordBatchBuffer;
+
+    use super::*;
+
+    #[test]
+    fn test_track_progress() {
+        let schema = Arc::new(Schema::new(vec![Field::new("id", DataType::Int32, false)]));
+        let batch =
+            RecordBatch::try_new(schema, vec![Arc::new(Int32Array::from_iter_values(0..100))])
+                .unwrap();
+        // Slices count the bytes of their rows only.
+        let batches = vec![batch.slice(0, 50), batch.slice(50, 30), batch.slice(80, 20)];
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let callback: WriteProgressCallback = {
+            let reports = reports.clone();
+            Arc::new(move |progress| {
+                reports.lock().unwrap().push(progress);
+                if progress.rows == 80 {
+                    panic!("the callback fails");
+                }
+            })
+        };
+        let (reader, progress) = track_progress(
+            Box::new(RecordBatchBuffer::new(batches)),
+            60,
+            Some(callback),
+        );
+        assert_eq!(reader.map(|b| b.unwrap().num_rows()).sum::<usize>(), 100);
+
+        let expected =
+            [(50, 200, 0), (80, 320, 0), (100, 400, 1)].map(|(rows, bytes, fragment)| {
+                WriteProgress {
+                    rows,
+                    bytes,
+                    fragment,
+                }
+            });
+        assert_eq!(*reports.lock().unwrap(), expected);
+        assert_eq!(*progress.lock().unwrap(), expected[2]);
+    }
+}
@@ -0,0 +1,176 @@
+// Copyright 2023 LanceDB Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Splitting of the oversized batches of a write, see [WriteOptions].
+
+use arrow_array::{RecordBatch, RecordBatchReader};
+use arrow_schema::{ArrowError, SchemaRef};
+
+use crate::error::Result;
+use crate::table::WriteOptions;
+
+/// A reader of the batches of `inner` sliced to the limits of `options`.
+///
+/// Slices share the buffers of the batch they are cut from, so splitting does
+/// not copy rows; it keeps lance from encoding a whole oversized batch at once.
+pub(crate) fn split_batches(
+    inner: Box<dyn RecordBatchReader>,
+    options: &WriteOptions,
+) -> Result<Box<dyn RecordBatchReader>> {
+    options.validate()?;
+    if options.max_bytes_per_batch.is_none() && options.max_rows_per_batch.is_none() {
+        return Ok(inner);
+    }
+    Ok(Box::new(SplitReader {
+        inner,
+        options: options.clone(),
+        pending: None,
+    }))
+}
+
+struct SplitReader {
+    inner: Box<dyn RecordBatchReader>,
+    options: WriteOptions,
+    /// The batch being split, the offset of its next chunk and the rows per chunk.
+    pending: Option<(RecordBatch, usize, usize)>,
+}
+
+impl SplitReader {
+    /// How many rows of `batch` fit in a chunk, at least one.
+    fn chunk_rows(&self, batch: &RecordBatch) -> usize {
+        let rows = batch.num_rows().max(1);
+        let by_bytes = self.options.max_bytes_per_batch.map(|max_bytes| {
+            let row_bytes = batch.get_array_memory_size().div_ceil(rows).max(1);
+            (max_bytes / row_bytes).max(1)
+        });
+        let by_rows = self.options.max_rows_per_batch;
+        by_bytes.into_iter().chain(by_rows).min().unwrap_or(rows)
+    }
+}
+
+impl Iterator for SplitReader {
+    type Item = std::result::Result<RecordBatch, ArrowError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pending.is_none() {
+            let batch = match self.inner.next()? {
+                Ok(batch) => batch,
+                Err(e) => return Some(Err(e)),
+            };
+            let chunk_rows = self.chunk_rows(&batch);
+            if batch.num_rows() <= chunk_rows {
+                return Some(Ok(batch));
+            }
+            self.pending = Some((batch, 0, chunk_rows));
+        }
+        let (batch, offset, chunk_rows) = self.pending.as_mut().unwrap();
+        let length = (*chunk_rows).min(batch.num_rows() - *offset);
+        let chunk = batch.slice(*offset, length);
+        *offset += length;
+        if *offset == batch.num_rows() {
+            self.pending = None;
+        }
+        Some(Ok(chunk))
+    }
+}
+
+impl RecordBatchReader for SplitReader {
+    fn schema(&self) -> SchemaRef {
+        self.inner.schema()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_array::{Array, Int32Array};
+    use arrow_schema::{DataType, Field, Schema};
+    use lance::arrow::RecordBatchBuffer;
+
+    use super::*;
+    use crate::error::Error;
+
+    fn batch(values: std::ops::Range<i32>) -> RecordBatch {
+        let schema = Arc::new(Schema::new(vec![Field::new("i", DataType::Int32, false)]));
+        RecordBatch::try_new(schema, vec![Arc::new(Int32Array::from_iter_values(values))]).unwrap()
+    }
+
+    fn split(batches: Vec<RecordBatch>, options: WriteOptions) -> Vec<RecordBatch> {
+        let reader: Box<dyn RecordBatchReader> = Box::new(RecordBatchBuffer::new(batches));
+        split_batches(reader, &options)
+            .unwrap()
+            .collect::<std::result::Result<_, _>>()
+            .unwrap()
+    }
+
+    fn values(batches: &[RecordBatch]) -> Vec<i32> {
+        batches
+            .iter()
+            .flat_map(|b| {
+                let column = b.column(0).as_any().downcast_ref::<Int32Array>().unwrap();
+                column.values().to_vec()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_split_by_rows() {
+        let options = WriteOptions {
+            max_bytes_per_batch: None,
+            max_rows_per_batch: Some(300),
+            ..Default::default()
+        };
+        let chunks = split(vec![batch(0..1000), batch(1000..1100)], options);
+        let rows = chunks.iter().map(RecordBatch::num_rows).collect::<Vec<_>>();
+        assert_eq!(rows, vec![300, 300, 300, 100, 100]);
+        assert_eq!(values(&chunks), (0..1100).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_split_by_bytes() {
+        let big = batch(0..10_000);
+        let row_bytes = big.get_array_memory_size().div_ceil(10_000);
+        let options = WriteOptions {
+            max_bytes_per_batch: Some(row_bytes * 1000),
+            max_rows_per_batch: None,
+            ..Default::default()
+        };
+        let chunks = split(vec![big], options);
+        assert_eq!(chunks.len(), 10);
+        assert_eq!(values(&chunks), (0..10_000).collect::<Vec<_>>());
+
+        // A row larger than the limit is still written, on its own.
+        let options = WriteOptions {
+            max_bytes_per_batch: Some(1),
+            max_rows_per_batch: None,
+            ..Default::default()
+        };
+        assert_eq!(split(vec![batch(0..3)], options).len(), 3);
+    }
+
+    #[test]
+    fn test_split_invalid() {
+        let reader: Box<dyn RecordBatchReader> = Box::new(RecordBatchBuffer::new(vec![]));
+        let options = WriteOptions {
+            max_bytes_per_batch: None,
+            max_rows_per_batch: Some(0),
+            ..Default::default()
+        };
+        assert!(matches!(
+            split_batches(reader, &options).err().unwrap(),
+            Error::InvalidInput { .. }
+        ));
+    }
+}
@@ -0,0 +1,194 @@
+// Copyright 2023 Lance Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::path::Path;
+
+use url::Url;
+
+use crate::error::{Error, Result};
+
+/// URI schemes a database can be opened on.
+const SUPPORTED_SCHEMES: &[&str] = &["file", "memory", "s3", "gs", "az", "db"];
+
+/// A database location, as resolved from the URI given to connect.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum DatabaseUri {
+    /// An absolute path on the local file system.
+    Local(String),
+    /// A URI handled by an object store, e.g. `s3://bucket/path` or `memory://`.
+    ObjectStore(String),
+    /// A database hosted by a LanceDB server, `db://<name>`.
+    Remote { uri: String, db_name: String },
+}
+
+impl DatabaseUri {
+    /// Parse and normalize a connection URI.
+    ///
+    /// Local paths and `file://` URIs are made absolute, and the scheme of other
+    /// URIs is lower-cased. Unsupported schemes are rejected.
+    pub(crate) fn parse(uri: &str) -> Result<Self> {
+        let trimmed = uri.trim();
+        if trimmed.is_empty() {
+            return Err(invalid_uri(uri, "the URI is empty"));
+        }
+        match Url::parse(trimmed) {
+            // A Windows drive letter parses as a single letter scheme.
+            Ok(url) if url.scheme().len() == 1 && cfg!(windows) => local_path(trimmed),
+            Ok(mut url) => {
+                if !SUPPORTED_SCHEMES.contains(&url.scheme()) {
+                    return Err(invalid_uri(
+                        trimmed,
+                        &format!(
+                            "unsupported scheme '{}', expected one of {}",
+                            url.scheme(),
+                            SUPPORTED_SCHEMES.join(", ")
+                        ),
+                    ));
+                }
+                if url.scheme() == "file" {
+                    let path = url
+                        .to_file_path()
+                        .map_err(|_| invalid_uri(trimmed, "not a valid file path"))?;
+                    return local_path(&path.to_string_lossy());
+                }
+                if url.scheme() == "db" {
+                    let db_name = url.host_str().unwrap_or_default();
+                    if db_name.is_empty() || !matches!(url.path(), "" | "/") {
+                        return Err(invalid_uri(trimmed, "expected db://<database name>"));
+                    }
+                    return Ok(Self::Remote {
+                        uri: format!("db://{db_name}"),
+                        db_name: db_name.to_string(),
+                    });
+                }
+                if url.scheme() != "memory" && url.host_str().unwrap_or_default().is_empty() {
+                    return Err(invalid_uri(trimmed, "missing bucket name"));
+                }
+                let path = url.path().trim_end_matches('/').to_string();
+                url.set_path(&path);
+                Ok(Self::ObjectStore(url.to_string()))
+            }
+            Err(_) => local_path(trimmed),
+        }
+    }
+
+    /// Whether this is the root of a bucket or of the local file system, which
+    /// is likely shared with data that does not belong to the database.
+    pub(crate) fn is_storage_root(&self) -> bool {
+        match self {
+            Self::Local(path) => Path::new(path).parent().is_none(),
+            Self::ObjectStore(uri) => Url::parse(uri)
+                .is_ok_and(|url| url.scheme() != "memory" && matches!(url.path(), "" | "/")),
+            Self::Remote { .. } => false,
+        }
+    }
+
+    pub(crate) fn as_str(&self) -> &str {
+        match self {
+            Self::Local(path) => path,
+            Self::ObjectStore(uri) => uri,
+            Self::Remote { uri, .. } => uri,
+        }
+    }
+}
+
+fn local_path(path: &str) -> Result<DatabaseUri> {
+    let path = Path::new(path);
+    let absolute = if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        std::env::current_dir()
+            .map_err(|e| invalid_uri(&path.to_string_lossy(), &e.to_string()))?
+            .join(path)
+    };
+    Ok(DatabaseUri::Local(absolute.to_string_lossy().to_string()))
+}
+
+fn invalid_uri(uri: &str, reason: &str) -> Error {
+    Error::InvalidUri {
+        uri: uri.to_string(),
+        reason: reason.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_local() {
+        assert_eq!(
+            DatabaseUri::parse("/tmp/db").unwrap(),
+            DatabaseUri::Local("/tmp/db".to_string())
+        );
+        assert_eq!(
+            DatabaseUri::parse("file:///tmp/db").unwrap(),
+            DatabaseUri::Local("/tmp/db".to_string())
+        );
+        let cwd = std::env::current_dir().unwrap();
+        assert_eq!(
+            DatabaseUri::parse("data/db").unwrap(),
+            DatabaseUri::Local(cwd.join("data/db").to_string_lossy().to_string())
+        );
+    }
+
+    #[test]
+    fn test_parse_object_store() {
+        assert_eq!(
+            DatabaseUri::parse("S3://bucket/db/").unwrap(),
+            DatabaseUri::ObjectStore("s3://bucket/db".to_string())
+        );
+        assert_eq!(
+            DatabaseUri::parse("memory://").unwrap().as_str(),
+            "memory://"
+        );
+    }
+
+    #[test]
+    fn test_is_storage_root() {
+        for uri in ["/", "file:///", "s3://bucket", "gs://bucket/"] {
+            assert!(DatabaseUri::parse(uri).unwrap().is_storage_root(), "{uri}");
+        }
+        for uri in ["/tmp/db", "s3://bucket/db", "memory://"] {
+            assert!(!DatabaseUri::parse(uri).unwrap().is_storage_root(), "{uri}");
+        }
+    }
+
+    #[test]
+    fn test_parse_remote() {
+        assert_eq!(
+            DatabaseUri::parse("db://my-project/").unwrap(),
+            DatabaseUri::Remote {
+                uri: "db://my-project".to_string(),
+                db_name: "my-project".to_string(),
+            }
+        );
+        assert!(matches!(
+            DatabaseUri::parse("db://my-project/tables").unwrap_err(),
+            Error::InvalidUri { .. }
+        ));
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        for uri in ["", "s4://bucket/db", "s3:///db"] {
+            assert!(matches!(
+                DatabaseUri::parse(uri).unwrap_err(),
+                Error::InvalidUri { .. }
+            ));
+        }
+        let err = DatabaseUri::parse("s4://bucket/db").unwrap_err();
+        assert!(err.to_string().contains("s4://bucket/db"));
+    }
+}
@@ -12,10 +12,46 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+pub mod arrow;
+pub mod blocking;
+pub mod cache;
 pub mod database;
+pub mod embeddings;
 pub mod error;
 pub mod index;
+pub mod ingest;
+pub mod io;
+pub mod prelude;
 pub mod query;
+#[cfg(feature = "remote")]
+pub mod remote;
+mod spans;
 pub mod table;
+#[cfg(all(test, any(feature = "remote", feature = "openai")))]
+mod test_util;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod testing;
 
-pub use database::Database;
+// The crates of the arrow and lance types in the public API, see [prelude].
+pub use arrow_array;
+pub use arrow_schema;
+pub use lance;
+
+pub use database::{connect, ConnectBuilder, CreateTableMode, Database};
+pub use error::{Error, Result};
+pub use query::{
+    FilterExpr, FilterMode, FullTextQuery, JsonPathColumn, MaterializeBuilder, MultiVectorScoring,
+    PreparedQuery, Query, QueryExecutor, QueryMetrics, QueryRow, QueryVector, ScanParams,
+    SearchRequest, SlowQueryCallback, SlowQueryEvent, SlowQueryHook, TwoStage,
+    DEFAULT_DISTINCT_OVERFETCH, DEFAULT_PREFILTER_THRESHOLD, DEFAULT_QUERY_LIMIT, FTS_SCORE_COLUMN,
+    IVF_PARTITION_COLUMN, JSON_EXTRACT, MAX_SCAN_PARALLELISM,
+};
+pub use table::{
+    AddReport, Checkpoint, ColumnCast, ColumnStats, Constraint, DeleteReport, DeleteStats,
+    DuplicatePair, Encoding, FragmentInfo, HealthCheck, HealthCheckKind, HealthReport,
+    IndexEvaluation, IndexHealth, IndexPartitionStats, IndexStats, IterRowsBuilder,
+    MaintenanceConfig, MaintenanceHandle, NativeTable, OnBadVectors, OnConstraintViolation,
+    OptimizationStats, PartitionStats, ProbedPartition, RejectedRow, RetentionPolicy, SchemaDelta,
+    ScopedTable, SearchEvaluation, SearchParams, SimilarityJoinOptions, Table, TableLike, TableRef,
+    TableStats, VectorStats, WriteOptions,
+};
@@ -0,0 +1,186 @@
+// Copyright 2023 LanceDB Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The types needed to call the API of this crate, including the arrow and lance
+//! types in its signatures, so that applications need no other dependency.
+//!
+//! Arrays other than the ones below are in the re-exported [crate::arrow_array].
+//!
+//! ```
+//! use vectordb::prelude::*;
+//!
+//! async fn example(uri: &str) -> Result<Vec<RecordBatch>> {
+//!     let db = connect(uri).execute().await?;
+//!     let batch = RecordBatchBuilder::new()
+//!         .column("id", Arc::new(Int32Array::from(vec![1, 2])))
+//!         .vector_column("vector", 2, vec![[1.0, 2.0], [3.0, 4.0]])
+//!         .build()?;
+//!     let schema = batch.schema();
+//!     let reader = RecordBatchIterator::new(vec![Ok(batch)], schema);
+//!     let table = db
+//!         .create_table("items", Box::new(reader))
+//!         .write_params(WriteParams {
+//!             mode: WriteMode::Create,
+//!             ..Default::default()
+//!         })
+//!         .execute()
+//!         .await?;
+//!     table
+//!         .search(QueryVector::from(vec![1.0, 2.0]))
+//!         .metric_type(Some(MetricType::Cosine))
+//!         .filter_expr(col("id").gt(lit(1)))
+//!         .execute()
+//!         .await?
+//!         .try_collect()
+//!         .await
+//!         .map_err(Error::from)
+//! }
+//! # fn main() {}
+//! ```
+
+pub use std::sync::Arc;
+
+pub use arrow_array::{
+    Array, ArrayRef, FixedSizeListArray, Float32Array, Int32Array, Int64Array, RecordBatch,
+    RecordBatchIterator, RecordBatchReader, StringArray,
+};
+pub use arrow_schema::{DataType, Field, Schema, SchemaRef};
+pub use async_trait::async_trait;
+pub use chrono::{DateTime, Utc};
+pub use datafusion::scalar::ScalarValue;
+pub use futures::{StreamExt, TryStreamExt};
+pub use half::f16;
+pub use lance::dataset::scanner::DatasetRecordBatchStream;
+pub use lance::dataset::{ReadParams, Version, WriteMode, WriteParams};
+pub use lance::index::vector::ivf::IvfBuildParams;
+pub use lance::index::vector::pq::PQBuildParams;
+pub use lance::index::vector::{MetricType, VectorIndexParams};
+pub use lance::io::object_store::{ObjectStoreParams, WrappingObjectStore};
+pub use lance::io::RecordBatchStream;
+
+pub use crate::arrow::{
+    batch_to_rows, json_array, parse_json, vec_to_fixed_size_list, IntoArrow, RecordBatchBuilder,
+    VectorLike,
+};
+pub use crate::cache::{CacheConfig, CacheStats, QueryCacheStats};
+pub use crate::database::{
+    connect, ConnectBuilder, CreateTableBuilder, CreateTableMode, Database, RollbackFailure,
+    TableHandle, Transaction,
+};
+pub use crate::embeddings::{EmbeddingDefinition, EmbeddingFunction, EmbeddingRegistry};
+pub use crate::error::{Error, Result};
+pub use crate::index::vector::{IvfPQIndexBuilder, VectorIndexBuilder};
+#[cfg(feature = "ipc")]
+pub use crate::io::ipc::{ExportIpcParams, IpcCompression};
+pub use crate::io::progress::{WriteProgress, WriteProgressCallback};
+pub use crate::query::{
+    col, lit, FilterExpr, FilterMode, FullTextQuery, JsonPathColumn, Literal, MaterializeBuilder,
+    MultiVectorScoring, PreparedQuery, Query, QueryExecutor, QueryMetrics, QueryRow, QueryVector,
+    ScanParams, SearchRequest, SlowQueryCallback, SlowQueryEvent, SlowQueryHook, TwoStage,
+};
+pub use crate::table::{
+    AddReport, ColumnCast, ColumnStats, Constraint, DeleteReport, DuplicatePair, Encoding,
+    FragmentInfo, HealthCheck, HealthCheckKind, HealthReport, IndexEvaluation, IndexHealth,
+    IndexPartitionStats, IndexStats, IterRowsBuilder, MaintenanceConfig, MaintenanceHandle,
+    NativeTable, OnBadVectors, OnConstraintViolation, OpenTableParams, OptimizationStats,
+    PartitionStats, ProbedPartition, RejectedRow, RetentionPolicy, SchemaDelta, ScopedTable,
+    SearchEvaluation, SearchParams, SimilarityJoinOptions, Table, TableLike, TableRef, TableStats,
+    VectorStats, WriteOptions,
+};
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    // Only the prelude, the calls below must not need any other import.
+    use super::*;
+
+    #[derive(Debug)]
+    struct Constant;
+
+    #[async_trait]
+    impl EmbeddingFunction for Constant {
+        fn name(&self) -> String {
+            "constant".to_string()
+        }
+
+        fn source_type(&self) -> DataType {
+            DataType::Utf8
+        }
+
+        fn dims(&self) -> usize {
+            2
+        }
+
+        async fn embed(&self, input: ArrayRef) -> Result<ArrayRef> {
+            let vectors = vec![[1.0, 0.0]; input.len()];
+            Ok(Arc::new(vec_to_fixed_size_list(2, vectors)?))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_prelude() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        let db: Database = connect(uri).execute().await.unwrap();
+
+        let batch = RecordBatchBuilder::new()
+            .column("id", Arc::new(Int32Array::from_iter_values(0..4)))
+            .vector_column("vector", 2, (0..4).map(|i| [i as f32, 1.0]))
+            .build()
+            .unwrap();
+        let schema: SchemaRef = batch.schema();
+        let reader: Box<dyn RecordBatchReader> =
+            Box::new(RecordBatchIterator::new(vec![Ok(batch)], schema.clone()));
+        let table: TableRef = db
+            .create_table("items", reader)
+            .mode(CreateTableMode::Create)
+            .execute()
+            .await
+            .unwrap();
+        assert_eq!(table.schema().await.unwrap(), schema);
+
+        let stream: DatasetRecordBatchStream = table
+            .search(QueryVector::from(vec![2.0, 1.0]))
+            .metric_type(Some(MetricType::L2))
+            .filter_expr(col("id").lt_eq(lit(2)))
+            .limit(1)
+            .execute()
+            .await
+            .unwrap();
+        assert_eq!(
+            stream.schema().field(0),
+            &Field::new("id", DataType::Int32, true)
+        );
+        let batches: Vec<RecordBatch> = stream.try_collect().await.unwrap();
+        let ids = batches[0]["id"].as_any().downcast_ref::<Int32Array>();
+        assert_eq!(ids.unwrap().values(), &[2]);
+
+        let builder = IvfPQIndexBuilder::new()
+            .column("vector")
+            .metric_type(MetricType::Cosine)
+            .ivf_params(IvfBuildParams::new(1))
+            .pq_params(PQBuildParams::default());
+        let _: VectorIndexParams = builder.build();
+        let _ = WriteParams {
+            mode: WriteMode::Append,
+            ..Default::default()
+        };
+        let vectors = Constant
+            .embed(Arc::new(StringArray::from(vec!["a"])))
+            .await
+            .unwrap();
+        assert_eq!(vectors.len(), 1);
+    }
+}
@@ -12,26 +12,630 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::sync::Arc;
+use std::borrow::Cow;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-use arrow_array::Float32Array;
+use arrow_array::{Array, BooleanArray, FixedSizeListArray, Float32Array, RecordBatch};
+use arrow_schema::{DataType, Schema as ArrowSchema, SchemaRef};
+use async_trait::async_trait;
+use datafusion::arrow::compute::{cast, filter_record_batch};
+use datafusion::error::DataFusionError;
+use datafusion::physical_plan::stream::RecordBatchStreamAdapter;
+use futures::{StreamExt, TryStreamExt};
 use lance::dataset::scanner::{DatasetRecordBatchStream, Scanner};
 use lance::dataset::Dataset;
+use lance::format::Fragment;
 use lance::index::vector::MetricType;
+use lance::io::RecordBatchStream;
+use serde::Serialize;
+use tracing::{debug, field, info_span, warn, Level, Span};
 
-use crate::error::Result;
+use crate::cache::QueryCache;
+use crate::embeddings::EmbeddingDefinition;
+use crate::error::{Error, Result};
+use crate::io::bad_vectors::{mark_null_vectors, marker_column_of, null_vector_column};
+use crate::spans::timed;
+use crate::table::{DatasetRef, DEFAULT_SELECTIVITY_SAMPLE, VECTOR_COLUMN_NAME};
+
+mod cosine;
+mod distinct;
+mod expr;
+pub(crate) mod filter;
+mod finite;
+pub(crate) mod flat;
+mod fts;
+mod ivf_partition;
+mod json;
+mod materialize;
+mod nprobes;
+mod ordered;
+mod prepared;
+mod request;
+mod result_size;
+mod temporal;
+mod two_stage;
+
+pub use distinct::DEFAULT_DISTINCT_OVERFETCH;
+pub use expr::{col, lit, FilterExpr, Literal};
+pub use fts::{FullTextQuery, FTS_SCORE_COLUMN};
+pub use ivf_partition::IVF_PARTITION_COLUMN;
+use json::project_paths;
+pub(crate) use json::JsonFilter;
+pub use json::{JsonPathColumn, JSON_EXTRACT};
+pub use materialize::{MaterializeBuilder, DEFAULT_MATERIALIZE_ROWS_PER_WRITE};
+pub use prepared::PreparedQuery;
+pub use request::SearchRequest;
+pub use two_stage::TwoStage;
+
+/// What a nearest neighbor query searches for.
+#[derive(Debug, Clone, PartialEq)]
+pub enum QueryVector {
+    /// A vector of the same width as the searched column.
+    Vector(Float32Array),
+    /// A text, embedded with the embedding function of the searched column.
+    Text(String),
+}
+
+impl From<Float32Array> for QueryVector {
+    fn from(vector: Float32Array) -> Self {
+        Self::Vector(vector)
+    }
+}
+
+impl From<Vec<f32>> for QueryVector {
+    fn from(vector: Vec<f32>) -> Self {
+        Self::Vector(Float32Array::from(vector))
+    }
+}
+
+#[cfg(feature = "ndarray")]
+impl From<ndarray::ArrayView1<'_, f32>> for QueryVector {
+    fn from(vector: ndarray::ArrayView1<'_, f32>) -> Self {
+        Self::Vector(Float32Array::from_iter_values(vector.iter().copied()))
+    }
+}
+
+#[cfg(feature = "ndarray")]
+impl From<ndarray::Array1<f32>> for QueryVector {
+    fn from(vector: ndarray::Array1<f32>) -> Self {
+        vector.view().into()
+    }
+}
+
+impl From<&str> for QueryVector {
+    fn from(text: &str) -> Self {
+        Self::Text(text.to_string())
+    }
+}
+
+impl From<String> for QueryVector {
+    fn from(text: String) -> Self {
+        Self::Text(text)
+    }
+}
+
+/// The row whose vector a query searches for, see [Query::nearest_to_row].
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryRow {
+    /// The column identifying the row.
+    pub key_column: String,
+    /// The value of the row in `key_column`.
+    pub key_value: Literal,
+}
+
+/// How a multivector query, see [Query::nearest_to_multi], scores the rows of a
+/// multivector column. Distances are those of the metric type of the query,
+/// and the rows with the lowest scores are returned first.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum MultiVectorScoring {
+    /// Late interaction as in ColBERT: each query vector is matched with its
+    /// nearest vector of the row, and the row scores the sum of their distances.
+    #[default]
+    Sum,
+    /// The row scores the distance of its vector nearest to any query vector.
+    Max,
+}
+
+/// How a query with a filter searches the rows matching it, see
+/// [Query::filter_mode].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize)]
+pub enum FilterMode {
+    /// The nearest neighbors are searched first and the filter is applied to
+    /// them, as lance filters, so fewer than `limit` rows may be returned.
+    #[default]
+    Postfilter,
+    /// The rows matching the filter are read first and their vectors are
+    /// compared with the query vector, without the index.
+    Prefilter,
+    /// Prefilter when the filter is estimated to match less than
+    /// [Query::prefilter_threshold] of the rows, see
+    /// [crate::NativeTable::estimate_selectivity], postfilter otherwise.
+    Auto,
+}
+
+/// The [Query::prefilter_threshold] of the queries that do not set one.
+pub const DEFAULT_PREFILTER_THRESHOLD: f64 = 0.05;
+
+/// The [Query::limit] of the queries that do not set one.
+pub const DEFAULT_QUERY_LIMIT: usize = 10;
+
+/// The column of the distances of the results of a search to the query
+/// vector, named as lance names it.
+pub(crate) const DISTANCE_COLUMN: &str = "score";
+
+/// The largest [ScanParams] value, higher values are rejected.
+pub const MAX_SCAN_PARALLELISM: usize = 1024;
+
+/// How many reads of a local table a query issues concurrently, and how its
+/// reads of an object store are shaped. `None` uses the lance defaults. Remote
+/// tables ignore them, their server scans them.
+///
+/// lance reads each page of a data file and each partition of an index with a
+/// range request of its own, the `io_` values of the requests apply to the
+/// tables in object stores, whose requests cost more than their bytes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ScanParams {
+    /// How many fragments, the files of a table, are read at the same time.
+    pub io_parallelism: Option<usize>,
+    /// How many batches of each fragment are read ahead of the search.
+    pub batch_readahead: Option<usize>,
+    /// The size of the blocks the small reads of data and index files are
+    /// served from, each block fetched with one request. 0 reads each range
+    /// with a request of its own, as lance does.
+    pub io_coalesce_bytes: Option<usize>,
+    /// The largest request issued, larger reads being split in concurrent
+    /// requests.
+    pub io_max_request_size: Option<usize>,
+    /// How many requests to the object store a query runs at the same time.
+    pub io_concurrency: Option<usize>,
+}
+
+impl ScanParams {
+    /// These parameters, with the values they do not set taken from `defaults`.
+    pub fn or(self, defaults: ScanParams) -> Self {
+        Self {
+            io_parallelism: self.io_parallelism.or(defaults.io_parallelism),
+            batch_readahead: self.batch_readahead.or(defaults.batch_readahead),
+            io_coalesce_bytes: self.io_coalesce_bytes.or(defaults.io_coalesce_bytes),
+            io_max_request_size: self.io_max_request_size.or(defaults.io_max_request_size),
+            io_concurrency: self.io_concurrency.or(defaults.io_concurrency),
+        }
+    }
+
+    /// Whether these parameters shape the requests to the object store.
+    pub(crate) fn tunes_io(&self) -> bool {
+        self.io_coalesce_bytes.is_some_and(|bytes| bytes > 0)
+            || self.io_max_request_size.is_some()
+            || self.io_concurrency.is_some()
+    }
+
+    /// Make sure every count is between 1 and [MAX_SCAN_PARALLELISM], and
+    /// `io_max_request_size` is not 0.
+    pub(crate) fn validate(&self) -> Result<()> {
+        if self.io_max_request_size == Some(0) {
+            return Err(Error::InvalidInput {
+                message: "io_max_request_size must be at least 1".to_string(),
+            });
+        }
+        for (name, value) in [
+            ("io_parallelism", self.io_parallelism),
+            ("batch_readahead", self.batch_readahead),
+            ("io_concurrency", self.io_concurrency),
+        ] {
+            if let Some(value) = value.filter(|v| !(1..=MAX_SCAN_PARALLELISM).contains(v)) {
+                return Err(Error::InvalidInput {
+                    message: format!(
+                        "{name} must be between 1 and {MAX_SCAN_PARALLELISM}, got {value}"
+                    ),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Configure `scanner` with the values set.
+    pub(crate) fn apply(&self, scanner: &mut Scanner) {
+        if let Some(io_parallelism) = self.io_parallelism {
+            scanner.fragment_readahead(io_parallelism);
+        }
+        if let Some(batch_readahead) = self.batch_readahead {
+            scanner.batch_readahead(batch_readahead);
+        }
+    }
+}
+
+/// Runs the queries of a table that is not stored in a local dataset, like a
+/// remote table or a mock table in tests, see [Query::with_executor].
+#[async_trait]
+pub trait QueryExecutor: Send + Sync {
+    /// The schema and batches of the results of `query`.
+    async fn execute(&self, query: &Query) -> Result<(SchemaRef, Vec<RecordBatch>)>;
+}
+
+/// What a [Query] runs against.
+#[derive(Clone)]
+pub(crate) enum QueryTarget {
+    Dataset(DatasetRef),
+    Executor(Arc<dyn QueryExecutor>),
+}
+
+/// How a query ran, see [Query::execute_with_metrics].
+///
+/// lance does not report the work done inside an index search, the fields it
+/// would need are `None` for queries that search an index.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct QueryMetrics {
+    /// Bytes read from object storage. `None` for local, in-memory and remote
+    /// tables, lance reads local files without the object store.
+    pub io_bytes_read: Option<u64>,
+    /// The requests issued to object storage, `None` exactly when
+    /// `io_bytes_read` is, see [ScanParams::io_coalesce_bytes].
+    pub io_requests: Option<u64>,
+    /// The IVF partitions probed by an index search, `None` for a flat search.
+    /// This is the `nprobes` of the query, or more for a filtered search with
+    /// a [Query::maximum_nprobes]. An index with fewer partitions probes all
+    /// of them.
+    pub index_partitions_probed: Option<usize>,
+    /// The rows the filter was evaluated on, the nearest neighbors found by the
+    /// search, or the rows of the fragments read when prefiltering.
+    pub rows_scanned: usize,
+    /// The rows left after the filter, which are the rows returned.
+    pub rows_after_filter: usize,
+    /// The vectors a flat search compared with the query vector. `None` when
+    /// prefiltering, the rows matching the filter are not counted.
+    pub distance_computations: Option<usize>,
+    /// The fragments a filtered flat search or a prefilter did not read, they
+    /// have no rows matching the filter, or their partition values do not
+    /// match it, see
+    /// [crate::database::CreateTableBuilder::partition_by].
+    pub fragments_skipped: usize,
+    /// Whether the column statistics proved that no row matches the filter, so
+    /// the query returned no rows without searching, see
+    /// [crate::NativeTable::column_stats].
+    pub filter_pruned: bool,
+    /// How the filter was applied, chosen by the selectivity estimate of the
+    /// filter for [FilterMode::Auto]. `None` without a filter, and for
+    /// remote tables. Flat searches of half-precision and multivector
+    /// columns always prefilter.
+    pub filter_mode: Option<FilterMode>,
+    /// Milliseconds spent embedding the query text.
+    pub embed_ms: f64,
+    /// Milliseconds spent planning the search.
+    pub plan_ms: f64,
+    /// Milliseconds spent running the search and reading its results.
+    pub execute_ms: f64,
+}
+
+/// A query that ran longer than the threshold of its [SlowQueryHook].
+#[derive(Debug, Clone)]
+pub struct SlowQueryEvent {
+    /// The name of the table the query ran against.
+    pub table: String,
+    /// The query, without its vector or text.
+    pub query: String,
+    /// How long the query took, until its results were read.
+    pub elapsed: Duration,
+    /// How the query ran. A query run with [Query::execute] only reports
+    /// `rows_after_filter` and `execute_ms`.
+    pub metrics: QueryMetrics,
+}
+
+/// Called with the queries slower than the threshold of a [SlowQueryHook].
+pub type SlowQueryCallback = Arc<dyn Fn(SlowQueryEvent) + Send + Sync>;
+
+/// Reports queries slower than a threshold, see [crate::ConnectBuilder::on_slow_query].
+#[derive(Clone)]
+pub struct SlowQueryHook {
+    threshold: Duration,
+    callback: SlowQueryCallback,
+}
+
+impl SlowQueryHook {
+    pub fn new(threshold: Duration, callback: SlowQueryCallback) -> Self {
+        Self {
+            threshold,
+            callback,
+        }
+    }
+
+    /// Call the callback if `elapsed` exceeds the threshold, on a blocking
+    /// thread of the runtime so that the caller is not held up.
+    fn report(
+        &self,
+        table: &str,
+        query: impl FnOnce() -> String,
+        elapsed: Duration,
+        metrics: &QueryMetrics,
+    ) {
+        if elapsed <= self.threshold {
+            return;
+        }
+        let event = SlowQueryEvent {
+            table: table.to_string(),
+            query: query(),
+            elapsed,
+            metrics: metrics.clone(),
+        };
+        let callback = self.callback.clone();
+        match tokio::runtime::Handle::try_current() {
+            Ok(runtime) => {
+                runtime.spawn_blocking(move || callback(event));
+            }
+            Err(_) => callback(event),
+        }
+    }
+}
+
+impl std::fmt::Debug for SlowQueryHook {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SlowQueryHook")
+            .field("threshold", &self.threshold)
+            .finish()
+    }
+}
+
+/// Reports a query run with [Query::execute] when its results are dropped.
+///
+/// The query is timed until its last batch, not until the drop.
+struct SlowQueryWatch {
+    hook: SlowQueryHook,
+    table: String,
+    query: String,
+    start: Instant,
+    elapsed: Duration,
+    rows: usize,
+}
+
+impl Drop for SlowQueryWatch {
+    fn drop(&mut self) {
+        let metrics = QueryMetrics {
+            rows_after_filter: self.rows,
+            execute_ms: self.elapsed.as_secs_f64() * 1000.0,
+            ..QueryMetrics::default()
+        };
+        let query = std::mem::take(&mut self.query);
+        self.hook
+            .report(&self.table, || query, self.elapsed, &metrics);
+    }
+}
+
+fn elapsed_ms(start: Instant) -> f64 {
+    start.elapsed().as_secs_f64() * 1000.0
+}
+
+/// A stream of the results `batches` of a query, of `schema`.
+fn batches_stream(schema: SchemaRef, batches: Vec<RecordBatch>) -> DatasetRecordBatchStream {
+    DatasetRecordBatchStream::new(Box::pin(RecordBatchStreamAdapter::new(
+        schema,
+        futures::stream::iter(batches.into_iter().map(Ok)),
+    )))
+}
+
+/// The first `limit` rows of `batches`.
+fn truncate(batches: Vec<RecordBatch>, mut limit: usize) -> Vec<RecordBatch> {
+    let mut results = Vec::with_capacity(batches.len());
+    for batch in batches {
+        let rows = batch.num_rows().min(limit);
+        results.push(batch.slice(0, rows));
+        limit -= rows;
+    }
+    results
+}
+
+/// The first `limit` rows of `batches` whose vector in `column` is not null,
+/// and the number of rows of null vectors left out. The column marking the
+/// null vectors is left out of the rows unless `keep_marker`.
+fn drop_null_vectors(
+    batches: Vec<RecordBatch>,
+    column: &str,
+    limit: usize,
+    keep_marker: bool,
+) -> Result<(Vec<RecordBatch>, usize)> {
+    let marker = null_vector_column(column);
+    let mut nulls = 0;
+    let mut rows = 0;
+    let mut results = Vec::with_capacity(batches.len());
+    for batch in batches {
+        let marks = batch
+            .column_by_name(&marker)
+            .and_then(|c| c.as_any().downcast_ref::<BooleanArray>());
+        let keep = (0..batch.num_rows())
+            .map(|row| {
+                let null = marks.is_some_and(|m| m.is_valid(row) && m.value(row));
+                nulls += usize::from(null);
+                let keep = !null && rows < limit;
+                rows += usize::from(keep);
+                Some(keep)
+            })
+            .collect::<BooleanArray>();
+        let batch = filter_record_batch(&batch, &keep).map_err(lance::Error::from)?;
+        let columns = (0..batch.num_columns())
+            .filter(|i| keep_marker || batch.schema().field(*i).name() != &marker)
+            .collect::<Vec<_>>();
+        results.push(batch.project(&columns).map_err(lance::Error::from)?);
+    }
+    Ok((results, nulls))
+}
+
+/// `schema` without the field `name`.
+fn without_field(schema: SchemaRef, name: &str) -> Result<SchemaRef> {
+    match schema.index_of(name) {
+        Ok(i) => {
+            let indices = (0..schema.fields().len()).filter(|j| *j != i);
+            Ok(Arc::new(
+                schema
+                    .project(&indices.collect::<Vec<_>>())
+                    .map_err(lance::Error::from)?,
+            ))
+        }
+        Err(_) => Ok(schema),
+    }
+}
+
+/// How the column of a query is searched, in the schema of a table version.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Search {
+    /// By lance, in a `Float32` vector column.
+    Lance,
+    /// By [flat::search_f16].
+    F16,
+    /// By [flat::search_multivector].
+    Multivector,
+}
+
+/// The column of a query resolved in the schema of the table `version`.
+#[derive(Debug, Clone)]
+pub(crate) struct ResolvedColumn {
+    pub version: u64,
+    pub search: Search,
+    /// The width of the vectors of a vector column.
+    pub dims: Option<usize>,
+}
+
+/// The parameters of a query that change its results, the key of its results
+/// in the query cache of its table. The keys are compared in full, so that
+/// two queries never share results, the floats by their bits.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct QueryKey {
+    /// The version of the table searched.
+    version: u64,
+    vectors: Vec<Vec<u32>>,
+    column: String,
+    limit: usize,
+    filter: Option<String>,
+    scopes: Vec<String>,
+    select: Option<Vec<String>>,
+    json_paths: Vec<JsonPathColumn>,
+    /// The key column and value of the [QueryRow].
+    query_row: Option<(String, String)>,
+    exclude_query_row: bool,
+    nprobes: usize,
+    maximum_nprobes: Option<usize>,
+    refine_factor: Option<u32>,
+    metric_type: Option<String>,
+    use_index: bool,
+    allow_index_fallback: bool,
+    require_covering_index: bool,
+    filter_mode: FilterMode,
+    prefilter_threshold: u64,
+    selectivity_sample: usize,
+    with_row_id: bool,
+    with_partition_id: bool,
+    allow_nonfinite: bool,
+    max_result_bytes: Option<usize>,
+    ordered: bool,
+    distinct_on: Option<String>,
+    distinct_overfetch: usize,
+    two_stage: Option<TwoStage>,
+    multivector_scoring: MultiVectorScoring,
+}
 
 /// A builder for nearest neighbor queries for LanceDB.
+///
+/// The results are ordered by distance to the query vector, nearest first, see
+/// [Query::ordered] for the order of the rows of the same distance.
+///
+/// Cloning a query is cheap, the table and the vectors are shared.
+#[derive(Clone)]
 pub struct Query {
-    pub dataset: Arc<Dataset>,
+    pub(crate) target: QueryTarget,
+    pub(crate) table_name: Option<String>,
+    pub(crate) slow_query: Option<SlowQueryHook>,
+    pub(crate) query_cache: Option<Arc<QueryCache>>,
+    /// The column last resolved by a [PreparedQuery], shared by its runs.
+    pub(crate) resolved: Option<Arc<Mutex<Option<ResolvedColumn>>>>,
+    pub(crate) embeddings: Vec<EmbeddingDefinition>,
+    /// The filters of the [crate::table::ScopedTable] of the query, joined
+    /// with its filter when it runs.
+    pub(crate) scopes: Vec<String>,
     pub query_vector: Float32Array,
+    pub query_text: Option<String>,
+    pub query_vectors: Option<Vec<Float32Array>>,
+    pub query_row: Option<QueryRow>,
+    pub exclude_query_row: bool,
+    pub multivector_scoring: MultiVectorScoring,
+    pub column: String,
     pub limit: usize,
+    /// Whether the query asked for every row, see [Query::limit_none].
+    pub unlimited: bool,
+    /// The largest limit the query may have, set by the connection of its
+    /// table, see [crate::ConnectBuilder::max_limit].
+    pub max_limit: Option<usize>,
     pub filter: Option<String>,
     pub select: Option<Vec<String>>,
+    pub json_paths: Vec<JsonPathColumn>,
     pub nprobes: usize,
+    /// The most partitions a filtered search probes, see
+    /// [Query::maximum_nprobes].
+    pub maximum_nprobes: Option<usize>,
+    /// The partitions the last index search probed, counted for
+    /// [QueryMetrics::index_partitions_probed].
+    pub(crate) probes_used: Option<Arc<AtomicUsize>>,
     pub refine_factor: Option<u32>,
     pub metric_type: Option<MetricType>,
     pub use_index: bool,
+    /// Whether a search with an index that cannot be read is flat, see
+    /// [Query::allow_index_fallback].
+    pub allow_index_fallback: bool,
+    /// Whether a search whose filter does not imply the row filter of the
+    /// index fails, see [Query::require_covering_index].
+    pub require_covering_index: bool,
+    pub filter_mode: FilterMode,
+    pub prefilter_threshold: f64,
+    pub selectivity_sample: usize,
+    pub with_row_id: bool,
+    /// Whether the results have an [IVF_PARTITION_COLUMN], see
+    /// [Query::with_partition_id].
+    pub with_partition_id: bool,
+    /// Whether the results keep the rows of NaN or infinite distances, see
+    /// [Query::allow_nonfinite].
+    pub allow_nonfinite: bool,
+    /// The estimated size of the results above which the query fails, see
+    /// [Query::max_result_bytes].
+    pub max_result_bytes: Option<usize>,
+    pub ordered: bool,
+    pub distinct_on: Option<String>,
+    pub distinct_overfetch: usize,
+    pub two_stage: Option<TwoStage>,
+    pub scan_params: ScanParams,
+}
+
+/// Leaves out the query vector and text, which may be large or sensitive.
+impl std::fmt::Debug for Query {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Query")
+            .field("column", &self.column)
+            .field("limit", &self.limit)
+            .field("unlimited", &self.unlimited)
+            .field("max_limit", &self.max_limit)
+            .field("filter", &self.filter)
+            .field("select", &self.select)
+            .field("json_paths", &self.json_paths)
+            .field("nprobes", &self.nprobes)
+            .field("maximum_nprobes", &self.maximum_nprobes)
+            .field("refine_factor", &self.refine_factor)
+            .field("metric_type", &self.metric_type)
+            .field("use_index", &self.use_index)
+            .field("allow_index_fallback", &self.allow_index_fallback)
+            .field("require_covering_index", &self.require_covering_index)
+            .field("filter_mode", &self.filter_mode)
+            .field("prefilter_threshold", &self.prefilter_threshold)
+            .field("selectivity_sample", &self.selectivity_sample)
+            .field("with_row_id", &self.with_row_id)
+            .field("with_partition_id", &self.with_partition_id)
+            .field("allow_nonfinite", &self.allow_nonfinite)
+            .field("max_result_bytes", &self.max_result_bytes)
+            .field("scopes", &self.scopes)
+            .field("ordered", &self.ordered)
+            .field("distinct_on", &self.distinct_on)
+            .field("distinct_overfetch", &self.distinct_overfetch)
+            .field("two_stage", &self.two_stage)
+            .field("multivector_scoring", &self.multivector_scoring)
+            .field("scan_params", &self.scan_params)
+            .finish()
+    }
 }
 
 impl Query {
@@ -45,49 +649,1103 @@ impl Query {
     /// # Returns
     ///
     /// * A [Query] object.
-    pub(crate) fn new(dataset: Arc<Dataset>, vector: Float32Array) -> Self {
+    pub(crate) fn new(dataset: impl Into<DatasetRef>, vector: Float32Array) -> Self {
+        Self::with_target(QueryTarget::Dataset(dataset.into()), vector)
+    }
+
+    /// Creates a new Query object run by `executor`.
+    ///
+    /// # Arguments
+    ///
+    /// * `executor` - Runs the query when it is executed.
+    /// * `vector` The vector used for this query.
+    pub fn with_executor(executor: Arc<dyn QueryExecutor>, vector: Float32Array) -> Self {
+        Self::with_target(QueryTarget::Executor(executor), vector)
+    }
+
+    fn with_target(target: QueryTarget, vector: Float32Array) -> Self {
         Query {
-            dataset,
+            target,
+            table_name: None,
+            slow_query: None,
+            query_cache: None,
+            resolved: None,
+            embeddings: Vec::new(),
+            scopes: Vec::new(),
             query_vector: vector,
-            limit: 10,
+            query_text: None,
+            query_vectors: None,
+            query_row: None,
+            exclude_query_row: true,
+            multivector_scoring: MultiVectorScoring::default(),
+            column: VECTOR_COLUMN_NAME.to_string(),
+            limit: DEFAULT_QUERY_LIMIT,
+            unlimited: false,
+            max_limit: None,
             nprobes: 20,
+            maximum_nprobes: None,
+            probes_used: None,
             refine_factor: None,
             metric_type: None,
             use_index: false,
+            allow_index_fallback: false,
+            require_covering_index: false,
+            filter_mode: FilterMode::default(),
+            prefilter_threshold: DEFAULT_PREFILTER_THRESHOLD,
+            selectivity_sample: DEFAULT_SELECTIVITY_SAMPLE,
+            with_row_id: false,
+            with_partition_id: false,
+            allow_nonfinite: false,
+            max_result_bytes: None,
+            ordered: false,
+            distinct_on: None,
+            distinct_overfetch: DEFAULT_DISTINCT_OVERFETCH,
+            two_stage: None,
             filter: None,
             select: None,
+            json_paths: Vec::new(),
+            scan_params: ScanParams::default(),
+        }
+    }
+
+    /// Name the table the query runs against in its tracing span.
+    pub(crate) fn with_table_name(mut self, name: &str) -> Self {
+        self.table_name = Some(name.to_string());
+        self
+    }
+
+    /// The name of the table the query runs against, `None` for a query of
+    /// an executor, see [Query::with_executor].
+    pub fn table_name(&self) -> Option<&str> {
+        self.table_name.as_deref()
+    }
+
+    /// Report the query to `hook` if it is slow.
+    pub(crate) fn with_slow_query_hook(mut self, hook: Option<SlowQueryHook>) -> Self {
+        self.slow_query = hook;
+        self
+    }
+
+    /// Serve the results of this query from `cache` when it ran before on the
+    /// same version of the table.
+    pub(crate) fn with_query_cache(mut self, cache: Option<Arc<QueryCache>>) -> Self {
+        self.query_cache = cache;
+        self
+    }
+
+    /// The parameters of this query that change its results, with `version`
+    /// the version of the table it searches and `query_vector` the resolved
+    /// vector.
+    ///
+    /// The query is destructured in full, so that a new field of [Query] does
+    /// not compile until it is either part of the key or left out here.
+    fn cache_key(&self, version: u64, query_vector: &Float32Array) -> QueryKey {
+        let Query {
+            // Where and how the query runs, not what it returns.
+            target: _,
+            table_name: _,
+            slow_query: _,
+            query_cache: _,
+            resolved: _,
+            probes_used: _,
+            scan_params: _,
+            // Resolved to `query_vector`.
+            embeddings: _,
+            query_vector: _,
+            query_text: _,
+            // Checked before the query runs, see [Query::check_limit].
+            unlimited: _,
+            max_limit: _,
+            scopes,
+            query_vectors,
+            query_row,
+            exclude_query_row,
+            multivector_scoring,
+            column,
+            limit,
+            filter,
+            select,
+            json_paths,
+            nprobes,
+            maximum_nprobes,
+            refine_factor,
+            metric_type,
+            use_index,
+            allow_index_fallback,
+            require_covering_index,
+            filter_mode,
+            prefilter_threshold,
+            selectivity_sample,
+            with_row_id,
+            with_partition_id,
+            allow_nonfinite,
+            max_result_bytes,
+            ordered,
+            distinct_on,
+            distinct_overfetch,
+            two_stage,
+        } = self;
+        let vectors = std::iter::once(query_vector).chain(query_vectors.iter().flatten());
+        QueryKey {
+            version,
+            vectors: vectors
+                .map(|v| v.values().iter().map(|value| value.to_bits()).collect())
+                .collect(),
+            column: column.clone(),
+            limit: *limit,
+            filter: filter.clone(),
+            scopes: scopes.clone(),
+            select: select.clone(),
+            json_paths: json_paths.clone(),
+            query_row: query_row
+                .as_ref()
+                .map(|row| (row.key_column.clone(), row.key_value.to_string())),
+            exclude_query_row: *exclude_query_row,
+            nprobes: *nprobes,
+            maximum_nprobes: *maximum_nprobes,
+            refine_factor: *refine_factor,
+            metric_type: metric_type.map(|m| m.to_string()),
+            use_index: *use_index,
+            allow_index_fallback: *allow_index_fallback,
+            require_covering_index: *require_covering_index,
+            filter_mode: *filter_mode,
+            prefilter_threshold: prefilter_threshold.to_bits(),
+            selectivity_sample: *selectivity_sample,
+            with_row_id: *with_row_id,
+            with_partition_id: *with_partition_id,
+            allow_nonfinite: *allow_nonfinite,
+            max_result_bytes: *max_result_bytes,
+            ordered: *ordered,
+            distinct_on: distinct_on.clone(),
+            distinct_overfetch: *distinct_overfetch,
+            two_stage: two_stage.clone(),
+            multivector_scoring: *multivector_scoring,
+        }
+    }
+
+    /// Scan with the parameters of the table the query runs against, see
+    /// [Query::io_parallelism], [Query::batch_readahead] and
+    /// [Query::io_coalesce_bytes].
+    pub(crate) fn with_scan_params(mut self, params: ScanParams) -> Self {
+        self.scan_params = params;
+        self
+    }
+
+    /// Reject the limits above `max_limit`, see [crate::ConnectBuilder::max_limit].
+    pub(crate) fn with_max_limit(mut self, max_limit: Option<usize>) -> Self {
+        self.max_limit = max_limit;
+        self
+    }
+
+    /// Make sure the query returns a bounded number of rows, at least one and
+    /// at most its [Query::max_limit].
+    fn check_limit(&self) -> Result<()> {
+        if self.unlimited {
+            return Err(Error::InvalidInput {
+                message: "limit_none only applies to scans, a vector query needs a limit"
+                    .to_string(),
+            });
+        }
+        if self.limit == 0 {
+            return Err(Error::InvalidInput {
+                message: "the limit of a query must be at least 1".to_string(),
+            });
+        }
+        match self.max_limit {
+            Some(max_limit) if self.limit > max_limit => Err(Error::InvalidInput {
+                message: format!(
+                    "the limit {} is above the maximum of {max_limit} of this connection",
+                    self.limit
+                ),
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Search with the embedding functions of the table the query runs against.
+    pub(crate) fn with_embeddings(mut self, embeddings: Vec<EmbeddingDefinition>) -> Self {
+        self.embeddings = embeddings;
+        self
+    }
+
+    /// The vector to search for, embedding the query text if there is one.
+    async fn resolve_vector(&self) -> Result<Float32Array> {
+        let Some(text) = self.query_text.as_ref() else {
+            return Ok(self.query_vector.clone());
+        };
+        let embedding = self
+            .embeddings
+            .iter()
+            .find(|e| e.vector_column == self.column)
+            .ok_or_else(|| Error::InvalidInput {
+                message: format!(
+                    "cannot search for text, no embedding function is registered for the column '{}'",
+                    self.column
+                ),
+            })?;
+        embedding.embed_text(text).await
+    }
+
+    /// This query searching for the vector of its [QueryRow], with the rows of
+    /// its key left out if [Query::exclude_query_row] is set. `None` if the
+    /// query has no row, or runs on an executor, which looks the row up itself.
+    ///
+    /// lance filters the nearest neighbors found by the search, so the query
+    /// searches for one more row than its limit, the row left out.
+    async fn seeded(&self) -> Result<Option<Query>> {
+        let (Some(row), QueryTarget::Dataset(target)) = (self.query_row.as_ref(), &self.target)
+        else {
+            return Ok(None);
+        };
+        let dataset = target.get().await?;
+        let key = col(&row.key_column).eq(lit(row.key_value.clone()));
+        let schema = ArrowSchema::from(dataset.schema());
+        let mut columns = vec![self.column.clone()];
+        columns.extend(marker_column_of(&schema, &self.column));
+        let mut scanner = dataset.scan();
+        scanner.project(&columns)?;
+        scanner.filter(&filter::lance_filter(&key.to_string(), &schema)?)?;
+        scanner.limit(Some(1), None)?;
+        let batches: Vec<RecordBatch> = scanner.try_into_stream().await?.try_collect().await?;
+        let Some(batch) = batches.iter().find(|b| b.num_rows() > 0) else {
+            return Err(Error::RowNotFound {
+                column: row.key_column.clone(),
+                key: row.key_value.to_string(),
+            });
+        };
+        let batch = mark_null_vectors(batch, &self.column, false).map_err(lance::Error::from)?;
+        let vectors = batch
+            .column(0)
+            .as_any()
+            .downcast_ref::<FixedSizeListArray>()
+            .ok_or_else(|| Error::InvalidInput {
+                message: format!(
+                    "nearest_to_row searches columns of one vector per row, '{}' is not one",
+                    self.column
+                ),
+            })?;
+        if vectors.is_null(0) {
+            return Err(Error::InvalidInput {
+                message: format!(
+                    "the row of the key {} has a null vector in the column '{}'",
+                    row.key_value, self.column
+                ),
+            });
+        }
+        let vector = cast(&vectors.value(0), &DataType::Float32).map_err(lance::Error::from)?;
+        let vector = vector.as_any().downcast_ref::<Float32Array>().unwrap();
+
+        let mut query = self.clone().query_vector(vector.clone());
+        if self.exclude_query_row {
+            let other = col(&row.key_column).is_null().or(!key);
+            query.filter = Some(match self.filter.as_ref() {
+                Some(filter) => format!("({other}) AND ({filter})"),
+                None => other.to_string(),
+            });
+            query.limit += 1;
+        }
+        Ok(Some(query))
+    }
+
+    /// Execute the queries and return its results.
+    ///
+    /// # Returns
+    ///
+    /// * A [DatasetRecordBatchStream] with the query's results.
+    pub async fn execute(&self) -> Result<DatasetRecordBatchStream> {
+        let start = Instant::now();
+        let query = self.within_scope()?;
+        // The searches are boxed, their futures are too large to be held on
+        // the stack of the callers.
+        let stream = match query.with_partition_id {
+            true => timed(self.span(), Box::pin(query.execute_with_partition_id())).await?,
+            false => timed(self.span(), Box::pin(query.execute_inner())).await?,
+        };
+        let stream = match query.max_result_bytes {
+            Some(_) => {
+                let schema = stream.schema();
+                batches_stream(schema, query.collect_capped(stream).await?)
+            }
+            None => stream,
+        };
+        let Some(hook) = self.slow_query.clone() else {
+            return Ok(stream);
+        };
+        let schema = stream.schema();
+        let mut watch = SlowQueryWatch {
+            hook,
+            table: self.table().to_string(),
+            query: format!("{self:?}"),
+            start,
+            elapsed: start.elapsed(),
+            rows: 0,
+        };
+        let stream = stream.map(move |batch| {
+            // Move the whole watch into the closure, it reports when the stream is dropped.
+            let watch = &mut watch;
+            let batch = batch.map_err(|e| DataFusionError::External(Box::new(e)))?;
+            watch.rows += batch.num_rows();
+            watch.elapsed = watch.start.elapsed();
+            Ok(batch)
+        });
+        Ok(DatasetRecordBatchStream::new(Box::pin(
+            RecordBatchStreamAdapter::new(schema, stream),
+        )))
+    }
+
+    /// Execute the query and collect its results, together with [QueryMetrics]
+    /// on how it ran.
+    ///
+    /// A query of a table in object storage reads from a separately opened
+    /// copy of the table, to count the bytes it reads.
+    pub async fn execute_with_metrics(&self) -> Result<(Vec<RecordBatch>, QueryMetrics)> {
+        let start = Instant::now();
+        let query = self.within_scope()?;
+        let (batches, metrics) = match query.with_partition_id {
+            true => timed(self.span(), query.execute_with_partition_id_metrics()).await?,
+            false => timed(self.span(), query.execute_with_metrics_inner()).await?,
+        };
+        query.check_result_bytes(&batches)?;
+        if let Some(hook) = self.slow_query.as_ref() {
+            hook.report(
+                self.table(),
+                || format!("{self:?}"),
+                start.elapsed(),
+                &metrics,
+            );
+        }
+        Ok((batches, metrics))
+    }
+
+    /// This query with its filter joined with its scopes, see
+    /// [crate::table::ScopedTable], which the filter cannot leave whatever it
+    /// is.
+    pub(crate) fn within_scope(&self) -> Result<Cow<'_, Query>> {
+        if self.scopes.is_empty() {
+            return Ok(Cow::Borrowed(self));
+        }
+        let filters = self.scopes.iter().chain(self.filter.as_ref());
+        let mut query = self.clone();
+        query.filter = Some(filter::and_filters(filters.map(String::as_str))?);
+        query.scopes = Vec::new();
+        Ok(Cow::Owned(query))
+    }
+
+    fn table(&self) -> &str {
+        self.table_name.as_deref().unwrap_or_default()
+    }
+    fn span(&self) -> Span {
+        info_span!(
+            "query",
+            table = self.table(),
+            column = %self.column,
+            limit = self.limit,
+            nprobes = self.nprobes,
+            filter = self.filter.as_deref(),
+            io_parallelism = self.scan_params.io_parallelism,
+            batch_readahead = self.scan_params.batch_readahead,
+            elapsed_ms = field::Empty
+        )
+    }
+
+    /// The lance search of this query, and what is left to do on its results,
+    /// if the query has a [JSON_EXTRACT] filter or [JsonPathColumn]s. `None` if
+    /// it has neither, or runs on an executor.
+    async fn json_search(&self) -> Result<Option<JsonSearch>> {
+        let QueryTarget::Dataset(target) = &self.target else {
+            return Ok(None);
+        };
+        let filter = match self.filter.as_ref() {
+            Some(filter) => {
+                let schema = ArrowSchema::from(target.get().await?.schema());
+                JsonFilter::plan(filter, &schema)?
+            }
+            None => None,
+        };
+        if filter.is_none() && self.json_paths.is_empty() {
+            return Ok(None);
+        }
+        let mut query = self.clone();
+        query.json_paths = Vec::new();
+        let mut added = Vec::new();
+        if filter.is_some() {
+            query.filter = None;
+        }
+        if let Some(select) = query.select.as_mut() {
+            let read = filter.iter().flat_map(|f| f.columns.iter());
+            for column in read.chain(self.json_paths.iter().map(|p| &p.column)) {
+                if !select.contains(column) {
+                    select.push(column.clone());
+                    added.push(column.clone());
+                }
+            }
+        }
+        Ok(Some(JsonSearch {
+            query,
+            filter,
+            paths: self.json_paths.clone(),
+            added,
+        }))
+    }
+
+    async fn execute_inner(&self) -> Result<DatasetRecordBatchStream> {
+        self.check_limit()?;
+        let Some(query) = self.seeded().await? else {
+            return self.search().await;
+        };
+        let stream = query.search().await?;
+        let schema = stream.schema();
+        let batches = stream.try_collect::<Vec<_>>().await?;
+        Ok(batches_stream(schema, truncate(batches, self.limit)))
+    }
+
+    async fn search(&self) -> Result<DatasetRecordBatchStream> {
+        if let Some(column) = self.distinct_on.as_deref() {
+            return self.search_distinct(column).await;
+        }
+        self.search_ranked().await
+    }
+
+    async fn search_ranked(&self) -> Result<DatasetRecordBatchStream> {
+        if self.ordered {
+            return self.search_ordered().await;
+        }
+        self.search_unordered().await
+    }
+
+    async fn search_unordered(&self) -> Result<DatasetRecordBatchStream> {
+        if let (Some(two_stage), QueryTarget::Dataset(_)) = (&self.two_stage, &self.target) {
+            return self.search_two_stage(two_stage).await;
+        }
+        self.search_one_stage().await
+    }
+
+    /// The results of this query, not searched in two stages.
+    async fn search_one_stage(&self) -> Result<DatasetRecordBatchStream> {
+        let Some(json) = self.json_search().await? else {
+            return self.search_finite().await;
+        };
+        let stream = json.query.search_finite().await?;
+        let schema = json.schema(&stream.schema())?;
+        let batches = stream.try_collect::<Vec<_>>().await?;
+        Ok(batches_stream(schema, json.finish(batches).await?))
+    }
+
+    async fn search_lance(&self) -> Result<DatasetRecordBatchStream> {
+        let stream = self.stream_lance().await?;
+        if self.max_result_bytes.is_none() {
+            return Ok(stream);
+        }
+        let schema = stream.schema();
+        Ok(batches_stream(schema, self.collect_capped(stream).await?))
+    }
+
+    /// The results of the lance search of this query, as lance reads them.
+    async fn stream_lance(&self) -> Result<DatasetRecordBatchStream> {
+        let query_vector = self.resolve_vector().await?;
+        let dataset = match &self.target {
+            QueryTarget::Dataset(dataset) => {
+                let latest = dataset.get().await?;
+                dataset.tuned(latest, &self.scan_params).await?
+            }
+            QueryTarget::Executor(executor) => {
+                let (schema, batches) = executor.execute(self).await?;
+                return Ok(batches_stream(schema, batches));
+            }
+        };
+        if tracing::enabled!(Level::DEBUG) {
+            self.trace_index_selection(&dataset).await?;
+        }
+        let Some(cache) = self.query_cache.as_ref() else {
+            return self.stream(dataset, &query_vector).await;
+        };
+        let version = dataset.version().version;
+        let key = self.cache_key(version, &query_vector);
+        let (schema, batches) = match cache.get(&key) {
+            Some(results) => results,
+            None => {
+                let stream = self.stream(dataset, &query_vector).await?;
+                let schema = stream.schema();
+                let batches = stream.try_collect::<Vec<_>>().await?;
+                cache.insert(key, version, (schema.clone(), batches.clone()));
+                (schema, batches)
+            }
+        };
+        Ok(batches_stream(schema, batches))
+    }
+
+    async fn execute_with_metrics_inner(&self) -> Result<(Vec<RecordBatch>, QueryMetrics)> {
+        self.check_limit()?;
+        let Some(query) = self.seeded().await? else {
+            return self.search_with_metrics().await;
+        };
+        let (batches, mut metrics) = query.search_with_metrics().await?;
+        let batches = truncate(batches, self.limit);
+        metrics.rows_after_filter = batches.iter().map(RecordBatch::num_rows).sum();
+        Ok((batches, metrics))
+    }
+
+    async fn search_with_metrics(&self) -> Result<(Vec<RecordBatch>, QueryMetrics)> {
+        if let Some(column) = self.distinct_on.as_deref() {
+            return self.search_distinct_with_metrics(column).await;
+        }
+        self.search_ranked_with_metrics().await
+    }
+
+    async fn search_ranked_with_metrics(&self) -> Result<(Vec<RecordBatch>, QueryMetrics)> {
+        if self.ordered {
+            return self.search_ordered_with_metrics().await;
+        }
+        self.search_unordered_with_metrics().await
+    }
+
+    async fn search_unordered_with_metrics(&self) -> Result<(Vec<RecordBatch>, QueryMetrics)> {
+        if let (Some(two_stage), QueryTarget::Dataset(_)) = (&self.two_stage, &self.target) {
+            return self.search_two_stage_with_metrics(two_stage).await;
+        }
+        self.search_one_stage_with_metrics().await
+    }
+
+    /// [Query::search_one_stage] and the [QueryMetrics] of its search.
+    async fn search_one_stage_with_metrics(&self) -> Result<(Vec<RecordBatch>, QueryMetrics)> {
+        let Some(json) = self.json_search().await? else {
+            return self.search_finite_with_metrics().await;
+        };
+        let (batches, mut metrics) = json.query.search_finite_with_metrics().await?;
+        let batches = json.finish(batches).await?;
+        metrics.rows_after_filter = batches.iter().map(RecordBatch::num_rows).sum();
+        Ok((batches, metrics))
+    }
+
+    async fn search_lance_with_metrics(&self) -> Result<(Vec<RecordBatch>, QueryMetrics)> {
+        let mut metrics = QueryMetrics::default();
+        let start = Instant::now();
+        let query_vector = self.resolve_vector().await?;
+        metrics.embed_ms = elapsed_ms(start);
+
+        let target = match &self.target {
+            QueryTarget::Dataset(dataset) => dataset,
+            QueryTarget::Executor(executor) => {
+                let start = Instant::now();
+                let (_, batches) = executor.execute(self).await?;
+                metrics.execute_ms = elapsed_ms(start);
+                metrics.rows_after_filter = batches.iter().map(RecordBatch::num_rows).sum();
+                metrics.rows_scanned = metrics.rows_after_filter;
+                return Ok((batches, metrics));
+            }
+        };
+        let dataset = target.get().await?;
+        if self.pruned(&dataset).await? {
+            let start = Instant::now();
+            let stream = self.stream(dataset, &query_vector).await?;
+            metrics.plan_ms = elapsed_ms(start);
+            metrics.filter_pruned = true;
+            return Ok((self.collect_capped(stream).await?, metrics));
+        }
+        metrics.filter_mode = self.chosen_filter_mode(&dataset).await?;
+        let prefilter = metrics.filter_mode == Some(FilterMode::Prefilter);
+        let indexed = !prefilter && self.uses_index(&dataset).await?;
+        let table_rows = if !indexed || self.filter.is_some() {
+            dataset.count_rows().await?
+        } else {
+            0
+        };
+        // The flat search of a prefilter reads the fragments of the partitions
+        // matching the filter, with or without an index.
+        let fragments = match prefilter {
+            true => self.partition_fragments(&dataset).await?,
+            false => self.matching_fragments(&dataset).await?,
+        };
+        let searched_rows = match fragments {
+            Some((fragments, skipped)) => {
+                metrics.fragments_skipped = skipped;
+                let mut rows = 0;
+                for fragment in dataset.get_fragments() {
+                    if fragments.iter().any(|f| f.id == fragment.id() as u64) {
+                        rows += fragment.count_rows().await?;
+                    }
+                }
+                rows
+            }
+            None => table_rows,
+        };
+        let (dataset, counters) = match target.metered(&dataset, &self.scan_params).await? {
+            Some((dataset, counters)) => (Arc::new(dataset), Some(counters)),
+            None => (dataset, None),
+        };
+
+        let start = Instant::now();
+        let probes_used = Arc::new(AtomicUsize::new(self.nprobes));
+        let query = Query {
+            probes_used: Some(probes_used.clone()),
+            ..self.clone()
+        };
+        let stream = query
+            .stream_filtered(dataset, &query_vector, metrics.filter_mode)
+            .await?;
+        metrics.plan_ms = elapsed_ms(start);
+        let start = Instant::now();
+        let batches = self.collect_capped(stream).await?;
+        metrics.execute_ms = elapsed_ms(start);
+
+        if let Some(counters) = counters {
+            metrics.io_bytes_read = Some(counters.bytes_read.load(Ordering::Relaxed));
+            metrics.io_requests = Some(counters.requests.load(Ordering::Relaxed));
+        }
+        metrics.rows_after_filter = batches.iter().map(RecordBatch::num_rows).sum();
+        // lance filters the nearest neighbors found by the search.
+        metrics.rows_scanned = match self.filter {
+            Some(_) if prefilter => searched_rows,
+            Some(_) => self.limit.min(searched_rows),
+            None => metrics.rows_after_filter,
+        };
+        if indexed {
+            metrics.index_partitions_probed = Some(probes_used.load(Ordering::Relaxed));
+        } else if !prefilter {
+            metrics.distance_computations = Some(searched_rows);
+        }
+        Ok((batches, metrics))
+    }
+
+    /// The results of this query of `dataset`.
+    async fn stream(
+        &self,
+        dataset: Arc<Dataset>,
+        query_vector: &Float32Array,
+    ) -> Result<DatasetRecordBatchStream> {
+        let filter_mode = self.chosen_filter_mode(&dataset).await?;
+        self.stream_filtered(dataset, query_vector, filter_mode)
+            .await
+    }
+
+    /// How the filter of this query of `dataset` is applied, `None` without a
+    /// filter.
+    async fn chosen_filter_mode(&self, dataset: &Dataset) -> Result<Option<FilterMode>> {
+        let (Some(filter), QueryTarget::Dataset(target)) = (self.filter.as_ref(), &self.target)
+        else {
+            return Ok(None);
+        };
+        if self.query_vectors.is_some() || self.resolve_column(dataset)?.search != Search::Lance {
+            return Ok(Some(FilterMode::Prefilter));
+        }
+        if self.filter_mode != FilterMode::Auto {
+            return Ok(Some(self.filter_mode));
+        }
+        let selectivity = target
+            .estimate_selectivity(dataset, filter, self.selectivity_sample)
+            .await?;
+        let mode = if selectivity < self.prefilter_threshold {
+            FilterMode::Prefilter
+        } else {
+            FilterMode::Postfilter
+        };
+        debug!(%filter, selectivity, ?mode, "filter mode chosen by the selectivity estimate");
+        Ok(Some(mode))
+    }
+
+    /// The results of this query of `dataset`, applying its filter as
+    /// `filter_mode`.
+    ///
+    /// lance only searches `Float32` vector columns, `Float16` vector columns and
+    /// multivector columns are searched by [flat], as are prefiltered searches.
+    async fn stream_filtered(
+        &self,
+        dataset: Arc<Dataset>,
+        query_vector: &Float32Array,
+        filter_mode: Option<FilterMode>,
+    ) -> Result<DatasetRecordBatchStream> {
+        self.scan_params.validate()?;
+        let column = self.resolve_column(&dataset)?;
+        match (column.search, self.query_vectors.as_ref()) {
+            (Search::Multivector, Some(vectors)) => {
+                return flat::search_multivector(&dataset, self, vectors).await;
+            }
+            (_, Some(_)) => {
+                return Err(Error::InvalidInput {
+                    message: format!(
+                        "nearest_to_multi searches multivector columns, '{}' is not one",
+                        self.column
+                    ),
+                });
+            }
+            (Search::Multivector, None) => {
+                return Err(Error::InvalidInput {
+                    message: format!(
+                        "the column '{}' has several vectors per row, search it with nearest_to_multi",
+                        self.column
+                    ),
+                });
+            }
+            _ => {}
+        }
+        if let Some(dims) = column.dims {
+            if dims != query_vector.len() {
+                return Err(Error::EmbeddingDimensionMismatch {
+                    column: self.column.clone(),
+                    expected: dims,
+                    got: query_vector.len(),
+                });
+            }
+        }
+        if column.search == Search::F16 {
+            return flat::search_f16(&dataset, self, query_vector).await;
+        }
+        if let Some(norms) = self.stored_norms(&dataset).await? {
+            return self
+                .search_stored_norms(dataset, query_vector, &norms, filter_mode)
+                .await;
+        }
+        if filter_mode == Some(FilterMode::Prefilter) {
+            return flat::search_f32(&dataset, self, query_vector).await;
+        }
+        if let Some(probed_rows) = self.widened_probes(&dataset, query_vector).await? {
+            return self
+                .search_widening_probes(dataset, query_vector, &probed_rows)
+                .await;
+        }
+        let result = self.search_dataset(dataset.clone(), query_vector).await;
+        let fallback = self.allow_index_fallback && self.use_index;
+        let (Err(err), true, QueryTarget::Dataset(target)) = (&result, fallback, &self.target)
+        else {
+            return result;
+        };
+        let Some((index, reason)) = target.unhealthy_index(&dataset, &self.column).await? else {
+            return result;
+        };
+        warn!(
+            column = %self.column,
+            %index,
+            %reason,
+            error = %err,
+            "the index cannot be searched, falling back to a flat search"
+        );
+        self.clone()
+            .use_index(false)
+            .search_dataset(dataset, query_vector)
+            .await
+    }
+
+    /// The results of this lance search of `dataset`, of the vectors of the
+    /// dimension of the query.
+    async fn search_dataset(
+        &self,
+        dataset: Arc<Dataset>,
+        query_vector: &Float32Array,
+    ) -> Result<DatasetRecordBatchStream> {
+        let mut query = Cow::Borrowed(self);
+        let uncovering = match self.use_index {
+            true => self.uncovering_index(&dataset).await?,
+            false => None,
+        };
+        if let Some((index, row_filter)) = uncovering {
+            if self.require_covering_index {
+                return Err(Error::InvalidInput {
+                    message: format!(
+                        "the index '{index}' only has the rows matching '{row_filter}', which the filter of the query does not imply"
+                    ),
+                });
+            }
+            query = Cow::Owned(self.clone().use_index(false));
+        }
+        if let (true, QueryTarget::Dataset(target)) = (query.use_index, &query.target) {
+            target.check_index_dims(&dataset, &query.column).await?;
+        }
+        if query.pruned(&dataset).await? {
+            let scanner = query.scanner(dataset.clone(), query_vector, query.limit)?;
+            let schema = query.results_schema(&dataset, &scanner)?;
+            return Ok(batches_stream(schema, Vec::new()));
+        }
+        let fragments = query.matching_fragments(&dataset).await?;
+        query
+            .search_non_null(dataset, query_vector, fragments.map(|(f, _)| f))
+            .await
+    }
+
+    /// The fragments of `dataset` a flat search with the filter of this query
+    /// reads, and the number it leaves out. `None` if it reads all of them.
+    ///
+    /// The fragments of a partitioned table are chosen by their partition
+    /// values, without reading them, those of other tables by
+    /// [DatasetRef::matching_fragments].
+    ///
+    /// lance filters the nearest neighbors found by a search, the rows of the
+    /// fragments left out would only take the place of results. An index
+    /// search reads the index whatever the fragments, so it leaves none out.
+    async fn matching_fragments(
+        &self,
+        dataset: &Dataset,
+    ) -> Result<Option<(Vec<Fragment>, usize)>> {
+        let (Some(filter), QueryTarget::Dataset(target)) = (self.filter.as_ref(), &self.target)
+        else {
+            return Ok(None);
+        };
+        if self.query_vectors.is_some() || self.resolve_column(dataset)?.search != Search::Lance {
+            return Ok(None);
+        }
+        if dataset.get_fragments().len() < 2 || self.uses_index(dataset).await? {
+            return Ok(None);
+        }
+        if let Some(fragments) = target.partition_fragments(dataset, filter).await? {
+            return Ok(Some(fragments));
+        }
+        let schema = ArrowSchema::from(dataset.schema());
+        let filter = filter::lance_filter(filter, &schema)?;
+        target.matching_fragments(dataset, &filter).await
+    }
+
+    /// The fragments of a partitioned table that can have rows matching the
+    /// filter of this query of `dataset`, and the number left out. `None` if
+    /// the table is not partitioned.
+    ///
+    /// The rows of the other fragments do not match the filter, a search that
+    /// filters the rows before comparing their vectors, as [flat] does, finds
+    /// the same rows without them.
+    async fn partition_fragments(
+        &self,
+        dataset: &Dataset,
+    ) -> Result<Option<(Vec<Fragment>, usize)>> {
+        let (Some(filter), QueryTarget::Dataset(target)) = (self.filter.as_ref(), &self.target)
+        else {
+            return Ok(None);
+        };
+        target.partition_fragments(dataset, filter).await
+    }
+
+    /// The results of this lance search of `dataset`, without the rows of null
+    /// vectors. lance stores them as zeros and ranks them like the others, so
+    /// the search is repeated with a larger limit while they take the place of
+    /// results.
+    ///
+    /// Only `fragments` are searched if they are given.
+    async fn search_non_null(
+        &self,
+        dataset: Arc<Dataset>,
+        query_vector: &Float32Array,
+        fragments: Option<Vec<Fragment>>,
+    ) -> Result<DatasetRecordBatchStream> {
+        let no_fragments = fragments.as_ref().is_some_and(Vec::is_empty);
+        if no_fragments || dataset.get_fragments().is_empty() {
+            // lance fails the search of a table without data files.
+            let scanner = self.scanner(dataset.clone(), query_vector, self.limit)?;
+            let schema = self.results_schema(&dataset, &scanner)?;
+            return Ok(batches_stream(schema, Vec::new()));
+        }
+        let keep_marker = self.unselected_marker(&dataset).is_none();
+        let mut limit = self.limit;
+        loop {
+            let mut scanner = self.scanner(dataset.clone(), query_vector, limit)?;
+            if let Some(fragments) = fragments.as_ref() {
+                scanner.with_fragments(fragments.clone());
+            }
+            let schema = self.results_schema(&dataset, &scanner)?;
+            let batches: Vec<RecordBatch> = scanner.try_into_stream().await?.try_collect().await?;
+            let rows = batches.iter().map(RecordBatch::num_rows).sum::<usize>();
+            let (batches, nulls) =
+                drop_null_vectors(batches, &self.column, self.limit, keep_marker)?;
+            // Fewer rows than the limit were left by the filter, or are all there are.
+            if nulls == 0 || rows < limit || rows - nulls >= self.limit {
+                return Ok(batches_stream(schema, batches));
+            }
+            limit = (limit * 2).max(self.limit + nulls);
+        }
+    }
+
+    /// The column marking the null vectors of the searched column of `dataset`,
+    /// if this query reads it only to leave them out: its columns are selected
+    /// and do not include it.
+    fn unselected_marker(&self, dataset: &Dataset) -> Option<String> {
+        let select = self.select.as_ref()?;
+        marker_column_of(&ArrowSchema::from(dataset.schema()), &self.column)
+            .filter(|marker| !select.contains(marker))
+    }
+
+    /// The schema of the results of `scanner`, a scanner of `dataset` for this
+    /// query.
+    fn results_schema(&self, dataset: &Dataset, scanner: &Scanner) -> Result<SchemaRef> {
+        let schema = scanner.schema()?;
+        match self.unselected_marker(dataset) {
+            Some(marker) => without_field(schema, &marker),
+            None => Ok(schema),
+        }
+    }
+
+    /// Whether the filter of this lance search of `dataset` matches no row, by
+    /// the column statistics of the table.
+    async fn pruned(&self, dataset: &Dataset) -> Result<bool> {
+        let (Some(filter), QueryTarget::Dataset(target)) = (self.filter.as_ref(), &self.target)
+        else {
+            return Ok(false);
+        };
+        if self.query_vectors.is_some() || self.resolve_column(dataset)?.search != Search::Lance {
+            return Ok(false);
+        }
+        target.filter_is_empty(dataset, filter).await
+    }
+
+    /// The searched column in the schema of `dataset`, reused by the runs of a
+    /// [PreparedQuery] until the version of the table changes.
+    ///
+    /// The selected columns have to be in the schema.
+    pub(crate) fn resolve_column(&self, dataset: &Dataset) -> Result<ResolvedColumn> {
+        let version = dataset.version().version;
+        let cached = self
+            .resolved
+            .as_ref()
+            .and_then(|r| r.lock().unwrap().clone());
+        if let Some(column) = cached.filter(|c| c.version == version) {
+            return Ok(column);
+        }
+        let schema = ArrowSchema::from(dataset.schema());
+        let missing = self
+            .select
+            .iter()
+            .flatten()
+            .filter(|c| schema.field_with_name(c).is_err())
+            .map(|c| format!("'{c}'"))
+            .collect::<Vec<_>>();
+        if !missing.is_empty() {
+            return Err(Error::InvalidInput {
+                message: format!(
+                    "the selected columns {} are not in the table",
+                    missing.join(", ")
+                ),
+            });
+        }
+        let field = schema.field_with_name(&self.column).ok();
+        let search = match field {
+            Some(field) if flat::is_multivector(field) => Search::Multivector,
+            Some(field) if flat::is_f16_vector(field) => Search::F16,
+            _ => Search::Lance,
+        };
+        let dims = match field.map(|f| f.data_type()) {
+            Some(DataType::FixedSizeList(_, width)) => Some(*width as usize),
+            _ => None,
+        };
+        let column = ResolvedColumn {
+            version,
+            search,
+            dims,
+        };
+        if let Some(resolved) = self.resolved.as_ref() {
+            *resolved.lock().unwrap() = Some(column.clone());
+        }
+        Ok(column)
+    }
+
+    /// A scanner of `dataset` for the `limit` nearest neighbors of this query.
+    fn scanner(
+        &self,
+        dataset: Arc<Dataset>,
+        query_vector: &Float32Array,
+        limit: usize,
+    ) -> Result<Scanner> {
+        let mut scanner: Scanner = dataset.scan();
+
+        scanner.nearest(&self.column, query_vector, limit)?;
+        scanner.nprobs(self.nprobes);
+        scanner.use_index(self.use_index);
+        self.scan_params.apply(&mut scanner);
+        if let Some(columns) = self.select.as_ref() {
+            let mut columns = columns.clone();
+            columns.extend(self.unselected_marker(&dataset));
+            scanner.project(columns.as_slice())?;
+        }
+        if let Some(filter) = self.filter.as_ref() {
+            let schema = ArrowSchema::from(dataset.schema());
+            scanner.filter(&filter::lance_filter(filter, &schema)?)?;
+        }
+        if self.with_row_id {
+            scanner.with_row_id();
+        }
+        self.refine_factor.map(|rf| scanner.refine(rf));
+        self.metric_type.map(|mt| scanner.distance_metric(mt));
+        Ok(scanner)
+    }
+
+    /// Whether the search of `dataset` uses an ANN index.
+    async fn uses_index(&self, dataset: &Dataset) -> Result<bool> {
+        if !self.use_index || self.uncovering_index(dataset).await?.is_some() {
+            return Ok(false);
+        }
+        let field_id = dataset.schema().field(&self.column).map(|f| f.id);
+        Ok(dataset
+            .load_indices()
+            .await?
+            .iter()
+            .any(|index| field_id.is_some_and(|id| index.fields.contains(&id))))
+    }
+
+    /// The name and row filter of an index of the column that leaves out rows
+    /// matching the filter of this query, see
+    /// [crate::index::vector::IvfPQIndexBuilder::row_filter].
+    async fn uncovering_index(&self, dataset: &Dataset) -> Result<Option<(String, Arc<str>)>> {
+        let QueryTarget::Dataset(target) = &self.target else {
+            return Ok(None);
+        };
+        let filter = self.filter.as_deref();
+        target.uncovering_index(dataset, &self.column, filter).await
+    }
+
+    /// Whether this search of `dataset` uses an index of only the rows
+    /// matching a filter that its own filter implies.
+    async fn uses_partial_index(&self, dataset: &Dataset) -> Result<bool> {
+        let QueryTarget::Dataset(target) = &self.target else {
+            return Ok(false);
+        };
+        if !self.uses_index(dataset).await? {
+            return Ok(false);
+        }
+        let Some(field) = dataset.schema().field(&self.column) else {
+            return Ok(false);
+        };
+        for index in dataset.load_indices().await? {
+            if index.fields.contains(&field.id) && target.row_filter(&index).await?.is_some() {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Emit a debug event telling whether the search uses an ANN index.
+    async fn trace_index_selection(&self, dataset: &Dataset) -> Result<()> {
+        if !self.use_index {
+            debug!(column = %self.column, "flat search, the index is not used");
+        } else if let Some((index, row_filter)) = self.uncovering_index(dataset).await? {
+            debug!(
+                column = %self.column,
+                %index,
+                %row_filter,
+                "flat search, the filter does not imply the row filter of the index"
+            );
+        } else if self.uses_index(dataset).await? {
+            debug!(column = %self.column, "ANN search with the index");
+        } else {
+            debug!(column = %self.column, "flat search, the column has no index");
         }
+        Ok(())
     }
 
-    /// Execute the queries and return its results.
-    ///
-    /// # Returns
+    /// Set the maximum number of results to return, [DEFAULT_QUERY_LIMIT] by
+    /// default.
     ///
-    /// * A [DatasetRecordBatchStream] with the query's results.
-    pub async fn execute(&self) -> Result<DatasetRecordBatchStream> {
-        let mut scanner: Scanner = self.dataset.scan();
-
-        scanner.nearest(
-            crate::table::VECTOR_COLUMN_NAME,
-            &self.query_vector,
-            self.limit,
-        )?;
-        scanner.nprobs(self.nprobes);
-        scanner.use_index(self.use_index);
-        self.select.as_ref().map(|p| scanner.project(p.as_slice()));
-        self.filter.as_ref().map(|f| scanner.filter(f));
-        self.refine_factor.map(|rf| scanner.refine(rf));
-        self.metric_type.map(|mt| scanner.distance_metric(mt));
-        Ok(scanner.try_into_stream().await?)
-    }
-
-    /// Set the maximum number of results to return.
+    /// Queries of a limit of 0, or above the [Query::max_limit] of their
+    /// connection, fail with an [Error::InvalidInput].
     ///
     /// # Arguments
     ///
     /// * `limit` - The maximum number of results to return.
     pub fn limit(mut self, limit: usize) -> Query {
         self.limit = limit;
+        self.unlimited = false;
+        self
+    }
+
+    /// Return every matching row. Only scans may be unlimited: vector queries
+    /// rank the rows by distance and always return the rows of a
+    /// [Query::limit], they fail with an [Error::InvalidInput]. An unlimited
+    /// query is [Query::materialize]d by a scan of its table.
+    pub fn limit_none(mut self) -> Query {
+        self.unlimited = true;
         self
     }
 
@@ -98,6 +1756,82 @@ impl Query {
     /// * `vector` - The vector that will be used for search.
     pub fn query_vector(mut self, query_vector: Float32Array) -> Query {
         self.query_vector = query_vector;
+        self.query_text = None;
+        self.query_vectors = None;
+        self.query_row = None;
+        self
+    }
+
+    /// Search for a vector, or for a text embedded with the embedding function
+    /// of the searched column.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - A [Float32Array], a `Vec<f32>`, an `ndarray` vector with the
+    ///   `ndarray` feature, or a string.
+    pub fn nearest_to(self, query: impl Into<QueryVector>) -> Query {
+        match query.into() {
+            QueryVector::Vector(vector) => self.query_vector(vector),
+            QueryVector::Text(text) => self.nearest_to_text(&text),
+        }
+    }
+
+    /// Search for `text`, embedded with the embedding function of the searched
+    /// column when the query is executed.
+    ///
+    /// Executing the query fails if the column has no embedding function, or
+    /// one that does not embed text.
+    pub fn nearest_to_text(mut self, text: &str) -> Query {
+        self.query_text = Some(text.to_string());
+        self.query_vectors = None;
+        self.query_row = None;
+        self
+    }
+
+    /// Search for the vector of the row of `key_value` in `key_column`, looked
+    /// up in the searched column when the query is executed. If several rows
+    /// have the key, the vector of one of them is used.
+    ///
+    /// The rows of the key are left out of the results unless
+    /// [Query::exclude_query_row] is unset. Executing the query fails with an
+    /// [Error::RowNotFound] if no row has the key.
+    pub fn nearest_to_row(mut self, key_column: &str, key_value: impl Into<Literal>) -> Query {
+        self.query_row = Some(QueryRow {
+            key_column: key_column.to_string(),
+            key_value: key_value.into(),
+        });
+        self.query_text = None;
+        self.query_vectors = None;
+        self
+    }
+
+    /// Whether the rows of the key of [Query::nearest_to_row] are left out of
+    /// the results. Defaults to `true`.
+    pub fn exclude_query_row(mut self, exclude: bool) -> Query {
+        self.exclude_query_row = exclude;
+        self
+    }
+
+    /// Search a multivector column, of any number of vectors per row, for
+    /// `vectors`. The rows are scored as set by [Query::multivector_scoring].
+    ///
+    /// Multivector columns have no index, they are searched flat.
+    pub fn nearest_to_multi(mut self, vectors: Vec<Vec<f32>>) -> Query {
+        self.query_vectors = Some(vectors.into_iter().map(Float32Array::from).collect());
+        self.query_text = None;
+        self.query_row = None;
+        self
+    }
+
+    /// Set how a multivector query scores the rows. Defaults to [MultiVectorScoring::Sum].
+    pub fn multivector_scoring(mut self, scoring: MultiVectorScoring) -> Query {
+        self.multivector_scoring = scoring;
+        self
+    }
+
+    /// Set the vector column to search. Defaults to `vector`.
+    pub fn column(mut self, column: &str) -> Query {
+        self.column = column.to_string();
         self
     }
 
@@ -111,6 +1845,28 @@ impl Query {
         self
     }
 
+    /// Set the partitions every index search probes, the [Query::nprobes]
+    /// of the query. An unfiltered search probes this many, and a filtered
+    /// one at least this many, see [Query::maximum_nprobes].
+    pub fn minimum_nprobes(self, nprobes: usize) -> Query {
+        self.nprobes(nprobes)
+    }
+
+    /// Set the most partitions a filtered index search probes, `None` by
+    /// default to probe [Query::minimum_nprobes] partitions only.
+    ///
+    /// A postfiltered search of an IVF index probes the minimum partitions
+    /// first. While fewer than `limit` of the rows they have match the filter,
+    /// the search is run again probing twice as many partitions, up to the
+    /// maximum, and taking all the rows of the partitions probed as candidates
+    /// of the filter. The partitions probed are reported by
+    /// [QueryMetrics::index_partitions_probed]. Unfiltered and prefiltered
+    /// searches are not affected, nor is a maximum under the minimum.
+    pub fn maximum_nprobes(mut self, nprobes: Option<usize>) -> Query {
+        self.maximum_nprobes = nprobes;
+        self
+    }
+
     /// Set the refine factor to use.
     ///
     /// # Arguments
@@ -131,8 +1887,48 @@ impl Query {
         self
     }
 
+    /// Set how many fragments of a local table are read at the same time, from
+    /// 1 to [MAX_SCAN_PARALLELISM]. Higher values use more of the IOPS of fast
+    /// disks, lower values avoid throttling by object stores.
+    pub fn io_parallelism(mut self, io_parallelism: usize) -> Query {
+        self.scan_params.io_parallelism = Some(io_parallelism);
+        self
+    }
+
+    /// Set how many batches of each fragment are read ahead of the search,
+    /// from 1 to [MAX_SCAN_PARALLELISM].
+    pub fn batch_readahead(mut self, batch_readahead: usize) -> Query {
+        self.scan_params.batch_readahead = Some(batch_readahead);
+        self
+    }
+
+    /// Set the size of the blocks the small reads of the data and index files
+    /// of a table in an object store are served from, each block fetched with
+    /// one request. 0 reads each page with a request of its own.
+    pub fn io_coalesce_bytes(mut self, io_coalesce_bytes: usize) -> Query {
+        self.scan_params.io_coalesce_bytes = Some(io_coalesce_bytes);
+        self
+    }
+
+    /// Set the largest request to the object store of a table, larger reads
+    /// being split in concurrent requests.
+    pub fn io_max_request_size(mut self, io_max_request_size: usize) -> Query {
+        self.scan_params.io_max_request_size = Some(io_max_request_size);
+        self
+    }
+
+    /// Set how many requests to the object store of a table the query runs at
+    /// the same time, from 1 to [MAX_SCAN_PARALLELISM].
+    pub fn io_concurrency(mut self, io_concurrency: usize) -> Query {
+        self.scan_params.io_concurrency = Some(io_concurrency);
+        self
+    }
+
     /// Whether to use an ANN index if available
     ///
+    /// The search fails with an [Error::StaleIndex] if the index is of
+    /// another dimension than the vectors of the column.
+    ///
     /// # Arguments
     ///
     /// * `use_index` - Sets Whether to use an ANN index if available
@@ -141,16 +1937,199 @@ impl Query {
         self
     }
 
+    /// Fail a search with the index with an [Error::InvalidInput] when its
+    /// filter does not imply the row filter of the index of the column, see
+    /// [crate::index::vector::IvfPQIndexBuilder::row_filter]. By default such
+    /// searches are flat.
+    pub fn require_covering_index(mut self, require: bool) -> Query {
+        self.require_covering_index = require;
+        self
+    }
+
+    /// Search without the index when an index of the column cannot be
+    /// searched, because its files are missing or unreadable or it is of
+    /// another dimension than the column, emitting a warning event. By
+    /// default such searches fail.
+    ///
+    /// [crate::table::NativeTable::index_stats] reports the index as
+    /// [crate::table::IndexHealth::Unhealthy].
+    pub fn allow_index_fallback(mut self, allow: bool) -> Query {
+        self.allow_index_fallback = allow;
+        self
+    }
+
+    /// How the filter selects the results, see [FilterMode]. Default:
+    /// [FilterMode::Postfilter], the results of a search run before.
+    ///
+    /// [QueryMetrics::filter_mode] reports the mode chosen by
+    /// [FilterMode::Auto].
+    pub fn filter_mode(mut self, mode: FilterMode) -> Query {
+        self.filter_mode = mode;
+        self
+    }
+
+    /// The fraction of the rows below which a filter of [FilterMode::Auto]
+    /// is applied before the search. Default: [DEFAULT_PREFILTER_THRESHOLD].
+    pub fn prefilter_threshold(mut self, threshold: f64) -> Query {
+        self.prefilter_threshold = threshold;
+        self
+    }
+
+    /// The rows read to estimate the selectivity of a filter of
+    /// [FilterMode::Auto] when the column statistics do not, see
+    /// [crate::NativeTable::estimate_selectivity_with_sample]. Default:
+    /// [DEFAULT_SELECTIVITY_SAMPLE].
+    pub fn selectivity_sample(mut self, rows: usize) -> Query {
+        self.selectivity_sample = rows;
+        self
+    }
+
     ///  A filter statement to be applied to this query.
     ///
     /// # Arguments
     ///
     /// * `filter` -  value A filter in the same format used by a sql WHERE clause.
+    ///
+    /// The filter may select rows by the values in JSON documents with
+    /// [JSON_EXTRACT], such filters are evaluated by datafusion instead of lance.
+    /// Timestamp and date columns may be compared with ISO-8601 strings, `now()`
+    /// and intervals, as in `ts > now() - INTERVAL '7 days'`.
     pub fn filter(mut self, filter: Option<String>) -> Query {
         self.filter = filter;
         self
     }
 
+    /// A filter built from a [FilterExpr], replacing the filter of this query.
+    ///
+    /// Unlike a SQL string put together by hand, the columns and values of the
+    /// expression are quoted, so values with quotes or newlines are safe.
+    pub fn filter_expr(mut self, filter: FilterExpr) -> Query {
+        self.filter = Some(filter.to_string());
+        self
+    }
+
+    /// Whether the results have a `_rowid` column of the ids of their rows, see
+    /// [crate::database::CreateTableBuilder::enable_stable_row_ids].
+    ///
+    /// The flat searches of float16 and multivector columns have no row ids.
+    pub fn with_row_id(mut self, with_row_id: bool) -> Query {
+        self.with_row_id = with_row_id;
+        self
+    }
+
+    /// Whether the results have an [IVF_PARTITION_COLUMN] column of the
+    /// partition of the IVF index of the column each row is in, to tell which
+    /// partitions the nearest rows are in, by a flat search, and which an
+    /// index search probed.
+    ///
+    /// The partition of a row is that of the nearest centroid to its vector,
+    /// see [crate::table::NativeTable::assign_partition]. It is null for the
+    /// rows appended since the index was trained. Fails with an
+    /// [Error::InvalidInput] if the column has no IVF index, or if the table
+    /// is remote.
+    pub fn with_partition_id(mut self, with_partition_id: bool) -> Query {
+        self.with_partition_id = with_partition_id;
+        self
+    }
+
+    /// Whether the results keep the rows at a NaN or infinite distance of the
+    /// query vector, `false` by default.
+    ///
+    /// The stored vectors with NaN or infinite values, which the
+    /// [crate::table::OnBadVectors] policy keeps out of the writes of the
+    /// table, are at such distances, and a NaN distance may be ranked before
+    /// the finite ones. By default their rows are left out of the results of
+    /// the flat and index searches alike, which search for more rows to
+    /// return `limit` rows of finite distances if there are that many.
+    pub fn allow_nonfinite(mut self, allow: bool) -> Query {
+        self.allow_nonfinite = allow;
+        self
+    }
+
+    /// Fail the query with an [Error::ResultTooLarge] if its results take an
+    /// estimated more than `bytes`, no cap by default.
+    ///
+    /// The results are collected one batch at a time, as they are read, and
+    /// the query fails as soon as the batches collected take more than `bytes`,
+    /// without reading the others. The size of a batch is that of the values
+    /// of its columns, the bytes of the strings and binaries of its
+    /// variable-width columns included.
+    pub fn max_result_bytes(mut self, bytes: usize) -> Query {
+        self.max_result_bytes = Some(bytes);
+        self
+    }
+
+    /// Whether the rows of the same distance are returned in the order of
+    /// their row ids, `false` by default.
+    ///
+    /// The results of a query are always ordered by distance. lance reads the
+    /// fragments of a table, and the partitions an index probes, concurrently,
+    /// and the unordered rows of the same distance come in the order they were
+    /// read, which changes from run to run and with [Query::io_parallelism],
+    /// as do the rows of the distance of the last one kept at the limit. An
+    /// index probes the same [Query::nprobes] partitions every time, those of
+    /// the centroids nearest the query vector. An ordered query returns the
+    /// same rows in the same order every time it runs against a version of
+    /// its table, and at the limit keeps the ties of the smallest row ids. It
+    /// searches for more rows than its limit while rows of the same distance
+    /// may be left out, and a postfiltered query searches twice, with and
+    /// without its filter, so queries are only ordered when asked to be. A
+    /// [Query::with_executor] orders the rows it returns itself.
+    ///
+    /// The order may still change between versions: compacting or updating
+    /// rows gives them new row ids, and training an index again gives
+    /// other approximate distances. With a [Query::refine_factor], the rows of the
+    /// same approximate distance at the end of those refined may differ, and
+    /// the flat searches without row ids, see [Query::with_row_id], keep their
+    /// ties in the order they were read.
+    pub fn ordered(mut self, ordered: bool) -> Query {
+        self.ordered = ordered;
+        self
+    }
+
+    /// Return only the nearest row of each value of `column`, for example one
+    /// chunk of each document, up to the limit of the query.
+    ///
+    /// The rows where `column` is null have no value in common, and are all
+    /// returned. The query searches for [Query::distinct_overfetch] times its
+    /// limit, and again for more rows while fewer than the limit have distinct
+    /// values. Fails with an [Error::InvalidInput] if the table has no
+    /// `column`.
+    pub fn distinct_on(mut self, column: &str) -> Query {
+        self.distinct_on = Some(column.to_string());
+        self
+    }
+
+    /// Set how many rows a [Query::distinct_on] query searches for, as a
+    /// multiple of its limit, [DEFAULT_DISTINCT_OVERFETCH] by default.
+    ///
+    /// A larger factor finds enough distinct values with fewer searches
+    /// when many rows share a value.
+    pub fn distinct_overfetch(mut self, factor: usize) -> Query {
+        self.distinct_overfetch = factor;
+        self
+    }
+
+    /// Search the column `fast_column` of truncated vectors, see
+    /// [crate::NativeTable::add_truncated_vector_column], for `over_fetch`
+    /// times the limit of the query, and return the nearest of them by the
+    /// exact distance of their vectors of `full_column`.
+    ///
+    /// The query vector, or the embedding of the query text, is a vector of
+    /// `full_column`, truncated and normalized to search `fast_column`. The
+    /// other settings of the query apply to the search of `fast_column`, and
+    /// the metric type to both. The results are ranked by the distances of
+    /// the full vectors, which replace those of the truncated vectors.
+    pub fn two_stage(mut self, fast_column: &str, full_column: &str, over_fetch: usize) -> Query {
+        self.column = full_column.to_string();
+        self.two_stage = Some(TwoStage {
+            fast_column: fast_column.to_string(),
+            full_column: full_column.to_string(),
+            over_fetch,
+        });
+        self
+    }
+
     /// Return only the specified columns.
     ///
     /// Only select the specified columns. If not specified, all columns will be returned.
@@ -158,19 +2137,86 @@ impl Query {
         self.select = columns;
         self
     }
+
+    /// Add the string column `name` to the results, of the values at `path` of
+    /// the JSON documents of `column`, as [JSON_EXTRACT] returns them.
+    ///
+    /// The column of the documents is read even if it is not selected. An
+    /// invalid path fails the execution of the query with an
+    /// [Error::InvalidInput].
+    pub fn select_json_path(mut self, name: &str, column: &str, path: &str) -> Query {
+        self.json_paths.push(JsonPathColumn {
+            name: name.to_string(),
+            column: column.to_string(),
+            path: path.to_string(),
+        });
+        self
+    }
+}
+
+/// A query with a [JSON_EXTRACT] filter or [JsonPathColumn]s, run by lance
+/// without them.
+struct JsonSearch {
+    /// The query run by lance, without the filter and reading the columns of
+    /// the filter and the paths.
+    query: Query,
+    filter: Option<JsonFilter>,
+    paths: Vec<JsonPathColumn>,
+    /// The columns read for the filter and the paths only.
+    added: Vec<String>,
+}
+
+impl JsonSearch {
+    /// The schema of the results, from the `schema` of the lance search.
+    fn schema(&self, schema: &SchemaRef) -> Result<SchemaRef> {
+        let empty = RecordBatch::new_empty(schema.clone());
+        Ok(self.project(project_paths(&empty, &self.paths)?)?.schema())
+    }
+
+    /// The results of the query from the results `batches` of the lance search.
+    async fn finish(&self, batches: Vec<RecordBatch>) -> Result<Vec<RecordBatch>> {
+        let batches = match self.filter.as_ref() {
+            Some(filter) => filter.apply(batches).await?,
+            None => batches,
+        };
+        batches
+            .iter()
+            .map(|batch| self.project(project_paths(batch, &self.paths)?))
+            .collect()
+    }
+
+    /// `batch` without the columns added to the lance search.
+    fn project(&self, batch: RecordBatch) -> Result<RecordBatch> {
+        let schema = batch.schema();
+        let indices = (0..schema.fields().len())
+            .filter(|i| !self.added.contains(schema.field(*i).name()))
+            .collect::<Vec<_>>();
+        Ok(batch.project(&indices).map_err(lance::Error::from)?)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use std::sync::Arc;
 
-    use arrow_array::{Float32Array, RecordBatch, RecordBatchReader};
+    use arrow_array::{
+        FixedSizeListArray, Float32Array, Int32Array, RecordBatch, RecordBatchReader, StringArray,
+    };
     use arrow_schema::{DataType, Field as ArrowField, Schema as ArrowSchema};
+    use futures::TryStreamExt;
     use lance::arrow::RecordBatchBuffer;
     use lance::dataset::Dataset;
+    use lance::index::vector::ivf::IvfBuildParams;
+    use lance::index::vector::pq::PQBuildParams;
     use lance::index::vector::MetricType;
 
-    use crate::query::Query;
+    use crate::arrow::{IntoArrow, RecordBatchBuilder};
+    use crate::database::connect;
+    use crate::error::Error;
+    use crate::index::vector::IvfPQIndexBuilder;
+    use crate::query::{col, lit, Query, ScanParams, DEFAULT_QUERY_LIMIT, MAX_SCAN_PARALLELISM};
+    use crate::table::{NativeTable, OpenTableParams};
+    use crate::testing::TestTableBuilder;
 
     #[tokio::test]
     async fn test_setters_getters() {
@@ -196,7 +2242,7 @@ mod tests {
         assert_eq!(query.query_vector, new_vector);
         assert_eq!(query.limit, 100);
         assert_eq!(query.nprobes, 1000);
-        assert_eq!(query.use_index, true);
+        assert!(query.use_index);
         assert_eq!(query.metric_type, Some(MetricType::Cosine));
         assert_eq!(query.refine_factor, Some(999));
     }
@@ -211,7 +2257,612 @@ mod tests {
         let vector = Float32Array::from_iter_values([0.1; 128]);
         let query = Query::new(Arc::new(ds), vector.clone());
         let result = query.execute().await;
-        assert_eq!(result.is_ok(), true);
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_execute_dimension_mismatch() {
+        let mut batches: Box<dyn RecordBatchReader> = Box::new(make_test_batches());
+        let ds = Dataset::write(&mut batches, "memory://foo", None)
+            .await
+            .unwrap();
+
+        let query = Query::new(Arc::new(ds), Float32Array::from_iter_values([0.1; 16]));
+        assert!(matches!(
+            query.execute().await.err().unwrap(),
+            Error::EmbeddingDimensionMismatch {
+                expected: 128,
+                got: 16,
+                ..
+            }
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_tracing_spans() {
+        let collector = collector::Collector::default();
+        let _guard = tracing::subscriber::set_default(collector.clone());
+
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let batches = RecordBatchBuilder::new()
+            .vector_column("vector", 2, vec![[0.0, 1.0], [1.0, 0.0], [1.0, 1.0]])
+            .build()
+            .unwrap()
+            .into_arrow(None)
+            .unwrap();
+        let table = NativeTable::create(tmp_dir.path().to_str().unwrap(), "test", batches, None)
+            .await
+            .unwrap();
+        let batches = RecordBatchBuilder::new()
+            .vector_column("vector", 2, vec![[2.0, 2.0]])
+            .build()
+            .unwrap()
+            .into_arrow(None)
+            .unwrap();
+        assert_eq!(table.add(batches, None).await.unwrap(), 1);
+        let add = collector.span("add").unwrap();
+        assert_eq!(add["table"], "test");
+        assert_eq!(add["rows"], "1");
+        assert!(add.contains_key("elapsed_ms"));
+
+        table
+            .search(vec![0.0, 0.0])
+            .limit(2)
+            .nprobes(5)
+            .filter(Some("true".to_string()))
+            .execute()
+            .await
+            .unwrap();
+        let query = collector.span("query").unwrap();
+        assert_eq!(query["table"], "test");
+        assert_eq!(query["column"], "vector");
+        assert_eq!(query["limit"], "2");
+        assert_eq!(query["nprobes"], "5");
+        assert_eq!(query["filter"], "true");
+        assert!(query.contains_key("elapsed_ms"));
+
+        table
+            .search(vec![0.0, 0.0])
+            .use_index(true)
+            .execute()
+            .await
+            .unwrap();
+        assert_eq!(
+            collector.events(),
+            vec![
+                "flat search, the index is not used",
+                "flat search, the column has no index"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_execute_with_metrics() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let table = TestTableBuilder::new(512)
+            .create(tmp_dir.path().to_str().unwrap(), "test")
+            .await
+            .unwrap();
+
+        let (batches, metrics) = table
+            .search(vec![0.5; 16])
+            .limit(20)
+            .filter(Some("id % 2 = 0".to_string()))
+            .execute_with_metrics()
+            .await
+            .unwrap();
+        let rows: usize = batches.iter().map(RecordBatch::num_rows).sum();
+        assert_eq!(metrics.rows_after_filter, rows);
+        assert_eq!(metrics.rows_scanned, 20);
+        assert!(rows <= 20);
+        assert_eq!(metrics.distance_computations, Some(512));
+        assert_eq!(metrics.index_partitions_probed, None);
+        assert_eq!(metrics.io_bytes_read, None);
+        assert_eq!(metrics.io_requests, None);
+        assert!(metrics.execute_ms > 0.0);
+
+        let builder = IvfPQIndexBuilder::new()
+            .ivf_params(IvfBuildParams::new(2))
+            .pq_params(PQBuildParams::default());
+        table.create_index(&builder).await.unwrap();
+        let (batches, metrics) = table
+            .search(vec![0.5; 16])
+            .use_index(true)
+            .nprobes(1)
+            .limit(5)
+            .execute_with_metrics()
+            .await
+            .unwrap();
+        assert_eq!(batches.iter().map(RecordBatch::num_rows).sum::<usize>(), 5);
+        assert_eq!(metrics.rows_scanned, 5);
+        assert!(metrics.index_partitions_probed.unwrap() <= 1);
+        assert_eq!(metrics.distance_computations, None);
+
+        let json = serde_json::to_value(&metrics).unwrap();
+        assert_eq!(json["rows_after_filter"], 5);
+        assert_eq!(json["index_partitions_probed"], 1);
+    }
+
+    #[tokio::test]
+    async fn test_scan_params() {
+        let collector = collector::Collector::default();
+        let _guard = tracing::subscriber::set_default(collector.clone());
+
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        let batches = RecordBatchBuilder::new()
+            .vector_column("vector", 2, vec![[0.0, 1.0], [1.0, 0.0], [1.0, 1.0]])
+            .build()
+            .unwrap()
+            .into_arrow(None)
+            .unwrap();
+        NativeTable::create(uri, "test", batches, None)
+            .await
+            .unwrap();
+        let params = OpenTableParams {
+            scan_params: ScanParams {
+                io_parallelism: Some(2),
+                batch_readahead: Some(3),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let table = NativeTable::open_with_params(uri, "test", params)
+            .await
+            .unwrap();
+
+        table.search(vec![0.0, 0.0]).execute().await.unwrap();
+        let query = collector.span("query").unwrap();
+        assert_eq!(query["io_parallelism"], "2");
+        assert_eq!(query["batch_readahead"], "3");
+
+        table
+            .search(vec![0.0, 0.0])
+            .io_parallelism(8)
+            .execute()
+            .await
+            .unwrap();
+        let query = collector.span("query").unwrap();
+        assert_eq!(query["io_parallelism"], "8");
+        assert_eq!(query["batch_readahead"], "3");
+
+        let db = connect(uri).batch_readahead(5).execute().await.unwrap();
+        let table = db.open_table("test").await.unwrap();
+        table.search(vec![0.0, 0.0].into()).execute().await.unwrap();
+        let query = collector.span("query").unwrap();
+        assert_eq!(query["batch_readahead"], "5");
+        assert!(!query.contains_key("io_parallelism"));
+
+        for query in [
+            table.search(vec![0.0, 0.0].into()).io_parallelism(0),
+            table
+                .search(vec![0.0, 0.0].into())
+                .batch_readahead(MAX_SCAN_PARALLELISM + 1),
+        ] {
+            assert!(matches!(
+                query.execute().await.err().unwrap(),
+                Error::InvalidInput { .. }
+            ));
+        }
+        assert!(matches!(
+            connect(uri)
+                .io_parallelism(0)
+                .execute()
+                .await
+                .err()
+                .unwrap(),
+            Error::InvalidInput { .. }
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_limits() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        let vectors = (0..30).map(|i| [i as f32, 1.0]).collect::<Vec<_>>();
+        let batches = RecordBatchBuilder::new()
+            .vector_column("vector", 2, vectors)
+            .build()
+            .unwrap()
+            .into_arrow(None)
+            .unwrap();
+        NativeTable::create(uri, "test", batches, None)
+            .await
+            .unwrap();
+        let db = connect(uri).max_limit(20).execute().await.unwrap();
+        let table = db.open_table("test").await.unwrap();
+        let rows = |query: Query| async move {
+            let stream = query.execute().await?;
+            let batches = stream.try_collect::<Vec<_>>().await?;
+            Ok::<_, Error>(batches.iter().map(RecordBatch::num_rows).sum::<usize>())
+        };
+
+        let search = || table.search(vec![0.0, 1.0].into());
+        assert_eq!(search().max_limit, Some(20));
+        assert_eq!(rows(search()).await.unwrap(), DEFAULT_QUERY_LIMIT);
+        assert_eq!(rows(search().limit(1)).await.unwrap(), 1);
+        assert_eq!(rows(search().limit(20)).await.unwrap(), 20);
+
+        let err = rows(search().limit(21)).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "LanceDBError: Invalid input: the limit 21 is above the maximum of 20 of this connection"
+        );
+        let err = search().limit(21).execute_with_metrics().await.unwrap_err();
+        assert!(matches!(err, Error::InvalidInput { .. }), "{err}");
+        let err = rows(search().limit(0)).await.unwrap_err();
+        assert!(err.to_string().contains("at least 1"), "{err}");
+        let err = rows(search().limit_none()).await.unwrap_err();
+        assert!(err.to_string().contains("limit_none"), "{err}");
+        // A later limit replaces limit_none.
+        assert_eq!(rows(search().limit_none().limit(3)).await.unwrap(), 3);
+
+        // Tables opened without the connection are not capped.
+        let table = NativeTable::open(uri, "test").await.unwrap();
+        let query = table.search(vec![0.0, 1.0]).limit(25);
+        assert_eq!(rows(query).await.unwrap(), 25);
+
+        let err = connect(uri).max_limit(0).execute().await.err().unwrap();
+        assert!(matches!(err, Error::InvalidInput { .. }), "{err}");
+    }
+
+    #[tokio::test]
+    async fn test_io_parallelism() {
+        let collector = collector::Collector::default();
+        let _guard = tracing::subscriber::set_default(collector.clone());
+
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        let table = TestTableBuilder::new(2000)
+            .dims(16)
+            .fragments(20)
+            .create(uri, "test")
+            .await
+            .unwrap();
+
+        // The fragments read one at a time or concurrently give the same
+        // results, every fragment is searched either way.
+        let mut results = vec![];
+        for io_parallelism in [1, 8] {
+            let (batches, metrics) = table
+                .search(vec![0.5; 16])
+                .io_parallelism(io_parallelism)
+                .execute_with_metrics()
+                .await
+                .unwrap();
+            let query = collector.span("query").unwrap();
+            assert_eq!(query["io_parallelism"], io_parallelism.to_string());
+            assert_eq!(metrics.distance_computations, Some(2000));
+            results.push(batches);
+        }
+        assert_eq!(results[0], results[1]);
+    }
+
+    #[tokio::test]
+    async fn test_fragment_pruning() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        let rows = |ids: std::ops::Range<i32>| {
+            RecordBatchBuilder::new()
+                .column("id", Arc::new(Int32Array::from_iter_values(ids.clone())))
+                .vector_column("vector", 2, ids.map(|i| [i as f32, i as f32]))
+                .build()
+                .unwrap()
+                .into_arrow(None)
+                .unwrap()
+        };
+        // The ids of the rows of a fragment are in a range of their own.
+        let table = NativeTable::create(uri, "fragments", rows(0..100), None)
+            .await
+            .unwrap();
+        table.add(rows(100..200), None).await.unwrap();
+        table.add(rows(200..300), None).await.unwrap();
+        assert_eq!(table.stats().await.unwrap().fragments, 3);
+        let single = NativeTable::create(uri, "single", rows(0..300), None)
+            .await
+            .unwrap();
+
+        for (filter, skipped, searched) in [
+            ("id >= 100 AND id < 200", 2, 100),
+            ("id = 150 OR id = 250", 1, 200),
+            ("id > 100 AND id < 101", 3, 0),
+            ("id % 2 = 0", 0, 300),
+        ] {
+            let query = |table: &NativeTable| {
+                table
+                    .search(vec![150.2, 150.2])
+                    .filter(Some(filter.to_string()))
+                    .limit(10)
+            };
+            let (batches, metrics) = query(&table).execute_with_metrics().await.unwrap();
+            let (expected, unpruned) = query(&single).execute_with_metrics().await.unwrap();
+            assert_eq!(batches, expected, "{filter}");
+            assert_eq!(metrics.fragments_skipped, skipped, "{filter}");
+            assert_eq!(metrics.distance_computations, Some(searched), "{filter}");
+            assert_eq!(unpruned.fragments_skipped, 0);
+            assert_eq!(unpruned.distance_computations, Some(300));
+            let streamed = query(&table)
+                .execute()
+                .await
+                .unwrap()
+                .try_collect::<Vec<_>>()
+                .await
+                .unwrap();
+            assert_eq!(streamed, batches, "{filter}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_json_metadata() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        let metadata = (0..10).map(|i| {
+            serde_json::json!({
+                "lang": if i % 2 == 0 { "en" } else { "fr" },
+                "source": {"year": 2015 + i, "tags": [format!("t{}", i % 3)]},
+            })
+        });
+        let batch = RecordBatchBuilder::new()
+            .column("id", Arc::new(Int32Array::from_iter_values(0..10)))
+            .json_column("metadata", metadata)
+            .vector_column("vector", 2, (0..10).map(|i| [i as f32, 1.0]))
+            .build()
+            .unwrap();
+        let batches: Box<dyn RecordBatchReader> = Box::new(RecordBatchBuffer::new(vec![batch]));
+        let table = NativeTable::create(uri, "docs", batches, None)
+            .await
+            .unwrap();
+        let search = || table.search(vec![0.0, 1.0]).limit(10);
+        let execute = |query: Query| async move {
+            let batches: Vec<RecordBatch> =
+                query.execute().await.unwrap().try_collect().await.unwrap();
+            batches
+        };
+        let ids = |batches: &[RecordBatch]| {
+            batches
+                .iter()
+                .flat_map(|b| {
+                    let ids = b["id"].as_any().downcast_ref::<Int32Array>().unwrap();
+                    ids.values().to_vec()
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let english = search().filter(Some("json_extract(metadata, '$.lang') = 'en'".to_string()));
+        assert_eq!(ids(&execute(english.clone()).await), vec![0, 2, 4, 6, 8]);
+        let nested = "CAST(json_extract(metadata, '$.source.year') AS INT) >= 2020 \
+            AND json_extract(metadata, '$.lang') = 'fr' AND id < 9";
+        let batches = execute(
+            search()
+                .filter(Some(nested.to_string()))
+                .select(Some(vec!["id".to_string()])),
+        )
+        .await;
+        assert_eq!(ids(&batches), vec![5, 7]);
+        // The documents were read for the filter only.
+        assert!(batches[0].column_by_name("metadata").is_none());
+        let tagged = search()
+            .filter(Some(
+                "json_extract(metadata, '$.source.tags[0]') = 't1'".to_string(),
+            ))
+            .limit(3);
+        // Filtered after the search, as lance filters: 4 is not among the 3 nearest.
+        assert_eq!(ids(&execute(tagged).await), vec![1]);
+
+        let projected = search()
+            .filter_expr(col("id").lt(lit(3)))
+            .select(Some(vec!["id".to_string()]))
+            .select_json_path("year", "metadata", "$.source.year")
+            .select_json_path("tag", "metadata", "$.source.tags[0]");
+        let batches = execute(projected.clone()).await;
+        let schema = batches[0].schema();
+        let names = schema
+            .fields()
+            .iter()
+            .map(|f| f.name().as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["id", "vector", "score", "year", "tag"]);
+        let years = batches[0]["year"]
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .unwrap();
+        assert_eq!(
+            years.iter().collect::<Vec<_>>(),
+            vec![Some("2015"), Some("2016"), Some("2017")]
+        );
+        let tags = batches[0]["tag"]
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .unwrap();
+        assert_eq!(tags.value(2), "t2");
+        use lance::io::RecordBatchStream;
+        let stream = projected.execute().await.unwrap();
+        assert_eq!(stream.schema().fields().len(), 5);
+
+        let batches = execute(english.clone().select(Some(vec!["metadata".to_string()]))).await;
+        let documents = crate::arrow::parse_json(batches[0]["metadata"].as_ref()).unwrap();
+        assert_eq!(documents[1].as_ref().unwrap()["source"]["year"], 2017);
+
+        let (batches, metrics) = english.execute_with_metrics().await.unwrap();
+        assert_eq!(ids(&batches), vec![0, 2, 4, 6, 8]);
+        assert_eq!((metrics.rows_scanned, metrics.rows_after_filter), (10, 5));
+
+        let invalid = search().filter(Some("json_extract(metadata, 'lang') = 'en'".to_string()));
+        let err = invalid.execute().await.err().unwrap();
+        assert!(matches!(err, Error::InvalidInput { .. }), "{err}");
+    }
+
+    #[tokio::test]
+    async fn test_nearest_to_row() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        let names = (0..20).map(|i| format!("item {i}")).collect::<Vec<_>>();
+        let batch = RecordBatchBuilder::new()
+            .column("id", Arc::new(Int32Array::from_iter_values(0..20)))
+            .column("name", Arc::new(StringArray::from(names)))
+            .vector_column(
+                "vector",
+                2,
+                (0..20).map(|i| [i as f32, (i % 3) as f32 + 1.0]),
+            )
+            .build()
+            .unwrap();
+        let batches: Box<dyn RecordBatchReader> = Box::new(RecordBatchBuffer::new(vec![batch]));
+        let table = NativeTable::create(uri, "items", batches, None)
+            .await
+            .unwrap();
+        let ids = |query: Query| async move {
+            let batches: Vec<RecordBatch> =
+                query.execute().await.unwrap().try_collect().await.unwrap();
+            batches
+                .iter()
+                .flat_map(|b| {
+                    let ids = b["id"].as_any().downcast_ref::<Int32Array>().unwrap();
+                    ids.values().to_vec()
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // The vector of the row, then a search for it without the row.
+        let two_step = |filter: Option<String>| {
+            let table = table.clone();
+            async move {
+                let rows: Vec<RecordBatch> = table
+                    .search(vec![0.0, 0.0])
+                    .filter(Some("id = 7".to_string()))
+                    .limit(20)
+                    .execute()
+                    .await
+                    .unwrap()
+                    .try_collect()
+                    .await
+                    .unwrap();
+                let vectors = rows[0]["vector"]
+                    .as_any()
+                    .downcast_ref::<FixedSizeListArray>()
+                    .unwrap()
+                    .value(0);
+                let vector = vectors.as_any().downcast_ref::<Float32Array>().unwrap();
+                let query = table.search(vector.clone()).limit(5).filter(filter);
+                let mut found = ids(query).await;
+                found.retain(|id| *id != 7);
+                found.truncate(4);
+                found
+            }
+        };
+        let query = table
+            .search(vec![0.0, 0.0])
+            .nearest_to_row("id", 7)
+            .limit(4);
+        let found = ids(query.clone()).await;
+        assert_eq!(found.len(), 4);
+        assert!(!found.contains(&7), "{found:?}");
+        assert_eq!(found, two_step(None).await);
+
+        let filtered = query.clone().filter_expr(col("id").gt(lit(5)));
+        let filter = Some("id > 5".to_string());
+        assert_eq!(ids(filtered).await, two_step(filter).await);
+        let by_name = table
+            .search(vec![0.0, 0.0])
+            .nearest_to_row("name", "item 7")
+            .limit(4);
+        assert_eq!(ids(by_name).await, found);
+        let with_seed = ids(query.clone().exclude_query_row(false)).await;
+        assert_eq!(with_seed[0], 7);
+        assert_eq!(with_seed[1..], found[..3]);
+
+        let (batches, metrics) = query.execute_with_metrics().await.unwrap();
+        assert_eq!(batches.iter().map(RecordBatch::num_rows).sum::<usize>(), 4);
+        assert_eq!(metrics.rows_after_filter, 4);
+
+        let missing = query.clone().nearest_to_row("id", 42).execute().await;
+        assert!(
+            matches!(missing, Err(Error::RowNotFound { ref column, ref key }) if column == "id" && key == "42")
+        );
+        // A later vector replaces the row.
+        assert_eq!(
+            ids(query.query_vector(Float32Array::from(vec![7.0, 2.0]))).await[0],
+            7
+        );
+    }
+
+    /// A tracing subscriber keeping the fields of all spans and the messages of all events.
+    mod collector {
+        use std::collections::HashMap;
+        use std::fmt::Debug;
+        use std::sync::{Arc, Mutex};
+
+        use tracing::field::{Field, Visit};
+        use tracing::span::{Attributes, Id, Record};
+        use tracing::{Event, Metadata, Subscriber};
+
+        type Fields = HashMap<String, String>;
+
+        #[derive(Default, Clone)]
+        pub(super) struct Collector {
+            spans: Arc<Mutex<Vec<(&'static str, Fields)>>>,
+            events: Arc<Mutex<Vec<String>>>,
+        }
+
+        impl Collector {
+            /// The fields of the last span named `name`.
+            pub(super) fn span(&self, name: &str) -> Option<Fields> {
+                let spans = self.spans.lock().unwrap();
+                let span = spans.iter().rev().find(|(n, _)| *n == name);
+                span.map(|(_, fields)| fields.clone())
+            }
+
+            pub(super) fn events(&self) -> Vec<String> {
+                self.events.lock().unwrap().clone()
+            }
+        }
+
+        struct Visitor<'a>(&'a mut Fields);
+
+        impl Visit for Visitor<'_> {
+            fn record_str(&mut self, field: &Field, value: &str) {
+                self.0.insert(field.name().to_string(), value.to_string());
+            }
+
+            fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+                self.0
+                    .insert(field.name().to_string(), format!("{value:?}"));
+            }
+        }
+
+        impl Subscriber for Collector {
+            fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+                true
+            }
+
+            fn new_span(&self, span: &Attributes<'_>) -> Id {
+                let mut fields = Fields::new();
+                span.record(&mut Visitor(&mut fields));
+                let mut spans = self.spans.lock().unwrap();
+                spans.push((span.metadata().name(), fields));
+                Id::from_u64(spans.len() as u64)
+            }
+
+            fn record(&self, span: &Id, values: &Record<'_>) {
+                let mut spans = self.spans.lock().unwrap();
+                let (_, fields) = &mut spans[span.into_u64() as usize - 1];
+                values.record(&mut Visitor(fields));
+            }
+
+            fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+            fn event(&self, event: &Event<'_>) {
+                let mut fields = Fields::new();
+                event.record(&mut Visitor(&mut fields));
+                if let Some(message) = fields.remove("message") {
+                    self.events.lock().unwrap().push(message);
+                }
+            }
+
+            fn enter(&self, _span: &Id) {}
+
+            fn exit(&self, _span: &Id) {}
+        }
     }
 
     fn make_test_batches() -> RecordBatchBuffer {
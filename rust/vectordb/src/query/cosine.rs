@@ -0,0 +1,323 @@
+// Copyright 2023 LanceDB Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The cosine searches of the vector columns with stored norms, see
+//! [crate::NativeTable::store_vector_norms].
+//!
+//! The distance of a row is `1 - q·v / (|q| |v|)`, with the norm `|v|` of its
+//! vector read from the column of norms. The flat searches compare all the
+//! rows matching the filter of the query, and the index searches with a
+//! [Query::refine_factor] rank the `limit * refine_factor` candidates of the
+//! index by it. The index searches without a refine factor return the
+//! approximate distances of the index.
+
+use std::sync::Arc;
+
+use arrow_array::cast::as_primitive_array;
+use arrow_array::types::Float32Type;
+use arrow_array::{Array, FixedSizeListArray, Float32Array, RecordBatch};
+use datafusion::arrow::compute::concat_batches;
+use futures::TryStreamExt;
+use lance::arrow::RecordBatchExt;
+use lance::dataset::scanner::DatasetRecordBatchStream;
+use lance::dataset::Dataset;
+use lance::index::vector::MetricType;
+use lance::io::RecordBatchStream;
+use lance::linalg::dot::dot;
+use lance::linalg::norm_l2::norm_l2;
+
+use super::flat::{nearest, scan};
+use super::{FilterMode, Query, QueryTarget, DISTANCE_COLUMN};
+use crate::error::{Error, Result};
+
+/// The cosine distances of the vectors of `column` in `batch` to
+/// `query_vector`, by the norms of `norms`. Null for the rows of a null vector
+/// or norm.
+fn cosine_distances(
+    batch: &RecordBatch,
+    column: &str,
+    norms: &str,
+    query_vector: &Float32Array,
+) -> Result<Float32Array> {
+    let vectors = batch
+        .column_by_name(column)
+        .and_then(|c| c.as_any().downcast_ref::<FixedSizeListArray>())
+        .ok_or_else(|| Error::InvalidInput {
+            message: format!("the column '{column}' is not a float32 vector column"),
+        })?;
+    let norms = batch
+        .column_by_name(norms)
+        .and_then(|c| c.as_any().downcast_ref::<Float32Array>())
+        .ok_or_else(|| Error::InvalidInput {
+            message: format!("the column '{norms}' is not a column of norms"),
+        })?;
+    let values = as_primitive_array::<Float32Type>(vectors.values().as_ref()).values();
+    let dims = vectors.value_length() as usize;
+    let offset = vectors.value_offset(0) as usize;
+    let query_norm = norm_l2(query_vector.values());
+    Ok((0..batch.num_rows())
+        .map(|row| {
+            if vectors.is_null(row) || norms.is_null(row) {
+                return None;
+            }
+            let start = offset + row * dims;
+            let product = dot(query_vector.values(), &values[start..start + dims]);
+            Some(1.0 - product / (query_norm * norms.value(row)))
+        })
+        .collect())
+}
+
+impl Query {
+    /// The column of the stored norms of the vector column of this query in
+    /// `dataset`, if it is searched by the cosine distance.
+    pub(super) async fn stored_norms(&self, dataset: &Dataset) -> Result<Option<String>> {
+        match (self.metric_type, &self.target) {
+            (Some(MetricType::Cosine), QueryTarget::Dataset(target)) => {
+                target.stored_norms(dataset, &self.column).await
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Search `dataset` for `query_vector` by the cosine distances of the
+    /// stored norms `norms`.
+    pub(super) async fn search_stored_norms(
+        &self,
+        dataset: Arc<Dataset>,
+        query_vector: &Float32Array,
+        norms: &str,
+        filter_mode: Option<FilterMode>,
+    ) -> Result<DatasetRecordBatchStream> {
+        let mut query = self.clone();
+        let added = query.select_also(&[&self.column, norms]);
+        if filter_mode == Some(FilterMode::Prefilter) || !self.uses_index(&dataset).await? {
+            let (_, batch) = scan(&dataset, &query).await?;
+            let distances = cosine_distances(&batch, &self.column, norms, query_vector)?;
+            let batch = drop_columns(batch, &added)?;
+            return nearest(batch.schema(), batch, distances, self);
+        }
+        let Some(refine_factor) = self.refine_factor else {
+            return self.search_dataset(dataset, query_vector).await;
+        };
+        query.refine_factor = None;
+        query.limit = self.limit.saturating_mul(refine_factor as usize);
+        let candidates = query.search_dataset(dataset, query_vector).await?;
+        let schema = candidates.schema();
+        let batches = candidates.try_collect::<Vec<_>>().await?;
+        let batch = concat_batches(&schema, &batches).map_err(lance::Error::from)?;
+        let distances = cosine_distances(&batch, &self.column, norms, query_vector)?;
+        let batch = drop_columns(batch, &added)?;
+        let batch = match batch.schema().field_with_name(DISTANCE_COLUMN) {
+            Ok(_) => batch.drop_column(DISTANCE_COLUMN)?,
+            Err(_) => batch,
+        };
+        nearest(batch.schema(), batch, distances, self)
+    }
+
+    /// Add `columns` to the selected columns of this query, if it selects
+    /// some, returning those it did not select.
+    fn select_also(&mut self, columns: &[&str]) -> Vec<String> {
+        let Some(select) = self.select.as_mut() else {
+            return Vec::new();
+        };
+        let mut added = Vec::new();
+        for column in columns {
+            if !select.iter().any(|c| c == column) {
+                select.push(column.to_string());
+                added.push(column.to_string());
+            }
+        }
+        added
+    }
+}
+
+/// `batch` without `columns`.
+fn drop_columns(mut batch: RecordBatch, columns: &[String]) -> Result<RecordBatch> {
+    for column in columns {
+        if batch.schema().field_with_name(column).is_ok() {
+            batch = batch.drop_column(column)?;
+        }
+    }
+    Ok(batch)
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow_array::{Int32Array, RecordBatchReader};
+    use lance::arrow::RecordBatchBuffer;
+    use lance::index::vector::ivf::IvfBuildParams;
+    use lance::index::vector::pq::PQBuildParams;
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::arrow::RecordBatchBuilder;
+    use crate::index::vector::IvfPQIndexBuilder;
+    use crate::table::{norm_column, NativeTable};
+
+    const DIMS: usize = 8;
+
+    /// The unnormalized vector of the row `id`, of pseudo-random values.
+    fn vector(id: i32) -> [f32; DIMS] {
+        let scale = 1.0 + (id % 7) as f32 * 3.0;
+        let mut state = id as u64 + 1;
+        let mut vector = [0.0; DIMS];
+        for value in vector.iter_mut() {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            *value = (((state >> 33) % 2000) as f32 / 100.0 - 10.0) * scale;
+        }
+        vector
+    }
+
+    fn rows(ids: std::ops::Range<i32>) -> Box<dyn RecordBatchReader> {
+        let batch = RecordBatchBuilder::new()
+            .column("id", Arc::new(Int32Array::from_iter_values(ids.clone())))
+            .vector_column("vector", DIMS as i32, ids.map(vector).collect::<Vec<_>>())
+            .build()
+            .unwrap();
+        Box::new(RecordBatchBuffer::new(vec![batch]))
+    }
+
+    /// The ids of the `limit` rows of `0..rows` nearest to `query` by the
+    /// cosine distance, and their distances, computed from their vectors.
+    fn offline(query: &[f32], rows: i32, limit: usize) -> Vec<(i32, f32)> {
+        let norm = |v: &[f32]| v.iter().map(|x| x * x).sum::<f32>().sqrt();
+        let mut distances = (0..rows)
+            .map(|id| {
+                let v = vector(id);
+                let product = v.iter().zip(query).map(|(a, b)| a * b).sum::<f32>();
+                (id, 1.0 - product / (norm(&v) * norm(query)))
+            })
+            .collect::<Vec<_>>();
+        distances.sort_by(|a, b| a.1.total_cmp(&b.1));
+        distances.truncate(limit);
+        distances
+    }
+
+    async fn search(query: Query) -> Vec<(i32, f32)> {
+        let batches = query
+            .execute()
+            .await
+            .unwrap()
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        batches
+            .iter()
+            .flat_map(|b| {
+                let ids = b["id"].as_any().downcast_ref::<Int32Array>().unwrap();
+                let scores = b[DISTANCE_COLUMN]
+                    .as_any()
+                    .downcast_ref::<Float32Array>()
+                    .unwrap();
+                (0..b.num_rows())
+                    .map(|row| (ids.value(row), scores.value(row)))
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    fn assert_near(found: &[(i32, f32)], expected: &[(i32, f32)]) {
+        assert_eq!(
+            found.iter().map(|(id, _)| *id).collect::<Vec<_>>(),
+            expected.iter().map(|(id, _)| *id).collect::<Vec<_>>()
+        );
+        for ((_, found), (_, expected)) in found.iter().zip(expected) {
+            assert!((found - expected).abs() < 1e-4, "{found} != {expected}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stored_norms() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        let table = NativeTable::create(uri, "test", rows(0..400), None)
+            .await
+            .unwrap();
+        let stats = table.vector_stats("vector", None).await.unwrap();
+        assert!(!stats.norms_materialized);
+
+        let err = table.store_vector_norms("missing").await.unwrap_err();
+        assert!(matches!(err, Error::InvalidInput { .. }), "{err}");
+        let err = table.store_vector_norms("id").await.unwrap_err();
+        assert!(matches!(err, Error::InvalidInput { .. }), "{err}");
+        table.store_vector_norms("vector").await.unwrap();
+        let version = table.version();
+        table.store_vector_norms("vector").await.unwrap();
+        assert_eq!(table.version(), version);
+        let stats = table.vector_stats("vector", None).await.unwrap();
+        assert!(stats.norms_materialized);
+
+        // The norms of the appended rows are stored as they are written.
+        table.add(rows(400..512), None).await.unwrap();
+        let table = NativeTable::open(uri, "test").await.unwrap();
+        let appended = table
+            .search(vector(450).to_vec())
+            .limit(1)
+            .select(Some(vec!["id".to_string(), norm_column("vector")]));
+        let batches = appended
+            .execute()
+            .await
+            .unwrap()
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        let norms = batches[0][norm_column("vector").as_str()]
+            .as_any()
+            .downcast_ref::<Float32Array>()
+            .unwrap();
+        let expected = vector(450).iter().map(|v| v * v).sum::<f32>().sqrt();
+        assert!((norms.value(0) - expected).abs() < 1e-3);
+
+        let query = [1.0, -2.0, 0.5, 3.0, -1.0, 0.0, 2.0, 1.5];
+        let expected = offline(&query, 512, 10);
+        let flat = table
+            .search(query.to_vec())
+            .metric_type(Some(MetricType::Cosine))
+            .limit(10);
+        assert_near(&search(flat).await, &expected);
+        // The columns not selected are left out.
+        let selected = table
+            .search(query.to_vec())
+            .metric_type(Some(MetricType::Cosine))
+            .select(Some(vec!["id".to_string()]))
+            .limit(10);
+        let batches = selected
+            .execute()
+            .await
+            .unwrap()
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        assert!(batches[0].column_by_name(&norm_column("vector")).is_none());
+
+        let builder = IvfPQIndexBuilder::new()
+            .metric_type(MetricType::Cosine)
+            .ivf_params(IvfBuildParams::new(2))
+            .pq_params(PQBuildParams {
+                num_sub_vectors: 2,
+                ..Default::default()
+            });
+        table.create_index(&builder).await.unwrap();
+        let refined = table
+            .search(query.to_vec())
+            .metric_type(Some(MetricType::Cosine))
+            .use_index(true)
+            .nprobes(2)
+            .refine_factor(Some(60))
+            .limit(10);
+        assert_near(&search(refined).await, &expected);
+    }
+}
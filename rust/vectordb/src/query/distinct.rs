@@ -0,0 +1,294 @@
+// Copyright 2023 LanceDB Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The results of [Query::distinct_on], the nearest row of each value of a
+//! column.
+//!
+//! The query is run for [Query::distinct_overfetch] times its limit, and
+//! again for more rows while the rows found have fewer distinct values than
+//! the limit and more rows may be left.
+
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use arrow_array::{Array, RecordBatch, UInt32Array};
+use arrow_schema::{Schema as ArrowSchema, SchemaRef};
+use datafusion::arrow::compute::{concat_batches, take};
+use datafusion::arrow::row::{RowConverter, SortField};
+use futures::TryStreamExt;
+use lance::arrow::RecordBatchExt;
+use lance::dataset::scanner::DatasetRecordBatchStream;
+use lance::io::RecordBatchStream;
+
+use super::{batches_stream, Query, QueryMetrics};
+use crate::error::{Error, Result};
+
+/// The rows searched for by a [Query::distinct_on] query, by default, as a
+/// multiple of its limit.
+pub const DEFAULT_DISTINCT_OVERFETCH: usize = 4;
+
+/// The first row of each value of `column` in the ranked `batches`, and every
+/// row where it is null, up to `limit` rows.
+fn first_of_each(
+    schema: &SchemaRef,
+    batches: &[RecordBatch],
+    column: &str,
+    limit: usize,
+) -> Result<Option<RecordBatch>> {
+    if batches.is_empty() {
+        return Ok(None);
+    }
+    let batch = concat_batches(schema, batches).map_err(lance::Error::from)?;
+    let values = batch
+        .column_by_name(column)
+        .ok_or_else(|| Error::InvalidInput {
+            message: format!("distinct_on: the results have no column '{column}'"),
+        })?;
+    let mut converter = RowConverter::new(vec![SortField::new(values.data_type().clone())])
+        .map_err(lance::Error::from)?;
+    let rows = converter
+        .convert_columns(std::slice::from_ref(values))
+        .map_err(lance::Error::from)?;
+    let mut seen = HashSet::new();
+    let mut kept = Vec::new();
+    for row in 0..batch.num_rows() {
+        if kept.len() == limit {
+            break;
+        }
+        if values.is_null(row) || seen.insert(rows.row(row).as_ref().to_vec()) {
+            kept.push(row as u32);
+        }
+    }
+    let indices = UInt32Array::from(kept);
+    let columns = batch
+        .columns()
+        .iter()
+        .map(|column| take(column.as_ref(), &indices, None))
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .map_err(lance::Error::from)?;
+    Ok(Some(
+        RecordBatch::try_new(batch.schema(), columns).map_err(lance::Error::from)?,
+    ))
+}
+
+impl Query {
+    /// The results of this query with one row of each value of its
+    /// [Query::distinct_on] column.
+    pub(super) async fn search_distinct(&self, column: &str) -> Result<DatasetRecordBatchStream> {
+        let (schema, batches, _) = self.distinct_results(column, false).await?;
+        Ok(batches_stream(schema, batches))
+    }
+
+    /// [Query::search_distinct] and the [QueryMetrics] of its last search.
+    pub(super) async fn search_distinct_with_metrics(
+        &self,
+        column: &str,
+    ) -> Result<(Vec<RecordBatch>, QueryMetrics)> {
+        let (_, batches, metrics) = self.distinct_results(column, true).await?;
+        let mut metrics = metrics.unwrap_or_default();
+        metrics.rows_after_filter = batches.iter().map(RecordBatch::num_rows).sum();
+        Ok((batches, metrics))
+    }
+
+    async fn distinct_results(
+        &self,
+        column: &str,
+        with_metrics: bool,
+    ) -> Result<(SchemaRef, Vec<RecordBatch>, Option<QueryMetrics>)> {
+        if self.distinct_overfetch == 0 {
+            return Err(Error::InvalidInput {
+                message: "distinct_overfetch must be at least 1".to_string(),
+            });
+        }
+        let mut searched = self.clone();
+        // The column is read for the search, and left out of the results if it
+        // was not selected.
+        let mut added = false;
+        if let Some(select) = searched.select.as_mut() {
+            if !select.iter().any(|c| c == column) {
+                select.push(column.to_string());
+                added = true;
+            }
+        }
+        let mut fetched = self.limit.saturating_mul(self.distinct_overfetch);
+        loop {
+            searched.limit = fetched;
+            let (schema, batches, metrics) = if with_metrics {
+                let (batches, metrics) = searched.search_ranked_with_metrics().await?;
+                let schema = batches
+                    .first()
+                    .map(RecordBatch::schema)
+                    .unwrap_or_else(|| Arc::new(ArrowSchema::empty()));
+                (schema, batches, Some(metrics))
+            } else {
+                let stream = searched.search_ranked().await?;
+                let schema = stream.schema();
+                (schema, stream.try_collect::<Vec<_>>().await?, None)
+            };
+            let rows = batches.iter().map(RecordBatch::num_rows).sum::<usize>();
+            let kept = first_of_each(&schema, &batches, column, self.limit)?;
+            let distinct = kept.as_ref().map(RecordBatch::num_rows).unwrap_or_default();
+            // Fewer rows than searched for are all the rows there are.
+            if distinct < self.limit && rows == fetched {
+                fetched = fetched.saturating_mul(self.distinct_overfetch.max(2));
+                continue;
+            }
+            let Some(mut batch) = kept else {
+                return Ok((schema, Vec::new(), metrics));
+            };
+            if added {
+                batch = batch.drop_column(column)?;
+            }
+            return Ok((batch.schema(), vec![batch], metrics));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow_array::{Int32Array, RecordBatchReader, StringArray};
+    use lance::arrow::RecordBatchBuffer;
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::arrow::RecordBatchBuilder;
+    use crate::table::NativeTable;
+
+    /// Four chunks of each of the documents `docs`, the chunks of the
+    /// document at `position` at a distance of about `position` to [1, 1].
+    ///
+    /// lance keeps the nulls of strings, not those of numbers, so documents
+    /// without an id are in the `source` column.
+    fn chunks(docs: Vec<Option<i32>>) -> Box<dyn RecordBatchReader> {
+        let mut sources = Vec::new();
+        let mut doc_ids = Vec::new();
+        let mut chunk_ids = Vec::new();
+        let mut vectors = Vec::new();
+        for (position, doc) in docs.into_iter().enumerate() {
+            for chunk in 0..4 {
+                doc_ids.push(doc.unwrap_or_default());
+                sources.push(doc.map(|d| format!("doc {d}")));
+                chunk_ids.push(position as i32 * 4 + chunk);
+                let offset = position as f32 + chunk as f32 * 0.1;
+                vectors.push([1.0 + offset, 1.0]);
+            }
+        }
+        let batch = RecordBatchBuilder::new()
+            .column("doc_id", Arc::new(Int32Array::from(doc_ids)))
+            .column("chunk_id", Arc::new(Int32Array::from(chunk_ids)))
+            .column("source", Arc::new(StringArray::from(sources)))
+            .vector_column("vector", 2, vectors)
+            .build()
+            .unwrap();
+        Box::new(RecordBatchBuffer::new(vec![batch]))
+    }
+
+    fn values(batches: &[RecordBatch], column: &str) -> Vec<i32> {
+        batches
+            .iter()
+            .flat_map(|b| {
+                let values = b[column].as_any().downcast_ref::<Int32Array>().unwrap();
+                values.values().to_vec()
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_distinct_on() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        let docs = (0..10).map(Some).collect();
+        let table = NativeTable::create(uri, "test", chunks(docs), None)
+            .await
+            .unwrap();
+        let query = |limit: usize| {
+            table
+                .search(vec![1.0, 1.0])
+                .limit(limit)
+                .distinct_on("doc_id")
+        };
+
+        let batches = query(3).execute().await.unwrap().try_collect::<Vec<_>>();
+        let batches = batches.await.unwrap();
+        assert_eq!(values(&batches, "doc_id"), vec![0, 1, 2]);
+        // The nearest chunk of each document.
+        assert_eq!(values(&batches, "chunk_id"), vec![0, 4, 8]);
+
+        // The search is repeated for more rows to find enough documents.
+        let (batches, metrics) = query(6)
+            .distinct_overfetch(1)
+            .execute_with_metrics()
+            .await
+            .unwrap();
+        assert_eq!(values(&batches, "doc_id"), (0..6).collect::<Vec<_>>());
+        assert_eq!(metrics.rows_after_filter, 6);
+
+        // The column is read for the search without being selected.
+        let selected = query(2)
+            .select(Some(vec!["chunk_id".to_string()]))
+            .execute()
+            .await
+            .unwrap();
+        assert!(selected.schema().field_with_name("doc_id").is_err());
+        let batches = selected.try_collect::<Vec<_>>().await.unwrap();
+        assert_eq!(values(&batches, "chunk_id"), vec![0, 4]);
+
+        // There are fewer documents than the limit.
+        let batches = query(20).execute().await.unwrap().try_collect::<Vec<_>>();
+        assert_eq!(batches.await.unwrap()[0].num_rows(), 10);
+
+        let err = query(3)
+            .distinct_overfetch(0)
+            .execute()
+            .await
+            .err()
+            .unwrap();
+        assert!(matches!(err, Error::InvalidInput { .. }), "{err}");
+        let err = table
+            .search(vec![1.0, 1.0])
+            .distinct_on("missing")
+            .execute()
+            .await
+            .err()
+            .unwrap();
+        assert!(matches!(err, Error::InvalidInput { .. }), "{err}");
+    }
+
+    #[tokio::test]
+    async fn test_distinct_on_nulls() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        let docs = vec![None, Some(1), None, Some(1)];
+        let table = NativeTable::create(uri, "test", chunks(docs), None)
+            .await
+            .unwrap();
+        let stream = table
+            .search(vec![1.0, 1.0])
+            .limit(10)
+            .distinct_on("source")
+            .execute()
+            .await
+            .unwrap();
+        let batches = stream.try_collect::<Vec<_>>().await.unwrap();
+        let sources = batches[0]["source"]
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .unwrap();
+        // Every row without a value is kept.
+        let mut expected = vec![None; 4];
+        expected.push(Some("doc 1"));
+        expected.extend(vec![None; 4]);
+        assert_eq!(sources.iter().collect::<Vec<_>>(), expected);
+    }
+}
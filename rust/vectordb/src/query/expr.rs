@@ -0,0 +1,319 @@
+// Copyright 2023 LanceDB Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Filters built from typed expressions instead of SQL text.
+//!
+//! ```
+//! use vectordb::query::{col, lit};
+//!
+//! let filter = col("category").eq(lit("men's shoes")).and(col("price").gt(lit(10)));
+//! assert_eq!(
+//!     filter.to_string(),
+//!     "(`category` = 'men''s shoes') AND (`price` > 10)"
+//! );
+//! ```
+
+use std::fmt;
+
+/// A value compared with columns in a [FilterExpr], see [lit].
+#[derive(Debug, Clone, PartialEq)]
+pub enum Literal {
+    Bool(bool),
+    Int(i64),
+    UInt(u64),
+    /// A float, which must be finite: SQL has no literal for NaN or infinities.
+    Float(f64),
+    String(String),
+}
+
+macro_rules! impl_from {
+    ($variant:ident, $target:ty, $($t:ty),*) => {
+        $(impl From<$t> for Literal {
+            fn from(value: $t) -> Self {
+                Self::$variant(<$target>::from(value))
+            }
+        })*
+    };
+}
+
+impl_from!(Bool, bool, bool);
+impl_from!(Int, i64, i8, i16, i32, i64);
+impl_from!(UInt, u64, u8, u16, u32, u64);
+impl_from!(Float, f64, f32, f64);
+impl_from!(String, String, &str, String);
+
+impl fmt::Display for Literal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Bool(value) => write!(f, "{value}"),
+            Self::Int(value) => write!(f, "{value}"),
+            Self::UInt(value) => write!(f, "{value}"),
+            // Display never uses an exponent, keep a point so the literal stays a float.
+            Self::Float(value) if value.fract() == 0.0 => write!(f, "{value}.0"),
+            Self::Float(value) => write!(f, "{value}"),
+            Self::String(value) => write!(f, "'{}'", value.replace('\'', "''")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Column(String),
+    Literal(Literal),
+    Binary(Box<FilterExpr>, &'static str, Box<FilterExpr>),
+    Not(Box<FilterExpr>),
+    IsNull(Box<FilterExpr>, bool),
+    InList(Box<FilterExpr>, Vec<Literal>, bool),
+}
+
+/// A filter of a query, rendered to the SQL that lance parses, see
+/// [crate::Query::filter_expr].
+///
+/// Columns are quoted and string values escaped, so names and values taken
+/// from users cannot change the meaning of the filter.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FilterExpr(Node);
+
+/// The column `name`.
+pub fn col(name: impl Into<String>) -> FilterExpr {
+    FilterExpr(Node::Column(name.into()))
+}
+
+/// The value `value`.
+pub fn lit(value: impl Into<Literal>) -> FilterExpr {
+    FilterExpr(Node::Literal(value.into()))
+}
+
+impl FilterExpr {
+    fn binary(self, op: &'static str, other: FilterExpr) -> FilterExpr {
+        FilterExpr(Node::Binary(Box::new(self), op, Box::new(other)))
+    }
+
+    pub fn eq(self, other: FilterExpr) -> FilterExpr {
+        self.binary("=", other)
+    }
+
+    pub fn not_eq(self, other: FilterExpr) -> FilterExpr {
+        self.binary("!=", other)
+    }
+
+    pub fn lt(self, other: FilterExpr) -> FilterExpr {
+        self.binary("<", other)
+    }
+
+    pub fn lt_eq(self, other: FilterExpr) -> FilterExpr {
+        self.binary("<=", other)
+    }
+
+    pub fn gt(self, other: FilterExpr) -> FilterExpr {
+        self.binary(">", other)
+    }
+
+    pub fn gt_eq(self, other: FilterExpr) -> FilterExpr {
+        self.binary(">=", other)
+    }
+
+    pub fn and(self, other: FilterExpr) -> FilterExpr {
+        self.binary("AND", other)
+    }
+
+    pub fn or(self, other: FilterExpr) -> FilterExpr {
+        self.binary("OR", other)
+    }
+
+    pub fn is_null(self) -> FilterExpr {
+        FilterExpr(Node::IsNull(Box::new(self), false))
+    }
+
+    pub fn is_not_null(self) -> FilterExpr {
+        FilterExpr(Node::IsNull(Box::new(self), true))
+    }
+
+    /// Whether the value is one of `values`. An empty list matches no row.
+    pub fn in_list<T: Into<Literal>>(self, values: impl IntoIterator<Item = T>) -> FilterExpr {
+        let values = values.into_iter().map(Into::into).collect();
+        FilterExpr(Node::InList(Box::new(self), values, false))
+    }
+
+    /// Whether the value is none of `values`. An empty list matches every row.
+    pub fn not_in_list<T: Into<Literal>>(self, values: impl IntoIterator<Item = T>) -> FilterExpr {
+        let values = values.into_iter().map(Into::into).collect();
+        FilterExpr(Node::InList(Box::new(self), values, true))
+    }
+
+    /// Render an operand, in parentheses unless it is a column or a value.
+    fn fmt_operand(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Node::Column(_) | Node::Literal(_) => write!(f, "{self}"),
+            _ => write!(f, "({self})"),
+        }
+    }
+}
+
+impl std::ops::Not for FilterExpr {
+    type Output = FilterExpr;
+
+    fn not(self) -> FilterExpr {
+        FilterExpr(Node::Not(Box::new(self)))
+    }
+}
+
+impl fmt::Display for FilterExpr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.0 {
+            // lance quotes identifiers with backticks, double quotes make a string.
+            Node::Column(name) => write!(f, "`{}`", name.replace('`', "``")),
+            Node::Literal(value) => write!(f, "{value}"),
+            Node::Binary(left, op, right) => {
+                left.fmt_operand(f)?;
+                write!(f, " {op} ")?;
+                right.fmt_operand(f)
+            }
+            Node::Not(expr) => {
+                write!(f, "NOT ")?;
+                expr.fmt_operand(f)
+            }
+            Node::IsNull(expr, negated) => {
+                expr.fmt_operand(f)?;
+                write!(f, " IS {}NULL", if *negated { "NOT " } else { "" })
+            }
+            // `IN ()` does not parse.
+            Node::InList(_, values, negated) if values.is_empty() => write!(f, "{negated}"),
+            Node::InList(expr, values, negated) => {
+                expr.fmt_operand(f)?;
+                write!(f, " {}IN (", if *negated { "NOT " } else { "" })?;
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{value}")?;
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_array::{Int32Array, RecordBatch, StringArray};
+    use futures::TryStreamExt;
+    use lance::arrow::RecordBatchBuffer;
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::arrow::RecordBatchBuilder;
+    use crate::table::NativeTable;
+
+    #[test]
+    fn test_render() {
+        let filter = col("category")
+            .eq(lit("shoes"))
+            .and(col("price").gt(lit(10)));
+        assert_eq!(
+            filter.to_string(),
+            "(`category` = 'shoes') AND (`price` > 10)"
+        );
+
+        let filter = col("title").eq(lit("it's \"new\"\n\\"));
+        assert_eq!(filter.to_string(), "`title` = 'it''s \"new\"\n\\'");
+        let filter = col("we`ird name").in_list([1.5, 2.0]);
+        assert_eq!(filter.to_string(), "`we``ird name` IN (1.5, 2.0)");
+        let filter = !col("a").is_null().or(col("b").is_not_null());
+        assert_eq!(
+            filter.to_string(),
+            "NOT ((`a` IS NULL) OR (`b` IS NOT NULL))"
+        );
+        assert_eq!(col("a").in_list(Vec::<i32>::new()).to_string(), "false");
+        assert_eq!(col("a").not_in_list([-1]).to_string(), "`a` NOT IN (-1)");
+    }
+
+    #[tokio::test]
+    async fn test_filter_expr() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        let titles = ["it's", "l'été", "two\nlines", "a \"quote\"", "plain", "\\"];
+        let batch = RecordBatchBuilder::new()
+            .column("id", Arc::new(Int32Array::from_iter_values(0..6)))
+            .column(
+                "title",
+                Arc::new(StringArray::from(vec![
+                    Some(titles[0]),
+                    Some(titles[1]),
+                    Some(titles[2]),
+                    Some(titles[3]),
+                    None,
+                    Some(titles[5]),
+                ])),
+            )
+            .vector_column(
+                "vector",
+                2,
+                (0..6).map(|i| [i as f32, 0.0]).collect::<Vec<_>>(),
+            )
+            .build()
+            .unwrap();
+        let reader = Box::new(RecordBatchBuffer::new(vec![batch]));
+        let table = NativeTable::create(uri, "test", reader, None)
+            .await
+            .unwrap();
+
+        let ids = |query: crate::Query| async move {
+            let batches: Vec<RecordBatch> = query
+                .limit(10)
+                .execute()
+                .await
+                .unwrap()
+                .try_collect()
+                .await
+                .unwrap();
+            let mut ids = batches
+                .iter()
+                .flat_map(|b| {
+                    let ids = b["id"].as_any().downcast_ref::<Int32Array>().unwrap();
+                    ids.values().to_vec()
+                })
+                .collect::<Vec<_>>();
+            ids.sort();
+            ids
+        };
+        for (i, title) in titles.iter().enumerate().filter(|(i, _)| *i != 4) {
+            let query = table
+                .search(vec![0.0, 0.0])
+                .filter_expr(col("title").eq(lit(*title)));
+            assert_eq!(ids(query).await, vec![i as i32], "{title}");
+        }
+
+        // The same rows as the equivalent SQL text.
+        let cases = [
+            (
+                col("title").in_list(["it's", "plain", "\\"]),
+                "title IN ('it''s', 'plain', '\\')",
+            ),
+            (col("title").is_null(), "title IS NULL"),
+            (
+                col("id").gt_eq(lit(2)).and(!col("title").is_null()),
+                "id >= 2 AND NOT title IS NULL",
+            ),
+            (col("id").not_in_list(Vec::<i32>::new()), "true"),
+        ];
+        for (expr, sql) in cases {
+            let expected = ids(table.search(vec![0.0, 0.0]).filter(Some(sql.to_string()))).await;
+            let got = ids(table.search(vec![0.0, 0.0]).filter_expr(expr.clone())).await;
+            assert_eq!(got, expected, "{expr}");
+        }
+    }
+}
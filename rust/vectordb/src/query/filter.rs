@@ -0,0 +1,251 @@
+// Copyright 2023 LanceDB Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Filters rewritten for the string columns lance cannot compare.
+//!
+//! lance plans `category = 'a'` on a dictionary-encoded column as a comparison of
+//! a dictionary with a string, which fails, and datafusion has no `LIKE` for
+//! dictionaries or `LargeUtf8` columns. Both work on the column cast to a string.
+//!
+//! The comparisons of timestamp and date columns are rewritten too, see
+//! [super::temporal].
+
+use std::borrow::Cow;
+
+use arrow_schema::{DataType, Schema as ArrowSchema};
+use datafusion::sql::sqlparser::ast::{BinaryOperator, DataType as SqlType, Expr};
+use datafusion::sql::sqlparser::dialect::{Dialect, GenericDialect};
+use datafusion::sql::sqlparser::parser::{Parser, ParserError};
+use datafusion::sql::sqlparser::tokenizer::Token;
+
+use super::temporal::rewrite_temporal;
+use crate::error::{Error, Result};
+
+/// The dialect of lance filters, which quotes identifiers with backticks only.
+#[derive(Debug)]
+struct LanceDialect(GenericDialect);
+
+impl Dialect for LanceDialect {
+    fn is_identifier_start(&self, ch: char) -> bool {
+        self.0.is_identifier_start(ch)
+    }
+
+    fn is_identifier_part(&self, ch: char) -> bool {
+        self.0.is_identifier_part(ch)
+    }
+
+    fn is_delimited_identifier_start(&self, ch: char) -> bool {
+        ch == '`'
+    }
+}
+
+/// The expression of `filter`, parsed as lance parses it.
+pub(super) fn parse(filter: &str) -> std::result::Result<Expr, ParserError> {
+    Parser::new(&LanceDialect(GenericDialect {}))
+        .try_with_sql(filter)
+        .and_then(|mut parser| parser.parse_expr())
+}
+
+/// Whether every row matching `filter` matches `implied`: every condition
+/// joined by `AND` in `implied` is one of `filter`, as parsed. `false` without
+/// a filter, or if either does not parse.
+pub(crate) fn filter_implies(filter: Option<&str>, implied: &str) -> bool {
+    let (Some(Ok(filter)), Ok(implied)) = (filter.map(parse), parse(implied)) else {
+        return false;
+    };
+    let mut conditions = Vec::new();
+    conjuncts(&filter, &mut conditions);
+    let mut implied_conditions = Vec::new();
+    conjuncts(&implied, &mut implied_conditions);
+    implied_conditions.iter().all(|c| conditions.contains(c))
+}
+
+/// The conditions joined by `AND` in `expr`, written out.
+fn conjuncts(expr: &Expr, conditions: &mut Vec<String>) {
+    match expr {
+        Expr::BinaryOp {
+            left,
+            op: BinaryOperator::And,
+            right,
+        } => {
+            conjuncts(left, conditions);
+            conjuncts(right, conditions);
+        }
+        Expr::Nested(expr) => conjuncts(expr, conditions),
+        expr => conditions.push(expr.to_string()),
+    }
+}
+
+/// `filters` joined by `AND`, each parsed whole and written out, so that none
+/// reaches out of its parentheses as the text `1 = 1) OR (1 = 1` would.
+///
+/// Fails with an [crate::Error::InvalidInput] if a filter is not a single
+/// expression.
+pub(crate) fn and_filters<'a>(filters: impl IntoIterator<Item = &'a str>) -> Result<String> {
+    let mut joined = Vec::new();
+    for filter in filters {
+        let expr = Parser::new(&LanceDialect(GenericDialect {}))
+            .try_with_sql(filter)
+            .and_then(|mut parser| {
+                let expr = parser.parse_expr()?;
+                parser.expect_token(&Token::EOF)?;
+                Ok(expr)
+            })
+            .map_err(|e| Error::InvalidInput {
+                message: format!("invalid filter '{filter}': {e}"),
+            })?;
+        joined.push(format!("({expr})"));
+    }
+    Ok(joined.join(" AND "))
+}
+
+/// `filter` on a table of `schema`, as lance plans it: with the comparisons of
+/// its timestamp and date columns rewritten, and its string columns cast, see
+/// [cast_string_columns].
+///
+/// Fails with an [crate::Error::InvalidInput] if an instant compared with a
+/// timestamp or date column is not valid.
+pub(crate) fn lance_filter<'a>(filter: &'a str, schema: &ArrowSchema) -> Result<Cow<'a, str>> {
+    Ok(match rewrite_temporal(filter, schema)? {
+        Cow::Borrowed(filter) => cast_string_columns(filter, schema),
+        Cow::Owned(filter) => Cow::Owned(cast_string_columns(&filter, schema).into_owned()),
+    })
+}
+
+/// Whether filters compare the column `data_type` cast to a string.
+fn needs_cast(data_type: &DataType) -> bool {
+    match data_type {
+        DataType::LargeUtf8 => true,
+        DataType::Dictionary(_, value) => matches!(**value, DataType::Utf8 | DataType::LargeUtf8),
+        _ => false,
+    }
+}
+
+/// `filter` on a table of `schema`, with the dictionary-encoded and `LargeUtf8`
+/// string columns it references cast to strings.
+///
+/// Filters that reference no such column, or do not parse, are returned as they
+/// are, lance reports their errors.
+fn cast_string_columns<'a>(filter: &'a str, schema: &ArrowSchema) -> Cow<'a, str> {
+    let columns = schema
+        .fields()
+        .iter()
+        .filter(|f| needs_cast(f.data_type()))
+        .map(|f| f.name().as_str())
+        .collect::<Vec<_>>();
+    if columns.is_empty() {
+        return Cow::Borrowed(filter);
+    }
+    let Ok(mut expr) = parse(filter) else {
+        return Cow::Borrowed(filter);
+    };
+    if cast_columns(&mut expr, &columns) {
+        Cow::Owned(expr.to_string())
+    } else {
+        Cow::Borrowed(filter)
+    }
+}
+
+/// Cast the references to `columns` in `expr`, returning whether there were any.
+fn cast_columns(expr: &mut Expr, columns: &[&str]) -> bool {
+    match expr {
+        Expr::Identifier(ident) if columns.contains(&ident.value.as_str()) => {
+            *expr = Expr::Cast {
+                expr: Box::new(Expr::Identifier(ident.clone())),
+                data_type: SqlType::String,
+            };
+            true
+        }
+        Expr::BinaryOp { left, right, .. } => {
+            // Both sides, without short-circuiting.
+            cast_columns(left, columns) | cast_columns(right, columns)
+        }
+        Expr::UnaryOp { expr, .. }
+        | Expr::Nested(expr)
+        | Expr::IsNull(expr)
+        | Expr::IsNotNull(expr)
+        | Expr::IsTrue(expr)
+        | Expr::IsNotTrue(expr)
+        | Expr::IsFalse(expr)
+        | Expr::IsNotFalse(expr)
+        | Expr::Cast { expr, .. }
+        | Expr::TryCast { expr, .. } => cast_columns(expr, columns),
+        Expr::Like { expr, pattern, .. } | Expr::ILike { expr, pattern, .. } => {
+            cast_columns(expr, columns) | cast_columns(pattern, columns)
+        }
+        Expr::InList { expr, list, .. } => list
+            .iter_mut()
+            .fold(cast_columns(expr, columns), |cast, item| {
+                cast_columns(item, columns) | cast
+            }),
+        Expr::Between {
+            expr, low, high, ..
+        } => cast_columns(expr, columns) | cast_columns(low, columns) | cast_columns(high, columns),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow_schema::Field;
+
+    use super::*;
+
+    #[test]
+    fn test_cast_string_columns() {
+        let dictionary = DataType::Dictionary(Box::new(DataType::Int32), Box::new(DataType::Utf8));
+        let schema = ArrowSchema::new(vec![
+            Field::new("category", dictionary, true),
+            Field::new("title", DataType::LargeUtf8, true),
+            Field::new("name", DataType::Utf8, true),
+        ]);
+        let cases = [
+            ("name = 'a'", "name = 'a'"),
+            ("category = 'a'", "CAST(category AS STRING) = 'a'"),
+            (
+                "`category` IN ('it''s', 'b') AND title LIKE 'x%'",
+                "CAST(`category` AS STRING) IN ('it''s', 'b') AND CAST(title AS STRING) LIKE 'x%'",
+            ),
+            (
+                "NOT (category IS NULL OR name = \"category\")",
+                "NOT (CAST(category AS STRING) IS NULL OR name = \"category\")",
+            ),
+            (
+                "title BETWEEN 'a' AND 'c'",
+                "CAST(title AS STRING) BETWEEN 'a' AND 'c'",
+            ),
+            // Not a filter of lance, left to it to report.
+            ("category = = 'a'", "category = = 'a'"),
+        ];
+        for (filter, expected) in cases {
+            assert_eq!(cast_string_columns(filter, &schema), expected, "{filter}");
+        }
+
+        let schema = ArrowSchema::new(vec![Field::new("category", DataType::Utf8, true)]);
+        assert!(matches!(
+            cast_string_columns("category = 'a'", &schema),
+            Cow::Borrowed(_)
+        ));
+    }
+
+    #[test]
+    fn test_and_filters() {
+        let joined = and_filters(["tenant = 'it''s'", "id < 5 OR `id` > 9"]).unwrap();
+        assert_eq!(joined, "(tenant = 'it''s') AND (id < 5 OR `id` > 9)");
+        for filter in ["1 = 1) OR (1 = 1", "id < 5 id", ""] {
+            let err = and_filters(["tenant = 'a'", filter]).unwrap_err();
+            assert!(matches!(err, Error::InvalidInput { .. }), "{err}");
+        }
+    }
+}
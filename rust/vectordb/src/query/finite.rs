@@ -0,0 +1,269 @@
+// Copyright 2023 LanceDB Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The results of searches without NaN or infinite distances, see
+//! [Query::allow_nonfinite].
+//!
+//! A stored vector with NaN or infinite values, written without the
+//! [crate::table::OnBadVectors] policy of the table, is at a NaN or infinite
+//! distance of the query vectors. lance ranks the distances by their total
+//! order, in which a negative NaN comes before every finite distance, so the
+//! rows of such distances are left out of the results of lance, and the
+//! search is run again for as many more rows as were left out, until it has
+//! `limit` rows or all the rows there are. The flat searches of [super::flat]
+//! and the ranking of [super::TwoStage] leave them out as they rank the rows.
+
+use std::sync::Arc;
+
+use arrow_array::{Array, BooleanArray, Float32Array, RecordBatch};
+use arrow_schema::{Schema as ArrowSchema, SchemaRef};
+use datafusion::arrow::compute::filter_record_batch;
+use futures::TryStreamExt;
+use lance::dataset::scanner::DatasetRecordBatchStream;
+use lance::io::RecordBatchStream;
+
+use super::{batches_stream, Query, QueryMetrics, DISTANCE_COLUMN};
+use crate::error::Result;
+
+/// Whether `distance` is kept by a query, see [Query::allow_nonfinite].
+pub(super) fn is_kept(distance: f32, allow_nonfinite: bool) -> bool {
+    allow_nonfinite || distance.is_finite()
+}
+
+/// `batches` without their rows of NaN or infinite distances, and the number
+/// of rows left out.
+fn finite_rows(batches: Vec<RecordBatch>) -> Result<(Vec<RecordBatch>, usize)> {
+    let mut dropped = 0;
+    let mut results = Vec::with_capacity(batches.len());
+    for batch in batches {
+        let Some(distances) = batch
+            .column_by_name(DISTANCE_COLUMN)
+            .and_then(|c| c.as_any().downcast_ref::<Float32Array>())
+        else {
+            results.push(batch);
+            continue;
+        };
+        let finite = (0..distances.len())
+            .map(|row| Some(distances.is_null(row) || distances.value(row).is_finite()))
+            .collect::<BooleanArray>();
+        let kept = finite.true_count();
+        if kept == batch.num_rows() {
+            results.push(batch);
+            continue;
+        }
+        dropped += batch.num_rows() - kept;
+        results.push(filter_record_batch(&batch, &finite).map_err(lance::Error::from)?);
+    }
+    Ok((results, dropped))
+}
+
+impl Query {
+    /// The results of the lance search of this query without the rows of NaN
+    /// or infinite distances, unless [Query::allow_nonfinite] is set.
+    pub(super) async fn search_finite(&self) -> Result<DatasetRecordBatchStream> {
+        if self.allow_nonfinite {
+            return self.search_lance().await;
+        }
+        let (schema, batches, _) = self.finite_results(false).await?;
+        Ok(batches_stream(schema, batches))
+    }
+
+    /// [Query::search_finite] and the [QueryMetrics] of its last search.
+    pub(super) async fn search_finite_with_metrics(
+        &self,
+    ) -> Result<(Vec<RecordBatch>, QueryMetrics)> {
+        if self.allow_nonfinite {
+            return self.search_lance_with_metrics().await;
+        }
+        let (_, batches, metrics) = self.finite_results(true).await?;
+        let mut metrics = metrics.unwrap_or_default();
+        metrics.rows_after_filter = batches.iter().map(RecordBatch::num_rows).sum();
+        Ok((batches, metrics))
+    }
+
+    async fn finite_results(
+        &self,
+        with_metrics: bool,
+    ) -> Result<(SchemaRef, Vec<RecordBatch>, Option<QueryMetrics>)> {
+        let mut searched = self.clone();
+        // The searches are boxed, or their futures would be inlined in that of
+        // every search, past the stack of a test thread.
+        loop {
+            let (schema, batches, metrics) = if with_metrics {
+                let (batches, metrics) = Box::pin(searched.search_lance_with_metrics()).await?;
+                let schema = batches
+                    .first()
+                    .map(RecordBatch::schema)
+                    .unwrap_or_else(|| Arc::new(ArrowSchema::empty()));
+                (schema, batches, Some(metrics))
+            } else {
+                let stream = Box::pin(searched.search_lance()).await?;
+                let schema = stream.schema();
+                (schema, stream.try_collect::<Vec<_>>().await?, None)
+            };
+            let rows = batches.iter().map(RecordBatch::num_rows).sum::<usize>();
+            let (batches, dropped) = finite_rows(batches)?;
+            // Fewer rows than searched for are all the rows there are.
+            if dropped > 0 && rows == searched.limit && rows - dropped < self.limit {
+                searched.limit = searched.limit.saturating_add(self.limit - (rows - dropped));
+                continue;
+            }
+            return Ok((schema, batches, metrics));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ops::Range;
+
+    use arrow_array::{Int32Array, RecordBatchReader};
+    use lance::arrow::RecordBatchBuffer;
+    use lance::dataset::{Dataset, WriteMode, WriteParams};
+    use lance::index::vector::ivf::IvfBuildParams;
+    use lance::index::vector::pq::PQBuildParams;
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::arrow::RecordBatchBuilder;
+    use crate::error::Error;
+    use crate::index::vector::IvfPQIndexBuilder;
+    use crate::query::FilterMode;
+    use crate::table::NativeTable;
+
+    /// Rows of the ids `ids`, the vector of the row 0 of NaN values and that
+    /// of the row 1 of infinite values.
+    fn rows(ids: Range<i32>) -> Box<dyn RecordBatchReader> {
+        let vectors = ids
+            .clone()
+            .map(|i| match i {
+                0 => [-f32::NAN; 4],
+                1 => [f32::INFINITY; 4],
+                i => [1.0 + i as f32 * 0.01, 1.0, 2.0, 0.0],
+            })
+            .collect::<Vec<_>>();
+        let batch = RecordBatchBuilder::new()
+            .column("id", Arc::new(Int32Array::from_iter_values(ids)))
+            .vector_column("vector", 4, vectors)
+            .build()
+            .unwrap();
+        Box::new(RecordBatchBuffer::new(vec![batch]))
+    }
+
+    /// The ids and distances of the rows of `batches`, in order.
+    fn results(batches: &[RecordBatch]) -> Vec<(i32, f32)> {
+        batches
+            .iter()
+            .flat_map(|b| {
+                let ids = b["id"].as_any().downcast_ref::<Int32Array>().unwrap();
+                let scores = b[DISTANCE_COLUMN]
+                    .as_any()
+                    .downcast_ref::<Float32Array>()
+                    .unwrap();
+                (0..b.num_rows())
+                    .map(|row| (ids.value(row), scores.value(row)))
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    async fn search(query: Query) -> Vec<(i32, f32)> {
+        let batches = query
+            .execute()
+            .await
+            .unwrap()
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        let (with_metrics, _) = query.execute_with_metrics().await.unwrap();
+        let found = results(&batches);
+        let ids = |results: &[(i32, f32)]| results.iter().map(|(id, _)| *id).collect::<Vec<_>>();
+        assert_eq!(ids(&results(&with_metrics)), ids(&found));
+        found
+    }
+
+    #[tokio::test]
+    async fn test_nonfinite_distances() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        // The bad vectors of the table are written by lance itself, the
+        // policy of the table would keep them out.
+        let err = NativeTable::create(uri, "rejected", rows(0..300), None)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::InvalidInput { .. }), "{err}");
+        let path = NativeTable::table_uri(uri, "test").unwrap();
+        Dataset::write(&mut rows(2..300), &path, None)
+            .await
+            .unwrap();
+        // lance cannot encode the NaN or infinite vectors in an index, the
+        // index is of the rows before them.
+        let table = NativeTable::open(uri, "test").await.unwrap();
+        let builder = IvfPQIndexBuilder::new()
+            .ivf_params(IvfBuildParams::new(2))
+            .pq_params(PQBuildParams {
+                num_sub_vectors: 2,
+                ..Default::default()
+            });
+        table.create_index(&builder).await.unwrap();
+        let params = WriteParams {
+            mode: WriteMode::Append,
+            ..Default::default()
+        };
+        Dataset::write(&mut rows(0..2), &path, Some(params))
+            .await
+            .unwrap();
+        let table = NativeTable::open(uri, "test").await.unwrap();
+
+        let vector = vec![1.0, 1.0, 2.0, 0.0];
+        let flat = search(table.search(vector.clone()).limit(5)).await;
+        assert_eq!(flat.len(), 5);
+        assert!(flat.iter().all(|(id, score)| *id > 1 && score.is_finite()));
+        let all = table
+            .search(vector.clone())
+            .limit(300)
+            .allow_nonfinite(true);
+        let all = search(all).await;
+        assert!(all.iter().any(|(id, score)| *id == 0 && score.is_nan()));
+        assert!(all
+            .iter()
+            .any(|(id, score)| *id == 1 && score.is_infinite()));
+        let prefiltered = table
+            .search(vector.clone())
+            .limit(5)
+            .filter(Some("id < 10".to_string()))
+            .filter_mode(FilterMode::Prefilter);
+        let prefiltered = search(prefiltered).await;
+        assert_eq!(prefiltered.len(), 5);
+        assert!(prefiltered.iter().all(|(id, _)| *id > 1));
+
+        let indexed = table
+            .search(vector.clone())
+            .limit(5)
+            .use_index(true)
+            .nprobes(2)
+            .refine_factor(Some(10));
+        let indexed = search(indexed).await;
+        assert_eq!(indexed.len(), 5);
+        assert!(indexed
+            .iter()
+            .all(|(id, score)| *id > 1 && score.is_finite()));
+        // The index search finds the rows of finite distances the flat search
+        // finds first.
+        assert_eq!(
+            indexed.iter().map(|(id, _)| *id).collect::<Vec<_>>(),
+            flat.iter().map(|(id, _)| *id).collect::<Vec<_>>()
+        );
+    }
+}
@@ -0,0 +1,265 @@
+// Copyright 2023 LanceDB Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Flat search of the vector columns lance does not search: half-precision
+//! vectors and multivector columns, and of the prefiltered searches, see
+//! [super::FilterMode::Prefilter].
+//!
+//! The vectors are compared with the query vectors here, as `Float32`. The
+//! filter is applied before the search, so a filtered query returns up to
+//! `limit` rows even if the nearest neighbors do not match the filter. Null
+//! vectors are not searched.
+
+use std::sync::Arc;
+
+use arrow_array::cast::as_primitive_array;
+use arrow_array::types::{Float16Type, Float32Type};
+use arrow_array::{
+    Array, ArrayRef, FixedSizeListArray, Float32Array, ListArray, RecordBatch, UInt32Array,
+};
+use arrow_schema::{DataType, Field, Schema as ArrowSchema, SchemaRef};
+use datafusion::arrow::compute::{concat_batches, take};
+use datafusion::physical_plan::stream::RecordBatchStreamAdapter;
+use futures::TryStreamExt;
+use lance::dataset::scanner::DatasetRecordBatchStream;
+use lance::dataset::Dataset;
+use lance::index::vector::MetricType;
+
+use super::filter::lance_filter;
+use super::finite::is_kept;
+use super::{MultiVectorScoring, Query, DISTANCE_COLUMN};
+use crate::error::{Error, Result};
+use crate::io::bad_vectors::{mark_null_vectors, marker_column_of};
+
+/// Whether `field` is a vector column of `Float16` values.
+pub(crate) fn is_f16_vector(field: &Field) -> bool {
+    match field.data_type() {
+        DataType::FixedSizeList(item, _) => item.data_type() == &DataType::Float16,
+        _ => false,
+    }
+}
+
+/// Whether `field` is a multivector column, a `List` of vectors.
+pub(crate) fn is_multivector(field: &Field) -> bool {
+    match field.data_type() {
+        DataType::List(item) => matches!(item.data_type(), DataType::FixedSizeList(_, _)),
+        _ => false,
+    }
+}
+
+/// Search the `Float16` vector column of `query` in `dataset` for `query_vector`.
+pub(crate) async fn search_f16(
+    dataset: &Dataset,
+    query: &Query,
+    query_vector: &Float32Array,
+) -> Result<DatasetRecordBatchStream> {
+    let (schema, batch) = scan(dataset, query).await?;
+    let vectors = batch
+        .column_by_name(&query.column)
+        .and_then(|c| c.as_any().downcast_ref::<FixedSizeListArray>())
+        .unwrap();
+    let values = as_primitive_array::<Float16Type>(vectors.values().as_ref())
+        .values()
+        .iter()
+        .map(|v| v.to_f32())
+        .collect::<Vec<_>>();
+    let distances = metric_type(query).batch_func()(
+        query_vector.values(),
+        &values,
+        vectors.value_length() as usize,
+    );
+    let distances = (0..batch.num_rows())
+        .map(|row| vectors.is_valid(row).then(|| distances.value(row)))
+        .collect::<Float32Array>();
+    nearest(schema, batch, distances, query)
+}
+
+/// Search the `Float32` vector column of `query` in `dataset` for
+/// `query_vector`, among the rows matching its filter.
+pub(crate) async fn search_f32(
+    dataset: &Dataset,
+    query: &Query,
+    query_vector: &Float32Array,
+) -> Result<DatasetRecordBatchStream> {
+    let (schema, batch) = scan(dataset, query).await?;
+    let vectors = batch
+        .column_by_name(&query.column)
+        .and_then(|c| c.as_any().downcast_ref::<FixedSizeListArray>())
+        .ok_or_else(|| Error::InvalidInput {
+            message: format!("the column '{}' is not a vector column", query.column),
+        })?;
+    let values = as_primitive_array::<Float32Type>(vectors.values().as_ref()).values();
+    let distances = metric_type(query).batch_func()(
+        query_vector.values(),
+        values,
+        vectors.value_length() as usize,
+    );
+    let distances = (0..batch.num_rows())
+        .map(|row| vectors.is_valid(row).then(|| distances.value(row)))
+        .collect::<Float32Array>();
+    nearest(schema, batch, distances, query)
+}
+
+/// Search the multivector column of `query` in `dataset` for `query_vectors`,
+/// scoring the rows as set by [Query::multivector_scoring].
+pub(crate) async fn search_multivector(
+    dataset: &Dataset,
+    query: &Query,
+    query_vectors: &[Float32Array],
+) -> Result<DatasetRecordBatchStream> {
+    if query_vectors.is_empty() {
+        return Err(Error::InvalidInput {
+            message: "a multivector query needs at least one vector".to_string(),
+        });
+    }
+    let (schema, batch) = scan(dataset, query).await?;
+    let rows = batch
+        .column_by_name(&query.column)
+        .and_then(|c| c.as_any().downcast_ref::<ListArray>())
+        .unwrap();
+    let vectors = rows
+        .values()
+        .as_any()
+        .downcast_ref::<FixedSizeListArray>()
+        .unwrap();
+    let dims = vectors.value_length() as usize;
+    if let Some(vector) = query_vectors.iter().find(|v| v.len() != dims) {
+        return Err(Error::EmbeddingDimensionMismatch {
+            column: query.column.clone(),
+            expected: dims,
+            got: vector.len(),
+        });
+    }
+
+    // The distances of all vectors of the column to each query vector.
+    let values = as_primitive_array::<Float32Type>(vectors.values().as_ref()).values();
+    let distance = metric_type(query).batch_func();
+    let distances = query_vectors
+        .iter()
+        .map(|q| distance(q.values(), values, dims))
+        .collect::<Vec<_>>();
+    let offsets = rows.value_offsets();
+    let scores = (0..rows.len())
+        .map(|row| {
+            if rows.is_null(row) {
+                return None;
+            }
+            let vectors_of_row = (offsets[row] as usize..offsets[row + 1] as usize)
+                .filter(|i| vectors.is_valid(*i))
+                .collect::<Vec<_>>();
+            let nearest = distances.iter().map(|d| {
+                vectors_of_row
+                    .iter()
+                    .map(|i| d.value(*i))
+                    .min_by(f32::total_cmp)
+            });
+            match query.multivector_scoring {
+                MultiVectorScoring::Sum => nearest.sum(),
+                MultiVectorScoring::Max => nearest.flatten().min_by(f32::total_cmp),
+            }
+        })
+        .collect::<Float32Array>();
+    nearest(schema, batch, scores, query)
+}
+
+fn metric_type(query: &Query) -> MetricType {
+    query.metric_type.unwrap_or(MetricType::L2)
+}
+
+/// The rows of `dataset` matching the filter of `query`, with its columns and
+/// its vector column, whose null vectors are null. The fragments of a
+/// partitioned table that have no such rows are not read, see
+/// [Query::partition_fragments].
+pub(super) async fn scan(dataset: &Dataset, query: &Query) -> Result<(SchemaRef, RecordBatch)> {
+    let mut columns = match query.select.as_ref() {
+        Some(columns) => columns.clone(),
+        None => ArrowSchema::from(dataset.schema())
+            .fields()
+            .iter()
+            .map(|f| f.name().clone())
+            .collect(),
+    };
+    if !columns.contains(&query.column) {
+        columns.push(query.column.clone());
+    }
+    let marker = marker_column_of(&ArrowSchema::from(dataset.schema()), &query.column);
+    let keep_marker = marker.as_ref().is_none_or(|m| columns.contains(m));
+    if !keep_marker {
+        columns.extend(marker);
+    }
+    let mut scanner = dataset.scan();
+    query.scan_params.apply(&mut scanner);
+    scanner.project(&columns)?;
+    if let Some(filter) = query.filter.as_ref() {
+        let schema = ArrowSchema::from(dataset.schema());
+        scanner.filter(&lance_filter(filter, &schema)?)?;
+    }
+    let schema = scanner.schema()?;
+    // Only the fragments of the partitions matching the filter are read.
+    if let Some((fragments, _)) = query.partition_fragments(dataset).await? {
+        if fragments.is_empty() {
+            return Ok((schema.clone(), RecordBatch::new_empty(schema)));
+        }
+        scanner.with_fragments(fragments);
+    }
+    let batches = scanner
+        .try_into_stream()
+        .await?
+        .try_collect::<Vec<_>>()
+        .await?;
+    let batch = concat_batches(&schema, &batches).map_err(lance::Error::from)?;
+    let batch =
+        mark_null_vectors(&batch, &query.column, keep_marker).map_err(lance::Error::from)?;
+    Ok((batch.schema(), batch))
+}
+
+/// The `limit` rows of `batch` with the smallest `distances` for `query`, with
+/// a column of the distances like the results lance returns. Rows with a null
+/// distance are left out, as are those of a NaN or infinite distance unless
+/// the query allows them, see [Query::allow_nonfinite].
+pub(super) fn nearest(
+    schema: SchemaRef,
+    batch: RecordBatch,
+    distances: Float32Array,
+    query: &Query,
+) -> Result<DatasetRecordBatchStream> {
+    let mut rows = (0..batch.num_rows())
+        .filter(|row| {
+            distances.is_valid(*row) && is_kept(distances.value(*row), query.allow_nonfinite)
+        })
+        .collect::<Vec<_>>();
+    rows.sort_by(|a, b| distances.value(*a).total_cmp(&distances.value(*b)));
+    rows.truncate(query.limit);
+    let indices = UInt32Array::from_iter_values(rows.into_iter().map(|row| row as u32));
+
+    let mut fields = schema.fields().iter().cloned().collect::<Vec<_>>();
+    fields.push(Arc::new(Field::new(
+        DISTANCE_COLUMN,
+        DataType::Float32,
+        false,
+    )));
+    let schema = Arc::new(ArrowSchema::new(fields));
+    let distances: ArrayRef = Arc::new(distances);
+    let columns = batch
+        .columns()
+        .iter()
+        .chain(std::iter::once(&distances))
+        .map(|column| take(column.as_ref(), &indices, None))
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .map_err(lance::Error::from)?;
+    let results = RecordBatch::try_new(schema.clone(), columns).map_err(lance::Error::from)?;
+    Ok(DatasetRecordBatchStream::new(Box::pin(
+        RecordBatchStreamAdapter::new(schema, futures::stream::iter([Ok(results)])),
+    )))
+}
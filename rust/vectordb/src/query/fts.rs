@@ -0,0 +1,572 @@
+// Copyright 2023 LanceDB Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Full text search of string columns, ranking the rows by BM25.
+//!
+//! There is no full text index: a search scans its columns, and the term
+//! statistics are those of the rows it reads, the rows matching its filter.
+
+use std::sync::Arc;
+
+use arrow_array::{
+    Array, ArrayRef, Float32Array, RecordBatch, StringArray, UInt32Array, UInt64Array,
+};
+use arrow_schema::{DataType, Field as ArrowField, Schema as ArrowSchema, SchemaRef};
+use datafusion::arrow::compute::{cast, concat_batches, take};
+use futures::TryStreamExt;
+use lance::dataset::scanner::DatasetRecordBatchStream;
+use lance::dataset::ROW_ID;
+use lance::io::RecordBatchStream;
+
+use super::{batches_stream, filter, ScanParams, DEFAULT_QUERY_LIMIT};
+use crate::error::{Error, Result};
+use crate::table::DatasetRef;
+
+/// The column of the BM25 scores of the results of a [FullTextQuery].
+pub const FTS_SCORE_COLUMN: &str = "_score";
+
+/// The BM25 saturation of the frequency of a term.
+const BM25_K1: f32 = 1.2;
+
+/// The BM25 normalization of the frequency of a term by the length of the text.
+const BM25_B: f32 = 0.75;
+
+/// How a term of a full text query matches a row, see [FullTextQuery].
+///
+/// A term given more than once matches by its strictest occurrence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Occur {
+    /// The row is scored by the term, if it has it.
+    Should,
+    /// `+term`, the row has to have the term.
+    Must,
+    /// `-term`, the row must not have the term.
+    MustNot,
+}
+
+/// The lowercase words of `text`, split at the characters that are not
+/// letters or digits.
+fn tokenize(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+}
+
+/// The terms of the full text query `text`, each once, in their order in it.
+///
+/// Fails with an [Error::InvalidInput] if no term can match a row.
+fn parse_query(text: &str) -> Result<Vec<(String, Occur)>> {
+    let mut terms: Vec<(String, Occur)> = Vec::new();
+    for word in text.split_whitespace() {
+        let (occur, word) = if let Some(word) = word.strip_prefix('+') {
+            (Occur::Must, word)
+        } else if let Some(word) = word.strip_prefix('-') {
+            (Occur::MustNot, word)
+        } else {
+            (Occur::Should, word)
+        };
+        for token in tokenize(word) {
+            match terms.iter_mut().find(|(term, _)| *term == token) {
+                Some((_, existing)) => *existing = (*existing).max(occur),
+                None => terms.push((token, occur)),
+            }
+        }
+    }
+    if terms.iter().all(|(_, occur)| *occur == Occur::MustNot) {
+        return Err(Error::InvalidInput {
+            message: format!("the full text query '{text}' has no terms to match"),
+        });
+    }
+    Ok(terms)
+}
+
+/// The length and the frequencies of the query terms of a column of a row.
+#[derive(Debug, Clone)]
+struct FieldTerms {
+    length: usize,
+    frequencies: Vec<u32>,
+}
+
+/// The matching rows read so far, and the statistics of the columns.
+#[derive(Debug)]
+struct Matches {
+    terms: Vec<(String, Occur)>,
+    rows: usize,
+    /// For each column, the sum of the lengths of its texts.
+    lengths: Vec<usize>,
+    /// For each column and each term, the rows whose text has the term.
+    document_frequencies: Vec<Vec<usize>>,
+    /// For each matched row, its row id and the terms of its columns.
+    matched: Vec<(u64, Vec<FieldTerms>)>,
+    /// The matched rows, for their columns in the results.
+    batches: Vec<RecordBatch>,
+}
+
+impl Matches {
+    fn new(terms: Vec<(String, Occur)>, fields: usize) -> Self {
+        Self {
+            document_frequencies: vec![vec![0; terms.len()]; fields],
+            lengths: vec![0; fields],
+            terms,
+            rows: 0,
+            matched: Vec::new(),
+            batches: Vec::new(),
+        }
+    }
+
+    /// Count the terms of `columns`, the searched columns of `batch`, and keep
+    /// its matching rows.
+    fn add(&mut self, batch: &RecordBatch, columns: &[ArrayRef]) -> Result<()> {
+        let texts = columns
+            .iter()
+            .map(|c| cast(c, &DataType::Utf8))
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .map_err(lance::Error::from)?;
+        let texts = texts
+            .iter()
+            .map(|c| c.as_any().downcast_ref::<StringArray>().unwrap())
+            .collect::<Vec<_>>();
+        let row_ids = batch[ROW_ID]
+            .as_any()
+            .downcast_ref::<UInt64Array>()
+            .unwrap();
+        let mut kept = Vec::new();
+        for row in 0..batch.num_rows() {
+            let fields = texts
+                .iter()
+                .map(|text| self.count(text, row))
+                .collect::<Vec<_>>();
+            for (field, terms) in fields.iter().enumerate() {
+                self.lengths[field] += terms.length;
+                for (term, frequency) in terms.frequencies.iter().enumerate() {
+                    if *frequency > 0 {
+                        self.document_frequencies[field][term] += 1;
+                    }
+                }
+            }
+            if self.matches(&fields) {
+                kept.push(row as u32);
+                self.matched.push((row_ids.value(row), fields));
+            }
+        }
+        self.rows += batch.num_rows();
+        if !kept.is_empty() {
+            let indices = UInt32Array::from(kept);
+            let columns = batch
+                .columns()
+                .iter()
+                .map(|c| take(c, &indices, None))
+                .collect::<std::result::Result<Vec<_>, _>>()
+                .map_err(lance::Error::from)?;
+            let kept = RecordBatch::try_new(batch.schema(), columns).map_err(lance::Error::from)?;
+            self.batches.push(kept);
+        }
+        Ok(())
+    }
+
+    /// The length and the term frequencies of the text of `row` of `texts`.
+    fn count(&self, texts: &StringArray, row: usize) -> FieldTerms {
+        let mut terms = FieldTerms {
+            length: 0,
+            frequencies: vec![0; self.terms.len()],
+        };
+        if texts.is_null(row) {
+            return terms;
+        }
+        for token in tokenize(texts.value(row)) {
+            terms.length += 1;
+            if let Some(term) = self.terms.iter().position(|(term, _)| *term == token) {
+                terms.frequencies[term] += 1;
+            }
+        }
+        terms
+    }
+
+    /// Whether a row of the terms `fields` matches the query.
+    fn matches(&self, fields: &[FieldTerms]) -> bool {
+        let mut scored = false;
+        for (term, (_, occur)) in self.terms.iter().enumerate() {
+            let found = fields.iter().any(|f| f.frequencies[term] > 0);
+            match occur {
+                Occur::Must if !found => return false,
+                Occur::MustNot if found => return false,
+                Occur::Must | Occur::Should => scored |= found,
+                Occur::MustNot => {}
+            }
+        }
+        scored
+    }
+
+    /// The BM25 score of a row of the terms `fields`, with the columns weighted
+    /// by `boosts`.
+    fn score(&self, fields: &[FieldTerms], boosts: &[f32]) -> f32 {
+        let rows = self.rows as f32;
+        let mut score = 0.0;
+        for (field, terms) in fields.iter().enumerate() {
+            let average_length = self.lengths[field] as f32 / rows.max(1.0);
+            let norm = BM25_K1
+                * (1.0 - BM25_B + BM25_B * terms.length as f32 / average_length.max(f32::EPSILON));
+            let mut field_score = 0.0;
+            for (term, (_, occur)) in self.terms.iter().enumerate() {
+                let frequency = terms.frequencies[term] as f32;
+                if *occur == Occur::MustNot || frequency == 0.0 {
+                    continue;
+                }
+                let documents = self.document_frequencies[field][term] as f32;
+                let idf = (1.0 + (rows - documents + 0.5) / (documents + 0.5)).ln();
+                field_score += idf * frequency * (BM25_K1 + 1.0) / (frequency + norm);
+            }
+            score += boosts[field] * field_score;
+        }
+        score
+    }
+}
+
+/// A full text search of the string columns of a table, see
+/// [crate::NativeTable::full_text_search_multi].
+///
+/// The query is a list of words, matched case-insensitively. A row matches if
+/// it has any of them, in any of the searched columns; `+word` has to be in the
+/// row and `-word` must not be. The rows are scored by the BM25 scores of the
+/// words in each column, multiplied by the boost of the column and summed, and
+/// returned best first in the [FTS_SCORE_COLUMN] column. Rows of the same
+/// score are in the order of their row ids.
+#[derive(Debug, Clone)]
+pub struct FullTextQuery {
+    dataset: DatasetRef,
+    fields: Vec<(String, f32)>,
+    query: String,
+    limit: usize,
+    select: Option<Vec<String>>,
+    filter: Option<String>,
+    scan_params: ScanParams,
+}
+
+impl FullTextQuery {
+    /// A search of the columns `fields`, each with the boost of its scores,
+    /// for `query`.
+    pub(crate) fn new(
+        dataset: DatasetRef,
+        fields: Vec<(String, f32)>,
+        query: String,
+        scan_params: ScanParams,
+    ) -> Self {
+        Self {
+            dataset,
+            fields,
+            query,
+            limit: DEFAULT_QUERY_LIMIT,
+            select: None,
+            filter: None,
+            scan_params,
+        }
+    }
+
+    /// Set the maximum number of results to return. Default:
+    /// [DEFAULT_QUERY_LIMIT].
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    /// Return only the columns `columns`, and the score. Default: all the columns.
+    pub fn select(mut self, columns: Option<Vec<String>>) -> Self {
+        self.select = columns;
+        self
+    }
+
+    /// Search only the rows matching `filter`, a SQL filter as for
+    /// [super::Query::filter]. The term statistics are those of these rows.
+    pub fn filter(mut self, filter: Option<String>) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Run the search.
+    ///
+    /// Fails with an [Error::InvalidInput] if a searched column is not a
+    /// string column of the table, if a boost is not a positive number, or if
+    /// the query has no words to match.
+    pub async fn execute(&self) -> Result<DatasetRecordBatchStream> {
+        self.scan_params.validate()?;
+        let terms = parse_query(&self.query)?;
+        let dataset = self.dataset.get().await?;
+        let schema = ArrowSchema::from(dataset.schema());
+        if self.fields.is_empty() {
+            return Err(Error::InvalidInput {
+                message: "a full text search needs a column to search".to_string(),
+            });
+        }
+        for (column, boost) in self.fields.iter() {
+            let field = schema
+                .field_with_name(column)
+                .map_err(|_| Error::InvalidInput {
+                    message: format!("the table has no column '{column}' to search"),
+                })?;
+            if !matches!(field.data_type(), DataType::Utf8 | DataType::LargeUtf8) {
+                return Err(Error::InvalidInput {
+                    message: format!(
+                        "the column '{column}' is of type {}, only string columns can be searched",
+                        field.data_type()
+                    ),
+                });
+            }
+            if !boost.is_finite() || *boost <= 0.0 {
+                return Err(Error::InvalidInput {
+                    message: format!(
+                        "the boost of the column '{column}' must be positive, not {boost}"
+                    ),
+                });
+            }
+        }
+
+        let mut scanner = dataset.scan();
+        self.scan_params.apply(&mut scanner);
+        if let Some(select) = self.select.as_ref() {
+            let mut columns = select.clone();
+            for (column, _) in self.fields.iter() {
+                if !columns.contains(column) {
+                    columns.push(column.clone());
+                }
+            }
+            scanner.project(columns.as_slice())?;
+        }
+        if let Some(filter) = self.filter.as_ref() {
+            scanner.filter(&filter::lance_filter(filter, &schema)?)?;
+        }
+        scanner.with_row_id();
+        let mut stream = scanner.try_into_stream().await?;
+        let scanned = stream.schema();
+        let mut matches = Matches::new(terms, self.fields.len());
+        while let Some(batch) = stream.try_next().await? {
+            let columns = self
+                .fields
+                .iter()
+                .map(|(column, _)| batch[column.as_str()].clone())
+                .collect::<Vec<_>>();
+            matches.add(&batch, &columns)?;
+        }
+
+        let boosts = self.fields.iter().map(|(_, b)| *b).collect::<Vec<_>>();
+        let mut ranked = matches
+            .matched
+            .iter()
+            .enumerate()
+            .map(|(position, (row_id, fields))| {
+                (position as u32, *row_id, matches.score(fields, &boosts))
+            })
+            .collect::<Vec<_>>();
+        ranked.sort_by(|a, b| b.2.total_cmp(&a.2).then(a.1.cmp(&b.1)));
+        ranked.truncate(self.limit);
+
+        let output = match self.select.as_ref() {
+            Some(select) => select.clone(),
+            None => scanned
+                .fields()
+                .iter()
+                .map(|f| f.name().clone())
+                .filter(|name| name != ROW_ID)
+                .collect(),
+        };
+        let indices = output
+            .iter()
+            .map(|name| scanned.index_of(name))
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .map_err(lance::Error::from)?;
+        let mut fields = indices
+            .iter()
+            .map(|i| scanned.field(*i).clone())
+            .collect::<Vec<_>>();
+        fields.push(ArrowField::new(FTS_SCORE_COLUMN, DataType::Float32, false));
+        let results = SchemaRef::new(ArrowSchema::new(fields));
+        if ranked.is_empty() {
+            return Ok(batches_stream(results, Vec::new()));
+        }
+        let matched = concat_batches(&scanned, &matches.batches).map_err(lance::Error::from)?;
+        let positions = UInt32Array::from_iter_values(ranked.iter().map(|r| r.0));
+        let mut columns = indices
+            .iter()
+            .map(|i| take(matched.column(*i), &positions, None))
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .map_err(lance::Error::from)?;
+        let scores = Float32Array::from_iter_values(ranked.iter().map(|r| r.2));
+        columns.push(Arc::new(scores));
+        let batch = RecordBatch::try_new(results.clone(), columns).map_err(lance::Error::from)?;
+        Ok(batches_stream(results, vec![batch]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use arrow_array::{Int32Array, RecordBatchReader};
+    use lance::arrow::RecordBatchBuffer;
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::arrow::RecordBatchBuilder;
+    use crate::table::NativeTable;
+
+    fn documents(rows: &[(&str, &str)]) -> Box<dyn RecordBatchReader> {
+        let vectors = (0..rows.len()).map(|i| [i as f32, 0.0]).collect::<Vec<_>>();
+        let titles = StringArray::from_iter_values(rows.iter().map(|r| r.0));
+        let bodies = StringArray::from_iter_values(rows.iter().map(|r| r.1));
+        let batch = RecordBatchBuilder::new()
+            .column(
+                "id",
+                Arc::new(Int32Array::from_iter_values(0..rows.len() as i32)),
+            )
+            .column("title", Arc::new(titles))
+            .column("body", Arc::new(bodies))
+            .vector_column("vector", 2, vectors)
+            .build()
+            .unwrap();
+        Box::new(RecordBatchBuffer::new(vec![batch]))
+    }
+
+    async fn search(query: FullTextQuery) -> (Vec<i32>, Vec<f32>) {
+        let batches = query
+            .execute()
+            .await
+            .unwrap()
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        let mut ids = Vec::new();
+        let mut scores = Vec::new();
+        for batch in batches {
+            let id = batch["id"].as_any().downcast_ref::<Int32Array>().unwrap();
+            ids.extend(id.values().iter());
+            let score = batch[FTS_SCORE_COLUMN].as_any();
+            scores.extend(
+                score
+                    .downcast_ref::<Float32Array>()
+                    .unwrap()
+                    .values()
+                    .iter(),
+            );
+        }
+        (ids, scores)
+    }
+
+    fn occurrences(query: &str) -> HashMap<String, Occur> {
+        parse_query(query).unwrap().into_iter().collect()
+    }
+
+    #[test]
+    fn test_parse_query() {
+        let terms = occurrences("Rust +lance -Python rust +Vector-DB");
+        assert_eq!(terms.len(), 5);
+        assert_eq!(terms["rust"], Occur::Should);
+        assert_eq!(terms["lance"], Occur::Must);
+        assert_eq!(terms["python"], Occur::MustNot);
+        assert_eq!(terms["vector"], Occur::Must);
+        assert_eq!(terms["db"], Occur::Must);
+        assert_eq!(occurrences("a b -a")["a"], Occur::MustNot);
+
+        for query in ["", "  ", "-only -negative", "+ - !"] {
+            let err = parse_query(query).unwrap_err();
+            assert!(matches!(err, Error::InvalidInput { .. }), "{query}: {err}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_full_text_search_boosts() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        let rows = [
+            ("cooking at home", "pasta, pasta and bread"),
+            (
+                "fresh pasta recipes from the north of italy",
+                "recipes from italy",
+            ),
+            ("gardening", "growing tomatoes for your sauce"),
+            ("travel notes", "the best pasta in rome"),
+        ];
+        let table = NativeTable::create(uri, "docs", documents(&rows), None)
+            .await
+            .unwrap();
+
+        // The short body repeating the word scores higher than the long title.
+        let fields = [("title", 1.0), ("body", 1.0)];
+        let (ids, scores) = search(table.full_text_search_multi(&fields, "pasta")).await;
+        assert_eq!(ids, vec![0, 1, 3], "{scores:?}");
+        assert!(scores.windows(2).all(|s| s[0] > s[1]), "{scores:?}");
+        let (ids, _) = search(table.full_text_search("body", "pasta")).await;
+        assert_eq!(ids, vec![0, 3]);
+
+        // Boosted 3x, the title match wins.
+        let boosted = table.full_text_search_multi(&[("title", 3.0), ("body", 1.0)], "pasta");
+        let (ids, scores) = search(boosted.clone()).await;
+        assert_eq!(ids, vec![1, 0, 3], "{scores:?}");
+        let (ids, _) = search(boosted.clone().limit(1)).await;
+        assert_eq!(ids, vec![1]);
+
+        let (ids, _) = search(boosted.clone().filter(Some("id > 1".to_string()))).await;
+        assert_eq!(ids, vec![3]);
+        let selected = boosted.select(Some(vec!["id".to_string()]));
+        let batches = selected.execute().await.unwrap();
+        let schema = batches.schema();
+        let columns = schema.fields().iter().map(|f| f.name().as_str());
+        assert_eq!(columns.collect::<Vec<_>>(), vec!["id", FTS_SCORE_COLUMN]);
+    }
+
+    #[tokio::test]
+    async fn test_full_text_search_operators() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        let rows = [
+            ("rust", "a vector database in rust"),
+            ("python", "a vector database in python"),
+            ("notes", "rust and python bindings"),
+            ("notes", "a vector database"),
+        ];
+        let table = NativeTable::create(uri, "docs", documents(&rows), None)
+            .await
+            .unwrap();
+        let fields = [("title", 1.0), ("body", 1.0)];
+
+        let (ids, _) = search(table.full_text_search_multi(&fields, "+vector -python")).await;
+        let mut sorted = ids.clone();
+        sorted.sort();
+        assert_eq!(sorted, vec![0, 3]);
+        let (ids, _) = search(table.full_text_search_multi(&fields, "+Rust +PYTHON")).await;
+        assert_eq!(ids, vec![2]);
+        let (ids, _) = search(table.full_text_search_multi(&fields, "missing")).await;
+        assert!(ids.is_empty());
+
+        // The bodies of rows 0 and 1 tie, in the order of their row ids.
+        let (ids, scores) = search(table.full_text_search("body", "database")).await;
+        assert_eq!(ids, vec![3, 0, 1]);
+        assert_eq!(scores[1], scores[2]);
+
+        for (fields, query) in [
+            (vec![("vector", 1.0)], "rust"),
+            (vec![("missing", 1.0)], "rust"),
+            (vec![("title", 0.0)], "rust"),
+            (vec![("title", f32::NAN)], "rust"),
+            (vec![], "rust"),
+            (vec![("title", 1.0)], "-rust"),
+        ] {
+            let err = table
+                .full_text_search_multi(&fields, query)
+                .execute()
+                .await
+                .err()
+                .unwrap();
+            assert!(matches!(err, Error::InvalidInput { .. }), "{err}");
+        }
+    }
+}
@@ -0,0 +1,293 @@
+// Copyright 2023 LanceDB Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The IVF partitions of the results of a search, see
+//! [Query::with_partition_id].
+//!
+//! lance returns the rows an index search finds without their partitions. The
+//! partition of a row is that of the nearest centroid to its vector, as lance
+//! assigns the rows it indexes, so it is computed from the centroids in the
+//! metadata of the index for the rows of the fragments the index covers.
+
+use std::sync::Arc;
+
+use arrow_array::{
+    Array, ArrayRef, FixedSizeListArray, Float32Array, RecordBatch, UInt32Array, UInt64Array,
+};
+use arrow_schema::{DataType, Field, Schema as ArrowSchema, SchemaRef};
+use futures::TryStreamExt;
+use lance::dataset::scanner::DatasetRecordBatchStream;
+use lance::dataset::ROW_ID;
+use lance::io::RecordBatchStream;
+
+use super::{batches_stream, Query, QueryMetrics, QueryTarget};
+use crate::error::{Error, Result};
+use crate::table::ColumnPartitions;
+
+/// The column of the IVF partition of each result, see
+/// [Query::with_partition_id].
+pub const IVF_PARTITION_COLUMN: &str = "_ivf_partition";
+
+impl Query {
+    /// The partitions of the rows of the column of this query.
+    async fn column_partitions(&self) -> Result<ColumnPartitions> {
+        let QueryTarget::Dataset(target) = &self.target else {
+            return Err(Error::InvalidInput {
+                message: "with_partition_id is only supported for local tables".to_string(),
+            });
+        };
+        let dataset = target.get().await?;
+        let partitions = target.column_partitions(&dataset, &self.column).await?;
+        partitions.ok_or_else(|| Error::InvalidInput {
+            message: format!(
+                "with_partition_id needs an IVF index of the column '{}'",
+                self.column
+            ),
+        })
+    }
+
+    /// This query without the partitions, returning the row ids that tell
+    /// the rows the index covers.
+    fn without_partition_id(&self) -> Query {
+        let mut query = self.clone();
+        query.with_partition_id = false;
+        query.with_row_id = true;
+        query
+    }
+
+    /// [Query::execute], with the partitions of the results.
+    pub(super) async fn execute_with_partition_id(&self) -> Result<DatasetRecordBatchStream> {
+        let partitions = self.column_partitions().await?;
+        let stream = self.without_partition_id().execute_inner().await?;
+        let schema = self.partitioned_schema(&stream.schema());
+        let batches = stream.try_collect::<Vec<_>>().await?;
+        let batches = self.with_partitions(&partitions, &schema, batches)?;
+        Ok(batches_stream(schema, batches))
+    }
+
+    /// [Query::execute_with_metrics], with the partitions of the results.
+    pub(super) async fn execute_with_partition_id_metrics(
+        &self,
+    ) -> Result<(Vec<RecordBatch>, QueryMetrics)> {
+        let partitions = self.column_partitions().await?;
+        let query = self.without_partition_id();
+        let (batches, metrics) = query.execute_with_metrics_inner().await?;
+        let Some(first) = batches.first() else {
+            return Ok((batches, metrics));
+        };
+        let schema = self.partitioned_schema(&first.schema());
+        Ok((
+            self.with_partitions(&partitions, &schema, batches)?,
+            metrics,
+        ))
+    }
+
+    /// The schema of the results of `schema` with their partitions.
+    fn partitioned_schema(&self, schema: &SchemaRef) -> SchemaRef {
+        let mut fields = schema
+            .fields()
+            .iter()
+            .filter(|f| self.with_row_id || f.name() != ROW_ID)
+            .cloned()
+            .collect::<Vec<_>>();
+        fields.push(Arc::new(Field::new(
+            IVF_PARTITION_COLUMN,
+            DataType::UInt32,
+            true,
+        )));
+        Arc::new(ArrowSchema::new(fields))
+    }
+
+    /// `batches` with the partitions of their rows, in `schema`.
+    fn with_partitions(
+        &self,
+        partitions: &ColumnPartitions,
+        schema: &SchemaRef,
+        batches: Vec<RecordBatch>,
+    ) -> Result<Vec<RecordBatch>> {
+        let mut results = Vec::with_capacity(batches.len());
+        for batch in batches {
+            let vectors = batch
+                .column_by_name(&self.column)
+                .and_then(|c| c.as_any().downcast_ref::<FixedSizeListArray>())
+                .ok_or_else(|| Error::InvalidInput {
+                    message: format!(
+                        "the results have no vectors of the column '{}'",
+                        self.column
+                    ),
+                })?;
+            // The rows of a flat search with a prefilter have no row ids.
+            let row_ids = batch
+                .column_by_name(ROW_ID)
+                .and_then(|c| c.as_any().downcast_ref::<UInt64Array>());
+            let ids = (0..batch.num_rows())
+                .map(|row| {
+                    if vectors.is_null(row) {
+                        return None;
+                    }
+                    let vector = vectors.value(row);
+                    let values = vector.as_any().downcast_ref::<Float32Array>()?;
+                    let row_id = row_ids.map(|ids| ids.value(row));
+                    partitions.partition(row_id, values.values())
+                })
+                .collect::<UInt32Array>();
+            let mut columns = schema
+                .fields()
+                .iter()
+                .filter(|f| f.name() != IVF_PARTITION_COLUMN)
+                .map(|f| batch[f.name().as_str()].clone())
+                .collect::<Vec<ArrayRef>>();
+            columns.push(Arc::new(ids));
+            results
+                .push(RecordBatch::try_new(schema.clone(), columns).map_err(lance::Error::from)?);
+        }
+        Ok(results)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lance::arrow::RecordBatchBuffer;
+    use lance::index::vector::ivf::IvfBuildParams;
+    use lance::index::vector::pq::PQBuildParams;
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::arrow::{vec_to_fixed_size_list, RecordBatchBuilder};
+    use crate::index::vector::IvfPQIndexBuilder;
+    use crate::table::NativeTable;
+
+    /// The partition and vector of each of the rows of `batches`.
+    fn partitions(batches: &[RecordBatch]) -> Vec<(Option<u32>, Vec<f32>)> {
+        batches
+            .iter()
+            .flat_map(|b| {
+                let ids = b[IVF_PARTITION_COLUMN]
+                    .as_any()
+                    .downcast_ref::<UInt32Array>()
+                    .unwrap();
+                let vectors = b["vector"]
+                    .as_any()
+                    .downcast_ref::<FixedSizeListArray>()
+                    .unwrap();
+                (0..b.num_rows())
+                    .map(|row| {
+                        let vector = vectors.value(row);
+                        let vector = vector.as_any().downcast_ref::<Float32Array>().unwrap();
+                        (
+                            ids.is_valid(row).then(|| ids.value(row)),
+                            vector.values().to_vec(),
+                        )
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_with_partition_id() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        // Two clusters, each indexed in a partition.
+        let vectors = (0..400)
+            .map(|i| {
+                let center = if i % 2 == 0 { 1.0 } else { 10.0 };
+                [center + (i % 5) as f32 * 0.1, center]
+            })
+            .collect::<Vec<_>>();
+        let batch = RecordBatchBuilder::new()
+            .vector_column("vector", 2, vectors)
+            .build()
+            .unwrap();
+        let reader = Box::new(RecordBatchBuffer::new(vec![batch]));
+        let table = NativeTable::create(uri, "test", reader, None)
+            .await
+            .unwrap();
+        let err = table
+            .search(vec![1.0, 1.0])
+            .with_partition_id(true)
+            .execute()
+            .await
+            .err()
+            .unwrap();
+        assert!(matches!(err, Error::InvalidInput { .. }), "{err}");
+
+        let centroids = vec_to_fixed_size_list(2, [[10.0; 2], [1.0; 2]]).unwrap();
+        let builder = IvfPQIndexBuilder::new()
+            .ivf_params(IvfBuildParams::try_with_centroids(2, Arc::new(centroids)).unwrap())
+            .pq_params(PQBuildParams {
+                num_sub_vectors: 1,
+                ..Default::default()
+            });
+        table.create_index(&builder).await.unwrap();
+
+        // The index probes the partition of the query vector, with the rows
+        // of its cluster.
+        let query = table
+            .search(vec![1.0, 1.0])
+            .use_index(true)
+            .nprobes(1)
+            .limit(20)
+            .with_partition_id(true);
+        let stream = query.execute().await.unwrap();
+        assert!(stream.schema().field_with_name(ROW_ID).is_err());
+        let batches = stream.try_collect::<Vec<_>>().await.unwrap();
+        let results = partitions(&batches);
+        assert_eq!(results.len(), 20);
+        for (partition, vector) in &results {
+            let assigned = table.assign_partition("vector_idx", vector).await.unwrap();
+            assert_eq!(*partition, Some(assigned), "{vector:?}");
+            assert_eq!(assigned, 1);
+        }
+
+        // A flat search tells the partitions of the true nearest neighbors.
+        let (batches, _) = query
+            .clone()
+            .use_index(false)
+            .with_row_id(true)
+            .execute_with_metrics()
+            .await
+            .unwrap();
+        assert!(batches[0].column_by_name(ROW_ID).is_some());
+        let results = partitions(&batches);
+        assert!(results.iter().all(|(partition, _)| *partition == Some(1)));
+
+        // The rows appended since the index was trained are in no partition.
+        let batch = RecordBatchBuilder::new()
+            .vector_column("vector", 2, vec![[4.0, 4.0]; 5])
+            .build()
+            .unwrap();
+        let reader = Box::new(RecordBatchBuffer::new(vec![batch]));
+        table.add(reader, None).await.unwrap();
+        let batches = table
+            .search(vec![4.0, 4.0])
+            .limit(5)
+            .with_partition_id(true)
+            .execute()
+            .await
+            .unwrap()
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        let results = partitions(&batches);
+        assert_eq!(results.len(), 5);
+        assert!(results.iter().all(|(partition, _)| partition.is_none()));
+
+        let err = table
+            .assign_partition("vector_idx", &[1.0; 3])
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::InvalidInput { .. }), "{err}");
+    }
+}
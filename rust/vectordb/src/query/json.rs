@@ -0,0 +1,398 @@
+// Copyright 2023 LanceDB Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Filters and projections of the paths of JSON documents, see
+//! [crate::arrow::parse_json].
+//!
+//! lance plans its filters itself and knows no user-defined function, so a
+//! filter calling `json_extract` is planned and evaluated by datafusion, on the
+//! nearest neighbors found by lance, as lance evaluates its own filters.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use arrow_array::cast::AsArray;
+use arrow_array::{Array, ArrayRef, RecordBatch, StringArray};
+use arrow_schema::{DataType, Field, Schema as ArrowSchema, SchemaRef};
+use datafusion::arrow::compute::concat_batches;
+use datafusion::common::tree_node::{TreeNode, VisitRecursion};
+use datafusion::common::DFSchema;
+use datafusion::config::ConfigOptions;
+use datafusion::error::{DataFusionError, Result as DataFusionResult};
+use datafusion::logical_expr::{
+    AggregateUDF, ColumnarValue, Expr, ReturnTypeFunction, ScalarFunctionImplementation, ScalarUDF,
+    Signature, TableSource, TypeSignature, Volatility,
+};
+use datafusion::optimizer::simplify_expressions::{ExprSimplifier, SimplifyContext};
+use datafusion::physical_expr::execution_props::ExecutionProps;
+use datafusion::physical_expr::{create_physical_expr, PhysicalExpr};
+use datafusion::prelude::{SessionConfig, SessionContext};
+use datafusion::scalar::ScalarValue;
+use datafusion::sql::planner::{ContextProvider, PlannerContext, SqlToRel};
+use datafusion::sql::TableReference;
+
+use super::filter;
+use crate::arrow::{documents, JsonPath};
+use crate::error::{Error, Result};
+
+/// The function of filters returning the value at a path of the JSON documents
+/// of a column, as text: `json_extract(metadata, '$.lang') = 'en'`.
+///
+/// Strings are returned as they are and other values as JSON, so numbers are
+/// compared as numbers once cast, `CAST(json_extract(metadata, '$.year') AS
+/// INT) > 2020`. JSON nulls, missing values and rows that are not JSON
+/// documents are null.
+pub const JSON_EXTRACT: &str = "json_extract";
+
+/// A column added to the results of a query, of the values at `path` of the
+/// JSON documents of `column`, see [super::Query::select_json_path].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct JsonPathColumn {
+    pub name: String,
+    pub column: String,
+    pub path: String,
+}
+
+/// The `json_extract` function of datafusion, see [JSON_EXTRACT].
+fn json_extract() -> ScalarUDF {
+    let signature = Signature::one_of(
+        [
+            DataType::Utf8,
+            DataType::LargeUtf8,
+            DataType::Binary,
+            DataType::LargeBinary,
+        ]
+        .map(|documents| TypeSignature::Exact(vec![documents, DataType::Utf8]))
+        .to_vec(),
+        Volatility::Immutable,
+    );
+    let return_type: ReturnTypeFunction = Arc::new(|_| Ok(Arc::new(DataType::Utf8)));
+    let fun: ScalarFunctionImplementation = Arc::new(|args| {
+        let rows = args
+            .iter()
+            .find_map(|arg| match arg {
+                ColumnarValue::Array(array) => Some(array.len()),
+                ColumnarValue::Scalar(_) => None,
+            })
+            .unwrap_or(1);
+        let values = args[0].clone().into_array(rows);
+        let paths = args[1].clone().into_array(rows);
+        let execution = |e: Error| DataFusionError::Execution(e.to_string());
+        let documents = documents(&values).map_err(execution)?;
+        let mut parsed: HashMap<&str, JsonPath> = HashMap::new();
+        let mut extracted = Vec::with_capacity(rows);
+        for (document, path) in documents.into_iter().zip(paths.as_string::<i32>().iter()) {
+            let (Some(document), Some(path)) = (document, path) else {
+                extracted.push(None);
+                continue;
+            };
+            if !parsed.contains_key(path) {
+                parsed.insert(path, path.parse().map_err(execution)?);
+            }
+            extracted.push(parsed[path].extract_one(document));
+        }
+        Ok(ColumnarValue::Array(Arc::new(StringArray::from(extracted))))
+    });
+    ScalarUDF::new(JSON_EXTRACT, &signature, &return_type, &fun)
+}
+
+/// The functions of filters planned by datafusion: `json_extract`, and the
+/// functions of the filter's caller.
+struct JsonFunctions {
+    functions: Vec<Arc<ScalarUDF>>,
+    options: ConfigOptions,
+}
+
+impl ContextProvider for JsonFunctions {
+    fn get_table_provider(&self, name: TableReference) -> DataFusionResult<Arc<dyn TableSource>> {
+        Err(DataFusionError::Plan(format!(
+            "filters cannot read the table '{name}'"
+        )))
+    }
+
+    fn get_function_meta(&self, name: &str) -> Option<Arc<ScalarUDF>> {
+        self.functions.iter().find(|f| f.name == name).cloned()
+    }
+
+    fn get_aggregate_meta(&self, _name: &str) -> Option<Arc<AggregateUDF>> {
+        None
+    }
+
+    fn get_variable_type(&self, _variable_names: &[String]) -> Option<DataType> {
+        None
+    }
+
+    fn options(&self) -> &ConfigOptions {
+        &self.options
+    }
+}
+
+/// A filter calling `json_extract`, planned by datafusion.
+#[derive(Debug, Clone)]
+pub(crate) struct JsonFilter {
+    expr: Expr,
+    /// The columns of the table the filter reads.
+    pub columns: Vec<String>,
+}
+
+impl JsonFilter {
+    /// `filter` planned for a table of `schema`, if it calls `json_extract`.
+    /// The JSON paths of the filter are checked.
+    pub(crate) fn plan(filter: &str, schema: &ArrowSchema) -> Result<Option<Self>> {
+        if !filter.to_ascii_lowercase().contains(JSON_EXTRACT) {
+            return Ok(None);
+        }
+        let (planned, calls) = Self::planned(filter, schema, Vec::new())?;
+        // Unless the name is in a string, then lance plans the filter.
+        Ok(calls.then_some(planned))
+    }
+
+    /// `filter` planned for a table of `schema`, whether or not it calls
+    /// `json_extract`, to evaluate it on rows read without it.
+    pub(crate) fn plan_any(filter: &str, schema: &ArrowSchema) -> Result<Self> {
+        Self::plan_with(filter, schema, Vec::new())
+    }
+
+    /// `filter` planned for a table of `schema`, which may also call
+    /// `functions`, to evaluate it on rows read without it.
+    pub(crate) fn plan_with(
+        filter: &str,
+        schema: &ArrowSchema,
+        functions: Vec<Arc<ScalarUDF>>,
+    ) -> Result<Self> {
+        Ok(Self::planned(filter, schema, functions)?.0)
+    }
+
+    /// `filter` planned for a table of `schema`, and whether it calls
+    /// `json_extract`.
+    fn planned(
+        filter: &str,
+        schema: &ArrowSchema,
+        mut functions: Vec<Arc<ScalarUDF>>,
+    ) -> Result<(Self, bool)> {
+        let invalid = |e: &dyn std::fmt::Display| Error::InvalidInput {
+            message: format!("invalid filter '{filter}': {e}"),
+        };
+        let sql = filter::parse(filter).map_err(|e| invalid(&e))?;
+        functions.push(Arc::new(json_extract()));
+        let functions = JsonFunctions {
+            functions,
+            options: ConfigOptions::default(),
+        };
+        let df_schema = DFSchema::try_from(schema.clone()).map_err(lance::Error::from)?;
+        let expr = SqlToRel::new(&functions)
+            .sql_to_expr(sql, &df_schema, &mut PlannerContext::new())
+            .map_err(|e| invalid(&e))?;
+
+        let mut calls = false;
+        let mut paths = Vec::new();
+        expr.apply(&mut |e| {
+            if let Expr::ScalarUDF(udf) = e {
+                calls |= udf.fun.name == JSON_EXTRACT;
+                if let Some(Expr::Literal(ScalarValue::Utf8(Some(path)))) = udf.args.get(1) {
+                    paths.push(path.clone());
+                }
+            }
+            Ok(VisitRecursion::Continue)
+        })
+        .map_err(lance::Error::from)?;
+        for path in paths {
+            path.parse::<JsonPath>()?;
+        }
+        let mut columns = expr
+            .to_columns()
+            .map_err(lance::Error::from)?
+            .into_iter()
+            .map(|c| c.name)
+            .collect::<Vec<_>>();
+        columns.sort();
+        Ok((Self { expr, columns }, calls))
+    }
+
+    /// The filter as a physical expression of the batches of `schema`, to
+    /// evaluate it on batches one at a time and without a runtime.
+    pub(crate) fn physical(&self, schema: &SchemaRef) -> Result<Arc<dyn PhysicalExpr>> {
+        let df_schema =
+            Arc::new(DFSchema::try_from(schema.as_ref().clone()).map_err(lance::Error::from)?);
+        let props = ExecutionProps::new();
+        // The physical planner does not coerce the types of comparisons.
+        let simplifier =
+            ExprSimplifier::new(SimplifyContext::new(&props).with_schema(df_schema.clone()));
+        let expr = simplifier
+            .coerce(self.expr.clone(), df_schema.clone())
+            .and_then(|expr| create_physical_expr(&expr, &df_schema, schema, &props))
+            .map_err(lance::Error::from)?;
+        Ok(expr)
+    }
+
+    /// The rows of `batches` that match the filter, in the same order.
+    pub(crate) async fn apply(&self, batches: Vec<RecordBatch>) -> Result<Vec<RecordBatch>> {
+        let Some(schema) = batches.first().map(RecordBatch::schema) else {
+            return Ok(batches);
+        };
+        let batch = concat_batches(&schema, &batches).map_err(lance::Error::from)?;
+        // A single partition keeps the order of the rows.
+        let context = SessionContext::with_config(SessionConfig::new().with_target_partitions(1));
+        let frame = context
+            .read_batch(batch)
+            .and_then(|frame| frame.filter(self.expr.clone()))
+            .map_err(lance::Error::from)?;
+        Ok(frame.collect().await.map_err(lance::Error::from)?)
+    }
+}
+
+/// `batch` with the columns of `projections` appended.
+pub(crate) fn project_paths(
+    batch: &RecordBatch,
+    projections: &[JsonPathColumn],
+) -> Result<RecordBatch> {
+    let schema = batch.schema();
+    let mut fields = schema
+        .fields()
+        .iter()
+        .map(|f| f.as_ref().clone())
+        .collect::<Vec<_>>();
+    let mut columns = batch.columns().to_vec();
+    for projection in projections {
+        let path = projection.path.parse::<JsonPath>()?;
+        let documents =
+            batch
+                .column_by_name(&projection.column)
+                .ok_or_else(|| Error::InvalidInput {
+                    message: format!("the results have no column '{}'", projection.column),
+                })?;
+        let values: ArrayRef = Arc::new(path.extract(documents.as_ref())?);
+        fields.push(Field::new(&projection.name, DataType::Utf8, true));
+        columns.push(values);
+    }
+    let schema = Arc::new(ArrowSchema::new_with_metadata(
+        fields,
+        schema.metadata().clone(),
+    ));
+    Ok(RecordBatch::try_new(schema, columns).map_err(lance::Error::from)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow_array::Int32Array;
+    use serde_json::json;
+
+    use super::*;
+    use crate::arrow::RecordBatchBuilder;
+
+    fn batch() -> RecordBatch {
+        RecordBatchBuilder::new()
+            .column("id", Arc::new(Int32Array::from_iter_values(0..4)))
+            .json_column(
+                "metadata",
+                [
+                    json!({"lang": "en", "source": {"year": 2021, "tags": ["a", "b"]}}),
+                    json!({"lang": "fr", "source": {"year": 2019}}),
+                    json!({"source": {"year": 2023, "tags": ["b"]}}),
+                    serde_json::Value::Null,
+                ],
+            )
+            .build()
+            .unwrap()
+    }
+
+    async fn ids(filter: &str) -> Vec<i32> {
+        let batch = batch();
+        let plan = JsonFilter::plan(filter, &batch.schema()).unwrap().unwrap();
+        let batches = plan
+            .apply(vec![batch.slice(0, 2), batch.slice(2, 2)])
+            .await
+            .unwrap();
+        batches
+            .iter()
+            .flat_map(|b| {
+                b["id"]
+                    .as_any()
+                    .downcast_ref::<Int32Array>()
+                    .unwrap()
+                    .values()
+                    .to_vec()
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_json_filter() {
+        assert_eq!(
+            ids("json_extract(metadata, '$.lang') = 'en'").await,
+            vec![0]
+        );
+        assert_eq!(
+            ids("CAST(json_extract(`metadata`, '$.source.year') AS INT) > 2020 AND id > 0").await,
+            vec![2]
+        );
+        assert_eq!(
+            ids("json_extract(metadata, '$.source.tags[0]') = 'b'").await,
+            vec![2]
+        );
+        assert_eq!(
+            ids("json_extract(metadata, '$.lang') IS NULL").await,
+            vec![2, 3]
+        );
+        assert_eq!(
+            ids("JSON_EXTRACT(metadata, '$.source.tags') IS NOT NULL OR id = 1").await,
+            vec![0, 1, 2]
+        );
+
+        let schema = batch().schema();
+        let plan = JsonFilter::plan("json_extract(metadata, '$.lang') = 'en'", &schema).unwrap();
+        assert_eq!(plan.unwrap().columns, vec!["metadata"]);
+        // Filters of lance.
+        assert!(JsonFilter::plan("id > 1", &schema).unwrap().is_none());
+        assert!(JsonFilter::plan("metadata = 'json_extract'", &schema)
+            .unwrap()
+            .is_none());
+        for filter in [
+            "json_extract(metadata, 'lang') = 'en'",
+            "json_extract(missing, '$.lang') = 'en'",
+            "json_extract(metadata, '$.lang') = = 'en'",
+        ] {
+            let err = JsonFilter::plan(filter, &schema).unwrap_err();
+            assert!(matches!(err, Error::InvalidInput { .. }), "{filter}: {err}");
+        }
+    }
+
+    #[test]
+    fn test_project_paths() {
+        let projections = [
+            JsonPathColumn {
+                name: "year".to_string(),
+                column: "metadata".to_string(),
+                path: "$.source.year".to_string(),
+            },
+            JsonPathColumn {
+                name: "tags".to_string(),
+                column: "metadata".to_string(),
+                path: "$.source.tags".to_string(),
+            },
+        ];
+        let projected = project_paths(&batch(), &projections).unwrap();
+        assert_eq!(projected.num_columns(), 4);
+        let years = projected["year"].as_string::<i32>();
+        assert_eq!(
+            years.iter().collect::<Vec<_>>(),
+            vec![Some("2021"), Some("2019"), Some("2023"), None]
+        );
+        let tags = projected["tags"].as_string::<i32>();
+        assert_eq!(
+            tags.iter().collect::<Vec<_>>(),
+            vec![Some(r#"["a","b"]"#), None, Some(r#"["b"]"#), None]
+        );
+    }
+}
@@ -0,0 +1,415 @@
+// Copyright 2023 LanceDB Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The results of a query written to a new table, see [Query::materialize].
+
+use arrow_array::{RecordBatch, RecordBatchIterator, RecordBatchReader};
+use arrow_schema::{Schema as ArrowSchema, SchemaRef};
+use futures::stream::BoxStream;
+use futures::{StreamExt, TryStreamExt};
+use lance::dataset::WriteMode;
+use lance::io::RecordBatchStream;
+
+use super::{filter, Query, QueryTarget, DISTANCE_COLUMN};
+use crate::database::{CreateTableBuilder, CreateTableMode, Database};
+use crate::error::{Error, Result};
+use crate::table::TableRef;
+
+/// Default number of rows [MaterializeBuilder] collects before writing them
+/// to the new table.
+pub const DEFAULT_MATERIALIZE_ROWS_PER_WRITE: usize = 64 * 1024;
+
+/// A builder for the table holding the results of a query, see
+/// [Query::materialize].
+pub struct MaterializeBuilder<'a> {
+    query: Query,
+    db: &'a Database,
+    name: String,
+    mode: CreateTableMode,
+    copy_embeddings: bool,
+    rows_per_write: usize,
+}
+
+impl MaterializeBuilder<'_> {
+    /// Set what to do if the table already exists, see [CreateTableMode].
+    /// With [CreateTableMode::ExistOk] the existing table is returned and the
+    /// query is not run. Default: [CreateTableMode::Create].
+    pub fn mode(mut self, mode: CreateTableMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Register the embedding functions of the queried table with the new
+    /// table, those whose source and vector columns are both in the results,
+    /// so that rows added to it later get their vectors computed. Default:
+    /// `false`.
+    pub fn copy_embeddings(mut self, copy: bool) -> Self {
+        self.copy_embeddings = copy;
+        self
+    }
+
+    /// Write the results to the new table `rows` at a time, at least, each
+    /// write after the first one appending a version. Default:
+    /// [DEFAULT_MATERIALIZE_ROWS_PER_WRITE].
+    pub fn rows_per_write(mut self, rows: usize) -> Self {
+        self.rows_per_write = rows;
+        self
+    }
+
+    /// Run the query and write its results to the new table.
+    ///
+    /// Fails with an [Error::InvalidInput] if the query returns no rows, a
+    /// table cannot be created without them.
+    ///
+    /// # Returns
+    ///
+    /// * A [TableRef] to the new table.
+    pub async fn execute(self) -> Result<TableRef> {
+        // Boxed, the searches and writes of the future are too large for the
+        // stack of the callers.
+        Box::pin(self.materialize()).await
+    }
+
+    async fn materialize(mut self) -> Result<TableRef> {
+        let (mode, exist_ok) = match std::mem::take(&mut self.mode) {
+            CreateTableMode::ExistOk(callback) => (CreateTableMode::Create, Some(callback)),
+            mode => (mode, None),
+        };
+        let mut rows = self.query.materialized_rows().await?;
+        let schema = rows.schema();
+        let open_existing = |callback| {
+            let empty = reader(schema.clone(), Vec::new());
+            self.db
+                .create_table(&self.name, empty)
+                .mode(CreateTableMode::ExistOk(callback))
+                .execute()
+        };
+        if let Some(callback) = exist_ok {
+            if self.db.table_names().await?.contains(&self.name) {
+                return open_existing(callback).await;
+            }
+            return match self.write(mode, &mut rows).await {
+                // Another writer created the table in the meantime.
+                Err(Error::TableAlreadyExists { .. }) => open_existing(callback).await,
+                result => result,
+            };
+        }
+        self.write(mode, &mut rows).await
+    }
+
+    /// Create the table with the first rows of `rows` and append the others.
+    async fn write(&self, mode: CreateTableMode, rows: &mut MaterializedRows) -> Result<TableRef> {
+        let schema = rows.schema();
+        let mut mode = Some(mode);
+        let mut table: Option<TableRef> = None;
+        let mut pending = Vec::new();
+        let mut pending_rows = 0;
+        loop {
+            let batch = rows.batches.try_next().await?;
+            let done = batch.is_none();
+            if let Some(batch) = batch {
+                pending_rows += batch.num_rows();
+                pending.push(batch);
+            }
+            if !done && pending_rows < self.rows_per_write {
+                continue;
+            }
+            if done && table.is_none() && pending_rows == 0 {
+                return Err(Error::InvalidInput {
+                    message: format!(
+                        "the query returned no rows, the table '{}' would have none",
+                        self.name
+                    ),
+                });
+            }
+            let batches = reader(schema.clone(), std::mem::take(&mut pending));
+            match (mode.take(), table.as_ref()) {
+                (Some(mode), _) => table = Some(self.create(mode, batches).execute().await?),
+                (None, Some(table)) if pending_rows > 0 => {
+                    table.add(batches, Some(WriteMode::Append)).await?;
+                }
+                _ => {}
+            }
+            pending_rows = 0;
+            if let (true, Some(table)) = (done, table.as_ref()) {
+                return Ok(table.clone());
+            }
+        }
+    }
+
+    /// The builder of the table, created with `batches`.
+    fn create(
+        &self,
+        mode: CreateTableMode,
+        batches: Box<dyn RecordBatchReader>,
+    ) -> CreateTableBuilder<'_> {
+        let schema = batches.schema();
+        let mut create = self.db.create_table(&self.name, batches).mode(mode);
+        if !self.copy_embeddings {
+            return create;
+        }
+        let copied = self.query.embeddings.iter().filter(|e| {
+            schema.field_with_name(&e.source_column).is_ok()
+                && schema.field_with_name(&e.vector_column).is_ok()
+        });
+        for embedding in copied {
+            create = create.embedding(
+                &embedding.source_column,
+                &embedding.vector_column,
+                embedding.function.clone(),
+            );
+        }
+        create
+    }
+}
+
+/// The rows of a materialized query, and their schema.
+struct MaterializedRows {
+    schema: SchemaRef,
+    batches: BoxStream<'static, Result<RecordBatch>>,
+}
+
+impl MaterializedRows {
+    fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+}
+
+fn reader(schema: SchemaRef, batches: Vec<RecordBatch>) -> Box<dyn RecordBatchReader + Send> {
+    Box::new(RecordBatchIterator::new(
+        batches.into_iter().map(Ok),
+        schema,
+    ))
+}
+
+impl Query {
+    /// Write the results of this query to the new table `name` of `db`.
+    ///
+    /// The rows are written as they are read, [MaterializeBuilder::rows_per_write]
+    /// at a time, with the columns of the results but the `score` of a vector
+    /// search. A query with [Query::limit_none] writes every row of its
+    /// table matching its filter, read by a scan of the table without
+    /// searching; its [Query::select] and [Query::with_row_id] still apply.
+    pub fn materialize<'a>(&self, db: &'a Database, name: &str) -> MaterializeBuilder<'a> {
+        MaterializeBuilder {
+            query: self.clone(),
+            db,
+            name: name.to_string(),
+            mode: CreateTableMode::Create,
+            copy_embeddings: false,
+            rows_per_write: DEFAULT_MATERIALIZE_ROWS_PER_WRITE,
+        }
+    }
+
+    /// The rows written by [Query::materialize].
+    async fn materialized_rows(&self) -> Result<MaterializedRows> {
+        if self.unlimited {
+            return self.within_scope()?.scan().await;
+        }
+        // The search is boxed, its future is too large to be held in that of
+        // the writes.
+        let stream = Box::pin(self.execute()).await?;
+        let schema = stream.schema();
+        // The materialized table does not keep the distances.
+        let Ok(score) = schema.index_of(DISTANCE_COLUMN) else {
+            return Ok(MaterializedRows {
+                schema,
+                batches: stream.map_err(Error::from).boxed(),
+            });
+        };
+        let kept = (0..schema.fields().len())
+            .filter(|i| *i != score)
+            .collect::<Vec<_>>();
+        let projected = schema.project(&kept).map_err(lance::Error::from)?;
+        let batches = stream.map_err(Error::from).and_then(move |batch| {
+            let batch = batch.project(&kept).map_err(lance::Error::from);
+            async move { Ok(batch?) }
+        });
+        Ok(MaterializedRows {
+            schema: SchemaRef::new(projected),
+            batches: batches.boxed(),
+        })
+    }
+
+    /// The rows of the table matching the filter of this query.
+    async fn scan(&self) -> Result<MaterializedRows> {
+        let QueryTarget::Dataset(target) = &self.target else {
+            return Err(Error::InvalidInput {
+                message: "a query of a remote table needs a limit to be materialized".to_string(),
+            });
+        };
+        let dataset = target.tuned(target.get().await?, &self.scan_params).await?;
+        let mut scanner = dataset.scan();
+        self.scan_params.apply(&mut scanner);
+        if let Some(columns) = self.select.as_ref() {
+            scanner.project(columns.as_slice())?;
+        }
+        if let Some(filter) = self.filter.as_ref() {
+            let schema = ArrowSchema::from(dataset.schema());
+            scanner.filter(&filter::lance_filter(filter, &schema)?)?;
+        }
+        if self.with_row_id {
+            scanner.with_row_id();
+        }
+        let stream = scanner.try_into_stream().await?;
+        Ok(MaterializedRows {
+            schema: stream.schema(),
+            batches: stream.map_err(Error::from).boxed(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_array::{Array, ArrayRef, FixedSizeListArray, Float32Array, Int32Array};
+    use arrow_schema::DataType;
+    use async_trait::async_trait;
+    use lance::arrow::{FixedSizeListArrayExt, RecordBatchBuffer};
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::arrow::RecordBatchBuilder;
+    use crate::connect;
+    use crate::embeddings::EmbeddingFunction;
+
+    /// Embeds integers as `[i, 0]`.
+    #[derive(Debug)]
+    struct IntEmbedding;
+
+    #[async_trait]
+    impl EmbeddingFunction for IntEmbedding {
+        fn name(&self) -> String {
+            "int".to_string()
+        }
+
+        fn source_type(&self) -> DataType {
+            DataType::Int32
+        }
+
+        fn dims(&self) -> usize {
+            2
+        }
+
+        async fn embed(&self, input: ArrayRef) -> Result<ArrayRef> {
+            let ints = input.as_any().downcast_ref::<Int32Array>().unwrap();
+            let values =
+                Float32Array::from_iter_values(ints.values().iter().flat_map(|i| [*i as f32, 0.0]));
+            Ok(Arc::new(FixedSizeListArray::try_new(values, 2)?))
+        }
+    }
+
+    fn ids(range: std::ops::Range<i32>) -> Box<dyn RecordBatchReader + Send> {
+        let batch = RecordBatchBuilder::new()
+            .column("id", Arc::new(Int32Array::from_iter_values(range)))
+            .build()
+            .unwrap();
+        Box::new(RecordBatchBuffer::new(vec![batch]))
+    }
+
+    async fn collect_ids(table: &TableRef, vector: f32, limit: usize) -> Vec<i32> {
+        let stream = table.search(vec![vector, 0.0].into()).limit(limit);
+        let batches: Vec<RecordBatch> =
+            stream.execute().await.unwrap().try_collect().await.unwrap();
+        batches
+            .iter()
+            .flat_map(|b| {
+                let ids = b["id"].as_any().downcast_ref::<Int32Array>().unwrap();
+                ids.values().to_vec()
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_materialize() {
+        let tmp_dir = tempdir().unwrap();
+        let db = connect(tmp_dir.path().to_str().unwrap())
+            .execute()
+            .await
+            .unwrap();
+        let docs = db
+            .create_table("docs", ids(0..100))
+            .embedding("id", "vector", Arc::new(IntEmbedding))
+            .execute()
+            .await
+            .unwrap();
+
+        // A filtered scan, written a few rows at a time.
+        let even = docs
+            .search(vec![0.0, 0.0].into())
+            .filter(Some("id % 2 = 0".to_string()))
+            .limit_none();
+        let table = even
+            .materialize(&db, "even")
+            .rows_per_write(16)
+            .copy_embeddings(true)
+            .execute()
+            .await
+            .unwrap();
+        assert_eq!(table.count_rows().await.unwrap(), 50);
+        assert_eq!(table.schema().await.unwrap(), docs.schema().await.unwrap());
+        assert_eq!(collect_ids(&table, 10.2, 3).await, vec![10, 12, 8]);
+        // The vectors of added rows are computed by the copied embedding.
+        table.add(ids(1000..1001), None).await.unwrap();
+        assert_eq!(collect_ids(&table, 999.0, 1).await, vec![1000]);
+
+        // The results of a vector search, without their score.
+        let nearest = docs.search(vec![20.0, 0.0].into()).limit(5);
+        let nearest = nearest.select(Some(vec!["id".to_string(), "vector".to_string()]));
+        let table = nearest.materialize(&db, "near").execute().await.unwrap();
+        let schema = table.schema().await.unwrap();
+        let names = schema.fields().iter().map(|f| f.name().as_str());
+        assert_eq!(names.collect::<Vec<_>>(), vec!["id", "vector"]);
+        let mut found = collect_ids(&table, 20.0, 10).await;
+        found.sort();
+        assert_eq!(found, vec![18, 19, 20, 21, 22]);
+
+        // Existing tables follow the CreateTableMode.
+        let err = nearest
+            .materialize(&db, "even")
+            .execute()
+            .await
+            .err()
+            .unwrap();
+        assert!(matches!(err, Error::TableAlreadyExists { .. }), "{err}");
+        let kept = nearest
+            .materialize(&db, "even")
+            .mode(CreateTableMode::exist_ok(|params| params))
+            .execute()
+            .await
+            .unwrap();
+        assert_eq!(kept.count_rows().await.unwrap(), 51);
+        let replaced = nearest
+            .materialize(&db, "even")
+            .mode(CreateTableMode::Overwrite)
+            .execute()
+            .await
+            .unwrap();
+        assert_eq!(replaced.count_rows().await.unwrap(), 5);
+
+        // lance does not create tables without rows.
+        let none = docs
+            .search(vec![0.0, 0.0].into())
+            .filter(Some("id > 1000".to_string()))
+            .limit_none();
+        let err = none.materialize(&db, "none").execute().await.err().unwrap();
+        assert!(matches!(err, Error::InvalidInput { .. }), "{err}");
+        assert!(!db
+            .table_names()
+            .await
+            .unwrap()
+            .contains(&"none".to_string()));
+    }
+}
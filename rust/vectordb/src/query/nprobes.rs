@@ -0,0 +1,198 @@
+// Copyright 2023 LanceDB Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The filtered index searches probing more partitions while they have fewer
+//! than `limit` results, see [Query::maximum_nprobes].
+//!
+//! lance filters the `limit` nearest rows of the partitions it probes, so
+//! probing more partitions alone does not find more rows matching the filter.
+//! Each search of more partitions asks for all the rows of the partitions it
+//! probes instead, by the partition lengths of the IVF index, so that all of
+//! them are filtered.
+
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use arrow_array::{Float32Array, RecordBatch};
+use futures::TryStreamExt;
+use lance::dataset::scanner::DatasetRecordBatchStream;
+use lance::dataset::Dataset;
+use lance::io::RecordBatchStream;
+
+use super::{batches_stream, truncate, Query, QueryTarget};
+use crate::error::Result;
+
+impl Query {
+    /// The rows of the partitions of the index of the column, in the order
+    /// this search of `dataset` probes them, if it probes more partitions
+    /// while it has fewer than `limit` results. `None` for an unfiltered
+    /// search, a flat one, or one without a [Query::maximum_nprobes] over
+    /// its [Query::nprobes].
+    pub(super) async fn widened_probes(
+        &self,
+        dataset: &Dataset,
+        query_vector: &Float32Array,
+    ) -> Result<Option<Vec<usize>>> {
+        let (Some(_), QueryTarget::Dataset(target)) = (self.filter.as_ref(), &self.target) else {
+            return Ok(None);
+        };
+        if self.maximum_nprobes.is_none_or(|max| max <= self.nprobes)
+            || !self.uses_index(dataset).await?
+        {
+            return Ok(None);
+        }
+        target
+            .probed_rows(dataset, &self.column, query_vector.values())
+            .await
+    }
+
+    /// The results of this postfiltered index search of `dataset`, probing
+    /// twice as many partitions as the last search, up to the
+    /// [Query::maximum_nprobes], while it has fewer than `limit` results.
+    ///
+    /// `probed_rows` are the rows of the partitions in the order they are
+    /// probed.
+    pub(super) async fn search_widening_probes(
+        &self,
+        dataset: Arc<Dataset>,
+        query_vector: &Float32Array,
+        probed_rows: &[usize],
+    ) -> Result<DatasetRecordBatchStream> {
+        let partitions = probed_rows.len();
+        let maximum = self.maximum_nprobes.unwrap_or(self.nprobes).min(partitions);
+        let mut query = self.clone();
+        let mut stream = self.search_dataset(dataset.clone(), query_vector).await?;
+        loop {
+            let schema = stream.schema();
+            let batches: Vec<RecordBatch> = stream.try_collect().await?;
+            let rows = batches.iter().map(RecordBatch::num_rows).sum::<usize>();
+            if rows >= self.limit || query.nprobes >= maximum {
+                if let Some(probes_used) = self.probes_used.as_ref() {
+                    probes_used.store(query.nprobes.min(partitions), Ordering::Relaxed);
+                }
+                return Ok(batches_stream(schema, truncate(batches, self.limit)));
+            }
+            query.nprobes = (query.nprobes.max(1) * 2).min(maximum);
+            let candidates = probed_rows[..query.nprobes].iter().sum::<usize>();
+            query.limit = candidates.max(self.limit);
+            stream = query.search_dataset(dataset.clone(), query_vector).await?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_array::{Int32Array, RecordBatchReader};
+    use lance::arrow::RecordBatchBuffer;
+    use lance::index::vector::ivf::IvfBuildParams;
+    use lance::index::vector::pq::PQBuildParams;
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::arrow::{vec_to_fixed_size_list, RecordBatchBuilder};
+    use crate::index::vector::IvfPQIndexBuilder;
+    use crate::query::FilterMode;
+    use crate::table::NativeTable;
+
+    /// The clusters of the vectors, each indexed in a partition.
+    const CLUSTERS: i32 = 16;
+
+    /// The center of the cluster `cluster`.
+    fn center(cluster: i32) -> [f32; 2] {
+        [cluster as f32 * 10.0; 2]
+    }
+
+    /// 100 rows of each cluster, the rows with an id divisible by 50 in the
+    /// even clusters.
+    fn rows() -> Box<dyn RecordBatchReader> {
+        let rows = 100 * CLUSTERS;
+        let vectors = (0..rows)
+            .map(|i| {
+                let [x, y] = center(i % CLUSTERS);
+                [x + (i / CLUSTERS) as f32 * 0.01, y]
+            })
+            .collect::<Vec<_>>();
+        let batch = RecordBatchBuilder::new()
+            .column("id", Arc::new(Int32Array::from_iter_values(0..rows)))
+            .vector_column("vector", 2, vectors)
+            .build()
+            .unwrap();
+        Box::new(RecordBatchBuffer::new(vec![batch]))
+    }
+
+    fn count(batches: &[RecordBatch]) -> usize {
+        batches.iter().map(RecordBatch::num_rows).sum()
+    }
+
+    #[tokio::test]
+    async fn test_maximum_nprobes() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        let table = NativeTable::create(uri, "test", rows(), None)
+            .await
+            .unwrap();
+        let centroids = vec_to_fixed_size_list(2, (0..CLUSTERS).map(center)).unwrap();
+        let builder = IvfPQIndexBuilder::new()
+            .ivf_params(
+                IvfBuildParams::try_with_centroids(CLUSTERS as usize, Arc::new(centroids)).unwrap(),
+            )
+            .pq_params(PQBuildParams {
+                num_sub_vectors: 1,
+                ..Default::default()
+            });
+        table.create_index(&builder).await.unwrap();
+
+        // The partitions of the even clusters have 4 of the rows matching the
+        // filter each, the nearest one probed first and the farthest last.
+        let search = |maximum: Option<usize>| {
+            table
+                .search(vec![0.0, 0.0])
+                .use_index(true)
+                .limit(20)
+                .minimum_nprobes(1)
+                .maximum_nprobes(maximum)
+                .filter(Some("id % 50 = 0".to_string()))
+                .filter_mode(FilterMode::Postfilter)
+        };
+        let (batches, metrics) = search(None).execute_with_metrics().await.unwrap();
+        assert!(count(&batches) <= 1, "{batches:?}");
+        assert_eq!(metrics.index_partitions_probed, Some(1));
+        for (maximum, found) in [(2, 4), (4, 8), (8, 16), (16, 20)] {
+            let (batches, metrics) = search(Some(maximum)).execute_with_metrics().await.unwrap();
+            assert_eq!(count(&batches), found, "{maximum}");
+            assert_eq!(metrics.index_partitions_probed, Some(maximum));
+        }
+
+        // The search stops probing as soon as it has `limit` results.
+        let (batches, metrics) = search(Some(16))
+            .limit(4)
+            .execute_with_metrics()
+            .await
+            .unwrap();
+        assert_eq!(count(&batches), 4);
+        assert_eq!(metrics.index_partitions_probed, Some(2));
+
+        // The unfiltered search probes the minimum partitions, whatever the
+        // maximum.
+        let unfiltered = |maximum| search(maximum).filter(None).filter_mode(FilterMode::Auto);
+        let (batches, metrics) = unfiltered(None).execute_with_metrics().await.unwrap();
+        let (widened, widened_metrics) = unfiltered(Some(16)).execute_with_metrics().await.unwrap();
+        assert_eq!(count(&batches), 20);
+        assert_eq!(widened, batches);
+        assert_eq!(metrics.index_partitions_probed, Some(1));
+        assert_eq!(widened_metrics.index_partitions_probed, Some(1));
+    }
+}
@@ -0,0 +1,437 @@
+// Copyright 2023 LanceDB Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The stable order of [Query::ordered] results.
+//!
+//! lance ranks the rows by distance, and rows of the same distance come in the
+//! order their batches were read, which changes with the IO parallelism. An
+//! index search probes the same partitions every time, but reads them
+//! concurrently too, and its rows of the distance of the last one kept at the
+//! limit change with the order the partitions were read. The query is run for
+//! more rows than its limit, until the rows of the distance of the last row
+//! kept are all found, and ties are ordered by row id. A postfiltered query
+//! first finds the rows it keeps without the filter.
+
+use std::cmp::Ordering;
+use std::sync::Arc;
+
+use arrow_array::{Array, BooleanArray, Float32Array, RecordBatch, UInt32Array, UInt64Array};
+use arrow_schema::{Schema as ArrowSchema, SchemaRef};
+use datafusion::arrow::compute::{concat_batches, filter_record_batch, take};
+use futures::TryStreamExt;
+use lance::arrow::RecordBatchExt;
+use lance::dataset::scanner::DatasetRecordBatchStream;
+use lance::dataset::ROW_ID;
+use lance::io::RecordBatchStream;
+
+use super::{batches_stream, FilterMode, Query, QueryMetrics, QueryTarget, DISTANCE_COLUMN};
+use crate::error::Result;
+
+/// The distances of the rows of `batch`, `None` if it has none.
+fn scores(batch: &RecordBatch) -> Option<&Float32Array> {
+    batch
+        .column_by_name(DISTANCE_COLUMN)?
+        .as_any()
+        .downcast_ref()
+}
+
+/// The row ids of the rows of `batch`, `None` if it has none.
+fn row_ids(batch: &RecordBatch) -> Option<&UInt64Array> {
+    batch.column_by_name(ROW_ID)?.as_any().downcast_ref()
+}
+
+/// The rows of `batch` by distance, then by row id if it has row ids.
+fn sort(batch: &RecordBatch) -> Result<RecordBatch> {
+    let Some(scores) = scores(batch) else {
+        return Ok(batch.clone());
+    };
+    let row_ids = row_ids(batch);
+    let score = |row: usize| scores.is_valid(row).then(|| scores.value(row));
+    let mut rows = (0..batch.num_rows()).collect::<Vec<_>>();
+    rows.sort_by(|a, b| {
+        let by_score = match (score(*a), score(*b)) {
+            (Some(a), Some(b)) => a.total_cmp(&b),
+            (a, b) => a.is_none().cmp(&b.is_none()),
+        };
+        by_score.then_with(|| match row_ids {
+            Some(ids) => ids.value(*a).cmp(&ids.value(*b)),
+            None => Ordering::Equal,
+        })
+    });
+    let indices = UInt32Array::from_iter_values(rows.into_iter().map(|row| row as u32));
+    let columns = batch
+        .columns()
+        .iter()
+        .map(|column| take(column.as_ref(), &indices, None))
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .map_err(lance::Error::from)?;
+    Ok(RecordBatch::try_new(batch.schema(), columns).map_err(lance::Error::from)?)
+}
+
+/// Whether the sorted `batch` may leave out rows of the distance of its row
+/// `limit - 1`: its last row has that distance too.
+fn tied_at_limit(batch: &RecordBatch, limit: usize) -> bool {
+    let Some(scores) = scores(batch) else {
+        return false;
+    };
+    let last = batch.num_rows() - 1;
+    limit > 0 && limit <= last && scores.value(limit - 1) == scores.value(last)
+}
+
+impl Query {
+    /// The results of this query in a stable order, see [Query::ordered].
+    pub(super) async fn search_ordered(&self) -> Result<DatasetRecordBatchStream> {
+        let (schema, batches, _) = self.ordered_results(false).await?;
+        Ok(batches_stream(schema, batches))
+    }
+
+    /// [Query::search_ordered] and the [QueryMetrics] of its last search.
+    pub(super) async fn search_ordered_with_metrics(
+        &self,
+    ) -> Result<(Vec<RecordBatch>, QueryMetrics)> {
+        let (_, batches, metrics) = self.ordered_results(true).await?;
+        let mut metrics = metrics.unwrap_or_default();
+        metrics.rows_after_filter = batches.iter().map(RecordBatch::num_rows).sum();
+        Ok((batches, metrics))
+    }
+
+    /// The results of one search of this query, sorted, `None` if there are
+    /// none.
+    async fn sorted(
+        &self,
+        with_metrics: bool,
+    ) -> Result<(SchemaRef, Option<RecordBatch>, Option<QueryMetrics>)> {
+        let (schema, batches, metrics) = if with_metrics {
+            let (batches, metrics) = self.search_unordered_with_metrics().await?;
+            let schema = batches
+                .first()
+                .map(RecordBatch::schema)
+                .unwrap_or_else(|| Arc::new(ArrowSchema::empty()));
+            (schema, batches, Some(metrics))
+        } else {
+            let stream = self.search_unordered().await?;
+            let schema = stream.schema();
+            (schema, stream.try_collect::<Vec<_>>().await?, None)
+        };
+        if batches.is_empty() {
+            return Ok((schema, None, metrics));
+        }
+        let batch = sort(&concat_batches(&schema, &batches).map_err(lance::Error::from)?)?;
+        Ok((schema, Some(batch), metrics))
+    }
+
+    /// The sorted results of this query with every row of the distance of its
+    /// row `limit - 1`, searching for more rows while some may be left out.
+    async fn without_ties_left_out(
+        mut self,
+        limit: usize,
+        with_metrics: bool,
+    ) -> Result<(SchemaRef, Option<RecordBatch>, Option<QueryMetrics>)> {
+        self.limit = limit + 1;
+        loop {
+            let (schema, batch, metrics) = self.sorted(with_metrics).await?;
+            match batch {
+                // Fewer rows than searched for are all the rows there are.
+                Some(batch) if batch.num_rows() == self.limit && tied_at_limit(&batch, limit) => {
+                    self.limit *= 2;
+                }
+                batch => return Ok((schema, batch, metrics)),
+            }
+        }
+    }
+
+    async fn ordered_results(
+        &self,
+        with_metrics: bool,
+    ) -> Result<(SchemaRef, Vec<RecordBatch>, Option<QueryMetrics>)> {
+        let mut searched = self.clone();
+        searched.with_row_id = true;
+        // The searches share the vector embedded once, and their results are
+        // cached as those of this query.
+        searched.query_vector = self.resolve_vector().await?;
+        searched.query_text = None;
+        searched.query_cache = None;
+        let cache = match (&self.target, &self.query_cache) {
+            (QueryTarget::Dataset(dataset), Some(cache)) if !with_metrics => {
+                let version = dataset.get().await?.version().version;
+                let key = self.cache_key(version, &searched.query_vector);
+                if let Some((schema, batches)) = cache.get(&key) {
+                    return Ok((schema, batches, None));
+                }
+                Some((cache, key, version))
+            }
+            _ => None,
+        };
+        let (schema, batches, metrics) = self.ordered_search(searched, with_metrics).await?;
+        if let Some((cache, key, version)) = cache {
+            cache.insert(key, version, (schema.clone(), batches.clone()));
+        }
+        Ok((schema, batches, metrics))
+    }
+
+    /// Whether the filter of this query is applied before the rows are ranked,
+    /// so that the rows of a tie left out by the limit are all filtered.
+    async fn prefiltered(&self) -> Result<bool> {
+        match &self.target {
+            QueryTarget::Dataset(dataset) if self.filter.is_some() => {
+                let dataset = dataset.get().await?;
+                Ok(self.chosen_filter_mode(&dataset).await? == Some(FilterMode::Prefilter))
+            }
+            _ => Ok(false),
+        }
+    }
+
+    /// Whether this query searches an index of only rows matching its filter,
+    /// which lance finds the nearest of without the rows the filter leaves
+    /// out, see [crate::index::vector::IvfPQIndexBuilder::row_filter].
+    async fn searches_filtered_index(&self) -> Result<bool> {
+        match &self.target {
+            QueryTarget::Dataset(dataset) if self.filter.is_some() => {
+                let dataset = dataset.get().await?;
+                self.uses_partial_index(&dataset).await
+            }
+            _ => Ok(false),
+        }
+    }
+
+    /// The results of `searched`, this query returning row ids, in a stable
+    /// order.
+    async fn ordered_search(
+        &self,
+        mut searched: Query,
+        with_metrics: bool,
+    ) -> Result<(SchemaRef, Vec<RecordBatch>, Option<QueryMetrics>)> {
+        let prefiltered = self.prefiltered().await?;
+        if prefiltered {
+            searched.filter_mode = FilterMode::Prefilter;
+        }
+        let filtered_index = self.searches_filtered_index().await?;
+        let (schema, batch, metrics) = if self.filter.is_none() || prefiltered || filtered_index {
+            searched
+                .without_ties_left_out(self.limit, with_metrics)
+                .await?
+        } else {
+            // lance filters the nearest rows it finds, so which rows of a tie
+            // are filtered depends on the order they are found in. The rows
+            // kept are found without the filter, and all of them are among the
+            // nearest rows searched for with the filter.
+            let mut unfiltered = searched.clone();
+            unfiltered.filter = None;
+            unfiltered.json_paths = Vec::new();
+            unfiltered.select = Some(vec![self.column.clone()]);
+            let (_, nearest, _) = unfiltered.without_ties_left_out(self.limit, false).await?;
+            let Some(nearest) = nearest else {
+                let (schema, _, metrics) = searched.sorted(with_metrics).await?;
+                return Ok((schema, Vec::new(), metrics));
+            };
+            let kept = row_ids(&nearest)
+                .map(|ids| ids.values()[..nearest.num_rows().min(self.limit)].to_vec())
+                .unwrap_or_default();
+            searched.limit = nearest.num_rows();
+            let (schema, batch, metrics) = searched.sorted(with_metrics).await?;
+            let batch = match batch {
+                // The flat searches without row ids keep the rows they found.
+                Some(batch) if row_ids(&batch).is_none() => Some(batch),
+                Some(batch) => {
+                    let ids = row_ids(&batch).unwrap();
+                    let keep = (0..batch.num_rows())
+                        .map(|row| Some(kept.contains(&ids.value(row))))
+                        .collect::<BooleanArray>();
+                    Some(filter_record_batch(&batch, &keep).map_err(lance::Error::from)?)
+                }
+                None => None,
+            };
+            (schema, batch, metrics)
+        };
+        let Some(batch) = batch else {
+            return Ok((schema, Vec::new(), metrics));
+        };
+        let mut batch = batch.slice(0, batch.num_rows().min(self.limit));
+        if !self.with_row_id && batch.schema().field_with_name(ROW_ID).is_ok() {
+            batch = batch.drop_column(ROW_ID)?;
+        }
+        Ok((batch.schema(), vec![batch], metrics))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow_array::{Int32Array, RecordBatchReader};
+    use lance::arrow::RecordBatchBuffer;
+    use lance::dataset::{Dataset, WriteMode, WriteParams};
+    use lance::index::vector::ivf::IvfBuildParams;
+    use lance::index::vector::pq::PQBuildParams;
+    use tempfile::tempdir;
+
+    use crate::arrow::RecordBatchBuilder;
+    use crate::index::vector::IvfPQIndexBuilder;
+    use crate::table::NativeTable;
+
+    use super::*;
+
+    fn rows(ids: std::ops::Range<i32>) -> Box<dyn RecordBatchReader> {
+        // Five distances to [1, 1], each shared by a fifth of the rows.
+        let vectors = ids
+            .clone()
+            .map(|i| [(i % 5 + 1) as f32, 1.0])
+            .collect::<Vec<_>>();
+        let batch = RecordBatchBuilder::new()
+            .column("id", Arc::new(Int32Array::from_iter_values(ids)))
+            .vector_column("vector", 2, vectors)
+            .build()
+            .unwrap();
+        Box::new(RecordBatchBuffer::new(vec![batch]))
+    }
+
+    fn ids(batches: &[RecordBatch]) -> Vec<i32> {
+        batches
+            .iter()
+            .flat_map(|b| {
+                let ids = b["id"].as_any().downcast_ref::<Int32Array>().unwrap();
+                ids.values().to_vec()
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_ordered() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        let path = format!("{uri}/test.lance");
+        for fragment in 0..8 {
+            let params = WriteParams {
+                mode: WriteMode::Append,
+                ..Default::default()
+            };
+            let mut reader = rows(fragment * 50..(fragment + 1) * 50);
+            Dataset::write(&mut reader, &path, Some(params))
+                .await
+                .unwrap();
+        }
+        let table = NativeTable::open(uri, "test").await.unwrap();
+        let query = |limit: usize| {
+            table
+                .search(vec![1.0, 1.0])
+                .io_parallelism(8)
+                .batch_readahead(8)
+                .limit(limit)
+                .ordered(true)
+        };
+
+        // 80 rows are at distance 0, the limit keeps those of the smallest ids.
+        let expected = (0..30).map(|i| i * 5).collect::<Vec<_>>();
+        for _ in 0..20 {
+            let stream = query(30).execute().await.unwrap();
+            assert!(stream.schema().field_with_name(ROW_ID).is_err());
+            let batches = stream.try_collect::<Vec<_>>().await.unwrap();
+            assert_eq!(ids(&batches), expected);
+        }
+        let (batches, metrics) = query(30).execute_with_metrics().await.unwrap();
+        assert_eq!(ids(&batches), expected);
+        assert_eq!(metrics.rows_after_filter, 30);
+
+        // The 80 rows at distance 0, then the first 20 at distance 1.
+        let stream = query(100).with_row_id(true).execute().await.unwrap();
+        let batches = stream.try_collect::<Vec<_>>().await.unwrap();
+        let mut expected = (0..80).map(|i| i * 5).collect::<Vec<_>>();
+        expected.extend((0..20).map(|i| i * 5 + 1));
+        assert_eq!(ids(&batches), expected);
+        assert!(batches[0].column_by_name(ROW_ID).is_some());
+
+        // The filter is applied to the 5 nearest rows, of the smallest ids.
+        for _ in 0..10 {
+            let filtered = query(5).filter(Some("id >= 10".to_string()));
+            let batches = filtered.execute().await.unwrap().try_collect::<Vec<_>>();
+            assert_eq!(ids(&batches.await.unwrap()), vec![10, 15, 20]);
+        }
+    }
+
+    async fn search(table: &NativeTable, use_index: bool, filter: Option<&str>) -> RecordBatch {
+        let batches = table
+            .search(vec![0.0, 0.0])
+            .use_index(use_index)
+            .nprobes(2)
+            .io_parallelism(8)
+            .batch_readahead(8)
+            .limit(7)
+            .filter(filter.map(String::from))
+            .with_row_id(true)
+            .ordered(true)
+            .execute()
+            .await
+            .unwrap()
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        assert_eq!(batches.len(), 1);
+        batches[0].clone()
+    }
+
+    #[tokio::test]
+    async fn test_stable_results() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        let path = format!("{uri}/test.lance");
+        // Two rows of each point of a grid around [0, 0], in four fragments,
+        // so that most distances are shared by several rows.
+        for fragment in 0..4 {
+            let ids = fragment * 128..(fragment + 1) * 128;
+            let vectors = ids
+                .clone()
+                .map(|i| [(i % 16 - 8) as f32, (i / 16 % 16 - 8) as f32])
+                .collect::<Vec<_>>();
+            let batch = RecordBatchBuilder::new()
+                .column("id", Arc::new(Int32Array::from_iter_values(ids)))
+                .vector_column("vector", 2, vectors)
+                .build()
+                .unwrap();
+            let params = WriteParams {
+                mode: WriteMode::Append,
+                ..Default::default()
+            };
+            let mut reader: Box<dyn RecordBatchReader> =
+                Box::new(RecordBatchBuffer::new(vec![batch]));
+            Dataset::write(&mut reader, &path, Some(params))
+                .await
+                .unwrap();
+        }
+        let table = NativeTable::open(uri, "test").await.unwrap();
+        let builder = IvfPQIndexBuilder::new()
+            .column("vector")
+            .ivf_params(IvfBuildParams::new(4))
+            .pq_params(PQBuildParams {
+                num_sub_vectors: 2,
+                ..Default::default()
+            });
+        table.create_index(&builder).await.unwrap();
+
+        for (use_index, filter) in [(false, None), (true, None), (true, Some("id % 3 != 0"))] {
+            let first = search(&table, use_index, filter).await;
+            // The filter is applied to the 7 nearest rows.
+            assert!(first.num_rows() > 0 && first.num_rows() <= 7);
+            if filter.is_none() {
+                assert_eq!(first.num_rows(), 7);
+            }
+            let scores = scores(&first).unwrap();
+            let ids = row_ids(&first).unwrap();
+            for row in 1..first.num_rows() {
+                let previous = (scores.value(row - 1), ids.value(row - 1));
+                assert!(previous < (scores.value(row), ids.value(row)));
+            }
+            for _ in 0..50 {
+                assert_eq!(search(&table, use_index, filter).await, first);
+            }
+        }
+    }
+}
@@ -0,0 +1,166 @@
+// Copyright 2023 LanceDB Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Queries prepared once and run for many vectors.
+
+use std::sync::Arc;
+
+use arrow_schema::Schema as ArrowSchema;
+use lance::dataset::scanner::DatasetRecordBatchStream;
+
+use super::filter::lance_filter;
+use super::{Query, QueryTarget, QueryVector};
+use crate::error::Result;
+
+/// A query template run with a different vector each time, see
+/// [crate::NativeTable::prepare].
+///
+/// Preparing checks the filter and resolves the selected and searched columns
+/// in the schema of the table. The runs reuse the resolved columns until the
+/// version or the schema of the table changes, when the first run resolves them
+/// again. lance
+/// takes filters as SQL text, so it still plans the filter of every run.
+#[derive(Debug, Clone)]
+pub struct PreparedQuery {
+    template: Query,
+}
+
+impl PreparedQuery {
+    /// Prepare `template`, failing if its filter or columns are not valid for
+    /// the current version of its table.
+    pub(crate) async fn new(mut template: Query) -> Result<Self> {
+        template.resolved = Some(Arc::default());
+        if let QueryTarget::Dataset(dataset) = &template.target {
+            let dataset = dataset.get().await?;
+            if let Some(filter) = template.filter.as_ref() {
+                let schema = ArrowSchema::from(dataset.schema());
+                dataset.scan().filter(&lance_filter(filter, &schema)?)?;
+            }
+            template.resolve_column(&dataset)?;
+        }
+        Ok(Self { template })
+    }
+
+    /// The query the runs are made of.
+    pub fn template(&self) -> &Query {
+        &self.template
+    }
+
+    /// Run the query for `query`, a vector or a text embedded with the
+    /// embedding function of the searched column.
+    pub async fn run(&self, query: impl Into<QueryVector>) -> Result<DatasetRecordBatchStream> {
+        self.template.clone().nearest_to(query).execute().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow_array::{Int32Array, RecordBatch, RecordBatchReader};
+    use futures::TryStreamExt;
+    use lance::arrow::RecordBatchBuffer;
+    use tempfile::tempdir;
+
+    use crate::arrow::RecordBatchBuilder;
+    use crate::error::Error;
+    use crate::table::NativeTable;
+
+    fn batches(ids: std::ops::Range<i32>) -> Box<dyn RecordBatchReader> {
+        let vectors = ids.clone().map(|i| [i as f32, (i % 7) as f32]);
+        let batch = RecordBatchBuilder::new()
+            .column("id", std::sync::Arc::new(Int32Array::from_iter_values(ids)))
+            .vector_column("vector", 2, vectors.collect::<Vec<_>>())
+            .build()
+            .unwrap();
+        Box::new(RecordBatchBuffer::new(vec![batch]))
+    }
+
+    #[tokio::test]
+    async fn test_prepared_query() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        let table = NativeTable::create(uri, "test", batches(0..100), None)
+            .await
+            .unwrap();
+        let template = table
+            .search(vec![0.0, 0.0])
+            .limit(5)
+            .filter(Some("id % 2 = 0".to_string()))
+            .select(Some(vec!["id".to_string()]));
+        let prepared = table.prepare(template.clone()).await.unwrap();
+
+        let ad_hoc = |vector: Vec<f32>| template.clone().nearest_to(vector);
+        for vector in [vec![10.0, 3.0], vec![50.0, 1.0], vec![99.0, 0.0]] {
+            let expected: Vec<RecordBatch> = ad_hoc(vector.clone())
+                .execute()
+                .await
+                .unwrap()
+                .try_collect()
+                .await
+                .unwrap();
+            let got: Vec<RecordBatch> = prepared
+                .run(vector)
+                .await
+                .unwrap()
+                .try_collect()
+                .await
+                .unwrap();
+            assert_eq!(got, expected);
+        }
+
+        // A new version of the table is searched, with its columns resolved again.
+        table.add(batches(100..200), None).await.unwrap();
+        let expected: Vec<RecordBatch> = ad_hoc(vec![150.0, 3.0])
+            .execute()
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+        let got: Vec<RecordBatch> = prepared
+            .run(vec![150.0, 3.0])
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(got, expected);
+        let resolved = prepared.template.resolved.as_ref().unwrap();
+        assert_eq!(resolved.lock().unwrap().as_ref().unwrap().version, 2);
+
+        let error = prepared.run(vec![1.0, 2.0, 3.0]).await.err().unwrap();
+        assert!(matches!(error, Error::EmbeddingDimensionMismatch { .. }));
+    }
+
+    #[tokio::test]
+    async fn test_prepare_invalid() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        let table = NativeTable::create(uri, "test", batches(0..10), None)
+            .await
+            .unwrap();
+
+        let template = table
+            .search(vec![0.0, 0.0])
+            .select(Some(vec!["nope".to_string()]));
+        let Err(Error::InvalidInput { message }) = table.prepare(template).await else {
+            panic!("expected an invalid input error");
+        };
+        assert_eq!(message, "the selected columns 'nope' are not in the table");
+
+        let template = table
+            .search(vec![0.0, 0.0])
+            .filter(Some("id >".to_string()));
+        assert!(table.prepare(template).await.is_err());
+    }
+}
@@ -0,0 +1,323 @@
+// Copyright 2023 LanceDB Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Nearest neighbor searches described by a plain struct, for services that
+//! take the parameters of a search over the wire.
+
+use arrow_array::RecordBatch;
+use futures::TryStreamExt;
+use lance::dataset::scanner::DatasetRecordBatchStream;
+use lance::index::vector::MetricType;
+use lance::io::RecordBatchStream;
+use serde::{Deserialize, Serialize};
+
+use super::{batches_stream, FilterMode, Query, DEFAULT_QUERY_LIMIT};
+use crate::error::{Error, Result};
+use crate::table::VECTOR_COLUMN_NAME;
+
+/// The parameters of a nearest neighbor search, run as the [Query] setting
+/// them by [crate::TableLike::search_request].
+///
+/// The fields left out of a deserialized request take the defaults of a
+/// [Query]. The results are those of the query for `k + offset` rows, less
+/// the first `offset` of them.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SearchRequest {
+    /// The query vector.
+    pub vector: Vec<f32>,
+    /// The searched vector column, see [Query::column].
+    pub column: String,
+    /// The number of results, see [Query::limit].
+    pub k: usize,
+    /// See [Query::nprobes], `None` for the default of a query.
+    pub nprobes: Option<usize>,
+    /// See [Query::refine_factor].
+    pub refine_factor: Option<u32>,
+    /// A SQL WHERE clause on the nearest neighbors, see [Query::filter].
+    pub filter: Option<String>,
+    /// The columns of the results, all of them if `None`, see [Query::select].
+    pub select: Option<Vec<String>>,
+    /// Whether the filter picks the searched rows instead of the results, see
+    /// [FilterMode::Prefilter]. Otherwise the query chooses by the selectivity
+    /// of the filter, see [FilterMode::Auto].
+    pub prefilter: bool,
+    /// The distance metric, `"l2"`, `"cosine"` or `"dot"`, `None` for that of
+    /// the index or L2, see [Query::metric_type].
+    pub metric: Option<String>,
+    /// See [Query::use_index].
+    pub use_index: bool,
+    /// The number of nearest neighbors skipped before the results.
+    pub offset: usize,
+}
+
+impl Default for SearchRequest {
+    fn default() -> Self {
+        Self {
+            vector: Vec::new(),
+            column: VECTOR_COLUMN_NAME.to_string(),
+            k: DEFAULT_QUERY_LIMIT,
+            nprobes: None,
+            refine_factor: None,
+            filter: None,
+            select: None,
+            prefilter: false,
+            metric: None,
+            use_index: false,
+            offset: 0,
+        }
+    }
+}
+
+/// `batches` without their first `offset` rows.
+fn skip(batches: Vec<RecordBatch>, mut offset: usize) -> Vec<RecordBatch> {
+    batches
+        .into_iter()
+        .filter_map(|batch| {
+            let skipped = batch.num_rows().min(offset);
+            offset -= skipped;
+            let rows = batch.num_rows() - skipped;
+            (rows > 0).then(|| batch.slice(skipped, rows))
+        })
+        .collect()
+}
+
+impl SearchRequest {
+    /// Fail with an [Error::InvalidInput] if the parameters of this request
+    /// cannot make a search, before it reads the table.
+    ///
+    /// The filter and the columns are checked against the table by the search.
+    pub fn validate(&self) -> Result<()> {
+        let invalid = |message: &str| {
+            Err(Error::InvalidInput {
+                message: message.to_string(),
+            })
+        };
+        if self.vector.is_empty() {
+            return invalid("the query vector of a search request is empty");
+        }
+        if self.vector.iter().any(|v| !v.is_finite()) {
+            return invalid("the query vector of a search request has NaN or infinite values");
+        }
+        if self.column.is_empty() {
+            return invalid("the column of a search request is empty");
+        }
+        if self.k == 0 {
+            return invalid("the k of a search request must be at least 1");
+        }
+        if self.k.checked_add(self.offset).is_none() {
+            return invalid("the k and offset of a search request are too large");
+        }
+        if self.nprobes == Some(0) {
+            return invalid("the nprobes of a search request must be at least 1");
+        }
+        if self.refine_factor == Some(0) {
+            return invalid("the refine_factor of a search request must be at least 1");
+        }
+        self.metric_type()?;
+        Ok(())
+    }
+
+    fn metric_type(&self) -> Result<Option<MetricType>> {
+        let Some(metric) = self.metric.as_deref() else {
+            return Ok(None);
+        };
+        MetricType::try_from(metric)
+            .map(Some)
+            .map_err(|_| Error::InvalidInput {
+                message: format!("unknown metric '{metric}', expected l2, cosine or dot"),
+            })
+    }
+
+    /// `query`, a search of [Self::vector] of a table, with the parameters of
+    /// this request.
+    fn apply(&self, query: Query) -> Result<Query> {
+        self.validate()?;
+        let mut query = query
+            .column(&self.column)
+            .limit(self.k + self.offset)
+            .refine_factor(self.refine_factor)
+            .filter(self.filter.clone())
+            .select(self.select.clone())
+            .metric_type(self.metric_type()?)
+            .use_index(self.use_index)
+            .filter_mode(if self.prefilter {
+                FilterMode::Prefilter
+            } else {
+                FilterMode::Auto
+            });
+        if let Some(nprobes) = self.nprobes {
+            query = query.nprobes(nprobes);
+        }
+        Ok(query)
+    }
+
+    /// Run this request as `query`, a search of [Self::vector] of a table.
+    pub(crate) async fn execute(&self, query: Query) -> Result<DatasetRecordBatchStream> {
+        let stream = self.apply(query)?.execute().await?;
+        if self.offset == 0 {
+            return Ok(stream);
+        }
+        let schema = stream.schema();
+        let batches = stream.try_collect::<Vec<_>>().await?;
+        Ok(batches_stream(schema, skip(batches, self.offset)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_array::{Array, Int32Array};
+    use lance::arrow::RecordBatchBuffer;
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::arrow::RecordBatchBuilder;
+    use crate::table::{NativeTable, TableLike};
+
+    async fn table(uri: &str) -> NativeTable {
+        let vectors = (0..20).map(|i| [i as f32, 1.0]).collect::<Vec<_>>();
+        let batch = RecordBatchBuilder::new()
+            .column("id", Arc::new(Int32Array::from_iter_values(0..20)))
+            .vector_column("vector", 2, vectors)
+            .build()
+            .unwrap();
+        let batches = Box::new(RecordBatchBuffer::new(vec![batch]));
+        NativeTable::create(uri, "test", batches, None)
+            .await
+            .unwrap()
+    }
+
+    async fn ids(stream: DatasetRecordBatchStream) -> Vec<i32> {
+        let batches = stream.try_collect::<Vec<_>>().await.unwrap();
+        batches
+            .iter()
+            .flat_map(|b| {
+                let ids = b["id"].as_any().downcast_ref::<Int32Array>().unwrap();
+                ids.values().to_vec()
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_search_request() {
+        let tmp_dir = tempdir().unwrap();
+        let table = table(tmp_dir.path().to_str().unwrap()).await;
+        let json = r#"{
+            "vector": [3.2, 1.0],
+            "k": 3,
+            "filter": "id != 4",
+            "select": ["id"],
+            "metric": "l2"
+        }"#;
+        let request: SearchRequest = serde_json::from_str(json).unwrap();
+        assert_eq!(request.column, "vector");
+        assert_eq!(request.offset, 0);
+        let encoded = serde_json::to_string(&request).unwrap();
+        assert_eq!(
+            serde_json::from_str::<SearchRequest>(&encoded).unwrap(),
+            request
+        );
+
+        let results = table.search_request(&request).await.unwrap();
+        let builder = table
+            .search(vec![3.2, 1.0])
+            .limit(3)
+            .filter(Some("id != 4".to_string()))
+            .select(Some(vec!["id".to_string()]))
+            .metric_type(Some(MetricType::L2));
+        let expected = ids(builder.execute().await.unwrap()).await;
+        assert_eq!(ids(results).await, expected);
+        assert_eq!(expected, vec![3, 2]);
+
+        // A prefilter searches the rows matching the filter.
+        let request = SearchRequest {
+            filter: Some("id > 15".to_string()),
+            prefilter: true,
+            ..request
+        };
+        let results = table.search_request(&request).await.unwrap();
+        assert_eq!(ids(results).await, vec![16, 17, 18]);
+
+        let request = SearchRequest {
+            vector: vec![10.2, 1.0],
+            k: 2,
+            offset: 1,
+            ..Default::default()
+        };
+        let results = table.search_request(&request).await.unwrap();
+        let all = ids(table
+            .search(vec![10.2, 1.0])
+            .limit(3)
+            .execute()
+            .await
+            .unwrap())
+        .await;
+        assert_eq!(ids(results).await, all[1..].to_vec());
+        // The dynamic table answers the same.
+        let dynamic: &dyn TableLike = &table;
+        let results = dynamic.search_request(&request).await.unwrap();
+        assert_eq!(ids(results).await, all[1..].to_vec());
+    }
+
+    #[test]
+    fn test_validate() {
+        let valid = SearchRequest {
+            vector: vec![1.0, 2.0],
+            ..Default::default()
+        };
+        assert!(valid.validate().is_ok());
+        let invalid = [
+            SearchRequest {
+                vector: Vec::new(),
+                ..valid.clone()
+            },
+            SearchRequest {
+                vector: vec![1.0, f32::NAN],
+                ..valid.clone()
+            },
+            SearchRequest {
+                column: String::new(),
+                ..valid.clone()
+            },
+            SearchRequest {
+                k: 0,
+                ..valid.clone()
+            },
+            SearchRequest {
+                offset: usize::MAX,
+                ..valid.clone()
+            },
+            SearchRequest {
+                nprobes: Some(0),
+                ..valid.clone()
+            },
+            SearchRequest {
+                refine_factor: Some(0),
+                ..valid.clone()
+            },
+            SearchRequest {
+                metric: Some("hamming".to_string()),
+                ..valid.clone()
+            },
+        ];
+        for request in invalid {
+            let err = request.validate().unwrap_err();
+            assert!(matches!(err, Error::InvalidInput { .. }), "{err}");
+        }
+        let unknown = serde_json::from_str::<SearchRequest>(r#"{"vector": [1.0], "limit": 3}"#);
+        assert!(unknown.is_err());
+    }
+}
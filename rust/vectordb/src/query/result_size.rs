@@ -0,0 +1,175 @@
+// Copyright 2023 LanceDB Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The estimated size of the results of a query, see
+//! [Query::max_result_bytes].
+//!
+//! The size of a batch is that of the parts of the buffers of its columns it
+//! uses: the values of the fixed-width columns, and the bytes between the
+//! first and last offsets of the variable-width ones, so that a column of
+//! long strings counts by their lengths. The results lance reads are
+//! collected with their sizes as they come, and the results of the query are
+//! checked once more, as they are returned.
+
+use arrow_array::{Array, RecordBatch};
+use futures::TryStreamExt;
+use lance::dataset::scanner::DatasetRecordBatchStream;
+
+use super::Query;
+use crate::error::{Error, Result};
+
+/// The estimated size in bytes of `batch`.
+pub(super) fn batch_bytes(batch: &RecordBatch) -> usize {
+    batch
+        .columns()
+        .iter()
+        .map(|column| {
+            let data = column.to_data();
+            // The types without a layout of their slices count whole buffers.
+            data.get_slice_memory_size()
+                .unwrap_or_else(|_| data.get_buffer_memory_size())
+        })
+        .sum()
+}
+
+impl Query {
+    /// The batches of `stream`, failing with an [Error::ResultTooLarge] as
+    /// soon as they take more than [Query::max_result_bytes].
+    pub(super) async fn collect_capped(
+        &self,
+        mut stream: DatasetRecordBatchStream,
+    ) -> Result<Vec<RecordBatch>> {
+        let Some(cap) = self.max_result_bytes else {
+            return Ok(stream.try_collect().await?);
+        };
+        let mut estimated = 0;
+        let mut batches = Vec::new();
+        while let Some(batch) = stream.try_next().await? {
+            estimated += batch_bytes(&batch);
+            if estimated > cap {
+                return Err(Error::ResultTooLarge { estimated, cap });
+            }
+            batches.push(batch);
+        }
+        Ok(batches)
+    }
+
+    /// Fail with an [Error::ResultTooLarge] if `batches` take more than
+    /// [Query::max_result_bytes].
+    pub(super) fn check_result_bytes(&self, batches: &[RecordBatch]) -> Result<()> {
+        let Some(cap) = self.max_result_bytes else {
+            return Ok(());
+        };
+        let estimated = batches.iter().map(batch_bytes).sum();
+        match estimated > cap {
+            true => Err(Error::ResultTooLarge { estimated, cap }),
+            false => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_array::{Int32Array, RecordBatchReader, StringArray};
+    use lance::arrow::RecordBatchBuffer;
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::arrow::RecordBatchBuilder;
+    use crate::table::NativeTable;
+
+    /// The length of the values of the `body` column.
+    const BODY: usize = 4096;
+
+    fn rows(rows: i32) -> Box<dyn RecordBatchReader> {
+        let body = "x".repeat(BODY);
+        let batch = RecordBatchBuilder::new()
+            .column("id", Arc::new(Int32Array::from_iter_values(0..rows)))
+            .column(
+                "body",
+                Arc::new(StringArray::from_iter_values((0..rows).map(|_| &body))),
+            )
+            .vector_column(
+                "vector",
+                2,
+                (0..rows).map(|i| [i as f32, 1.0]).collect::<Vec<_>>(),
+            )
+            .build()
+            .unwrap();
+        Box::new(RecordBatchBuffer::new(vec![batch]))
+    }
+
+    #[test]
+    fn test_batch_bytes() {
+        let batch = rows(10).next().unwrap().unwrap();
+        // The bodies, and a few bytes for the ids, offsets and vectors.
+        let bytes = batch_bytes(&batch);
+        assert!((10 * BODY..10 * BODY + 1024).contains(&bytes), "{bytes}");
+        // A slice counts the bodies it has, not those of its buffers.
+        let bytes = batch_bytes(&batch.slice(0, 5));
+        assert!((5 * BODY..5 * BODY + 1024).contains(&bytes), "{bytes}");
+    }
+
+    #[tokio::test]
+    async fn test_max_result_bytes() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        let table = NativeTable::create(uri, "test", rows(100), None)
+            .await
+            .unwrap();
+        let cap = 50 * BODY;
+        let search = || table.search(vec![0.0, 1.0]).limit(100);
+
+        let err = search()
+            .max_result_bytes(cap)
+            .execute()
+            .await
+            .err()
+            .unwrap();
+        assert!(
+            matches!(err, Error::ResultTooLarge { estimated, cap: c } if estimated > cap && c == cap),
+            "{err}"
+        );
+        assert!(err.to_string().contains("Query::select"), "{err}");
+        let err = search()
+            .max_result_bytes(cap)
+            .execute_with_metrics()
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::ResultTooLarge { .. }), "{err}");
+
+        // Under the cap: fewer rows, or without the wide column.
+        let count = |batches: &[RecordBatch]| batches.iter().map(|b| b.num_rows()).sum::<usize>();
+        let batches = search()
+            .limit(40)
+            .max_result_bytes(cap)
+            .execute()
+            .await
+            .unwrap()
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        assert_eq!(count(&batches), 40);
+        let (batches, _) = search()
+            .select(Some(vec!["id".to_string()]))
+            .max_result_bytes(cap)
+            .execute_with_metrics()
+            .await
+            .unwrap();
+        assert_eq!(count(&batches), 100);
+        assert!(batches[0].column_by_name("body").is_none());
+    }
+}
@@ -0,0 +1,810 @@
+// Copyright 2023 LanceDB Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Filters comparing timestamp and date columns with ISO-8601 strings, `now()`
+//! and intervals: `created_at > now() - INTERVAL '7 days'`.
+//!
+//! lance compares a column only with literals of its own type, and plans no
+//! function returning a time. The instants compared with a temporal column are
+//! computed here, when the filter is planned, and the comparison rewritten as
+//! one of timestamps without a timezone, the number of units since the epoch
+//! in UTC: `CAST(created_at AS TIMESTAMP(6)) > CAST(1704067200000000 AS
+//! TIMESTAMP(6))`.
+//!
+//! - A string with an offset, `'2024-01-01T12:00:00+02:00'` or a trailing `Z`,
+//!   is that instant.
+//! - A string without one is a time in the timezone of the column, it fails if
+//!   the time is ambiguous or skipped there by a change of offset. The columns
+//!   without a timezone, and the dates, are in UTC.
+//! - A date, `'2024-01-01'`, is its midnight, and a date column is compared as
+//!   the midnights of its dates.
+//! - `now()` and `current_timestamp` are the time the filter is planned,
+//!   `current_date` the midnight of that day in UTC.
+//! - An interval, `INTERVAL '1 day 12 hours'` or `INTERVAL '7' DAY`, is added
+//!   to or subtracted from an instant, the months and years in the calendar.
+
+use std::borrow::Cow;
+
+use arrow_array::timezone::Tz;
+use arrow_schema::{DataType, Schema as ArrowSchema, TimeUnit};
+use chrono::{
+    DateTime, Duration, FixedOffset, LocalResult, Months, NaiveDate, NaiveDateTime, TimeZone, Utc,
+};
+use datafusion::sql::sqlparser::ast::{
+    BinaryOperator, DataType as SqlType, DateTimeField, Expr, Interval as SqlInterval,
+    TimezoneInfo, UnaryOperator, Value,
+};
+
+use super::filter::parse;
+use crate::error::{Error, Result};
+
+/// The formats of the times of ISO-8601 strings, without their offset.
+const TIME_FORMATS: [&str; 4] = [
+    "%Y-%m-%dT%H:%M:%S%.f",
+    "%Y-%m-%d %H:%M:%S%.f",
+    "%Y-%m-%dT%H:%M",
+    "%Y-%m-%d %H:%M",
+];
+
+/// `filter` on a table of `schema`, with the comparisons of its timestamp and
+/// date columns with instants rewritten as lance plans them.
+///
+/// Filters that reference no such column, or do not parse, are returned as they
+/// are, lance reports their errors. Fails with an [Error::InvalidInput] if an
+/// instant compared with a column is not valid.
+pub(super) fn rewrite_temporal<'a>(filter: &'a str, schema: &ArrowSchema) -> Result<Cow<'a, str>> {
+    if !schema
+        .fields()
+        .iter()
+        .any(|f| TemporalColumn::unit(f.data_type()).is_some())
+    {
+        return Ok(Cow::Borrowed(filter));
+    }
+    let Ok(mut expr) = parse(filter) else {
+        return Ok(Cow::Borrowed(filter));
+    };
+    let rewriter = Rewriter {
+        schema,
+        now: Utc::now(),
+    };
+    let rewritten = rewriter
+        .rewrite(&mut expr)
+        .map_err(|message| Error::InvalidInput {
+            message: format!("invalid filter '{filter}': {message}"),
+        })?;
+    Ok(if rewritten {
+        Cow::Owned(expr.to_string())
+    } else {
+        Cow::Borrowed(filter)
+    })
+}
+
+/// The errors of the instants of a filter, without the filter.
+type Rewritten<T> = std::result::Result<T, String>;
+
+/// A timestamp or date column referenced by a filter.
+struct TemporalColumn {
+    /// The reference of the filter.
+    expr: Expr,
+    name: String,
+    unit: TimeUnit,
+    /// The timezone of the strings without an offset, `None` for UTC.
+    timezone: Option<(String, Tz)>,
+    /// Whether the column is cast to a timestamp without a timezone.
+    cast: bool,
+}
+
+impl TemporalColumn {
+    /// The unit of the timestamps `data_type` is compared as, `None` if it is
+    /// not a temporal type.
+    fn unit(data_type: &DataType) -> Option<TimeUnit> {
+        match data_type {
+            DataType::Timestamp(unit, _) => Some(unit.clone()),
+            DataType::Date32 | DataType::Date64 => Some(TimeUnit::Microsecond),
+            _ => None,
+        }
+    }
+
+    /// The SQL precision of timestamps of `unit`.
+    fn precision(&self) -> u64 {
+        match self.unit {
+            TimeUnit::Second => 0,
+            TimeUnit::Millisecond => 3,
+            TimeUnit::Microsecond => 6,
+            TimeUnit::Nanosecond => 9,
+        }
+    }
+
+    fn sql_type(&self) -> SqlType {
+        SqlType::Timestamp(Some(self.precision()), TimezoneInfo::None)
+    }
+
+    /// The column as it is compared, a timestamp without a timezone.
+    fn compared(&self) -> Expr {
+        if !self.cast {
+            return self.expr.clone();
+        }
+        Expr::Cast {
+            expr: Box::new(self.expr.clone()),
+            data_type: self.sql_type(),
+        }
+    }
+
+    /// `instant` as a literal compared with the column.
+    fn literal(&self, instant: DateTime<Utc>) -> Rewritten<Expr> {
+        let per_second: i64 = match self.unit {
+            TimeUnit::Second => 1,
+            TimeUnit::Millisecond => 1_000,
+            TimeUnit::Microsecond => 1_000_000,
+            TimeUnit::Nanosecond => 1_000_000_000,
+        };
+        let fraction = instant.timestamp_subsec_nanos() as i64 / (1_000_000_000 / per_second);
+        let value = instant
+            .timestamp()
+            .checked_mul(per_second)
+            .and_then(|value| value.checked_add(fraction))
+            .ok_or_else(|| {
+                format!(
+                    "{instant} is out of the range of the timestamps of '{}'",
+                    self.name
+                )
+            })?;
+        Ok(Expr::Cast {
+            expr: Box::new(Expr::Value(Value::Number(value.to_string(), false))),
+            data_type: self.sql_type(),
+        })
+    }
+
+    /// The instant of `naive`, a time in the timezone of the column.
+    fn local(&self, naive: NaiveDateTime, text: &str) -> Rewritten<DateTime<Utc>> {
+        let Some((name, timezone)) = self.timezone.as_ref() else {
+            return Ok(Utc.from_utc_datetime(&naive));
+        };
+        match timezone.from_local_datetime(&naive) {
+            LocalResult::Single(instant) => Ok(instant.with_timezone(&Utc)),
+            LocalResult::Ambiguous(first, second) => Err(format!(
+                "'{text}' is ambiguous in the timezone '{name}' of '{}', it is both {first} and {second}, add an offset",
+                self.name
+            )),
+            LocalResult::None => Err(format!(
+                "'{text}' does not exist in the timezone '{name}' of '{}'",
+                self.name
+            )),
+        }
+    }
+
+    /// The instant of `text`, an ISO-8601 date or timestamp.
+    fn parse(&self, text: &str) -> Rewritten<DateTime<Utc>> {
+        let trimmed = text.trim();
+        if let Ok(date) = NaiveDate::parse_from_str(trimmed, "%Y-%m-%d") {
+            return self.local(date.and_hms_opt(0, 0, 0).unwrap(), text);
+        }
+        let (time, offset) = split_offset(trimmed);
+        let naive = TIME_FORMATS
+            .iter()
+            .find_map(|format| NaiveDateTime::parse_from_str(time, format).ok());
+        match (naive, offset) {
+            (Some(naive), Some(offset)) => naive
+                .checked_sub_signed(offset)
+                .map(|utc| Utc.from_utc_datetime(&utc))
+                .ok_or_else(|| format!("'{text}' is out of range")),
+            (Some(naive), None) => self.local(naive, text),
+            (None, _) if is_ambiguous_date(trimmed) => Err(format!(
+                "'{text}' is ambiguous, the day and the month could be either way around, write dates as '2024-01-31'"
+            )),
+            (None, _) => Err(format!(
+                "'{text}' is not an ISO-8601 date or timestamp, such as '2024-01-31', '2024-01-31 12:00:00' or '2024-01-31T12:00:00Z'"
+            )),
+        }
+    }
+}
+
+/// The time of `text` and its offset, if it ends with `Z` or `±HH[:MM]`.
+fn split_offset(text: &str) -> (&str, Option<Duration>) {
+    if let Some(time) = text.strip_suffix(['Z', 'z']) {
+        return (time, Some(Duration::zero()));
+    }
+    // The offset is after the date.
+    let Some(start) = text
+        .get(10..)
+        .and_then(|time| time.rfind(['+', '-']))
+        .map(|start| start + 10)
+    else {
+        return (text, None);
+    };
+    let (time, offset) = text.split_at(start);
+    let digits = offset[1..].replace(':', "");
+    let (hours, minutes) = match digits.len() {
+        2 => (digits.as_str(), "0"),
+        4 => digits.split_at(2),
+        _ => return (text, None),
+    };
+    let (Ok(hours), Ok(minutes)) = (hours.parse::<i32>(), minutes.parse::<i32>()) else {
+        return (text, None);
+    };
+    let seconds = (hours * 60 + minutes) * 60;
+    let seconds = if offset.starts_with('-') {
+        -seconds
+    } else {
+        seconds
+    };
+    match FixedOffset::east_opt(seconds) {
+        Some(_) => (time, Some(Duration::seconds(seconds as i64))),
+        None => (text, None),
+    }
+}
+
+/// Whether `text` is a date of which the day and the month could be swapped,
+/// `01/02/2024` or `01-02-24`.
+fn is_ambiguous_date(text: &str) -> bool {
+    let parts = text.split(['/', '-', '.']).collect::<Vec<_>>();
+    parts.len() == 3
+        && parts
+            .iter()
+            .all(|p| !p.is_empty() && p.chars().all(|c| c.is_ascii_digit()))
+        && parts[0].len() <= 2
+        && parts[1].len() <= 2
+}
+
+/// An interval added to instants: months, then a duration.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Interval {
+    months: i64,
+    duration: Duration,
+}
+
+impl Interval {
+    fn zero() -> Self {
+        Self {
+            months: 0,
+            duration: Duration::zero(),
+        }
+    }
+
+    /// `amount` of `unit`, `"day"` or `"days"`.
+    fn new(amount: i64, unit: &str) -> Rewritten<Self> {
+        // The months and microseconds of one unit.
+        let (months, micros): (i64, i64) = match unit.to_ascii_lowercase().as_str() {
+            "year" | "years" => (12, 0),
+            "month" | "months" | "mon" | "mons" => (1, 0),
+            "week" | "weeks" => (0, 7 * 24 * 3_600_000_000),
+            "day" | "days" => (0, 24 * 3_600_000_000),
+            "hour" | "hours" => (0, 3_600_000_000),
+            "minute" | "minutes" | "min" | "mins" => (0, 60_000_000),
+            "second" | "seconds" | "sec" | "secs" => (0, 1_000_000),
+            "millisecond" | "milliseconds" | "ms" => (0, 1_000),
+            "microsecond" | "microseconds" | "us" => (0, 1),
+            _ => return Err(format!("unknown unit of interval '{unit}'")),
+        };
+        let too_large = || format!("the interval of {amount} {unit} is too large");
+        Ok(Self {
+            months: amount.checked_mul(months).ok_or_else(too_large)?,
+            duration: Duration::microseconds(amount.checked_mul(micros).ok_or_else(too_large)?),
+        })
+    }
+
+    /// The interval of `INTERVAL '1 day 12 hours'` or `INTERVAL '7' DAY`.
+    fn parse(interval: &SqlInterval) -> Rewritten<Self> {
+        let text = match interval.value.as_ref() {
+            Expr::Value(Value::SingleQuotedString(text)) => text.clone(),
+            Expr::Value(Value::Number(number, _)) => number.clone(),
+            value => return Err(format!("the interval {value} is not a string")),
+        };
+        if interval.last_field.is_some() {
+            return Err(format!("{interval} is not supported"));
+        }
+        let amount = |amount: &str| {
+            amount
+                .parse::<i64>()
+                .map_err(|_| format!("'{amount}' of {interval} is not an integer"))
+        };
+        if let Some(field) = interval.leading_field {
+            let unit = match field {
+                DateTimeField::Year => "year",
+                DateTimeField::Month => "month",
+                DateTimeField::Week => "week",
+                DateTimeField::Day => "day",
+                DateTimeField::Hour => "hour",
+                DateTimeField::Minute => "minute",
+                DateTimeField::Second => "second",
+                DateTimeField::Millisecond | DateTimeField::Milliseconds => "millisecond",
+                DateTimeField::Microsecond | DateTimeField::Microseconds => "microsecond",
+                _ => return Err(format!("{interval} is not supported")),
+            };
+            return Self::new(amount(text.trim())?, unit);
+        }
+        let words = text.split_whitespace().collect::<Vec<_>>();
+        if words.is_empty() || !words.len().is_multiple_of(2) {
+            return Err(format!(
+                "{interval} is not amounts of units, such as INTERVAL '1 day 12 hours'"
+            ));
+        }
+        words
+            .chunks(2)
+            .map(|pair| Self::new(amount(pair[0])?, pair[1]))
+            .try_fold(Self::zero(), |sum, interval| sum.plus(interval?, 1))
+    }
+
+    /// This interval plus `sign` times `other`.
+    fn plus(self, other: Self, sign: i64) -> Rewritten<Self> {
+        let duration = if sign < 0 {
+            self.duration.checked_sub(&other.duration)
+        } else {
+            self.duration.checked_add(&other.duration)
+        };
+        let months = other
+            .months
+            .checked_mul(sign)
+            .and_then(|months| self.months.checked_add(months));
+        match (months, duration) {
+            (Some(months), Some(duration)) => Ok(Self { months, duration }),
+            _ => Err("the interval is too large".to_string()),
+        }
+    }
+
+    /// `instant` plus `sign` times this interval.
+    fn add_to(self, instant: DateTime<Utc>, sign: i64) -> Rewritten<DateTime<Utc>> {
+        let months = sign.saturating_mul(self.months);
+        let months_added = match u32::try_from(months.unsigned_abs()) {
+            Ok(abs) if months < 0 => instant.checked_sub_months(Months::new(abs)),
+            Ok(abs) => instant.checked_add_months(Months::new(abs)),
+            Err(_) => None,
+        };
+        let duration = if sign < 0 {
+            -self.duration
+        } else {
+            self.duration
+        };
+        months_added
+            .and_then(|instant| instant.checked_add_signed(duration))
+            .ok_or_else(|| format!("{instant} plus the interval is out of range"))
+    }
+}
+
+/// Rewrites the comparisons of the temporal columns of a filter.
+struct Rewriter<'a> {
+    schema: &'a ArrowSchema,
+    /// The instant of `now()`, the same for the whole filter.
+    now: DateTime<Utc>,
+}
+
+impl Rewriter<'_> {
+    /// The temporal column `expr` references, if it does.
+    fn column(&self, expr: &Expr) -> Option<TemporalColumn> {
+        let name = match expr {
+            // lance reads double-quoted identifiers as strings.
+            Expr::Identifier(ident) if ident.quote_style != Some('"') => ident.value.clone(),
+            Expr::CompoundIdentifier(idents) => idents
+                .iter()
+                .map(|i| i.value.as_str())
+                .collect::<Vec<_>>()
+                .join("."),
+            _ => return None,
+        };
+        let field = self.schema.field_with_name(&name).ok()?;
+        let unit = TemporalColumn::unit(field.data_type())?;
+        let timezone = match field.data_type() {
+            DataType::Timestamp(_, Some(timezone)) => {
+                Some((timezone.to_string(), timezone.parse::<Tz>().ok()?))
+            }
+            _ => None,
+        };
+        Some(TemporalColumn {
+            expr: expr.clone(),
+            name,
+            unit,
+            cast: !matches!(field.data_type(), DataType::Timestamp(_, None)),
+            timezone,
+        })
+    }
+
+    /// The instant `expr` is, compared with `column`, `None` if it is not a
+    /// constant instant.
+    fn instant(&self, expr: &Expr, column: &TemporalColumn) -> Rewritten<Option<DateTime<Utc>>> {
+        match expr {
+            Expr::Value(Value::SingleQuotedString(text)) => column.parse(text).map(Some),
+            Expr::TypedString {
+                data_type: SqlType::Date | SqlType::Timestamp(..) | SqlType::Datetime(_),
+                value,
+            } => column.parse(value).map(Some),
+            Expr::Function(function) => {
+                let name = function.name.to_string().to_ascii_lowercase();
+                let instant = match name.as_str() {
+                    "now" | "current_timestamp" => self.now,
+                    "current_date" => {
+                        Utc.from_utc_datetime(&self.now.date_naive().and_hms_opt(0, 0, 0).unwrap())
+                    }
+                    _ => return Ok(None),
+                };
+                if !function.args.is_empty() {
+                    return Err(format!("{name}() takes no arguments"));
+                }
+                Ok(Some(instant))
+            }
+            Expr::Nested(expr) => self.instant(expr, column),
+            Expr::BinaryOp { left, op, right } => {
+                let sign = match op {
+                    BinaryOperator::Plus => 1,
+                    BinaryOperator::Minus => -1,
+                    _ => return Ok(None),
+                };
+                if let Some(interval) = self.interval(right)? {
+                    let Some(instant) = self.instant(left, column)? else {
+                        return Ok(None);
+                    };
+                    return interval.add_to(instant, sign).map(Some);
+                }
+                match (sign, self.interval(left)?) {
+                    (1, Some(interval)) => match self.instant(right, column)? {
+                        Some(instant) => interval.add_to(instant, 1).map(Some),
+                        None => Ok(None),
+                    },
+                    _ => Ok(None),
+                }
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// The interval `expr` is, `None` if it is not a constant interval.
+    fn interval(&self, expr: &Expr) -> Rewritten<Option<Interval>> {
+        match expr {
+            Expr::Interval(interval) => Interval::parse(interval).map(Some),
+            Expr::Nested(expr) => self.interval(expr),
+            Expr::UnaryOp {
+                op: UnaryOperator::Minus,
+                expr,
+            } => Ok(self
+                .interval(expr)?
+                .map(|interval| Interval::zero().plus(interval, -1))
+                .transpose()?),
+            Expr::BinaryOp {
+                left,
+                op: op @ (BinaryOperator::Plus | BinaryOperator::Minus),
+                right,
+            } => {
+                let (Some(left), Some(right)) = (self.interval(left)?, self.interval(right)?)
+                else {
+                    return Ok(None);
+                };
+                let sign = if *op == BinaryOperator::Minus { -1 } else { 1 };
+                left.plus(right, sign).map(Some)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// `value` compared with `column`, as a literal, if it is an instant.
+    fn literal(&self, value: &Expr, column: &TemporalColumn) -> Rewritten<Option<Expr>> {
+        match self.instant(value, column)? {
+            Some(instant) => column.literal(instant).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Rewrite the comparisons of temporal columns in `expr`, returning
+    /// whether there were any.
+    fn rewrite(&self, expr: &mut Expr) -> Rewritten<bool> {
+        match expr {
+            Expr::BinaryOp { left, op, right } if is_comparison(op) => {
+                if let Some(column) = self.column(left) {
+                    if let Some(literal) = self.literal(right, &column)? {
+                        **left = column.compared();
+                        **right = literal;
+                        return Ok(true);
+                    }
+                }
+                if let Some(column) = self.column(right) {
+                    if let Some(literal) = self.literal(left, &column)? {
+                        **left = literal;
+                        **right = column.compared();
+                        return Ok(true);
+                    }
+                }
+                Ok(false)
+            }
+            Expr::BinaryOp { left, right, .. } => {
+                // Both sides, without short-circuiting.
+                Ok(self.rewrite(left)? | self.rewrite(right)?)
+            }
+            Expr::UnaryOp { expr, .. } | Expr::Nested(expr) => self.rewrite(expr),
+            Expr::Between {
+                expr: value,
+                negated,
+                low,
+                high,
+            } => {
+                let Some(column) = self.column(value) else {
+                    return Ok(false);
+                };
+                let (Some(low), Some(high)) =
+                    (self.literal(low, &column)?, self.literal(high, &column)?)
+                else {
+                    return Ok(false);
+                };
+                // lance has no BETWEEN.
+                let compare = |op, literal| Expr::BinaryOp {
+                    left: Box::new(column.compared()),
+                    op,
+                    right: Box::new(literal),
+                };
+                let (low_op, high_op, join) = if *negated {
+                    (BinaryOperator::Lt, BinaryOperator::Gt, BinaryOperator::Or)
+                } else {
+                    (
+                        BinaryOperator::GtEq,
+                        BinaryOperator::LtEq,
+                        BinaryOperator::And,
+                    )
+                };
+                *expr = Expr::Nested(Box::new(Expr::BinaryOp {
+                    left: Box::new(compare(low_op, low)),
+                    op: join,
+                    right: Box::new(compare(high_op, high)),
+                }));
+                Ok(true)
+            }
+            Expr::InList {
+                expr: value, list, ..
+            } => {
+                let Some(column) = self.column(value) else {
+                    return Ok(false);
+                };
+                let literals = list
+                    .iter()
+                    .map(|item| self.literal(item, &column))
+                    .collect::<Rewritten<Option<Vec<_>>>>()?;
+                let Some(literals) = literals else {
+                    return Ok(false);
+                };
+                **value = column.compared();
+                *list = literals;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+}
+
+fn is_comparison(op: &BinaryOperator) -> bool {
+    matches!(
+        op,
+        BinaryOperator::Eq
+            | BinaryOperator::NotEq
+            | BinaryOperator::Lt
+            | BinaryOperator::LtEq
+            | BinaryOperator::Gt
+            | BinaryOperator::GtEq
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_array::{
+        Date32Array, Int32Array, RecordBatch, RecordBatchReader, TimestampMicrosecondArray,
+        TimestampMillisecondArray,
+    };
+    use arrow_schema::Field;
+    use futures::TryStreamExt;
+    use lance::arrow::RecordBatchBuffer;
+    use lance::dataset::Dataset;
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::arrow::RecordBatchBuilder;
+    use crate::query::filter::lance_filter;
+    use crate::table::NativeTable;
+
+    const NEW_YORK: &str = "America/New_York";
+
+    fn schema() -> ArrowSchema {
+        ArrowSchema::new(vec![
+            Field::new("id", DataType::Int32, false),
+            Field::new(
+                "ts",
+                DataType::Timestamp(TimeUnit::Microsecond, Some("UTC".into())),
+                true,
+            ),
+            Field::new(
+                "local",
+                DataType::Timestamp(TimeUnit::Millisecond, Some(NEW_YORK.into())),
+                true,
+            ),
+            Field::new("naive", DataType::Timestamp(TimeUnit::Second, None), true),
+            Field::new("day", DataType::Date32, true),
+        ])
+    }
+
+    #[test]
+    fn test_rewrite_temporal() {
+        let schema = schema();
+        let cases = [
+            (
+                "ts > '2024-01-01'",
+                "CAST(ts AS TIMESTAMP(6)) > CAST(1704067200000000 AS TIMESTAMP(6))",
+            ),
+            (
+                "'2024-01-01T02:00:00+02:00' = `ts`",
+                "CAST(1704067200000000 AS TIMESTAMP(6)) = CAST(`ts` AS TIMESTAMP(6))",
+            ),
+            (
+                "naive >= '2024-01-01T00:00:01Z'",
+                "naive >= CAST(1704067201 AS TIMESTAMP(0))",
+            ),
+            // Noon in New York, EDT, is 16:00 UTC.
+            (
+                "local < '2024-07-01 12:00'",
+                "CAST(local AS TIMESTAMP(3)) < CAST(1719849600000 AS TIMESTAMP(3))",
+            ),
+            (
+                "day BETWEEN '2024-01-01' AND '2024-01-31' AND id > 1",
+                "(CAST(day AS TIMESTAMP(6)) >= CAST(1704067200000000 AS TIMESTAMP(6)) \
+                 AND CAST(day AS TIMESTAMP(6)) <= CAST(1706659200000000 AS TIMESTAMP(6))) AND id > 1",
+            ),
+            (
+                "ts IN ('2024-01-01', TIMESTAMP '2024-01-02 00:00:00')",
+                "CAST(ts AS TIMESTAMP(6)) IN (CAST(1704067200000000 AS TIMESTAMP(6)), \
+                 CAST(1704153600000000 AS TIMESTAMP(6)))",
+            ),
+            // A month before the 31st of March is the 29th of February.
+            (
+                "ts > TIMESTAMP '2024-03-31' - INTERVAL '1 month 2 days'",
+                "CAST(ts AS TIMESTAMP(6)) > CAST(1708992000000000 AS TIMESTAMP(6))",
+            ),
+            (
+                "naive < '2024-01-01' + INTERVAL '90' MINUTE",
+                "naive < CAST(1704072600 AS TIMESTAMP(0))",
+            ),
+            ("id > 1", "id > 1"),
+            ("ts IS NULL", "ts IS NULL"),
+        ];
+        for (filter, expected) in cases {
+            let rewritten = rewrite_temporal(filter, &schema).unwrap();
+            assert_eq!(rewritten, expected, "{filter}");
+        }
+        assert!(matches!(
+            rewrite_temporal("id > 1", &schema).unwrap(),
+            Cow::Borrowed(_)
+        ));
+
+        let rewritten = rewrite_temporal("ts > now() - INTERVAL '7 days'", &schema).unwrap();
+        let expected = (Utc::now() - Duration::days(7)).timestamp_micros();
+        let micros = rewritten
+            .split(['(', ' '])
+            .filter_map(|word| word.parse::<i64>().ok())
+            .next()
+            .unwrap();
+        assert!((expected - micros).abs() < 60_000_000, "{rewritten}");
+
+        for (filter, error) in [
+            ("ts > '01/02/2024'", "ambiguous, the day and the month"),
+            ("ts > 'yesterday'", "not an ISO-8601 date or timestamp"),
+            (
+                "local = '2024-11-03 01:30'",
+                "ambiguous in the timezone 'America/New_York'",
+            ),
+            ("local = '2024-03-10 02:30'", "does not exist"),
+            ("ts > now() - INTERVAL '7 fortnights'", "unknown unit"),
+            ("ts > now(1)", "takes no arguments"),
+        ] {
+            let err = rewrite_temporal(filter, &schema).unwrap_err();
+            let Error::InvalidInput { message } = err else {
+                panic!("unexpected error {err}");
+            };
+            assert!(message.contains(error), "{filter}: {message}");
+        }
+    }
+
+    fn rows(now: DateTime<Utc>) -> Box<dyn RecordBatchReader> {
+        let ids = 0..5;
+        // Every other day before now.
+        let ts = ids
+            .clone()
+            .map(|i| (now - Duration::days(2 * i as i64)).timestamp_micros());
+        // From the 1st of January of 2024.
+        let day = ids.clone().map(|i| 19723 + i);
+        // Every hour from 10:00 in New York, 14:00 UTC.
+        let local = ids.clone().map(|i| 1719842400000 + i as i64 * 3_600_000);
+        let vectors = ids.clone().map(|i| [i as f32, 0.0]).collect::<Vec<_>>();
+        let batch = RecordBatchBuilder::new()
+            .column("id", Arc::new(Int32Array::from_iter_values(ids)))
+            .column(
+                "ts",
+                Arc::new(TimestampMicrosecondArray::from_iter_values(ts).with_timezone("UTC")),
+            )
+            .column("day", Arc::new(Date32Array::from_iter_values(day)))
+            .column(
+                "local",
+                Arc::new(
+                    TimestampMillisecondArray::from_iter_values(local).with_timezone(NEW_YORK),
+                ),
+            )
+            .vector_column("vector", 2, vectors)
+            .build()
+            .unwrap();
+        Box::new(RecordBatchBuffer::new(vec![batch]))
+    }
+
+    fn ids(batches: &[RecordBatch]) -> Vec<i32> {
+        let mut ids = batches
+            .iter()
+            .flat_map(|b| {
+                b["id"]
+                    .as_any()
+                    .downcast_ref::<Int32Array>()
+                    .unwrap()
+                    .values()
+                    .to_vec()
+            })
+            .collect::<Vec<_>>();
+        ids.sort();
+        ids
+    }
+
+    #[tokio::test]
+    async fn test_temporal_filters() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        let table = NativeTable::create(uri, "test", rows(Utc::now()), None)
+            .await
+            .unwrap();
+        let dataset = Dataset::open(table.uri()).await.unwrap();
+        let schema = ArrowSchema::from(dataset.schema());
+
+        for (filter, expected) in [
+            ("ts > now() - INTERVAL '5 days'", vec![0, 1, 2]),
+            ("ts <= current_timestamp - INTERVAL '1 week'", vec![4]),
+            ("day >= '2024-01-03'", vec![2, 3, 4]),
+            ("day BETWEEN '2024-01-02' AND '2024-01-03'", vec![1, 2]),
+            ("local < '2024-07-01 12:00'", vec![0, 1]),
+            ("local >= '2024-07-01T16:00:00Z' AND id < 4", vec![2, 3]),
+        ] {
+            let mut scanner = dataset.scan();
+            scanner
+                .filter(&lance_filter(filter, &schema).unwrap())
+                .unwrap();
+            let scanned = scanner
+                .try_into_stream()
+                .await
+                .unwrap()
+                .try_collect::<Vec<_>>()
+                .await
+                .unwrap();
+            assert_eq!(ids(&scanned), expected, "scan {filter}");
+
+            let searched = table
+                .search(vec![0.0, 0.0])
+                .filter(Some(filter.to_string()))
+                .limit(10)
+                .execute()
+                .await
+                .unwrap()
+                .try_collect::<Vec<_>>()
+                .await
+                .unwrap();
+            assert_eq!(ids(&searched), expected, "search {filter}");
+        }
+
+        let err = table
+            .search(vec![0.0, 0.0])
+            .filter(Some("day > '3/1/24'".to_string()))
+            .execute()
+            .await
+            .err()
+            .unwrap();
+        assert!(matches!(err, Error::InvalidInput { .. }), "{err}");
+    }
+}
@@ -0,0 +1,369 @@
+// Copyright 2023 LanceDB Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The two-stage searches of [Query::two_stage].
+//!
+//! The column of truncated vectors, see
+//! [crate::NativeTable::add_truncated_vector_column], is searched for the
+//! truncated query vector, for [TwoStage::over_fetch] times the limit of the
+//! query. The candidates found are ranked again by the exact distances of
+//! their full vectors, and the nearest `limit` of them are returned.
+
+use std::sync::Arc;
+
+use arrow_array::cast::as_primitive_array;
+use arrow_array::types::Float32Type;
+use arrow_array::{Array, ArrayRef, FixedSizeListArray, Float32Array, RecordBatch, UInt32Array};
+use arrow_schema::{DataType, Field, Schema as ArrowSchema, SchemaRef};
+use datafusion::arrow::compute::{cast, concat_batches, take};
+use futures::TryStreamExt;
+use lance::arrow::RecordBatchExt;
+use lance::dataset::scanner::DatasetRecordBatchStream;
+use lance::index::vector::MetricType;
+use lance::io::RecordBatchStream;
+
+use super::finite::is_kept;
+use super::{batches_stream, Query, QueryMetrics, QueryTarget, DISTANCE_COLUMN};
+use crate::error::{Error, Result};
+use crate::io::bad_vectors::{mark_null_vectors, marker_column_of};
+use crate::table::truncate_vector;
+
+/// The columns of a [Query::two_stage] search.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TwoStage {
+    /// The column of truncated vectors searched for candidates.
+    pub fast_column: String,
+    /// The column of full vectors the candidates are ranked by.
+    pub full_column: String,
+    /// The candidates searched for, as a multiple of the limit of the query.
+    pub over_fetch: usize,
+}
+
+/// The `limit` rows of `batch` nearest to `query_vector` by their vectors of
+/// `column`, with their distances in the distance column. Rows of null vectors
+/// are left out, as are those of a NaN or infinite distance without
+/// `allow_nonfinite`.
+fn rerank(
+    batch: &RecordBatch,
+    column: &str,
+    query_vector: &Float32Array,
+    metric_type: MetricType,
+    limit: usize,
+    allow_nonfinite: bool,
+) -> Result<RecordBatch> {
+    let batch = &mark_null_vectors(batch, column, true).map_err(lance::Error::from)?;
+    let vectors = batch
+        .column_by_name(column)
+        .and_then(|c| c.as_any().downcast_ref::<FixedSizeListArray>())
+        .ok_or_else(|| Error::InvalidInput {
+            message: format!("two_stage: '{column}' is not a vector column"),
+        })?;
+    let values = cast(vectors.values(), &DataType::Float32).map_err(lance::Error::from)?;
+    let values = as_primitive_array::<Float32Type>(values.as_ref()).values();
+    let dims = vectors.value_length() as usize;
+    let offset = vectors.value_offset(0) as usize;
+    let values = &values[offset..offset + vectors.len() * dims];
+    let distances = metric_type.batch_func()(query_vector.values(), values, dims);
+
+    let mut rows = (0..batch.num_rows())
+        .filter(|row| vectors.is_valid(*row) && is_kept(distances.value(*row), allow_nonfinite))
+        .collect::<Vec<_>>();
+    rows.sort_by(|a, b| distances.value(*a).total_cmp(&distances.value(*b)));
+    rows.truncate(limit);
+    let indices = UInt32Array::from_iter_values(rows.into_iter().map(|row| row as u32));
+
+    let batch = match batch.schema().field_with_name(DISTANCE_COLUMN) {
+        Ok(_) => batch.drop_column(DISTANCE_COLUMN)?,
+        Err(_) => batch.clone(),
+    };
+    let mut fields = batch.schema().fields().iter().cloned().collect::<Vec<_>>();
+    fields.push(Arc::new(Field::new(
+        DISTANCE_COLUMN,
+        DataType::Float32,
+        false,
+    )));
+    let schema = Arc::new(ArrowSchema::new(fields));
+    let distances: ArrayRef = distances;
+    let columns = batch
+        .columns()
+        .iter()
+        .chain(std::iter::once(&distances))
+        .map(|column| take(column.as_ref(), &indices, None))
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .map_err(lance::Error::from)?;
+    Ok(RecordBatch::try_new(schema, columns).map_err(lance::Error::from)?)
+}
+
+impl Query {
+    /// The results of this query searched in two stages, see [Query::two_stage].
+    pub(super) async fn search_two_stage(
+        &self,
+        two_stage: &TwoStage,
+    ) -> Result<DatasetRecordBatchStream> {
+        let (schema, batches, _) = self.two_stage_results(two_stage, false).await?;
+        Ok(batches_stream(schema, batches))
+    }
+
+    /// [Query::search_two_stage] and the [QueryMetrics] of its search of the
+    /// fast column.
+    pub(super) async fn search_two_stage_with_metrics(
+        &self,
+        two_stage: &TwoStage,
+    ) -> Result<(Vec<RecordBatch>, QueryMetrics)> {
+        let (_, batches, metrics) = self.two_stage_results(two_stage, true).await?;
+        let mut metrics = metrics.unwrap_or_default();
+        metrics.rows_after_filter = batches.iter().map(RecordBatch::num_rows).sum();
+        Ok((batches, metrics))
+    }
+
+    async fn two_stage_results(
+        &self,
+        two_stage: &TwoStage,
+        with_metrics: bool,
+    ) -> Result<(SchemaRef, Vec<RecordBatch>, Option<QueryMetrics>)> {
+        let TwoStage {
+            fast_column,
+            full_column,
+            over_fetch,
+        } = two_stage;
+        if *over_fetch == 0 {
+            return Err(Error::InvalidInput {
+                message: "the over_fetch of a two-stage query must be at least 1".to_string(),
+            });
+        }
+        let QueryTarget::Dataset(target) = &self.target else {
+            unreachable!("two-stage queries of executors are run by the executors");
+        };
+        let mut full = self.clone();
+        full.column = full_column.clone();
+        let query_vector = full.resolve_vector().await?;
+
+        let schema = ArrowSchema::from(target.get().await?.schema());
+        let width = |column: &str| match schema.field_with_name(column).map(|f| f.data_type()) {
+            Ok(DataType::FixedSizeList(_, width)) => Ok(*width as usize),
+            _ => Err(Error::InvalidInput {
+                message: format!("two_stage: '{column}' is not a vector column of the table"),
+            }),
+        };
+        let (fast_dims, full_dims) = (width(fast_column)?, width(full_column)?);
+        if full_dims != query_vector.len() {
+            return Err(Error::EmbeddingDimensionMismatch {
+                column: full_column.clone(),
+                expected: full_dims,
+                got: query_vector.len(),
+            });
+        }
+        if fast_dims > full_dims {
+            return Err(Error::InvalidInput {
+                message: format!(
+                    "two_stage: the vectors of '{fast_column}' are wider than those of '{full_column}'"
+                ),
+            });
+        }
+
+        let mut searched = self.clone();
+        searched.column = fast_column.clone();
+        searched.query_text = None;
+        searched.query_vector = truncate_vector(query_vector.values(), fast_dims).into();
+        searched.limit = self.limit.saturating_mul(*over_fetch);
+        // The full vectors and the marks of their null vectors are read for
+        // the ranking, and left out of the results if they were not selected.
+        let mut added = Vec::new();
+        if let Some(select) = searched.select.as_mut() {
+            let marker = marker_column_of(&schema, full_column);
+            for column in std::iter::once(full_column.clone()).chain(marker) {
+                if !select.contains(&column) {
+                    select.push(column.clone());
+                    added.push(column);
+                }
+            }
+        }
+        let (schema, batches, metrics) = if with_metrics {
+            let (batches, metrics) = searched.search_one_stage_with_metrics().await?;
+            let schema = batches
+                .first()
+                .map(RecordBatch::schema)
+                .unwrap_or_else(|| Arc::new(ArrowSchema::empty()));
+            (schema, batches, Some(metrics))
+        } else {
+            let stream = searched.search_one_stage().await?;
+            let schema = stream.schema();
+            (schema, stream.try_collect::<Vec<_>>().await?, None)
+        };
+        if batches.is_empty() {
+            return Ok((schema, batches, metrics));
+        }
+        let candidates = concat_batches(&schema, &batches).map_err(lance::Error::from)?;
+        let metric_type = self.metric_type.unwrap_or(MetricType::L2);
+        let mut batch = rerank(
+            &candidates,
+            full_column,
+            &query_vector,
+            metric_type,
+            self.limit,
+            self.allow_nonfinite,
+        )?;
+        for column in &added {
+            batch = batch.drop_column(column)?;
+        }
+        Ok((batch.schema(), vec![batch], metrics))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use arrow_array::{Int32Array, RecordBatchReader};
+    use lance::arrow::RecordBatchBuffer;
+    use rand::Rng;
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::arrow::RecordBatchBuilder;
+    use crate::table::NativeTable;
+
+    const ROWS: usize = 500;
+    const DIMS: usize = 64;
+    const FAST_DIMS: usize = 16;
+
+    /// A vector like a Matryoshka embedding, its first dimensions varying the
+    /// most.
+    fn random_vector(rng: &mut impl Rng) -> Vec<f32> {
+        (0..DIMS)
+            .map(|i| rng.gen_range(-1.0..1.0) / (1.0 + i as f32 / 4.0))
+            .collect()
+    }
+
+    fn rows(vectors: &[Vec<f32>]) -> Box<dyn RecordBatchReader> {
+        let batch = RecordBatchBuilder::new()
+            .column(
+                "id",
+                Arc::new(Int32Array::from_iter_values(0..vectors.len() as i32)),
+            )
+            .vector_column("vector", DIMS as i32, vectors.to_vec())
+            .build()
+            .unwrap();
+        Box::new(RecordBatchBuffer::new(vec![batch]))
+    }
+
+    fn ids(batches: &[RecordBatch]) -> Vec<i32> {
+        batches
+            .iter()
+            .flat_map(|b| {
+                let ids = b["id"].as_any().downcast_ref::<Int32Array>().unwrap();
+                ids.values().to_vec()
+            })
+            .collect()
+    }
+
+    fn distances(batches: &[RecordBatch]) -> Vec<f32> {
+        batches
+            .iter()
+            .flat_map(|b| {
+                let scores = b[DISTANCE_COLUMN].as_any();
+                scores
+                    .downcast_ref::<Float32Array>()
+                    .unwrap()
+                    .values()
+                    .to_vec()
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_two_stage() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        let mut rng = rand::thread_rng();
+        let vectors = (0..ROWS)
+            .map(|_| random_vector(&mut rng))
+            .collect::<Vec<_>>();
+        let table = NativeTable::create(uri, "test", rows(&vectors), None)
+            .await
+            .unwrap();
+        table
+            .add_truncated_vector_column("vector", "fast", FAST_DIMS)
+            .await
+            .unwrap();
+        let collect = |query: Query| async move {
+            let stream = query.execute().await.unwrap();
+            stream.try_collect::<Vec<_>>().await.unwrap()
+        };
+
+        // With as many candidates as rows, the results are those of the flat
+        // search of the full vectors.
+        let query = random_vector(&mut rng);
+        let exact = collect(table.search(query.clone()).limit(10)).await;
+        let two_stage =
+            table
+                .search(query.clone())
+                .limit(10)
+                .two_stage("fast", "vector", ROWS / 10);
+        let reranked = collect(two_stage).await;
+        assert_eq!(ids(&reranked), ids(&exact));
+        for (a, b) in distances(&reranked).iter().zip(distances(&exact)) {
+            assert!((a - b).abs() < 1e-4, "{a} != {b}");
+        }
+
+        // With fewer candidates, most of the nearest rows are still found.
+        let mut found = 0;
+        for _ in 0..10 {
+            let query = random_vector(&mut rng);
+            let exact = collect(table.search(query.clone()).limit(10)).await;
+            let two_stage = table
+                .search(query)
+                .limit(10)
+                .two_stage("fast", "vector", 10);
+            let reranked = collect(two_stage).await;
+            assert_eq!(ids(&reranked).len(), 10);
+            let exact = ids(&exact).into_iter().collect::<HashSet<_>>();
+            found += ids(&reranked)
+                .iter()
+                .filter(|id| exact.contains(id))
+                .count();
+        }
+        let recall = found as f64 / 100.0;
+        assert!(recall >= 0.9, "recall {recall}");
+
+        // The full vectors are read without being selected.
+        let selected = table
+            .search(query.clone())
+            .limit(5)
+            .select(Some(vec!["id".to_string()]))
+            .two_stage("fast", "vector", ROWS / 5);
+        let (batches, metrics) = selected.execute_with_metrics().await.unwrap();
+        let schema = batches[0].schema();
+        assert!(schema.field_with_name("vector").is_err(), "{schema:?}");
+        assert_eq!(ids(&batches), ids(&exact)[..5].to_vec());
+        assert_eq!(metrics.rows_after_filter, 5);
+
+        for invalid in [
+            table.search(query.clone()).two_stage("fast", "vector", 0),
+            table.search(query.clone()).two_stage("id", "vector", 2),
+            table
+                .search(query[..8].to_vec())
+                .two_stage("fast", "vector", 2),
+        ] {
+            let err = invalid.execute().await.err().unwrap();
+            assert!(
+                matches!(
+                    err,
+                    Error::InvalidInput { .. } | Error::EmbeddingDimensionMismatch { .. }
+                ),
+                "{err}"
+            );
+        }
+    }
+}
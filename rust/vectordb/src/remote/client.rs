@@ -0,0 +1,124 @@
+// Copyright 2023 LanceDB Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::io::Cursor;
+
+use arrow_array::{RecordBatch, RecordBatchReader};
+use arrow_ipc::reader::FileReader;
+use arrow_ipc::writer::StreamWriter;
+use arrow_schema::SchemaRef;
+use reqwest::{RequestBuilder, Response};
+
+use crate::error::{Error, Result};
+
+pub(crate) const ARROW_STREAM_CONTENT_TYPE: &str = "application/vnd.apache.arrow.stream";
+pub(crate) const ARROW_FILE_CONTENT_TYPE: &str = "application/vnd.apache.arrow.file";
+
+const DEFAULT_REGION: &str = "us-east-1";
+
+/// An HTTP client for the REST API of a LanceDB server.
+#[derive(Debug, Clone)]
+pub(crate) struct RestfulClient {
+    client: reqwest::Client,
+    host: String,
+    api_key: String,
+    db_name: String,
+}
+
+impl RestfulClient {
+    /// Create a client for the database `db_name`.
+    ///
+    /// The server is `https://<db_name>.<region>.api.lancedb.com`, unless
+    /// `host_override` is given.
+    pub(crate) fn try_new(
+        db_name: &str,
+        api_key: Option<&str>,
+        region: Option<&str>,
+        host_override: Option<&str>,
+    ) -> Result<Self> {
+        let api_key = api_key.ok_or_else(|| Error::InvalidUri {
+            uri: format!("db://{db_name}"),
+            reason: "an api key is required to connect to a remote database".to_string(),
+        })?;
+        let host = match host_override {
+            Some(host) => host.trim_end_matches('/').to_string(),
+            None => format!(
+                "https://{db_name}.{}.api.lancedb.com",
+                region.unwrap_or(DEFAULT_REGION)
+            ),
+        };
+        Ok(Self {
+            client: reqwest::Client::builder().build()?,
+            host,
+            api_key: api_key.to_string(),
+            db_name: db_name.to_string(),
+        })
+    }
+
+    pub(crate) fn get(&self, path: &str) -> RequestBuilder {
+        self.with_headers(self.client.get(format!("{}{path}", self.host)))
+    }
+
+    pub(crate) fn post(&self, path: &str) -> RequestBuilder {
+        self.with_headers(self.client.post(format!("{}{path}", self.host)))
+    }
+
+    fn with_headers(&self, request: RequestBuilder) -> RequestBuilder {
+        request
+            .header("x-api-key", &self.api_key)
+            .header("x-lancedb-database", &self.db_name)
+    }
+
+    /// Send `request`, turning responses with an error status into [Error::Http].
+    pub(crate) async fn send(&self, request: RequestBuilder) -> Result<Response> {
+        let response = request.send().await?;
+        let status = response.status();
+        if status.is_success() {
+            return Ok(response);
+        }
+        let body = response.text().await.unwrap_or_default();
+        Err(Error::Http {
+            status: Some(status.as_u16()),
+            message: format!("{status}: {body}"),
+        })
+    }
+}
+
+/// Serialize `batches` as an Arrow IPC stream.
+///
+/// # Returns
+///
+/// * The encoded stream and the number of rows in it.
+pub(crate) fn batches_to_ipc(batches: Box<dyn RecordBatchReader>) -> Result<(Vec<u8>, usize)> {
+    let mut writer =
+        StreamWriter::try_new(Vec::new(), batches.schema().as_ref()).map_err(lance::Error::from)?;
+    let mut num_rows = 0;
+    for batch in batches {
+        let batch = batch.map_err(lance::Error::from)?;
+        num_rows += batch.num_rows();
+        writer.write(&batch).map_err(lance::Error::from)?;
+    }
+    writer.finish().map_err(lance::Error::from)?;
+    Ok((writer.into_inner().map_err(lance::Error::from)?, num_rows))
+}
+
+/// Deserialize an Arrow IPC file.
+pub(crate) fn ipc_to_batches(bytes: &[u8]) -> Result<(SchemaRef, Vec<RecordBatch>)> {
+    let reader = FileReader::try_new(Cursor::new(bytes), None).map_err(lance::Error::from)?;
+    let schema = reader.schema();
+    let batches = reader
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .map_err(lance::Error::from)?;
+    Ok((schema, batches))
+}
@@ -0,0 +1,121 @@
+// Copyright 2023 LanceDB Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+
+use arrow_array::RecordBatchReader;
+use lance::dataset::{WriteMode, WriteParams};
+use serde::Deserialize;
+
+use super::client::{batches_to_ipc, RestfulClient, ARROW_STREAM_CONTENT_TYPE};
+use super::table::{write_mode_name, RemoteTable};
+use crate::error::{Error, Result};
+use crate::query::SlowQueryHook;
+use crate::table::TableRef;
+
+#[derive(Deserialize)]
+struct ListTablesResponse {
+    tables: Vec<String>,
+}
+
+/// The tables of a database hosted by a LanceDB server.
+#[derive(Debug, Clone)]
+pub(crate) struct RemoteDatabase {
+    client: RestfulClient,
+    slow_query: Option<SlowQueryHook>,
+    max_limit: Option<usize>,
+}
+
+impl RemoteDatabase {
+    pub(crate) fn new(
+        client: RestfulClient,
+        slow_query: Option<SlowQueryHook>,
+        max_limit: Option<usize>,
+    ) -> Self {
+        Self {
+            client,
+            slow_query,
+            max_limit,
+        }
+    }
+
+    fn table(&self, name: &str) -> TableRef {
+        Arc::new(RemoteTable::new(
+            self.client.clone(),
+            name,
+            self.slow_query.clone(),
+            self.max_limit,
+        ))
+    }
+
+    pub(crate) async fn table_names(&self) -> Result<Vec<String>> {
+        let response = self.client.send(self.client.get("/v1/table/")).await?;
+        Ok(response.json::<ListTablesResponse>().await?.tables)
+    }
+
+    pub(crate) async fn create_table(
+        &self,
+        name: &str,
+        batches: Box<dyn RecordBatchReader>,
+        params: Option<WriteParams>,
+    ) -> Result<TableRef> {
+        let mode = params.map(|p| p.mode).unwrap_or(WriteMode::Create);
+        let (body, _) = batches_to_ipc(batches)?;
+        let request = self
+            .client
+            .post(&format!("/v1/table/{name}/create/"))
+            .query(&[("mode", write_mode_name(mode))])
+            .header(reqwest::header::CONTENT_TYPE, ARROW_STREAM_CONTENT_TYPE)
+            .body(body);
+        self.client.send(request).await.map_err(|e| match e {
+            Error::Http {
+                status: Some(409), ..
+            } => Error::TableAlreadyExists {
+                name: name.to_string(),
+            },
+            e => e,
+        })?;
+        Ok(self.table(name))
+    }
+
+    pub(crate) async fn open_table(&self, name: &str) -> Result<TableRef> {
+        let request = self.client.post(&format!("/v1/table/{name}/describe/"));
+        self.client
+            .send(request)
+            .await
+            .map_err(|e| table_not_found(e, name))?;
+        Ok(self.table(name))
+    }
+
+    pub(crate) async fn drop_table(&self, name: &str) -> Result<()> {
+        let request = self.client.post(&format!("/v1/table/{name}/drop/"));
+        self.client
+            .send(request)
+            .await
+            .map_err(|e| table_not_found(e, name))?;
+        Ok(())
+    }
+}
+
+/// Map a 404 from a table endpoint to [Error::TableNotFound].
+pub(crate) fn table_not_found(e: Error, name: &str) -> Error {
+    match e {
+        Error::Http {
+            status: Some(404), ..
+        } => Error::TableNotFound {
+            name: name.to_string(),
+        },
+        e => e,
+    }
+}
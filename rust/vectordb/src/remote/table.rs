@@ -0,0 +1,381 @@
+// Copyright 2023 LanceDB Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+
+use arrow_array::{Float32Array, RecordBatch, RecordBatchReader};
+use arrow_schema::{Schema, SchemaRef};
+use async_trait::async_trait;
+use lance::dataset::WriteMode;
+use lance::index::vector::StageParams;
+use serde::Deserialize;
+use serde_json::json;
+
+use super::client::{
+    batches_to_ipc, ipc_to_batches, RestfulClient, ARROW_FILE_CONTENT_TYPE,
+    ARROW_STREAM_CONTENT_TYPE,
+};
+use super::db::table_not_found;
+use crate::error::{Error, Result};
+use crate::index::vector::VectorIndexBuilder;
+use crate::query::{Query, QueryExecutor, QueryVector, SlowQueryHook};
+use crate::table::{TableLike, VECTOR_COLUMN_NAME};
+
+#[derive(Deserialize)]
+struct DescribeTableResponse {
+    schema: Schema,
+}
+
+/// A table of a database hosted by a LanceDB server.
+///
+/// Every operation is a request to the server. Data is sent as an Arrow IPC
+/// stream and query results come back as an Arrow IPC file.
+#[derive(Debug, Clone)]
+pub struct RemoteTable {
+    client: RestfulClient,
+    name: String,
+    slow_query: Option<SlowQueryHook>,
+    max_limit: Option<usize>,
+}
+
+impl std::fmt::Display for RemoteTable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "RemoteTable({})", self.name)
+    }
+}
+
+impl RemoteTable {
+    pub(crate) fn new(
+        client: RestfulClient,
+        name: &str,
+        slow_query: Option<SlowQueryHook>,
+        max_limit: Option<usize>,
+    ) -> Self {
+        Self {
+            client,
+            name: name.to_string(),
+            slow_query,
+            max_limit,
+        }
+    }
+}
+
+#[async_trait]
+impl QueryExecutor for RemoteTable {
+    /// Run `query` on the server.
+    async fn execute(&self, query: &Query) -> Result<(SchemaRef, Vec<RecordBatch>)> {
+        if query.query_vectors.is_some() {
+            return Err(Error::InvalidInput {
+                message: "multivector queries are not supported by remote tables".to_string(),
+            });
+        }
+        if query.query_row.is_some() {
+            return Err(Error::InvalidInput {
+                message: "queries by row are not supported by remote tables".to_string(),
+            });
+        }
+        if !query.json_paths.is_empty() {
+            return Err(Error::InvalidInput {
+                message: "JSON path columns are not supported by remote tables".to_string(),
+            });
+        }
+        if query.two_stage.is_some() {
+            return Err(Error::InvalidInput {
+                message: "two-stage queries are not supported by remote tables".to_string(),
+            });
+        }
+        let body = json!({
+            "vector": query.query_vector.values().to_vec(),
+            "vector_column": query.column,
+            "k": query.limit,
+            "nprobes": query.nprobes,
+            "refine_factor": query.refine_factor,
+            "metric": query.metric_type.map(|m| m.to_string()),
+            "filter": query.filter,
+            "columns": query.select,
+            "use_index": query.use_index,
+        });
+        let request = self
+            .client
+            .post(&format!("/v1/table/{}/query/", self.name))
+            .json(&body)
+            .header(reqwest::header::ACCEPT, ARROW_FILE_CONTENT_TYPE);
+        let response = self
+            .client
+            .send(request)
+            .await
+            .map_err(|e| table_not_found(e, &self.name))?;
+        ipc_to_batches(&response.bytes().await?)
+    }
+}
+
+#[async_trait]
+impl TableLike for RemoteTable {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn schema(&self) -> Result<SchemaRef> {
+        let request = self
+            .client
+            .post(&format!("/v1/table/{}/describe/", self.name));
+        let response = self
+            .client
+            .send(request)
+            .await
+            .map_err(|e| table_not_found(e, &self.name))?;
+        Ok(Arc::new(
+            response.json::<DescribeTableResponse>().await?.schema,
+        ))
+    }
+
+    async fn count_rows(&self) -> Result<usize> {
+        let request = self
+            .client
+            .post(&format!("/v1/table/{}/count_rows/", self.name))
+            .json(&json!({}));
+        let response = self
+            .client
+            .send(request)
+            .await
+            .map_err(|e| table_not_found(e, &self.name))?;
+        Ok(response.json::<usize>().await?)
+    }
+
+    async fn count_rows_filtered(&self, filter: &str) -> Result<usize> {
+        let request = self
+            .client
+            .post(&format!("/v1/table/{}/count_rows/", self.name))
+            .json(&json!({ "predicate": filter }));
+        let response = self
+            .client
+            .send(request)
+            .await
+            .map_err(|e| table_not_found(e, &self.name))?;
+        Ok(response.json::<usize>().await?)
+    }
+
+    async fn add(
+        &self,
+        batches: Box<dyn RecordBatchReader + Send>,
+        write_mode: Option<WriteMode>,
+    ) -> Result<usize> {
+        let mode = write_mode.unwrap_or(WriteMode::Append);
+        let (body, num_rows) = batches_to_ipc(batches)?;
+        let request = self
+            .client
+            .post(&format!("/v1/table/{}/insert/", self.name))
+            .query(&[("mode", write_mode_name(mode))])
+            .header(reqwest::header::CONTENT_TYPE, ARROW_STREAM_CONTENT_TYPE)
+            .body(body);
+        self.client
+            .send(request)
+            .await
+            .map_err(|e| table_not_found(e, &self.name))?;
+        Ok(num_rows)
+    }
+
+    async fn create_index(&self, index_builder: &(dyn VectorIndexBuilder + Sync)) -> Result<()> {
+        let params = index_builder.build();
+        let mut body = json!({
+            "column": index_builder.column_name().unwrap_or(VECTOR_COLUMN_NAME),
+            "index_name": index_builder.name(),
+            "index_type": "IVF_PQ",
+            "metric_type": params.metric_type.to_string(),
+            "replace": index_builder.get_replace(),
+        });
+        for stage in params.stages.iter() {
+            match stage {
+                StageParams::Ivf(ivf) => {
+                    body["num_partitions"] = json!(ivf.num_partitions);
+                }
+                StageParams::PQ(pq) => {
+                    body["num_sub_vectors"] = json!(pq.num_sub_vectors);
+                    body["num_bits"] = json!(pq.num_bits);
+                    body["use_opq"] = json!(pq.use_opq);
+                }
+                _ => {
+                    return Err(Error::InvalidInput {
+                        message: "remote tables only support IVF_PQ indices".to_string(),
+                    })
+                }
+            }
+        }
+        let request = self
+            .client
+            .post(&format!("/v1/table/{}/create_index/", self.name))
+            .json(&body);
+        self.client
+            .send(request)
+            .await
+            .map_err(|e| table_not_found(e, &self.name))?;
+        Ok(())
+    }
+
+    fn search(&self, query: QueryVector) -> Query {
+        Query::with_executor(
+            Arc::new(self.clone()),
+            Float32Array::from(Vec::<f32>::new()),
+        )
+        .with_table_name(&self.name)
+        .with_slow_query_hook(self.slow_query.clone())
+        .with_max_limit(self.max_limit)
+        .nearest_to(query)
+    }
+
+    async fn delete(&self, predicate: &str) -> Result<()> {
+        let request = self
+            .client
+            .post(&format!("/v1/table/{}/delete/", self.name))
+            .json(&json!({ "predicate": predicate }));
+        self.client
+            .send(request)
+            .await
+            .map_err(|e| table_not_found(e, &self.name))?;
+        Ok(())
+    }
+}
+
+/// The name of `mode` in the server API.
+pub(crate) fn write_mode_name(mode: WriteMode) -> &'static str {
+    match mode {
+        WriteMode::Create => "create",
+        WriteMode::Append => "append",
+        WriteMode::Overwrite => "overwrite",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_array::{Float32Array, Int32Array, RecordBatch, RecordBatchReader};
+    use arrow_ipc::reader::StreamReader;
+    use arrow_ipc::writer::FileWriter;
+    use arrow_schema::{DataType, Field, Schema};
+    use futures::TryStreamExt;
+    use lance::arrow::RecordBatchBuffer;
+
+    use crate::database::connect;
+    use crate::table::TableLike;
+    use crate::test_util::{mock_server, MockResponse};
+
+    fn make_batch() -> RecordBatch {
+        let schema = Arc::new(Schema::new(vec![Field::new("i", DataType::Int32, false)]));
+        RecordBatch::try_new(schema, vec![Arc::new(Int32Array::from_iter_values(0..10))]).unwrap()
+    }
+
+    fn remote_table(host: &str) -> super::RemoteTable {
+        let client =
+            super::RestfulClient::try_new("my-project", Some("secret"), None, Some(host)).unwrap();
+        super::RemoteTable::new(client, "docs", None, None)
+    }
+
+    #[tokio::test]
+    async fn test_add() {
+        let (host, requests) =
+            mock_server(|_| MockResponse::ok("application/json", b"{}".to_vec())).await;
+        let table = remote_table(&host);
+
+        let batches: Box<dyn RecordBatchReader + Send> =
+            Box::new(RecordBatchBuffer::new(vec![make_batch()]));
+        assert_eq!(table.add(batches, None).await.unwrap(), 10);
+
+        let requests = requests.lock().unwrap();
+        let request = &requests[0];
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/v1/table/docs/insert/?mode=append");
+        assert_eq!(request.headers["x-api-key"], "secret");
+        assert_eq!(request.headers["x-lancedb-database"], "my-project");
+        assert_eq!(
+            request.headers["content-type"],
+            "application/vnd.apache.arrow.stream"
+        );
+        let sent = StreamReader::try_new(request.body.as_slice(), None)
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(sent, vec![make_batch()]);
+    }
+
+    #[tokio::test]
+    async fn test_search() {
+        let batch = make_batch();
+        let mut writer = FileWriter::try_new(Vec::new(), batch.schema().as_ref()).unwrap();
+        writer.write(&batch).unwrap();
+        writer.finish().unwrap();
+        let response = writer.into_inner().unwrap();
+        let (host, requests) = mock_server(move |_| {
+            MockResponse::ok("application/vnd.apache.arrow.file", response.clone())
+        })
+        .await;
+        let table = remote_table(&host);
+
+        let results = table
+            .search(Float32Array::from(vec![0.5, 1.5]).into())
+            .limit(5)
+            .filter(Some("i > 1".to_string()))
+            .execute()
+            .await
+            .unwrap()
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        assert_eq!(results, vec![batch]);
+
+        let requests = requests.lock().unwrap();
+        let request = &requests[0];
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/v1/table/docs/query/");
+        let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+        assert_eq!(body["vector"], serde_json::json!([0.5, 1.5]));
+        assert_eq!(body["k"], 5);
+        assert_eq!(body["nprobes"], 20);
+        assert_eq!(body["filter"], "i > 1");
+    }
+
+    #[tokio::test]
+    async fn test_schema() {
+        let schema = make_batch().schema();
+        let body = serde_json::to_vec(&serde_json::json!({ "schema": schema.as_ref() })).unwrap();
+        let (host, requests) =
+            mock_server(move |_| MockResponse::ok("application/json", body.clone())).await;
+        let table = remote_table(&host);
+
+        assert_eq!(table.schema().await.unwrap(), schema);
+        assert_eq!(requests.lock().unwrap()[0].path, "/v1/table/docs/describe/");
+    }
+
+    #[tokio::test]
+    async fn test_connect() {
+        let (host, requests) =
+            mock_server(|_| MockResponse::ok("application/json", b"{}".to_vec())).await;
+        let db = connect("db://my-project")
+            .api_key("secret")
+            .region("eu-west-1")
+            .host_override(&host)
+            .execute()
+            .await
+            .unwrap();
+        let table = db.open_table("docs").await.unwrap();
+        assert_eq!(table.name(), "docs");
+        assert_eq!(requests.lock().unwrap()[0].path, "/v1/table/docs/describe/");
+
+        let result = connect("db://my-project").execute().await;
+        assert!(matches!(
+            result.err().unwrap(),
+            crate::error::Error::InvalidUri { .. }
+        ));
+    }
+}
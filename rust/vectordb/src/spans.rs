@@ -0,0 +1,77 @@
+// Copyright 2023 LanceDB Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Helpers for the [tracing] spans of table operations.
+//!
+//! Every span has an `elapsed_ms` field, recorded when the operation finishes.
+
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+use arrow_array::{RecordBatch, RecordBatchReader};
+use arrow_schema::{ArrowError, SchemaRef};
+use tracing::{Instrument, Span};
+
+/// Run `future` in `span`, recording how long it took.
+pub(crate) async fn timed<F: Future>(span: Span, future: F) -> F::Output {
+    let start = Instant::now();
+    let output = future.instrument(span.clone()).await;
+    span.record("elapsed_ms", start.elapsed().as_millis() as u64);
+    output
+}
+
+/// A reader counting the rows read from the reader it wraps.
+pub(crate) struct CountingReader {
+    inner: Box<dyn RecordBatchReader>,
+    rows: Arc<AtomicUsize>,
+}
+
+impl CountingReader {
+    /// Wrap `inner`, returning the count of rows read so far.
+    pub(crate) fn wrap(
+        inner: Box<dyn RecordBatchReader>,
+    ) -> (Box<dyn RecordBatchReader>, Arc<AtomicUsize>) {
+        let rows = Arc::new(AtomicUsize::new(0));
+        let reader = Self {
+            inner,
+            rows: rows.clone(),
+        };
+        (Box::new(reader), rows)
+    }
+}
+
+impl Iterator for CountingReader {
+    type Item = Result<RecordBatch, ArrowError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let batch = self.inner.next();
+        if let Some(Ok(batch)) = batch.as_ref() {
+            self.rows.fetch_add(batch.num_rows(), Ordering::Relaxed);
+        }
+        batch
+    }
+}
+
+impl RecordBatchReader for CountingReader {
+    fn schema(&self) -> SchemaRef {
+        self.inner.schema()
+    }
+}
+
+/// The number of rows read from a [CountingReader].
+pub(crate) fn rows_read(rows: &AtomicUsize) -> usize {
+    rows.load(Ordering::Relaxed)
+}
@@ -12,30 +12,200 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::collections::{BTreeMap, HashSet};
 use std::path::Path;
-use std::sync::Arc;
-
-use arrow_array::{Float32Array, RecordBatchReader};
-use lance::dataset::{Dataset, ReadParams, WriteMode, WriteParams};
-use lance::index::IndexType;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+use arrow_array::{Float32Array, RecordBatch, RecordBatchReader};
+use arrow_schema::{DataType, Schema as ArrowSchema, SchemaRef};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use futures::TryStreamExt;
+use lance::dataset::scanner::DatasetRecordBatchStream;
+use lance::dataset::{Dataset, ReadParams, Version, WriteMode, WriteParams};
+use lance::io::object_store::ObjectStoreParams;
+use lance::session::Session;
 use snafu::prelude::*;
-
-use crate::error::{Error, InvalidTableNameSnafu, Result};
-use crate::index::vector::VectorIndexBuilder;
-use crate::query::Query;
+use tracing::{field, info_span, Span};
+
+use crate::arrow::IntoArrow;
+use crate::cache::{CacheConfig, MetadataCache, QueryCache, QueryCacheStats};
+use crate::embeddings::{embed_batches, EmbeddingDefinition};
+use crate::error::{
+    DeleteLimitExceededSnafu, Error, InvalidTableNameSnafu, NoVersionBeforeSnafu, Result,
+};
+use crate::index::vector::{
+    decode_index_configs, encode_index_configs, IndexConfig, VectorIndexBuilder, INDICES_FILE,
+};
+use crate::io::auto_id::{with_auto_ids, with_versions};
+use crate::io::bad_vectors::{
+    check_vectors, marker_column_of, null_vector_field, vector_column_names,
+};
+use crate::io::conform::{conform, decode_dictionaries, encode_dictionaries};
+use crate::io::constraints::check_constraints;
+use crate::io::content_hash::{
+    check_content_hash_left_out, content_hash_field, table_hashes, with_content_hashes,
+};
+#[cfg(feature = "ipc")]
+use crate::io::ipc::{read_ipc_file, ExportIpcParams, IpcFileWriter};
+use crate::io::norms::with_norms;
+use crate::io::object_store::memory_object_store;
+use crate::io::progress::{track_progress, WriteProgressCallback};
+use crate::io::split::split_batches;
+use crate::query::filter::lance_filter;
+use crate::query::{
+    FullTextQuery, PreparedQuery, Query, QueryVector, ScanParams, SearchRequest, SlowQueryHook,
+};
+use crate::spans::{rows_read, timed, CountingReader};
+
+mod changes;
+mod checkpoints;
+mod commit;
+mod constraints;
+mod dataset;
+mod delete;
+mod duplicates;
+mod evaluate;
+mod health;
+mod indices;
+mod ivf;
+mod ivf_build;
+mod join;
+mod keys;
+mod maintenance;
+mod merge;
+mod metadata;
+mod norms;
+mod partial;
+mod partitions;
+mod rename;
+mod retention;
+mod rows;
+mod schema;
+mod scoped;
+mod stats;
+mod truncated;
+mod vector_stats;
+mod write;
+
+pub use crate::io::bad_vectors::null_vector_column;
+pub use crate::io::constraints::VECTOR_NORM;
+pub use crate::io::content_hash::CONTENT_HASH_COLUMN;
+pub use changes::LAST_MODIFIED_VERSION_COLUMN;
+pub use checkpoints::Checkpoint;
+pub(crate) use commit::CommitLock;
+pub use constraints::{Constraint, OnConstraintViolation, RejectedRow};
+pub(crate) use dataset::DatasetRef;
+pub use delete::{DeleteStats, MAX_IN_LIST_VALUES};
+pub use duplicates::DuplicatePair;
+pub use evaluate::{IndexEvaluation, SearchEvaluation, SearchParams, DEFAULT_EVALUATION_QUERIES};
+pub use health::{HealthCheck, HealthCheckKind, HealthReport, HEALTH_CHECK_TIMEOUT};
+pub use indices::{IndexHealth, IndexStats};
+pub(crate) use ivf::ColumnPartitions;
+pub use ivf::{IndexPartitionStats, PartitionStats, ProbedPartition};
+pub use join::{
+    SimilarityJoinOptions, DEFAULT_JOIN_BATCH_SIZE, JOIN_DISTANCE_COLUMN, JOIN_RANK_COLUMN,
+};
+pub(crate) use keys::{AutoId, TableProperties};
+pub use maintenance::{MaintenanceConfig, MaintenanceHandle, OptimizationStats};
+pub use norms::norm_column;
+pub use partitions::FragmentInfo;
+pub(crate) use partitions::{check_partition_column, Partitioning};
+pub use retention::RetentionPolicy;
+pub use rows::IterRowsBuilder;
+pub use schema::{ColumnCast, SchemaDelta};
+pub use scoped::ScopedTable;
+pub use stats::{ColumnStats, TableStats, DEFAULT_SELECTIVITY_SAMPLE};
+pub(crate) use truncated::truncate_vector;
+pub use vector_stats::{VectorStats, DEFAULT_VECTOR_STATS_SAMPLE};
 
 pub const VECTOR_COLUMN_NAME: &str = "vector";
 pub const LANCE_FILE_EXTENSION: &str = "lance";
+/// Default number of times a write retries taking a table's commit lock.
+pub(crate) const DEFAULT_MAX_COMMIT_RETRIES: usize = 20;
+
+/// The operations shared by local and remote tables, so that application code
+/// does not need to know which kind it has.
+///
+/// The trait is object safe, applications can implement it to test code taking
+/// a [TableRef] without a database. A mock table answers queries by creating them
+/// with [Query::with_executor].
+///
+/// Its futures are `Send`, a [TableRef] can be used in tasks spawned on a
+/// multi-threaded runtime.
+#[async_trait]
+pub trait TableLike: std::fmt::Debug + std::fmt::Display + Send + Sync {
+    /// The name of the table.
+    fn name(&self) -> &str;
+
+    /// The schema of the table.
+    async fn schema(&self) -> Result<SchemaRef>;
+
+    /// Returns the number of rows in this table.
+    async fn count_rows(&self) -> Result<usize>;
+
+    /// Returns the number of rows in this table matching `filter`, a SQL WHERE
+    /// clause.
+    async fn count_rows_filtered(&self, filter: &str) -> Result<usize>;
+
+    /// Insert records into this table.
+    ///
+    /// # Arguments
+    ///
+    /// * `batches` RecordBatch to be saved in the Table
+    /// * `write_mode` Append / Overwrite existing records. Default: Append
+    async fn add(
+        &self,
+        batches: Box<dyn RecordBatchReader + Send>,
+        write_mode: Option<WriteMode>,
+    ) -> Result<usize>;
+
+    /// Create index on the table.
+    async fn create_index(&self, index_builder: &(dyn VectorIndexBuilder + Sync)) -> Result<()>;
+
+    /// Creates a new Query object that can be executed.
+    ///
+    /// `query` is a vector, or a text embedded with the table's embedding function.
+    fn search(&self, query: QueryVector) -> Query;
+
+    /// Run `request`, the search of [TableLike::search] with its parameters.
+    ///
+    /// Fails with an [Error::InvalidInput] if [SearchRequest::validate] does.
+    async fn search_request(&self, request: &SearchRequest) -> Result<DatasetRecordBatchStream> {
+        let query = self.search(request.vector.clone().into());
+        request.execute(query).await
+    }
 
-/// A table in a LanceDB database.
+    /// Delete the rows matching `predicate`, a SQL WHERE clause.
+    async fn delete(&self, predicate: &str) -> Result<()>;
+}
+
+/// A shared handle to a local or remote table.
+pub type TableRef = Arc<dyn TableLike>;
+
+/// A table stored in a lance dataset.
 #[derive(Debug, Clone)]
-pub struct Table {
+pub struct NativeTable {
     name: String,
     uri: String,
-    dataset: Arc<Dataset>,
+    dataset: DatasetRef,
+    max_commit_retries: usize,
+    /// Shared by the clones of the handle, and renamed with their columns.
+    embeddings: Arc<RwLock<Vec<EmbeddingDefinition>>>,
+    slow_query: Option<SlowQueryHook>,
+    scan_params: ScanParams,
+    max_limit: Option<usize>,
+    write_options: WriteOptions,
+    query_cache: Option<Arc<QueryCache>>,
+    index_build_threads: Option<usize>,
 }
 
-impl std::fmt::Display for Table {
+/// The former name of [NativeTable].
+pub type Table = NativeTable;
+
+impl std::fmt::Display for NativeTable {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "Table({})", self.name)
     }
@@ -44,9 +214,350 @@ impl std::fmt::Display for Table {
 #[derive(Default)]
 pub struct OpenTableParams {
     pub open_table_params: ReadParams,
+
+    /// How long a table may serve reads from the version it last loaded before
+    /// checking storage for a newer one. `None` never checks automatically.
+    pub read_consistency_interval: Option<Duration>,
+
+    /// How many times a write waits for another writer to finish committing
+    /// before failing with [Error::CommitConflict]. `None` uses the default of 20.
+    pub max_commit_retries: Option<usize>,
+
+    /// Called for the queries of the table that take longer than its threshold.
+    pub slow_query: Option<SlowQueryHook>,
+
+    /// The scan parameters of the queries of the table that do not set them.
+    pub scan_params: ScanParams,
+
+    /// The largest limit of the queries of the table, see [Query::max_limit].
+    /// `None` does not cap them.
+    pub max_limit: Option<usize>,
+
+    /// How the batches written to the table are split and their vectors
+    /// checked. `None` uses [WriteOptions::default].
+    pub write_options: Option<WriteOptions>,
+
+    /// The default [crate::index::vector::IvfPQIndexBuilder::num_threads] of
+    /// the indices built on
+    /// the table. `None` uses the available parallelism.
+    pub index_build_threads: Option<usize>,
+}
+
+/// The default [WriteOptions::max_bytes_per_batch], 64 MiB.
+pub const DEFAULT_MAX_BYTES_PER_BATCH: usize = 64 * 1024 * 1024;
+
+/// Limits on the size of the batches handed to lance when writing a table.
+///
+/// Larger incoming batches are sliced into consecutive chunks, in order and
+/// without splitting rows, so that writing one huge batch does not hold several
+/// encoded copies of it in memory. The chunks are still committed as a single
+/// version. A `None` limit is not enforced.
+///
+/// The options also hold the [OnBadVectors] policy of the written rows, whether
+/// they may have null vectors, whether appends add the new columns of the
+/// written data to the table or leave them out, whether rows already in the
+/// table are written again, the [Encoding] of columns in the data files, and
+/// the [OnConstraintViolation] policy of the rows violating a [Constraint] of
+/// the table.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WriteOptions {
+    /// The largest in-memory size of a batch, in bytes. Default:
+    /// [DEFAULT_MAX_BYTES_PER_BATCH].
+    pub max_bytes_per_batch: Option<usize>,
+
+    /// The largest number of rows of a batch. Default: `None`.
+    pub max_rows_per_batch: Option<usize>,
+
+    /// What to do with the rows of bad vectors. Default: [OnBadVectors::Error].
+    pub on_bad_vectors: OnBadVectors,
+
+    /// Whether rows may have a null vector, for example rows not embedded yet.
+    /// The null vectors are marked in the column named by [null_vector_column],
+    /// added to the table by the first write allowing them, and searches leave
+    /// them out, see [ColumnStats::null_count]. Default: `false`, null vectors
+    /// are bad.
+    pub allow_null_vectors: bool,
+
+    /// Whether appended data with nullable columns the table does not have
+    /// adds them to the table, null in its existing rows. Default: `false`,
+    /// such appends fail with an [Error::Schema].
+    pub schema_evolution: bool,
+
+    /// Whether the columns of appended data the table does not have, and
+    /// [WriteOptions::schema_evolution] does not add, are left out. Default:
+    /// `false`, such appends fail with an [Error::Schema] naming them.
+    pub drop_unknown_columns: bool,
+
+    /// The [Encoding] of columns by name, used by the writes creating them:
+    /// the creation of a table and overwrites, which fail with an
+    /// [Error::InvalidInput] for the columns not in the written data. Default:
+    /// empty, every column is [Encoding::Plain].
+    pub column_encodings: BTreeMap<String, Encoding>,
+
+    /// The columns whose values identify the content of a row. A written row
+    /// whose content is already in the table, or earlier in the same write, is
+    /// skipped and counted in [AddReport::skipped_rows]. The hashes of the
+    /// contents are stored in the column [CONTENT_HASH_COLUMN], added to the
+    /// table by its first deduplicated write. Default: `None`, every row is
+    /// written.
+    pub dedupe_on_hash: Option<Vec<String>>,
+
+    /// What to do with the rows violating a constraint of the table, see
+    /// [NativeTable::set_constraints]. Default: [OnConstraintViolation::Error].
+    pub on_constraint_violation: OnConstraintViolation,
+}
+
+impl Default for WriteOptions {
+    fn default() -> Self {
+        Self {
+            max_bytes_per_batch: Some(DEFAULT_MAX_BYTES_PER_BATCH),
+            max_rows_per_batch: None,
+            on_bad_vectors: OnBadVectors::default(),
+            allow_null_vectors: false,
+            schema_evolution: false,
+            drop_unknown_columns: false,
+            column_encodings: BTreeMap::new(),
+            dedupe_on_hash: None,
+            on_constraint_violation: OnConstraintViolation::default(),
+        }
+    }
+}
+
+/// How the values of a column are stored in the data files of a table, see
+/// [WriteOptions::column_encoding].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Encoding {
+    /// The values as they are.
+    #[default]
+    Plain,
+    /// The distinct values once for the table, and an index into them for each
+    /// row. Only for string columns, best for the ones of few distinct values.
+    ///
+    /// lance 0.5 keeps the distinct values of the whole table in its manifest
+    /// and cannot append to such columns: adding rows to the table fails with
+    /// an [Error::Schema], overwrites do not.
+    Dictionary,
+    /// The values compressed with zstd at this level. lance 0.5 writes no
+    /// compressed columns, so the options fail validation with an
+    /// [Error::InvalidInput].
+    Zstd(i32),
+}
+
+/// What to do with the written rows whose vector is bad: null, with null
+/// values, or with NaN or infinite values.
+///
+/// Only the `FixedSizeList` columns of `Float32` or `Float16` values are
+/// checked, after embeddings have been computed.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum OnBadVectors {
+    /// Fail the write with an [Error::InvalidInput] naming the column and row.
+    #[default]
+    Error,
+    /// Leave the rows out of the write.
+    Drop,
+    /// Replace every value of the bad vectors with this one, which must be finite.
+    Fill(f32),
+}
+
+/// The rows of a write, see [NativeTable::add_with_report].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AddReport {
+    /// The version committed by the write.
+    pub version: u64,
+    /// The rows written, including the filled ones.
+    pub rows: usize,
+    /// The in-memory size of the rows written, see
+    /// [crate::io::progress::WriteProgress::bytes].
+    pub bytes: usize,
+    /// The fragments the write added.
+    pub fragments: usize,
+    /// The rows left out by [OnBadVectors::Drop].
+    pub dropped_rows: usize,
+    /// The rows whose vectors were replaced by [OnBadVectors::Fill].
+    pub filled_rows: usize,
+    /// The rows left out by [WriteOptions::dedupe_on_hash].
+    pub skipped_rows: usize,
+    /// The rows left out by [OnConstraintViolation::Reject], in order.
+    pub rejected_rows: Vec<RejectedRow>,
+}
+
+/// The rows removed by [NativeTable::delete_returning].
+#[derive(Debug, Clone, Default)]
+pub struct DeleteReport {
+    /// The version committed by the delete.
+    pub version: u64,
+    /// The number of rows deleted.
+    pub rows: usize,
+    /// The deleted rows, with the columns asked for.
+    pub batches: Vec<RecordBatch>,
+}
+
+impl WriteOptions {
+    /// Set what to do with the rows of bad vectors.
+    pub fn on_bad_vectors(mut self, policy: OnBadVectors) -> Self {
+        self.on_bad_vectors = policy;
+        self
+    }
+
+    /// Set whether rows may have a null vector.
+    pub fn allow_null_vectors(mut self, allow: bool) -> Self {
+        self.allow_null_vectors = allow;
+        self
+    }
+
+    /// Set whether appends add the new nullable columns of the written data.
+    pub fn schema_evolution(mut self, schema_evolution: bool) -> Self {
+        self.schema_evolution = schema_evolution;
+        self
+    }
+
+    /// Set whether appends leave out the columns the table does not have.
+    pub fn drop_unknown_columns(mut self, drop: bool) -> Self {
+        self.drop_unknown_columns = drop;
+        self
+    }
+
+    /// Skip the written rows whose values of `columns` are already in the table.
+    pub fn dedupe_on_hash(mut self, columns: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.dedupe_on_hash = Some(columns.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Set what to do with the rows violating a constraint of the table.
+    pub fn on_constraint_violation(mut self, policy: OnConstraintViolation) -> Self {
+        self.on_constraint_violation = policy;
+        self
+    }
+
+    /// Set the [Encoding] of `column` in the data files.
+    pub fn column_encoding(mut self, column: impl Into<String>, encoding: Encoding) -> Self {
+        self.column_encodings.insert(column.into(), encoding);
+        self
+    }
+
+    /// Set whether the string `column` is stored with [Encoding::Dictionary].
+    pub fn dictionary_encoding(self, column: impl Into<String>, enabled: bool) -> Self {
+        let encoding = if enabled {
+            Encoding::Dictionary
+        } else {
+            Encoding::Plain
+        };
+        self.column_encoding(column, encoding)
+    }
+
+    /// The columns of `schema`, the schema of the data creating them, to
+    /// write with [Encoding::Dictionary].
+    pub(crate) fn dictionary_columns(&self, schema: &ArrowSchema) -> Result<Vec<String>> {
+        let mut columns = Vec::new();
+        for (column, encoding) in &self.column_encodings {
+            let field = schema
+                .field_with_name(column)
+                .map_err(|_| Error::InvalidInput {
+                    message: format!(
+                        "cannot set the encoding of the column '{column}', it is not in the written data"
+                    ),
+                })?;
+            if *encoding != Encoding::Dictionary {
+                continue;
+            }
+            if !matches!(field.data_type(), DataType::Utf8 | DataType::LargeUtf8) {
+                return Err(Error::InvalidInput {
+                    message: format!(
+                        "cannot dictionary-encode the column '{column}' of type {}, only string columns",
+                        field.data_type()
+                    ),
+                });
+            }
+            columns.push(column.clone());
+        }
+        Ok(columns)
+    }
+
+    /// Make sure every limit set is at least 1, the fill value is finite and
+    /// every encoding can be written.
+    pub(crate) fn validate(&self) -> Result<()> {
+        if let OnBadVectors::Fill(value) = self.on_bad_vectors {
+            if !value.is_finite() {
+                return Err(Error::InvalidInput {
+                    message: format!("the fill value of bad vectors must be finite, got {value}"),
+                });
+            }
+        }
+        if self.dedupe_on_hash.as_ref().is_some_and(Vec::is_empty) {
+            return Err(Error::InvalidInput {
+                message: "dedupe_on_hash needs at least one column".to_string(),
+            });
+        }
+        for (name, value) in [
+            ("max_bytes_per_batch", self.max_bytes_per_batch),
+            ("max_rows_per_batch", self.max_rows_per_batch),
+        ] {
+            if value == Some(0) {
+                return Err(Error::InvalidInput {
+                    message: format!("{name} must be at least 1"),
+                });
+            }
+        }
+        for (column, encoding) in &self.column_encodings {
+            if let Encoding::Zstd(level) = encoding {
+                return Err(Error::InvalidInput {
+                    message: format!(
+                        "cannot compress the column '{column}' with zstd level {level}, lance 0.5 writes no compressed columns"
+                    ),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Make sure `params` carries a session, so that reloads of a table keep its index cache.
+fn with_session(mut params: ReadParams) -> ReadParams {
+    if params.session.is_none() {
+        params.session = Some(Arc::new(Session::new(params.index_cache_size)));
+    }
+    params
+}
+
+/// Make sure the params of a `memory://` table at `uri` carry a store, the
+/// one the table is written to and read back from, see
+/// [crate::io::object_store::memory_object_store].
+fn with_memory_store(uri: &str, mut params: ReadParams) -> ReadParams {
+    let wrapped = params
+        .store_options
+        .as_ref()
+        .is_some_and(|options| options.object_store_wrapper.is_some());
+    if uri.starts_with("memory://") && !wrapped {
+        params.store_options = Some(ObjectStoreParams {
+            object_store_wrapper: Some(Arc::new(memory_object_store())),
+        });
+    }
+    params
+}
+
+/// Make sure the vector columns of `schema` have the widths of the same columns in `expected`.
+pub(crate) fn check_vector_dims(expected: &ArrowSchema, schema: &ArrowSchema) -> Result<()> {
+    for field in schema.fields() {
+        let DataType::FixedSizeList(_, got) = field.data_type() else {
+            continue;
+        };
+        let Ok(expected) = expected.field_with_name(field.name()) else {
+            continue;
+        };
+        if let DataType::FixedSizeList(_, width) = expected.data_type() {
+            if width != got {
+                return Err(Error::EmbeddingDimensionMismatch {
+                    column: field.name().clone(),
+                    expected: *width as usize,
+                    got: *got as usize,
+                });
+            }
+        }
+    }
+    Ok(())
 }
 
-impl Table {
+impl NativeTable {
     /// Opens an existing Table
     ///
     /// # Arguments
@@ -56,7 +567,7 @@ impl Table {
     ///
     /// # Returns
     ///
-    /// * A [Table] object.
+    /// * A [NativeTable] object.
     pub async fn open(base_uri: &str, name: &str) -> Result<Self> {
         Self::open_with_params(base_uri, name, OpenTableParams::default()).await
     }
@@ -71,35 +582,121 @@ impl Table {
     ///
     /// # Returns
     ///
-    /// * A [Table] object.
+    /// * A [NativeTable] object.
     pub async fn open_with_params(
         base_uri: &str,
         name: &str,
         params: OpenTableParams,
     ) -> Result<Self> {
-        let path = Path::new(base_uri);
+        Self::open_with_cache(base_uri, name, params, None).await
+    }
+
+    /// Opens an existing Table, serving its metadata from `metadata_cache` when possible.
+    pub(crate) async fn open_with_cache(
+        base_uri: &str,
+        name: &str,
+        params: OpenTableParams,
+        metadata_cache: Option<Arc<MetadataCache>>,
+    ) -> Result<Self> {
+        let uri = Self::table_uri(base_uri, name)?;
+        let read_params = with_session(params.open_table_params);
+
+        let cached = metadata_cache
+            .as_ref()
+            .and_then(|cache| cache.get(&uri, params.read_consistency_interval));
+        let dataset = match cached {
+            Some(dataset) => dataset,
+            None => {
+                // lance creates the directory of a local dataset it cannot
+                // find, which would be left behind.
+                if !uri.contains("://") && !Path::new(&uri).exists() {
+                    return Err(Error::TableNotFound {
+                        name: name.to_string(),
+                    });
+                }
+                let dataset = dataset::open_latest(&uri, &read_params)
+                    .await
+                    .map_err(|e| match e {
+                        lance::Error::DatasetNotFound { .. } => Error::TableNotFound {
+                            name: name.to_string(),
+                        },
+                        e => e.into(),
+                    })?;
+                let dataset = Arc::new(dataset);
+                if let Some(cache) = metadata_cache.as_ref() {
+                    cache.insert(&uri, dataset.clone());
+                }
+                dataset
+            }
+        };
+        Ok(NativeTable {
+            name: name.to_string(),
+            dataset: DatasetRef::new(
+                uri.clone(),
+                dataset,
+                read_params,
+                params.read_consistency_interval,
+                metadata_cache,
+            ),
+            uri,
+            max_commit_retries: params
+                .max_commit_retries
+                .unwrap_or(DEFAULT_MAX_COMMIT_RETRIES),
+            embeddings: Arc::default(),
+            slow_query: params.slow_query,
+            scan_params: params.scan_params,
+            max_limit: params.max_limit,
+            write_options: params.write_options.unwrap_or_default(),
+            query_cache: None,
+            index_build_threads: params.index_build_threads,
+        })
+    }
+
+    /// Compute the vector columns of `embeddings` when they are missing from added data.
+    pub(crate) fn with_embeddings(mut self, embeddings: Vec<EmbeddingDefinition>) -> Self {
+        self.embeddings = Arc::new(RwLock::new(embeddings));
+        self
+    }
 
+    /// Serve repeated identical queries of this handle from a cache configured
+    /// by `config`.
+    ///
+    /// Results are keyed by the query vector and parameters and by the table
+    /// version, so they are computed again once the table changes. Only
+    /// [Query::execute] uses the cache, [Query::execute_with_metrics] always
+    /// runs the query.
+    pub fn with_query_cache(mut self, config: CacheConfig) -> Self {
+        self.query_cache = Some(Arc::new(QueryCache::new(config)));
+        self
+    }
+
+    /// The counters of the query cache of this handle, `None` without one.
+    pub fn query_cache_stats(&self) -> Option<QueryCacheStats> {
+        self.query_cache.as_ref().map(|cache| cache.stats())
+    }
+
+    /// The embeddings computed by this table on [NativeTable::add].
+    pub fn embeddings(&self) -> Vec<EmbeddingDefinition> {
+        self.embeddings.read().unwrap().clone()
+    }
+
+    pub(crate) fn uri(&self) -> &str {
+        &self.uri
+    }
+
+    pub(crate) fn table_uri(base_uri: &str, name: &str) -> Result<String> {
+        // The tables of `memory://` are paths in its store, not its host.
+        let base_uri = match base_uri.strip_suffix("://") {
+            Some(scheme) => format!("{scheme}:///"),
+            None => base_uri.to_string(),
+        };
+        let path = Path::new(&base_uri);
         let table_uri = path.join(format!("{}.{}", name, LANCE_FILE_EXTENSION));
-        let uri = table_uri
+        Ok(table_uri
             .as_path()
             .to_str()
-            .context(InvalidTableNameSnafu { name })?;
-
-        let dataset = Dataset::open_with_params(uri, &params.open_table_params)
-            .await
-            .map_err(|e| match e {
-                lance::Error::DatasetNotFound { .. } => Error::TableNotFound {
-                    name: name.to_string(),
-                },
-                e => Error::Lance {
-                    message: e.to_string(),
-                },
-            })?;
-        Ok(Table {
-            name: name.to_string(),
-            uri: uri.to_string(),
-            dataset: Arc::new(dataset),
-        })
+            .context(InvalidTableNameSnafu { name })?
+            .to_string())
     }
 
     /// Creates a new Table
@@ -112,55 +709,227 @@ impl Table {
     ///
     /// # Returns
     ///
-    /// * A [Table] object.
+    /// * A [NativeTable] object.
     pub async fn create(
         base_uri: &str,
         name: &str,
-        mut batches: Box<dyn RecordBatchReader>,
+        batches: Box<dyn RecordBatchReader>,
         params: Option<WriteParams>,
     ) -> Result<Self> {
-        let base_path = Path::new(base_uri);
-        let table_uri = base_path.join(format!("{}.{}", name, LANCE_FILE_EXTENSION));
-        let uri = table_uri
-            .as_path()
-            .to_str()
-            .context(InvalidTableNameSnafu { name })?
-            .to_string();
-        let dataset = Dataset::write(&mut batches, &uri, params)
-            .await
-            .map_err(|e| match e {
-                lance::Error::DatasetAlreadyExists { .. } => Error::TableAlreadyExists {
-                    name: name.to_string(),
-                },
-                e => Error::Lance {
-                    message: e.to_string(),
-                },
-            })?;
-        Ok(Table {
+        Self::create_with_cache(
+            base_uri,
+            name,
+            batches,
+            params,
+            OpenTableParams::default(),
+            None,
+            None,
+        )
+        .await
+    }
+
+    /// Creates a new Table, and opens it with `open_params` sharing `metadata_cache`.
+    ///
+    /// With `partition_by`, the rows of each value of the column are written
+    /// to fragments of their own.
+    pub(crate) async fn create_with_cache(
+        base_uri: &str,
+        name: &str,
+        batches: Box<dyn RecordBatchReader>,
+        params: Option<WriteParams>,
+        open_params: OpenTableParams,
+        metadata_cache: Option<Arc<MetadataCache>>,
+        partition_by: Option<&str>,
+    ) -> Result<Self> {
+        let uri = Self::table_uri(base_uri, name)?;
+        let write_options = open_params.write_options.unwrap_or_default();
+        write_options.validate()?;
+        let batches = decode_dictionaries(batches);
+        let dictionaries = write_options.dictionary_columns(&batches.schema())?;
+        let batches = encode_dictionaries(batches, &dictionaries);
+        let (mut batches, bad_vectors) = check_vectors(batches, &write_options);
+        if let Some(columns) = write_options.dedupe_on_hash.as_deref() {
+            check_content_hash_left_out(&batches.schema())?;
+            batches =
+                with_content_hashes(batches, columns, Default::default(), Default::default())?;
+        }
+        let schema = batches.schema();
+        let batches = split_batches(batches, &write_options)?;
+        let (mut batches, rows) = CountingReader::wrap(batches);
+        let read_params = with_memory_store(&uri, with_session(open_params.open_table_params));
+        let (dataset, values) = match partition_by {
+            Some(column) => {
+                let params = params.unwrap_or_default();
+                let existing = Dataset::open_with_params(&uri, &read_params).await.ok();
+                if existing.is_some() && matches!(params.mode, WriteMode::Create) {
+                    return Err(Error::TableAlreadyExists {
+                        name: name.to_string(),
+                    });
+                }
+                let (dataset, values) = partitions::write_partitioned(
+                    &uri,
+                    existing.as_ref(),
+                    batches.as_mut(),
+                    column,
+                    &params,
+                    &read_params,
+                )
+                .await
+                .map_err(|e| bad_vectors.take_error(e))?;
+                (dataset, Some(values))
+            }
+            None => {
+                let dataset = write::write_dataset(&mut batches, &uri, params, &read_params)
+                    .await
+                    .map_err(|e| match e {
+                        lance::Error::DatasetAlreadyExists { .. } => Error::TableAlreadyExists {
+                            name: name.to_string(),
+                        },
+                        e => bad_vectors.take_error(e.into()),
+                    })?;
+                (dataset, None)
+            }
+        };
+        Span::current().record("rows", rows_read(&rows));
+        let dataset = DatasetRef::new(
+            uri.clone(),
+            Arc::new(dataset),
+            read_params,
+            open_params.read_consistency_interval,
+            metadata_cache,
+        );
+        let current = dataset.reload().await?;
+        let table = NativeTable {
             name: name.to_string(),
+            dataset,
             uri,
-            dataset: Arc::new(dataset),
-        })
+            max_commit_retries: open_params
+                .max_commit_retries
+                .unwrap_or(DEFAULT_MAX_COMMIT_RETRIES),
+            embeddings: Arc::default(),
+            slow_query: open_params.slow_query,
+            scan_params: open_params.scan_params,
+            max_limit: open_params.max_limit,
+            write_options,
+            query_cache: None,
+            index_build_threads: open_params.index_build_threads,
+        };
+        if let (Some(column), Some(values)) = (partition_by, values) {
+            table.record_partitions(&current, column, values).await?;
+        }
+        table.record_metadata(&schema).await?;
+        Ok(table)
     }
 
     /// Create index on the table.
-    pub async fn create_index(&mut self, index_builder: &impl VectorIndexBuilder) -> Result<()> {
+    ///
+    /// The indexed column is checked by [VectorIndexBuilder::validate] first.
+    pub async fn create_index(
+        &self,
+        index_builder: &(impl VectorIndexBuilder + ?Sized),
+    ) -> Result<()> {
         use lance::index::DatasetIndexExt;
 
-        let dataset = self
+        let column = index_builder.column_name().unwrap_or(VECTOR_COLUMN_NAME);
+        let span = info_span!(
+            "create_index",
+            table = %self.name,
+            column = %column,
+            elapsed_ms = field::Empty
+        );
+        let write = self
             .dataset
-            .create_index(
-                &[index_builder
-                    .get_column()
-                    .unwrap_or(VECTOR_COLUMN_NAME.to_string())
-                    .as_str()],
-                IndexType::Vector,
-                index_builder.get_index_name(),
-                &index_builder.build(),
-                index_builder.get_replace(),
+            .write(self.max_commit_retries, |dataset| async move {
+                let schema = ArrowSchema::from(dataset.schema());
+                if let Ok(field) = schema.field_with_name(column) {
+                    index_builder.validate(field)?;
+                }
+                let params = index_builder.build();
+                if marker_column_of(&schema, column).is_some() {
+                    // lance trains the index on every row, it cannot leave out null vectors.
+                    let nulls = self.dataset.column_stats(&dataset, column).await?.null_count;
+                    if nulls > 0 {
+                        return Err(Error::InvalidInput {
+                            message: format!(
+                                "cannot build an index of the column '{column}', {nulls} rows have null vectors"
+                            ),
+                        });
+                    }
+                }
+                if let Some((indexed, _)) = self
+                    .build_ivf_pq_index(&dataset, column, index_builder, &params)
+                    .await?
+                {
+                    return Ok(indexed);
+                }
+                if index_builder.filter().is_some() {
+                    return Err(Error::InvalidInput {
+                        message: format!(
+                            "cannot index the rows of '{column}' matching a filter, a row filter needs an IVF_PQ index without OPQ of a float32 vector column"
+                        ),
+                    });
+                }
+                Ok(dataset
+                    .create_index(
+                        &[column],
+                        index_builder.index_type(),
+                        index_builder.name().map(str::to_string),
+                        &params,
+                        index_builder.get_replace(),
+                    )
+                    .await?)
+            });
+        timed(span, write).await?;
+        let name = index_builder
+            .name()
+            .map_or_else(|| format!("{column}_idx"), str::to_string);
+        if let Some(mut config) = IndexConfig::new(name, column.to_string(), &index_builder.build())
+        {
+            config.row_filter = index_builder.filter().map(str::to_string);
+            self.record_index_config(config).await?;
+        }
+        Ok(())
+    }
+
+    /// The parameters of the indices of this table recorded by [Self::create_index].
+    async fn index_configs(&self) -> Result<Vec<IndexConfig>> {
+        let (store, base) = self.dataset.object_store().await?;
+        let path = base.child(INDICES_FILE);
+        match store.inner.get(&path).await {
+            Ok(result) => decode_index_configs(&path, &result.bytes().await?),
+            Err(object_store::Error::NotFound { .. }) => Ok(Vec::new()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Record `config`, replacing the configuration of the index of the same name.
+    async fn record_index_config(&self, config: IndexConfig) -> Result<()> {
+        let mut configs = self.index_configs().await?;
+        configs.retain(|c| c.name != config.name);
+        configs.push(config);
+        self.write_index_configs(&configs).await
+    }
+
+    /// Forget the configuration of the index `name` recorded by [Self::create_index].
+    async fn forget_index_config(&self, name: &str) -> Result<()> {
+        let mut configs = self.index_configs().await?;
+        if !configs.iter().any(|c| c.name == name) {
+            return Ok(());
+        }
+        configs.retain(|c| c.name != name);
+        self.write_index_configs(&configs).await
+    }
+
+    /// Replace the index configurations recorded by [Self::create_index].
+    async fn write_index_configs(&self, configs: &[IndexConfig]) -> Result<()> {
+        let (store, base) = self.dataset.object_store().await?;
+        store
+            .inner
+            .put(
+                &base.child(INDICES_FILE),
+                encode_index_configs(configs)?.into(),
             )
             .await?;
-        self.dataset = Arc::new(dataset);
         Ok(())
     }
 
@@ -170,176 +939,2376 @@ impl Table {
     ///
     /// * `batches` RecordBatch to be saved in the Table
     /// * `write_mode` Append / Overwrite existing records. Default: Append
+    ///
+    /// The vector columns of the table's embeddings are computed from their
+    /// source columns when they are missing from `batches`.
+    ///
+    /// Appends from concurrent writers are applied one after the other. An add
+    /// fails with [Error::CommitConflict] if another writer changed the schema
+    /// since this table was loaded.
+    ///
     /// # Returns
     ///
     /// * The number of rows added
     pub async fn add(
-        &mut self,
-        mut batches: Box<dyn RecordBatchReader>,
+        &self,
+        batches: Box<dyn RecordBatchReader>,
         write_mode: Option<WriteMode>,
     ) -> Result<usize> {
-        let mut params = WriteParams::default();
-        params.mode = write_mode.unwrap_or(WriteMode::Append);
+        Ok(self.add_with_report(batches, write_mode).await?.rows)
+    }
+
+    /// Like [NativeTable::add], also counting the rows dropped or filled by the
+    /// [OnBadVectors] policy of the table's [WriteOptions].
+    pub async fn add_with_report(
+        &self,
+        batches: Box<dyn RecordBatchReader>,
+        write_mode: Option<WriteMode>,
+    ) -> Result<AddReport> {
+        self.add_with_progress(batches, write_mode, None).await
+    }
+
+    /// Like [NativeTable::add_with_report], calling `progress` with the rows
+    /// written so far as lance reads them, see [crate::io::progress].
+    ///
+    /// The write is committed at the end, in one step: dropping the returned
+    /// future before it completes leaves the table as it was, and the data
+    /// files written so far unused.
+    pub async fn add_with_progress(
+        &self,
+        batches: Box<dyn RecordBatchReader>,
+        write_mode: Option<WriteMode>,
+        progress: Option<WriteProgressCallback>,
+    ) -> Result<AddReport> {
+        let span = info_span!(
+            "add",
+            table = %self.name,
+            mode = ?write_mode,
+            rows = field::Empty,
+            dropped_rows = field::Empty,
+            filled_rows = field::Empty,
+            elapsed_ms = field::Empty
+        );
+        let report = timed(span.clone(), self.write(batches, write_mode, progress)).await?;
+        span.record("rows", report.rows);
+        span.record("dropped_rows", report.dropped_rows);
+        span.record("filled_rows", report.filled_rows);
+        Ok(report)
+    }
+
+    /// Write `batches`, returning the rows written, dropped and filled.
+    async fn write(
+        &self,
+        batches: Box<dyn RecordBatchReader>,
+        write_mode: Option<WriteMode>,
+        progress: Option<WriteProgressCallback>,
+    ) -> Result<AddReport> {
+        let batches = embed_batches(&self.embeddings(), batches).await?;
+        let mut batches = decode_dictionaries(batches);
+        let params = WriteParams {
+            mode: write_mode.unwrap_or(WriteMode::Append),
+            ..WriteParams::default()
+        };
+        let next_id = Arc::new(AtomicU64::new(0));
+        let properties = self.properties().await?;
+        let partition_by = properties.partition_by.map(|p| p.column);
+        let partition_by = partition_by.as_deref();
+        let auto_id = properties.auto_id;
+        if let Some(auto_id) = auto_id.as_ref() {
+            batches = with_auto_ids(batches, &auto_id.column, next_id.clone())?;
+        }
+        let auto_id = auto_id.is_some();
+        let version = Arc::new(AtomicU64::new(0));
+        if properties.track_modifications {
+            batches = with_versions(batches, LAST_MODIFIED_VERSION_COLUMN, version.clone())?;
+        }
+        let dedupe_on_hash = self.write_options.dedupe_on_hash.as_deref();
+        if dedupe_on_hash.is_some() {
+            check_content_hash_left_out(&batches.schema())?;
+        }
+        if matches!(params.mode, WriteMode::Append) {
+            let mut current = self.dataset.get().await?;
+            let schema = ArrowSchema::from(current.schema());
+            check_vector_dims(&schema, &batches.schema())?;
+            let mut added = Vec::new();
+            if self.write_options.allow_null_vectors {
+                added.extend(
+                    vector_column_names(&schema)
+                        .filter(|column| marker_column_of(&schema, column).is_none())
+                        .map(null_vector_field),
+                );
+            }
+            if dedupe_on_hash.is_some() && schema.field_with_name(CONTENT_HASH_COLUMN).is_err() {
+                added.push(content_hash_field());
+            }
+            if !added.is_empty() {
+                current = self.add_null_columns(&added).await?;
+            }
+            batches = self.conform_batches(current, batches).await?;
+        } else {
+            let dictionaries = self.write_options.dictionary_columns(&batches.schema())?;
+            batches = encode_dictionaries(batches, &dictionaries);
+        }
+
+        let (batches, violations) = check_constraints(
+            batches,
+            &properties.constraints,
+            self.write_options.on_constraint_violation,
+        )?;
+        let (mut batches, bad_vectors) = check_vectors(batches, &self.write_options);
+        if !properties.norm_columns.is_empty() {
+            batches = with_norms(batches, &properties.norm_columns);
+        }
+        // The hashes of the table are read under the commit lock, so that
+        // concurrent writers do not both write the same rows.
+        let hashes = Arc::new(Mutex::new(HashSet::new()));
+        let skipped = Arc::new(AtomicUsize::new(0));
+        if let Some(columns) = dedupe_on_hash {
+            batches = with_content_hashes(batches, columns, hashes.clone(), skipped.clone())?;
+        }
+        let batches = split_batches(batches, &self.write_options)?;
+        let schema = batches.schema();
+        let (mut batches, written) = track_progress(batches, params.max_rows_per_file, progress);
+        let reader = &mut batches;
+        let mut existing = HashSet::new();
+        let existing_ref = &mut existing;
+        let dataset = self
+            .dataset
+            .write(self.max_commit_retries, |current| async move {
+                // The ids are read and recorded under the commit lock, so that
+                // concurrent writers assign distinct ids.
+                if auto_id {
+                    self.load_next_auto_id(&next_id).await?;
+                }
+                version.store(current.version().version + 1, Ordering::Relaxed);
+                if let (Some(columns), WriteMode::Append) = (dedupe_on_hash, params.mode) {
+                    *hashes.lock().unwrap() = table_hashes(&current, columns).await?;
+                }
+                if matches!(params.mode, WriteMode::Append) {
+                    existing_ref.extend(current.get_fragments().iter().map(|f| f.id()));
+                }
+                let written = match partition_by {
+                    Some(column) if !matches!(params.mode, WriteMode::Create) => {
+                        let (written, values) = partitions::write_partitioned(
+                            &self.uri,
+                            Some(&current),
+                            reader.as_mut(),
+                            column,
+                            &params,
+                            &self.dataset.read_params(),
+                        )
+                        .await?;
+                        self.record_partitions(&written, column, values).await?;
+                        written
+                    }
+                    _ => {
+                        let read_params = self.dataset.read_params();
+                        write::write_dataset(reader, &self.uri, Some(params), &read_params).await?
+                    }
+                };
+                if auto_id {
+                    self.store_next_auto_id(&next_id).await?;
+                }
+                Ok(written)
+            })
+            .await
+            .map_err(|e| violations.take_error(bad_vectors.take_error(e)))?;
+        if matches!(params.mode, WriteMode::Overwrite) {
+            self.record_metadata(&schema).await?;
+        }
+        let written = *written.lock().unwrap();
+        let rejected = std::mem::take(&mut *violations.rejected.lock().unwrap());
+        Ok(AddReport {
+            version: dataset.version().version,
+            rows: written.rows,
+            bytes: written.bytes,
+            fragments: dataset
+                .get_fragments()
+                .iter()
+                .filter(|f| !existing.contains(&f.id()))
+                .count(),
+            dropped_rows: bad_vectors.dropped.load(Ordering::Relaxed),
+            filled_rows: bad_vectors.filled.load(Ordering::Relaxed),
+            skipped_rows: skipped.load(Ordering::Relaxed),
+            rejected_rows: rejected,
+        })
+    }
+
+    /// `batches` conformed to the schema of the table by column name, after
+    /// adding their new columns to the table with
+    /// [WriteOptions::schema_evolution].
+    async fn conform_batches(
+        &self,
+        mut current: Arc<Dataset>,
+        mut batches: Box<dyn RecordBatchReader>,
+    ) -> Result<Box<dyn RecordBatchReader>> {
+        if self.write_options.drop_unknown_columns {
+            let table = ArrowSchema::from(current.schema());
+            let schema = batches.schema();
+            let known = schema
+                .fields()
+                .iter()
+                .filter(|f| {
+                    table.field_with_name(f.name()).is_ok()
+                        || self.write_options.schema_evolution && f.is_nullable()
+                })
+                .cloned()
+                .collect::<Vec<_>>();
+            if known.len() < schema.fields().len() {
+                let known = ArrowSchema::new_with_metadata(known, schema.metadata().clone());
+                batches = conform(batches, Arc::new(known));
+            }
+        }
+        let delta = schema::schema_delta(&ArrowSchema::from(current.schema()), &batches.schema());
+        if !delta.incompatible.is_empty() {
+            return Err(Error::Schema {
+                message: delta.incompatible.join("; "),
+            });
+        }
+        if !delta.new_nullable.is_empty() {
+            if !self.write_options.schema_evolution {
+                let columns = delta.new_nullable.iter().map(|f| f.name().as_str());
+                return Err(Error::Schema {
+                    message: format!(
+                        "the table has no column '{}', enable schema evolution to add it",
+                        columns.collect::<Vec<_>>().join("', '")
+                    ),
+                });
+            }
+            current = self.add_null_columns(&delta.new_nullable).await?;
+        }
+        let schema = Arc::new(ArrowSchema::from(current.schema()));
+        if schema.fields() == batches.schema().fields() {
+            return Ok(batches);
+        }
+        Ok(conform(batches, schema))
+    }
+
+    /// Append `rows`, for example a `Vec` of structs, see [crate::arrow].
+    ///
+    /// The rows are converted to the types of the table's columns. Columns the
+    /// rows do not fit fail the add with an [Error::Schema] naming each of them.
+    pub async fn add_rows(&self, rows: impl IntoArrow) -> Result<usize> {
+        let schema = self.schema().await?;
+        let batches = rows.into_arrow(Some(schema.clone()))?;
+        let embeddings = self.embeddings();
+        let missing: Vec<String> = schema
+            .fields()
+            .iter()
+            .filter(|f| batches.schema().field_with_name(f.name()).is_err())
+            .filter(|f| !embeddings.iter().any(|e| &e.vector_column == f.name()))
+            .map(|f| format!("column '{}' is missing", f.name()))
+            .collect();
+        if !missing.is_empty() {
+            return Err(Error::Schema {
+                message: missing.join("; "),
+            });
+        }
+        self.add(batches, None).await
+    }
 
-        self.dataset = Arc::new(Dataset::write(&mut batches, &self.uri, Some(params)).await?);
-        Ok(batches.count())
+    /// Append the rows of the Polars DataFrame `df`.
+    ///
+    /// The `List` columns of `df` that are vector columns of this table become
+    /// vectors of the dimensions of the table, see
+    /// [crate::Database::create_table_from_polars].
+    #[cfg(feature = "polars")]
+    pub async fn add_polars(&self, df: &polars::prelude::DataFrame) -> Result<usize> {
+        let schema = self.schema().await?;
+        let vector_columns = schema
+            .fields()
+            .iter()
+            .filter_map(|field| match field.data_type() {
+                DataType::FixedSizeList(_, dims) => Some((field.name().clone(), *dims)),
+                _ => None,
+            })
+            .collect();
+        let batches = crate::io::dataframe::dataframe_to_batches(df, &vector_columns)?;
+        self.add(batches, Some(WriteMode::Append)).await
+    }
+
+    /// Append the vectors of the rows of `matrix` to the column `vector`, with
+    /// `ids` in the column `id` and the columns of `extra`, one row of each
+    /// for every row of the matrix.
+    ///
+    /// The values of the matrix are copied once, see
+    /// [crate::arrow::ndarray_to_fixed_size_list]. Fails before writing
+    /// anything if `ids` or `extra` do not have a row for every vector.
+    #[cfg(feature = "ndarray")]
+    pub async fn add_vectors(
+        &self,
+        ids: &[i64],
+        matrix: ndarray::ArrayView2<'_, f32>,
+        extra: Option<arrow_array::RecordBatch>,
+    ) -> Result<usize> {
+        use arrow_array::{Array, Int64Array, RecordBatch};
+        use arrow_schema::Field;
+
+        let rows = matrix.nrows();
+        let extra_rows = extra.as_ref().map_or(rows, RecordBatch::num_rows);
+        if ids.len() != rows || extra_rows != rows {
+            return Err(Error::InvalidInput {
+                message: format!(
+                    "the matrix has {rows} vectors, got {} ids and {extra_rows} rows of extra columns",
+                    ids.len()
+                ),
+            });
+        }
+        let vectors = crate::arrow::ndarray_to_fixed_size_list(matrix)?;
+        // The columns are as nullable as those of the table, lance fails the
+        // append of columns that differ.
+        let schema = self.schema().await?;
+        let nullable = |name: &str| {
+            schema
+                .field_with_name(name)
+                .map_or(true, |f| f.is_nullable())
+        };
+        let mut fields = vec![
+            Field::new("id", DataType::Int64, nullable("id")),
+            Field::new("vector", vectors.data_type().clone(), nullable("vector")),
+        ];
+        let mut columns: Vec<arrow_array::ArrayRef> =
+            vec![Arc::new(Int64Array::from(ids.to_vec())), Arc::new(vectors)];
+        if let Some(extra) = extra {
+            for (field, column) in extra.schema().fields().iter().zip(extra.columns()) {
+                if field.name() == "id" || field.name() == "vector" {
+                    return Err(Error::InvalidInput {
+                        message: format!(
+                            "the extra columns cannot include the column '{}'",
+                            field.name()
+                        ),
+                    });
+                }
+                fields.push(field.as_ref().clone());
+                columns.push(column.clone());
+            }
+        }
+        let batch = RecordBatch::try_new(Arc::new(ArrowSchema::new(fields)), columns)
+            .map_err(lance::Error::from)?;
+        self.add(batch.into_arrow(None)?, Some(WriteMode::Append))
+            .await
     }
 
     /// Creates a new Query object that can be executed.
     ///
     /// # Arguments
     ///
-    /// * `vector` The vector used for this query.
+    /// * `query` The vector used for this query, or a text to embed with the
+    ///   table's embedding function, see [Query::nearest_to].
     ///
     /// # Returns
     ///
     /// * A [Query] object.
-    pub fn search(&self, query_vector: Float32Array) -> Query {
-        Query::new(self.dataset.clone(), query_vector)
+    pub fn search(&self, query: impl Into<QueryVector>) -> Query {
+        Query::new(self.dataset.clone(), Float32Array::from(Vec::<f32>::new()))
+            .with_table_name(&self.name)
+            .with_embeddings(self.embeddings())
+            .with_slow_query_hook(self.slow_query.clone())
+            .with_scan_params(self.scan_params)
+            .with_max_limit(self.max_limit)
+            .with_query_cache(self.query_cache.clone())
+            .nearest_to(query)
+    }
+
+    /// Run `request`, the search of [NativeTable::search] with its parameters.
+    ///
+    /// Fails with an [Error::InvalidInput] if [SearchRequest::validate] does.
+    pub async fn search_request(
+        &self,
+        request: &SearchRequest,
+    ) -> Result<DatasetRecordBatchStream> {
+        request.execute(self.search(request.vector.clone())).await
+    }
+
+    /// A full text search of the string column `column` for `query`, see
+    /// [FullTextQuery].
+    pub fn full_text_search(&self, column: &str, query: &str) -> FullTextQuery {
+        self.full_text_search_multi(&[(column, 1.0)], query)
+    }
+
+    /// A full text search of the string columns `fields` for `query`, the BM25
+    /// scores of each column multiplied by its boost, see [FullTextQuery].
+    pub fn full_text_search_multi(&self, fields: &[(&str, f32)], query: &str) -> FullTextQuery {
+        let fields = fields.iter().map(|(c, b)| (c.to_string(), *b)).collect();
+        FullTextQuery::new(
+            self.dataset.clone(),
+            fields,
+            query.to_string(),
+            self.scan_params,
+        )
+    }
+
+    /// Prepare `template`, a query of this table, to run it for many vectors,
+    /// see [PreparedQuery].
+    pub async fn prepare(&self, template: Query) -> Result<PreparedQuery> {
+        PreparedQuery::new(template).await
+    }
+
+    /// The schema of the current version of this table, with the schema- and
+    /// field-level metadata of the schemas it was written with, see
+    /// [NativeTable::replace_schema_metadata].
+    pub async fn schema(&self) -> Result<SchemaRef> {
+        let schema = ArrowSchema::from(self.dataset.get().await?.schema());
+        Ok(Arc::new(self.properties().await?.with_metadata(schema)))
     }
 
     /// Returns the number of rows in this Table
     pub async fn count_rows(&self) -> Result<usize> {
-        Ok(self.dataset.count_rows().await?)
+        Ok(self.dataset.get().await?.count_rows().await?)
     }
 
-    /// Merge new data into this table.
-    pub async fn merge(
-        &mut self,
-        mut batches: Box<dyn RecordBatchReader>,
-        left_on: &str,
-        right_on: &str,
-    ) -> Result<()> {
-        let mut dataset = self.dataset.as_ref().clone();
-        dataset.merge(&mut batches, left_on, right_on).await?;
-        self.dataset = Arc::new(dataset);
+    /// Returns the number of rows in this Table matching `filter`, a SQL WHERE
+    /// clause. An invalid filter fails with an [Error::InvalidInput].
+    pub async fn count_rows_filtered(&self, filter: &str) -> Result<usize> {
+        self.scoped(filter).count_rows().await
+    }
+
+    /// The version of this table the handle reads.
+    pub fn version(&self) -> u64 {
+        self.dataset.current().version().version
+    }
+
+    /// The versions of this table, with the times they were committed.
+    pub async fn versions(&self) -> Result<Vec<Version>> {
+        Ok(self.dataset.get().await?.versions().await?)
+    }
+
+    /// A handle reading `version` of this table.
+    ///
+    /// The handle stays at the version, writes to it fail; the table opened
+    /// again reads the latest version. It does not share the query cache of
+    /// this handle, see [Self::with_query_cache].
+    pub async fn checkout(&self, version: u64) -> Result<NativeTable> {
+        Ok(NativeTable {
+            dataset: self.dataset.checkout(version).await?,
+            query_cache: None,
+            ..self.clone()
+        })
+    }
+
+    /// A handle reading the latest version of this table committed at or before
+    /// `timestamp`, see [Self::checkout].
+    ///
+    /// Fails with [Error::NoVersionBefore] if the table was created after it.
+    pub async fn checkout_at(&self, timestamp: DateTime<Utc>) -> Result<NativeTable> {
+        let version = self
+            .versions()
+            .await?
+            .into_iter()
+            .filter(|v| v.timestamp <= timestamp)
+            .map(|v| v.version)
+            .max()
+            .context(NoVersionBeforeSnafu { timestamp })?;
+        self.checkout(version).await
+    }
+
+    /// Commit the data of `version` as the latest version of this table, for
+    /// example to undo the writes after it, which stay in the version list.
+    ///
+    /// The indices are those of `version` too.
+    pub async fn restore(&self, version: u64) -> Result<()> {
+        self.restore_from(version, None).await
+    }
+
+    /// [Self::restore] `version`, if the latest version is still `expected`.
+    ///
+    /// Fails with [Error::CommitConflict] if another writer committed a version
+    /// after `expected`, whose writes the restore would undo.
+    pub(crate) async fn restore_from(&self, version: u64, expected: Option<u64>) -> Result<()> {
+        self.dataset
+            .write(self.max_commit_retries, |latest| async move {
+                let current = latest.version().version;
+                if let Some(expected) = expected.filter(|expected| *expected != current) {
+                    return Err(Error::CommitConflict {
+                        uri: self.uri.clone(),
+                        reason: format!(
+                            "version {current} was committed after version {expected}, restoring version {version} would undo it"
+                        ),
+                    });
+                }
+                self.commit_version(&latest, version).await
+            })
+            .await?;
         Ok(())
     }
 
-    /// Delete rows from the table
-    pub async fn delete(&mut self, predicate: &str) -> Result<()> {
-        let mut dataset = self.dataset.as_ref().clone();
-        dataset.delete(predicate).await?;
-        self.dataset = Arc::new(dataset);
+    /// Commit the fragments, schema and indices of `version` on top of
+    /// `latest`, under the commit lock.
+    ///
+    /// The indices of `latest` are not kept: they can cover fragments that
+    /// `version` does not have, whose ids the next append would reuse.
+    async fn commit_version(&self, latest: &Dataset, version: u64) -> Result<Dataset> {
+        let restored = latest.checkout_version(version).await?;
+        let fragments = maintenance::fragments(&restored);
+        let indices = restored.load_indices().await?;
+        let schema = restored.schema().clone();
+        self.dataset
+            .commit_manifest(latest, indices, |manifest| {
+                manifest.schema = schema;
+                manifest.fragments = Arc::new(fragments);
+            })
+            .await
+    }
+
+    /// The statistics of `column` in the current version of this table.
+    ///
+    /// They are computed by reading the column the first time they are asked
+    /// for, by this method or by a filtered query, and kept until the table
+    /// changes. Filtered queries use them to return no rows without searching
+    /// when the filter cannot match, for example `x > 100` when the largest
+    /// value of `x` is 50.
+    pub async fn column_stats(&self, column: &str) -> Result<ColumnStats> {
+        let dataset = self.dataset.get().await?;
+        self.dataset.column_stats(&dataset, column).await
+    }
+
+    /// The [TableStats] of the current version of this table.
+    ///
+    /// The size of the data files is read from the store, a request for each
+    /// file.
+    pub async fn stats(&self) -> Result<TableStats> {
+        let dataset = self.dataset.get().await?;
+        self.dataset.table_stats(&dataset).await
+    }
+
+    /// Write the rows of this table to the Arrow IPC file at `path`, one batch
+    /// at a time, returning the number of rows written.
+    #[cfg(feature = "ipc")]
+    pub async fn export_ipc(
+        &self,
+        path: impl AsRef<Path>,
+        params: ExportIpcParams,
+    ) -> Result<usize> {
+        use lance::io::RecordBatchStream;
+
+        let dataset = self.dataset.get().await?;
+        let mut scanner = dataset.scan();
+        if let Some(columns) = params.columns.as_ref() {
+            scanner.project(columns)?;
+        }
+        if let Some(filter) = params.filter.as_ref() {
+            let schema = ArrowSchema::from(dataset.schema());
+            scanner.filter(&lance_filter(filter, &schema)?)?;
+        }
+        let mut stream = scanner.try_into_stream().await?;
+        let mut writer =
+            IpcFileWriter::try_new(path.as_ref(), &stream.schema(), params.compression)?;
+        let mut rows = 0;
+        while let Some(batch) = stream.try_next().await? {
+            rows += batch.num_rows();
+            writer.write(&batch)?;
+        }
+        writer.finish()?;
+        Ok(rows)
+    }
+
+    /// Append the rows of the Arrow IPC file at `path`, reading one batch at a time.
+    #[cfg(feature = "ipc")]
+    pub async fn add_ipc(&self, path: impl AsRef<Path>) -> Result<usize> {
+        self.add(read_ipc_file(path.as_ref())?, Some(WriteMode::Append))
+            .await
+    }
+
+    /// Delete rows from the table, see [Self::delete_with_stats].
+    pub async fn delete(&self, predicate: &str) -> Result<()> {
+        self.delete_with_stats(predicate).await?;
         Ok(())
     }
+
+    /// Delete the rows matching `predicate`, returning them with the columns
+    /// of `projection`, or all columns if it is `None`.
+    ///
+    /// The rows are read from the version the delete is committed on, under
+    /// the commit lock, so they are the rows removed. With `max_rows`, a delete
+    /// matching more rows fails with [Error::DeleteLimitExceeded] once they are
+    /// read, without deleting anything.
+    pub async fn delete_returning(
+        &self,
+        predicate: &str,
+        projection: Option<Vec<String>>,
+        max_rows: Option<usize>,
+    ) -> Result<DeleteReport> {
+        let mut batches = Vec::new();
+        let deleted = &mut batches;
+        let dataset = self
+            .dataset
+            .write(self.max_commit_retries, |current| async move {
+                let schema = ArrowSchema::from(current.schema());
+                let predicate = lance_filter(predicate, &schema)?;
+                let mut scanner = current.scan();
+                if let Some(columns) = projection.as_ref() {
+                    scanner.project(columns)?;
+                }
+                scanner.filter(&predicate)?;
+                let mut stream = scanner.try_into_stream().await?;
+                let mut rows = 0;
+                while let Some(batch) = stream.try_next().await? {
+                    rows += batch.num_rows();
+                    if let Some(max_rows) = max_rows {
+                        ensure!(rows <= max_rows, DeleteLimitExceededSnafu { max_rows });
+                    }
+                    deleted.push(batch);
+                }
+                let mut dataset = current.as_ref().clone();
+                dataset.delete(&predicate).await?;
+                Ok(dataset)
+            })
+            .await?;
+        Ok(DeleteReport {
+            version: dataset.version().version,
+            rows: batches.iter().map(RecordBatch::num_rows).sum(),
+            batches,
+        })
+    }
 }
 
-#[cfg(test)]
-mod tests {
-    use std::sync::atomic::{AtomicBool, Ordering};
-    use std::sync::Arc;
+#[async_trait]
+impl TableLike for NativeTable {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn schema(&self) -> Result<SchemaRef> {
+        NativeTable::schema(self).await
+    }
+
+    async fn count_rows(&self) -> Result<usize> {
+        NativeTable::count_rows(self).await
+    }
+
+    async fn count_rows_filtered(&self, filter: &str) -> Result<usize> {
+        NativeTable::count_rows_filtered(self, filter).await
+    }
+
+    /// The lance dataset writer is not `Send`, so the rows are written on a
+    /// blocking thread.
+    async fn add(
+        &self,
+        batches: Box<dyn RecordBatchReader + Send>,
+        write_mode: Option<WriteMode>,
+    ) -> Result<usize> {
+        let table = self.clone();
+        let runtime = tokio::runtime::Handle::current();
+        tokio::task::spawn_blocking(move || {
+            runtime.block_on(NativeTable::add(&table, batches, write_mode))
+        })
+        .await
+        .map_err(|e| Error::Runtime {
+            message: format!("adding rows to the table failed: {e}"),
+        })?
+    }
+
+    async fn create_index(&self, index_builder: &(dyn VectorIndexBuilder + Sync)) -> Result<()> {
+        NativeTable::create_index(self, index_builder).await
+    }
+
+    fn search(&self, query: QueryVector) -> Query {
+        NativeTable::search(self, query)
+    }
+
+    async fn delete(&self, predicate: &str) -> Result<()> {
+        NativeTable::delete(self, predicate).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
 
     use arrow_array::{
-        Array, FixedSizeListArray, Float32Array, Int32Array, RecordBatch, RecordBatchReader,
+        Array, Float32Array, Int32Array, RecordBatch, RecordBatchReader, StringArray,
     };
-    use arrow_data::ArrayDataBuilder;
     use arrow_schema::{DataType, Field, Schema};
+    use datafusion::scalar::ScalarValue;
     use lance::arrow::RecordBatchBuffer;
     use lance::dataset::{Dataset, WriteMode};
     use lance::index::vector::ivf::IvfBuildParams;
     use lance::index::vector::pq::PQBuildParams;
     use lance::io::object_store::{ObjectStoreParams, WrappingObjectStore};
+    use lance::io::RecordBatchStream;
     use rand::Rng;
     use tempfile::tempdir;
 
-    use super::*;
-    use crate::index::vector::IvfPQIndexBuilder;
+    use super::*;
+    use crate::arrow::{vec_to_fixed_size_list, RecordBatchBuilder};
+    use crate::embeddings::tests::MockEmbedding;
+    use crate::index::vector::IvfPQIndexBuilder;
+    use crate::query::{PreparedQuery, QueryExecutor};
+    use crate::testing::TestTableBuilder;
+
+    #[tokio::test]
+    async fn test_open() {
+        let tmp_dir = tempdir().unwrap();
+        let dataset_path = tmp_dir.path().join("test.lance");
+        let uri = tmp_dir.path().to_str().unwrap();
+
+        let mut batches: Box<dyn RecordBatchReader> = Box::new(make_test_batches());
+        Dataset::write(&mut batches, dataset_path.to_str().unwrap(), None)
+            .await
+            .unwrap();
+
+        let table = NativeTable::open(uri, "test").await.unwrap();
+
+        assert_eq!(table.name, "test")
+    }
+
+    #[tokio::test]
+    async fn test_open_not_found() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        let table = NativeTable::open(uri, "test").await;
+        assert!(matches!(table.unwrap_err(), Error::TableNotFound { .. }));
+    }
+
+    #[test]
+    fn test_object_store_path() {
+        use std::path::Path as StdPath;
+        let p = StdPath::new("s3://bucket/path/to/file");
+        let c = p.join("subfile");
+        assert_eq!(c.to_str().unwrap(), "s3://bucket/path/to/file/subfile");
+    }
+
+    #[tokio::test]
+    async fn test_create_already_exists() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+
+        let batches: Box<dyn RecordBatchReader> = Box::new(make_test_batches());
+        let _ = batches.schema().clone();
+        NativeTable::create(uri, "test", batches, None)
+            .await
+            .unwrap();
+
+        let batches: Box<dyn RecordBatchReader> = Box::new(make_test_batches());
+        let result = NativeTable::create(uri, "test", batches, None).await;
+        assert!(matches!(
+            result.unwrap_err(),
+            Error::TableAlreadyExists { .. }
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_add() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+
+        let batches: Box<dyn RecordBatchReader> = Box::new(make_test_batches());
+        let schema = batches.schema().clone();
+        let table = NativeTable::create(uri, "test", batches, None)
+            .await
+            .unwrap();
+        assert_eq!(table.count_rows().await.unwrap(), 10);
+
+        let new_batches: Box<dyn RecordBatchReader> =
+            Box::new(RecordBatchBuffer::new(vec![RecordBatch::try_new(
+                schema,
+                vec![Arc::new(Int32Array::from_iter_values(100..110))],
+            )
+            .unwrap()]));
+
+        table.add(new_batches, None).await.unwrap();
+        assert_eq!(table.count_rows().await.unwrap(), 20);
+        assert_eq!(table.name, "test");
+    }
+
+    #[tokio::test]
+    async fn test_add_split_batches() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+
+        let batches: Box<dyn RecordBatchReader> = Box::new(make_test_batches());
+        let schema = batches.schema().clone();
+        NativeTable::create(uri, "test", batches, None)
+            .await
+            .unwrap();
+        let params = OpenTableParams {
+            write_options: Some(WriteOptions {
+                max_bytes_per_batch: None,
+                max_rows_per_batch: Some(1000),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let table = NativeTable::open_with_params(uri, "test", params)
+            .await
+            .unwrap();
+        let version = table.dataset.get().await.unwrap().version().version;
+
+        let new_batches: Box<dyn RecordBatchReader> =
+            Box::new(RecordBatchBuffer::new(vec![RecordBatch::try_new(
+                schema,
+                vec![Arc::new(Int32Array::from_iter_values(10..10_010))],
+            )
+            .unwrap()]));
+        assert_eq!(table.add(new_batches, None).await.unwrap(), 10_000);
+
+        // The chunks of the batch are committed together, in order.
+        let dataset = table.dataset.get().await.unwrap();
+        assert_eq!(dataset.version().version, version + 1);
+        let batches = dataset
+            .scan()
+            .try_into_stream()
+            .await
+            .unwrap()
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        let values = batches
+            .iter()
+            .flat_map(|b| {
+                let column = b.column(0).as_any().downcast_ref::<Int32Array>().unwrap();
+                column.values().to_vec()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(values, (0..10_010).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn test_add_with_progress() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+
+        let batches: Box<dyn RecordBatchReader> = Box::new(make_test_batches());
+        let schema = batches.schema().clone();
+        let table = NativeTable::create(uri, "test", batches, None)
+            .await
+            .unwrap();
+
+        // lance starts a new fragment after the batch bringing a file to 1M rows.
+        let batches = (0..3)
+            .map(|i| {
+                let ids = Int32Array::from_iter_values(i * 600_000..(i + 1) * 600_000);
+                RecordBatch::try_new(schema.clone(), vec![Arc::new(ids)]).unwrap()
+            })
+            .collect::<Vec<_>>();
+        let calls = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let progress: WriteProgressCallback = {
+            let calls = calls.clone();
+            Arc::new(move |progress| calls.lock().unwrap().push(progress))
+        };
+        let report = table
+            .add_with_progress(
+                Box::new(RecordBatchBuffer::new(batches)),
+                None,
+                Some(progress),
+            )
+            .await
+            .unwrap();
+
+        let calls = calls.lock().unwrap().clone();
+        assert_eq!(
+            calls
+                .iter()
+                .map(|p| (p.rows, p.fragment))
+                .collect::<Vec<_>>(),
+            vec![(600_000, 0), (1_200_000, 0), (1_800_000, 1)]
+        );
+        assert!(calls.windows(2).all(|w| w[0].bytes < w[1].bytes));
+        let expected = AddReport {
+            version: 2,
+            rows: 1_800_000,
+            bytes: calls[2].bytes,
+            fragments: 2,
+            dropped_rows: 0,
+            filled_rows: 0,
+            skipped_rows: 0,
+            rejected_rows: Vec::new(),
+        };
+        assert_eq!(report, expected);
+        assert_eq!(report.bytes, 1_800_000 * 4);
+        assert_eq!(table.count_rows().await.unwrap(), 1_800_010);
+    }
+
+    #[tokio::test]
+    async fn test_dedupe_on_hash() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        let rows = |ids: Vec<i32>| -> Box<dyn RecordBatchReader> {
+            let vectors = ids.iter().map(|i| [*i as f32, 0.0]).collect::<Vec<_>>();
+            let batch = RecordBatchBuilder::new()
+                .column("id", Arc::new(Int32Array::from(ids)))
+                .vector_column("vector", 2, vectors)
+                .build()
+                .unwrap();
+            Box::new(RecordBatchBuffer::new(vec![batch]))
+        };
+        // The rows written before the table deduplicates are hashed on reading.
+        NativeTable::create(uri, "test", rows(vec![0, 1]), None)
+            .await
+            .unwrap();
+        let params = OpenTableParams {
+            write_options: Some(WriteOptions::default().dedupe_on_hash(["id"])),
+            ..Default::default()
+        };
+        let table = NativeTable::open_with_params(uri, "test", params)
+            .await
+            .unwrap();
+        let report = table
+            .add_with_report(rows(vec![1, 2, 3, 3]), None)
+            .await
+            .unwrap();
+        assert_eq!((report.rows, report.skipped_rows), (2, 2));
+        assert_eq!(table.count_rows().await.unwrap(), 4);
+        let schema = table.schema().await.unwrap();
+        assert!(schema.field_with_name(CONTENT_HASH_COLUMN).is_ok());
+
+        for _ in 0..2 {
+            let report = table
+                .add_with_report(rows(vec![0, 1, 2, 3]), None)
+                .await
+                .unwrap();
+            assert_eq!((report.rows, report.skipped_rows), (0, 4));
+            assert_eq!(table.count_rows().await.unwrap(), 4);
+        }
+        let report = table.add_with_report(rows(vec![3, 4]), None).await.unwrap();
+        assert_eq!((report.rows, report.skipped_rows), (1, 1));
+        assert_eq!(table.count_rows().await.unwrap(), 5);
+
+        // The rows appended without deduplicating are hashed on reading too.
+        let plain = NativeTable::open(uri, "test").await.unwrap();
+        plain.add(rows(vec![5]), None).await.unwrap();
+        let report = table.add_with_report(rows(vec![5, 6]), None).await.unwrap();
+        assert_eq!((report.rows, report.skipped_rows), (1, 1));
+
+        // An overwrite only leaves out the duplicates it has.
+        let report = table
+            .add_with_report(rows(vec![0, 0]), Some(WriteMode::Overwrite))
+            .await
+            .unwrap();
+        assert_eq!((report.rows, report.skipped_rows), (1, 1));
+
+        let batch = RecordBatchBuilder::new()
+            .column("id", Arc::new(Int32Array::from(vec![7])))
+            .vector_column("vector", 2, vec![[7.0, 0.0]])
+            .column(
+                CONTENT_HASH_COLUMN,
+                Arc::new(arrow_array::UInt64Array::from(vec![1])),
+            )
+            .build()
+            .unwrap();
+        let batches = Box::new(RecordBatchBuffer::new(vec![batch]));
+        let err = table.add(batches, None).await.unwrap_err();
+        assert!(matches!(err, Error::InvalidInput { .. }), "{err}");
+        let invalid = WriteOptions::default().dedupe_on_hash(Vec::<String>::new());
+        assert!(invalid.validate().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_on_bad_vectors() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        let vectors = |v: Vec<[f32; 2]>| -> Box<dyn RecordBatchReader> {
+            let batch = RecordBatchBuilder::new()
+                .vector_column("vector", 2, v)
+                .build()
+                .unwrap();
+            Box::new(RecordBatchBuffer::new(vec![batch]))
+        };
+        NativeTable::create(uri, "test", vectors(vec![[0.0, 0.0]]), None)
+            .await
+            .unwrap();
+        let open = |policy| async move {
+            let params = OpenTableParams {
+                write_options: Some(WriteOptions::default().on_bad_vectors(policy)),
+                ..Default::default()
+            };
+            NativeTable::open_with_params(uri, "test", params)
+                .await
+                .unwrap()
+        };
+        let bad = || vectors(vec![[1.0, 1.0], [f32::NAN, 1.0]]);
+
+        let table = open(OnBadVectors::Error).await;
+        let Err(Error::InvalidInput { message }) = table.add(bad(), None).await else {
+            panic!("expected an invalid input error");
+        };
+        assert_eq!(
+            message,
+            "the vector of row 1 of the column 'vector' is bad, it has NaN or infinite values"
+        );
+        assert_eq!(table.count_rows().await.unwrap(), 1);
+
+        let table = open(OnBadVectors::Drop).await;
+        let report = table.add_with_report(bad(), None).await.unwrap();
+        let expected = AddReport {
+            rows: 1,
+            dropped_rows: 1,
+            filled_rows: 0,
+            ..report.clone()
+        };
+        assert_eq!(report, expected);
+        assert_eq!(table.count_rows().await.unwrap(), 2);
+
+        let table = open(OnBadVectors::Fill(0.5)).await;
+        let report = table.add_with_report(bad(), None).await.unwrap();
+        let expected = AddReport {
+            rows: 2,
+            dropped_rows: 0,
+            filled_rows: 1,
+            ..report.clone()
+        };
+        assert_eq!(report, expected);
+        assert_eq!(table.count_rows().await.unwrap(), 4);
+
+        let invalid = WriteOptions::default().on_bad_vectors(OnBadVectors::Fill(f32::NAN));
+        assert!(invalid.validate().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_null_vectors() {
+        use arrow_array::types::Float32Type;
+        use arrow_array::FixedSizeListArray;
+        use lance::index::vector::MetricType;
+
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        // Every fourth row has a null vector. Fewer than 8 dimensions, lance's
+        // AVX cosine distance reads 8 values at a time from aligned memory only.
+        let vectors = FixedSizeListArray::from_iter_primitive::<Float32Type, _, _>(
+            (0..512).map(|i| {
+                (i % 4 != 0).then(|| {
+                    (0..6)
+                        .map(|j| Some(((i * 7 + j * 13) % 17 + 1) as f32))
+                        .collect::<Vec<_>>()
+                })
+            }),
+            6,
+        );
+        let batch = RecordBatchBuilder::new()
+            .column("id", Arc::new(Int32Array::from_iter_values(0..512)))
+            .column("vector", Arc::new(vectors))
+            .build()
+            .unwrap();
+        let reader = || -> Box<dyn RecordBatchReader> {
+            Box::new(RecordBatchBuffer::new(vec![batch.clone()]))
+        };
+
+        let Err(Error::InvalidInput { message }) =
+            NativeTable::create(uri, "rejected", reader(), None).await
+        else {
+            panic!("expected an invalid input error");
+        };
+        assert!(message.contains("null"), "{message}");
+
+        let params = OpenTableParams {
+            write_options: Some(WriteOptions::default().allow_null_vectors(true)),
+            ..Default::default()
+        };
+        let table = NativeTable::create_with_cache(uri, "test", reader(), None, params, None, None)
+            .await
+            .unwrap();
+        assert_eq!(table.column_stats("vector").await.unwrap().null_count, 128);
+
+        let search = |metric| {
+            let query = table
+                .search(vec![1.0; 6])
+                .metric_type(Some(metric))
+                .limit(10);
+            async move {
+                let batches: Vec<RecordBatch> =
+                    query.execute().await.unwrap().try_collect().await.unwrap();
+                batches
+                    .iter()
+                    .flat_map(|b| {
+                        b["id"]
+                            .as_any()
+                            .downcast_ref::<Int32Array>()
+                            .unwrap()
+                            .values()
+                            .to_vec()
+                    })
+                    .collect::<Vec<_>>()
+            }
+        };
+        for metric in [MetricType::L2, MetricType::Cosine] {
+            let ids = search(metric).await;
+            assert_eq!(ids.len(), 10, "{metric:?}");
+            assert!(ids.iter().all(|id| id % 4 != 0), "{metric:?} {ids:?}");
+        }
+
+        let index = |metric_type| {
+            IvfPQIndexBuilder::new()
+                .column("vector")
+                .ivf_params(IvfBuildParams::new(4))
+                .pq_params(PQBuildParams {
+                    num_sub_vectors: 2,
+                    metric_type,
+                    ..Default::default()
+                })
+        };
+        for metric_type in [MetricType::L2, MetricType::Cosine] {
+            let Err(Error::InvalidInput { message }) =
+                table.create_index(&index(metric_type)).await
+            else {
+                panic!("expected an invalid input error");
+            };
+            assert_eq!(
+                message,
+                "cannot build an index of the column 'vector', 128 rows have null vectors"
+            );
+        }
+
+        // A vector of zeros is not null.
+        let zeros = RecordBatchBuilder::new()
+            .column("id", Arc::new(Int32Array::from(vec![512])))
+            .vector_column("vector", 6, vec![[0.0; 6]])
+            .build()
+            .unwrap();
+        table
+            .add(Box::new(RecordBatchBuffer::new(vec![zeros])), None)
+            .await
+            .unwrap();
+        assert_eq!(table.column_stats("vector").await.unwrap().null_count, 128);
+        let batches: Vec<RecordBatch> = table
+            .search(vec![0.0; 6])
+            .select(Some(vec!["id".to_string()]))
+            .limit(1)
+            .execute()
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(batches[0]["id"].as_ref(), &Int32Array::from(vec![512]));
+        let marker = null_vector_column("vector");
+        assert!(batches[0].schema().field_with_name(&marker).is_err());
+
+        // The first append allowing null vectors adds the marker column.
+        let vectors = FixedSizeListArray::from_iter_primitive::<Float32Type, _, _>(
+            [Some(vec![Some(1.0), Some(1.0)]), None],
+            2,
+        );
+        let batch = RecordBatchBuilder::new()
+            .column("vector", Arc::new(vectors))
+            .build()
+            .unwrap();
+        let reader = || -> Box<dyn RecordBatchReader> {
+            Box::new(RecordBatchBuffer::new(vec![batch.slice(0, 1)]))
+        };
+        NativeTable::create(uri, "later", reader(), None)
+            .await
+            .unwrap();
+        let params = OpenTableParams {
+            write_options: Some(WriteOptions::default().allow_null_vectors(true)),
+            ..Default::default()
+        };
+        let table = NativeTable::open_with_params(uri, "later", params)
+            .await
+            .unwrap();
+        table
+            .add(Box::new(RecordBatchBuffer::new(vec![batch.clone()])), None)
+            .await
+            .unwrap();
+        assert!(table
+            .schema()
+            .await
+            .unwrap()
+            .field_with_name(&marker)
+            .is_ok());
+        let stats = table.column_stats("vector").await.unwrap();
+        assert_eq!((stats.rows, stats.null_count), (3, 1));
+    }
+
+    #[tokio::test]
+    async fn test_schema_delta_and_evolution() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        let batch = RecordBatchBuilder::new()
+            .column("id", Arc::new(Int32Array::from_iter_values(0..2)))
+            .column("name", Arc::new(StringArray::from(vec!["x", "y"])))
+            .vector_column("vector", 2, vec![[0.0, 0.0], [1.0, 1.0]])
+            .build()
+            .unwrap();
+        let table = NativeTable::create(
+            uri,
+            "test",
+            Box::new(RecordBatchBuffer::new(vec![batch])),
+            None,
+        )
+        .await
+        .unwrap();
+
+        // Narrower ids, without the names.
+        let batch = RecordBatchBuilder::new()
+            .column("id", Arc::new(arrow_array::Int8Array::from(vec![2, 3])))
+            .vector_column("vector", 2, vec![[2.0, 2.0], [3.0, 3.0]])
+            .build()
+            .unwrap();
+        let delta = table.check_compatible(&batch.schema()).await.unwrap();
+        assert_eq!(delta.casts.len(), 1);
+        assert_eq!(delta.missing_nullable, vec!["name"]);
+        assert!(delta.is_compatible(false));
+        let rows = Box::new(RecordBatchBuffer::new(vec![batch]));
+        assert_eq!(table.add(rows, None).await.unwrap(), 2);
+        assert_eq!(table.count_rows().await.unwrap(), 4);
+
+        // A new column fails the add without schema evolution.
+        let tagged = || -> Box<dyn RecordBatchReader> {
+            let batch = RecordBatchBuilder::new()
+                .column("tag", Arc::new(StringArray::from(vec!["a", "b"])))
+                .vector_column("vector", 2, vec![[2.0, 2.0], [3.0, 3.0]])
+                .column("id", Arc::new(Int32Array::from_iter_values(4..6)))
+                .build()
+                .unwrap();
+            Box::new(RecordBatchBuffer::new(vec![batch]))
+        };
+        let delta = table.check_compatible(&tagged().schema()).await.unwrap();
+        assert_eq!(
+            delta.new_nullable,
+            vec![Field::new("tag", DataType::Utf8, true)]
+        );
+        let Err(Error::Schema { message }) = table.add(tagged(), None).await else {
+            panic!("expected a schema error");
+        };
+        assert_eq!(
+            message,
+            "the table has no column 'tag', enable schema evolution to add it"
+        );
+
+        let params = OpenTableParams {
+            write_options: Some(WriteOptions::default().schema_evolution(true)),
+            ..Default::default()
+        };
+        let table = NativeTable::open_with_params(uri, "test", params)
+            .await
+            .unwrap();
+        assert_eq!(table.add(tagged(), None).await.unwrap(), 2);
+        assert_eq!(table.count_rows().await.unwrap(), 6);
+        let schema = table.schema().await.unwrap();
+        assert_eq!(schema.field(3), &Field::new("tag", DataType::Utf8, true));
+        let batches = table
+            .search(vec![0.0, 0.0])
+            .select(Some(vec!["id".to_string(), "tag".to_string()]))
+            .limit(10)
+            .execute()
+            .await
+            .unwrap()
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        let batch =
+            datafusion::arrow::compute::concat_batches(&batches[0].schema(), &batches).unwrap();
+        let ids = batch["id"].as_any().downcast_ref::<Int32Array>().unwrap();
+        let tags = batch["tag"].as_any().downcast_ref::<StringArray>().unwrap();
+        for (id, tag) in ids.iter().zip(tags.iter()) {
+            let expected = match id.unwrap() {
+                4 => Some("a"),
+                5 => Some("b"),
+                _ => None,
+            };
+            assert_eq!(tag, expected);
+        }
+
+        // Incompatible data fails with every reason.
+        let batch = RecordBatchBuilder::new()
+            .column("id", Arc::new(StringArray::from(vec!["x"])))
+            .build()
+            .unwrap();
+        let delta = table.check_compatible(&batch.schema()).await.unwrap();
+        assert!(!delta.is_compatible(true));
+        let rows = Box::new(RecordBatchBuffer::new(vec![batch]));
+        let Err(Error::Schema { message }) = table.add(rows, None).await else {
+            panic!("expected a schema error");
+        };
+        assert_eq!(
+            message,
+            "the column 'id' is Utf8, which cannot be cast to the table's Int32"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_filter_after_column_replaced() {
+        let tmp_dir = tempdir().unwrap();
+        let local = tmp_dir.path().to_str().unwrap();
+        let rows =
+            |ids: std::ops::Range<i32>, tag: Option<&str>| -> Box<dyn RecordBatchReader + Send> {
+                let vectors = ids.clone().map(|i| [i as f32, 0.0]).collect::<Vec<_>>();
+                let mut batch = RecordBatchBuilder::new()
+                    .column("id", Arc::new(Int32Array::from_iter_values(ids.clone())))
+                    .vector_column("vector", 2, vectors);
+                if let Some(tag) = tag {
+                    let tags = StringArray::from(vec![tag; ids.len()]);
+                    batch = batch.column("tag", Arc::new(tags));
+                }
+                Box::new(RecordBatchBuffer::new(vec![batch.build().unwrap()]))
+            };
+        let ids = |batches: Vec<RecordBatch>| {
+            let mut ids = batches
+                .iter()
+                .flat_map(|b| {
+                    let ids = b["id"].as_any().downcast_ref::<Int32Array>().unwrap();
+                    ids.values().to_vec()
+                })
+                .collect::<Vec<_>>();
+            ids.sort();
+            ids
+        };
+        for uri in ["memory://", local] {
+            let db = crate::connect(uri)
+                .write_options(WriteOptions::default().schema_evolution(true))
+                .execute()
+                .await
+                .unwrap();
+            let table = db
+                .create_table("t", rows(0..4, Some("a")))
+                .execute()
+                .await
+                .unwrap();
+            let query = table
+                .search(vec![0.0, 0.0].into())
+                .filter(Some("tag = 'b'".to_string()))
+                .limit(10);
+            let prepared = PreparedQuery::new(query.clone()).await.unwrap();
+            let results = query.execute().await.unwrap().try_collect().await.unwrap();
+            assert_eq!(ids(results), Vec::<i32>::new());
+            let results = prepared.run(vec![0.0, 0.0]).await.unwrap();
+            assert_eq!(ids(results.try_collect().await.unwrap()), Vec::<i32>::new());
+
+            // The column is dropped, and added again with other values.
+            table
+                .add(rows(0..4, None), Some(WriteMode::Overwrite))
+                .await
+                .unwrap();
+            table.add(rows(4..8, Some("b")), None).await.unwrap();
+            let results = query.execute().await.unwrap().try_collect().await.unwrap();
+            assert_eq!(ids(results), vec![4, 5, 6, 7], "{uri}");
+            let results = prepared.run(vec![0.0, 0.0]).await.unwrap();
+            let results = results.try_collect().await.unwrap();
+            assert_eq!(ids(results), vec![4, 5, 6, 7], "{uri}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_add_by_column_name() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        let vectors = vec_to_fixed_size_list(2, [[1.0, 1.0]]).unwrap();
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("id", DataType::Int32, false),
+            Field::new("name", DataType::Utf8, true),
+            Field::new("vector", vectors.data_type().clone(), true),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(Int32Array::from(vec![0])),
+                Arc::new(StringArray::from(vec!["zero"])),
+                Arc::new(vectors),
+            ],
+        )
+        .unwrap();
+        let table = NativeTable::create(
+            uri,
+            "test",
+            Box::new(RecordBatchBuffer::new(vec![batch])),
+            None,
+        )
+        .await
+        .unwrap();
+        // The ids the table requires are not nullable in the added rows.
+        let rows = |batch: RecordBatchBuilder| -> Box<dyn RecordBatchReader> {
+            let batch = batch.build().unwrap();
+            let fields = batch
+                .schema()
+                .fields()
+                .iter()
+                .map(|f| f.as_ref().clone().with_nullable(f.name() != "id"))
+                .collect::<Vec<_>>();
+            let schema = Arc::new(Schema::new(fields));
+            let batch = RecordBatch::try_new(schema, batch.columns().to_vec()).unwrap();
+            Box::new(RecordBatchBuffer::new(vec![batch]))
+        };
+
+        // The columns in another order, and without the nullable names.
+        let reordered = RecordBatchBuilder::new()
+            .vector_column("vector", 2, vec![[2.0, 1.0]])
+            .column("name", Arc::new(StringArray::from(vec!["one"])))
+            .column("id", Arc::new(Int32Array::from(vec![1])));
+        table.add(rows(reordered), None).await.unwrap();
+        let unnamed = RecordBatchBuilder::new()
+            .vector_column("vector", 2, vec![[3.0, 1.0]])
+            .column("id", Arc::new(Int32Array::from(vec![2])));
+        table.add(rows(unnamed), None).await.unwrap();
+        let batches = table
+            .search(vec![1.0, 1.0])
+            .limit(10)
+            .execute()
+            .await
+            .unwrap()
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        let ids = batches[0]["id"].as_any().downcast_ref::<Int32Array>();
+        assert_eq!(ids.unwrap().values().to_vec(), vec![0, 1, 2]);
+        let names = batches[0]["name"].as_any().downcast_ref::<StringArray>();
+        let names = names.unwrap().iter().collect::<Vec<_>>();
+        assert_eq!(names, vec![Some("zero"), Some("one"), None]);
+
+        // The ids are required.
+        let without_ids = RecordBatchBuilder::new().vector_column("vector", 2, vec![[4.0, 1.0]]);
+        let Err(Error::Schema { message }) = table.add(rows(without_ids), None).await else {
+            panic!("expected a schema error");
+        };
+        assert_eq!(message, "the column 'id' is missing");
+
+        // Unknown columns fail the add, unless they are dropped.
+        let tagged = || {
+            RecordBatchBuilder::new()
+                .column("tag", Arc::new(StringArray::from(vec!["a"])))
+                .column("id", Arc::new(Int32Array::from(vec![3])))
+                .vector_column("vector", 2, vec![[4.0, 1.0]])
+        };
+        let Err(Error::Schema { message }) = table.add(rows(tagged()), None).await else {
+            panic!("expected a schema error");
+        };
+        assert!(message.contains("'tag'"), "{message}");
+        let params = OpenTableParams {
+            write_options: Some(WriteOptions::default().drop_unknown_columns(true)),
+            ..Default::default()
+        };
+        let lenient = NativeTable::open_with_params(uri, "test", params)
+            .await
+            .unwrap();
+        assert_eq!(lenient.add(rows(tagged()), None).await.unwrap(), 1);
+        assert_eq!(lenient.schema().await.unwrap().fields(), schema.fields());
+        assert_eq!(lenient.count_rows().await.unwrap(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_column_stats() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        let rows = |ids: std::ops::Range<i32>| -> Box<dyn RecordBatchReader> {
+            let vectors = ids.clone().map(|i| [i as f32, 0.0]).collect::<Vec<_>>();
+            let batch = RecordBatchBuilder::new()
+                .column("id", Arc::new(Int32Array::from_iter_values(ids)))
+                .vector_column("vector", 2, vectors)
+                .build()
+                .unwrap();
+            Box::new(RecordBatchBuffer::new(vec![batch]))
+        };
+        let table = NativeTable::create(uri, "test", rows(0..50), None)
+            .await
+            .unwrap();
+
+        let stats = table.column_stats("id").await.unwrap();
+        let expected = ColumnStats {
+            rows: 50,
+            null_count: 0,
+            min: Some(ScalarValue::Int32(Some(0))),
+            max: Some(ScalarValue::Int32(Some(49))),
+            distinct_estimate: Some(50),
+        };
+        assert_eq!(stats, expected);
+
+        // No row is searched for a filter the statistics rule out.
+        let query = table
+            .search(vec![0.0, 0.0])
+            .filter(Some("id > 100".to_string()));
+        let (batches, metrics) = query.execute_with_metrics().await.unwrap();
+        assert!(batches.is_empty());
+        assert!(metrics.filter_pruned);
+        assert_eq!(metrics.rows_scanned, 0);
+        assert_eq!(metrics.distance_computations, None);
+        let pruned = query.execute().await.unwrap().schema();
+        let query = table
+            .search(vec![49.0, 0.0])
+            .filter(Some("id > 10".to_string()));
+        let (batches, metrics) = query.execute_with_metrics().await.unwrap();
+        assert!(!metrics.filter_pruned);
+        assert_eq!(batches[0].num_rows(), 10);
+        assert_eq!(pruned, batches[0].schema());
+
+        // The statistics of a new version are computed again.
+        table.add(rows(50..200), None).await.unwrap();
+        let stats = table.column_stats("id").await.unwrap();
+        assert_eq!(stats.max, Some(ScalarValue::Int32(Some(199))));
+        let query = table
+            .search(vec![199.0, 0.0])
+            .filter(Some("id > 100".to_string()));
+        let (batches, metrics) = query.execute_with_metrics().await.unwrap();
+        assert!(!metrics.filter_pruned);
+        assert_eq!(batches[0].num_rows(), 10);
+    }
+
+    #[tokio::test]
+    async fn test_table_stats() {
+        let batches = |range: std::ops::Range<i32>| -> Box<dyn RecordBatchReader> {
+            let schema = Arc::new(Schema::new(vec![Field::new("i", DataType::Int32, false)]));
+            let batch =
+                RecordBatch::try_new(schema, vec![Arc::new(Int32Array::from_iter_values(range))])
+                    .unwrap();
+            Box::new(RecordBatchBuffer::new(vec![batch]))
+        };
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        let table = NativeTable::create(uri, "test", batches(0..1000), None)
+            .await
+            .unwrap();
+        let stats = table.stats().await.unwrap();
+        assert_eq!(
+            (stats.rows, stats.fragments, stats.data_files),
+            (1000, 1, 1)
+        );
+        let written = stats.bytes_on_disk.unwrap();
+        // At least the 4 bytes of each value.
+        assert!(written >= 4000, "{written}");
+
+        table.add(batches(1000..3000), None).await.unwrap();
+        table.delete("i < 10").await.unwrap();
+        let stats = table.stats().await.unwrap();
+        assert_eq!(stats.version, table.version());
+        assert_eq!((stats.rows, stats.fragments), (2990, 2));
+        assert!(stats.bytes_on_disk.unwrap() >= written + 8000);
+
+        let memory = NativeTable::create("memory://", "test", batches(0..10), None)
+            .await
+            .unwrap();
+        let stats = memory.stats().await.unwrap();
+        assert_eq!(stats.rows, 10);
+        assert!(stats.bytes_on_disk.unwrap() >= 40);
+    }
+
+    #[tokio::test]
+    async fn test_column_encodings() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        let categories = ["a rather long category name", "another long category name"];
+        let rows = || -> Box<dyn RecordBatchReader> {
+            let batch = RecordBatchBuilder::new()
+                .column("id", Arc::new(Int32Array::from_iter_values(0..20_000)))
+                .column(
+                    "category",
+                    Arc::new(StringArray::from_iter_values(
+                        (0..20_000).map(|i| categories[i % 2]),
+                    )),
+                )
+                .build()
+                .unwrap();
+            Box::new(RecordBatchBuffer::new(vec![batch]))
+        };
+        let create = |name: &'static str, options: WriteOptions| async move {
+            let params = OpenTableParams {
+                write_options: Some(options),
+                ..Default::default()
+            };
+            NativeTable::create_with_cache(uri, name, rows(), None, params, None, None).await
+        };
+
+        let plain = create("plain", WriteOptions::default()).await.unwrap();
+        let options = WriteOptions::default().dictionary_encoding("category", true);
+        let encoded = create("encoded", options).await.unwrap();
+        let plain_bytes = plain.stats().await.unwrap().bytes_on_disk.unwrap();
+        let encoded_bytes = encoded.stats().await.unwrap().bytes_on_disk.unwrap();
+        assert!(
+            encoded_bytes * 2 < plain_bytes,
+            "{encoded_bytes} vs {plain_bytes}"
+        );
+        let dataset = encoded.dataset.get().await.unwrap();
+        let field = ArrowSchema::from(dataset.schema());
+        let field = field.field_with_name("category").unwrap();
+        assert!(matches!(field.data_type(), DataType::Dictionary(_, _)));
+        let batches = dataset
+            .scan()
+            .project(&["category"])
+            .unwrap()
+            .try_into_stream()
+            .await
+            .unwrap()
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        let values = batches
+            .iter()
+            .flat_map(|batch| {
+                let values =
+                    datafusion::arrow::compute::cast(batch.column(0), &DataType::Utf8).unwrap();
+                let values = values.as_any().downcast_ref::<StringArray>().unwrap();
+                values
+                    .iter()
+                    .map(|v| v.unwrap().to_string())
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(values.len(), 20_000);
+        assert!(values
+            .iter()
+            .enumerate()
+            .all(|(i, v)| v == categories[i % 2]));
+
+        // lance cannot append to the dictionary column, overwrites replace it.
+        let Err(Error::Schema { message }) = encoded.add(rows(), None).await else {
+            panic!("expected a schema error");
+        };
+        assert!(message.contains("'category'"), "{message}");
+        encoded
+            .add(rows(), Some(WriteMode::Overwrite))
+            .await
+            .unwrap();
+        assert_eq!(encoded.count_rows().await.unwrap(), 20_000);
+
+        // Compression is not written by lance 0.5, unknown columns are rejected.
+        let options = WriteOptions::default().column_encoding("category", Encoding::Zstd(3));
+        let Err(Error::InvalidInput { message }) = create("zstd", options).await else {
+            panic!("expected an invalid input error");
+        };
+        assert!(message.contains("zstd level 3"), "{message}");
+        let options = WriteOptions::default().dictionary_encoding("body", true);
+        let Err(Error::InvalidInput { message }) = create("unknown", options).await else {
+            panic!("expected an invalid input error");
+        };
+        assert!(message.contains("'body'"), "{message}");
+        let options = WriteOptions::default().dictionary_encoding("id", true);
+        assert!(create("numbers", options).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_dictionary_columns() {
+        use arrow_array::types::Int32Type;
+        use arrow_array::{DictionaryArray, LargeStringArray};
+
+        use crate::query::{col, lit};
+
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        let batch = |ids: std::ops::Range<i32>, categories: Vec<&str>, titles: Vec<&str>| {
+            let categories: DictionaryArray<Int32Type> = categories.into_iter().collect();
+            RecordBatchBuilder::new()
+                .column("id", Arc::new(Int32Array::from_iter_values(ids.clone())))
+                .column("category", Arc::new(categories))
+                .column("title", Arc::new(LargeStringArray::from(titles)))
+                .vector_column("vector", 2, ids.map(|i| [i as f32, 1.0]))
+                .build()
+                .unwrap()
+        };
+        let ids = |query: Query| async move {
+            let batches: Vec<RecordBatch> = query
+                .limit(10)
+                .execute()
+                .await
+                .unwrap()
+                .try_collect()
+                .await
+                .unwrap();
+            let mut ids = batches
+                .iter()
+                .flat_map(|b| {
+                    let ids = b["id"].as_any().downcast_ref::<Int32Array>().unwrap();
+                    ids.values().to_vec()
+                })
+                .collect::<Vec<_>>();
+            ids.sort();
+            ids
+        };
+
+        // A table written with dictionary-encoded columns by lance.
+        // lance stores the null keys of dictionaries as zeros, there are none.
+        let mut reader: Box<dyn RecordBatchReader> = Box::new(RecordBatchBuffer::new(vec![batch(
+            0..4,
+            vec!["a", "b", "a", "c"],
+            vec!["xa", "ya", "xb", "yb"],
+        )]));
+        let path = tmp_dir.path().join("encoded.lance");
+        Dataset::write(&mut reader, path.to_str().unwrap(), None)
+            .await
+            .unwrap();
+        let table = NativeTable::open(uri, "encoded").await.unwrap();
+        let cases = [
+            ("category = 'a'", vec![0, 2]),
+            ("`category` IN ('b', 'd')", vec![1]),
+            ("category IS NOT NULL AND id > 2", vec![3]),
+            ("category LIKE 'a%' AND id > 0", vec![2]),
+            ("title LIKE 'y%'", vec![1, 3]),
+            ("title = 'xb' OR category = 'b'", vec![1, 2]),
+        ];
+        for (filter, expected) in cases {
+            let query = table
+                .search(vec![0.0, 1.0])
+                .filter(Some(filter.to_string()));
+            assert_eq!(ids(query).await, expected, "{filter}");
+        }
+        let query = table
+            .search(vec![0.0, 1.0])
+            .filter_expr(col("category").eq(lit("a")));
+        assert_eq!(ids(query).await, vec![0, 2]);
+        let query = table
+            .search(vec![0.0, 1.0])
+            .filter(Some("category = 'a'".to_string()));
+        assert!(table.prepare(query).await.is_ok());
+
+        // Projections keep the encoding.
+        let batches: Vec<RecordBatch> = table
+            .search(vec![0.0, 1.0])
+            .select(Some(vec!["category".to_string(), "title".to_string()]))
+            .execute()
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+        let schema = table.schema().await.unwrap();
+        assert_eq!(batches[0].schema().field(0), schema.field(1));
+        assert_eq!(batches[0].schema().field(1), schema.field(2));
+
+        let rows = Box::new(RecordBatchBuffer::new(vec![batch(
+            4..5,
+            vec!["a"],
+            vec!["xc"],
+        )]));
+        let err = table.add(rows, None).await.unwrap_err();
+        assert!(
+            matches!(&err, Error::Schema { message } if message.contains("'category'")),
+            "{err}"
+        );
+        table.delete("category = 'a'").await.unwrap();
+        assert_eq!(table.count_rows().await.unwrap(), 2);
+
+        // Tables are created with the values of dictionaries, which can be appended.
+        let rows = Box::new(RecordBatchBuffer::new(vec![batch(
+            0..2,
+            vec!["a", "b"],
+            vec!["xa", "ya"],
+        )]));
+        let table = NativeTable::create(uri, "decoded", rows, None)
+            .await
+            .unwrap();
+        let schema = table.schema().await.unwrap();
+        assert_eq!(schema.field(1).data_type(), &DataType::Utf8);
+        let rows = Box::new(RecordBatchBuffer::new(vec![batch(
+            2..4,
+            vec!["b", "c"],
+            vec!["xb", "yb"],
+        )]));
+        assert_eq!(table.add(rows, None).await.unwrap(), 2);
+        let query = table
+            .search(vec![0.0, 1.0])
+            .filter(Some("category = 'b' AND title LIKE 'x%'".to_string()));
+        assert_eq!(ids(query).await, vec![2]);
+        table.delete("title LIKE 'y%'").await.unwrap();
+        assert_eq!(table.count_rows().await.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_vector_dims_checked() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+
+        let vector_batch = |dims: i32| {
+            RecordBatchBuilder::new()
+                .column("text", Arc::new(StringArray::from(vec!["a", "bb"])))
+                .vector_column("vector", dims, vec![vec![1.0; dims as usize]; 2])
+                .build()
+                .unwrap()
+                .into_arrow(None)
+                .unwrap()
+        };
+        let is_mismatch = |err: Error, expected_dims: usize, got_dims: usize| {
+            matches!(
+                err,
+                Error::EmbeddingDimensionMismatch { ref column, expected, got }
+                    if column == "vector" && expected == expected_dims && got == got_dims
+            )
+        };
+        let table = NativeTable::create(uri, "test", vector_batch(4), None)
+            .await
+            .unwrap();
+
+        let err = table.add(vector_batch(8), None).await.unwrap_err();
+        assert!(is_mismatch(err, 4, 8));
+        assert_eq!(table.count_rows().await.unwrap(), 2);
+        table
+            .add(vector_batch(8), Some(WriteMode::Overwrite))
+            .await
+            .unwrap();
+
+        let err = table
+            .search(Float32Array::from(vec![0.0; 4]))
+            .execute()
+            .await
+            .err()
+            .unwrap();
+        assert!(is_mismatch(err, 8, 4));
+
+        let table = table.with_embeddings(vec![EmbeddingDefinition::new(
+            "text",
+            "vector",
+            Arc::new(MockEmbedding { dims: 2 }),
+        )]);
+        let err = table.search("ab").execute().await.err().unwrap();
+        assert!(is_mismatch(err, 8, 2));
+    }
+
+    #[tokio::test]
+    async fn test_add_overwrite() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+
+        let batches: Box<dyn RecordBatchReader> = Box::new(make_test_batches());
+        let schema = batches.schema().clone();
+        let table = NativeTable::create(uri, "test", batches, None)
+            .await
+            .unwrap();
+        assert_eq!(table.count_rows().await.unwrap(), 10);
+
+        let new_batches: Box<dyn RecordBatchReader> =
+            Box::new(RecordBatchBuffer::new(vec![RecordBatch::try_new(
+                schema,
+                vec![Arc::new(Int32Array::from_iter_values(100..110))],
+            )
+            .unwrap()]));
+
+        table
+            .add(new_batches, Some(WriteMode::Overwrite))
+            .await
+            .unwrap();
+        assert_eq!(table.count_rows().await.unwrap(), 10);
+        assert_eq!(table.name, "test");
+    }
+
+    #[tokio::test]
+    async fn test_delete_returning() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        let names = (0..20).map(|i| format!("item {i}")).collect::<Vec<_>>();
+        let batch = RecordBatchBuilder::new()
+            .column("id", Arc::new(Int32Array::from_iter_values(0..20)))
+            .column("name", Arc::new(StringArray::from(names)))
+            .vector_column("vector", 2, (0..20).map(|i| [i as f32, 1.0]))
+            .build()
+            .unwrap();
+        let batches: Box<dyn RecordBatchReader> = Box::new(RecordBatchBuffer::new(vec![batch]));
+        let table = NativeTable::create(uri, "test", batches, None)
+            .await
+            .unwrap();
+
+        let predicate = "id % 3 = 0 OR name = 'item 7'";
+        let projection = vec!["name".to_string(), "id".to_string()];
+        let dataset = table.dataset.get().await.unwrap();
+        let mut scanner = dataset.scan();
+        scanner.project(&projection).unwrap();
+        scanner.filter(predicate).unwrap();
+        let expected: Vec<RecordBatch> = scanner
+            .try_into_stream()
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+
+        let report = table
+            .delete_returning(predicate, Some(projection), None)
+            .await
+            .unwrap();
+        assert_eq!((report.version, report.rows), (2, 8));
+        assert_eq!(report.batches, expected);
+        assert_eq!(table.version(), 2);
+        assert_eq!(table.count_rows().await.unwrap(), 12);
+
+        // Too many rows, nothing is deleted.
+        let err = table
+            .delete_returning("id < 10", None, Some(3))
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(err, Error::DeleteLimitExceeded { max_rows: 3 }),
+            "{err}"
+        );
+        assert_eq!(table.version(), 2);
+        let report = table
+            .delete_returning("id >= 18", None, Some(3))
+            .await
+            .unwrap();
+        assert_eq!(report.rows, 1);
+        assert_eq!(report.batches[0].num_columns(), 3);
+        assert_eq!(table.count_rows().await.unwrap(), 11);
+    }
+
+    #[tokio::test]
+    async fn test_checkout_at() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+
+        let table = NativeTable::create(uri, "test", Box::new(make_test_batches()), None)
+            .await
+            .unwrap();
+        for _ in 0..2 {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            table
+                .add(Box::new(make_test_batches()), None)
+                .await
+                .unwrap();
+        }
+        let versions = table.versions().await.unwrap();
+        let times = versions.iter().map(|v| v.timestamp).collect::<Vec<_>>();
+        assert_eq!(
+            versions.iter().map(|v| v.version).collect::<Vec<_>>(),
+            vec![1, 2, 3]
+        );
+
+        let nanosecond = chrono::Duration::nanoseconds(1);
+        let between = times[1] + (times[2] - times[1]) / 2;
+        let cases = [
+            (times[0], 1),
+            (times[1] - nanosecond, 1),
+            (times[1], 2),
+            (between, 2),
+            (times[2] - nanosecond, 2),
+            (Utc::now(), 3),
+        ];
+        for (timestamp, version) in cases {
+            let checkout = table.checkout_at(timestamp).await.unwrap();
+            assert_eq!(checkout.version(), version, "{timestamp}");
+            assert_eq!(checkout.count_rows().await.unwrap(), 10 * version as usize);
+        }
+
+        let timestamp = times[0] - nanosecond;
+        let err = table.checkout_at(timestamp).await.unwrap_err();
+        assert!(
+            matches!(err, Error::NoVersionBefore { timestamp: t } if t == timestamp),
+            "{err}"
+        );
+
+        // A checkout stays at its version and cannot be written.
+        let checkout = table.checkout_at(between).await.unwrap();
+        table
+            .add(Box::new(make_test_batches()), None)
+            .await
+            .unwrap();
+        assert_eq!(checkout.count_rows().await.unwrap(), 20);
+        let rows = Box::new(make_test_batches());
+        assert!(matches!(
+            checkout.add(rows, None).await,
+            Err(Error::InvalidInput { .. })
+        ));
+        assert_eq!(table.count_rows().await.unwrap(), 40);
+    }
+
+    #[tokio::test]
+    async fn test_add_rows() {
+        #[derive(serde::Serialize)]
+        struct Doc {
+            text: String,
+            score: Option<f32>,
+            // Nullable, as the vectors computed by embedding functions are.
+            vector: Option<Vec<f32>>,
+        }
+        #[derive(serde::Serialize)]
+        struct Text {
+            text: String,
+        }
+
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        let docs = vec![
+            Doc {
+                text: "a".to_string(),
+                score: Some(1.0),
+                vector: Some(vec![0.0; 2]),
+            },
+            Doc {
+                text: "b".to_string(),
+                score: None,
+                vector: Some(vec![1.0; 2]),
+            },
+        ];
+        let table = NativeTable::create(uri, "test", docs.into_arrow(None).unwrap(), None)
+            .await
+            .unwrap();
+
+        table
+            .add_rows(vec![Doc {
+                text: "c".to_string(),
+                score: None,
+                vector: Some(vec![2.0; 2]),
+            }])
+            .await
+            .unwrap();
+        assert_eq!(table.count_rows().await.unwrap(), 3);
+
+        let err = table
+            .add_rows(vec![Text {
+                text: "d".to_string(),
+            }])
+            .await
+            .unwrap_err();
+        let Error::Schema { message } = err else {
+            panic!("unexpected error {err}");
+        };
+        assert_eq!(
+            message,
+            "column 'score' is missing; column 'vector' is missing"
+        );
+
+        let table = table.with_embeddings(vec![EmbeddingDefinition::new(
+            "text",
+            "vector",
+            Arc::new(MockEmbedding { dims: 2 }),
+        )]);
+        #[derive(serde::Serialize)]
+        struct Scored {
+            text: String,
+            score: Option<f32>,
+        }
+        table
+            .add_rows(vec![Scored {
+                text: "d".to_string(),
+                score: Some(2.0),
+            }])
+            .await
+            .unwrap();
+        assert_eq!(table.count_rows().await.unwrap(), 4);
+    }
 
+    #[cfg(feature = "ndarray")]
     #[tokio::test]
-    async fn test_open() {
+    async fn test_ndarray_vectors() {
+        use arrow_array::Int64Array;
+        use ndarray::{s, Array2};
+
         let tmp_dir = tempdir().unwrap();
-        let dataset_path = tmp_dir.path().join("test.lance");
         let uri = tmp_dir.path().to_str().unwrap();
-
-        let mut batches: Box<dyn RecordBatchReader> = Box::new(make_test_batches());
-        Dataset::write(&mut batches, dataset_path.to_str().unwrap(), None)
+        let mut rng = rand::thread_rng();
+        let matrix = Array2::from_shape_fn((64, 8), |_| rng.gen::<f32>());
+        let rows: Vec<Vec<f32>> = matrix.rows().into_iter().map(|r| r.to_vec()).collect();
+        let ids = (0..64).collect::<Vec<i64>>();
+        let batch = RecordBatchBuilder::new()
+            .column("id", Arc::new(Int64Array::from(ids.clone())))
+            .vector_column("vector", 8, rows.clone())
+            .build()
+            .unwrap();
+        let from_vecs = NativeTable::create(uri, "vecs", batch.into_arrow(None).unwrap(), None)
             .await
             .unwrap();
 
-        let table = Table::open(uri, "test").await.unwrap();
+        let first = RecordBatchBuilder::new()
+            .column("id", Arc::new(Int64Array::from(ids[..32].to_vec())))
+            .column(
+                "vector",
+                Arc::new(
+                    crate::arrow::ndarray_to_fixed_size_list(matrix.slice(s![..32, ..])).unwrap(),
+                ),
+            )
+            .build()
+            .unwrap();
+        let from_matrix = NativeTable::create(uri, "matrix", first.into_arrow(None).unwrap(), None)
+            .await
+            .unwrap();
+        // A matrix in column-major order.
+        let rest = matrix.slice(s![32.., ..]).t().to_owned();
+        let added = from_matrix
+            .add_vectors(&ids[32..], rest.t(), None)
+            .await
+            .unwrap();
+        assert_eq!(added, 32);
+
+        let nearest = |query: Query| async move {
+            let batches = query
+                .execute()
+                .await
+                .unwrap()
+                .try_collect::<Vec<_>>()
+                .await
+                .unwrap();
+            batches
+                .iter()
+                .flat_map(|b| {
+                    let ids = b.column_by_name("id").unwrap();
+                    let ids = ids.as_any().downcast_ref::<Int64Array>().unwrap();
+                    ids.values().to_vec()
+                })
+                .collect::<Vec<_>>()
+        };
+        for row in [3, 40] {
+            let expected = nearest(from_vecs.search(rows[row].clone()).limit(5)).await;
+            assert_eq!(expected[0], row as i64);
+            let query = from_matrix.search(matrix.row(row)).limit(5);
+            assert_eq!(nearest(query).await, expected);
+        }
 
-        assert_eq!(table.name, "test")
+        // Mismatched shapes fail before anything is written.
+        let err = from_matrix
+            .add_vectors(&ids[..3], matrix.slice(s![..2, ..]), None)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::InvalidInput { .. }), "{err}");
+        let extra = RecordBatchBuilder::new()
+            .column("tag", Arc::new(Int64Array::from(vec![1])))
+            .build()
+            .unwrap();
+        let err = from_matrix
+            .add_vectors(&ids[..2], matrix.slice(s![..2, ..]), Some(extra))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::InvalidInput { .. }), "{err}");
+        assert_eq!(from_matrix.count_rows().await.unwrap(), 64);
     }
 
     #[tokio::test]
-    async fn test_open_not_found() {
+    async fn test_concurrent_add() {
         let tmp_dir = tempdir().unwrap();
-        let uri = tmp_dir.path().to_str().unwrap();
-        let table = Table::open(uri, "test").await;
-        assert!(matches!(table.unwrap_err(), Error::TableNotFound { .. }));
-    }
+        let uri = tmp_dir.path().to_str().unwrap().to_string();
 
-    #[test]
-    fn test_object_store_path() {
-        use std::path::Path as StdPath;
-        let p = StdPath::new("s3://bucket/path/to/file");
-        let c = p.join("subfile");
-        assert_eq!(c.to_str().unwrap(), "s3://bucket/path/to/file/subfile");
+        let batches: Box<dyn RecordBatchReader> = Box::new(make_test_batches());
+        NativeTable::create(&uri, "test", batches, None)
+            .await
+            .unwrap();
+
+        // Each writer runs on its own thread and runtime, like separate
+        // processes, so that the tables are opened while others commit.
+        let writers = (0..8)
+            .map(|_| {
+                let uri = uri.clone();
+                std::thread::spawn(move || {
+                    let runtime = tokio::runtime::Builder::new_current_thread()
+                        .enable_all()
+                        .build()
+                        .unwrap();
+                    runtime.block_on(async {
+                        let table = NativeTable::open(&uri, "test").await.unwrap();
+                        let batches: Box<dyn RecordBatchReader> = Box::new(make_test_batches());
+                        table.add(batches, None).await.unwrap();
+                    })
+                })
+            })
+            .collect::<Vec<_>>();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        let table = NativeTable::open(&uri, "test").await.unwrap();
+        assert_eq!(table.count_rows().await.unwrap(), 90);
     }
 
-    #[tokio::test]
-    async fn test_create_already_exists() {
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_read_your_writes() {
         let tmp_dir = tempdir().unwrap();
         let uri = tmp_dir.path().to_str().unwrap();
-
         let batches: Box<dyn RecordBatchReader> = Box::new(make_test_batches());
-        let _ = batches.schema().clone();
-        Table::create(&uri, "test", batches, None).await.unwrap();
+        let table = NativeTable::create(uri, "test", batches, None)
+            .await
+            .unwrap();
+        let clone = table.clone();
+        let before = table.dataset.current();
 
-        let batches: Box<dyn RecordBatchReader> = Box::new(make_test_batches());
-        let result = Table::create(&uri, "test", batches, None).await;
-        assert!(matches!(
-            result.unwrap_err(),
-            Error::TableAlreadyExists { .. }
-        ));
+        table
+            .add(Box::new(make_test_batches()), None)
+            .await
+            .unwrap();
+        assert_eq!(table.count_rows().await.unwrap(), 20);
+        assert_eq!(clone.count_rows().await.unwrap(), 20);
+        // A reload that read the version before the write does not undo it.
+        table.dataset.set(before.as_ref().clone());
+        assert_eq!(clone.count_rows().await.unwrap(), 20);
+
+        // Clones writing on other threads read their own writes, and those
+        // of the others.
+        let writers = (0..4)
+            .map(|w| {
+                let table = table.clone();
+                let runtime = tokio::runtime::Handle::current();
+                // lance write futures are not Send, so each writer blocks its own thread.
+                std::thread::spawn(move || {
+                    runtime.block_on(async move {
+                        let rows = |table: NativeTable| async move {
+                            let dataset = table.dataset.get().await.unwrap();
+                            let mut scanner = dataset.scan();
+                            scanner.filter(&format!("i = {}", 100 + w)).unwrap();
+                            let batches = scanner.try_into_stream().await.unwrap();
+                            let batches = batches.try_collect::<Vec<_>>().await.unwrap();
+                            batches.iter().map(|b| b.num_rows()).sum::<usize>()
+                        };
+                        let schema =
+                            Arc::new(Schema::new(vec![Field::new("i", DataType::Int32, false)]));
+                        let ids = Int32Array::from(vec![100 + w; 5]);
+                        let batch = RecordBatch::try_new(schema, vec![Arc::new(ids)]).unwrap();
+                        let batches = Box::new(RecordBatchBuffer::new(vec![batch]));
+                        table.add(batches, None).await.unwrap();
+                        assert_eq!(rows(table.clone()).await, 5);
+                        table.delete(&format!("i = {}", 100 + w)).await.unwrap();
+                        assert_eq!(rows(table).await, 0);
+                    })
+                })
+            })
+            .collect::<Vec<_>>();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        assert_eq!(clone.count_rows().await.unwrap(), 20);
+        let version = clone.dataset.current().version().version;
+        // The create, the first add, and an add and a delete by each writer.
+        assert_eq!(version, 10);
     }
 
     #[tokio::test]
-    async fn test_add() {
+    async fn test_schema_change_conflict() {
         let tmp_dir = tempdir().unwrap();
         let uri = tmp_dir.path().to_str().unwrap();
 
         let batches: Box<dyn RecordBatchReader> = Box::new(make_test_batches());
-        let schema = batches.schema().clone();
-        let mut table = Table::create(&uri, "test", batches, None).await.unwrap();
-        assert_eq!(table.count_rows().await.unwrap(), 10);
+        let first = NativeTable::create(uri, "test", batches, None)
+            .await
+            .unwrap();
+        let second = NativeTable::open(uri, "test").await.unwrap();
 
-        let new_batches: Box<dyn RecordBatchReader> =
-            Box::new(RecordBatchBuffer::new(vec![RecordBatch::try_new(
-                schema,
-                vec![Arc::new(Int32Array::from_iter_values(100..110))],
-            )
-            .unwrap()]));
+        first
+            .merge(make_merge_batches("a"), "i", "i")
+            .await
+            .unwrap();
+        let result = second.merge(make_merge_batches("b"), "i", "i").await;
+        assert!(matches!(result, Err(Error::CommitConflict { .. })));
 
-        table.add(new_batches, None).await.unwrap();
-        assert_eq!(table.count_rows().await.unwrap(), 20);
-        assert_eq!(table.name, "test");
+        // The conflict leaves the table at the latest version, so a retry succeeds.
+        second
+            .merge(make_merge_batches("b"), "i", "i")
+            .await
+            .unwrap();
+        assert_eq!(second.dataset.current().schema().fields.len(), 3);
     }
 
     #[tokio::test]
-    async fn test_add_overwrite() {
-        let tmp_dir = tempdir().unwrap();
-        let uri = tmp_dir.path().to_str().unwrap();
+    async fn test_merge() {
+        use arrow_array::{Array, FixedSizeListArray};
+        use datafusion::arrow::compute::concat_batches;
 
-        let batches: Box<dyn RecordBatchReader> = Box::new(make_test_batches());
-        let schema = batches.schema().clone();
-        let mut table = Table::create(uri, "test", batches, None).await.unwrap();
-        assert_eq!(table.count_rows().await.unwrap(), 10);
+        use crate::io::bad_vectors::mark_null_vectors;
 
-        let new_batches: Box<dyn RecordBatchReader> =
-            Box::new(RecordBatchBuffer::new(vec![RecordBatch::try_new(
-                schema,
-                vec![Arc::new(Int32Array::from_iter_values(100..110))],
-            )
-            .unwrap()]));
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        let titles = (0..300).map(|i| format!("doc {i}")).collect::<Vec<_>>();
+        let batch = RecordBatchBuilder::new()
+            .column("id", Arc::new(Int32Array::from_iter_values(0..300)))
+            .column("title", Arc::new(StringArray::from(titles)))
+            .build()
+            .unwrap();
+        let batches: Box<dyn RecordBatchReader> = Box::new(RecordBatchBuffer::new(vec![batch]));
+        let table = NativeTable::create(uri, "docs", batches, None)
+            .await
+            .unwrap();
 
+        // Embeddings of all but the last 10 rows, in another order.
+        let mut rng = rand::thread_rng();
+        let ids = (0..290).rev().collect::<Vec<i32>>();
+        let vectors = ids
+            .iter()
+            .map(|_| [(); 4].map(|_| rng.gen_range(0.1..1.0)))
+            .collect::<Vec<[f32; 4]>>();
+        let embeddings = |ids: Vec<i32>, vectors: Vec<[f32; 4]>| -> Box<dyn RecordBatchReader> {
+            let batch = RecordBatchBuilder::new()
+                .column("doc_id", Arc::new(Int32Array::from(ids)))
+                .vector_column("vector", 4, vectors)
+                .build()
+                .unwrap();
+            Box::new(RecordBatchBuffer::new(vec![batch]))
+        };
         table
-            .add(new_batches, Some(WriteMode::Overwrite))
+            .merge(embeddings(ids.clone(), vectors.clone()), "id", "doc_id")
             .await
             .unwrap();
-        assert_eq!(table.count_rows().await.unwrap(), 10);
-        assert_eq!(table.name, "test");
+        let schema = table.schema().await.unwrap();
+        let names = schema
+            .fields()
+            .iter()
+            .map(|f| f.name().as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["id", "title", "vector", "_vector_is_null"]);
+        assert_eq!(table.count_rows().await.unwrap(), 300);
+
+        let dataset = table.dataset.get().await.unwrap();
+        let mut scanner = dataset.scan();
+        scanner
+            .project(&["id", "vector", "_vector_is_null"])
+            .unwrap();
+        let batches: Vec<RecordBatch> = scanner
+            .try_into_stream()
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+        let batch = concat_batches(&batches[0].schema(), &batches).unwrap();
+        let batch = mark_null_vectors(&batch, "vector", false).unwrap();
+        let merged = batch["vector"]
+            .as_any()
+            .downcast_ref::<FixedSizeListArray>()
+            .unwrap();
+        for row in 0..300 {
+            let unmatched = row >= 290;
+            assert_eq!(merged.is_null(row), unmatched, "{row}");
+        }
+        let vector = merged.value(7);
+        let vector = vector.as_any().downcast_ref::<Float32Array>().unwrap();
+        assert_eq!(vector.values(), &vectors[289 - 7]);
+
+        // The unmatched rows have no vector to index.
+        let builder = IvfPQIndexBuilder::new()
+            .column("vector")
+            .ivf_params(IvfBuildParams::new(2))
+            .pq_params(PQBuildParams {
+                num_sub_vectors: 2,
+                ..Default::default()
+            });
+        let err = table.create_index(&builder).await.unwrap_err();
+        assert!(matches!(err, Error::InvalidInput { .. }), "{err}");
+        let found: Vec<RecordBatch> = table
+            .search(vectors[0].to_vec())
+            .limit(1)
+            .execute()
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+        let found = found[0]["id"]
+            .as_any()
+            .downcast_ref::<Int32Array>()
+            .unwrap();
+        assert_eq!(found.value(0), 289);
+
+        // The vectors are in the table.
+        let err = table
+            .merge(embeddings(ids, vectors), "id", "doc_id")
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::InvalidInput { .. }), "{err}");
+        let err = table
+            .merge(make_merge_batches("extra"), "id", "missing")
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::InvalidInput { .. }), "{err}");
+        let duplicated = RecordBatchBuilder::new()
+            .column("doc_id", Arc::new(Int32Array::from(vec![1, 2, 1, 3, 2, 1])))
+            .column("score", Arc::new(Int32Array::from_iter_values(0..6)))
+            .build()
+            .unwrap();
+        let err = table
+            .merge(
+                Box::new(RecordBatchBuffer::new(vec![duplicated])),
+                "id",
+                "doc_id",
+            )
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(&err, Error::DuplicateKeys { column, count: 2, .. } if column == "doc_id"),
+            "{err}"
+        );
+        assert_eq!(table.schema().await.unwrap().fields().len(), 4);
+    }
+
+    fn make_merge_batches(column: &str) -> Box<dyn RecordBatchReader> {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("i", DataType::Int32, false),
+            Field::new(column, DataType::Int32, false),
+        ]));
+        Box::new(RecordBatchBuffer::new(vec![RecordBatch::try_new(
+            schema,
+            vec![
+                Arc::new(Int32Array::from_iter_values(0..10)),
+                Arc::new(Int32Array::from_iter_values(10..20)),
+            ],
+        )
+        .unwrap()]))
     }
 
     #[tokio::test]
@@ -353,7 +3322,7 @@ mod tests {
             .await
             .unwrap();
 
-        let table = Table::open(uri, "test").await.unwrap();
+        let table = NativeTable::open(uri, "test").await.unwrap();
 
         let vector = Float32Array::from_iter_values([0.1, 0.2]);
         let query = table.search(vector.clone());
@@ -377,10 +3346,112 @@ mod tests {
             original: Arc<dyn object_store::ObjectStore>,
         ) -> Arc<dyn object_store::ObjectStore> {
             self.called.store(true, Ordering::Relaxed);
-            return original;
+            original
         }
     }
 
+    #[tokio::test]
+    async fn test_query_cache() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        let batches = |values: std::ops::Range<i32>| {
+            RecordBatchBuilder::new()
+                .column("i", Arc::new(Int32Array::from_iter_values(values.clone())))
+                .vector_column("vector", 2, values.map(|i| vec![i as f32, 0.0]))
+                .build()
+                .unwrap()
+                .into_arrow(None)
+                .unwrap()
+        };
+        let table = NativeTable::create(uri, "test", batches(0..10), None)
+            .await
+            .unwrap()
+            .with_query_cache(CacheConfig::default());
+        let query = |filter: &str| {
+            table
+                .search(vec![4.0, 0.0])
+                .filter(Some(filter.to_string()))
+                .limit(3)
+        };
+        let rows = |batches: Vec<RecordBatch>| {
+            batches
+                .iter()
+                .flat_map(|b| {
+                    let column = b.column_by_name("i").unwrap();
+                    let column = column.as_any().downcast_ref::<Int32Array>().unwrap();
+                    column.values().to_vec()
+                })
+                .collect::<Vec<_>>()
+        };
+        let execute = |query: Query| async move {
+            rows(query.execute().await.unwrap().try_collect().await.unwrap())
+        };
+
+        let first = execute(query("i < 8")).await;
+        assert_eq!(first, vec![4, 3, 5]);
+        assert_eq!(execute(query("i < 8")).await, first);
+        let stats = table.query_cache_stats().unwrap();
+        assert_eq!((stats.hits, stats.misses, stats.entries), (1, 1, 1));
+
+        // Another filter is another query.
+        assert_eq!(execute(query("i > 3")).await, vec![4, 5]);
+        assert_eq!(table.query_cache_stats().unwrap().misses, 2);
+        // As are queries of the same vector that exclude or keep other rows.
+        let query_row = |exclude| {
+            query("i < 8")
+                .nearest_to_row("i", 4)
+                .exclude_query_row(exclude)
+        };
+        assert_eq!(execute(query_row(false)).await, first);
+        assert_eq!(table.query_cache_stats().unwrap().misses, 3);
+        let excluded = execute(query_row(true)).await;
+        assert_eq!(excluded.len(), 3);
+        assert!(!excluded.contains(&4));
+        assert_eq!(table.query_cache_stats().unwrap().misses, 4);
+        assert_eq!(execute(query("i < 8").allow_nonfinite(true)).await, first);
+        assert_eq!(table.query_cache_stats().unwrap().misses, 5);
+
+        // Adding rows makes a new version, so the query runs again.
+        table.add(batches(4..5), None).await.unwrap();
+        assert_eq!(execute(query("i < 8")).await[..2], [4, 4]);
+        let stats = table.query_cache_stats().unwrap();
+        assert_eq!((stats.hits, stats.misses, stats.entries), (1, 6, 1));
+
+        // A checkout reads its own version, not the results of the latest.
+        let reopened = NativeTable::open(uri, "test")
+            .await
+            .unwrap()
+            .with_query_cache(CacheConfig::default());
+        let search = |table: &NativeTable| {
+            table
+                .search(vec![4.0, 0.0])
+                .filter(Some("i < 8".to_string()))
+                .limit(3)
+        };
+        assert_eq!(execute(search(&reopened)).await[..2], [4, 4]);
+        let checkout = reopened.checkout(1).await.unwrap();
+        assert_eq!(execute(search(&checkout)).await, first);
+        assert_eq!(execute(search(&reopened)).await[..2], [4, 4]);
+        let stats = reopened.query_cache_stats().unwrap();
+        assert_eq!((stats.hits, stats.misses, stats.entries), (1, 1, 1));
+
+        // The adds to an in-memory table are new versions too.
+        let table = NativeTable::create("memory://", "test", batches(0..10), None)
+            .await
+            .unwrap()
+            .with_query_cache(CacheConfig::default());
+        let query = table
+            .search(vec![4.0, 0.0])
+            .filter(Some("i < 8".to_string()))
+            .limit(3);
+        assert_eq!(execute(query.clone()).await, first);
+        assert_eq!(execute(query.clone()).await, first);
+        table.add(batches(4..5), None).await.unwrap();
+        assert_eq!(execute(query).await[..2], [4, 4]);
+        let stats = table.query_cache_stats().unwrap();
+        assert_eq!((stats.hits, stats.misses, stats.entries), (1, 2, 1));
+    }
+
     #[tokio::test]
     async fn test_open_table_options() {
         let tmp_dir = tempdir().unwrap();
@@ -401,10 +3472,13 @@ mod tests {
                 }),
                 ..ReadParams::default()
             },
+            ..OpenTableParams::default()
         };
 
         assert!(!wrapper.called());
-        let _ = Table::open_with_params(uri, "test", param).await.unwrap();
+        let _ = NativeTable::open_with_params(uri, "test", param)
+            .await
+            .unwrap();
         assert!(wrapper.called());
     }
 
@@ -417,71 +3491,446 @@ mod tests {
         .unwrap()])
     }
 
-    #[tokio::test]
-    async fn test_create_index() {
-        use arrow_array::RecordBatch;
-        use arrow_schema::{DataType, Field, Schema as ArrowSchema};
-        use rand;
-        use std::iter::repeat_with;
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_table_ref_in_spawned_task() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        let builder = TestTableBuilder::new(100).dims(4);
+        let table: TableRef = Arc::new(builder.create(uri, "test").await.unwrap());
+
+        let query = builder.query_vector(0);
+        let spawned = table.clone();
+        let (added, results) = tokio::spawn(async move {
+            let added = spawned.add(builder.reader()?, None).await?;
+            let results = spawned
+                .search(Float32Array::from(query).into())
+                .limit(5)
+                .execute()
+                .await?
+                .try_collect::<Vec<_>>()
+                .await?;
+            Result::Ok((added, results))
+        })
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(added, 100);
+        assert_eq!(results.iter().map(|b| b.num_rows()).sum::<usize>(), 5);
+        assert_eq!(table.count_rows().await.unwrap(), 200);
+    }
+
+    /// A table answering from memory, as an application would mock one.
+    #[derive(Debug)]
+    struct MockTable {
+        batch: RecordBatch,
+        deleted: std::sync::Mutex<Vec<String>>,
+    }
+
+    impl std::fmt::Display for MockTable {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "MockTable")
+        }
+    }
+
+    #[async_trait]
+    impl QueryExecutor for MockTable {
+        async fn execute(&self, query: &Query) -> Result<(SchemaRef, Vec<RecordBatch>)> {
+            let rows = query.limit.min(self.batch.num_rows());
+            Ok((self.batch.schema(), vec![self.batch.slice(0, rows)]))
+        }
+    }
+
+    #[async_trait]
+    impl TableLike for MockTable {
+        fn name(&self) -> &str {
+            "mock"
+        }
+
+        async fn schema(&self) -> Result<SchemaRef> {
+            Ok(self.batch.schema())
+        }
+
+        async fn count_rows(&self) -> Result<usize> {
+            Ok(self.batch.num_rows())
+        }
+
+        async fn count_rows_filtered(&self, _filter: &str) -> Result<usize> {
+            Err(Error::InvalidInput {
+                message: "mock tables are not filtered".to_string(),
+            })
+        }
+
+        async fn add(
+            &self,
+            batches: Box<dyn RecordBatchReader + Send>,
+            _write_mode: Option<WriteMode>,
+        ) -> Result<usize> {
+            Ok(batches.map(|b| b.unwrap().num_rows()).sum())
+        }
+
+        async fn create_index(
+            &self,
+            _index_builder: &(dyn VectorIndexBuilder + Sync),
+        ) -> Result<()> {
+            Err(Error::InvalidInput {
+                message: "mock tables have no index".to_string(),
+            })
+        }
+
+        fn search(&self, query: QueryVector) -> Query {
+            let executor = Arc::new(MockTable {
+                batch: self.batch.clone(),
+                deleted: Default::default(),
+            });
+            Query::with_executor(executor, Float32Array::from(Vec::<f32>::new())).nearest_to(query)
+        }
+
+        async fn delete(&self, predicate: &str) -> Result<()> {
+            self.deleted.lock().unwrap().push(predicate.to_string());
+            Ok(())
+        }
+    }
 
-        use arrow_array::Float32Array;
+    #[tokio::test]
+    async fn test_mock_table() {
+        let batch = make_test_batches().batches.remove(0);
+        let mock = Arc::new(MockTable {
+            batch: batch.clone(),
+            deleted: Default::default(),
+        });
+        let table: TableRef = mock.clone();
+
+        assert_eq!(table.name(), "mock");
+        assert_eq!(table.schema().await.unwrap(), batch.schema());
+        assert_eq!(table.count_rows().await.unwrap(), 10);
+        assert_eq!(
+            table
+                .add(Box::new(make_test_batches()), None)
+                .await
+                .unwrap(),
+            10
+        );
+        let results = table
+            .search(Float32Array::from(vec![0.0]).into())
+            .limit(3)
+            .execute()
+            .await
+            .unwrap()
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        assert_eq!(results, vec![batch.slice(0, 3)]);
+        table.delete("i > 5").await.unwrap();
+        assert_eq!(*mock.deleted.lock().unwrap(), vec!["i > 5"]);
+        assert!(table.create_index(&IvfPQIndexBuilder::new()).await.is_err());
+
+        // The futures of a mock are `Send` as well.
+        let spawned = table.clone();
+        let results = tokio::spawn(async move {
+            spawned.delete("i < 2").await?;
+            spawned
+                .search(Float32Array::from(vec![0.0]).into())
+                .limit(2)
+                .execute()
+                .await?
+                .try_collect::<Vec<_>>()
+                .await
+                .map_err(Error::from)
+        })
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(results, vec![batch.slice(0, 2)]);
+        assert_eq!(*mock.deleted.lock().unwrap(), vec!["i > 5", "i < 2"]);
+    }
 
+    #[tokio::test]
+    async fn test_create_index() {
         let tmp_dir = tempdir().unwrap();
         let uri = tmp_dir.path().to_str().unwrap();
 
-        let dimension = 16;
-        let schema = Arc::new(ArrowSchema::new(vec![Field::new(
-            "embeddings",
-            DataType::FixedSizeList(
-                Arc::new(Field::new("item", DataType::Float32, true)),
-                dimension,
-            ),
-            false,
-        )]));
+        let table = TestTableBuilder::new(512)
+            .vector_column("embeddings")
+            .create(uri, "test")
+            .await
+            .unwrap();
 
-        let mut rng = rand::thread_rng();
-        let float_arr = Float32Array::from(
-            repeat_with(|| rng.gen::<f32>())
-                .take(512 * dimension as usize)
-                .collect::<Vec<f32>>(),
+        table
+            .create_index(
+                &IvfPQIndexBuilder::new()
+                    .column("embeddings")
+                    .index_name("my_index")
+                    .ivf_params(IvfBuildParams::new(256))
+                    .pq_params(PQBuildParams::default()),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(
+            table.dataset.current().load_indices().await.unwrap().len(),
+            1
         );
+        assert_eq!(table.count_rows().await.unwrap(), 512);
+        assert_eq!(table.name, "test");
+    }
 
-        let vectors = Arc::new(create_fixed_size_list(float_arr, dimension).unwrap());
-        let batches = RecordBatchBuffer::new(vec![RecordBatch::try_new(
-            schema.clone(),
-            vec![vectors.clone()],
-        )
-        .unwrap()]);
+    #[tokio::test]
+    async fn test_restore_before_index() {
+        use arrow_array::FixedSizeListArray;
+
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
 
-        let reader: Box<dyn RecordBatchReader + Send> = Box::new(batches);
-        let mut table = Table::create(uri, "test", reader, None).await.unwrap();
+        let rows = |seed| TestTableBuilder::new(256).seed(seed);
+        let table = rows(0).create(uri, "test").await.unwrap();
+        table.add(rows(1).reader().unwrap(), None).await.unwrap();
+        table
+            .create_index(
+                &IvfPQIndexBuilder::new()
+                    .column("vector")
+                    .ivf_params(IvfBuildParams::new(4)),
+            )
+            .await
+            .unwrap();
 
-        let mut i = IvfPQIndexBuilder::new();
+        // The index of the version restored covers fragments it does not
+        // have, which the append below writes again.
+        table.restore(1).await.unwrap();
+        assert!(table
+            .dataset
+            .current()
+            .load_indices()
+            .await
+            .unwrap()
+            .is_empty());
+        let appended = rows(2);
+        table.add(appended.reader().unwrap(), None).await.unwrap();
+        assert_eq!(table.count_rows().await.unwrap(), 512);
 
-        let index_builder = i
-            .column("embeddings".to_string())
-            .index_name("my_index".to_string())
-            .ivf_params(IvfBuildParams::new(256))
-            .pq_params(PQBuildParams::default());
+        let batch = &appended.batches().unwrap()[0];
+        let vectors = batch["vector"]
+            .as_any()
+            .downcast_ref::<FixedSizeListArray>()
+            .unwrap();
+        for row in [0, 100, 255] {
+            let vector = vectors
+                .value(row)
+                .as_any()
+                .downcast_ref::<Float32Array>()
+                .unwrap()
+                .values()
+                .to_vec();
+            let results: Vec<RecordBatch> = table
+                .search(vector)
+                .limit(1)
+                .execute()
+                .await
+                .unwrap()
+                .try_collect()
+                .await
+                .unwrap();
+            let nearest = &results[0];
+            let id = nearest["id"].as_any().downcast_ref::<Int32Array>().unwrap();
+            let distance = nearest["score"]
+                .as_any()
+                .downcast_ref::<Float32Array>()
+                .unwrap();
+            assert_eq!((id.value(0), distance.value(0)), (row as i32, 0.0));
+        }
+    }
 
-        table.create_index(index_builder).await.unwrap();
+    #[tokio::test]
+    async fn test_f16_vectors() {
+        use half::f16;
 
-        assert_eq!(table.dataset.load_indices().await.unwrap().len(), 1);
-        assert_eq!(table.count_rows().await.unwrap(), 512);
-        assert_eq!(table.name, "test");
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        let mut rng = rand::thread_rng();
+        let vectors: Vec<Vec<f16>> = (0..64)
+            .map(|_| (0..8).map(|_| f16::from_f32(rng.gen())).collect())
+            .collect();
+        let upcast: Vec<Vec<f32>> = vectors
+            .iter()
+            .map(|v| v.iter().map(|x| x.to_f32()).collect())
+            .collect();
+        let ids = || Arc::new(Int32Array::from_iter_values(0..64));
+        let half = RecordBatchBuilder::new()
+            .column("id", ids())
+            .vector_column_f16("vector", 8, vectors)
+            .build()
+            .unwrap();
+        let half = NativeTable::create(uri, "half", half.into_arrow(None).unwrap(), None)
+            .await
+            .unwrap();
+        let single = RecordBatchBuilder::new()
+            .column("id", ids())
+            .vector_column("vector", 8, upcast.clone())
+            .build()
+            .unwrap();
+        let single = NativeTable::create(uri, "single", single.into_arrow(None).unwrap(), None)
+            .await
+            .unwrap();
+
+        let nearest = |query: Query| async move {
+            let batches = query
+                .execute()
+                .await
+                .unwrap()
+                .try_collect::<Vec<_>>()
+                .await
+                .unwrap();
+            batches
+                .iter()
+                .flat_map(|b| {
+                    let ids = b.column_by_name("id").unwrap();
+                    let ids = ids.as_any().downcast_ref::<Int32Array>().unwrap();
+                    ids.values().to_vec()
+                })
+                .collect::<Vec<_>>()
+        };
+        let query = upcast[5].clone();
+        let expected = nearest(single.search(query.clone()).limit(5)).await;
+        assert_eq!(expected[0], 5);
+        assert_eq!(nearest(half.search(query.clone()).limit(5)).await, expected);
+
+        let filter = Some("id > 10".to_string());
+        let filtered = nearest(half.search(query.clone()).filter(filter).limit(5)).await;
+        assert_eq!(filtered.len(), 5);
+        assert!(filtered.iter().all(|id| *id > 10));
+
+        let selected = half
+            .search(query.clone())
+            .select(Some(vec!["id".to_string()]))
+            .execute()
+            .await
+            .unwrap()
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        let names = selected[0]
+            .schema()
+            .fields()
+            .iter()
+            .map(|f| f.name().clone())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["id", "vector", "score"]);
+
+        #[derive(serde::Serialize)]
+        struct Row {
+            id: i32,
+            vector: Vec<f32>,
+        }
+        half.add_rows(vec![Row {
+            id: 64,
+            vector: vec![0.5; 8],
+        }])
+        .await
+        .unwrap();
+        assert_eq!(nearest(half.search(vec![0.5; 8]).limit(1)).await, vec![64]);
+
+        let index = IvfPQIndexBuilder::new().column("vector");
+        assert!(matches!(
+            half.create_index(&index).await.unwrap_err(),
+            Error::InvalidInput { message } if message.contains("float16")
+        ));
     }
 
-    fn create_fixed_size_list<T: Array>(values: T, list_size: i32) -> Result<FixedSizeListArray> {
-        let list_type = DataType::FixedSizeList(
-            Arc::new(Field::new("item", values.data_type().clone(), true)),
-            list_size,
-        );
-        let data = ArrayDataBuilder::new(list_type)
-            .len(values.len() / list_size as usize)
-            .add_child_data(values.into_data())
+    #[tokio::test]
+    async fn test_multivector() {
+        use crate::arrow::vec_to_multivector;
+        use crate::query::MultiVectorScoring;
+
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        // The vectors of row 0 cover both query vectors, row 1 is near one of
+        // them and row 2 is near the mean of the query vectors.
+        let tokens = vec![
+            vec![vec![1.0, 0.0], vec![0.0, 1.0], vec![1.0, 0.0]],
+            vec![vec![0.9, 0.1]],
+            vec![vec![0.5, 0.5]],
+        ];
+        let pooled = tokens
+            .iter()
+            .map(|vectors| {
+                let n = vectors.len() as f32;
+                vec![
+                    vectors.iter().map(|v| v[0]).sum::<f32>() / n,
+                    vectors.iter().map(|v| v[1]).sum::<f32>() / n,
+                ]
+            })
+            .collect::<Vec<_>>();
+        let batch = RecordBatchBuilder::new()
+            .column("id", Arc::new(Int32Array::from_iter_values(0..3)))
+            .multivector_column("tokens", 2, tokens)
+            .vector_column("pooled", 2, pooled)
             .build()
             .unwrap();
+        let table = NativeTable::create(uri, "multi", batch.into_arrow(None).unwrap(), None)
+            .await
+            .unwrap();
+
+        let ids = |query: Query| async move {
+            let batches = query
+                .execute()
+                .await
+                .unwrap()
+                .try_collect::<Vec<_>>()
+                .await
+                .unwrap();
+            batches
+                .iter()
+                .flat_map(|b| {
+                    let ids = b.column_by_name("id").unwrap();
+                    let ids = ids.as_any().downcast_ref::<Int32Array>().unwrap();
+                    ids.values().to_vec()
+                })
+                .collect::<Vec<_>>()
+        };
+        let single = table.search(vec![0.5, 0.5]).column("pooled");
+        assert_eq!(ids(single).await, vec![2, 0, 1]);
+        let multi = || {
+            table
+                .search(Vec::<f32>::new())
+                .column("tokens")
+                .nearest_to_multi(vec![vec![1.0, 0.0], vec![0.0, 1.0]])
+        };
+        assert_eq!(ids(multi()).await, vec![0, 2, 1]);
+        let max = multi().multivector_scoring(MultiVectorScoring::Max);
+        assert_eq!(ids(max).await, vec![0, 1, 2]);
+        let filtered = multi().filter(Some("id > 0".to_string())).limit(1);
+        assert_eq!(ids(filtered).await, vec![2]);
+
+        let err = table
+            .search(vec![0.5, 0.5])
+            .column("tokens")
+            .execute()
+            .await;
+        assert!(matches!(err.err().unwrap(), Error::InvalidInput { .. }));
+        let err = multi().column("pooled").execute().await;
+        assert!(matches!(err.err().unwrap(), Error::InvalidInput { .. }));
+        let err = multi()
+            .nearest_to_multi(vec![vec![1.0, 0.0, 0.0]])
+            .execute()
+            .await;
+        assert!(matches!(
+            err.err().unwrap(),
+            Error::EmbeddingDimensionMismatch {
+                expected: 2,
+                got: 3,
+                ..
+            }
+        ));
+
+        let index = IvfPQIndexBuilder::new().column("tokens");
+        assert!(matches!(
+            table.create_index(&index).await.unwrap_err(),
+            Error::InvalidInput { message } if message.contains("multivector")
+        ));
 
-        Ok(FixedSizeListArray::from(data))
+        let ragged = vec_to_multivector(2, vec![vec![vec![1.0, 0.0]], vec![vec![1.0]]]);
+        assert!(matches!(
+            ragged.unwrap_err(),
+            Error::InvalidInput { message } if message.contains("row 1")
+        ));
     }
 }
@@ -0,0 +1,230 @@
+// Copyright 2023 LanceDB Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The rows written and deleted since a version of a table, for the tables
+//! created with [crate::database::CreateTableBuilder::track_modifications].
+//!
+//! Each row records the version it was written in. A deleted row leaves no
+//! trace in the later versions, so the deleted rows are those of the older
+//! version missing from the current one, found by their `_rowid` in tables
+//! whose rows keep it.
+
+use std::collections::BTreeSet;
+
+use arrow_array::{Array, UInt64Array};
+use futures::TryStreamExt;
+use lance::dataset::scanner::DatasetRecordBatchStream;
+use lance::dataset::{Dataset, ROW_ID};
+
+use super::NativeTable;
+use crate::error::{Error, Result};
+
+/// The column of the version each row was written in, see
+/// [crate::database::CreateTableBuilder::track_modifications].
+pub const LAST_MODIFIED_VERSION_COLUMN: &str = "_last_modified_version";
+
+/// The row ids of the rows of `dataset`, only those written in `version` or
+/// before if given.
+async fn row_ids(dataset: &Dataset, written_by: Option<u64>) -> Result<BTreeSet<u64>> {
+    let mut scanner = dataset.scan();
+    scanner.project(&[LAST_MODIFIED_VERSION_COLUMN])?;
+    if let Some(version) = written_by {
+        scanner.filter(&format!("{LAST_MODIFIED_VERSION_COLUMN} <= {version}"))?;
+    }
+    scanner.with_row_id();
+    let mut stream = scanner.try_into_stream().await?;
+    let mut row_ids = BTreeSet::new();
+    while let Some(batch) = stream.try_next().await? {
+        let ids = batch[ROW_ID].as_any().downcast_ref::<UInt64Array>();
+        row_ids.extend(ids.into_iter().flat_map(|ids| ids.values().iter().copied()));
+    }
+    Ok(row_ids)
+}
+
+impl NativeTable {
+    /// Fail with an [Error::InvalidInput] unless the table tracks the versions
+    /// of its rows.
+    async fn check_tracks_modifications(&self) -> Result<()> {
+        if self.properties().await?.track_modifications {
+            return Ok(());
+        }
+        Err(Error::InvalidInput {
+            message: format!(
+                "the table '{}' was not created to track modifications",
+                self.name
+            ),
+        })
+    }
+
+    /// The rows written after `version` of this table, by adds or by an
+    /// overwrite, with their `_rowid`.
+    ///
+    /// Fails with an [Error::InvalidInput] unless the table was created with
+    /// [crate::database::CreateTableBuilder::track_modifications].
+    pub async fn changed_since(&self, version: u64) -> Result<DatasetRecordBatchStream> {
+        self.check_tracks_modifications().await?;
+        let dataset = self.dataset.get().await?;
+        let mut scanner = dataset.scan();
+        self.scan_params.apply(&mut scanner);
+        scanner.filter(&format!("{LAST_MODIFIED_VERSION_COLUMN} > {version}"))?;
+        scanner.with_row_id();
+        Ok(scanner.try_into_stream().await?)
+    }
+
+    /// The `_rowid` of the rows of `version` of this table deleted since, in
+    /// increasing order, the rows of an overwritten version included.
+    ///
+    /// `version` has to be one of the versions kept by the table. Fails with an
+    /// [Error::InvalidInput] unless the table was created with
+    /// [crate::database::CreateTableBuilder::track_modifications] and
+    /// [crate::database::CreateTableBuilder::enable_stable_row_ids].
+    pub async fn deleted_since(&self, version: u64) -> Result<Vec<u64>> {
+        self.check_tracks_modifications().await?;
+        if !self.stable_row_ids().await? {
+            return Err(Error::InvalidInput {
+                message: format!(
+                    "the deleted rows of the table '{}' cannot be found, its rows do not keep their row ids",
+                    self.name
+                ),
+            });
+        }
+        if version == 0 {
+            return Ok(Vec::new());
+        }
+        let dataset = self.dataset.get().await?;
+        let older = row_ids(&dataset.checkout_version(version).await?, None).await?;
+        // The row ids of an overwritten version are given to the new rows.
+        let kept = row_ids(&dataset, Some(version)).await?;
+        Ok(older.difference(&kept).copied().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_array::{Int32Array, RecordBatchReader};
+    use lance::arrow::RecordBatchBuffer;
+    use lance::dataset::WriteMode;
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::arrow::RecordBatchBuilder;
+    use crate::connect;
+
+    fn rows(ids: std::ops::Range<i32>) -> Box<dyn RecordBatchReader> {
+        let vectors = ids.clone().map(|i| [i as f32, 0.0]).collect::<Vec<_>>();
+        let batch = RecordBatchBuilder::new()
+            .column("id", Arc::new(Int32Array::from_iter_values(ids)))
+            .vector_column("vector", 2, vectors)
+            .build()
+            .unwrap();
+        Box::new(RecordBatchBuffer::new(vec![batch]))
+    }
+
+    async fn changed_ids(table: &NativeTable, version: u64) -> Vec<i32> {
+        let batches = table
+            .changed_since(version)
+            .await
+            .unwrap()
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        let mut ids = batches
+            .iter()
+            .flat_map(|b| {
+                let ids = b["id"].as_any().downcast_ref::<Int32Array>().unwrap();
+                ids.values().to_vec()
+            })
+            .collect::<Vec<_>>();
+        ids.sort();
+        ids
+    }
+
+    #[tokio::test]
+    async fn test_changed_since() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        let db = connect(uri).execute().await.unwrap();
+        db.create_table("t", rows(0..4))
+            .track_modifications(true)
+            .enable_stable_row_ids(true)
+            .execute()
+            .await
+            .unwrap();
+        let table = &NativeTable::open(uri, "t").await.unwrap();
+        assert_eq!(table.version(), 1);
+        assert_eq!(changed_ids(table, 0).await, vec![0, 1, 2, 3]);
+        assert!(changed_ids(table, 1).await.is_empty());
+        let schema = table.schema().await.unwrap();
+        let column = schema
+            .field_with_name(LAST_MODIFIED_VERSION_COLUMN)
+            .unwrap();
+        assert_eq!(column.data_type(), &arrow_schema::DataType::UInt64);
+
+        table.add(rows(4..6), None).await.unwrap();
+        table.delete("id = 1 OR id = 4").await.unwrap();
+        table.add(rows(6..8), None).await.unwrap();
+        let version = table.version();
+        assert_eq!(changed_ids(table, 1).await, vec![5, 6, 7]);
+        assert_eq!(changed_ids(table, 2).await, vec![6, 7]);
+        assert!(changed_ids(table, version).await.is_empty());
+        assert_eq!(table.deleted_since(1).await.unwrap(), vec![1]);
+        // Row 4 was added and deleted after version 1.
+        assert_eq!(table.deleted_since(2).await.unwrap().len(), 2);
+        assert!(table.deleted_since(version).await.unwrap().is_empty());
+
+        table
+            .add(rows(10..12), Some(WriteMode::Overwrite))
+            .await
+            .unwrap();
+        assert_eq!(changed_ids(table, version).await, vec![10, 11]);
+        assert_eq!(table.deleted_since(version).await.unwrap().len(), 6);
+
+        // The column is assigned by the table.
+        let batch = RecordBatchBuilder::new()
+            .column("id", Arc::new(Int32Array::from(vec![20])))
+            .vector_column("vector", 2, vec![[0.0, 0.0]])
+            .column(
+                LAST_MODIFIED_VERSION_COLUMN,
+                Arc::new(UInt64Array::from(vec![1])),
+            )
+            .build()
+            .unwrap();
+        let batches = Box::new(RecordBatchBuffer::new(vec![batch]));
+        let err = table.add(batches, None).await.unwrap_err();
+        assert!(matches!(err, Error::InvalidInput { .. }), "{err}");
+    }
+
+    #[tokio::test]
+    async fn test_untracked_table() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        let db = connect(uri).execute().await.unwrap();
+        db.create_table("t", rows(0..4)).execute().await.unwrap();
+        let table = NativeTable::open(uri, "t").await.unwrap();
+        let err = table.changed_since(0).await.err().unwrap();
+        assert!(matches!(err, Error::InvalidInput { .. }), "{err}");
+        assert!(table.schema().await.unwrap().fields().len() == 2);
+
+        db.create_table("tracked", rows(0..4))
+            .track_modifications(true)
+            .execute()
+            .await
+            .unwrap();
+        let table = NativeTable::open(uri, "tracked").await.unwrap();
+        let err = table.deleted_since(1).await;
+        assert!(matches!(err.unwrap_err(), Error::InvalidInput { .. }));
+    }
+}
@@ -0,0 +1,352 @@
+// Copyright 2023 LanceDB Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Named checkpoints of a table, see [NativeTable::checkpoint].
+//!
+//! lance 0.5 has no tags, so the table records its checkpoints in a file of
+//! its directory: the version of each, and the [TableProperties] the table
+//! had then, the settings lance does not record. Rolling back commits the
+//! data of the version again and puts back its settings, so that the
+//! constraints, keys and metadata match the rows they describe.
+
+use std::collections::HashSet;
+use std::time::Duration;
+
+use chrono::{DateTime, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::{NativeTable, TableProperties};
+use crate::error::{Error, Result};
+
+/// The file of the checkpoints of a table, in its directory.
+pub(crate) const CHECKPOINTS_FILE: &str = "_checkpoints.json";
+
+/// A named version of a table, see [NativeTable::checkpoint].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Checkpoint {
+    pub name: String,
+    /// The version of the table when the checkpoint was made.
+    pub version: u64,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct StoredCheckpoint {
+    name: String,
+    version: u64,
+    created_at_ms: i64,
+    properties: TableProperties,
+}
+
+impl From<&StoredCheckpoint> for Checkpoint {
+    fn from(stored: &StoredCheckpoint) -> Self {
+        Self {
+            name: stored.name.clone(),
+            version: stored.version,
+            created_at: Utc.timestamp_millis_opt(stored.created_at_ms).unwrap(),
+        }
+    }
+}
+
+impl NativeTable {
+    async fn read_checkpoints(&self) -> Result<Vec<StoredCheckpoint>> {
+        let (store, base) = self.dataset.object_store().await?;
+        match store.inner.get(&base.child(CHECKPOINTS_FILE)).await {
+            Ok(result) => {
+                let bytes = result.bytes().await?;
+                serde_json::from_slice(&bytes).map_err(|e| Error::InvalidInput {
+                    message: format!("cannot decode the table checkpoints: {e}"),
+                })
+            }
+            Err(object_store::Error::NotFound { .. }) => Ok(Vec::new()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn write_checkpoints(&self, checkpoints: &[StoredCheckpoint]) -> Result<()> {
+        let bytes = serde_json::to_vec(checkpoints).map_err(|e| Error::InvalidInput {
+            message: format!("cannot encode the table checkpoints: {e}"),
+        })?;
+        let (store, base) = self.dataset.object_store().await?;
+        store
+            .inner
+            .put(&base.child(CHECKPOINTS_FILE), bytes.into())
+            .await?;
+        Ok(())
+    }
+
+    /// The versions of the checkpoints of this table, which
+    /// [NativeTable::optimize] does not prune.
+    pub(crate) async fn checkpoint_versions(&self) -> Result<HashSet<u64>> {
+        let checkpoints = self.read_checkpoints().await?;
+        Ok(checkpoints.iter().map(|c| c.version).collect())
+    }
+
+    /// Record the current version of this table and its settings as the
+    /// checkpoint `name`, replacing the checkpoint of that name if there is
+    /// one, to go back to with [NativeTable::rollback_to_checkpoint].
+    ///
+    /// The version of a checkpoint is kept by the pruning of
+    /// [NativeTable::optimize] until the checkpoint is expired. Fails with an
+    /// [Error::InvalidInput] if `name` is empty.
+    pub async fn checkpoint(&self, name: &str) -> Result<Checkpoint> {
+        if name.is_empty() {
+            return Err(Error::InvalidInput {
+                message: "the name of a checkpoint cannot be empty".to_string(),
+            });
+        }
+        let created_at_ms = Utc::now().timestamp_millis();
+        // Under the commit lock, so that no write comes between the version
+        // and the settings recorded.
+        let latest = self
+            .dataset
+            .write(self.max_commit_retries, |latest| async move {
+                let mut checkpoints = self.read_checkpoints().await?;
+                checkpoints.retain(|c| c.name != name);
+                checkpoints.push(StoredCheckpoint {
+                    name: name.to_string(),
+                    version: latest.version().version,
+                    created_at_ms,
+                    properties: self.properties().await?,
+                });
+                self.write_checkpoints(&checkpoints).await?;
+                Ok(latest.as_ref().clone())
+            })
+            .await?;
+        Ok(Checkpoint {
+            name: name.to_string(),
+            version: latest.version().version,
+            created_at: Utc.timestamp_millis_opt(created_at_ms).unwrap(),
+        })
+    }
+
+    /// The checkpoints of this table, oldest first.
+    pub async fn list_checkpoints(&self) -> Result<Vec<Checkpoint>> {
+        let mut checkpoints = self
+            .read_checkpoints()
+            .await?
+            .iter()
+            .map(Checkpoint::from)
+            .collect::<Vec<_>>();
+        checkpoints.sort_by(|a, b| (a.created_at, &a.name).cmp(&(b.created_at, &b.name)));
+        Ok(checkpoints)
+    }
+
+    /// Commit the data of the checkpoint `name` as the latest version of this
+    /// table, like [NativeTable::restore], and put back the settings the table
+    /// had then: its constraints, primary key and metadata.
+    ///
+    /// The settings are put back with the data, under the commit lock, and
+    /// the current ones again if the commit fails. The ids of
+    /// [crate::database::CreateTableBuilder::auto_id] continue from the
+    /// largest one assigned, so that the rows written after the rollback do
+    /// not reuse the ids of rows written after the checkpoint. The checkpoint
+    /// is kept. Fails with an [Error::InvalidInput] if there is no checkpoint
+    /// `name`.
+    pub async fn rollback_to_checkpoint(&self, name: &str) -> Result<()> {
+        self.dataset
+            .write(self.max_commit_retries, |latest| async move {
+                let checkpoint = self
+                    .read_checkpoints()
+                    .await?
+                    .into_iter()
+                    .find(|c| c.name == name)
+                    .ok_or_else(|| Error::InvalidInput {
+                        message: format!("the table has no checkpoint '{name}'"),
+                    })?;
+                let current = self.properties().await?;
+                let mut properties = checkpoint.properties;
+                if let (Some(auto_id), Some(assigned)) =
+                    (properties.auto_id.as_mut(), current.auto_id.as_ref())
+                {
+                    auto_id.next = auto_id.next.max(assigned.next);
+                }
+                self.set_properties(properties).await?;
+                match self.commit_version(&latest, checkpoint.version).await {
+                    Ok(committed) => Ok(committed),
+                    Err(e) => {
+                        self.set_properties(current).await?;
+                        Err(e)
+                    }
+                }
+            })
+            .await?;
+        Ok(())
+    }
+
+    /// Remove the checkpoints of this table made more than `older_than` ago,
+    /// so that [NativeTable::optimize] can prune their versions. Returns the
+    /// number of checkpoints removed.
+    pub async fn expire_checkpoints(&self, older_than: Duration) -> Result<usize> {
+        let cutoff = Utc::now().timestamp_millis() - older_than.as_millis() as i64;
+        let mut expired = 0;
+        let expired_ref = &mut expired;
+        self.dataset
+            .write(self.max_commit_retries, |latest| async move {
+                let mut checkpoints = self.read_checkpoints().await?;
+                let count = checkpoints.len();
+                checkpoints.retain(|c| c.created_at_ms >= cutoff);
+                *expired_ref = count - checkpoints.len();
+                if *expired_ref > 0 {
+                    self.write_checkpoints(&checkpoints).await?;
+                }
+                Ok(latest.as_ref().clone())
+            })
+            .await?;
+        Ok(expired)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use arrow_array::{Array, Float32Array, Int32Array, RecordBatchReader};
+    use futures::TryStreamExt;
+    use lance::arrow::RecordBatchBuffer;
+    use lance::dataset::WriteMode;
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::arrow::RecordBatchBuilder;
+    use crate::table::{Constraint, MaintenanceConfig};
+
+    fn rows(ids: std::ops::Range<i32>, score: bool) -> Box<dyn RecordBatchReader> {
+        let mut builder = RecordBatchBuilder::new()
+            .column("id", Arc::new(Int32Array::from_iter_values(ids.clone())));
+        if score {
+            let scores = ids.map(|i| i as f32 / 100.0);
+            builder = builder.column("score", Arc::new(Float32Array::from_iter_values(scores)));
+        }
+        Box::new(RecordBatchBuffer::new(vec![builder.build().unwrap()]))
+    }
+
+    async fn ids(table: &NativeTable) -> Vec<i32> {
+        let dataset = table.dataset.get().await.unwrap();
+        let batches = dataset
+            .scan()
+            .try_into_stream()
+            .await
+            .unwrap()
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        let mut ids = batches
+            .iter()
+            .flat_map(|b| {
+                let ids = b["id"].as_any().downcast_ref::<Int32Array>().unwrap();
+                ids.values().to_vec()
+            })
+            .collect::<Vec<_>>();
+        ids.sort();
+        ids
+    }
+
+    #[tokio::test]
+    async fn test_rollback_to_checkpoint() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        let table = NativeTable::create(uri, "test", rows(0..10, true), None)
+            .await
+            .unwrap();
+        let constraints = vec![Constraint::new("score", "score BETWEEN 0 AND 1")];
+        table.set_constraints(constraints.clone()).await.unwrap();
+        let metadata = HashMap::from([("model".to_string(), "v1".to_string())]);
+        table
+            .replace_schema_metadata(metadata.clone())
+            .await
+            .unwrap();
+        let checkpoint = table.checkpoint("before").await.unwrap();
+        assert_eq!(checkpoint.version, table.version());
+
+        // Change the settings, and drop the score column.
+        table
+            .set_constraints(vec![Constraint::new("id", "id < 100")])
+            .await
+            .unwrap();
+        table.replace_schema_metadata(HashMap::new()).await.unwrap();
+        table
+            .add(rows(0..4, false), Some(WriteMode::Overwrite))
+            .await
+            .unwrap();
+        assert!(table
+            .schema()
+            .await
+            .unwrap()
+            .field_with_name("score")
+            .is_err());
+
+        table.rollback_to_checkpoint("before").await.unwrap();
+        let table = NativeTable::open(uri, "test").await.unwrap();
+        assert!(table.version() > checkpoint.version);
+        assert_eq!(ids(&table).await, (0..10).collect::<Vec<_>>());
+        let schema = table.schema().await.unwrap();
+        assert!(schema.field_with_name("score").is_ok());
+        assert_eq!(schema.metadata(), &metadata);
+        assert_eq!(table.constraints().await.unwrap(), constraints);
+
+        // The checkpoint is kept, and replaced by one of the same name.
+        let checkpoints = table.list_checkpoints().await.unwrap();
+        assert_eq!(checkpoints, vec![checkpoint.clone()]);
+        let replaced = table.checkpoint("before").await.unwrap();
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        table.checkpoint("after").await.unwrap();
+        let checkpoints = table.list_checkpoints().await.unwrap();
+        let names = checkpoints
+            .iter()
+            .map(|c| c.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["before", "after"]);
+        assert_eq!(checkpoints[0].version, replaced.version);
+        assert!(table
+            .checkpoint_versions()
+            .await
+            .unwrap()
+            .contains(&replaced.version));
+
+        let err = table.rollback_to_checkpoint("missing").await.unwrap_err();
+        assert!(matches!(err, Error::InvalidInput { .. }), "{err}");
+        assert!(table.checkpoint("").await.is_err());
+
+        assert_eq!(
+            table
+                .expire_checkpoints(Duration::from_secs(3600))
+                .await
+                .unwrap(),
+            0
+        );
+        // Pruning keeps the versions of the checkpoints, until they expire.
+        table.add(rows(10..12, true), None).await.unwrap();
+        let prune = MaintenanceConfig {
+            compact: false,
+            optimize_indices: false,
+            prune_older_than: Some(Duration::ZERO),
+            ..Default::default()
+        };
+        table.optimize(&prune).await.unwrap();
+        let versions = table.versions().await.unwrap();
+        assert!(versions.iter().any(|v| v.version == replaced.version));
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        let config = MaintenanceConfig {
+            expire_checkpoints_older_than: Some(Duration::ZERO),
+            ..prune
+        };
+        let stats = table.optimize(&config).await.unwrap();
+        assert_eq!(stats.checkpoints_expired, 2);
+        assert!(table.list_checkpoints().await.unwrap().is_empty());
+        let versions = table.versions().await.unwrap();
+        assert!(!versions.iter().any(|v| v.version == replaced.version));
+    }
+}
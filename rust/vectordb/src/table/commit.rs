@@ -0,0 +1,169 @@
+// Copyright 2023 LanceDB Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions, TryLockError};
+use std::path::Path;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+
+use tokio::sync::OwnedMutexGuard;
+
+use crate::error::{Error, Result};
+
+const LOCK_FILE_NAME: &str = "_commit.lock";
+/// Appended to the directory of a table for the file of its creation lock.
+const CREATE_LOCK_SUFFIX: &str = ".create.lock";
+
+pub(super) const INITIAL_COMMIT_BACKOFF: Duration = Duration::from_millis(10);
+pub(super) const MAX_COMMIT_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Exclusive right to commit a new version of a table.
+///
+/// lance overwrites the manifest of the next version without checking whether it
+/// already exists, so concurrent writers must not commit at the same time. Local
+/// tables are guarded by an OS file lock, which covers other processes and is
+/// released if the holder crashes. Other stores only have a process-wide lock.
+pub(crate) struct CommitLock {
+    _file: Option<File>,
+    _guard: Option<OwnedMutexGuard<()>>,
+}
+
+impl CommitLock {
+    /// Try to take the commit lock of the table at `uri`.
+    ///
+    /// # Returns
+    ///
+    /// * `None` if another writer holds the lock.
+    pub(crate) fn try_acquire(uri: &str) -> Result<Option<Self>> {
+        Self::try_acquire_at(uri, &format!("{uri}/{LOCK_FILE_NAME}"))
+    }
+
+    /// Take the lock guarding the creation of the table at `uri`, waiting for
+    /// the other creators to finish.
+    ///
+    /// The lock is not the commit lock, which the creation takes to write the
+    /// first versions. Its file is next to the directory of the table, which
+    /// is not created unless the table is.
+    pub(crate) async fn acquire_create(uri: &str) -> Result<Self> {
+        let lock_path = Self::create_lock_path(uri);
+        if let (false, Some(parent)) = (uri.contains("://"), Path::new(&lock_path).parent()) {
+            std::fs::create_dir_all(parent).map_err(|e| lock_error(uri, e))?;
+        }
+        let mut backoff = INITIAL_COMMIT_BACKOFF;
+        loop {
+            if let Some(lock) = Self::try_acquire_at(uri, &lock_path)? {
+                return Ok(lock);
+            }
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(MAX_COMMIT_BACKOFF);
+        }
+    }
+
+    /// The file of the creation lock of the table at `uri`, which is left
+    /// behind until the table is dropped.
+    pub(crate) fn create_lock_path(uri: &str) -> String {
+        format!("{}{CREATE_LOCK_SUFFIX}", uri.trim_end_matches('/'))
+    }
+
+    /// Try to take the lock of the file at `path`, for the table at `uri`.
+    fn try_acquire_at(uri: &str, path: &str) -> Result<Option<Self>> {
+        if uri.is_empty() || uri.contains("://") {
+            return Ok(process_lock(path).try_lock_owned().ok().map(|guard| Self {
+                _file: None,
+                _guard: Some(guard),
+            }));
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(path)
+            .map_err(|e| lock_error(uri, e))?;
+        match file.try_lock() {
+            Ok(()) => Ok(Some(Self {
+                _file: Some(file),
+                _guard: None,
+            })),
+            Err(TryLockError::WouldBlock) => Ok(None),
+            Err(TryLockError::Error(e)) => Err(lock_error(uri, e)),
+        }
+    }
+}
+
+fn process_lock(uri: &str) -> Arc<tokio::sync::Mutex<()>> {
+    static LOCKS: OnceLock<Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>> = OnceLock::new();
+    LOCKS
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap()
+        .entry(uri.to_string())
+        .or_default()
+        .clone()
+}
+
+fn lock_error(uri: &str, e: std::io::Error) -> Error {
+    Error::CommitConflict {
+        uri: uri.to_string(),
+        reason: format!("unable to take the commit lock: {e}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn test_lock_is_exclusive() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+
+        let lock = CommitLock::try_acquire(uri).unwrap();
+        assert!(lock.is_some());
+        assert!(CommitLock::try_acquire(uri).unwrap().is_none());
+        drop(lock);
+        assert!(CommitLock::try_acquire(uri).unwrap().is_some());
+
+        let lock = CommitLock::try_acquire("memory://table.lance").unwrap();
+        assert!(lock.is_some());
+        assert!(CommitLock::try_acquire("memory://table.lance")
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_create_lock() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().join("t.lance");
+        let uri = uri.to_str().unwrap();
+
+        let lock = CommitLock::acquire_create(uri).await.unwrap();
+        // The lock does not create the directory of the table.
+        assert!(!Path::new(uri).exists());
+        // The creator can take the commit lock to write the table.
+        std::fs::create_dir_all(uri).unwrap();
+        assert!(CommitLock::try_acquire(uri).unwrap().is_some());
+        let waiting = CommitLock::acquire_create(uri);
+        tokio::pin!(waiting);
+        assert!(
+            tokio::time::timeout(Duration::from_millis(50), &mut waiting)
+                .await
+                .is_err()
+        );
+        drop(lock);
+        waiting.await.unwrap();
+    }
+}
@@ -0,0 +1,207 @@
+// Copyright 2023 LanceDB Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Row-level constraints of tables, see [NativeTable::set_constraints].
+//!
+//! A constraint is a SQL boolean expression of the columns of a row, planned
+//! and evaluated by datafusion on the written batches after they have been
+//! embedded and conformed to the table, before their vectors are checked. It
+//! may call `json_extract`, see [crate::query::JSON_EXTRACT], and
+//! `vector_norm`, see [super::VECTOR_NORM].
+//!
+//! The table has no update or upsert. [NativeTable::merge] only adds columns,
+//! which the constraints, set on the columns of the table, do not read.
+
+use serde::{Deserialize, Serialize};
+
+use super::NativeTable;
+use crate::error::Result;
+use crate::io::constraints::validate_constraints;
+
+/// A named SQL boolean expression every row written to a table must satisfy,
+/// for example `score BETWEEN 0 AND 1`.
+///
+/// As for the `CHECK` constraints of SQL, a row is only a violation if the
+/// expression is false: a null `score` satisfies the example, add `score IS
+/// NOT NULL` to reject it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Constraint {
+    /// The name of the constraint, in the errors and reports of violations.
+    pub name: String,
+    /// The SQL boolean expression.
+    pub expr: String,
+}
+
+impl Constraint {
+    pub fn new(name: impl Into<String>, expr: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            expr: expr.into(),
+        }
+    }
+}
+
+/// What to do with the written rows violating a [Constraint] of the table.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OnConstraintViolation {
+    /// Fail the write with an [crate::Error::InvalidInput] naming the row and
+    /// the constraint.
+    #[default]
+    Error,
+    /// Leave the rows out of the write, and report them in
+    /// [super::AddReport::rejected_rows].
+    Reject,
+}
+
+/// A written row left out by [OnConstraintViolation::Reject].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RejectedRow {
+    /// The index of the row in the written data.
+    pub row: usize,
+    /// The names of the constraints the row violates.
+    pub constraints: Vec<String>,
+}
+
+impl NativeTable {
+    /// Replace the constraints of this table by `constraints`, checked on the
+    /// rows of every later write, see [OnConstraintViolation]. The constraints
+    /// are stored with the table, and the rows already in it are not checked.
+    ///
+    /// Fails with an [crate::Error::InvalidInput] if two constraints have the
+    /// same name, or if a constraint is not a boolean expression of the
+    /// columns of the table.
+    pub async fn set_constraints(&self, constraints: Vec<Constraint>) -> Result<()> {
+        validate_constraints(&constraints, &self.schema().await?)?;
+        let mut properties = self.properties().await?;
+        properties.constraints = constraints;
+        self.set_properties(properties).await
+    }
+
+    /// The constraints of this table, see [NativeTable::set_constraints].
+    pub async fn constraints(&self) -> Result<Vec<Constraint>> {
+        Ok(self.properties().await?.constraints)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_array::{Float32Array, Int32Array, RecordBatchReader, StringArray};
+    use lance::arrow::RecordBatchBuffer;
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::arrow::RecordBatchBuilder;
+    use crate::error::Error;
+    use crate::table::{OpenTableParams, WriteOptions};
+
+    fn rows(titles: &[&str], scores: &[f32], vectors: &[[f32; 2]]) -> Box<dyn RecordBatchReader> {
+        let ids = 0..titles.len() as i32;
+        let batch = RecordBatchBuilder::new()
+            .column("id", Arc::new(Int32Array::from_iter_values(ids)))
+            .column("title", Arc::new(StringArray::from(titles.to_vec())))
+            .column("score", Arc::new(Float32Array::from(scores.to_vec())))
+            .vector_column("vector", 2, vectors.to_vec())
+            .build()
+            .unwrap();
+        Box::new(RecordBatchBuffer::new(vec![batch]))
+    }
+
+    fn constraints() -> Vec<Constraint> {
+        vec![
+            Constraint::new("title", "title != ''"),
+            Constraint::new("score", "score BETWEEN 0 AND 1"),
+            Constraint::new("norm", "vector_norm(vector) > 0"),
+        ]
+    }
+
+    async fn open(uri: &str, policy: OnConstraintViolation) -> NativeTable {
+        let params = OpenTableParams {
+            write_options: Some(WriteOptions::default().on_constraint_violation(policy)),
+            ..Default::default()
+        };
+        NativeTable::open_with_params(uri, "test", params)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_constraints() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        let table = NativeTable::create(uri, "test", rows(&["a"], &[0.5], &[[1.0, 0.0]]), None)
+            .await
+            .unwrap();
+        table.set_constraints(constraints()).await.unwrap();
+        assert_eq!(table.constraints().await.unwrap(), constraints());
+
+        // Rows satisfying every constraint are written.
+        let ok = rows(&["b", "c"], &[0.0, 1.0], &[[0.0, 2.0], [1.0, 1.0]]);
+        assert_eq!(table.add(ok, None).await.unwrap(), 2);
+
+        // The constraints are stored with the table.
+        let bad = || {
+            rows(
+                &["d", "", "e", "f"],
+                &[0.5, 0.5, 1.5, 0.5],
+                &[[1.0, 0.0], [1.0, 0.0], [0.0, 0.0], [3.0, 4.0]],
+            )
+        };
+        let table = open(uri, OnConstraintViolation::Error).await;
+        let err = table.add(bad(), None).await.unwrap_err();
+        let Error::InvalidInput { message } = err else {
+            panic!("unexpected error {err}");
+        };
+        assert!(
+            message.contains("row 1") && message.contains("'title'"),
+            "{message}"
+        );
+        assert_eq!(table.count_rows().await.unwrap(), 3);
+
+        let table = open(uri, OnConstraintViolation::Reject).await;
+        let report = table.add_with_report(bad(), None).await.unwrap();
+        assert_eq!(report.rows, 2);
+        assert_eq!(
+            report.rejected_rows,
+            vec![
+                RejectedRow {
+                    row: 1,
+                    constraints: vec!["title".to_string()],
+                },
+                RejectedRow {
+                    row: 2,
+                    constraints: vec!["score".to_string(), "norm".to_string()],
+                },
+            ]
+        );
+        assert_eq!(table.count_rows().await.unwrap(), 5);
+
+        for invalid in [
+            vec![
+                Constraint::new("a", "id > 0"),
+                Constraint::new("a", "id < 9"),
+            ],
+            vec![Constraint::new("", "id > 0")],
+            vec![Constraint::new("missing", "missing > 0")],
+            vec![Constraint::new("number", "id + 1")],
+        ] {
+            let err = table.set_constraints(invalid).await.unwrap_err();
+            assert!(matches!(err, Error::InvalidInput { .. }), "{err}");
+        }
+        table.set_constraints(Vec::new()).await.unwrap();
+        assert!(table.constraints().await.unwrap().is_empty());
+        assert_eq!(table.add(bad(), None).await.unwrap(), 4);
+    }
+}
@@ -0,0 +1,408 @@
+// Copyright 2023 LanceDB Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use futures::FutureExt;
+use lance::dataset::{Dataset, ReadParams};
+use lance::io::object_store::{ObjectStore, ObjectStoreParams, WrappingObjectStore};
+use object_store::path::Path;
+
+use super::commit::{CommitLock, INITIAL_COMMIT_BACKOFF, MAX_COMMIT_BACKOFF};
+use super::indices::CheckedIndices;
+use super::keys::KeyCache;
+use super::partial::RowFilters;
+use super::partitions::PartitionCache;
+use super::stats::StatsCache;
+use crate::cache::MetadataCache;
+use crate::error::{Error, Result};
+use crate::io::coalesce::CoalescingWrapper;
+use crate::io::metered::{IoCounters, MeteredWrapper};
+use crate::query::ScanParams;
+
+struct DatasetState {
+    dataset: Arc<Dataset>,
+    checked_at: Instant,
+}
+
+/// A dataset opened with scan parameters, and the version it was opened at.
+type TunedDataset = (u64, ScanParams, Arc<Dataset>);
+
+/// The number of times [open_latest] reads the latest manifest again.
+const LATEST_MANIFEST_RETRIES: usize = 4;
+
+/// Open the latest version of the dataset at `uri`.
+///
+/// Readers do not take the commit lock, and each commit replaces the latest
+/// manifest: a reader can find it missing, or read the end of one version with
+/// the start of the next, which lance fails on or panics on. Opening is retried
+/// with backoff before it fails.
+pub(super) async fn open_latest(uri: &str, params: &ReadParams) -> lance::Result<Dataset> {
+    let mut backoff = INITIAL_COMMIT_BACKOFF;
+    let mut attempt = 0;
+    loop {
+        let opened = AssertUnwindSafe(Dataset::open_with_params(uri, params))
+            .catch_unwind()
+            .await;
+        match opened {
+            Ok(Err(
+                lance::Error::DatasetNotFound { .. }
+                | lance::Error::CorruptFile { .. }
+                | lance::Error::IO { .. },
+            ))
+            | Err(_)
+                if attempt < LATEST_MANIFEST_RETRIES =>
+            {
+                attempt += 1;
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_COMMIT_BACKOFF);
+            }
+            Ok(result) => return result,
+            Err(panic) => std::panic::resume_unwind(panic),
+        }
+    }
+}
+
+/// The dataset backing a [crate::table::Table].
+///
+/// Clones share the same view, so a version committed through one handle is
+/// visible to all of them. Reads go through [DatasetRef::get], which reloads the
+/// latest version once `read_consistency_interval` has elapsed.
+///
+/// A checkout, made by [DatasetRef::checkout], stays at its version and fails
+/// writes.
+#[derive(Clone)]
+pub(crate) struct DatasetRef {
+    uri: String,
+    state: Arc<RwLock<DatasetState>>,
+    read_params: Arc<ReadParams>,
+    read_consistency_interval: Option<Duration>,
+    metadata_cache: Option<Arc<MetadataCache>>,
+    stats: Arc<StatsCache>,
+    keys: Arc<KeyCache>,
+    checked_indices: Arc<CheckedIndices>,
+    partitions: Arc<PartitionCache>,
+    row_filters: Arc<RowFilters>,
+    /// The version and scan parameters of the dataset last opened by
+    /// [DatasetRef::tuned], with it.
+    tuned: Arc<Mutex<Option<TunedDataset>>>,
+    checkout: bool,
+}
+
+impl std::fmt::Debug for DatasetRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DatasetRef")
+            .field("uri", &self.uri)
+            .field("dataset", &self.current())
+            .field("read_consistency_interval", &self.read_consistency_interval)
+            .field("checkout", &self.checkout)
+            .finish()
+    }
+}
+
+impl From<Arc<Dataset>> for DatasetRef {
+    fn from(dataset: Arc<Dataset>) -> Self {
+        Self::new(String::new(), dataset, ReadParams::default(), None, None)
+    }
+}
+
+impl DatasetRef {
+    pub(crate) fn new(
+        uri: String,
+        dataset: Arc<Dataset>,
+        read_params: ReadParams,
+        read_consistency_interval: Option<Duration>,
+        metadata_cache: Option<Arc<MetadataCache>>,
+    ) -> Self {
+        Self {
+            uri,
+            state: Arc::new(RwLock::new(DatasetState {
+                dataset,
+                checked_at: Instant::now(),
+            })),
+            read_params: Arc::new(read_params),
+            read_consistency_interval,
+            metadata_cache,
+            stats: Arc::default(),
+            keys: Arc::default(),
+            checked_indices: Arc::default(),
+            partitions: Arc::default(),
+            row_filters: Arc::default(),
+            tuned: Arc::default(),
+            checkout: false,
+        }
+    }
+
+    /// A view of `version` of the dataset, which is never reloaded.
+    ///
+    /// The shared metadata cache holds the latest versions, so it is not used.
+    pub(crate) async fn checkout(&self, version: u64) -> Result<Self> {
+        let dataset = self.current().checkout_version(version).await?;
+        Ok(Self {
+            uri: self.uri.clone(),
+            state: Arc::new(RwLock::new(DatasetState {
+                dataset: Arc::new(dataset),
+                checked_at: Instant::now(),
+            })),
+            read_params: self.read_params.clone(),
+            read_consistency_interval: None,
+            metadata_cache: None,
+            stats: Arc::default(),
+            keys: Arc::default(),
+            checked_indices: Arc::default(),
+            partitions: Arc::default(),
+            // The files of an index are the same in every version.
+            row_filters: self.row_filters.clone(),
+            tuned: Arc::default(),
+            checkout: true,
+        })
+    }
+
+    /// The column statistics computed for the current version.
+    pub(crate) fn stats(&self) -> &StatsCache {
+        &self.stats
+    }
+
+    /// The key index last built.
+    pub(crate) fn keys(&self) -> &KeyCache {
+        &self.keys
+    }
+
+    /// The columns whose indices are of their dimension in the current version.
+    pub(crate) fn checked_indices(&self) -> &CheckedIndices {
+        &self.checked_indices
+    }
+
+    /// The partitioning of the table read for the current version.
+    pub(crate) fn partitions(&self) -> &PartitionCache {
+        &self.partitions
+    }
+
+    /// The row filters of the indices read so far.
+    pub(crate) fn row_filters(&self) -> &RowFilters {
+        &self.row_filters
+    }
+
+    /// The dataset as last loaded, without checking for newer versions.
+    pub(crate) fn current(&self) -> Arc<Dataset> {
+        self.state.read().unwrap().dataset.clone()
+    }
+
+    /// The dataset to read from, reloaded if the consistency interval has elapsed.
+    pub(crate) async fn get(&self) -> Result<Arc<Dataset>> {
+        if let Some(interval) = self.read_consistency_interval {
+            let checked_at = self.state.read().unwrap().checked_at;
+            if checked_at.elapsed() >= interval {
+                return self.reload().await;
+            }
+        }
+        Ok(self.current())
+    }
+
+    /// Load the latest version of the dataset.
+    pub(crate) async fn reload(&self) -> Result<Arc<Dataset>> {
+        if self.is_detached() || self.checkout {
+            return Ok(self.current());
+        }
+        let dataset = open_latest(&self.uri, &self.read_params()).await?;
+        Ok(self.set(dataset))
+    }
+
+    /// Make a newly committed version of the dataset the current one.
+    ///
+    /// Datasets returned by lance write operations carry a fresh session and the
+    /// default object store, so they are checked out again through this table's
+    /// read params to keep the connection's shared cache and storage options.
+    pub(crate) async fn commit(&self, written: Dataset) -> Result<Arc<Dataset>> {
+        let dataset = self
+            .current()
+            .checkout_version(written.version().version)
+            .await?;
+        Ok(self.set(dataset))
+    }
+
+    /// Run the write operation `op` and commit the version it produces.
+    ///
+    /// `op` runs under the table's commit lock, against the latest version in
+    /// storage. If other writers committed since the version this handle has
+    /// loaded, the operation is rebased onto theirs as long as they left the
+    /// schema unchanged; otherwise it fails with [Error::CommitConflict] and this
+    /// handle is left at the latest version, so the caller can retry. While
+    /// another writer holds the lock, taking it is retried up to `max_retries`
+    /// times with exponential backoff.
+    pub(crate) async fn write<F, Fut>(&self, max_retries: usize, op: F) -> Result<Arc<Dataset>>
+    where
+        F: FnOnce(Arc<Dataset>) -> Fut,
+        Fut: Future<Output = Result<Dataset>>,
+    {
+        if self.checkout {
+            return Err(Error::InvalidInput {
+                message: format!(
+                    "the table is checked out at version {}, open it again to write to the latest version",
+                    self.current().version().version
+                ),
+            });
+        }
+        let base = self.get().await?;
+        let mut backoff = INITIAL_COMMIT_BACKOFF;
+        for attempt in 0..=max_retries {
+            let Some(lock) = CommitLock::try_acquire(&self.uri)? else {
+                if attempt < max_retries {
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(MAX_COMMIT_BACKOFF);
+                }
+                continue;
+            };
+            let latest = self.reload().await?;
+            if latest.version().version != base.version().version
+                && latest.schema() != base.schema()
+            {
+                return Err(Error::CommitConflict {
+                    uri: self.uri.clone(),
+                    reason: format!(
+                        "the schema was changed by version {} after this table loaded version {}",
+                        latest.version().version,
+                        base.version().version
+                    ),
+                });
+            }
+            let written = op(latest).await?;
+            let dataset = self.commit(written).await?;
+            drop(lock);
+            return Ok(dataset);
+        }
+        Err(Error::CommitConflict {
+            uri: self.uri.clone(),
+            reason: format!("another writer still held the table after {max_retries} retries"),
+        })
+    }
+
+    /// Replace the current dataset, unless it is older than the current one,
+    /// which is kept and returned.
+    ///
+    /// A reload that started before a write through a clone of this handle can
+    /// finish after the write is committed, with the version before it. Keeping
+    /// the newer version lets every clone read the writes made through any of
+    /// them.
+    pub(crate) fn set(&self, dataset: Dataset) -> Arc<Dataset> {
+        let mut state = self.state.write().unwrap();
+        state.checked_at = Instant::now();
+        if dataset.version().version < state.dataset.version().version {
+            return state.dataset.clone();
+        }
+        let dataset = Arc::new(dataset);
+        state.dataset = dataset.clone();
+        if let Some(cache) = self.metadata_cache.as_ref() {
+            cache.insert(&self.uri, dataset.clone());
+        }
+        dataset
+    }
+
+    /// `dataset` opened again, counting the bytes read from it and the
+    /// requests issued, with its reads shaped by `scan_params`.
+    ///
+    /// `None` for a `memory://` dataset, which lance checks out through a new,
+    /// empty store, and for a local dataset, whose files lance reads without
+    /// the object store.
+    pub(crate) async fn metered(
+        &self,
+        dataset: &Dataset,
+        scan_params: &ScanParams,
+    ) -> Result<Option<(Dataset, Arc<IoCounters>)>> {
+        if !self.reads_object_store() {
+            return Ok(None);
+        }
+        let mut params = self.read_params();
+        let store_options = params.store_options.take().unwrap_or_default();
+        let metered = MeteredWrapper::new(store_options.object_store_wrapper);
+        let counters = metered.counters();
+        let mut wrapper: Arc<dyn WrappingObjectStore> = Arc::new(metered);
+        if scan_params.tunes_io() {
+            wrapper = Arc::new(CoalescingWrapper::new(*scan_params, Some(wrapper)));
+        }
+        params.store_options = Some(ObjectStoreParams {
+            object_store_wrapper: Some(wrapper),
+        });
+        let dataset =
+            Dataset::checkout_with_params(&self.uri, dataset.version().version, &params).await?;
+        Ok(Some((dataset, counters)))
+    }
+
+    /// `dataset` opened again with its reads shaped by `scan_params`, see
+    /// [crate::io::coalesce].
+    ///
+    /// `dataset` itself if `scan_params` does not tune the reads, or if they
+    /// do not go through the object store. The dataset opened for a version
+    /// and parameters is kept, with the blocks its store fetched.
+    pub(crate) async fn tuned(
+        &self,
+        dataset: Arc<Dataset>,
+        scan_params: &ScanParams,
+    ) -> Result<Arc<Dataset>> {
+        if !scan_params.tunes_io() || !self.reads_object_store() {
+            return Ok(dataset);
+        }
+        let version = dataset.version().version;
+        if let Some((v, p, tuned)) = self.tuned.lock().unwrap().as_ref() {
+            if *v == version && p == scan_params {
+                return Ok(tuned.clone());
+            }
+        }
+        let mut params = self.read_params();
+        let store_options = params.store_options.take().unwrap_or_default();
+        let wrapper = CoalescingWrapper::new(*scan_params, store_options.object_store_wrapper);
+        params.store_options = Some(ObjectStoreParams {
+            object_store_wrapper: Some(Arc::new(wrapper)),
+        });
+        let tuned = Arc::new(Dataset::checkout_with_params(&self.uri, version, &params).await?);
+        *self.tuned.lock().unwrap() = Some((version, *scan_params, tuned.clone()));
+        Ok(tuned)
+    }
+
+    /// Whether lance reads the files of the dataset through its object store,
+    /// which it does not for a local dataset. A `memory://` dataset is left out
+    /// too, lance checks its versions out through a new, empty store.
+    fn reads_object_store(&self) -> bool {
+        let local = !self.uri.contains("://") || self.uri.starts_with("file://");
+        !local && !self.uri.starts_with("memory://")
+    }
+
+    /// Whether the dataset was given without the URI of its table, to a query
+    /// of a bare dataset, so that it cannot be reopened and the files its
+    /// table keeps next to it cannot be read.
+    pub(crate) fn is_detached(&self) -> bool {
+        self.uri.is_empty()
+    }
+
+    /// The object store of the dataset, with the table's storage options, and
+    /// the path of the dataset in it.
+    pub(crate) async fn object_store(&self) -> Result<(ObjectStore, Path)> {
+        let params = self.read_params.store_options.clone().unwrap_or_default();
+        Ok(ObjectStore::from_uri_and_params(&self.uri, params).await?)
+    }
+
+    /// The params the dataset is opened with, with the table's session and
+    /// storage options.
+    pub(crate) fn read_params(&self) -> ReadParams {
+        ReadParams {
+            block_size: self.read_params.block_size,
+            index_cache_size: self.read_params.index_cache_size,
+            session: self.read_params.session.clone(),
+            store_options: self.read_params.store_options.clone(),
+        }
+    }
+}
@@ -0,0 +1,328 @@
+// Copyright 2023 LanceDB Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The deletes scanning only the fragments that can have the rows they
+//! delete, see [NativeTable::delete_with_stats].
+//!
+//! lance evaluates the predicate of a delete on every row of the table. A
+//! predicate listing values of the primary key is looked up in the key index
+//! instead, and each fragment of the rows found is scanned for its own keys
+//! only; the fragments of the other partitions of a partitioned table are
+//! left out as they are by the searches. A long list of values is evaluated
+//! in lists of [MAX_IN_LIST_VALUES] values, which lance parses without
+//! nesting them too deeply.
+
+use std::collections::{BTreeMap, HashSet};
+use std::sync::Arc;
+
+use arrow_schema::Schema as ArrowSchema;
+use datafusion::sql::sqlparser::ast::{BinaryOperator, Expr};
+use datafusion::sql::sqlparser::dialect::GenericDialect;
+use datafusion::sql::sqlparser::parser::Parser;
+
+use super::partitions::filter_literal;
+use super::NativeTable;
+use crate::error::Result;
+use crate::query::filter::lance_filter;
+use crate::query::{col, Literal};
+
+/// The most values of a list of values evaluated by lance at once.
+pub const MAX_IN_LIST_VALUES: usize = 1024;
+
+/// How [NativeTable::delete_with_stats] found the rows it deleted.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DeleteStats {
+    /// The version committed by the delete.
+    pub version: u64,
+    /// The number of rows deleted.
+    pub rows_deleted: usize,
+    /// The rows the predicate was evaluated on, those of each fragment
+    /// scanned for each list of values it was evaluated in.
+    pub rows_scanned: usize,
+    /// The fragments that were not scanned.
+    pub fragments_skipped: usize,
+    /// Whether the rows were looked up in the key index of the primary key,
+    /// see [NativeTable::ensure_primary_key].
+    pub index_used: bool,
+}
+
+/// The column and values of a predicate matching the rows where a column is
+/// one of a list of literals, with `=`, `IN` and `OR`. `None` for another
+/// predicate.
+fn listed_values(expr: &Expr) -> Option<(String, Vec<Literal>)> {
+    let column = |expr: &Expr| match expr {
+        Expr::Identifier(ident) => Some(ident.value.clone()),
+        _ => None,
+    };
+    match expr {
+        Expr::Nested(expr) => listed_values(expr),
+        Expr::BinaryOp {
+            left,
+            op: BinaryOperator::Or,
+            right,
+        } => {
+            let (column, mut values) = listed_values(left)?;
+            let (other, more) = listed_values(right)?;
+            values.extend(more);
+            (column == other).then_some((column, values))
+        }
+        Expr::BinaryOp {
+            left,
+            op: BinaryOperator::Eq,
+            right,
+        } => match (column(left), column(right)) {
+            (Some(column), _) => Some((column, vec![literal(right)?])),
+            (_, Some(column)) => Some((column, vec![literal(left)?])),
+            _ => None,
+        },
+        Expr::InList {
+            expr,
+            list,
+            negated: false,
+        } => Some((
+            column(expr)?,
+            list.iter().map(literal).collect::<Option<_>>()?,
+        )),
+        _ => None,
+    }
+}
+
+/// A literal integer or string of a predicate.
+fn literal(expr: &Expr) -> Option<Literal> {
+    match filter_literal(expr)? {
+        serde_json::Value::String(value) => Some(Literal::String(value)),
+        value => value
+            .as_i64()
+            .map(Literal::Int)
+            .or_else(|| value.as_u64().map(Literal::UInt)),
+    }
+}
+
+/// The predicates of `column` being one of `values`, each of at most
+/// [MAX_IN_LIST_VALUES] values.
+fn in_lists(column: &str, values: &[Literal], schema: &ArrowSchema) -> Result<Vec<String>> {
+    values
+        .chunks(MAX_IN_LIST_VALUES)
+        .map(|chunk| {
+            let predicate = col(column).in_list(chunk.iter().cloned()).to_string();
+            Ok(lance_filter(&predicate, schema)?.into_owned())
+        })
+        .collect()
+}
+
+impl NativeTable {
+    /// Delete the rows matching `predicate`, returning how they were found.
+    ///
+    /// A predicate of `=`, `IN` or `OR` of values of the primary key looks
+    /// the rows up in the key index and only scans the fragments of the rows
+    /// found, for their keys. The key index is kept for the version committed
+    /// by the delete, so that later deletes do not build it again. The
+    /// fragments of the other values of the partition column of a partitioned
+    /// table are not scanned either. Lists of more than [MAX_IN_LIST_VALUES]
+    /// values are evaluated a list of as many values at a time.
+    pub async fn delete_with_stats(&self, predicate: &str) -> Result<DeleteStats> {
+        let mut stats = DeleteStats::default();
+        let deleted = &mut stats;
+        let dataset = self
+            .dataset
+            .write(self.max_commit_retries, |latest| async move {
+                let schema = ArrowSchema::from(latest.schema());
+                let listed = Parser::new(&GenericDialect {})
+                    .try_with_sql(predicate)
+                    .and_then(|mut parser| parser.parse_expr())
+                    .ok()
+                    .and_then(|expr| listed_values(&expr));
+                let predicates = match listed.as_ref() {
+                    Some((column, values)) => in_lists(column, values, &schema)?,
+                    None => vec![lance_filter(predicate, &schema)?.into_owned()],
+                };
+                // The predicates each scanned fragment is evaluated on, by id.
+                let mut scanned = BTreeMap::new();
+                let mut found_keys = None;
+                let keys = match listed {
+                    Some((column, values)) => self
+                        .lookup_keys(&latest, &column, values)
+                        .await?
+                        .map(|found| (column, found)),
+                    None => None,
+                };
+                if let Some((column, found)) = keys {
+                    let mut per_fragment = BTreeMap::<u64, Vec<Literal>>::new();
+                    for (row_id, key) in &found {
+                        per_fragment
+                            .entry(row_id >> 32)
+                            .or_default()
+                            .push(key.clone());
+                    }
+                    for (id, keys) in per_fragment {
+                        scanned.insert(id, in_lists(&column, &keys, &schema)?);
+                    }
+                    found_keys = Some(found.into_iter().map(|(_, key)| key).collect::<Vec<_>>());
+                    deleted.index_used = true;
+                } else {
+                    let kept = self
+                        .dataset
+                        .partition_fragments(&latest, predicate)
+                        .await?
+                        .map(|(kept, _)| kept.iter().map(|f| f.id).collect::<HashSet<_>>());
+                    for fragment in latest.get_fragments() {
+                        let id = fragment.id() as u64;
+                        if kept.as_ref().is_none_or(|kept| kept.contains(&id)) {
+                            scanned.insert(id, predicates.clone());
+                        }
+                    }
+                }
+
+                let mut fragments = Vec::new();
+                for fragment in latest.get_fragments() {
+                    let Some(predicates) = scanned.get(&(fragment.id() as u64)) else {
+                        deleted.fragments_skipped += 1;
+                        fragments.push(fragment.metadata().clone());
+                        continue;
+                    };
+                    let before = fragment.count_rows().await?;
+                    let mut rows = before;
+                    let mut remaining = Some(fragment);
+                    for predicate in predicates {
+                        let Some(fragment) = remaining.take() else {
+                            break;
+                        };
+                        deleted.rows_scanned += rows;
+                        remaining = fragment.delete(predicate).await?;
+                        rows = match remaining.as_ref() {
+                            Some(fragment) => fragment.count_rows().await?,
+                            None => 0,
+                        };
+                    }
+                    deleted.rows_deleted += before - rows;
+                    // A fragment without rows left is dropped.
+                    if let Some(fragment) = remaining {
+                        fragments.push(fragment.metadata().clone());
+                    }
+                }
+                // lance drops the indices of a table without fragments.
+                let indices = match fragments.is_empty() {
+                    true => Vec::new(),
+                    false => latest.load_indices().await?,
+                };
+                let written = self
+                    .dataset
+                    .commit_manifest(&latest, indices, |manifest| {
+                        manifest.fragments = Arc::new(fragments);
+                    })
+                    .await?;
+                if let Some(keys) = found_keys {
+                    self.forget_keys(&latest, &written, &keys)?;
+                }
+                Ok(written)
+            })
+            .await?;
+        stats.version = dataset.version().version;
+        Ok(stats)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ops::Range;
+
+    use arrow_array::{Int32Array, RecordBatchReader, StringArray};
+    use lance::arrow::RecordBatchBuffer;
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::arrow::RecordBatchBuilder;
+
+    fn rows(ids: Range<i32>) -> Box<dyn RecordBatchReader> {
+        let names = ids.clone().map(|i| format!("name {i}")).collect::<Vec<_>>();
+        let vectors = ids.clone().map(|i| [i as f32, 1.0]).collect::<Vec<_>>();
+        let batch = RecordBatchBuilder::new()
+            .column("id", Arc::new(Int32Array::from_iter_values(ids)))
+            .column("name", Arc::new(StringArray::from(names)))
+            .vector_column("vector", 2, vectors)
+            .build()
+            .unwrap();
+        Box::new(RecordBatchBuffer::new(vec![batch]))
+    }
+
+    fn in_list(column: &str, values: impl IntoIterator<Item = String>) -> String {
+        format!(
+            "{column} IN ({})",
+            values.into_iter().collect::<Vec<_>>().join(", ")
+        )
+    }
+
+    #[tokio::test]
+    async fn test_delete_with_stats() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        // 10 fragments of 100 rows, of the ids from 0, 100, 200...
+        let table = NativeTable::create(uri, "test", rows(0..100), None)
+            .await
+            .unwrap();
+        for i in 1..10 {
+            table.add(rows(i * 100..(i + 1) * 100), None).await.unwrap();
+        }
+        table.ensure_primary_key("id").await.unwrap();
+
+        // The rows scanned are those of the fragments of the rows deleted.
+        let stats = table.delete_with_stats("id IN (5, 17, 250)").await.unwrap();
+        assert_eq!(
+            stats,
+            DeleteStats {
+                version: table.version(),
+                rows_deleted: 3,
+                rows_scanned: 200,
+                fragments_skipped: 8,
+                index_used: true,
+            }
+        );
+        let stats = table.delete_with_stats("id = 999").await.unwrap();
+        assert_eq!((stats.rows_deleted, stats.rows_scanned), (1, 100));
+        assert!(stats.index_used);
+        // The key index of the new version has no rows of the deleted keys.
+        let stats = table
+            .delete_with_stats("id = 5 OR (id = 17)")
+            .await
+            .unwrap();
+        assert_eq!((stats.rows_deleted, stats.rows_scanned), (0, 0));
+        assert_eq!(stats.fragments_skipped, 10);
+        let batch = table.take_by_key([5, 6, 999]).await.unwrap();
+        assert_eq!(batch.num_rows(), 1);
+
+        // A long list, mostly of missing keys.
+        let ids = (300..330).chain(10_000..19_970).map(|i| i.to_string());
+        let stats = table.delete_with_stats(&in_list("id", ids)).await.unwrap();
+        assert_eq!((stats.rows_deleted, stats.rows_scanned), (30, 100));
+        assert!(stats.index_used);
+        assert_eq!(table.count_rows().await.unwrap(), 966);
+
+        // Without the index every fragment is scanned, for each list of
+        // values: the rows deleted by the first are not scanned by the second.
+        let names = (400..410)
+            .chain(10_000..11_990)
+            .map(|i| format!("'name {i}'"));
+        let stats = table
+            .delete_with_stats(&in_list("name", names))
+            .await
+            .unwrap();
+        assert_eq!((stats.rows_deleted, stats.rows_scanned), (10, 966 + 956));
+        assert_eq!(stats.fragments_skipped, 0);
+        assert!(!stats.index_used);
+        let stats = table.delete_with_stats("id >= 900").await.unwrap();
+        assert_eq!((stats.rows_deleted, stats.rows_scanned), (99, 956));
+        assert!(!stats.index_used);
+        assert_eq!(table.count_rows().await.unwrap(), 857);
+    }
+}
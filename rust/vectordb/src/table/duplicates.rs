@@ -0,0 +1,242 @@
+// Copyright 2023 LanceDB Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Near-duplicate rows, found by searching the table for each of its vectors.
+
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use arrow_array::{Array, FixedSizeListArray, Float32Array, RecordBatch, UInt64Array};
+use arrow_schema::{DataType, Schema as ArrowSchema};
+use futures::stream::{self, BoxStream};
+use futures::{StreamExt, TryStreamExt};
+use lance::dataset::{Dataset, ROW_ID};
+
+use super::NativeTable;
+use crate::error::{Error, Result};
+use crate::io::bad_vectors::{mark_null_vectors, marker_column_of};
+use crate::query::flat::{is_f16_vector, is_multivector};
+use crate::query::{Query, DISTANCE_COLUMN};
+
+/// The searches of [NativeTable::find_duplicates] run at the same time.
+const DUPLICATE_SEARCH_PARALLELISM: usize = 8;
+
+/// Two rows whose vectors are within the threshold of
+/// [NativeTable::find_duplicates].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DuplicatePair {
+    /// The `_rowid` of the first row, the lower of the two.
+    pub row_id: u64,
+    /// The `_rowid` of the second row.
+    pub duplicate_row_id: u64,
+    /// The distance of the vectors, as reported in the `score` column of a search.
+    pub distance: f32,
+}
+
+/// The rows of `dataset` within `threshold` of the row `row_id`, searching for
+/// its `vector` in `column`.
+async fn search_row(
+    dataset: Arc<Dataset>,
+    column: &str,
+    row_id: u64,
+    vector: Float32Array,
+    threshold: f32,
+    limit: usize,
+) -> Result<Vec<(u64, f32)>> {
+    let query = Query::new(dataset, vector)
+        .column(column)
+        .use_index(true)
+        .with_row_id(true)
+        .select(Some(vec![column.to_string()]))
+        // The row itself is among the nearest.
+        .limit(limit + 1);
+    let batches: Vec<RecordBatch> = query.execute().await?.try_collect().await?;
+    let mut found = Vec::new();
+    for batch in &batches {
+        let row_ids = batch[ROW_ID].as_any().downcast_ref::<UInt64Array>();
+        let scores = batch[DISTANCE_COLUMN]
+            .as_any()
+            .downcast_ref::<Float32Array>();
+        let (Some(row_ids), Some(scores)) = (row_ids, scores) else {
+            continue;
+        };
+        for (other, score) in row_ids.values().iter().zip(scores.values().iter()) {
+            if *other != row_id && *score <= threshold {
+                found.push((*other, *score));
+            }
+        }
+    }
+    Ok(found)
+}
+
+/// The row ids and vectors of the rows of `batch` with a vector in `column`.
+fn row_vectors(batch: &RecordBatch, column: &str) -> Result<Vec<(u64, Float32Array)>> {
+    let batch = mark_null_vectors(batch, column, false).map_err(lance::Error::from)?;
+    let row_ids = batch[ROW_ID].as_any().downcast_ref::<UInt64Array>();
+    let vectors = batch[column].as_any().downcast_ref::<FixedSizeListArray>();
+    let (Some(row_ids), Some(vectors)) = (row_ids, vectors) else {
+        return Ok(Vec::new());
+    };
+    Ok((0..batch.num_rows())
+        .filter(|row| vectors.is_valid(*row))
+        .filter_map(|row| {
+            let vector = vectors.value(row);
+            let vector = vector.as_any().downcast_ref::<Float32Array>()?.clone();
+            Some((row_ids.value(row), vector))
+        })
+        .collect())
+}
+
+impl NativeTable {
+    /// The pairs of rows whose vectors in `column` are at most `threshold`
+    /// apart, found by searching the table for the vector of each row and
+    /// keeping up to `limit_per_row` of its nearest rows.
+    ///
+    /// The rows are read and searched a batch at a time from the current
+    /// version of the table, using the index of the column if it has one, and
+    /// the pairs are streamed as they are found. Each pair is returned once,
+    /// with the lower row id first; only the pairs found from one row and not
+    /// yet from the other are kept in memory. A pair may be missed when one of
+    /// its rows has more than `limit_per_row` closer rows.
+    pub async fn find_duplicates(
+        &self,
+        column: &str,
+        threshold: f32,
+        limit_per_row: usize,
+    ) -> Result<BoxStream<'static, Result<DuplicatePair>>> {
+        let dataset = self.dataset.get().await?;
+        let schema = ArrowSchema::from(dataset.schema());
+        let field = schema
+            .field_with_name(column)
+            .map_err(|_| Error::InvalidInput {
+                message: format!("the table has no column '{column}'"),
+            })?;
+        if is_f16_vector(field)
+            || is_multivector(field)
+            || !matches!(field.data_type(), DataType::FixedSizeList(..))
+        {
+            return Err(Error::InvalidInput {
+                message: format!(
+                    "find_duplicates searches columns of float32 vectors, '{column}' is {}",
+                    field.data_type()
+                ),
+            });
+        }
+
+        let mut columns = vec![column.to_string()];
+        columns.extend(marker_column_of(&schema, column));
+        let mut scanner = dataset.scan();
+        scanner.project(&columns)?;
+        scanner.with_row_id();
+        let column = column.to_string();
+        let rows = {
+            let column = column.clone();
+            scanner
+                .try_into_stream()
+                .await?
+                .map_err(Error::from)
+                .map_ok(move |batch| {
+                    let rows = match row_vectors(&batch, &column) {
+                        Ok(rows) => rows.into_iter().map(Ok).collect(),
+                        Err(e) => vec![Err(e)],
+                    };
+                    stream::iter(rows)
+                })
+                .try_flatten()
+        };
+        // The pairs found from their first row only.
+        let mut pending: HashSet<(u64, u64)> = HashSet::new();
+        let pairs = rows
+            .map_ok(move |(row_id, vector)| {
+                let (dataset, column) = (dataset.clone(), column.clone());
+                async move {
+                    let found =
+                        search_row(dataset, &column, row_id, vector, threshold, limit_per_row)
+                            .await?;
+                    Ok::<_, Error>((row_id, found))
+                }
+            })
+            .try_buffered(DUPLICATE_SEARCH_PARALLELISM)
+            .map_ok(move |(row_id, found)| {
+                let mut pairs = Vec::new();
+                for (other, distance) in found {
+                    let key = (row_id.min(other), row_id.max(other));
+                    if !pending.remove(&key) {
+                        pending.insert(key);
+                        pairs.push(Ok::<_, Error>(DuplicatePair {
+                            row_id: key.0,
+                            duplicate_row_id: key.1,
+                            distance,
+                        }));
+                    }
+                }
+                stream::iter(pairs)
+            })
+            .try_flatten();
+        Ok(pairs.boxed())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow_array::{Int32Array, RecordBatchReader};
+    use lance::arrow::RecordBatchBuffer;
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::arrow::RecordBatchBuilder;
+
+    #[tokio::test]
+    async fn test_find_duplicates() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        let mut vectors = (0..30).map(|i| [i as f32 * 10.0, 1.0]).collect::<Vec<_>>();
+        // Planted duplicates at squared distances 0.01, 0.09, 0.25 and 0.
+        vectors.push([30.1, 1.0]);
+        vectors.push([120.3, 1.0]);
+        vectors.push([200.5, 1.0]);
+        vectors.push([50.0, 1.0]);
+        let batch = RecordBatchBuilder::new()
+            .column("id", Arc::new(Int32Array::from_iter_values(0..34)))
+            .vector_column("vector", 2, vectors)
+            .build()
+            .unwrap();
+        let batches: Box<dyn RecordBatchReader> = Box::new(RecordBatchBuffer::new(vec![batch]));
+        let table = NativeTable::create(uri, "test", batches, None)
+            .await
+            .unwrap();
+
+        let find = |threshold| {
+            let table = table.clone();
+            async move {
+                let stream = table.find_duplicates("vector", threshold, 3).await.unwrap();
+                let mut pairs: Vec<DuplicatePair> = stream.try_collect().await.unwrap();
+                pairs.sort_by_key(|p| (p.row_id, p.duplicate_row_id));
+                pairs
+                    .iter()
+                    .map(|p| (p.row_id, p.duplicate_row_id, (p.distance * 100.0).round()))
+                    .collect::<Vec<_>>()
+            }
+        };
+        assert_eq!(
+            find(0.1).await,
+            vec![(3, 30, 1.0), (5, 33, 0.0), (12, 31, 9.0)]
+        );
+        assert_eq!(find(0.0).await, vec![(5, 33, 0.0)]);
+        assert_eq!(find(0.3).await.len(), 4);
+
+        let err = table.find_duplicates("id", 0.1, 3).await.err().unwrap();
+        assert!(matches!(err, Error::InvalidInput { .. }), "{err}");
+    }
+}
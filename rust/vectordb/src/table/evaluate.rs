@@ -0,0 +1,305 @@
+// Copyright 2023 LanceDB Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The recall and latency of the searches of an index, for choosing their
+//! parameters.
+
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use arrow_array::{Array, FixedSizeListArray, Float32Array, RecordBatch, UInt64Array};
+use arrow_schema::{DataType, Schema as ArrowSchema};
+use datafusion::arrow::compute::cast;
+use futures::TryStreamExt;
+use lance::dataset::{Dataset, ROW_ID};
+
+use super::vector_stats::sample_offsets;
+use super::NativeTable;
+use crate::error::{Error, Result};
+use crate::io::bad_vectors::{mark_null_vectors, marker_column_of};
+use crate::query::Query;
+
+/// The queries sampled from the table by [NativeTable::evaluate_index] when
+/// none are given.
+pub const DEFAULT_EVALUATION_QUERIES: usize = 100;
+
+/// Parameters of the searches of an index, see [Query::nprobes] and
+/// [Query::refine_factor].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SearchParams {
+    pub nprobes: usize,
+    pub refine_factor: Option<u32>,
+}
+
+/// The searches of an index with one [SearchParams].
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchEvaluation {
+    pub params: SearchParams,
+    /// The mean over the queries of the fraction of their exact nearest
+    /// neighbors that the searches found.
+    pub recall: f64,
+    /// The mean time of a search.
+    pub mean_latency: Duration,
+    /// The time 95% of the searches took at most.
+    pub p95_latency: Duration,
+}
+
+/// The results of [NativeTable::evaluate_index].
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexEvaluation {
+    /// The number of queries searched for.
+    pub queries: usize,
+    /// The number of nearest neighbors searched for.
+    pub k: usize,
+    /// The mean time of an exact search.
+    pub flat_latency: Duration,
+    /// The evaluation of each parameter combination, in the order given.
+    pub results: Vec<SearchEvaluation>,
+}
+
+/// The row ids of the `k` nearest neighbors of `vector` in `column`, and the
+/// time the search took.
+async fn search(
+    dataset: Arc<Dataset>,
+    column: &str,
+    vector: &Float32Array,
+    k: usize,
+    params: Option<SearchParams>,
+) -> Result<(HashSet<u64>, Duration)> {
+    let mut query = Query::new(dataset, vector.clone())
+        .column(column)
+        .limit(k)
+        .with_row_id(true)
+        .select(Some(vec![column.to_string()]))
+        .use_index(params.is_some());
+    if let Some(params) = params {
+        query = query
+            .nprobes(params.nprobes)
+            .refine_factor(params.refine_factor);
+    }
+    let start = Instant::now();
+    let batches: Vec<RecordBatch> = query.execute().await?.try_collect().await?;
+    let elapsed = start.elapsed();
+    let mut row_ids = HashSet::new();
+    for batch in &batches {
+        let ids = batch[ROW_ID].as_any().downcast_ref::<UInt64Array>();
+        row_ids.extend(ids.expect("row ids are u64").values().iter().copied());
+    }
+    Ok((row_ids, elapsed))
+}
+
+/// The mean and 95th percentile of `latencies`.
+fn latency_stats(mut latencies: Vec<Duration>) -> (Duration, Duration) {
+    if latencies.is_empty() {
+        return (Duration::ZERO, Duration::ZERO);
+    }
+    latencies.sort_unstable();
+    let mean = latencies.iter().sum::<Duration>() / latencies.len() as u32;
+    let p95 = latencies[(latencies.len() * 95).div_ceil(100) - 1];
+    (mean, p95)
+}
+
+/// Up to `count` vectors of `column` sampled evenly from the rows of `dataset`,
+/// leaving out null vectors.
+async fn sample_queries(
+    dataset: &Dataset,
+    column: &str,
+    count: usize,
+) -> Result<Vec<Float32Array>> {
+    let mut columns = vec![column.to_string()];
+    columns.extend(marker_column_of(
+        &ArrowSchema::from(dataset.schema()),
+        column,
+    ));
+    let schema = dataset.schema().project(&columns)?;
+    let fragments = dataset.get_fragments();
+    let mut lengths = Vec::with_capacity(fragments.len());
+    for fragment in &fragments {
+        lengths.push(fragment.fragment_length().await?);
+    }
+    let total = lengths.iter().sum::<usize>();
+    let mut queries = Vec::new();
+    for (fragment, length) in fragments.iter().zip(lengths) {
+        let offsets = sample_offsets(length, total, count.min(total));
+        let batch = fragment.take(&offsets, &schema).await?;
+        let batch = mark_null_vectors(&batch, column, false).map_err(lance::Error::from)?;
+        let Some(vectors) = batch
+            .column(0)
+            .as_any()
+            .downcast_ref::<FixedSizeListArray>()
+        else {
+            return Err(Error::InvalidInput {
+                message: format!("the column '{column}' is not a vector column"),
+            });
+        };
+        for row in 0..vectors.len() {
+            if vectors.is_valid(row) {
+                let vector =
+                    cast(&vectors.value(row), &DataType::Float32).map_err(lance::Error::from)?;
+                let vector = vector.as_any().downcast_ref::<Float32Array>().unwrap();
+                queries.push(vector.clone());
+            }
+        }
+    }
+    queries.truncate(count);
+    Ok(queries)
+}
+
+impl NativeTable {
+    /// Measure the recall@`k` and the latency of the searches of the index of
+    /// `column` with each combination of parameters of `grid`.
+    ///
+    /// The exact nearest neighbors of the queries are found by searching
+    /// without the index, from the same version of the table as the indexed
+    /// searches. The results are not cached, so repeated searches of a
+    /// combination are timed as well. If `queries` is empty,
+    /// [DEFAULT_EVALUATION_QUERIES] vectors are sampled from the table.
+    pub async fn evaluate_index(
+        &self,
+        column: &str,
+        queries: &[Vec<f32>],
+        k: usize,
+        grid: &[SearchParams],
+    ) -> Result<IndexEvaluation> {
+        let dataset = self.dataset.get().await?;
+        let queries = if queries.is_empty() {
+            sample_queries(&dataset, column, DEFAULT_EVALUATION_QUERIES).await?
+        } else {
+            queries
+                .iter()
+                .map(|q| Float32Array::from(q.clone()))
+                .collect()
+        };
+
+        let mut truth = Vec::with_capacity(queries.len());
+        let mut flat_latencies = Vec::with_capacity(queries.len());
+        for query in &queries {
+            let (row_ids, elapsed) = search(dataset.clone(), column, query, k, None).await?;
+            truth.push(row_ids);
+            flat_latencies.push(elapsed);
+        }
+
+        let mut results = Vec::with_capacity(grid.len());
+        for params in grid {
+            let mut recall = 0.0;
+            let mut latencies = Vec::with_capacity(queries.len());
+            for (query, expected) in queries.iter().zip(&truth) {
+                let (found, elapsed) =
+                    search(dataset.clone(), column, query, k, Some(*params)).await?;
+                latencies.push(elapsed);
+                recall += match expected.len() {
+                    0 => 1.0,
+                    len => expected.intersection(&found).count() as f64 / len as f64,
+                };
+            }
+            let (mean_latency, p95_latency) = latency_stats(latencies);
+            results.push(SearchEvaluation {
+                params: *params,
+                recall: recall / queries.len().max(1) as f64,
+                mean_latency,
+                p95_latency,
+            });
+        }
+        Ok(IndexEvaluation {
+            queries: queries.len(),
+            k,
+            flat_latency: latency_stats(flat_latencies).0,
+            results,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::iter::repeat_with;
+
+    use arrow_array::RecordBatchReader;
+    use lance::arrow::RecordBatchBuffer;
+    use lance::index::vector::ivf::IvfBuildParams;
+    use lance::index::vector::pq::PQBuildParams;
+    use rand::Rng;
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::arrow::RecordBatchBuilder;
+    use crate::index::vector::IvfPQIndexBuilder;
+
+    #[test]
+    fn test_latency_stats() {
+        let latencies = (1..=20).map(Duration::from_millis).collect::<Vec<_>>();
+        assert_eq!(
+            latency_stats(latencies),
+            (Duration::from_micros(10_500), Duration::from_millis(19))
+        );
+        assert_eq!(latency_stats(Vec::new()), (Duration::ZERO, Duration::ZERO));
+    }
+
+    #[tokio::test]
+    async fn test_evaluate_index() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        let mut rng = rand::thread_rng();
+        let vectors = repeat_with(|| [(); 4].map(|_| rng.gen_range(0.1..1.0)))
+            .take(512)
+            .collect::<Vec<[f32; 4]>>();
+        let batch = RecordBatchBuilder::new()
+            .vector_column("vector", 4, vectors)
+            .build()
+            .unwrap();
+        let batches: Box<dyn RecordBatchReader> = Box::new(RecordBatchBuffer::new(vec![batch]));
+        let table = NativeTable::create(uri, "test", batches, None)
+            .await
+            .unwrap();
+        let builder = IvfPQIndexBuilder::new()
+            .column("vector")
+            .ivf_params(IvfBuildParams::new(4))
+            .pq_params(PQBuildParams {
+                num_sub_vectors: 2,
+                ..Default::default()
+            });
+        table.create_index(&builder).await.unwrap();
+
+        // All partitions, with the PQ distances of all rows refined.
+        let exact = SearchParams {
+            nprobes: 4,
+            refine_factor: Some(200),
+        };
+        let grid = [
+            SearchParams {
+                nprobes: 1,
+                refine_factor: None,
+            },
+            exact,
+        ];
+        let queries = vec![vec![0.5; 4], vec![0.2, 0.9, 0.4, 0.7]];
+        let report = table
+            .evaluate_index("vector", &queries, 5, &grid)
+            .await
+            .unwrap();
+        assert_eq!((report.queries, report.k), (2, 5));
+        assert_eq!(report.results.len(), 2);
+        assert_eq!(report.results[0].params, grid[0]);
+        assert!(report.results[0].recall <= 1.0);
+        assert_eq!(report.results[1].recall, 1.0);
+        assert!(report.results[1].p95_latency >= report.results[1].mean_latency / 2);
+
+        let sampled = table
+            .evaluate_index("vector", &[], 5, &[exact])
+            .await
+            .unwrap();
+        assert_eq!(sampled.queries, DEFAULT_EVALUATION_QUERIES);
+        assert_eq!(sampled.results[0].recall, 1.0);
+    }
+}
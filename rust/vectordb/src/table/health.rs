@@ -0,0 +1,259 @@
+// Copyright 2023 LanceDB Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The health checks of [crate::Database::health_check] and
+//! [NativeTable::health_check], cheap enough for readiness probes: they read
+//! the storage the way queries do, without running one.
+
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use lance::dataset::Dataset;
+use lance::io::read_manifest;
+
+use super::indices::{check_dims, LATEST_MANIFEST};
+use super::NativeTable;
+use crate::error::{Error, Result};
+
+/// The time each check of a health check may take before it fails with an
+/// [Error::Timeout].
+pub const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// What a [HealthCheck] read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HealthCheckKind {
+    /// The root of the database was listed.
+    Storage,
+    /// The manifest of the latest version of the table was read.
+    Manifest,
+    /// A data file of the table was opened and a row of it read.
+    DataFile,
+    /// The indices of the table were listed, and the header of the first
+    /// vector index read.
+    Index,
+}
+
+/// A passed check of a [HealthReport], with the time it took.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HealthCheck {
+    pub kind: HealthCheckKind,
+    pub latency: Duration,
+}
+
+/// The checks of a health check that passed, in the order they ran. A health
+/// check fails with the error of its first failed check.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HealthReport {
+    /// The URI of the database or table checked.
+    pub uri: String,
+    /// The latest version of the table, `None` for a database.
+    pub version: Option<u64>,
+    pub checks: Vec<HealthCheck>,
+}
+
+impl HealthReport {
+    pub(crate) fn new(uri: &str) -> Self {
+        Self {
+            uri: uri.to_string(),
+            version: None,
+            checks: Vec::new(),
+        }
+    }
+
+    /// The time the check `kind` took, `None` if it was not run.
+    pub fn latency(&self, kind: HealthCheckKind) -> Option<Duration> {
+        self.checks
+            .iter()
+            .find(|c| c.kind == kind)
+            .map(|c| c.latency)
+    }
+
+    /// The time all the checks took.
+    pub fn total_latency(&self) -> Duration {
+        self.checks.iter().map(|c| c.latency).sum()
+    }
+
+    /// Run the check `kind`, failing with an [Error::Timeout] if it takes
+    /// longer than [HEALTH_CHECK_TIMEOUT], and record its latency if it passes.
+    pub(crate) async fn run<T>(
+        &mut self,
+        kind: HealthCheckKind,
+        check: impl Future<Output = Result<T>>,
+    ) -> Result<T> {
+        let started = Instant::now();
+        let value = tokio::time::timeout(HEALTH_CHECK_TIMEOUT, check)
+            .await
+            .map_err(|_| Error::Timeout {
+                uri: self.uri.clone(),
+                timeout: HEALTH_CHECK_TIMEOUT,
+            })??;
+        self.checks.push(HealthCheck {
+            kind,
+            latency: started.elapsed(),
+        });
+        Ok(value)
+    }
+}
+
+/// Open the data file of the first column of the first fragment of `dataset`,
+/// if it has one, and read its first row.
+async fn read_data_file(dataset: &Dataset) -> Result<()> {
+    let (Some(fragment), Some(field)) = (
+        dataset.get_fragments().into_iter().next(),
+        dataset.schema().fields.first(),
+    ) else {
+        return Ok(());
+    };
+    let projection = dataset.schema().project(&[&field.name])?;
+    let reader = fragment.open(&projection).await?;
+    reader.read_range(0..1).await?;
+    Ok(())
+}
+
+impl NativeTable {
+    /// Check that the table can be read, for readiness probes: read the
+    /// manifest of its latest version, and if `deep` is set, one of its data
+    /// files and the header of its first vector index, without running a
+    /// query.
+    ///
+    /// Fails with an [Error::TableNotFound] if the table has no manifest, an
+    /// [Error::ObjectStore] if its storage cannot be reached, an
+    /// [Error::StaleIndex] if its index is of another dimension than its
+    /// column, and an [Error::Timeout] if a check takes longer than
+    /// [HEALTH_CHECK_TIMEOUT].
+    pub async fn health_check(&self, deep: bool) -> Result<HealthReport> {
+        let mut report = HealthReport::new(&self.uri);
+        let version = report
+            .run(HealthCheckKind::Manifest, self.read_latest_version())
+            .await?;
+        report.version = Some(version);
+        if !deep {
+            return Ok(report);
+        }
+        let dataset = report
+            .run(HealthCheckKind::DataFile, async {
+                let dataset = self.dataset.get().await?;
+                read_data_file(&dataset).await?;
+                Ok::<_, Error>(dataset)
+            })
+            .await?;
+        report
+            .run(HealthCheckKind::Index, async {
+                let indices = dataset.load_indices().await?;
+                for index in &indices {
+                    if let Some(vector_index) = self.dataset.read_vector_index(index).await? {
+                        return check_dims(&dataset, index, vector_index.dimension as usize);
+                    }
+                }
+                Ok(())
+            })
+            .await?;
+        Ok(report)
+    }
+
+    /// The version of the latest manifest of the table, read from its store.
+    async fn read_latest_version(&self) -> Result<u64> {
+        let (store, base) = self.dataset.object_store().await?;
+        let path = base.child(LATEST_MANIFEST);
+        match store.inner.head(&path).await {
+            Ok(_) => {}
+            Err(object_store::Error::NotFound { .. }) => {
+                return Err(Error::TableNotFound {
+                    name: self.name.clone(),
+                })
+            }
+            Err(source) => {
+                return Err(Error::ObjectStore {
+                    uri: self.uri.clone(),
+                    source,
+                })
+            }
+        }
+        Ok(read_manifest(&store, &path).await?.version)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_array::Int32Array;
+    use lance::arrow::RecordBatchBuffer;
+    use lance::index::vector::ivf::IvfBuildParams;
+    use lance::index::vector::pq::PQBuildParams;
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::arrow::RecordBatchBuilder;
+    use crate::index::vector::IvfPQIndexBuilder;
+
+    #[tokio::test]
+    async fn test_health_check() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        let vectors = (0..256)
+            .map(|i| (0..8).map(|j| (i * j) as f32).collect::<Vec<f32>>())
+            .collect::<Vec<_>>();
+        let batch = RecordBatchBuilder::new()
+            .column("id", Arc::new(Int32Array::from_iter_values(0..256)))
+            .vector_column("vector", 8, vectors)
+            .build()
+            .unwrap();
+        let reader = Box::new(RecordBatchBuffer::new(vec![batch]));
+        let table = NativeTable::create(uri, "test", reader, None)
+            .await
+            .unwrap();
+        let builder = IvfPQIndexBuilder::new()
+            .column("vector")
+            .ivf_params(IvfBuildParams::new(2))
+            .pq_params(PQBuildParams {
+                num_sub_vectors: 2,
+                ..Default::default()
+            });
+        table.create_index(&builder).await.unwrap();
+
+        let report = table.health_check(false).await.unwrap();
+        let version = table.dataset.current().version().version;
+        assert_eq!(report.version, Some(version));
+        let kinds = report.checks.iter().map(|c| c.kind).collect::<Vec<_>>();
+        assert_eq!(kinds, vec![HealthCheckKind::Manifest]);
+
+        let report = table.health_check(true).await.unwrap();
+        let kinds = report.checks.iter().map(|c| c.kind).collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            vec![
+                HealthCheckKind::Manifest,
+                HealthCheckKind::DataFile,
+                HealthCheckKind::Index
+            ]
+        );
+        assert!(report.latency(HealthCheckKind::DataFile).is_some());
+        assert!(report.latency(HealthCheckKind::Storage).is_none());
+        assert_eq!(
+            report.total_latency(),
+            report.checks.iter().map(|c| c.latency).sum::<Duration>()
+        );
+
+        // A table whose data files are gone passes the shallow check only.
+        let path = std::path::Path::new(table.uri());
+        std::fs::remove_dir_all(path.join("data")).unwrap();
+        table.health_check(false).await.unwrap();
+        table.health_check(true).await.unwrap_err();
+
+        std::fs::remove_dir_all(path).unwrap();
+        let err = table.health_check(false).await.unwrap_err();
+        assert!(matches!(err, Error::TableNotFound { .. }), "{err}");
+    }
+}
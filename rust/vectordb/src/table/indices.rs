@@ -0,0 +1,485 @@
+// Copyright 2023 LanceDB Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Vector indices left over a column of another dimension, indices whose
+//! files cannot be read, the rows indices cover, and dropping indices.
+//!
+//! An index records the dimension of the vectors it was built on, and lance
+//! searches it without comparing it with the column, returning wrong results
+//! or failing deep in the search.
+
+use std::collections::HashSet;
+use std::sync::Mutex;
+
+use arrow_schema::DataType;
+use lance::dataset::Dataset;
+use lance::format::{Index, Manifest};
+use lance::io::{read_manifest, write_manifest};
+
+use super::ivf::named_index;
+use super::{DatasetRef, NativeTable};
+use crate::error::{Error, Result};
+
+/// The version of a table an index was trained on, and the rows it covers, see
+/// [NativeTable::index_stats].
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexStats {
+    pub index_name: String,
+    /// The indexed column.
+    pub column: String,
+    /// The version of the table the index was trained on, recorded by lance
+    /// with the index: the version committing the index, of the rows it was
+    /// trained on.
+    pub trained_at_version: u64,
+    /// The rows of the table at [Self::trained_at_version].
+    pub rows_at_training: usize,
+    /// The rows of the fragments the index was trained on, less those deleted
+    /// since.
+    pub indexed_rows: usize,
+    /// The rows of the fragments appended since the index was trained, which
+    /// searches scan without the index.
+    pub unindexed_rows: usize,
+    /// The filter of the rows the index covers, `None` if it covers all rows.
+    /// [Self::indexed_rows] counts all the rows of its fragments.
+    pub row_filter: Option<String>,
+    /// Whether the files of the index can be searched.
+    pub health: IndexHealth,
+}
+
+/// Whether an index can be searched, see [IndexStats::health].
+#[derive(Debug, Clone, PartialEq)]
+pub enum IndexHealth {
+    Healthy,
+    /// The index files are missing or cannot be read, or the index is of
+    /// another dimension than its column. Searches with the index fail,
+    /// unless they fall back to a flat search, see
+    /// [crate::query::Query::allow_index_fallback].
+    Unhealthy {
+        reason: String,
+    },
+}
+
+/// The directory of the manifests of the versions of a dataset.
+pub(super) const VERSIONS_DIR: &str = "_versions";
+
+/// The manifest of the latest version of a dataset.
+pub(super) const LATEST_MANIFEST: &str = "_latest.manifest";
+
+/// The columns whose indices were found to be of their dimension, for a
+/// version of a table.
+#[derive(Debug, Default)]
+pub(crate) struct CheckedIndices {
+    columns: Mutex<(u64, HashSet<String>)>,
+}
+
+/// Fail with an [Error::StaleIndex] if the column of `index` in `dataset` has
+/// vectors of another dimension than `dims`, the dimension of the index.
+pub(super) fn check_dims(dataset: &Dataset, index: &Index, dims: usize) -> Result<()> {
+    let schema = dataset.schema().project_by_ids(&index.fields)?;
+    let Some(field) = schema.fields.first() else {
+        return Ok(());
+    };
+    match field.data_type() {
+        DataType::FixedSizeList(_, width) if width as usize != dims => Err(Error::StaleIndex {
+            index: index.name.clone(),
+            expected_dim: dims,
+            column_dim: width as usize,
+        }),
+        _ => Ok(()),
+    }
+}
+
+impl DatasetRef {
+    /// Fail with an [Error::StaleIndex] if an index of `column` in `dataset`
+    /// is of another dimension than the vectors of the column.
+    ///
+    /// The dimensions of the indices are read from their index files once
+    /// for each version of the table.
+    pub(crate) async fn check_index_dims(&self, dataset: &Dataset, column: &str) -> Result<()> {
+        // The indices of a bare dataset are not read from its store.
+        if self.is_detached() {
+            return Ok(());
+        }
+        let version = dataset.version().version;
+        {
+            let checked = self.checked_indices().columns.lock().unwrap();
+            if checked.0 == version && checked.1.contains(column) {
+                return Ok(());
+            }
+        }
+        let Some(field) = dataset.schema().field(column) else {
+            return Ok(());
+        };
+        let indices = dataset.load_indices().await?;
+        for index in indices.iter().filter(|i| i.fields.contains(&field.id)) {
+            if let Some(vector_index) = self.read_vector_index(index).await? {
+                check_dims(dataset, index, vector_index.dimension as usize)?;
+            }
+        }
+        let mut checked = self.checked_indices().columns.lock().unwrap();
+        if checked.0 != version {
+            *checked = (version, HashSet::new());
+        }
+        checked.1.insert(column.to_string());
+        Ok(())
+    }
+
+    /// Whether `index` of `dataset` can be searched: its index file can be read
+    /// and it is of the dimension of its column.
+    pub(crate) async fn index_health(&self, dataset: &Dataset, index: &Index) -> IndexHealth {
+        // The indices of a bare dataset are not read from its store.
+        if self.is_detached() {
+            return IndexHealth::Healthy;
+        }
+        let checked = match self.read_vector_index(index).await {
+            Ok(Some(vector_index)) => check_dims(dataset, index, vector_index.dimension as usize),
+            Ok(None) => Ok(()),
+            Err(e) => Err(e),
+        };
+        match checked {
+            Ok(()) => IndexHealth::Healthy,
+            Err(e) => IndexHealth::Unhealthy {
+                reason: e.to_string(),
+            },
+        }
+    }
+
+    /// The name of the first index of `column` in `dataset` that cannot be
+    /// searched, with the reason, `None` if all of them can.
+    pub(crate) async fn unhealthy_index(
+        &self,
+        dataset: &Dataset,
+        column: &str,
+    ) -> Result<Option<(String, String)>> {
+        let Some(field) = dataset.schema().field(column) else {
+            return Ok(None);
+        };
+        let indices = dataset.load_indices().await?;
+        for index in indices.iter().filter(|i| i.fields.contains(&field.id)) {
+            if let IndexHealth::Unhealthy { reason } = self.index_health(dataset, index).await {
+                return Ok(Some((index.name.clone(), reason)));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Commit the rows of `dataset`, the latest version, as a new version
+    /// with the indices `indices`.
+    pub(super) async fn commit_indices(
+        &self,
+        dataset: &Dataset,
+        indices: Vec<Index>,
+    ) -> Result<Dataset> {
+        self.commit_manifest(dataset, indices, |_| ()).await
+    }
+
+    /// Commit the rows of `dataset`, the latest version, as a new version
+    /// with the indices `indices` and its manifest changed by `update`.
+    pub(super) async fn commit_manifest(
+        &self,
+        dataset: &Dataset,
+        indices: Vec<Index>,
+        update: impl FnOnce(&mut Manifest),
+    ) -> Result<Dataset> {
+        let (store, base) = self.object_store().await?;
+        let version = dataset.version().version;
+        let versions = base.child(VERSIONS_DIR);
+        let mut manifest =
+            read_manifest(&store, &versions.child(format!("{version}.manifest"))).await?;
+        // The manifest is read without the values of its dictionary fields,
+        // which are written with it.
+        manifest.schema = dataset.schema().clone();
+        update(&mut manifest);
+        manifest.version = version + 1;
+        manifest.tag = None;
+        manifest.set_timestamp(None);
+        for path in [
+            versions.child(format!("{}.manifest", version + 1)),
+            base.child(LATEST_MANIFEST),
+        ] {
+            let mut writer = store.create(&path).await?;
+            let position =
+                write_manifest(&mut writer, &mut manifest, Some(indices.clone())).await?;
+            writer.write_magics(position).await?;
+            writer.shutdown().await?;
+        }
+        Ok(dataset.checkout_version(version + 1).await?)
+    }
+}
+
+impl NativeTable {
+    /// Drop the index `name`, for example when its column has vectors of
+    /// another dimension than the index, see [Error::StaleIndex].
+    ///
+    /// Searches of the column are flat until an index is created again, and
+    /// the index files stay for the older versions. Fails with an
+    /// [Error::IndexNotFound] if the table has no index `name`.
+    pub async fn drop_index(&self, name: &str) -> Result<()> {
+        let not_found = || Error::IndexNotFound {
+            name: name.to_string(),
+        };
+        self.dataset
+            .write(self.max_commit_retries, |latest| async move {
+                let indices = latest.load_indices().await?;
+                if !indices.iter().any(|i| i.name == name) {
+                    return Err(not_found());
+                }
+                let kept = indices.into_iter().filter(|i| i.name != name).collect();
+                self.dataset.commit_indices(&latest, kept).await
+            })
+            .await?;
+        self.forget_index_config(name).await
+    }
+
+    /// The version the index `name` was trained on, how many rows of the
+    /// current version it covers, and whether its files can be searched.
+    ///
+    /// The rows of the fragments written after the training version are not
+    /// indexed, whether appended or rewritten by a compaction. Fails with an
+    /// [Error::IndexNotFound] if the table has no index `name`.
+    pub async fn index_stats(&self, name: &str) -> Result<IndexStats> {
+        let dataset = self.dataset.get().await?;
+        let index = named_index(&dataset, name).await?;
+        let column = dataset.schema().project_by_ids(&index.fields)?.fields[0]
+            .name
+            .clone();
+        let health = self.dataset.index_health(&dataset, &index).await;
+        let row_filter = self.dataset.row_filter(&index).await?;
+        let trained = dataset.checkout_version(index.dataset_version).await?;
+        let trained_fragments = trained
+            .get_fragments()
+            .iter()
+            .map(|f| f.id())
+            .collect::<HashSet<_>>();
+        let mut stats = IndexStats {
+            index_name: index.name,
+            column,
+            trained_at_version: index.dataset_version,
+            rows_at_training: trained.count_rows().await?,
+            indexed_rows: 0,
+            unindexed_rows: 0,
+            row_filter: row_filter.map(|f| f.to_string()),
+            health,
+        };
+        for fragment in dataset.get_fragments() {
+            let rows = fragment.count_rows().await?;
+            if trained_fragments.contains(&fragment.id()) {
+                stats.indexed_rows += rows;
+            } else {
+                stats.unindexed_rows += rows;
+            }
+        }
+        Ok(stats)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use arrow_array::RecordBatchReader;
+    use futures::TryStreamExt;
+    use lance::arrow::RecordBatchBuffer;
+    use lance::dataset::WriteMode;
+    use lance::index::vector::ivf::IvfBuildParams;
+    use lance::index::vector::pq::PQBuildParams;
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::arrow::RecordBatchBuilder;
+    use crate::index::vector::IvfPQIndexBuilder;
+
+    fn rows(dims: usize) -> Box<dyn RecordBatchReader> {
+        let vectors = (0..256)
+            .map(|i| {
+                (0..dims)
+                    .map(|d| (i * dims + d + 1) as f32)
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        let batch = RecordBatchBuilder::new()
+            .vector_column("vector", dims as i32, vectors)
+            .build()
+            .unwrap();
+        Box::new(RecordBatchBuffer::new(vec![batch]))
+    }
+
+    fn index_builder() -> IvfPQIndexBuilder {
+        IvfPQIndexBuilder::new()
+            .column("vector")
+            .index_name("vector_idx")
+            .ivf_params(IvfBuildParams::new(2))
+            .pq_params(PQBuildParams {
+                num_sub_vectors: 2,
+                ..Default::default()
+            })
+    }
+
+    async fn search(table: &NativeTable, dims: usize) -> Result<usize> {
+        let batches = table
+            .search(vec![1.0; dims])
+            .use_index(true)
+            .limit(5)
+            .execute()
+            .await?
+            .try_collect::<Vec<_>>()
+            .await?;
+        Ok(batches.iter().map(|b| b.num_rows()).sum())
+    }
+
+    /// `table` overwritten with vectors of 8 dimensions, keeping its index of
+    /// 4, as writers keeping the indices over a change of the vectors leave
+    /// a table.
+    async fn stale_table(uri: &str) -> NativeTable {
+        let table = NativeTable::create(uri, "test", rows(4), None)
+            .await
+            .unwrap();
+        table.create_index(&index_builder()).await.unwrap();
+        let indices = table.dataset.get().await.unwrap().load_indices().await;
+        table
+            .add(rows(8), Some(WriteMode::Overwrite))
+            .await
+            .unwrap();
+        let latest = table.dataset.get().await.unwrap();
+        assert!(latest.load_indices().await.unwrap().is_empty());
+        let stale = table
+            .dataset
+            .commit_indices(&latest, indices.unwrap())
+            .await
+            .unwrap();
+        table.dataset.commit(stale).await.unwrap();
+        table
+    }
+
+    #[tokio::test]
+    async fn test_stale_index() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        let table = stale_table(uri).await;
+        let err = search(&table, 8).await.unwrap_err();
+        let Error::StaleIndex {
+            index,
+            expected_dim,
+            column_dim,
+        } = err
+        else {
+            panic!("expected a stale index, got {err}");
+        };
+        assert_eq!(
+            (index.as_str(), expected_dim, column_dim),
+            ("vector_idx", 4, 8)
+        );
+        let err = table.index_partition_stats("vector_idx").await.unwrap_err();
+        assert!(matches!(err, Error::StaleIndex { .. }), "{err}");
+        let health = table.index_stats("vector_idx").await.unwrap().health;
+        assert!(
+            matches!(health, IndexHealth::Unhealthy { .. }),
+            "{health:?}"
+        );
+        // A flat search does not use the index.
+        let flat = table.search(vec![1.0; 8]).limit(5);
+        let batches = flat.execute().await.unwrap().try_collect::<Vec<_>>();
+        assert_eq!(batches.await.unwrap()[0].num_rows(), 5);
+
+        table.drop_index("vector_idx").await.unwrap();
+        assert_eq!(search(&table, 8).await.unwrap(), 5);
+        let reopened = NativeTable::open(uri, "test").await.unwrap();
+        assert_eq!(search(&reopened, 8).await.unwrap(), 5);
+        let err = table.drop_index("vector_idx").await.unwrap_err();
+        assert!(matches!(err, Error::IndexNotFound { .. }), "{err}");
+    }
+
+    #[tokio::test]
+    async fn test_replace_stale_index() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        let table = stale_table(uri).await;
+        assert!(search(&table, 8).await.is_err());
+        table.create_index(&index_builder()).await.unwrap();
+        assert_eq!(search(&table, 8).await.unwrap(), 5);
+        let stats = table.index_partition_stats("vector_idx").await.unwrap();
+        assert_eq!(stats.indexed_rows, 256);
+    }
+
+    #[tokio::test]
+    async fn test_missing_index_files() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        let table = NativeTable::create(uri, "test", rows(4), None)
+            .await
+            .unwrap();
+        table.create_index(&index_builder()).await.unwrap();
+        let health = table.index_stats("vector_idx").await.unwrap().health;
+        assert_eq!(health, IndexHealth::Healthy);
+        let dataset = table.dataset.get().await.unwrap();
+        let uuid = dataset.load_indices().await.unwrap()[0].uuid;
+        let index_dir = Path::new(table.uri())
+            .join("_indices")
+            .join(uuid.to_string());
+        std::fs::remove_dir_all(index_dir).unwrap();
+
+        // The index files are read by a table opened after they are gone.
+        let table = NativeTable::open(uri, "test").await.unwrap();
+        assert!(search(&table, 4).await.is_err());
+        let fallback = table
+            .search(vec![1.0; 4])
+            .use_index(true)
+            .allow_index_fallback(true)
+            .limit(5);
+        let batches = fallback.execute().await.unwrap();
+        let batches = batches.try_collect::<Vec<_>>().await.unwrap();
+        assert_eq!(batches.iter().map(|b| b.num_rows()).sum::<usize>(), 5);
+        let health = table.index_stats("vector_idx").await.unwrap().health;
+        assert!(
+            matches!(health, IndexHealth::Unhealthy { .. }),
+            "{health:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_index_stats() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        let table = NativeTable::create(uri, "test", rows(4), None)
+            .await
+            .unwrap();
+        table.create_index(&index_builder()).await.unwrap();
+        let trained_at_version = table.version();
+        let stats = table.index_stats("vector_idx").await.unwrap();
+        assert_eq!(
+            stats,
+            IndexStats {
+                index_name: "vector_idx".to_string(),
+                column: "vector".to_string(),
+                trained_at_version,
+                rows_at_training: 256,
+                indexed_rows: 256,
+                unindexed_rows: 0,
+                row_filter: None,
+                health: IndexHealth::Healthy,
+            }
+        );
+
+        table.add(rows(4), None).await.unwrap();
+        table.add(rows(4), None).await.unwrap();
+        let stats = table.index_stats("vector_idx").await.unwrap();
+        assert_eq!(stats.trained_at_version, trained_at_version);
+        assert_eq!(stats.rows_at_training, 256);
+        assert_eq!(stats.indexed_rows, 256);
+        assert_eq!(stats.unindexed_rows, 512);
+
+        let err = table.index_stats("missing").await.unwrap_err();
+        assert!(matches!(err, Error::IndexNotFound { .. }), "{err}");
+    }
+}
@@ -0,0 +1,475 @@
+// Copyright 2023 LanceDB Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The partitions of IVF indices, read from the metadata of their index files.
+//!
+//! The centroids and partition lengths are at the end of the file, so reading
+//! them does not load the index.
+
+use std::collections::HashSet;
+
+use lance::dataset::Dataset;
+use lance::format::Index;
+use lance::index::pb;
+use lance::index::vector::MetricType;
+use lance::io::{read_message_from_buf, read_metadata_offset};
+
+use super::indices::check_dims;
+use super::{DatasetRef, NativeTable};
+use crate::error::{Error, Result};
+
+/// The file of a vector index, in its directory under `_indices`.
+pub(super) const INDEX_FILE: &str = "index.idx";
+
+/// The bytes read from the end of an index file to find its metadata.
+const INDEX_TAIL_BYTES: usize = 64 * 1024;
+
+/// The IVF stage of a vector index.
+pub(super) struct IvfIndex {
+    pub(super) dims: usize,
+    pub(super) metric_type: MetricType,
+    pub(super) ivf: pb::Ivf,
+}
+
+impl IvfIndex {
+    /// The distances of `vector` to the centroids, by the metric of the index.
+    fn distances(&self, vector: &[f32]) -> Vec<f32> {
+        let distances = self.metric_type.batch_func()(vector, &self.ivf.centroids, self.dims);
+        distances.values().to_vec()
+    }
+
+    /// The partitions in the order a search of `vector` probes them, those of
+    /// the nearest centroids first.
+    fn probe_order(&self, vector: &[f32]) -> Vec<usize> {
+        let distances = self.distances(vector);
+        let mut order = (0..distances.len()).collect::<Vec<_>>();
+        order.sort_by(|a, b| distances[*a].total_cmp(&distances[*b]));
+        order
+    }
+
+    /// The partition of `vector`, that of the nearest centroid as lance
+    /// assigns the rows it indexes. `None` if the index has no partitions.
+    fn partition(&self, vector: &[f32]) -> Option<u32> {
+        let distances = self.distances(vector);
+        let nearest = (0..distances.len()).min_by(|a, b| distances[*a].total_cmp(&distances[*b]));
+        nearest.map(|partition| partition as u32)
+    }
+}
+
+/// The partitions of the rows of a column in its IVF index, see
+/// [crate::query::Query::with_partition_id].
+pub(crate) struct ColumnPartitions {
+    ivf: IvfIndex,
+    /// The fragments of the rows the index was trained on.
+    fragments: HashSet<u64>,
+}
+
+impl ColumnPartitions {
+    /// The partition of the row `row_id` of `vector`, `None` if the row was
+    /// appended since the index was trained.
+    pub(crate) fn partition(&self, row_id: Option<u64>, vector: &[f32]) -> Option<u32> {
+        if row_id.is_some_and(|id| !self.fragments.contains(&(id >> 32))) {
+            return None;
+        }
+        self.ivf.partition(vector)
+    }
+}
+
+/// A partition of an IVF index, see [NativeTable::index_partition_stats].
+#[derive(Debug, Clone, PartialEq)]
+pub struct PartitionStats {
+    /// The position of the partition in the index.
+    pub partition: usize,
+    /// The rows of the partition.
+    pub rows: usize,
+    /// The fraction of the indexed rows in the partition.
+    pub fraction: f64,
+    /// The L2 norm of the centroid of the partition.
+    pub centroid_norm: f32,
+}
+
+/// The partitions of an IVF index, see [NativeTable::index_partition_stats].
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexPartitionStats {
+    pub index_name: String,
+    /// The indexed column.
+    pub column: String,
+    pub metric_type: MetricType,
+    /// The rows covered by the index, the sum of the rows of its partitions.
+    pub indexed_rows: usize,
+    pub partitions: Vec<PartitionStats>,
+}
+
+impl IndexPartitionStats {
+    /// The partition of the most rows, `None` if the index has no partitions.
+    ///
+    /// A partition of a large [PartitionStats::fraction] is scanned by most
+    /// searches, and the others have too few rows for their neighbors to be
+    /// found with few probes.
+    pub fn largest(&self) -> Option<&PartitionStats> {
+        self.partitions.iter().max_by_key(|p| p.rows)
+    }
+}
+
+/// A partition probed by a search, see [NativeTable::debug_probe].
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProbedPartition {
+    pub partition: usize,
+    /// The distance of the query vector to the centroid of the partition, by
+    /// the metric of the index. lance probes the partitions of the smallest
+    /// distances.
+    pub distance: f32,
+    /// The rows of the partition.
+    pub rows: usize,
+}
+
+/// The latest index of `dataset` named `name`.
+pub(super) async fn named_index(dataset: &Dataset, name: &str) -> Result<Index> {
+    dataset
+        .load_indices()
+        .await?
+        .into_iter()
+        .filter(|i| i.name == name)
+        .max_by_key(|i| i.dataset_version)
+        .ok_or_else(|| Error::IndexNotFound {
+            name: name.to_string(),
+        })
+}
+
+impl DatasetRef {
+    /// The metadata of the vector index `index`, `None` if it is not a vector
+    /// index.
+    pub(super) async fn read_vector_index(&self, index: &Index) -> Result<Option<pb::VectorIndex>> {
+        let (store, base) = self.object_store().await?;
+        let path = base
+            .child("_indices")
+            .child(index.uuid.to_string())
+            .child(INDEX_FILE);
+        let size = store.inner.head(&path).await?.size;
+        let begin = size.saturating_sub(INDEX_TAIL_BYTES);
+        let tail = store.inner.get_range(&path, begin..size).await?;
+        let position = read_metadata_offset(&tail).map_err(lance::Error::from)?;
+        let metadata = if position < begin {
+            store.inner.get_range(&path, position..size).await?
+        } else {
+            tail.slice(position - begin..)
+        };
+        let proto: pb::Index = read_message_from_buf(&metadata).map_err(lance::Error::from)?;
+        match proto.implementation {
+            Some(pb::index::Implementation::VectorIndex(vector_index)) => Ok(Some(vector_index)),
+            _ => Ok(None),
+        }
+    }
+
+    /// The IVF stage of `index`, `None` if it is not an IVF index, or if its
+    /// vectors are transformed before partitioning, as by OPQ.
+    pub(super) async fn read_ivf_index(&self, index: &Index) -> Result<Option<IvfIndex>> {
+        let Some(vector_index) = self.read_vector_index(index).await? else {
+            return Ok(None);
+        };
+        let metric_type = pb::VectorMetricType::from_i32(vector_index.metric_type)
+            .map(MetricType::from)
+            .unwrap_or(MetricType::L2);
+        let mut stages = vector_index.stages.into_iter().filter_map(|s| s.stage);
+        let Some(pb::vector_index_stage::Stage::Ivf(ivf)) = stages.next() else {
+            return Ok(None);
+        };
+        Ok(Some(IvfIndex {
+            dims: vector_index.dimension as usize,
+            metric_type,
+            ivf,
+        }))
+    }
+
+    /// The latest IVF index of `column` in `dataset`, `None` if the column
+    /// has no IVF index of its dimension.
+    async fn column_ivf_index(
+        &self,
+        dataset: &Dataset,
+        column: &str,
+    ) -> Result<Option<(Index, IvfIndex)>> {
+        // The indices of a bare dataset are not read from its store.
+        if self.is_detached() {
+            return Ok(None);
+        }
+        let Some(field_id) = dataset.schema().field(column).map(|f| f.id) else {
+            return Ok(None);
+        };
+        let indices = dataset.load_indices().await?;
+        let Some(index) = indices
+            .into_iter()
+            .filter(|i| i.fields.contains(&field_id))
+            .max_by_key(|i| i.dataset_version)
+        else {
+            return Ok(None);
+        };
+        let Some(ivf) = self.read_ivf_index(&index).await? else {
+            return Ok(None);
+        };
+        if check_dims(dataset, &index, ivf.dims).is_err() {
+            return Ok(None);
+        }
+        Ok(Some((index, ivf)))
+    }
+
+    /// The partitions of the rows of `column` in `dataset`, of its latest IVF
+    /// index. `None` if the column has no IVF index of its dimension.
+    pub(crate) async fn column_partitions(
+        &self,
+        dataset: &Dataset,
+        column: &str,
+    ) -> Result<Option<ColumnPartitions>> {
+        let Some((index, ivf)) = self.column_ivf_index(dataset, column).await? else {
+            return Ok(None);
+        };
+        let trained = dataset.checkout_version(index.dataset_version).await?;
+        let fragments = trained
+            .get_fragments()
+            .iter()
+            .map(|f| f.id() as u64)
+            .collect();
+        Ok(Some(ColumnPartitions { ivf, fragments }))
+    }
+
+    /// The rows of the partitions of the latest IVF index of `column` in
+    /// `dataset`, in the order a search of `vector` probes them. `None` if the
+    /// column has no IVF index of the dimension of `vector`.
+    pub(crate) async fn probed_rows(
+        &self,
+        dataset: &Dataset,
+        column: &str,
+        vector: &[f32],
+    ) -> Result<Option<Vec<usize>>> {
+        let Some((_, ivf)) = self.column_ivf_index(dataset, column).await? else {
+            return Ok(None);
+        };
+        if ivf.dims != vector.len() {
+            return Ok(None);
+        }
+        let order = ivf.probe_order(vector);
+        Ok(Some(
+            order
+                .into_iter()
+                .map(|partition| ivf.ivf.lengths[partition] as usize)
+                .collect(),
+        ))
+    }
+}
+
+impl NativeTable {
+    /// The IVF stage of the index `name` of `dataset`, failing with an
+    /// [Error::IndexNotFound] if there is none, and with an
+    /// [Error::StaleIndex] if its column no longer has vectors of its
+    /// dimension.
+    async fn named_ivf_index(&self, dataset: &Dataset, name: &str) -> Result<(Index, IvfIndex)> {
+        let not_found = || Error::IndexNotFound {
+            name: name.to_string(),
+        };
+        let index = named_index(dataset, name).await?;
+        let ivf = self
+            .dataset
+            .read_ivf_index(&index)
+            .await?
+            .ok_or_else(not_found)?;
+        check_dims(dataset, &index, ivf.dims)?;
+        Ok((index, ivf))
+    }
+
+    /// The rows and centroid norms of the partitions of the IVF index `name`,
+    /// to find the skewed partitions of an index of poor recall.
+    ///
+    /// The rows appended since the index was built are in no partition. Fails
+    /// with an [Error::IndexNotFound] if the table has no IVF index `name`.
+    pub async fn index_partition_stats(&self, name: &str) -> Result<IndexPartitionStats> {
+        let dataset = self.dataset.get().await?;
+        let (index, ivf) = self.named_ivf_index(&dataset, name).await?;
+        let column = dataset.schema().project_by_ids(&index.fields)?.fields[0]
+            .name
+            .clone();
+        let indexed_rows = ivf.ivf.lengths.iter().map(|l| *l as usize).sum::<usize>();
+        let partitions = ivf
+            .ivf
+            .lengths
+            .iter()
+            .zip(ivf.ivf.centroids.chunks_exact(ivf.dims.max(1)))
+            .enumerate()
+            .map(|(partition, (rows, centroid))| PartitionStats {
+                partition,
+                rows: *rows as usize,
+                fraction: *rows as f64 / indexed_rows.max(1) as f64,
+                centroid_norm: centroid.iter().map(|v| v * v).sum::<f32>().sqrt(),
+            })
+            .collect();
+        Ok(IndexPartitionStats {
+            index_name: index.name,
+            column,
+            metric_type: ivf.metric_type,
+            indexed_rows,
+            partitions,
+        })
+    }
+
+    /// The first `n` partitions of the IVF index `name` a search of `vector`
+    /// probes, in the order lance ranks them.
+    ///
+    /// A search of `nprobes` scans the first `nprobes` of them. Fails with an
+    /// [Error::InvalidInput] if `vector` is not of the dimension of the index.
+    pub async fn debug_probe(
+        &self,
+        name: &str,
+        vector: &[f32],
+        n: usize,
+    ) -> Result<Vec<ProbedPartition>> {
+        let ivf = self.probed_ivf_index(name, vector).await?;
+        let distances = ivf.distances(vector);
+        Ok(ivf
+            .probe_order(vector)
+            .into_iter()
+            .take(n)
+            .map(|partition| ProbedPartition {
+                partition,
+                distance: distances[partition],
+                rows: ivf.ivf.lengths[partition] as usize,
+            })
+            .collect())
+    }
+
+    /// The partition of the IVF index `name` the rows of `vector` are in, the
+    /// first one a search of `vector` probes.
+    ///
+    /// The partition of a result of a search is in its
+    /// [crate::query::IVF_PARTITION_COLUMN], see
+    /// [crate::query::Query::with_partition_id]. Fails with an
+    /// [Error::InvalidInput] if `vector` is not of the dimension of the index.
+    pub async fn assign_partition(&self, name: &str, vector: &[f32]) -> Result<u32> {
+        let ivf = self.probed_ivf_index(name, vector).await?;
+        ivf.partition(vector).ok_or_else(|| Error::InvalidInput {
+            message: format!("the index '{name}' has no partitions"),
+        })
+    }
+
+    /// The IVF stage of the index `name`, failing with an
+    /// [Error::InvalidInput] if `vector` is not of its dimension.
+    async fn probed_ivf_index(&self, name: &str, vector: &[f32]) -> Result<IvfIndex> {
+        let dataset = self.dataset.get().await?;
+        let (_, ivf) = self.named_ivf_index(&dataset, name).await?;
+        if vector.len() != ivf.dims {
+            return Err(Error::InvalidInput {
+                message: format!(
+                    "the index '{name}' has vectors of {} dimensions, the query has {}",
+                    ivf.dims,
+                    vector.len()
+                ),
+            });
+        }
+        Ok(ivf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_array::RecordBatchReader;
+    use lance::arrow::RecordBatchBuffer;
+    use lance::index::vector::ivf::IvfBuildParams;
+    use lance::index::vector::pq::PQBuildParams;
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::arrow::{vec_to_fixed_size_list, RecordBatchBuilder};
+    use crate::index::vector::IvfPQIndexBuilder;
+
+    fn vectors(vectors: Vec<[f32; 3]>) -> Box<dyn RecordBatchReader> {
+        let batch = RecordBatchBuilder::new()
+            .vector_column("vector", 3, vectors)
+            .build()
+            .unwrap();
+        Box::new(RecordBatchBuffer::new(vec![batch]))
+    }
+
+    #[tokio::test]
+    async fn test_partition_stats() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        // Three clusters of 300, 60 and 40 rows, indexed with a partition each.
+        let rows = (0..400)
+            .map(|i| {
+                let jitter = (i % 7) as f32 * 0.01;
+                let center = if i < 300 {
+                    1.0
+                } else if i < 360 {
+                    10.0
+                } else {
+                    -10.0
+                };
+                [center + jitter, center, center - jitter]
+            })
+            .collect::<Vec<_>>();
+        let table = NativeTable::create(uri, "test", vectors(rows), None)
+            .await
+            .unwrap();
+        let centroids = vec_to_fixed_size_list(3, [[1.0; 3], [10.0; 3], [-10.0; 3]]).unwrap();
+        let ivf_params = IvfBuildParams::try_with_centroids(3, Arc::new(centroids)).unwrap();
+        let builder = IvfPQIndexBuilder::new()
+            .column("vector")
+            .index_name("vector_idx")
+            .ivf_params(ivf_params)
+            .pq_params(PQBuildParams {
+                num_sub_vectors: 1,
+                ..Default::default()
+            });
+        table.create_index(&builder).await.unwrap();
+        // Appended rows are in no partition.
+        table.add(vectors(vec![[5.0; 3]; 10]), None).await.unwrap();
+
+        let stats = table.index_partition_stats("vector_idx").await.unwrap();
+        assert_eq!(stats.column, "vector");
+        assert_eq!(stats.metric_type, MetricType::L2);
+        assert_eq!(stats.indexed_rows, 400);
+        assert_eq!(
+            stats.partitions.iter().map(|p| p.rows).sum::<usize>(),
+            stats.indexed_rows
+        );
+        let rows = stats.partitions.iter().map(|p| p.rows).collect::<Vec<_>>();
+        assert_eq!(rows, vec![300, 60, 40]);
+        let largest = stats.largest().unwrap();
+        assert_eq!((largest.partition, largest.fraction), (0, 0.75));
+        let norm = stats.partitions[1].centroid_norm;
+        assert!((norm - 10.0 * 3f32.sqrt()).abs() < 1e-3, "{stats:?}");
+
+        let probed = table
+            .debug_probe("vector_idx", &[9.0, 9.0, 9.0], 2)
+            .await
+            .unwrap();
+        let partitions = probed.iter().map(|p| p.partition).collect::<Vec<_>>();
+        assert_eq!(partitions, vec![1, 0]);
+        assert_eq!(probed[0].rows, 60);
+        // The squared L2 distance, as lance ranks the partitions.
+        assert!((probed[0].distance - 3.0).abs() < 1e-3, "{probed:?}");
+        let all = table
+            .debug_probe("vector_idx", &[0.0; 3], 10)
+            .await
+            .unwrap();
+        assert_eq!(all.len(), 3);
+
+        let err = table
+            .debug_probe("vector_idx", &[0.0; 2], 1)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::InvalidInput { .. }), "{err}");
+        let err = table.index_partition_stats("missing").await.unwrap_err();
+        assert!(matches!(err, Error::IndexNotFound { .. }), "{err}");
+    }
+}
@@ -0,0 +1,1009 @@
+// Copyright 2023 LanceDB Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! IVF_PQ indices trained on a sample of the vectors and built in one scan of
+//! the column.
+//!
+//! lance keeps the PQ codes and row ids of every row in memory until it writes
+//! the index file. Here they are buffered by partition, and spilled to local
+//! files when the buffers grow over [IvfPQIndexBuilder::max_memory_bytes], so
+//! the memory used grows with the sample and the partitions, not the table.
+//! The fragments of the table are assigned to partitions by
+//! [IvfPQIndexBuilder::num_threads] tasks at once.
+//!
+//! The index file is the one lance writes: each partition, the PQ codes of its
+//! rows then their row ids, and the index metadata. An index of the rows
+//! matching a filter has the filter in a file next to it, see
+//! [super::partial].
+//!
+//! [IvfPQIndexBuilder::max_memory_bytes]: crate::index::vector::IvfPQIndexBuilder::max_memory_bytes
+//! [IvfPQIndexBuilder::num_threads]: crate::index::vector::IvfPQIndexBuilder::num_threads
+
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use arrow_array::{Array, ArrayRef, FixedSizeListArray, Float32Array, UInt64Array, UInt8Array};
+use arrow_schema::{DataType, Schema as ArrowSchema};
+use futures::TryStreamExt;
+use lance::arrow::linalg::matrix::MatrixView;
+use lance::dataset::fragment::FileFragment;
+use lance::dataset::{Dataset, ROW_ID};
+use lance::format::Index;
+use lance::index::pb;
+use lance::index::vector::ivf::IvfBuildParams;
+use lance::index::vector::pq::{PQBuildParams, ProductQuantizer};
+use lance::index::vector::{MetricType, StageParams, VectorIndexParams};
+use lance::io::object_writer::ObjectWriter;
+use lance::utils::kmeans::{KMeans, KMeansParams};
+use rand::seq::index::sample;
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+use super::ivf::INDEX_FILE;
+use super::NativeTable;
+use crate::error::{Error, Result};
+use crate::index::vector::{
+    VectorIndexBuilder, DEFAULT_INDEX_BUILD_MEMORY_BYTES, DEFAULT_SAMPLE_RATE,
+};
+use crate::query::filter::lance_filter;
+
+/// The sampled rows taken from the table at once.
+const SAMPLE_BATCH_ROWS: usize = 8192;
+
+/// The bytes of a spill file read back at once.
+const SPILL_READ_BYTES: usize = 1024 * 1024;
+
+/// The rows an index build read and the bytes it spilled.
+#[derive(Debug, Default, PartialEq)]
+pub(super) struct BuildStats {
+    /// The rows sampled to train the centroids and the codebook.
+    pub(super) training_rows: usize,
+    /// The rows assigned to partitions, every row of the table matching the
+    /// row filter of the index.
+    pub(super) assigned_rows: usize,
+    pub(super) spilled_bytes: usize,
+    /// The most fragments assigned to partitions by tasks at the same time.
+    pub(super) concurrent_tasks: usize,
+}
+
+/// The values of the vectors of `array`, a fixed size list of float32 of
+/// `dims` values.
+fn vector_values(array: &ArrayRef, dims: usize) -> Result<Float32Array> {
+    let list = array
+        .as_any()
+        .downcast_ref::<FixedSizeListArray>()
+        .ok_or_else(|| Error::Schema {
+            message: format!("expected vectors, got {}", array.data_type()),
+        })?;
+    let values = list
+        .values()
+        .as_any()
+        .downcast_ref::<Float32Array>()
+        .ok_or_else(|| Error::Schema {
+            message: format!("expected float32 vectors, got {}", list.data_type()),
+        })?;
+    if list.is_empty() {
+        return Ok(Float32Array::from(Vec::<f32>::new()));
+    }
+    Ok(values.slice(list.value_offset(0) as usize, list.len() * dims))
+}
+
+/// `rows` vectors of `values` chosen at random, all of them if there are
+/// fewer.
+fn subsample(values: &Float32Array, dims: usize, rows: usize) -> Float32Array {
+    let num_rows = values.len() / dims;
+    if num_rows <= rows {
+        return values.clone();
+    }
+    let mut chosen = sample(&mut rand::thread_rng(), num_rows, rows).into_vec();
+    chosen.sort_unstable();
+    let values = values.values();
+    Float32Array::from_iter_values(
+        chosen
+            .into_iter()
+            .flat_map(|row| values[row * dims..(row + 1) * dims].iter().copied()),
+    )
+}
+
+/// The partition of each vector of `vectors`, the nearest of `centroids` by
+/// `metric_type`, and the residuals of the vectors to their centroids.
+fn assign(
+    vectors: &[f32],
+    centroids: &[f32],
+    dims: usize,
+    metric_type: MetricType,
+) -> (Vec<u32>, Vec<f32>) {
+    let distance = metric_type.batch_func();
+    let mut partitions = Vec::with_capacity(vectors.len() / dims);
+    let mut residuals = Vec::with_capacity(vectors.len());
+    for vector in vectors.chunks_exact(dims) {
+        let distances = distance(vector, centroids, dims);
+        let partition = distances
+            .values()
+            .iter()
+            .enumerate()
+            .min_by(|a, b| a.1.total_cmp(b.1))
+            .map(|(partition, _)| partition)
+            .unwrap_or_default();
+        let centroid = &centroids[partition * dims..(partition + 1) * dims];
+        residuals.extend(vector.iter().zip(centroid).map(|(v, c)| v - c));
+        partitions.push(partition as u32);
+    }
+    (partitions, residuals)
+}
+
+/// The PQ codes of the rows of a batch, and their partitions.
+struct AssignedBatch {
+    partitions: Vec<u32>,
+    codes: UInt8Array,
+    row_ids: UInt64Array,
+}
+
+/// Assigns the rows of the fragments of a table to the partitions of an index.
+#[derive(Clone)]
+struct Assigner {
+    column: String,
+    /// The filter of the rows indexed, as lance plans it.
+    filter: Option<String>,
+    dims: usize,
+    centroids: Arc<Float32Array>,
+    pq: Arc<ProductQuantizer>,
+    metric_type: MetricType,
+}
+
+impl Assigner {
+    /// Assign the rows of `fragment` to partitions and encode their residuals,
+    /// sending the batches to `sender` in order.
+    async fn assign_fragment(
+        self,
+        fragment: FileFragment,
+        sender: mpsc::Sender<AssignedBatch>,
+    ) -> Result<()> {
+        let Self {
+            column,
+            filter,
+            dims,
+            centroids,
+            pq,
+            metric_type,
+        } = self;
+        let mut scanner = fragment.scan();
+        scanner.project(&[&column])?;
+        if let Some(filter) = filter.as_ref() {
+            scanner.filter(filter)?;
+        }
+        scanner.with_row_id();
+        let mut stream = scanner.try_into_stream().await?;
+        while let Some(batch) = stream.try_next().await? {
+            let vectors = vector_values(&batch[column.as_str()], dims)?;
+            let centroids = centroids.clone();
+            let (partitions, residuals) = tokio::task::spawn_blocking(move || {
+                assign(vectors.values(), centroids.values(), dims, metric_type)
+            })
+            .await
+            .map_err(|e| Error::Runtime {
+                message: format!("assigning the rows to partitions failed: {e}"),
+            })?;
+            let residuals = MatrixView::new(Arc::new(residuals.into()), dims);
+            let codes = pq.transform(&residuals, metric_type).await?;
+            let codes = codes
+                .values()
+                .as_any()
+                .downcast_ref::<UInt8Array>()
+                .unwrap()
+                .clone();
+            let row_ids = batch[ROW_ID]
+                .as_any()
+                .downcast_ref::<UInt64Array>()
+                .unwrap()
+                .clone();
+            let assigned = AssignedBatch {
+                partitions,
+                codes,
+                row_ids,
+            };
+            if sender.send(assigned).await.is_err() {
+                // The build failed and stopped reading the fragment.
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The threads assigning rows to partitions by default, the available
+/// parallelism.
+fn default_num_threads() -> usize {
+    std::thread::available_parallelism()
+        .map(|threads| threads.get())
+        .unwrap_or(1)
+}
+
+/// A directory of spill files, removed when it is dropped.
+struct SpillDir {
+    path: PathBuf,
+}
+
+impl Drop for SpillDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+fn io_error(path: &std::path::Path) -> impl FnOnce(std::io::Error) -> Error + '_ {
+    move |source| Error::Io {
+        path: path.to_string_lossy().to_string(),
+        source,
+    }
+}
+
+/// Run `io`, which reads or writes spill files, on a blocking thread.
+async fn spill_io<T: Send + 'static>(io: impl FnOnce() -> Result<T> + Send + 'static) -> Result<T> {
+    tokio::task::spawn_blocking(io)
+        .await
+        .map_err(|e| Error::Runtime {
+            message: format!("reading or writing the spilled partitions failed: {e}"),
+        })?
+}
+
+/// The PQ codes and row ids of the rows of each partition, in memory until
+/// they hold more than `max_memory_bytes`, then appended to the spill files
+/// of their partitions.
+struct PartitionBuffers {
+    code_len: usize,
+    max_memory_bytes: usize,
+    codes: Vec<Vec<u8>>,
+    row_ids: Vec<Vec<u64>>,
+    rows: Vec<u32>,
+    buffered_bytes: usize,
+    spill_path: PathBuf,
+    spill: Option<SpillDir>,
+    spilled_bytes: usize,
+}
+
+impl PartitionBuffers {
+    fn new(
+        partitions: usize,
+        code_len: usize,
+        max_memory_bytes: usize,
+        spill_path: PathBuf,
+    ) -> Self {
+        Self {
+            code_len,
+            max_memory_bytes,
+            codes: vec![Vec::new(); partitions],
+            row_ids: vec![Vec::new(); partitions],
+            rows: vec![0; partitions],
+            buffered_bytes: 0,
+            spill_path,
+            spill: None,
+            spilled_bytes: 0,
+        }
+    }
+
+    async fn push(&mut self, partitions: &[u32], codes: &[u8], row_ids: &[u64]) -> Result<()> {
+        for (row, partition) in partitions.iter().enumerate() {
+            let partition = *partition as usize;
+            let code = &codes[row * self.code_len..(row + 1) * self.code_len];
+            self.codes[partition].extend_from_slice(code);
+            self.row_ids[partition].push(row_ids[row]);
+            self.rows[partition] += 1;
+        }
+        self.buffered_bytes += partitions.len() * (self.code_len + 8);
+        if self.buffered_bytes > self.max_memory_bytes {
+            self.spill().await?;
+        }
+        Ok(())
+    }
+
+    fn spill_file(&self, partition: usize, kind: &str) -> PathBuf {
+        self.spill_path.join(format!("{partition}.{kind}"))
+    }
+
+    /// Append the buffers to the spill files and release them.
+    async fn spill(&mut self) -> Result<()> {
+        let create_dir = self.spill.is_none().then(|| self.spill_path.clone());
+        // Removed when the build ends, even if the files are not written.
+        self.spill.get_or_insert_with(|| SpillDir {
+            path: self.spill_path.clone(),
+        });
+        let mut writes = Vec::new();
+        for partition in 0..self.codes.len() {
+            if self.codes[partition].is_empty() {
+                continue;
+            }
+            let codes = std::mem::take(&mut self.codes[partition]);
+            let row_ids = std::mem::take(&mut self.row_ids[partition])
+                .into_iter()
+                .flat_map(u64::to_le_bytes)
+                .collect::<Vec<_>>();
+            for (kind, bytes) in [("codes", codes), ("ids", row_ids)] {
+                self.spilled_bytes += bytes.len();
+                writes.push((self.spill_file(partition, kind), bytes));
+            }
+        }
+        self.buffered_bytes = 0;
+        spill_io(move || {
+            if let Some(dir) = create_dir {
+                fs::create_dir_all(&dir).map_err(io_error(&dir))?;
+            }
+            for (path, bytes) in writes {
+                let mut file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&path)
+                    .map_err(io_error(&path))?;
+                file.write_all(&bytes).map_err(io_error(&path))?;
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    /// The spilled bytes of `kind` of `partition`, in chunks.
+    async fn read_spilled(&self, partition: usize, kind: &str) -> Result<Option<SpillReader>> {
+        if self.spill.is_none() {
+            return Ok(None);
+        }
+        let path = self.spill_file(partition, kind);
+        spill_io(move || match File::open(&path) {
+            Ok(file) => Ok(Some(SpillReader {
+                path,
+                file: Some(file),
+            })),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(io_error(&path)(e)),
+        })
+        .await
+    }
+
+    /// Write the PQ codes then the row ids of each partition to `writer`,
+    /// the spilled ones first, returning the offsets of the partitions.
+    async fn write(&mut self, writer: &mut ObjectWriter) -> Result<Vec<u64>> {
+        let mut offsets = Vec::with_capacity(self.codes.len());
+        for partition in 0..self.codes.len() {
+            offsets.push(writer.tell() as u64);
+            if let Some(mut spilled) = self.read_spilled(partition, "codes").await? {
+                while let Some(chunk) = spilled.next_chunk().await? {
+                    writer
+                        .write_plain_encoded_array(&UInt8Array::from(chunk))
+                        .await?;
+                }
+            }
+            let codes = std::mem::take(&mut self.codes[partition]);
+            if !codes.is_empty() {
+                writer
+                    .write_plain_encoded_array(&UInt8Array::from(codes))
+                    .await?;
+            }
+            if let Some(mut spilled) = self.read_spilled(partition, "ids").await? {
+                while let Some(chunk) = spilled.next_chunk().await? {
+                    let row_ids = chunk
+                        .chunks_exact(8)
+                        .map(|id| u64::from_le_bytes(id.try_into().unwrap()));
+                    writer
+                        .write_plain_encoded_array(&UInt64Array::from_iter_values(row_ids))
+                        .await?;
+                }
+            }
+            let row_ids = std::mem::take(&mut self.row_ids[partition]);
+            if !row_ids.is_empty() {
+                writer
+                    .write_plain_encoded_array(&UInt64Array::from(row_ids))
+                    .await?;
+            }
+        }
+        Ok(offsets)
+    }
+}
+
+struct SpillReader {
+    path: PathBuf,
+    /// The file, `None` while a chunk is read from it.
+    file: Option<File>,
+}
+
+impl SpillReader {
+    /// The next bytes of the file, a whole number of row ids, `None` at its
+    /// end.
+    async fn next_chunk(&mut self) -> Result<Option<Vec<u8>>> {
+        let Some(mut file) = self.file.take() else {
+            return Ok(None);
+        };
+        let path = self.path.clone();
+        let (file, chunk) = spill_io(move || {
+            let mut chunk = Vec::with_capacity(SPILL_READ_BYTES);
+            let read = (&mut file)
+                .take(SPILL_READ_BYTES as u64)
+                .read_to_end(&mut chunk)
+                .map_err(io_error(&path))?;
+            Ok((file, (read > 0).then_some(chunk)))
+        })
+        .await?;
+        self.file = Some(file);
+        Ok(chunk)
+    }
+}
+
+/// The ivf and pq parameters of `params`, `None` unless it is an IVF_PQ index
+/// without OPQ.
+fn ivf_pq_params(params: &VectorIndexParams) -> Option<(&IvfBuildParams, &PQBuildParams)> {
+    match params.stages.as_slice() {
+        [StageParams::Ivf(ivf), StageParams::PQ(pq)] if !pq.use_opq => Some((ivf, pq)),
+        _ => None,
+    }
+}
+
+impl NativeTable {
+    /// `dataset`, the latest version, with the IVF_PQ index of `params` built
+    /// on `column`, and the rows the build read. `None` if lance builds the
+    /// index: it is not an IVF_PQ index, or it has OPQ.
+    pub(super) async fn build_ivf_pq_index(
+        &self,
+        dataset: &Dataset,
+        column: &str,
+        builder: &(impl VectorIndexBuilder + ?Sized),
+        params: &VectorIndexParams,
+    ) -> Result<Option<(Dataset, BuildStats)>> {
+        let Some((ivf_params, pq_params)) = ivf_pq_params(params) else {
+            return Ok(None);
+        };
+        let Some(field) = dataset.schema().field(column) else {
+            return Ok(None);
+        };
+        let DataType::FixedSizeList(item, dims) = field.data_type() else {
+            return Ok(None);
+        };
+        if item.data_type() != &DataType::Float32 {
+            return Ok(None);
+        }
+        let dims = dims as usize;
+        let sample_rate = builder.get_sample_rate().unwrap_or(DEFAULT_SAMPLE_RATE);
+        let max_memory_bytes = builder
+            .get_max_memory_bytes()
+            .unwrap_or(DEFAULT_INDEX_BUILD_MEMORY_BYTES);
+        let num_threads = builder
+            .get_num_threads()
+            .or(self.index_build_threads)
+            .unwrap_or_else(default_num_threads);
+        if sample_rate == 0 || max_memory_bytes == 0 || num_threads == 0 {
+            return Err(Error::InvalidInput {
+                message: "sample_rate, max_memory_bytes and num_threads must be at least 1"
+                    .to_string(),
+            });
+        }
+        if pq_params.num_bits != 8 {
+            return Err(Error::InvalidInput {
+                message: format!("PQ codes are of 8 bits, not {}", pq_params.num_bits),
+            });
+        }
+        let num_sub_vectors = pq_params.num_sub_vectors;
+        if num_sub_vectors == 0 || !dims.is_multiple_of(num_sub_vectors) {
+            return Err(Error::InvalidInput {
+                message: format!(
+                    "the {dims} dimensions of '{column}' cannot be split into {num_sub_vectors} sub-vectors"
+                ),
+            });
+        }
+        let name = builder
+            .name()
+            .map_or_else(|| format!("{column}_idx"), str::to_string);
+        let indices = dataset.load_indices().await?;
+        if let Some(index) = indices.iter().find(|i| i.name == name) {
+            if index.fields != [field.id] {
+                return Err(lance::Error::Index {
+                    message: format!(
+                        "Index name '{name} already exists with different fields, please specify a different name"
+                    ),
+                }
+                .into());
+            }
+            if !builder.get_replace() {
+                return Err(lance::Error::Index {
+                    message: format!(
+                        "Index name '{name} already exists, please specify a different name or use replace=True"
+                    ),
+                }
+                .into());
+            }
+        }
+
+        let row_filter = builder.filter();
+        let filter = match row_filter {
+            Some(filter) => {
+                let schema = ArrowSchema::from(dataset.schema());
+                Some(lance_filter(filter, &schema)?.into_owned())
+            }
+            None => None,
+        };
+
+        let mut stats = BuildStats::default();
+        let num_partitions = ivf_params.num_partitions;
+        let num_centroids = ProductQuantizer::num_centroids(pq_params.num_bits as u32);
+        let sample_rows = num_partitions.max(num_centroids) * sample_rate;
+        let training = match filter.as_ref() {
+            Some(filter) => sample_matching(dataset, column, filter, dims, sample_rows).await?,
+            None => sample_vectors(dataset, column, dims, sample_rows).await?,
+        };
+        stats.training_rows = training.len() / dims;
+
+        let centroids = match ivf_params.centroids.as_ref() {
+            Some(centroids) => vector_values(&(centroids.clone() as ArrayRef), dims)?,
+            None => {
+                let kmeans = KMeansParams {
+                    max_iters: ivf_params.max_iters as u32,
+                    metric_type: params.metric_type,
+                    ..Default::default()
+                };
+                let data = subsample(&training, dims, num_partitions * sample_rate);
+                let model = KMeans::new_with_params(&data, dims, num_partitions, &kmeans).await?;
+                model.centroids.as_ref().clone()
+            }
+        };
+        if centroids.len() != num_partitions * dims {
+            return Err(Error::InvalidInput {
+                message: format!(
+                    "expected {num_partitions} IVF centroids of {dims} dimensions, got {} values",
+                    centroids.len()
+                ),
+            });
+        }
+        let metric_type = pq_params.metric_type;
+        let pq = match pq_params.codebook.as_ref() {
+            Some(codebook) => ProductQuantizer::new_with_codebook(
+                num_sub_vectors,
+                pq_params.num_bits as u32,
+                dims,
+                codebook.clone(),
+            ),
+            None => {
+                let (_, residuals) =
+                    assign(training.values(), centroids.values(), dims, metric_type);
+                let residuals = MatrixView::new(Arc::new(residuals.into()), dims);
+                let mut pq =
+                    ProductQuantizer::new(num_sub_vectors, pq_params.num_bits as u32, dims);
+                pq.train(&residuals, metric_type, pq_params.max_iters)
+                    .await?;
+                pq
+            }
+        };
+        drop(training);
+
+        let uuid = Uuid::new_v4();
+        let spill_path = std::env::temp_dir().join(format!("lancedb-index-{uuid}"));
+        let mut buffers = PartitionBuffers::new(
+            num_partitions,
+            num_sub_vectors,
+            max_memory_bytes,
+            spill_path,
+        );
+        // The fragments are assigned by `num_threads` tasks, and their rows
+        // pushed in the order of the fragments, so that the partitions list
+        // the rows in the same order for any number of threads.
+        let assigner = Assigner {
+            column: column.to_string(),
+            filter,
+            dims,
+            centroids: Arc::new(centroids),
+            pq: Arc::new(pq),
+            metric_type,
+        };
+        let mut fragments = dataset.get_fragments().into_iter();
+        let mut running = VecDeque::with_capacity(num_threads);
+        loop {
+            while running.len() < num_threads {
+                let Some(fragment) = fragments.next() else {
+                    break;
+                };
+                let (sender, receiver) = mpsc::channel(1);
+                let task = tokio::spawn(assigner.clone().assign_fragment(fragment, sender));
+                running.push_back((task, receiver));
+            }
+            stats.concurrent_tasks = stats.concurrent_tasks.max(running.len());
+            let Some((task, mut receiver)) = running.pop_front() else {
+                break;
+            };
+            while let Some(batch) = receiver.recv().await {
+                buffers
+                    .push(
+                        &batch.partitions,
+                        batch.codes.values(),
+                        batch.row_ids.values(),
+                    )
+                    .await?;
+                stats.assigned_rows += batch.row_ids.len();
+            }
+            task.await.map_err(|e| Error::Runtime {
+                message: format!("assigning the rows to partitions failed: {e}"),
+            })??;
+        }
+        let Assigner { centroids, pq, .. } = assigner;
+
+        let (store, base) = self.dataset.object_store().await?;
+        let path = base
+            .child("_indices")
+            .child(uuid.to_string())
+            .child(INDEX_FILE);
+        let mut writer = store.create(&path).await?;
+        let offsets = buffers.write(&mut writer).await?;
+        stats.spilled_bytes = buffers.spilled_bytes;
+        let metadata = pb::Index {
+            name: name.clone(),
+            columns: vec![column.to_string()],
+            dataset_version: dataset.version().version,
+            index_type: pb::IndexType::Vector.into(),
+            implementation: Some(pb::index::Implementation::VectorIndex(pb::VectorIndex {
+                spec_version: 1,
+                dimension: dims as u32,
+                stages: vec![
+                    pb::VectorIndexStage {
+                        stage: Some(pb::vector_index_stage::Stage::Ivf(pb::Ivf {
+                            centroids: centroids.values().to_vec(),
+                            offsets,
+                            lengths: buffers.rows.clone(),
+                        })),
+                    },
+                    pb::VectorIndexStage {
+                        stage: Some(pb::vector_index_stage::Stage::Pq(pq.as_ref().into())),
+                    },
+                ],
+                metric_type: match metric_type {
+                    MetricType::L2 => pb::VectorMetricType::L2.into(),
+                    MetricType::Cosine => pb::VectorMetricType::Cosine.into(),
+                    MetricType::Dot => pb::VectorMetricType::Dot.into(),
+                },
+            })),
+        };
+        let position = writer.write_protobuf(&metadata).await?;
+        writer.write_magics(position).await?;
+        writer.shutdown().await?;
+        if let Some(row_filter) = row_filter {
+            // Before the index is committed, so that no search uses the index
+            // without its filter.
+            self.dataset.write_row_filter(&uuid, row_filter).await?;
+        }
+
+        let version = dataset.version().version;
+        let mut indices = indices
+            .into_iter()
+            .filter(|i| i.name != name)
+            .collect::<Vec<_>>();
+        indices.push(Index::new(uuid, &name, &[field.id], version + 1));
+        let indexed = self.dataset.commit_indices(dataset, indices).await?;
+        Ok(Some((indexed, stats)))
+    }
+}
+
+/// The values of `rows` vectors of `column` chosen at random, of all of them
+/// if the table has fewer rows. Only the chosen rows are read, unless rows
+/// were deleted: lance 0.5 takes the rows of a fragment by their offsets
+/// before its deletions, so the column is scanned for the chosen rows.
+async fn sample_vectors(
+    dataset: &Dataset,
+    column: &str,
+    dims: usize,
+    rows: usize,
+) -> Result<Float32Array> {
+    let num_rows = dataset.count_rows().await?;
+    let mut chosen = if num_rows > rows {
+        sample(&mut rand::thread_rng(), num_rows, rows).into_vec()
+    } else {
+        (0..num_rows).collect()
+    };
+    chosen.sort_unstable();
+    let mut values = Vec::with_capacity(chosen.len() * dims);
+    let deleted = dataset
+        .get_fragments()
+        .iter()
+        .any(|f| f.metadata().deletion_file.is_some());
+    if !deleted {
+        let projection = dataset.schema().project(&[column])?;
+        for rows in chosen.chunks(SAMPLE_BATCH_ROWS) {
+            let batch = dataset.take(rows, &projection).await?;
+            values.extend_from_slice(vector_values(&batch[column], dims)?.values());
+        }
+        return Ok(values.into());
+    }
+    let mut scanner = dataset.scan();
+    scanner.project(&[column])?;
+    let mut stream = scanner.try_into_stream().await?;
+    let (mut offset, mut chosen) = (0, chosen.into_iter().peekable());
+    while let Some(batch) = stream.try_next().await? {
+        let batch_values = vector_values(&batch[column], dims)?;
+        while let Some(row) = chosen.next_if(|row| *row < offset + batch.num_rows()) {
+            let start = (row - offset) * dims;
+            values.extend_from_slice(&batch_values.values()[start..start + dims]);
+        }
+        offset += batch.num_rows();
+    }
+    Ok(values.into())
+}
+
+/// The values of `rows` vectors of `column` chosen at random among the rows
+/// matching `filter`, of all of them if fewer match. The matching rows are
+/// read.
+async fn sample_matching(
+    dataset: &Dataset,
+    column: &str,
+    filter: &str,
+    dims: usize,
+    rows: usize,
+) -> Result<Float32Array> {
+    let mut scanner = dataset.scan();
+    scanner.project(&[column])?;
+    scanner.filter(filter)?;
+    let mut stream = scanner.try_into_stream().await?;
+    let mut values = Vec::new();
+    while let Some(batch) = stream.try_next().await? {
+        values.extend_from_slice(vector_values(&batch[column], dims)?.values());
+    }
+    Ok(subsample(&values.into(), dims, rows))
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow_array::{Int32Array, RecordBatch, RecordBatchReader};
+    use lance::arrow::{FixedSizeListArrayExt, RecordBatchBuffer};
+    use lance::dataset::WriteParams;
+    use lance::index::{DatasetIndexExt, IndexType};
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::arrow::RecordBatchBuilder;
+    use crate::index::vector::IvfPQIndexBuilder;
+
+    const DIMS: usize = 8;
+
+    /// `rows` vectors around 4 clusters.
+    fn rows(rows: usize) -> Box<dyn RecordBatchReader> {
+        let vectors = (0..rows)
+            .map(|row| {
+                let cluster = (row % 4) as f32 * 10.0;
+                (0..DIMS)
+                    .map(|d| cluster + ((row * 7 + d * 13) % 17) as f32 / 17.0)
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        let batch = RecordBatchBuilder::new()
+            .column("id", Arc::new(Int32Array::from_iter_values(0..rows as i32)))
+            .vector_column("vector", DIMS as i32, vectors)
+            .build()
+            .unwrap();
+        Box::new(RecordBatchBuffer::new(vec![batch]))
+    }
+
+    fn index_builder() -> IvfPQIndexBuilder {
+        IvfPQIndexBuilder::new()
+            .ivf_params(IvfBuildParams::new(4))
+            .pq_params(PQBuildParams {
+                num_sub_vectors: 2,
+                max_iters: 5,
+                ..Default::default()
+            })
+            .sample_rate(2)
+    }
+
+    async fn build(table: &NativeTable, builder: &IvfPQIndexBuilder) -> BuildStats {
+        let dataset = table.dataset.get().await.unwrap();
+        let (indexed, stats) = table
+            .build_ivf_pq_index(&dataset, "vector", builder, &builder.build())
+            .await
+            .unwrap()
+            .unwrap();
+        table.dataset.commit(indexed).await.unwrap();
+        stats
+    }
+
+    async fn search(table: &NativeTable, vector: Vec<f32>) -> Vec<RecordBatch> {
+        table
+            .search(vector)
+            .use_index(true)
+            .nprobes(4)
+            .limit(5)
+            .execute()
+            .await
+            .unwrap()
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap()
+    }
+
+    /// A builder of the centroids and the codebook of an index trained on
+    /// `table`, to build the same index again.
+    async fn trained_builder(table: &NativeTable) -> IvfPQIndexBuilder {
+        table.create_index(&index_builder()).await.unwrap();
+        let dataset = table.dataset.get().await.unwrap();
+        let index = dataset.load_indices().await.unwrap().remove(0);
+        let stages = table.dataset.read_vector_index(&index).await.unwrap();
+        let (mut centroids, mut codebook) = (Vec::new(), Vec::new());
+        for stage in stages.unwrap().stages.into_iter().filter_map(|s| s.stage) {
+            match stage {
+                pb::vector_index_stage::Stage::Ivf(ivf) => centroids = ivf.centroids,
+                pb::vector_index_stage::Stage::Pq(pq) => codebook = pq.codebook,
+                _ => {}
+            }
+        }
+        let centroids =
+            FixedSizeListArray::try_new(Float32Array::from(centroids), DIMS as i32).unwrap();
+        IvfPQIndexBuilder::new()
+            .ivf_params(IvfBuildParams::try_with_centroids(4, Arc::new(centroids)).unwrap())
+            .pq_params(PQBuildParams::with_codebook(
+                2,
+                8,
+                Arc::new(Float32Array::from(codebook)),
+            ))
+    }
+
+    /// A table of `num_rows` rows in fragments of `rows_per_fragment`.
+    async fn fragmented_table(uri: &str, num_rows: usize, rows_per_fragment: usize) -> NativeTable {
+        // lance closes a file once it holds `max_rows_per_file` rows, after
+        // writing a whole batch.
+        let batch = rows(num_rows).next().unwrap().unwrap();
+        let batches = (0..num_rows)
+            .step_by(100)
+            .map(|offset| batch.slice(offset, 100.min(num_rows - offset)))
+            .collect();
+        let params = WriteParams {
+            max_rows_per_file: rows_per_fragment,
+            max_rows_per_group: 100,
+            ..Default::default()
+        };
+        let batches = Box::new(RecordBatchBuffer::new(batches));
+        NativeTable::create(uri, "test", batches, Some(params))
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_build_from_sample() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        let table = NativeTable::create(uri, "test", rows(2000), None)
+            .await
+            .unwrap();
+        let stats = build(&table, &index_builder()).await;
+        // 2 vectors for each of the 256 PQ centroids.
+        assert_eq!(stats.training_rows, 512);
+        assert_eq!(stats.assigned_rows, 2000);
+        assert_eq!(stats.spilled_bytes, 0);
+
+        let partitions = table.index_partition_stats("vector_idx").await.unwrap();
+        assert_eq!(partitions.indexed_rows, 2000);
+        let batches = search(&table, vec![20.5; DIMS]).await;
+        let ids = batches[0]["id"].as_any().downcast_ref::<Int32Array>();
+        // The nearest rows are in the cluster at 20.
+        assert!(ids.unwrap().values().iter().all(|id| id % 4 == 2));
+
+        let builder = index_builder().replace(false);
+        let dataset = table.dataset.get().await.unwrap();
+        let built = table
+            .build_ivf_pq_index(&dataset, "vector", &builder, &builder.build())
+            .await;
+        assert!(built.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_spill_partitions() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        let table = NativeTable::create(uri, "test", rows(2000), None)
+            .await
+            .unwrap();
+        // Trained once, the index is built the same with and without spilling.
+        let builder = trained_builder(&table).await;
+
+        let query = vec![10.5; DIMS];
+        let builder = builder.max_memory_bytes(usize::MAX);
+        assert_eq!(build(&table, &builder).await.spilled_bytes, 0);
+        let in_memory = search(&table, query.clone()).await;
+        let partitions = table.index_partition_stats("vector_idx").await.unwrap();
+        let builder = builder.max_memory_bytes(1000);
+        assert!(build(&table, &builder).await.spilled_bytes > 1000);
+        assert_eq!(search(&table, query.clone()).await, in_memory);
+        let spilled = table.index_partition_stats("vector_idx").await.unwrap();
+        assert_eq!(spilled, partitions);
+        assert_eq!(spilled.indexed_rows, 2000);
+
+        // lance builds the same index.
+        let dataset = table.dataset.get().await.unwrap();
+        let indexed = dataset
+            .create_index(&["vector"], IndexType::Vector, None, &builder.build(), true)
+            .await
+            .unwrap();
+        table.dataset.commit(indexed).await.unwrap();
+        assert_eq!(search(&table, query).await, in_memory);
+    }
+
+    #[tokio::test]
+    async fn test_index_read_by_lance() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        let table = fragmented_table(uri, 2000, 300).await;
+        let builder = trained_builder(&table).await.max_memory_bytes(1000);
+        let queries = [vec![0.5; DIMS], vec![10.5; DIMS], vec![25.0; DIMS]];
+
+        build(&table, &builder).await;
+        let dataset = table.dataset.get().await.unwrap();
+        let index = dataset.load_indices().await.unwrap().remove(0);
+        let built = table.dataset.read_vector_index(&index).await.unwrap();
+        let mut results = Vec::new();
+        for query in &queries {
+            results.push(search(&table, query.clone()).await);
+        }
+
+        // The index lance builds with the same centroids and codebook has the
+        // same metadata, and lance's reader finds the same rows in both.
+        let indexed = dataset
+            .create_index(&["vector"], IndexType::Vector, None, &builder.build(), true)
+            .await
+            .unwrap();
+        table.dataset.commit(indexed).await.unwrap();
+        let dataset = table.dataset.get().await.unwrap();
+        let lance_index = dataset.load_indices().await.unwrap().remove(0);
+        assert_ne!(lance_index.uuid, index.uuid);
+        let lance_built = table.dataset.read_vector_index(&lance_index).await.unwrap();
+        assert!(built.is_some());
+        assert_eq!(built, lance_built);
+        for (query, expected) in queries.iter().zip(&results) {
+            assert_eq!(&search(&table, query.clone()).await, expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_num_threads() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        let table = fragmented_table(uri, 2000, 300).await;
+        assert_eq!(table.dataset.get().await.unwrap().get_fragments().len(), 7);
+        table.delete("id % 10 = 3").await.unwrap();
+
+        // The rows of the fragments are assigned concurrently, and listed in
+        // the partitions in the same order as one at a time.
+        let builder = trained_builder(&table).await;
+        let queries = [vec![0.5; DIMS], vec![10.5; DIMS], vec![25.0; DIMS]];
+        let one = build(&table, &builder.clone().num_threads(1)).await;
+        assert_eq!(one.assigned_rows, 1800);
+        assert_eq!(one.concurrent_tasks, 1);
+        let partitions = table.index_partition_stats("vector_idx").await.unwrap();
+        let mut results = Vec::new();
+        for query in &queries {
+            results.push(search(&table, query.clone()).await);
+        }
+        for num_threads in [3, 7, 16] {
+            let stats = build(&table, &builder.clone().num_threads(num_threads)).await;
+            // No more tasks than fragments.
+            assert_eq!(stats.concurrent_tasks, num_threads.min(7));
+            let stats = BuildStats {
+                concurrent_tasks: 1,
+                ..stats
+            };
+            assert_eq!(stats, one);
+            let stats = table.index_partition_stats("vector_idx").await.unwrap();
+            assert_eq!(stats, partitions);
+            for (query, expected) in queries.iter().zip(&results) {
+                assert_eq!(&search(&table, query.clone()).await, expected);
+            }
+        }
+
+        let builder = builder.num_threads(0);
+        let dataset = table.dataset.get().await.unwrap();
+        let built = table
+            .build_ivf_pq_index(&dataset, "vector", &builder, &builder.build())
+            .await;
+        assert!(matches!(built, Err(Error::InvalidInput { .. })));
+    }
+}
@@ -0,0 +1,523 @@
+// Copyright 2023 LanceDB Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Similarity joins, the nearest rows of a table for each row of another.
+
+use std::sync::Arc;
+
+use arrow_array::{Array, ArrayRef, FixedSizeListArray, Float32Array, RecordBatch, UInt32Array};
+use arrow_schema::{DataType, Field, Schema as ArrowSchema, SchemaRef};
+use datafusion::arrow::compute::{concat, concat_batches, take};
+use futures::stream::{self, BoxStream};
+use futures::{StreamExt, TryStreamExt};
+use lance::dataset::ROW_ID;
+use lance::index::vector::MetricType;
+
+use super::NativeTable;
+use crate::error::{Error, Result};
+use crate::io::bad_vectors::{mark_null_vectors, marker_column_of};
+use crate::query::filter::lance_filter;
+use crate::query::flat::{is_f16_vector, is_multivector};
+use crate::query::DISTANCE_COLUMN;
+
+/// The searches of the right table run at the same time by
+/// [NativeTable::similarity_join].
+const JOIN_SEARCH_PARALLELISM: usize = 8;
+
+/// Default number of rows of the left table read and searched at a time by
+/// [NativeTable::similarity_join].
+pub const DEFAULT_JOIN_BATCH_SIZE: usize = 1024;
+
+/// The column of the rank of a right row among the nearest rows of its left
+/// row, 1 for the nearest, in the results of [NativeTable::similarity_join].
+pub const JOIN_RANK_COLUMN: &str = "rank";
+
+/// The column of the distance of the vectors of the two rows in the results
+/// of [NativeTable::similarity_join].
+pub const JOIN_DISTANCE_COLUMN: &str = "distance";
+
+/// Options of [NativeTable::similarity_join].
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimilarityJoinOptions {
+    /// The columns of the left table returned with each pair, named
+    /// `left_<column>`. With none, the `_rowid` of the left row is returned,
+    /// named `left_rowid`.
+    pub left_columns: Vec<String>,
+    /// The columns of the right table returned with each pair, named
+    /// `right_<column>`. With none, the `_rowid` of the right row is returned,
+    /// named `right_rowid`.
+    pub right_columns: Vec<String>,
+    /// The rows of the left table to find neighbors for.
+    pub left_filter: Option<String>,
+    /// The rows of the right table that can be neighbors, applied to the
+    /// nearest rows found, see [crate::Query::filter].
+    pub right_filter: Option<String>,
+    /// The distance metric, the one of the index of the right column by default.
+    pub metric_type: Option<MetricType>,
+    /// The IVF partitions probed by the searches of the index of the right
+    /// column, see [crate::Query::nprobes].
+    pub nprobes: Option<usize>,
+    /// The rows of the left table read, searched and returned at a time.
+    pub batch_size: usize,
+}
+
+impl Default for SimilarityJoinOptions {
+    fn default() -> Self {
+        Self {
+            left_columns: Vec::new(),
+            right_columns: Vec::new(),
+            left_filter: None,
+            right_filter: None,
+            metric_type: None,
+            nprobes: None,
+            batch_size: DEFAULT_JOIN_BATCH_SIZE,
+        }
+    }
+}
+
+/// The columns of a side of the join read from its table, and their names in
+/// the results.
+struct JoinSide {
+    columns: Vec<String>,
+    fields: Vec<Field>,
+}
+
+impl JoinSide {
+    fn new(side: &str, schema: &ArrowSchema, columns: &[String]) -> Result<Self> {
+        if columns.is_empty() {
+            return Ok(Self {
+                columns: vec![ROW_ID.to_string()],
+                fields: vec![Field::new(format!("{side}_rowid"), DataType::UInt64, true)],
+            });
+        }
+        let fields = columns
+            .iter()
+            .map(|column| {
+                let field = schema
+                    .field_with_name(column)
+                    .map_err(|_| Error::InvalidInput {
+                        message: format!("the {side} table has no column '{column}'"),
+                    })?;
+                Ok(Field::new(
+                    format!("{side}_{column}"),
+                    field.data_type().clone(),
+                    true,
+                ))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self {
+            columns: columns.to_vec(),
+            fields,
+        })
+    }
+
+    fn with_row_id(&self) -> bool {
+        self.columns.iter().any(|c| c == ROW_ID)
+    }
+}
+
+/// The width of the float32 vectors of `column`, searched by the join.
+fn vector_width(schema: &ArrowSchema, side: &str, column: &str) -> Result<i32> {
+    let field = schema
+        .field_with_name(column)
+        .map_err(|_| Error::InvalidInput {
+            message: format!("the {side} table has no column '{column}'"),
+        })?;
+    match field.data_type() {
+        DataType::FixedSizeList(_, width) if !is_f16_vector(field) && !is_multivector(field) => {
+            Ok(*width)
+        }
+        data_type => Err(Error::InvalidInput {
+            message: format!(
+                "similarity_join searches columns of float32 vectors, '{column}' of the {side} table is {data_type}"
+            ),
+        }),
+    }
+}
+
+/// What the searches of one batch of the left table share.
+struct Join {
+    right: NativeTable,
+    left_column: String,
+    right_column: String,
+    k: usize,
+    options: SimilarityJoinOptions,
+    left: JoinSide,
+    right_side: JoinSide,
+    schema: SchemaRef,
+}
+
+impl Join {
+    /// The nearest rows of the right table to `vector`, in the order of their
+    /// distance.
+    async fn search(&self, vector: Float32Array) -> Result<Option<RecordBatch>> {
+        let mut query = self
+            .right
+            .search(vector)
+            .column(&self.right_column)
+            .limit(self.k)
+            .use_index(true)
+            .metric_type(self.options.metric_type)
+            .filter(self.options.right_filter.clone())
+            .with_row_id(self.right_side.with_row_id());
+        let select = self.right_side.columns.iter().filter(|c| *c != ROW_ID);
+        query = query.select(Some(select.cloned().collect()));
+        if let Some(nprobes) = self.options.nprobes {
+            query = query.nprobes(nprobes);
+        }
+        let batches: Vec<RecordBatch> = query.execute().await?.try_collect().await?;
+        let Some(first) = batches.first() else {
+            return Ok(None);
+        };
+        Ok(Some(
+            concat_batches(&first.schema(), &batches).map_err(lance::Error::from)?,
+        ))
+    }
+
+    /// The pairs of the rows of `batch` of the left table and their nearest
+    /// rows of the right table.
+    async fn join_batch(self: Arc<Self>, batch: RecordBatch) -> Result<Option<RecordBatch>> {
+        let batch =
+            mark_null_vectors(&batch, &self.left_column, true).map_err(lance::Error::from)?;
+        let vectors = batch[self.left_column.as_str()]
+            .as_any()
+            .downcast_ref::<FixedSizeListArray>()
+            .cloned();
+        let Some(vectors) = vectors else {
+            return Ok(None);
+        };
+        let rows = (0..batch.num_rows())
+            .filter(|row| vectors.is_valid(*row))
+            .filter_map(|row| {
+                let vector = vectors.value(row);
+                let vector = vector.as_any().downcast_ref::<Float32Array>()?.clone();
+                Some((row as u32, vector))
+            })
+            .collect::<Vec<_>>();
+        let found = stream::iter(rows)
+            .map(|(row, vector)| {
+                let join = self.clone();
+                async move { Ok::<_, Error>((row, join.search(vector).await?)) }
+            })
+            .buffered(JOIN_SEARCH_PARALLELISM)
+            .try_collect::<Vec<_>>()
+            .await?;
+
+        let mut left_rows = Vec::new();
+        let mut ranks = Vec::new();
+        let mut distances = Vec::new();
+        let mut right_batches = Vec::new();
+        for (row, neighbors) in found.into_iter() {
+            let Some(neighbors) = neighbors else {
+                continue;
+            };
+            let scores = neighbors[DISTANCE_COLUMN]
+                .as_any()
+                .downcast_ref::<Float32Array>()
+                .ok_or_else(|| Error::Schema {
+                    message: format!("the '{DISTANCE_COLUMN}' column of a search is not float32"),
+                })?;
+            for (rank, score) in scores.values().iter().enumerate() {
+                left_rows.push(row);
+                ranks.push(rank as u32 + 1);
+                distances.push(*score);
+            }
+            right_batches.push(neighbors);
+        }
+        if left_rows.is_empty() {
+            return Ok(None);
+        }
+
+        let left_rows = UInt32Array::from(left_rows);
+        let mut columns: Vec<ArrayRef> = Vec::new();
+        for column in &self.left.columns {
+            let values = take(batch[column.as_str()].as_ref(), &left_rows, None)
+                .map_err(lance::Error::from)?;
+            columns.push(values);
+        }
+        for column in &self.right_side.columns {
+            let values = right_batches
+                .iter()
+                .map(|b| b[column.as_str()].as_ref())
+                .collect::<Vec<_>>();
+            columns.push(concat(&values).map_err(lance::Error::from)?);
+        }
+        columns.push(Arc::new(UInt32Array::from(ranks)));
+        columns.push(Arc::new(Float32Array::from(distances)));
+        let batch =
+            RecordBatch::try_new(self.schema.clone(), columns).map_err(lance::Error::from)?;
+        Ok(Some(batch))
+    }
+}
+
+impl NativeTable {
+    /// The `k` nearest rows of `right` to each row of this table, by the
+    /// vectors of `left_column` of this table and `right_column` of `right`.
+    ///
+    /// Each row of the results is a pair of a left and a right row, with the
+    /// columns of [SimilarityJoinOptions::left_columns] and
+    /// [SimilarityJoinOptions::right_columns], the [JOIN_RANK_COLUMN] of the
+    /// right row among the neighbors of the left row and the
+    /// [JOIN_DISTANCE_COLUMN] of their vectors. The pairs of a left row are
+    /// in the order of their rank.
+    ///
+    /// The left table is read [SimilarityJoinOptions::batch_size] rows at a
+    /// time, the rows of a batch are searched for in `right`, using the index
+    /// of `right_column` if it has one, and their pairs are returned before
+    /// the next batch is read. Left rows with a null vector have no pairs.
+    pub async fn similarity_join(
+        &self,
+        right: &NativeTable,
+        left_column: &str,
+        right_column: &str,
+        k: usize,
+        options: SimilarityJoinOptions,
+    ) -> Result<BoxStream<'static, Result<RecordBatch>>> {
+        if k == 0 || options.batch_size == 0 {
+            return Err(Error::InvalidInput {
+                message: "similarity_join needs a k and a batch_size of at least 1".to_string(),
+            });
+        }
+        let dataset = self.dataset.get().await?;
+        let left_schema = ArrowSchema::from(dataset.schema());
+        let right_schema = right.schema().await?;
+        let left_width = vector_width(&left_schema, "left", left_column)?;
+        let right_width = vector_width(&right_schema, "right", right_column)?;
+        if left_width != right_width {
+            return Err(Error::InvalidInput {
+                message: format!(
+                    "the vectors of '{left_column}' have {left_width} dimensions, those of '{right_column}' {right_width}"
+                ),
+            });
+        }
+        let left = JoinSide::new("left", &left_schema, &options.left_columns)?;
+        let right_side = JoinSide::new("right", &right_schema, &options.right_columns)?;
+        let mut fields = left.fields.clone();
+        fields.extend(right_side.fields.iter().cloned());
+        fields.push(Field::new(JOIN_RANK_COLUMN, DataType::UInt32, false));
+        fields.push(Field::new(JOIN_DISTANCE_COLUMN, DataType::Float32, false));
+        let schema = Arc::new(ArrowSchema::new(fields));
+
+        let mut scanner = dataset.scan();
+        let mut projection = left
+            .columns
+            .iter()
+            .filter(|c| *c != ROW_ID)
+            .map(String::as_str)
+            .collect::<Vec<_>>();
+        if !projection.contains(&left_column) {
+            projection.push(left_column);
+        }
+        let marker = marker_column_of(&left_schema, left_column);
+        if let Some(marker) = marker.as_deref().filter(|m| !projection.contains(m)) {
+            projection.push(marker);
+        }
+        scanner.project(&projection)?;
+        if left.with_row_id() {
+            scanner.with_row_id();
+        }
+        if let Some(filter) = options.left_filter.as_ref() {
+            scanner.filter(&lance_filter(filter, &left_schema)?)?;
+        }
+        scanner.batch_size(options.batch_size);
+        let join = Arc::new(Join {
+            right: right.clone(),
+            left_column: left_column.to_string(),
+            right_column: right_column.to_string(),
+            k,
+            options,
+            left,
+            right_side,
+            schema,
+        });
+        let pairs = scanner
+            .try_into_stream()
+            .await?
+            .map_err(Error::from)
+            .and_then(move |batch| join.clone().join_batch(batch))
+            .try_filter_map(|batch| async move { Ok(batch) });
+        Ok(pairs.boxed())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow_array::{Int32Array, RecordBatchReader, UInt64Array};
+    use lance::arrow::RecordBatchBuffer;
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::arrow::RecordBatchBuilder;
+
+    type Pair = (i32, i32, u32, f32);
+
+    /// Rows `ids` with scattered 3-dimensional vectors, which have no ties of
+    /// their distances.
+    fn vectors(ids: std::ops::Range<i32>, seed: f32) -> Vec<(i32, [f32; 3])> {
+        ids.map(|i| {
+            let x = i as f32 + seed;
+            (i, [x.sin(), (x * 1.3).cos(), (x * 0.7).sin()])
+        })
+        .collect()
+    }
+
+    fn rows(vectors: &[(i32, [f32; 3])]) -> Box<dyn RecordBatchReader> {
+        let ids = vectors.iter().map(|(id, _)| *id);
+        let batch = RecordBatchBuilder::new()
+            .column("id", Arc::new(Int32Array::from_iter_values(ids)))
+            .vector_column(
+                "vector",
+                3,
+                vectors.iter().map(|(_, v)| *v).collect::<Vec<_>>(),
+            )
+            .build()
+            .unwrap();
+        Box::new(RecordBatchBuffer::new(vec![batch]))
+    }
+
+    /// The `k` nearest right rows of each left row by brute force, the right
+    /// rows filtered after the search.
+    fn reference(
+        left: &[(i32, [f32; 3])],
+        right: &[(i32, [f32; 3])],
+        k: usize,
+        keep: impl Fn(i32) -> bool,
+    ) -> Vec<Pair> {
+        let mut pairs = Vec::new();
+        for (left_id, a) in left {
+            let mut neighbors = right
+                .iter()
+                .map(|(id, b)| {
+                    let distance = a.iter().zip(b).map(|(x, y)| (x - y) * (x - y)).sum::<f32>();
+                    (distance, *id)
+                })
+                .collect::<Vec<_>>();
+            neighbors.sort_by(|x, y| x.partial_cmp(y).unwrap());
+            let neighbors = neighbors.into_iter().take(k).filter(|(_, id)| keep(*id));
+            for (rank, (distance, id)) in neighbors.enumerate() {
+                pairs.push((*left_id, id, rank as u32 + 1, distance));
+            }
+        }
+        pairs
+    }
+
+    async fn join(
+        left: &NativeTable,
+        right: &NativeTable,
+        k: usize,
+        options: SimilarityJoinOptions,
+    ) -> Vec<Pair> {
+        let stream = left
+            .similarity_join(right, "vector", "vector", k, options)
+            .await
+            .unwrap();
+        let batches: Vec<RecordBatch> = stream.try_collect().await.unwrap();
+        let mut pairs = Vec::new();
+        for batch in batches {
+            let ids = |name: &str| batch[name].as_any().downcast_ref::<Int32Array>().unwrap();
+            let ranks = batch["rank"]
+                .as_any()
+                .downcast_ref::<UInt32Array>()
+                .unwrap();
+            let distances = batch["distance"].as_any().downcast_ref::<Float32Array>();
+            let distances = distances.unwrap();
+            for row in 0..batch.num_rows() {
+                pairs.push((
+                    ids("left_id").value(row),
+                    ids("right_id").value(row),
+                    ranks.value(row),
+                    distances.value(row),
+                ));
+            }
+        }
+        pairs
+    }
+
+    fn assert_pairs(mut pairs: Vec<Pair>, expected: Vec<Pair>) {
+        pairs.sort_by_key(|p| (p.0, p.2));
+        let ids = |pairs: &[Pair]| pairs.iter().map(|p| (p.0, p.1, p.2)).collect::<Vec<_>>();
+        assert_eq!(ids(&pairs), ids(&expected));
+        for (pair, expected) in pairs.iter().zip(&expected) {
+            assert!((pair.3 - expected.3).abs() < 1e-5, "{pair:?} {expected:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_similarity_join() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        let (left_rows, right_rows) = (vectors(0..40, 0.5), vectors(0..100, 100.0));
+        let left = NativeTable::create(uri, "left", rows(&left_rows), None)
+            .await
+            .unwrap();
+        let right = NativeTable::create(uri, "right", rows(&right_rows), None)
+            .await
+            .unwrap();
+        let options = SimilarityJoinOptions {
+            left_columns: vec!["id".to_string()],
+            right_columns: vec!["id".to_string()],
+            batch_size: 16,
+            ..Default::default()
+        };
+
+        let pairs = join(&left, &right, 5, options.clone()).await;
+        assert_eq!(pairs.len(), 200);
+        assert_pairs(pairs, reference(&left_rows, &right_rows, 5, |_| true));
+
+        // A left filter leaves out left rows, a right filter the right rows
+        // of the nearest ones.
+        let filtered = SimilarityJoinOptions {
+            left_filter: Some("id < 10".to_string()),
+            right_filter: Some("id >= 50".to_string()),
+            ..options.clone()
+        };
+        let pairs = join(&left, &right, 5, filtered).await;
+        let expected = reference(&left_rows[..10], &right_rows, 5, |id| id >= 50);
+        let expected = expected
+            .iter()
+            .enumerate()
+            .map(|(i, p)| {
+                // The ranks of the rows left by the filter.
+                let rank = expected[..i].iter().filter(|q| q.0 == p.0).count() as u32 + 1;
+                (p.0, p.1, rank, p.3)
+            })
+            .collect();
+        assert_pairs(pairs, expected);
+
+        // Without key columns, the row ids of the pairs are returned.
+        let stream = left
+            .similarity_join(&right, "vector", "vector", 1, Default::default())
+            .await
+            .unwrap();
+        let batches: Vec<RecordBatch> = stream.try_collect().await.unwrap();
+        let schema = batches[0].schema();
+        let names = schema.fields().iter().map(|f| f.name().as_str());
+        assert_eq!(
+            names.collect::<Vec<_>>(),
+            vec!["left_rowid", "right_rowid", "rank", "distance"]
+        );
+        let right_ids = batches[0]["right_rowid"]
+            .as_any()
+            .downcast_ref::<UInt64Array>();
+        assert!(right_ids.unwrap().values().iter().all(|id| *id < 100));
+        assert_eq!(batches.iter().map(RecordBatch::num_rows).sum::<usize>(), 40);
+
+        let err = left
+            .similarity_join(&right, "id", "vector", 1, Default::default())
+            .await
+            .err()
+            .unwrap();
+        assert!(matches!(err, Error::InvalidInput { .. }), "{err}");
+    }
+}
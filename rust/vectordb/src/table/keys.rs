@@ -0,0 +1,673 @@
+// Copyright 2023 LanceDB Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Stable row ids and primary keys of tables.
+//!
+//! lance addresses a row by its fragment and its offset in the fragment, the
+//! `_rowid` of queries made with [crate::Query::with_row_id]. Deletes leave the
+//! other rows in place, only the compaction of [NativeTable::optimize] moves
+//! them, so tables created with stable row ids are not compacted.
+
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use arrow_array::{Array, RecordBatch, UInt32Array, UInt64Array};
+use arrow_schema::{DataType, Schema as ArrowSchema};
+use datafusion::arrow::compute::{cast, concat_batches, take};
+use datafusion::scalar::ScalarValue;
+use futures::TryStreamExt;
+use lance::dataset::{Dataset, ROW_ID};
+use serde::{Deserialize, Serialize};
+
+use super::constraints::Constraint;
+use super::partitions::Partitioning;
+use super::{DatasetRef, NativeTable};
+use crate::error::{Error, Result};
+use crate::query::{col, Literal};
+
+/// The file of the [TableProperties] of a table, in its directory.
+pub(crate) const PROPERTIES_FILE: &str = "_properties.json";
+
+/// The most duplicated keys reported by [Error::DuplicateKeys].
+pub(super) const MAX_DUPLICATE_EXAMPLES: usize = 5;
+
+/// The settings of a table that lance does not record.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct TableProperties {
+    /// Whether the rows of the table keep their `_rowid`, see the module docs.
+    #[serde(default)]
+    pub stable_row_ids: bool,
+    /// The column set by [NativeTable::ensure_primary_key].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub primary_key: Option<String>,
+    /// The column of ids assigned to the written rows.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auto_id: Option<AutoId>,
+    /// The column the rows are partitioned by, and the value of each fragment.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub partition_by: Option<Partitioning>,
+    /// Whether the written rows record their version, see
+    /// [crate::database::CreateTableBuilder::track_modifications].
+    #[serde(default)]
+    pub track_modifications: bool,
+    /// The constraints of the written rows, see [NativeTable::set_constraints].
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub constraints: Vec<Constraint>,
+    /// The schema-level metadata of the table, see [super::metadata].
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub schema_metadata: HashMap<String, String>,
+    /// The metadata of the top-level fields of the table, by field name.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub field_metadata: HashMap<String, HashMap<String, String>>,
+    /// The column of the stored norms of each vector column, see
+    /// [NativeTable::store_vector_norms].
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub norm_columns: HashMap<String, String>,
+}
+
+/// The column of [crate::database::CreateTableBuilder::auto_id], and the id of
+/// the next row written.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct AutoId {
+    pub column: String,
+    pub next: u64,
+}
+
+/// The rows of the keys of a version of a table.
+#[derive(Debug)]
+pub(crate) struct KeyIndex {
+    column: String,
+    version: u64,
+    data_type: DataType,
+    rows: HashMap<ScalarValue, u64>,
+}
+
+/// The key index last built.
+#[derive(Debug, Default)]
+pub(crate) struct KeyCache {
+    index: Mutex<Option<Arc<KeyIndex>>>,
+}
+
+/// Index the keys of `column` in `dataset`, failing if they are not unique.
+async fn build_key_index(dataset: &Dataset, column: &str) -> Result<KeyIndex> {
+    let field = dataset
+        .schema()
+        .field(column)
+        .ok_or_else(|| Error::InvalidInput {
+            message: format!("the table has no column '{column}'"),
+        })?;
+    let data_type = field.data_type();
+    if !data_type.is_integer() && !matches!(data_type, DataType::Utf8 | DataType::LargeUtf8) {
+        return Err(Error::InvalidInput {
+            message: format!(
+                "the primary key '{column}' must be a column of integers or strings, not {data_type}"
+            ),
+        });
+    }
+
+    let mut scanner = dataset.scan();
+    scanner.project(&[column])?;
+    scanner.with_row_id();
+    let mut rows = HashMap::new();
+    let mut duplicated = HashSet::new();
+    let mut examples = Vec::new();
+    let mut nulls = 0;
+    let mut stream = scanner.try_into_stream().await?;
+    while let Some(batch) = stream.try_next().await? {
+        let keys = batch[column].as_ref();
+        let row_ids = batch[ROW_ID].as_any().downcast_ref::<UInt64Array>();
+        let row_ids = row_ids.expect("row ids are u64");
+        for (i, row_id) in row_ids.values().iter().enumerate() {
+            if keys.is_null(i) {
+                nulls += 1;
+                continue;
+            }
+            match rows.entry(ScalarValue::try_from_array(keys, i).map_err(lance::Error::from)?) {
+                Entry::Vacant(entry) => {
+                    entry.insert(*row_id);
+                }
+                Entry::Occupied(entry) => {
+                    if duplicated.insert(entry.key().clone())
+                        && examples.len() < MAX_DUPLICATE_EXAMPLES
+                    {
+                        examples.push(entry.key().to_string());
+                    }
+                }
+            }
+        }
+    }
+    if nulls > 0 {
+        return Err(Error::InvalidInput {
+            message: format!("the primary key '{column}' has {nulls} null values"),
+        });
+    }
+    if !duplicated.is_empty() {
+        return Err(Error::DuplicateKeys {
+            column: column.to_string(),
+            count: duplicated.len(),
+            examples,
+        });
+    }
+    Ok(KeyIndex {
+        column: column.to_string(),
+        version: dataset.version().version,
+        data_type,
+        rows,
+    })
+}
+
+impl KeyIndex {
+    /// `key` as a value of the key column, `None` if it does not fit its type.
+    fn key_value(&self, key: &Literal) -> Result<Option<ScalarValue>> {
+        let value = match key {
+            Literal::Bool(value) => ScalarValue::Boolean(Some(*value)),
+            Literal::Int(value) => ScalarValue::Int64(Some(*value)),
+            Literal::UInt(value) => ScalarValue::UInt64(Some(*value)),
+            Literal::Float(value) => ScalarValue::Float64(Some(*value)),
+            Literal::String(value) => ScalarValue::Utf8(Some(value.clone())),
+        };
+        let Ok(array) = cast(&value.to_array(), &self.data_type) else {
+            return Ok(None);
+        };
+        if array.is_null(0) {
+            return Ok(None);
+        }
+        let value = ScalarValue::try_from_array(&array, 0).map_err(lance::Error::from)?;
+        Ok(Some(value))
+    }
+
+    /// The row ids and keys of the rows of `keys`, in their order, without
+    /// the keys that are not in the table or are repeated.
+    fn lookup(&self, keys: Vec<Literal>) -> Result<Vec<(u64, Literal)>> {
+        let mut seen = HashSet::new();
+        let mut found = Vec::new();
+        for key in keys {
+            let Some(value) = self.key_value(&key)? else {
+                continue;
+            };
+            if let Some(row_id) = self.rows.get(&value) {
+                if seen.insert(*row_id) {
+                    found.push((*row_id, key));
+                }
+            }
+        }
+        Ok(found)
+    }
+}
+
+/// The rows `row_ids` of `dataset`, in their order.
+async fn take_rows(dataset: &Dataset, row_ids: &[u64]) -> Result<RecordBatch> {
+    let schema = Arc::new(ArrowSchema::from(dataset.schema()));
+    let mut per_fragment: BTreeMap<u64, Vec<(u32, usize)>> = BTreeMap::new();
+    for (position, row_id) in row_ids.iter().enumerate() {
+        let offsets = per_fragment.entry(row_id >> 32).or_default();
+        offsets.push((*row_id as u32, position));
+    }
+    let fragments = dataset.get_fragments();
+    let mut batches = Vec::new();
+    // The position in the requested order of each taken row.
+    let mut positions = Vec::with_capacity(row_ids.len());
+    for (id, mut offsets) in per_fragment {
+        let Some(fragment) = fragments.iter().find(|f| f.id() as u64 == id) else {
+            continue;
+        };
+        offsets.sort_unstable();
+        let indices = offsets
+            .iter()
+            .map(|(offset, _)| *offset)
+            .collect::<Vec<_>>();
+        batches.push(fragment.take(&indices, dataset.schema()).await?);
+        positions.extend(offsets.iter().map(|(_, position)| *position));
+    }
+    let batch = concat_batches(&schema, &batches).map_err(lance::Error::from)?;
+    let mut order = (0..positions.len() as u32).collect::<Vec<_>>();
+    order.sort_unstable_by_key(|i| positions[*i as usize]);
+    let order = UInt32Array::from(order);
+    let columns = batch
+        .columns()
+        .iter()
+        .map(|column| take(column.as_ref(), &order, None))
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .map_err(lance::Error::from)?;
+    Ok(RecordBatch::try_new(schema, columns).map_err(lance::Error::from)?)
+}
+
+impl DatasetRef {
+    pub(crate) async fn properties(&self) -> Result<TableProperties> {
+        if self.is_detached() {
+            return Ok(TableProperties::default());
+        }
+        let (store, base) = self.object_store().await?;
+        match store.inner.get(&base.child(PROPERTIES_FILE)).await {
+            Ok(result) => {
+                let bytes = result.bytes().await?;
+                serde_json::from_slice(&bytes).map_err(|e| Error::InvalidInput {
+                    message: format!("cannot decode the table properties: {e}"),
+                })
+            }
+            Err(object_store::Error::NotFound { .. }) => Ok(TableProperties::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+impl NativeTable {
+    pub(crate) async fn properties(&self) -> Result<TableProperties> {
+        self.dataset.properties().await
+    }
+
+    pub(crate) async fn set_properties(&self, properties: TableProperties) -> Result<()> {
+        let bytes = serde_json::to_vec(&properties).map_err(|e| Error::InvalidInput {
+            message: format!("cannot encode the table properties: {e}"),
+        })?;
+        let (store, base) = self.dataset.object_store().await?;
+        store
+            .inner
+            .put(&base.child(PROPERTIES_FILE), bytes.into())
+            .await?;
+        Ok(())
+    }
+
+    /// Whether the table was created with stable row ids, see
+    /// [crate::database::CreateTableBuilder::enable_stable_row_ids].
+    pub async fn stable_row_ids(&self) -> Result<bool> {
+        Ok(self.properties().await?.stable_row_ids)
+    }
+
+    /// The column of the ids the table assigns to the written rows, see
+    /// [crate::database::CreateTableBuilder::auto_id].
+    pub async fn auto_id(&self) -> Result<Option<String>> {
+        Ok(self
+            .properties()
+            .await?
+            .auto_id
+            .map(|auto_id| auto_id.column))
+    }
+
+    /// Set `next` to the id of the next row written, while holding the commit
+    /// lock of the table.
+    pub(super) async fn load_next_auto_id(&self, next: &AtomicU64) -> Result<()> {
+        if let Some(auto_id) = self.properties().await?.auto_id {
+            next.store(auto_id.next, Ordering::Relaxed);
+        }
+        Ok(())
+    }
+
+    /// Record `next` as the id of the next row written, once the rows are
+    /// written and before the commit lock is released.
+    pub(super) async fn store_next_auto_id(&self, next: &AtomicU64) -> Result<()> {
+        let mut properties = self.properties().await?;
+        if let Some(auto_id) = properties.auto_id.as_mut() {
+            auto_id.next = next.load(Ordering::Relaxed);
+            self.set_properties(properties).await?;
+        }
+        Ok(())
+    }
+
+    /// The column set by [Self::ensure_primary_key].
+    pub async fn primary_key(&self) -> Result<Option<String>> {
+        Ok(self.properties().await?.primary_key)
+    }
+
+    /// Make `column`, of integers or strings, the primary key of the table,
+    /// used by [Self::take_by_key] and [Self::delete_by_key].
+    ///
+    /// The keys are indexed to check that they are unique and not null, and the
+    /// index is kept to look the keys up. Duplicated keys fail with an
+    /// [Error::DuplicateKeys] giving some of them. Later versions of the table
+    /// are indexed again when they are looked up.
+    pub async fn ensure_primary_key(&self, column: &str) -> Result<()> {
+        let dataset = self.dataset.get().await?;
+        let index = build_key_index(&dataset, column).await?;
+        let mut properties = self.properties().await?;
+        if properties.primary_key.as_deref() != Some(column) {
+            properties.primary_key = Some(column.to_string());
+            self.set_properties(properties).await?;
+        }
+        *self.dataset.keys().index.lock().unwrap() = Some(Arc::new(index));
+        Ok(())
+    }
+
+    /// The key index of the current version of the table.
+    async fn key_index(&self, dataset: &Dataset) -> Result<Arc<KeyIndex>> {
+        let Some(column) = self.properties().await?.primary_key else {
+            return Err(Error::InvalidInput {
+                message: "the table has no primary key, see ensure_primary_key".to_string(),
+            });
+        };
+        let version = dataset.version().version;
+        let cached = self.dataset.keys().index.lock().unwrap().clone();
+        if let Some(index) = cached.filter(|i| i.version == version && i.column == column) {
+            return Ok(index);
+        }
+        let index = Arc::new(build_key_index(dataset, &column).await?);
+        *self.dataset.keys().index.lock().unwrap() = Some(index.clone());
+        Ok(index)
+    }
+
+    /// The rows of `keys`, values of the primary key, in their order. The keys
+    /// that are not in the table are left out.
+    pub async fn take_by_key<T: Into<Literal>>(
+        &self,
+        keys: impl IntoIterator<Item = T>,
+    ) -> Result<RecordBatch> {
+        let dataset = self.dataset.get().await?;
+        let index = self.key_index(&dataset).await?;
+        let keys = keys.into_iter().map(Into::into).collect();
+        let row_ids = index.lookup(keys)?.into_iter().map(|(row_id, _)| row_id);
+        take_rows(&dataset, &row_ids.collect::<Vec<_>>()).await
+    }
+
+    /// Delete the rows of `keys`, values of the primary key, returning the
+    /// number of rows deleted.
+    pub async fn delete_by_key<T: Into<Literal>>(
+        &self,
+        keys: impl IntoIterator<Item = T>,
+    ) -> Result<usize> {
+        let dataset = self.dataset.get().await?;
+        let index = self.key_index(&dataset).await?;
+        let keys = keys.into_iter().map(Into::into).collect();
+        let found = index.lookup(keys)?;
+        if found.is_empty() {
+            return Ok(0);
+        }
+        let filter = col(&index.column).in_list(found.iter().map(|(_, key)| key.clone()));
+        self.delete(&filter.to_string()).await?;
+        Ok(found.len())
+    }
+
+    /// The row ids and keys of the rows of `keys`, values of `column`, by the
+    /// key index of `dataset`. `None` if `column` is not the primary key.
+    pub(super) async fn lookup_keys(
+        &self,
+        dataset: &Dataset,
+        column: &str,
+        keys: Vec<Literal>,
+    ) -> Result<Option<Vec<(u64, Literal)>>> {
+        if self.properties().await?.primary_key.as_deref() != Some(column) {
+            return Ok(None);
+        }
+        let index = self.key_index(dataset).await?;
+        Ok(Some(index.lookup(keys)?))
+    }
+
+    /// Keep the key index of `dataset` as that of `deleted`, its version
+    /// without the rows of `keys`, so that it is not built again.
+    pub(super) fn forget_keys(
+        &self,
+        dataset: &Dataset,
+        deleted: &Dataset,
+        keys: &[Literal],
+    ) -> Result<()> {
+        let mut cached = self.dataset.keys().index.lock().unwrap();
+        let version = dataset.version().version;
+        let Some(index) = cached.as_ref().filter(|i| i.version == version) else {
+            return Ok(());
+        };
+        let mut rows = index.rows.clone();
+        for key in keys {
+            if let Some(value) = index.key_value(key)? {
+                rows.remove(&value);
+            }
+        }
+        *cached = Some(Arc::new(KeyIndex {
+            column: index.column.clone(),
+            version: deleted.version().version,
+            data_type: index.data_type.clone(),
+            rows,
+        }));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow_array::{Int32Array, RecordBatchReader, StringArray};
+    use lance::arrow::RecordBatchBuffer;
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::arrow::RecordBatchBuilder;
+    use crate::database::{connect, CreateTableMode};
+    use crate::table::MaintenanceConfig;
+
+    fn rows(ids: Vec<i32>) -> Box<dyn RecordBatchReader> {
+        let names = ids.iter().map(|i| format!("name {i}")).collect::<Vec<_>>();
+        let vectors = ids.iter().map(|i| [*i as f32, 0.0]).collect::<Vec<_>>();
+        let batch = RecordBatchBuilder::new()
+            .column("id", Arc::new(Int32Array::from(ids)))
+            .column("name", Arc::new(StringArray::from(names)))
+            .vector_column("vector", 2, vectors)
+            .build()
+            .unwrap();
+        Box::new(RecordBatchBuffer::new(vec![batch]))
+    }
+
+    fn ids(batch: &RecordBatch) -> Vec<i32> {
+        let ids = batch["id"].as_any().downcast_ref::<Int32Array>().unwrap();
+        ids.values().to_vec()
+    }
+
+    #[tokio::test]
+    async fn test_primary_key() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        let table = NativeTable::create(uri, "test", rows((0..10).collect()), None)
+            .await
+            .unwrap();
+        assert!(matches!(
+            table.take_by_key([1]).await,
+            Err(Error::InvalidInput { .. })
+        ));
+        table.ensure_primary_key("id").await.unwrap();
+        assert_eq!(table.primary_key().await.unwrap().as_deref(), Some("id"));
+
+        table.add(rows((10..20).collect()), None).await.unwrap();
+        let batch = table.take_by_key([15, 3, 42, 3, 11]).await.unwrap();
+        assert_eq!(ids(&batch), vec![15, 3, 11]);
+        let names = batch["name"]
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .unwrap();
+        assert_eq!(names.value(0), "name 15");
+
+        assert_eq!(table.delete_by_key([3, 15, 99]).await.unwrap(), 2);
+        assert_eq!(table.count_rows().await.unwrap(), 18);
+        let batch = table.take_by_key([3, 4, 15]).await.unwrap();
+        assert_eq!(ids(&batch), vec![4]);
+
+        // A reopened table keeps its primary key.
+        let table = NativeTable::open(uri, "test").await.unwrap();
+        assert_eq!(ids(&table.take_by_key([19]).await.unwrap()), vec![19]);
+    }
+
+    /// The row ids of the rows of `table` by id.
+    async fn row_ids(table: &NativeTable) -> BTreeMap<i32, u64> {
+        let batches: Vec<RecordBatch> = table
+            .search(vec![0.0, 0.0])
+            .limit(100)
+            .with_row_id(true)
+            .execute()
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+        let mut row_ids = BTreeMap::new();
+        for batch in &batches {
+            let ids = batch["id"].as_any().downcast_ref::<Int32Array>().unwrap();
+            let rows = batch[ROW_ID]
+                .as_any()
+                .downcast_ref::<UInt64Array>()
+                .unwrap();
+            row_ids.extend(
+                ids.values()
+                    .iter()
+                    .copied()
+                    .zip(rows.values().iter().copied()),
+            );
+        }
+        row_ids
+    }
+
+    #[tokio::test]
+    async fn test_stable_row_ids() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        let db = connect(uri).execute().await.unwrap();
+        for (name, stable) in [("stable", true), ("moving", false)] {
+            db.create_table(name, rows(vec![0, 1]))
+                .enable_stable_row_ids(stable)
+                .execute()
+                .await
+                .unwrap();
+            let table = NativeTable::open(uri, name).await.unwrap();
+            assert_eq!(table.stable_row_ids().await.unwrap(), stable);
+            for i in 1..4 {
+                table.add(rows(vec![2 * i, 2 * i + 1]), None).await.unwrap();
+            }
+            table.delete("id = 1").await.unwrap();
+            let before = row_ids(&table).await;
+            assert_eq!(before.len(), 7);
+
+            let stats = table.optimize(&MaintenanceConfig::default()).await.unwrap();
+            let after = row_ids(&table).await;
+            if stable {
+                assert_eq!(stats.fragments_removed, 0);
+                assert_eq!(after, before);
+            } else {
+                assert_eq!(stats.fragments_removed, 4);
+                assert_ne!(after, before);
+                assert_eq!(
+                    after.keys().collect::<Vec<_>>(),
+                    before.keys().collect::<Vec<_>>()
+                );
+            }
+        }
+
+        // Replacing a table resets its properties.
+        db.create_table("stable", rows(vec![0]))
+            .mode(CreateTableMode::Overwrite)
+            .execute()
+            .await
+            .unwrap();
+        let table = NativeTable::open(uri, "stable").await.unwrap();
+        assert!(!table.stable_row_ids().await.unwrap());
+    }
+
+    /// The auto ids of the rows of `table` by id.
+    async fn auto_ids(table: &NativeTable) -> BTreeMap<i32, u64> {
+        let dataset = table.dataset.get().await.unwrap();
+        let batches: Vec<RecordBatch> = dataset
+            .scan()
+            .try_into_stream()
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+        let mut auto_ids = BTreeMap::new();
+        for batch in &batches {
+            let uids = batch["uid"].as_any().downcast_ref::<UInt64Array>().unwrap();
+            auto_ids.extend(ids(batch).into_iter().zip(uids.values().iter().copied()));
+        }
+        auto_ids
+    }
+
+    #[tokio::test]
+    async fn test_auto_id() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        let db = connect(uri).execute().await.unwrap();
+        db.create_table("test", rows(vec![0, 1, 2]))
+            .auto_id("uid")
+            .execute()
+            .await
+            .unwrap();
+        let table = NativeTable::open(uri, "test").await.unwrap();
+        assert_eq!(table.auto_id().await.unwrap().as_deref(), Some("uid"));
+        table.add(rows(vec![3, 4]), None).await.unwrap();
+        table.add(rows(vec![5]), None).await.unwrap();
+        let expected = (0..6).map(|i| (i, i as u64)).collect::<BTreeMap<_, _>>();
+        assert_eq!(auto_ids(&table).await, expected);
+
+        // The ids of deleted rows are not reused, also by a reopened table.
+        table.delete("id = 5").await.unwrap();
+        let table = NativeTable::open(uri, "test").await.unwrap();
+        table.add(rows(vec![6]), None).await.unwrap();
+        let uids = auto_ids(&table).await;
+        assert_eq!((uids.get(&5), uids[&6]), (None, 6));
+
+        // Concurrent appends get distinct ids.
+        let appends = (0..4).map(|i| async move {
+            let table = NativeTable::open(uri, "test").await.unwrap();
+            table.add(rows(vec![10 * i + 10, 10 * i + 11]), None).await
+        });
+        for append in futures::future::join_all(appends).await {
+            append.unwrap();
+        }
+        let table = NativeTable::open(uri, "test").await.unwrap();
+        let mut uids = auto_ids(&table).await.into_values().collect::<Vec<_>>();
+        uids.sort();
+        let expected = (0..5).chain(6..15).collect::<Vec<_>>();
+        assert_eq!(uids, expected);
+
+        // The ids are keys.
+        table.ensure_primary_key("uid").await.unwrap();
+        let labels = RecordBatchBuilder::new()
+            .column("uid", Arc::new(UInt64Array::from(vec![0, 6])))
+            .column("label", Arc::new(StringArray::from(vec!["first", "last"])))
+            .build()
+            .unwrap();
+        let labels = Box::new(RecordBatchBuffer::new(vec![labels]));
+        table.merge(labels, "uid", "uid").await.unwrap();
+        let batch = table.take_by_key([6u64]).await.unwrap();
+        let label = batch["label"].as_any().downcast_ref::<StringArray>();
+        assert_eq!(label.unwrap().value(0), "last");
+
+        let mut with_ids = rows(vec![20]);
+        let next = Arc::new(AtomicU64::new(0));
+        with_ids = crate::io::auto_id::with_auto_ids(with_ids, "uid", next).unwrap();
+        let err = table.add(with_ids, None).await.unwrap_err();
+        assert!(matches!(err, Error::InvalidInput { .. }), "{err}");
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_keys() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        let table = NativeTable::create(uri, "test", rows(vec![1, 2, 2, 3, 3, 3, 4]), None)
+            .await
+            .unwrap();
+        let Err(Error::DuplicateKeys {
+            column,
+            count,
+            examples,
+        }) = table.ensure_primary_key("id").await
+        else {
+            panic!("expected duplicate keys");
+        };
+        assert_eq!((column.as_str(), count), ("id", 2));
+        assert_eq!(examples, vec!["2", "3"]);
+        assert_eq!(table.primary_key().await.unwrap(), None);
+
+        assert!(matches!(
+            table.ensure_primary_key("vector").await,
+            Err(Error::InvalidInput { .. })
+        ));
+        table.ensure_primary_key("name").await.err().unwrap();
+    }
+}
@@ -0,0 +1,763 @@
+// Copyright 2023 LanceDB Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Table maintenance: deleting expired rows, compacting small fragments,
+//! rebuilding stale indices and pruning old versions, on demand or in a
+//! background task.
+
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use arrow_array::RecordBatch;
+use futures::TryStreamExt;
+use lance::dataset::fragment::FileFragment;
+use lance::dataset::{Dataset, WriteMode, WriteParams};
+use lance::datatypes::Schema;
+use lance::format::Fragment;
+use lance::io::deletion_file_path;
+use lance::io::object_store::ObjectStore;
+use object_store::path::Path;
+use tokio::runtime::Handle;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tracing::{field, info_span, warn};
+
+use super::indices::VERSIONS_DIR;
+use super::retention::RetentionPolicy;
+use super::write;
+use super::NativeTable;
+use crate::error::{Error, Result};
+use crate::spans::timed;
+
+/// What a maintenance run does, see [NativeTable::optimize].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MaintenanceConfig {
+    /// How long [NativeTable::start_maintenance] waits between runs.
+    pub interval: Duration,
+
+    /// Delete the rows older than the retention period first, see
+    /// [NativeTable::apply_retention]. `None` keeps all rows.
+    pub retention: Option<RetentionPolicy>,
+
+    /// Merge the small fragments appended since the table was indexed.
+    pub compact: bool,
+
+    /// Rebuild the indices that do not cover all rows, with the parameters
+    /// they were created with.
+    pub optimize_indices: bool,
+
+    /// Remove the checkpoints made longer ago than this before pruning, see
+    /// [NativeTable::expire_checkpoints]. `None` keeps all checkpoints.
+    pub expire_checkpoints_older_than: Option<Duration>,
+
+    /// Remove the versions older than this, except the latest one and those
+    /// indices were built on or checkpoints were made at. `None` keeps all
+    /// versions.
+    pub prune_older_than: Option<Duration>,
+}
+
+impl Default for MaintenanceConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(3600),
+            retention: None,
+            compact: true,
+            optimize_indices: true,
+            expire_checkpoints_older_than: None,
+            prune_older_than: None,
+        }
+    }
+}
+
+/// What a maintenance run did.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OptimizationStats {
+    /// The rows deleted by the retention policy.
+    pub rows_expired: usize,
+    /// The fragments merged into others by compaction.
+    pub fragments_removed: usize,
+    /// The fragments compaction wrote.
+    pub fragments_added: usize,
+    /// The indices rebuilt.
+    pub indices_optimized: usize,
+    /// The checkpoints removed.
+    pub checkpoints_expired: usize,
+    /// The versions removed.
+    pub versions_pruned: usize,
+    /// The data and deletion files removed with the versions, no remaining
+    /// version uses them.
+    pub files_removed: usize,
+}
+
+#[derive(Default)]
+struct MaintenanceState {
+    runs: usize,
+    last_stats: Option<OptimizationStats>,
+    last_error: Option<String>,
+}
+
+/// Runs the maintenance of a table in the background until dropped, see
+/// [NativeTable::start_maintenance].
+///
+/// Dropping the handle lets a run in progress finish, so that it does not
+/// leave written but uncommitted files behind, and then stops the task.
+pub struct MaintenanceHandle {
+    stop: watch::Sender<bool>,
+    task: Option<JoinHandle<()>>,
+    state: Arc<Mutex<MaintenanceState>>,
+}
+
+impl MaintenanceHandle {
+    /// The number of runs finished so far, successful or not.
+    pub fn runs(&self) -> usize {
+        self.state.lock().unwrap().runs
+    }
+
+    /// What the last successful run did.
+    pub fn last_stats(&self) -> Option<OptimizationStats> {
+        self.state.lock().unwrap().last_stats.clone()
+    }
+
+    /// The error of the last run, `None` if it succeeded.
+    pub fn last_error(&self) -> Option<String> {
+        self.state.lock().unwrap().last_error.clone()
+    }
+
+    /// Stop the task, waiting for a run in progress to finish.
+    pub async fn stop(mut self) {
+        let _ = self.stop.send(true);
+        if let Some(task) = self.task.take() {
+            let _ = task.await;
+        }
+    }
+}
+
+impl Drop for MaintenanceHandle {
+    fn drop(&mut self) {
+        let _ = self.stop.send(true);
+    }
+}
+
+/// The fragments of `dataset`, in order.
+pub(super) fn fragments(dataset: &Dataset) -> Vec<Fragment> {
+    dataset
+        .get_fragments()
+        .iter()
+        .map(|f| f.metadata().clone())
+        .collect()
+}
+
+/// The largest fragment id of `dataset`.
+fn max_fragment_id(dataset: &Dataset) -> Option<u64> {
+    dataset.get_fragments().iter().map(|f| f.id() as u64).max()
+}
+
+/// The largest id of the fragments covered by an index of `dataset`, `None` if
+/// it has no index. Fragments with larger ids are searched without an index.
+async fn max_indexed_fragment_id(dataset: &Dataset) -> Result<Option<u64>> {
+    let mut max_id = None;
+    for index in dataset.load_indices().await? {
+        let indexed = dataset.checkout_version(index.dataset_version).await?;
+        max_id = max_id.max(max_fragment_id(&indexed));
+    }
+    Ok(max_id)
+}
+
+/// Write `batches` as the fragment `id` of the dataset at `base` of `store`.
+///
+/// The lance file writer is not `Send`, so it runs on a blocking thread,
+/// keeping the maintenance task spawnable on a multi-threaded runtime.
+async fn write_fragment(
+    store: ObjectStore,
+    base: Path,
+    id: u64,
+    schema: Schema,
+    batches: Vec<RecordBatch>,
+) -> Result<Fragment> {
+    let runtime = Handle::current();
+    tokio::task::spawn_blocking(move || {
+        let params = WriteParams::default();
+        let fragment = write::write_fragment(&store, &base, id, &schema, batches, &params);
+        runtime.block_on(fragment).map_err(Box::new)
+    })
+    .await
+    .map_err(|e| Error::Runtime {
+        message: format!("writing a compacted fragment failed: {e}"),
+    })?
+    .map_err(|e| Error::from(*e))
+}
+
+/// Write the rows of `group`, fragments of `dataset`, as the fragment `id`.
+async fn write_merged(
+    store: &ObjectStore,
+    base: &Path,
+    dataset: &Dataset,
+    group: &[FileFragment],
+    id: u64,
+) -> Result<Fragment> {
+    let mut batches = vec![];
+    for fragment in group {
+        let stream = fragment.scan().try_into_stream().await?;
+        batches.extend(stream.try_collect::<Vec<_>>().await?);
+    }
+    let schema = dataset.schema().clone();
+    write_fragment(store.clone(), base.clone(), id, schema, batches).await
+}
+
+/// A group of fragments compaction merges.
+struct Merge {
+    /// The merged fragments, as they were read.
+    sources: Vec<Fragment>,
+    /// The fragment of their rows, `None` if all of them were deleted.
+    written: Option<Fragment>,
+}
+
+/// The groups of consecutive fragments of `dataset` compaction merges, with
+/// their number of rows.
+async fn compaction_groups(dataset: &Dataset) -> Result<Vec<(Vec<FileFragment>, usize)>> {
+    let max_indexed = max_indexed_fragment_id(dataset).await?;
+    let max_rows = WriteParams::default().max_rows_per_file;
+    let arrow_schema = arrow_schema::Schema::from(dataset.schema());
+    // Rewritten files get the field ids of a new schema, so tables whose
+    // columns were added or removed afterwards are not compacted.
+    let plain_schema = &Schema::try_from(&arrow_schema)? == dataset.schema();
+
+    let mut groups: Vec<(Vec<FileFragment>, usize)> = vec![];
+    let mut open_group = false;
+    for fragment in dataset.get_fragments() {
+        let metadata = fragment.metadata();
+        let eligible = plain_schema
+            && metadata.files.len() == 1
+            && max_indexed.is_none_or(|max| metadata.id > max);
+        let rows = fragment.count_rows().await?;
+        if !eligible || rows >= max_rows {
+            open_group = false;
+            continue;
+        }
+        match groups.last_mut() {
+            Some((group, group_rows)) if open_group && *group_rows + rows <= max_rows => {
+                group.push(fragment);
+                *group_rows += rows;
+            }
+            _ => groups.push((vec![fragment], rows)),
+        }
+        open_group = true;
+    }
+    groups.retain(|(group, _)| group.len() > 1 || group[0].metadata().deletion_file.is_some());
+    Ok(groups)
+}
+
+/// Delete the data files of `fragments`, which no version uses, logging the
+/// failures.
+///
+/// The fragments are collected by the callers, whose iterator adapters would
+/// otherwise be held across the deletes and keep the maintenance task from
+/// being spawned.
+async fn delete_data_files(store: &ObjectStore, base: &Path, fragments: Vec<Fragment>) {
+    for file in fragments.iter().flat_map(|f| &f.files) {
+        let path = base.child(write::DATA_DIR).child(file.path.as_str());
+        match store.inner.delete(&path).await {
+            Ok(()) | Err(object_store::Error::NotFound { .. }) => {}
+            Err(e) => warn!("deleting the unused data file {path} failed: {e}"),
+        }
+    }
+}
+
+fn unix_millis(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or_default()
+}
+
+impl NativeTable {
+    /// Run the maintenance actions of `config` once.
+    ///
+    /// The actions commit through the table's commit lock, like every write,
+    /// so they wait for concurrent writers instead of conflicting with them.
+    pub async fn optimize(&self, config: &MaintenanceConfig) -> Result<OptimizationStats> {
+        let span = info_span!("optimize", table = %self.name, elapsed_ms = field::Empty);
+        timed(span, async {
+            let mut stats = OptimizationStats::default();
+            if let Some(retention) = config.retention.as_ref() {
+                let deleted = self
+                    .apply_retention(&retention.column, retention.older_than, retention.batch)
+                    .await?;
+                stats.rows_expired = deleted.iter().sum();
+            }
+            if config.compact {
+                (stats.fragments_removed, stats.fragments_added) = self.compact_files().await?;
+            }
+            if config.optimize_indices {
+                stats.indices_optimized = self.optimize_indices().await?;
+            }
+            if let Some(older_than) = config.expire_checkpoints_older_than {
+                stats.checkpoints_expired = self.expire_checkpoints(older_than).await?;
+            }
+            if let Some(older_than) = config.prune_older_than {
+                (stats.versions_pruned, stats.files_removed) =
+                    self.prune_versions(older_than).await?;
+            }
+            Ok(stats)
+        })
+        .await
+    }
+
+    /// Run [Self::optimize] with `config` every [MaintenanceConfig::interval] on
+    /// a background task of the current tokio runtime, until the returned
+    /// handle is dropped.
+    ///
+    /// A failed run is logged and recorded on the handle, later runs still happen.
+    pub fn start_maintenance(&self, config: MaintenanceConfig) -> MaintenanceHandle {
+        let (stop, mut stopped) = watch::channel(false);
+        let state = Arc::new(Mutex::new(MaintenanceState::default()));
+        let table = self.clone();
+        let task_state = state.clone();
+        let task = tokio::spawn(async move {
+            loop {
+                if tokio::time::timeout(config.interval, stopped.changed())
+                    .await
+                    .is_ok()
+                {
+                    return;
+                }
+                let result = table.optimize(&config).await;
+                let mut state = task_state.lock().unwrap();
+                state.runs += 1;
+                match result {
+                    Ok(stats) => {
+                        state.last_stats = Some(stats);
+                        state.last_error = None;
+                    }
+                    Err(e) => {
+                        warn!(table = %table.name, "maintenance failed: {e}");
+                        state.last_error = Some(e.to_string());
+                    }
+                }
+            }
+        });
+        MaintenanceHandle {
+            stop,
+            task: Some(task),
+            state,
+        }
+    }
+
+    /// Merge the consecutive fragments no index covers, up to the lance default
+    /// number of rows per file, returning the number of fragments removed and
+    /// added. Fragments covered by an index keep their rows, whose ids the
+    /// index refers to, and tables with stable row ids are not compacted.
+    ///
+    /// The merged fragments are written once, before taking the commit lock.
+    /// A group whose fragments were changed by a concurrent writer in the
+    /// meantime is not committed, and the files no commit uses are deleted.
+    async fn compact_files(&self) -> Result<(usize, usize)> {
+        if self.properties().await?.stable_row_ids {
+            return Ok((0, 0));
+        }
+        let planned = self.dataset.reload().await?;
+        let groups = compaction_groups(&planned).await?;
+        if groups.is_empty() {
+            return Ok((0, 0));
+        }
+
+        let (store, base) = self.dataset.object_store().await?;
+        let mut merges: Vec<Merge> = Vec::with_capacity(groups.len());
+        let mut next_id = max_fragment_id(&planned).unwrap_or_default() + 1;
+        for (group, rows) in groups {
+            let sources = group.iter().map(|f| f.metadata().clone()).collect();
+            let mut written = None;
+            if rows > 0 {
+                match write_merged(&store, &base, &planned, &group, next_id).await {
+                    Ok(fragment) => written = Some(fragment),
+                    Err(e) => {
+                        let files = merges.iter().filter_map(|m| m.written.clone()).collect();
+                        delete_data_files(&store, &base, files).await;
+                        return Err(e);
+                    }
+                }
+                next_id += 1;
+            }
+            merges.push(Merge { sources, written });
+        }
+
+        let mut committed = vec![false; merges.len()];
+        let committed_ref = &mut committed;
+        let merges_ref = &merges;
+        let commit = self
+            .dataset
+            .write(self.max_commit_retries, |latest| async move {
+                let max_indexed = max_indexed_fragment_id(&latest).await?;
+                let mut fragments = fragments(&latest);
+                let mut next_id = max_fragment_id(&latest).unwrap_or_default() + 1;
+                for (merge, committed) in merges_ref.iter().zip(committed_ref.iter_mut()) {
+                    let unchanged = merge.sources.iter().all(|source| {
+                        max_indexed.is_none_or(|max| source.id > max) && fragments.contains(source)
+                    });
+                    if !unchanged {
+                        continue;
+                    }
+                    let position = fragments
+                        .iter()
+                        .position(|f| merge.sources.contains(f))
+                        .unwrap();
+                    fragments.retain(|f| !merge.sources.contains(f));
+                    if let Some(written) = &merge.written {
+                        // The data files do not refer to their fragment, so it
+                        // gets an id after those appended in the meantime.
+                        let mut written = written.clone();
+                        written.id = next_id;
+                        next_id += 1;
+                        fragments.insert(position, written);
+                    }
+                    *committed = true;
+                }
+                if !committed_ref.iter().any(|c| *c) {
+                    return Ok(latest.as_ref().clone());
+                }
+                let read_params = self.dataset.read_params();
+                let schema = latest.schema();
+                Ok(write::commit_fragments(
+                    &self.uri,
+                    &read_params,
+                    schema,
+                    &fragments,
+                    WriteMode::Append,
+                )
+                .await?)
+            })
+            .await;
+
+        if let Err(e) = commit {
+            // The manifest may have been written before the failure, so the
+            // files the latest version uses are kept.
+            if let Ok(latest) = self.dataset.reload().await {
+                let used = fragments(&latest)
+                    .into_iter()
+                    .flat_map(|f| f.files)
+                    .map(|f| f.path)
+                    .collect::<HashSet<_>>();
+                let unused = merges
+                    .iter()
+                    .filter_map(|m| m.written.clone())
+                    .filter(|f| f.files.iter().all(|d| !used.contains(&d.path)))
+                    .collect();
+                delete_data_files(&store, &base, unused).await;
+            }
+            return Err(e);
+        }
+        let mut counts = (0, 0);
+        for (merge, committed) in merges.iter().zip(&committed) {
+            if *committed {
+                counts.0 += merge.sources.len();
+                counts.1 += usize::from(merge.written.is_some());
+            }
+        }
+        let uncommitted = merges
+            .iter()
+            .zip(&committed)
+            .filter(|(_, committed)| !**committed)
+            .filter_map(|(m, _)| m.written.clone())
+            .collect();
+        delete_data_files(&store, &base, uncommitted).await;
+        Ok(counts)
+    }
+
+    /// Rebuild the indices that do not cover the fragments appended after they
+    /// were created, returning how many were rebuilt. Indices whose parameters
+    /// were not recorded by [Self::create_index] are left as they are.
+    async fn optimize_indices(&self) -> Result<usize> {
+        let configs = self.index_configs().await?;
+        let dataset = self.dataset.get().await?;
+        let max_id = max_fragment_id(&dataset);
+        let mut rebuilt = 0;
+        for index in dataset.load_indices().await? {
+            let indexed = dataset.checkout_version(index.dataset_version).await?;
+            if max_fragment_id(&indexed) >= max_id {
+                continue;
+            }
+            let Some(config) = configs.iter().find(|c| c.name == index.name) else {
+                continue;
+            };
+            self.create_index(&config.builder()?).await?;
+            rebuilt += 1;
+        }
+        Ok(rebuilt)
+    }
+
+    /// Remove the versions older than `older_than`, except the latest one and
+    /// those indices were built on or checkpoints were made at, and the data
+    /// and deletion files only they use. Returns the number of versions and
+    /// files removed.
+    ///
+    /// The versions are pruned under the commit lock, so that the files of a
+    /// version being committed are not taken for unused ones. Readers still
+    /// using a removed version fail once its files are gone.
+    async fn prune_versions(&self, older_than: Duration) -> Result<(usize, usize)> {
+        let mut counts = (0, 0);
+        let counts_ref = &mut counts;
+        self.dataset
+            .write(self.max_commit_retries, |latest| async move {
+                *counts_ref = self.prune_versions_of(&latest, older_than).await?;
+                Ok(latest.as_ref().clone())
+            })
+            .await?;
+        Ok(counts)
+    }
+
+    /// Prune the versions of `latest`, see [Self::prune_versions].
+    async fn prune_versions_of(
+        &self,
+        latest: &Dataset,
+        older_than: Duration,
+    ) -> Result<(usize, usize)> {
+        let cutoff = unix_millis(SystemTime::now()) - older_than.as_millis() as i64;
+        let index_versions = latest
+            .load_indices()
+            .await?
+            .iter()
+            .map(|i| i.dataset_version)
+            .collect::<HashSet<_>>();
+        let checkpoint_versions = self.checkpoint_versions().await?;
+        let (removed, kept): (Vec<_>, Vec<_>) =
+            latest.versions().await?.into_iter().partition(|v| {
+                v.version != latest.version().version
+                    && v.timestamp.timestamp_millis() < cutoff
+                    && !index_versions.contains(&v.version)
+                    && !checkpoint_versions.contains(&v.version)
+            });
+        if removed.is_empty() {
+            return Ok((0, 0));
+        }
+
+        let (store, base) = self.dataset.object_store().await?;
+        // The data and deletion files of the fragments of the versions.
+        let files = |dataset: &Dataset| {
+            let mut files = HashSet::new();
+            for fragment in fragments(dataset) {
+                let data = fragment.files.iter();
+                files.extend(data.map(|f| base.child(write::DATA_DIR).child(f.path.as_str())));
+                if let Some(deletions) = &fragment.deletion_file {
+                    files.insert(deletion_file_path(&base, fragment.id, deletions));
+                }
+            }
+            files
+        };
+        let mut kept_files = HashSet::new();
+        for version in &kept {
+            kept_files.extend(files(&latest.checkout_version(version.version).await?));
+        }
+        let mut removed_files = HashSet::new();
+        for version in &removed {
+            let dataset = latest.checkout_version(version.version).await?;
+            removed_files.extend(
+                files(&dataset)
+                    .into_iter()
+                    .filter(|f| !kept_files.contains(f)),
+            );
+        }
+
+        // The manifests go first, so that no version is left without its files.
+        for version in &removed {
+            let path = base
+                .child(VERSIONS_DIR)
+                .child(format!("{}.manifest", version.version));
+            store.inner.delete(&path).await?;
+        }
+        for file in &removed_files {
+            match store.inner.delete(file).await {
+                Ok(()) | Err(object_store::Error::NotFound { .. }) => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok((removed.len(), removed_files.len()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_array::{Int32Array, RecordBatchReader};
+    use lance::index::vector::ivf::IvfBuildParams;
+    use lance::index::vector::pq::PQBuildParams;
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::arrow::{IntoArrow, RecordBatchBuilder};
+    use crate::index::vector::IvfPQIndexBuilder;
+
+    fn batches(values: std::ops::Range<i32>) -> Box<dyn RecordBatchReader> {
+        RecordBatchBuilder::new()
+            .column("i", Arc::new(Int32Array::from_iter(values.clone())))
+            .vector_column("vector", 2, values.map(|i| vec![i as f32, 1.0]))
+            .build()
+            .unwrap()
+            .into_arrow(None)
+            .unwrap()
+    }
+
+    async fn values(table: &NativeTable) -> Vec<i32> {
+        let dataset = table.dataset.get().await.unwrap();
+        let batches = dataset
+            .scan()
+            .try_into_stream()
+            .await
+            .unwrap()
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        batches
+            .iter()
+            .flat_map(|b| {
+                let column = b.column(0).as_any().downcast_ref::<Int32Array>().unwrap();
+                column.values().to_vec()
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_optimize() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        let table = NativeTable::create(uri, "test", batches(0..256), None)
+            .await
+            .unwrap();
+        let builder = IvfPQIndexBuilder::new()
+            .ivf_params(IvfBuildParams::new(2))
+            .pq_params(PQBuildParams {
+                num_sub_vectors: 1,
+                ..PQBuildParams::default()
+            });
+        table.create_index(&builder).await.unwrap();
+        for start in (256..296).step_by(10) {
+            table.add(batches(start..start + 10), None).await.unwrap();
+        }
+        table.delete("i = 260").await.unwrap();
+
+        let config = MaintenanceConfig {
+            optimize_indices: false,
+            ..Default::default()
+        };
+        let stats = table.optimize(&config).await.unwrap();
+        // The four appended fragments are merged, the indexed one is kept.
+        assert_eq!(stats.fragments_removed, 4);
+        assert_eq!(stats.fragments_added, 1);
+        let dataset = table.dataset.get().await.unwrap();
+        assert_eq!(dataset.get_fragments().len(), 2);
+        let expected = (0..296).filter(|i| *i != 260).collect::<Vec<_>>();
+        assert_eq!(values(&table).await, expected);
+        let results = table
+            .search(vec![290.0, 1.0])
+            .use_index(true)
+            .limit(1)
+            .execute()
+            .await
+            .unwrap()
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        assert_eq!(results[0].num_rows(), 1);
+
+        let config = MaintenanceConfig {
+            compact: false,
+            prune_older_than: Some(Duration::ZERO),
+            ..Default::default()
+        };
+        let stats = table.optimize(&config).await.unwrap();
+        assert_eq!(stats.indices_optimized, 1);
+        // All versions but the latest one, which the rebuilt index was built on.
+        let dataset = table.dataset.reload().await.unwrap();
+        assert_eq!(dataset.versions().await.unwrap().len(), 1);
+        assert!(stats.versions_pruned > 0);
+        assert!(stats.files_removed > 0);
+        // The deleted row was compacted away, no version uses its deletion file.
+        let deletions = std::path::Path::new(table.uri()).join("_deletions");
+        assert_eq!(std::fs::read_dir(deletions).unwrap().count(), 0);
+        assert_eq!(values(&table).await, expected);
+        let reopened = NativeTable::open(uri, "test").await.unwrap();
+        assert_eq!(reopened.count_rows().await.unwrap(), 295);
+    }
+
+    #[tokio::test]
+    async fn test_compact_files_failed_commit() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        let table = NativeTable::create(uri, "test", batches(0..10), None)
+            .await
+            .unwrap();
+        for start in (10..40).step_by(10) {
+            table.add(batches(start..start + 10), None).await.unwrap();
+        }
+        let data_files = || {
+            std::fs::read_dir(std::path::Path::new(table.uri()).join("data"))
+                .unwrap()
+                .count()
+        };
+        assert_eq!(data_files(), 4);
+
+        // The merged fragment is written, but a checked out table cannot commit it.
+        let checked_out = table.checkout(table.version()).await.unwrap();
+        assert!(matches!(
+            checked_out.compact_files().await,
+            Err(Error::InvalidInput { .. })
+        ));
+        assert_eq!(data_files(), 4);
+
+        assert_eq!(table.compact_files().await.unwrap(), (4, 1));
+        assert_eq!(data_files(), 5);
+        assert_eq!(values(&table).await, (0..40).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn test_start_maintenance() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        let table = NativeTable::create(uri, "test", batches(0..10), None)
+            .await
+            .unwrap();
+        for start in (10..40).step_by(10) {
+            table.add(batches(start..start + 10), None).await.unwrap();
+        }
+
+        let handle = table.start_maintenance(MaintenanceConfig {
+            interval: Duration::from_millis(20),
+            ..Default::default()
+        });
+        while handle.runs() == 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(handle.last_error(), None);
+        assert!(handle.last_stats().is_some());
+        let dataset = table.dataset.get().await.unwrap();
+        assert_eq!(dataset.get_fragments().len(), 1);
+        assert_eq!(values(&table).await, (0..40).collect::<Vec<_>>());
+
+        let state = handle.state.clone();
+        handle.stop().await;
+        let runs = state.lock().unwrap().runs;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(state.lock().unwrap().runs, runs);
+
+        // Dropping the handle stops the task too.
+        let handle = table.start_maintenance(MaintenanceConfig {
+            interval: Duration::from_millis(20),
+            ..Default::default()
+        });
+        let state = handle.state.clone();
+        drop(handle);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(state.lock().unwrap().runs, 0);
+    }
+}
@@ -0,0 +1,197 @@
+// Copyright 2023 LanceDB Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The left joins of [NativeTable::merge].
+//!
+//! lance gathers the new columns of the rows with arrow, which fails on null
+//! vectors, and fills the rows without a match with them. The merged data is
+//! given a row for each key of the table it does not have, and its null vectors
+//! are replaced by vectors of zeros marked in the columns named by
+//! [null_vector_column](crate::table::null_vector_column), as writes allowing
+//! null vectors store them.
+
+use std::collections::HashSet;
+
+use arrow_array::{new_null_array, Array, RecordBatch, RecordBatchReader};
+use arrow_schema::SchemaRef;
+use datafusion::arrow::compute::{cast, filter_record_batch, is_not_null};
+use datafusion::scalar::ScalarValue;
+use futures::TryStreamExt;
+use lance::arrow::RecordBatchBuffer;
+use lance::dataset::Dataset;
+
+use super::keys::MAX_DUPLICATE_EXAMPLES;
+use super::NativeTable;
+use crate::error::{Error, Result};
+use crate::io::bad_vectors::{with_null_vector_fields, zero_null_vector_columns};
+
+/// The keys of `column` in `batches`, failing with an [Error::DuplicateKeys] if
+/// a key is in several rows. Null keys match no row and are left out.
+fn unique_keys(batches: &[RecordBatch], column: &str) -> Result<HashSet<ScalarValue>> {
+    let mut keys = HashSet::new();
+    let mut duplicated = HashSet::new();
+    let mut examples = Vec::new();
+    for batch in batches {
+        let values = batch[column].as_ref();
+        for i in (0..values.len()).filter(|i| !values.is_null(*i)) {
+            let key = ScalarValue::try_from_array(values, i).map_err(lance::Error::from)?;
+            if !keys.insert(key.clone())
+                && duplicated.insert(key.clone())
+                && examples.len() < MAX_DUPLICATE_EXAMPLES
+            {
+                examples.push(key.to_string());
+            }
+        }
+    }
+    if !duplicated.is_empty() {
+        return Err(Error::DuplicateKeys {
+            column: column.to_string(),
+            count: duplicated.len(),
+            examples,
+        });
+    }
+    Ok(keys)
+}
+
+/// `batches` without the rows of null keys in `right_on`, with a row of nulls
+/// for each key of `left_on` in `dataset` they do not have, and with marked
+/// vectors of zeros for null vectors.
+async fn pad_merged(
+    dataset: &Dataset,
+    schema: &SchemaRef,
+    batches: &[RecordBatch],
+    keys: &HashSet<ScalarValue>,
+    left_on: &str,
+    right_on: &str,
+) -> Result<Vec<RecordBatch>> {
+    let key_type = schema.field_with_name(right_on).unwrap().data_type();
+    let mut scanner = dataset.scan();
+    scanner.project(&[left_on])?;
+    let mut stream = scanner.try_into_stream().await?;
+    let mut missing = HashSet::new();
+    let mut null_keys = false;
+    while let Some(batch) = stream.try_next().await? {
+        let values = cast(batch.column(0), key_type).map_err(|e| Error::InvalidInput {
+            message: format!(
+                "the key '{left_on}' of the table does not fit the key '{right_on}': {e}"
+            ),
+        })?;
+        for i in 0..values.len() {
+            if values.is_null(i) {
+                null_keys = true;
+                continue;
+            }
+            let key = ScalarValue::try_from_array(&values, i).map_err(lance::Error::from)?;
+            if !keys.contains(&key) {
+                missing.insert(key);
+            }
+        }
+    }
+
+    let mut padded = Vec::with_capacity(batches.len() + 1);
+    for batch in batches {
+        let keys = batch[right_on].as_ref();
+        if keys.null_count() == 0 {
+            padded.push(batch.clone());
+            continue;
+        }
+        let valid = is_not_null(keys).map_err(lance::Error::from)?;
+        padded.push(filter_record_batch(batch, &valid).map_err(lance::Error::from)?);
+    }
+    let rows = missing.len() + usize::from(null_keys);
+    if rows > 0 {
+        let mut key_values = missing.into_iter().collect::<Vec<_>>();
+        if null_keys {
+            key_values.push(ScalarValue::try_from(key_type).map_err(lance::Error::from)?);
+        }
+        let mut columns = Vec::with_capacity(schema.fields().len());
+        for field in schema.fields() {
+            columns.push(if field.name() == right_on {
+                ScalarValue::iter_to_array(key_values.clone()).map_err(lance::Error::from)?
+            } else {
+                new_null_array(field.data_type(), rows)
+            });
+        }
+        padded.push(RecordBatch::try_new(schema.clone(), columns).map_err(lance::Error::from)?);
+    }
+    if padded.is_empty() {
+        // Neither the table nor the merged data have rows, lance fails a join
+        // without batches.
+        padded.push(RecordBatch::new_empty(schema.clone()));
+    }
+    let schema = with_null_vector_fields(schema);
+    padded
+        .into_iter()
+        .map(|batch| Ok(zero_null_vector_columns(batch, &schema).map_err(lance::Error::from)?))
+        .collect()
+}
+
+impl NativeTable {
+    /// Add the columns of `batches` to this table, by a left join of the rows
+    /// of the table on `left_on` with the rows of `batches` on `right_on`.
+    ///
+    /// The rows of the table without a match, and of a null key, have nulls in
+    /// the new columns, and the columns the table has are not rewritten.
+    /// `batches` are read into memory. Fails with an [Error::InvalidInput] if
+    /// a column of `batches` other than `right_on` is in the table, and with
+    /// an [Error::DuplicateKeys] if a key of `right_on` is in several rows.
+    pub async fn merge(
+        &self,
+        batches: Box<dyn RecordBatchReader>,
+        left_on: &str,
+        right_on: &str,
+    ) -> Result<()> {
+        let schema = batches.schema();
+        if schema.field_with_name(right_on).is_err() {
+            return Err(Error::InvalidInput {
+                message: format!("the merged data has no column '{right_on}'"),
+            });
+        }
+        let batches = batches
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .map_err(lance::Error::from)?;
+        let keys = unique_keys(&batches, right_on)?;
+        let (schema, batches, keys) = (&schema, &batches, &keys);
+        self.dataset
+            .write(self.max_commit_retries, |current| async move {
+                if current.schema().field(left_on).is_none() {
+                    return Err(Error::InvalidInput {
+                        message: format!("the table has no column '{left_on}'"),
+                    });
+                }
+                let existing = schema
+                    .fields()
+                    .iter()
+                    .filter(|f| f.name() != right_on && current.schema().field(f.name()).is_some())
+                    .map(|f| format!("'{}'", f.name()))
+                    .collect::<Vec<_>>();
+                if !existing.is_empty() {
+                    return Err(Error::InvalidInput {
+                        message: format!(
+                            "the merged columns {} are already in the table",
+                            existing.join(", ")
+                        ),
+                    });
+                }
+                let padded = pad_merged(&current, schema, batches, keys, left_on, right_on).await?;
+                let mut dataset = current.as_ref().clone();
+                let mut reader: Box<dyn RecordBatchReader> =
+                    Box::new(RecordBatchBuffer::new(padded));
+                dataset.merge(&mut reader, left_on, right_on).await?;
+                Ok(dataset)
+            })
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,208 @@
+// Copyright 2023 LanceDB Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The schema- and field-level metadata of a table, see [NativeTable::schema].
+//!
+//! lance 0.5 does not persist the metadata of the schemas it writes, so the
+//! table records it with its [TableProperties]: the metadata of the schema a
+//! table is created or overwritten with, and of the columns added to it by
+//! schema evolution. The metadata of the top-level fields is recorded by
+//! field name, and follows [NativeTable::rename_column].
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use arrow_schema::{Field, Schema as ArrowSchema};
+
+use super::{NativeTable, TableProperties};
+use crate::error::Result;
+
+impl TableProperties {
+    /// Record the metadata of `schema`, the schema of the rows of a new or
+    /// overwritten table, replacing the metadata recorded so far.
+    pub(crate) fn set_metadata(&mut self, schema: &ArrowSchema) {
+        self.schema_metadata = schema.metadata().clone();
+        self.field_metadata.clear();
+        self.add_field_metadata(schema.fields().iter().map(|f| f.as_ref()));
+    }
+
+    /// Record the metadata of `fields`, columns added to the table.
+    pub(crate) fn add_field_metadata<'a>(&mut self, fields: impl IntoIterator<Item = &'a Field>) {
+        for field in fields {
+            if !field.metadata().is_empty() {
+                self.field_metadata
+                    .insert(field.name().clone(), field.metadata().clone());
+            }
+        }
+    }
+
+    /// `schema`, read from lance, with the metadata recorded for it.
+    pub(crate) fn with_metadata(&self, schema: ArrowSchema) -> ArrowSchema {
+        let fields = schema
+            .fields()
+            .iter()
+            .map(|f| match self.field_metadata.get(f.name()) {
+                Some(metadata) => Arc::new(f.as_ref().clone().with_metadata(metadata.clone())),
+                None => f.clone(),
+            })
+            .collect::<Vec<_>>();
+        ArrowSchema::new_with_metadata(fields, self.schema_metadata.clone())
+    }
+}
+
+impl NativeTable {
+    /// Record the metadata of `schema`, the schema the rows of this table
+    /// were created or overwritten with.
+    pub(crate) async fn record_metadata(&self, schema: &ArrowSchema) -> Result<()> {
+        let recorded = self.properties().await?;
+        let mut properties = recorded.clone();
+        properties.set_metadata(schema);
+        if properties != recorded {
+            self.set_properties(properties).await?;
+        }
+        Ok(())
+    }
+
+    /// Record the metadata of `fields`, the columns added to this table.
+    pub(crate) async fn record_field_metadata(&self, fields: &[Field]) -> Result<()> {
+        if fields.iter().all(|f| f.metadata().is_empty()) {
+            return Ok(());
+        }
+        let mut properties = self.properties().await?;
+        properties.add_field_metadata(fields);
+        self.set_properties(properties).await
+    }
+
+    /// Replace the schema-level metadata of this table, returned by
+    /// [NativeTable::schema], with `metadata`.
+    ///
+    /// Only the settings of the table are written: the data and the version of
+    /// the table are left as they are. The metadata of the fields is kept.
+    pub async fn replace_schema_metadata(&self, metadata: HashMap<String, String>) -> Result<()> {
+        let mut properties = self.properties().await?;
+        properties.schema_metadata = metadata;
+        self.set_properties(properties).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow_array::{ArrayRef, Int32Array, RecordBatch, RecordBatchReader, StringArray};
+    use arrow_schema::DataType;
+    use lance::arrow::RecordBatchBuffer;
+    use lance::dataset::WriteMode;
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::table::{OpenTableParams, WriteOptions};
+
+    fn metadata(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    /// Rows of an `id` column, and of a `tag` column with `tag`.
+    fn rows(schema_metadata: &[(&str, &str)], tag: bool) -> Box<dyn RecordBatchReader> {
+        let id =
+            Field::new("id", DataType::Int32, false).with_metadata(metadata(&[("unit", "count")]));
+        let mut fields = vec![id];
+        let mut columns: Vec<ArrayRef> = vec![Arc::new(Int32Array::from(vec![1, 2]))];
+        if tag {
+            let tag = Field::new("tag", DataType::Utf8, true)
+                .with_metadata(metadata(&[("encoding", "label")]));
+            fields.push(tag);
+            columns.push(Arc::new(StringArray::from(vec!["a", "b"])));
+        }
+        let schema = ArrowSchema::new_with_metadata(fields, metadata(schema_metadata));
+        let batch = RecordBatch::try_new(Arc::new(schema), columns).unwrap();
+        Box::new(RecordBatchBuffer::new(vec![batch]))
+    }
+
+    #[tokio::test]
+    async fn test_schema_metadata() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        let created = metadata(&[("owner", "search"), ("model", "v1")]);
+        NativeTable::create(
+            uri,
+            "test",
+            rows(&[("owner", "search"), ("model", "v1")], false),
+            None,
+        )
+        .await
+        .unwrap();
+
+        let params = OpenTableParams {
+            write_options: Some(WriteOptions::default().schema_evolution(true)),
+            ..Default::default()
+        };
+        let table = NativeTable::open_with_params(uri, "test", params)
+            .await
+            .unwrap();
+        let schema = table.schema().await.unwrap();
+        assert_eq!(schema.metadata(), &created);
+        assert_eq!(
+            schema.field_with_name("id").unwrap().metadata(),
+            &metadata(&[("unit", "count")])
+        );
+
+        // An append keeps the metadata, and records that of its new columns.
+        table
+            .add(rows(&[("owner", "other")], true), None)
+            .await
+            .unwrap();
+        let table = NativeTable::open(uri, "test").await.unwrap();
+        let schema = table.schema().await.unwrap();
+        assert_eq!(schema.metadata(), &created);
+        assert_eq!(
+            schema.field_with_name("id").unwrap().metadata(),
+            &metadata(&[("unit", "count")])
+        );
+        assert_eq!(
+            schema.field_with_name("tag").unwrap().metadata(),
+            &metadata(&[("encoding", "label")])
+        );
+
+        // The schema metadata is replaced without a new version.
+        let version = table.version();
+        let replaced = metadata(&[("model", "v2")]);
+        table
+            .replace_schema_metadata(replaced.clone())
+            .await
+            .unwrap();
+        let table = NativeTable::open(uri, "test").await.unwrap();
+        assert_eq!(table.version(), version);
+        let schema = table.schema().await.unwrap();
+        assert_eq!(schema.metadata(), &replaced);
+        assert_eq!(
+            schema.field_with_name("tag").unwrap().metadata(),
+            &metadata(&[("encoding", "label")])
+        );
+
+        // An overwrite replaces the metadata with that of its rows.
+        table
+            .add(
+                rows(&[("owner", "batch")], false),
+                Some(WriteMode::Overwrite),
+            )
+            .await
+            .unwrap();
+        let table = NativeTable::open(uri, "test").await.unwrap();
+        let schema = table.schema().await.unwrap();
+        assert_eq!(schema.metadata(), &metadata(&[("owner", "batch")]));
+        assert!(schema.field_with_name("tag").is_err());
+    }
+}
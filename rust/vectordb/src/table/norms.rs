@@ -0,0 +1,129 @@
+// Copyright 2023 LanceDB Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The stored L2 norms of the vectors of a column, see
+//! [NativeTable::store_vector_norms].
+//!
+//! The cosine distance of a row divides the dot product of its vector with
+//! the query vector by the norms of both, so the flat searches and the refine
+//! stage of the index searches of a column with stored norms read the norm
+//! of each row instead of computing it from its vector, which is searched as
+//! it was written, unnormalized.
+
+use std::sync::Arc;
+
+use arrow_array::{ArrayRef, FixedSizeListArray, RecordBatch};
+use arrow_schema::{DataType, Field, Schema as ArrowSchema};
+use lance::dataset::Dataset;
+
+use super::{DatasetRef, NativeTable};
+use crate::error::{Error, Result};
+use crate::io::norms::vector_norms;
+
+/// The column of the stored norms of the vectors of `column`.
+pub fn norm_column(column: &str) -> String {
+    format!("_{column}_norm")
+}
+
+impl DatasetRef {
+    /// The column of the stored norms of the vectors of `column` in `dataset`,
+    /// `None` if they are not stored.
+    pub(crate) async fn stored_norms(
+        &self,
+        dataset: &Dataset,
+        column: &str,
+    ) -> Result<Option<String>> {
+        let properties = self.properties().await?;
+        let norms = properties.norm_columns.get(column);
+        Ok(norms
+            .filter(|norms| dataset.schema().field(norms).is_some())
+            .cloned())
+    }
+}
+
+impl NativeTable {
+    /// Store the L2 norms of the vectors of the column `column` in the column
+    /// [norm_column] of the table, so that the searches of the column by
+    /// [lance::index::vector::MetricType::Cosine] divide by them, without
+    /// normalizing the stored vectors.
+    ///
+    /// The norms are computed from the rows of the table when they are
+    /// stored, and from the vectors of each write after that. The data files
+    /// and indices of the other columns are kept, and the existing indices of
+    /// `column` are still used: the norms change the exact distances of the
+    /// flat searches and of the refine stage of [crate::Query::refine_factor].
+    /// Fails with an [Error::InvalidInput] if `column` is not a `Float32`
+    /// vector column. Storing them again does nothing.
+    pub async fn store_vector_norms(&self, column: &str) -> Result<()> {
+        let invalid = |message: String| Error::InvalidInput { message };
+        let norms = norm_column(column);
+        let norms = norms.as_str();
+        self.dataset
+            .write(self.max_commit_retries, |latest| async move {
+                let schema = ArrowSchema::from(latest.schema());
+                match schema.field_with_name(column).map(|f| f.data_type()) {
+                    Ok(DataType::FixedSizeList(item, _))
+                        if item.data_type() == &DataType::Float32 => {}
+                    Ok(_) => {
+                        return Err(invalid(format!(
+                            "the column '{column}' is not a float32 vector column"
+                        )))
+                    }
+                    Err(_) => return Err(invalid(format!("the table has no column '{column}'"))),
+                }
+                let mut properties = self.properties().await?;
+                if schema.field_with_name(norms).is_ok() {
+                    if properties.norm_columns.get(column).map(String::as_str) == Some(norms) {
+                        return Ok(latest.as_ref().clone());
+                    }
+                    return Err(invalid(format!("the table already has a column '{norms}'")));
+                }
+                let field = Field::new(norms, DataType::Float32, true);
+                let columns = Arc::new(ArrowSchema::new(vec![field]));
+
+                let mut fragments = Vec::new();
+                for fragment in latest.get_fragments() {
+                    let mut updater = fragment.updater(Some(&[column])).await?;
+                    while let Some(batch) = updater.next().await? {
+                        let vectors = batch[column]
+                            .as_any()
+                            .downcast_ref::<FixedSizeListArray>()
+                            .unwrap();
+                        let values: ArrayRef =
+                            Arc::new(vector_norms(vectors).map_err(lance::Error::from)?);
+                        let batch = RecordBatch::try_new(columns.clone(), vec![values])
+                            .map_err(lance::Error::from)?;
+                        updater.update(batch).await?;
+                    }
+                    fragments.push(updater.finish().await?);
+                }
+                let schema = latest.schema().merge(columns.as_ref())?;
+                let indices = latest.load_indices().await?;
+                let stored = self
+                    .dataset
+                    .commit_manifest(&latest, indices, |manifest| {
+                        manifest.schema = schema;
+                        manifest.fragments = Arc::new(fragments);
+                    })
+                    .await?;
+                properties
+                    .norm_columns
+                    .insert(column.to_string(), norms.to_string());
+                self.set_properties(properties).await?;
+                Ok(stored)
+            })
+            .await?;
+        Ok(())
+    }
+}
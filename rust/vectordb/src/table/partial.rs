@@ -0,0 +1,217 @@
+// Copyright 2023 LanceDB Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Vector indices of the rows matching a filter, see
+//! [crate::index::vector::IvfPQIndexBuilder::row_filter].
+//!
+//! lance searches an index without knowing which rows it left out, so a search
+//! uses a partial index only when its own filter leaves out at least the same
+//! rows. The filter of an index is in [ROW_FILTER_FILE], in the directory of
+//! the index, written before the index is committed.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use lance::dataset::Dataset;
+use lance::format::Index;
+use uuid::Uuid;
+
+use super::DatasetRef;
+use crate::error::Result;
+use crate::query::filter::filter_implies;
+
+/// The file of the filter of the rows of a partial index, next to its index file.
+pub(super) const ROW_FILTER_FILE: &str = "row_filter.sql";
+
+/// The row filters of the indices of a table read so far, `None` for the
+/// indices of all rows. The files of an index never change.
+#[derive(Debug, Default)]
+pub(crate) struct RowFilters {
+    filters: Mutex<HashMap<Uuid, Option<Arc<str>>>>,
+}
+
+impl DatasetRef {
+    /// The filter of the rows `index` covers, `None` if it covers all rows.
+    pub(crate) async fn row_filter(&self, index: &Index) -> Result<Option<Arc<str>>> {
+        // The row filters of a bare dataset are not read from its store.
+        if self.is_detached() {
+            return Ok(None);
+        }
+        if let Some(filter) = self.row_filters().filters.lock().unwrap().get(&index.uuid) {
+            return Ok(filter.clone());
+        }
+        let (store, base) = self.object_store().await?;
+        let path = base
+            .child("_indices")
+            .child(index.uuid.to_string())
+            .child(ROW_FILTER_FILE);
+        let filter = match store.inner.get(&path).await {
+            Ok(result) => Some(Arc::from(String::from_utf8_lossy(&result.bytes().await?))),
+            Err(object_store::Error::NotFound { .. }) => None,
+            Err(e) => return Err(e.into()),
+        };
+        let mut filters = self.row_filters().filters.lock().unwrap();
+        filters.insert(index.uuid, filter.clone());
+        Ok(filter)
+    }
+
+    /// Record `filter` as the row filter of the index `uuid`.
+    pub(super) async fn write_row_filter(&self, uuid: &Uuid, filter: &str) -> Result<()> {
+        let (store, base) = self.object_store().await?;
+        let path = base
+            .child("_indices")
+            .child(uuid.to_string())
+            .child(ROW_FILTER_FILE);
+        store.inner.put(&path, filter.to_string().into()).await?;
+        Ok(())
+    }
+
+    /// The name and row filter of the first index of `column` in `dataset`
+    /// that leaves out rows matching `filter`, whose filter `filter` does not
+    /// imply. `None` if the search can use the indices of the column.
+    pub(crate) async fn uncovering_index(
+        &self,
+        dataset: &Dataset,
+        column: &str,
+        filter: Option<&str>,
+    ) -> Result<Option<(String, Arc<str>)>> {
+        let Some(field) = dataset.schema().field(column) else {
+            return Ok(None);
+        };
+        let indices = dataset.load_indices().await?;
+        for index in indices.iter().filter(|i| i.fields.contains(&field.id)) {
+            if let Some(row_filter) = self.row_filter(index).await? {
+                if !filter_implies(filter, &row_filter) {
+                    return Ok(Some((index.name.clone(), row_filter)));
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow_array::{Int32Array, RecordBatch, StringArray};
+    use futures::TryStreamExt;
+    use lance::arrow::RecordBatchBuffer;
+    use lance::index::vector::ivf::IvfBuildParams;
+    use lance::index::vector::pq::PQBuildParams;
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::arrow::RecordBatchBuilder;
+    use crate::error::Error;
+    use crate::index::vector::IvfPQIndexBuilder;
+    use crate::query::Query;
+    use crate::table::NativeTable;
+
+    /// The ids of the rows of `batches`, and whether they are active.
+    fn rows(batches: &[RecordBatch]) -> Vec<(i32, bool)> {
+        batches
+            .iter()
+            .flat_map(|b| {
+                let ids = b["id"].as_any().downcast_ref::<Int32Array>().unwrap();
+                let status = b["status"].as_any().downcast_ref::<StringArray>().unwrap();
+                (0..b.num_rows())
+                    .map(|row| (ids.value(row), status.value(row) == "active"))
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    async fn search(query: Query) -> Result<(Vec<(i32, bool)>, bool)> {
+        let (batches, metrics) = query
+            .select(Some(vec!["id".to_string(), "status".to_string()]))
+            .execute_with_metrics()
+            .await?;
+        Ok((rows(&batches), metrics.index_partitions_probed.is_some()))
+    }
+
+    #[tokio::test]
+    async fn test_partial_index() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        // The 512 active rows are enough to train the PQ codebook.
+        let vectors = (0..4096)
+            .map(|i| {
+                (0..8)
+                    .map(|j| ((i * 7 + j * 13) % 31) as f32)
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        let status = (0..4096).map(|i| if i % 8 == 0 { "active" } else { "inactive" });
+        let batch = RecordBatchBuilder::new()
+            .column("id", Arc::new(Int32Array::from_iter_values(0..4096)))
+            .column("status", Arc::new(StringArray::from_iter_values(status)))
+            .vector_column("vector", 8, vectors)
+            .build()
+            .unwrap();
+        let reader = Box::new(RecordBatchBuffer::new(vec![batch]));
+        let table = NativeTable::create(uri, "test", reader, None)
+            .await
+            .unwrap();
+        let builder = IvfPQIndexBuilder::new()
+            .ivf_params(IvfBuildParams::new(2))
+            .pq_params(PQBuildParams {
+                num_sub_vectors: 2,
+                ..Default::default()
+            })
+            .row_filter("status = 'active'");
+        table.create_index(&builder).await.unwrap();
+        let stats = table.index_stats("vector_idx").await.unwrap();
+        assert_eq!(stats.row_filter.as_deref(), Some("status = 'active'"));
+
+        let vector = vec![3.0; 8];
+        // The index has only active rows, all the results match the filter.
+        let query = table
+            .search(vector.clone())
+            .use_index(true)
+            .filter(Some("status = 'active' AND id >= 0".to_string()));
+        let (results, indexed) = search(query).await.unwrap();
+        assert!(indexed);
+        assert_eq!(results.len(), 10);
+        assert!(results.iter().all(|(_, active)| *active));
+
+        // Other searches are flat, as if the table had no index.
+        for filter in [
+            None,
+            Some("status = 'inactive'"),
+            Some("status = 'active' OR id < 8"),
+        ] {
+            let filter = filter.map(str::to_string);
+            let query = table.search(vector.clone()).filter(filter.clone());
+            let (expected, _) = search(query.clone()).await.unwrap();
+            let (results, indexed) = search(query.clone().use_index(true)).await.unwrap();
+            assert!(!indexed, "{filter:?}");
+            assert_eq!(results, expected, "{filter:?}");
+
+            let query = query.use_index(true).require_covering_index(true);
+            let err = search(query).await.unwrap_err();
+            assert!(matches!(err, Error::InvalidInput { .. }), "{err}");
+        }
+
+        let results = table
+            .search(vector)
+            .use_index(true)
+            .filter(Some("status = 'inactive'".to_string()))
+            .execute()
+            .await
+            .unwrap()
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        assert!(rows(&results).iter().all(|(_, active)| !*active));
+    }
+}
@@ -0,0 +1,639 @@
+// Copyright 2023 LanceDB Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Tables partitioned by the values of a column, see
+//! [crate::database::CreateTableBuilder::partition_by].
+//!
+//! The rows written to the table are grouped by the value of the column, and
+//! each group is written to fragments of its own. The value of each fragment is
+//! recorded with the table properties, by the data file of the fragment, so
+//! that a flat search whose filter requires values of the column only reads
+//! the fragments of these values.
+//!
+//! The fragments rewritten by [NativeTable::optimize] hold the rows of several
+//! values, they are not recorded and every search reads them.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+
+use arrow_array::{RecordBatch, RecordBatchReader, UInt32Array};
+use arrow_schema::{DataType, Schema as ArrowSchema};
+use datafusion::arrow::compute::take;
+use datafusion::scalar::ScalarValue;
+use datafusion::sql::sqlparser::ast::{BinaryOperator, Expr, UnaryOperator, Value};
+use datafusion::sql::sqlparser::dialect::GenericDialect;
+use datafusion::sql::sqlparser::parser::Parser;
+use lance::dataset::{Dataset, ReadParams, WriteMode, WriteParams};
+use lance::datatypes::Schema;
+use lance::format::Fragment;
+use lance::io::object_store::ObjectStore;
+use serde::{Deserialize, Serialize};
+
+use super::maintenance;
+use super::write::{commit_fragments, write_fragment};
+use super::{DatasetRef, NativeTable};
+use crate::error::{Error, Result};
+use crate::query::Literal;
+
+/// The partition column of a table and the value of each of its fragments,
+/// by the path of their data file.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct Partitioning {
+    pub column: String,
+    #[serde(default)]
+    pub fragments: BTreeMap<String, serde_json::Value>,
+}
+
+impl Partitioning {
+    pub(crate) fn new(column: &str) -> Self {
+        Self {
+            column: column.to_string(),
+            fragments: BTreeMap::new(),
+        }
+    }
+
+    /// The recorded value of `fragment`, `None` if it is not recorded.
+    fn value(&self, fragment: &Fragment) -> Option<&serde_json::Value> {
+        match fragment.files.as_slice() {
+            [first, ..] => self.fragments.get(&first.path),
+            [] => None,
+        }
+    }
+
+    /// Forget the fragments that are not in `dataset`.
+    fn retain(&mut self, dataset: &Dataset) {
+        let files = dataset
+            .get_fragments()
+            .iter()
+            .filter_map(|f| f.metadata().files.first().map(|file| file.path.clone()))
+            .collect::<std::collections::HashSet<_>>();
+        self.fragments.retain(|path, _| files.contains(path));
+    }
+}
+
+/// A fragment of a table, see [NativeTable::list_fragments].
+#[derive(Debug, Clone, PartialEq)]
+pub struct FragmentInfo {
+    pub id: u64,
+    /// The rows of the fragment, without the deleted ones.
+    pub rows: usize,
+    /// The value of the partition column of the rows of the fragment. `None`
+    /// if the table is not partitioned, if the rows are null, or if they have
+    /// several values, as after a compaction.
+    pub partition: Option<Literal>,
+}
+
+/// The partitioning of a version of a table, read once for each version.
+#[derive(Debug, Default)]
+pub(crate) struct PartitionCache {
+    partitioning: Mutex<Option<(u64, Option<Arc<Partitioning>>)>>,
+}
+
+/// Fail with an [Error::InvalidInput] unless `column` of `schema` is a column
+/// of integers or strings.
+pub(crate) fn check_partition_column(schema: &ArrowSchema, column: &str) -> Result<()> {
+    let field = schema
+        .field_with_name(column)
+        .map_err(|_| Error::InvalidInput {
+            message: format!("the partition column '{column}' is not in the data"),
+        })?;
+    let data_type = field.data_type();
+    if !data_type.is_integer() && !matches!(data_type, DataType::Utf8 | DataType::LargeUtf8) {
+        return Err(Error::InvalidInput {
+            message: format!(
+                "the partition column '{column}' must be a column of integers or strings, not {data_type}"
+            ),
+        });
+    }
+    Ok(())
+}
+
+/// The value of a row of the partition column, as recorded.
+fn partition_value(value: ScalarValue) -> serde_json::Value {
+    match value {
+        ScalarValue::Int8(Some(v)) => v.into(),
+        ScalarValue::Int16(Some(v)) => v.into(),
+        ScalarValue::Int32(Some(v)) => v.into(),
+        ScalarValue::Int64(Some(v)) => v.into(),
+        ScalarValue::UInt8(Some(v)) => v.into(),
+        ScalarValue::UInt16(Some(v)) => v.into(),
+        ScalarValue::UInt32(Some(v)) => v.into(),
+        ScalarValue::UInt64(Some(v)) => v.into(),
+        ScalarValue::Utf8(Some(v)) | ScalarValue::LargeUtf8(Some(v)) => v.into(),
+        _ => serde_json::Value::Null,
+    }
+}
+
+fn literal(value: &serde_json::Value) -> Option<Literal> {
+    match value {
+        serde_json::Value::Number(n) => n
+            .as_i64()
+            .map(Literal::Int)
+            .or_else(|| n.as_u64().map(Literal::UInt)),
+        serde_json::Value::String(s) => Some(Literal::String(s.clone())),
+        _ => None,
+    }
+}
+
+/// The rows of one value of the partition column, not yet written.
+struct Group {
+    value: serde_json::Value,
+    batches: Vec<RecordBatch>,
+    rows: usize,
+}
+
+/// Writes the rows of each value of the partition column to fragments of
+/// their own.
+struct PartitionWriter<'a> {
+    store: ObjectStore,
+    base: object_store::path::Path,
+    schema: &'a Schema,
+    params: &'a WriteParams,
+    next_id: u64,
+    fragments: Vec<Fragment>,
+    values: BTreeMap<String, serde_json::Value>,
+}
+
+impl PartitionWriter<'_> {
+    /// Write the rows of `group` to a new fragment.
+    async fn flush(&mut self, group: &mut Group) -> Result<()> {
+        if group.rows == 0 {
+            return Ok(());
+        }
+        let batches = std::mem::take(&mut group.batches);
+        let fragment = write_fragment(
+            &self.store,
+            &self.base,
+            self.next_id,
+            self.schema,
+            batches,
+            self.params,
+        )
+        .await?;
+        let filename = fragment.files[0].path.clone();
+        self.fragments.push(fragment);
+        self.values.insert(filename, group.value.clone());
+        self.next_id += 1;
+        group.rows = 0;
+        Ok(())
+    }
+}
+
+/// Write the rows of `reader` to the dataset at `uri`, `current` if it
+/// exists, each value of `column` to fragments of its own, and commit them as
+/// a new version of the dataset with `mode`, through the object store of
+/// `read_params`.
+///
+/// Returns the written dataset and the value of each new fragment. The rows of
+/// each value are kept in memory until there are `max_rows_per_file` of them.
+pub(super) async fn write_partitioned(
+    uri: &str,
+    current: Option<&Dataset>,
+    reader: &mut dyn RecordBatchReader,
+    column: &str,
+    params: &WriteParams,
+    read_params: &ReadParams,
+) -> Result<(Dataset, BTreeMap<String, serde_json::Value>)> {
+    let append = matches!(params.mode, WriteMode::Append);
+    let schema = match current {
+        Some(current) if append => current.schema().clone(),
+        _ => Schema::try_from(reader.schema().as_ref())?,
+    };
+    check_partition_column(&ArrowSchema::from(&schema), column)?;
+    let existing = match current {
+        Some(current) if append => maintenance::fragments(current),
+        _ => Vec::new(),
+    };
+    let store_params = read_params.store_options.clone().unwrap_or_default();
+    let (store, base) = ObjectStore::from_uri_and_params(uri, store_params).await?;
+    let mut writer = PartitionWriter {
+        store,
+        base,
+        schema: &schema,
+        params,
+        next_id: existing.iter().map(|f| f.id + 1).max().unwrap_or(0),
+        fragments: existing,
+        values: BTreeMap::new(),
+    };
+    let mut groups: HashMap<String, Group> = HashMap::new();
+    for batch in reader {
+        let batch = batch.map_err(lance::Error::from)?;
+        let values = batch
+            .column_by_name(column)
+            .ok_or_else(|| Error::InvalidInput {
+                message: format!("the partition column '{column}' is not in the data"),
+            })?;
+        let mut rows: HashMap<String, (serde_json::Value, Vec<u32>)> = HashMap::new();
+        for row in 0..batch.num_rows() {
+            let value = ScalarValue::try_from_array(values, row).map_err(lance::Error::from)?;
+            let value = partition_value(value);
+            rows.entry(value.to_string())
+                .or_insert_with(|| (value, Vec::new()))
+                .1
+                .push(row as u32);
+        }
+        for (key, (value, indices)) in rows {
+            let indices = UInt32Array::from(indices);
+            let columns = batch
+                .columns()
+                .iter()
+                .map(|c| take(c.as_ref(), &indices, None))
+                .collect::<std::result::Result<Vec<_>, _>>()
+                .map_err(lance::Error::from)?;
+            let rows = RecordBatch::try_new(batch.schema(), columns).map_err(lance::Error::from)?;
+            let group = groups.entry(key).or_insert_with(|| Group {
+                value,
+                batches: Vec::new(),
+                rows: 0,
+            });
+            group.rows += rows.num_rows();
+            group.batches.push(rows);
+            if group.rows >= params.max_rows_per_file {
+                writer.flush(group).await?;
+            }
+        }
+    }
+    let mut groups = groups.into_iter().collect::<Vec<_>>();
+    groups.sort_by(|a, b| a.0.cmp(&b.0));
+    for (_, mut group) in groups {
+        writer.flush(&mut group).await?;
+    }
+    let dataset =
+        commit_fragments(uri, read_params, &schema, &writer.fragments, params.mode).await?;
+    Ok((dataset, writer.values))
+}
+
+/// The values of `column` the rows matching `filter` can have, `None` if they
+/// can have any. Only equalities and `IN` lists of literals, combined with
+/// `AND`, are read.
+fn required_values(expr: &Expr, column: &str) -> Option<Vec<serde_json::Value>> {
+    let is_column = |expr: &Expr| matches!(expr, Expr::Identifier(ident) if ident.value == column);
+    match expr {
+        Expr::Nested(expr) => required_values(expr, column),
+        Expr::BinaryOp {
+            left,
+            op: BinaryOperator::And,
+            right,
+        } => match (
+            required_values(left, column),
+            required_values(right, column),
+        ) {
+            (Some(left), Some(right)) => {
+                Some(left.into_iter().filter(|v| right.contains(v)).collect())
+            }
+            (left, right) => left.or(right),
+        },
+        Expr::BinaryOp {
+            left,
+            op: BinaryOperator::Eq,
+            right,
+        } => {
+            if is_column(left) {
+                filter_literal(right).map(|v| vec![v])
+            } else if is_column(right) {
+                filter_literal(left).map(|v| vec![v])
+            } else {
+                None
+            }
+        }
+        Expr::InList {
+            expr,
+            list,
+            negated: false,
+        } if is_column(expr) => list.iter().map(filter_literal).collect(),
+        _ => None,
+    }
+}
+
+/// A literal integer or string of a filter, as a recorded value.
+pub(super) fn filter_literal(expr: &Expr) -> Option<serde_json::Value> {
+    match expr {
+        Expr::Value(Value::Number(number, _)) => number
+            .parse::<i64>()
+            .map(serde_json::Value::from)
+            .or_else(|_| number.parse::<u64>().map(serde_json::Value::from))
+            .ok(),
+        Expr::Value(Value::SingleQuotedString(s)) => Some(s.clone().into()),
+        Expr::UnaryOp {
+            op: UnaryOperator::Minus,
+            expr,
+        } => filter_literal(expr)?
+            .as_i64()
+            .map(|number| (-number).into()),
+        Expr::Nested(expr) => filter_literal(expr),
+        _ => None,
+    }
+}
+
+impl DatasetRef {
+    /// The partitioning of `dataset`, `None` if the table is not partitioned.
+    async fn partitioning(&self, dataset: &Dataset) -> Result<Option<Arc<Partitioning>>> {
+        let version = dataset.version().version;
+        let cached = self.partitions().partitioning.lock().unwrap().clone();
+        if let Some((_, partitioning)) = cached.filter(|(v, _)| *v == version) {
+            return Ok(partitioning);
+        }
+        let partitioning = self.properties().await?.partition_by.map(Arc::new);
+        *self.partitions().partitioning.lock().unwrap() = Some((version, partitioning.clone()));
+        Ok(partitioning)
+    }
+
+    /// The fragments of `dataset` that can have rows matching `filter`, and
+    /// the number of fragments left out. `None` if no fragment is left out.
+    pub(crate) async fn partition_fragments(
+        &self,
+        dataset: &Dataset,
+        filter: &str,
+    ) -> Result<Option<(Vec<Fragment>, usize)>> {
+        let Some(partitioning) = self.partitioning(dataset).await? else {
+            return Ok(None);
+        };
+        let Ok(expr) = Parser::new(&GenericDialect {})
+            .try_with_sql(filter)
+            .and_then(|mut parser| parser.parse_expr())
+        else {
+            return Ok(None);
+        };
+        let Some(values) = required_values(&expr, &partitioning.column) else {
+            return Ok(None);
+        };
+        // A literal of another type than the column is cast by lance, the
+        // fragments are not left out by it.
+        let strings = dataset
+            .schema()
+            .field(&partitioning.column)
+            .is_some_and(|f| matches!(f.data_type(), DataType::Utf8 | DataType::LargeUtf8));
+        if values.iter().any(|v| v.is_string() != strings) {
+            return Ok(None);
+        }
+        let fragments = maintenance::fragments(dataset);
+        let total = fragments.len();
+        let kept = fragments
+            .into_iter()
+            .filter(|f| partitioning.value(f).is_none_or(|v| values.contains(v)))
+            .collect::<Vec<_>>();
+        if kept.len() == total {
+            return Ok(None);
+        }
+        let skipped = total - kept.len();
+        Ok(Some((kept, skipped)))
+    }
+}
+
+impl NativeTable {
+    /// The column the rows of the table are partitioned by, see
+    /// [crate::database::CreateTableBuilder::partition_by].
+    pub async fn partition_column(&self) -> Result<Option<String>> {
+        Ok(self.properties().await?.partition_by.map(|p| p.column))
+    }
+
+    /// Record the values of `column` of the fragments `values`, written to
+    /// `dataset`, and forget the fragments no longer in it.
+    pub(super) async fn record_partitions(
+        &self,
+        dataset: &Dataset,
+        column: &str,
+        values: BTreeMap<String, serde_json::Value>,
+    ) -> Result<()> {
+        let mut properties = self.properties().await?;
+        let partitioning = properties
+            .partition_by
+            .get_or_insert_with(|| Partitioning::new(column));
+        if partitioning.column != column {
+            *partitioning = Partitioning::new(column);
+        }
+        partitioning.fragments.extend(values);
+        partitioning.retain(dataset);
+        self.set_properties(properties).await
+    }
+
+    /// The fragments of the current version of the table, and the value of
+    /// the partition column of their rows.
+    pub async fn list_fragments(&self) -> Result<Vec<FragmentInfo>> {
+        let dataset = self.dataset.get().await?;
+        let partitioning = self.dataset.partitioning(&dataset).await?;
+        let mut fragments = Vec::new();
+        for fragment in dataset.get_fragments() {
+            let partition = partitioning
+                .as_ref()
+                .and_then(|p| p.value(fragment.metadata()))
+                .and_then(literal);
+            fragments.push(FragmentInfo {
+                id: fragment.id() as u64,
+                rows: fragment.count_rows().await?,
+                partition,
+            });
+        }
+        Ok(fragments)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow_array::{Int32Array, StringArray};
+    use futures::TryStreamExt;
+    use lance::arrow::RecordBatchBuffer;
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::arrow::RecordBatchBuilder;
+    use crate::connect;
+    use crate::query::{FilterMode, QueryMetrics};
+
+    /// `rows` rows of each of the tenants, in one batch, the rows of a tenant
+    /// at a distance of about their id to [0, 0].
+    fn rows(tenants: &[&str], ids: std::ops::Range<i32>) -> Box<dyn RecordBatchReader> {
+        let mut tenant = Vec::new();
+        let mut id = Vec::new();
+        let mut vectors = Vec::new();
+        for i in ids {
+            for t in tenants {
+                tenant.push(t.to_string());
+                id.push(i);
+                vectors.push([i as f32, 0.0]);
+            }
+        }
+        let batch = RecordBatchBuilder::new()
+            .column("tenant_id", Arc::new(StringArray::from(tenant)))
+            .column("id", Arc::new(Int32Array::from(id)))
+            .vector_column("vector", 2, vectors)
+            .build()
+            .unwrap();
+        Box::new(RecordBatchBuffer::new(vec![batch]))
+    }
+
+    async fn search(table: &NativeTable, filter: &str) -> (Vec<RecordBatch>, QueryMetrics) {
+        table
+            .search(vec![0.0, 0.0])
+            .filter(Some(filter.to_string()))
+            .limit(3)
+            .execute_with_metrics()
+            .await
+            .unwrap()
+    }
+
+    async fn prefilter(table: &NativeTable, filter: &str) -> (Vec<RecordBatch>, QueryMetrics) {
+        table
+            .search(vec![0.0, 0.0])
+            .filter(Some(filter.to_string()))
+            .filter_mode(FilterMode::Prefilter)
+            .limit(15)
+            .execute_with_metrics()
+            .await
+            .unwrap()
+    }
+
+    fn tenants(batches: &[RecordBatch]) -> Vec<String> {
+        batches
+            .iter()
+            .flat_map(|b| {
+                let tenants = b["tenant_id"].as_any().downcast_ref::<StringArray>();
+                let tenants = tenants.unwrap().iter().flatten().map(str::to_string);
+                tenants.collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_partition_by() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        let db = connect(uri).execute().await.unwrap();
+        db.create_table("docs", rows(&["a", "b", "c"], 0..10))
+            .partition_by("tenant_id")
+            .execute()
+            .await
+            .unwrap();
+        let table = NativeTable::open(uri, "docs").await.unwrap();
+        table.add(rows(&["a", "d"], 10..20), None).await.unwrap();
+        assert_eq!(
+            table.partition_column().await.unwrap().as_deref(),
+            Some("tenant_id")
+        );
+
+        let fragments = table.list_fragments().await.unwrap();
+        let partitions = fragments
+            .iter()
+            .map(|f| (f.partition.clone().unwrap().to_string(), f.rows))
+            .collect::<Vec<_>>();
+        let expected = [("a", 10), ("b", 10), ("c", 10), ("a", 10), ("d", 10)];
+        let expected = expected.map(|(t, rows)| (format!("'{t}'"), rows));
+        assert_eq!(partitions, expected);
+
+        // A tenant's search reads only the fragments of the tenant.
+        let (batches, metrics) = search(&table, "tenant_id = 'b'").await;
+        assert_eq!(tenants(&batches), vec!["b"; 3]);
+        assert_eq!(metrics.fragments_skipped, 4);
+        assert_eq!(metrics.distance_computations, Some(10));
+        let (batches, metrics) = search(&table, "id < 12 AND tenant_id IN ('a', 'd')").await;
+        assert_eq!(tenants(&batches).len(), 3);
+        assert!(tenants(&batches).iter().all(|t| t == "a" || t == "d"));
+        assert_eq!(metrics.fragments_skipped, 2);
+        let (batches, metrics) = search(&table, "tenant_id = 'bb'").await;
+        assert!(tenants(&batches).is_empty());
+        assert_eq!(metrics.fragments_skipped, 5);
+
+        // Other filters skip the fragments without matching rows, found by
+        // evaluating the filter.
+        let (_, metrics) = search(&table, "tenant_id != 'b'").await;
+        assert_eq!(metrics.fragments_skipped, 1);
+        assert_eq!(metrics.distance_computations, Some(40));
+        let (_, metrics) = search(&table, "tenant_id = 'b' OR id = 1").await;
+        assert_eq!(metrics.fragments_skipped, 2);
+
+        // Deleted fragments are forgotten, the others are still pruned.
+        table.delete("tenant_id = 'c'").await.unwrap();
+        table.add(rows(&["b"], 20..25), None).await.unwrap();
+        let (batches, metrics) = search(&table, "tenant_id = 'b'").await;
+        assert_eq!(tenants(&batches), vec!["b"; 3]);
+        assert_eq!(metrics.fragments_skipped, 3);
+        assert_eq!(metrics.distance_computations, Some(15));
+        // The 10 nearest rows of the tenant, of its 15.
+        let stream = table
+            .search(vec![0.0, 0.0])
+            .filter(Some("tenant_id = 'b'".to_string()));
+        let batches = stream.execute().await.unwrap().try_collect::<Vec<_>>();
+        assert_eq!(tenants(&batches.await.unwrap()), vec!["b"; 10]);
+
+        let err = db
+            .create_table("bad", rows(&["a"], 0..1))
+            .partition_by("vector")
+            .execute()
+            .await
+            .err()
+            .unwrap();
+        assert!(matches!(err, Error::InvalidInput { .. }), "{err}");
+    }
+
+    #[tokio::test]
+    async fn test_prefilter_partition_by() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        let db = connect(uri).execute().await.unwrap();
+        db.create_table("docs", rows(&["a", "b", "c"], 0..10))
+            .partition_by("tenant_id")
+            .execute()
+            .await
+            .unwrap();
+        db.create_table("plain", rows(&["a", "b", "c"], 0..10))
+            .execute()
+            .await
+            .unwrap();
+        let partitioned = NativeTable::open(uri, "docs").await.unwrap();
+        let plain = NativeTable::open(uri, "plain").await.unwrap();
+        for table in [&partitioned, &plain] {
+            table.add(rows(&["a", "d"], 10..20), None).await.unwrap();
+        }
+        // A prefilter reads only the fragments of the tenants of the filter,
+        // and finds the same rows as in a table that is not partitioned. The
+        // rows of a tenant are at distinct distances, so in the same order.
+        for (filter, skipped, rows) in [
+            ("tenant_id = 'a'", 3, 20),
+            ("id >= 5 AND tenant_id IN ('b', 'd')", 3, 20),
+            ("tenant_id = 'bb'", 5, 0),
+            ("id > 3", 0, 50),
+        ] {
+            let (batches, metrics) = prefilter(&partitioned, filter).await;
+            let (expected, plain_metrics) = prefilter(&plain, filter).await;
+            assert_eq!(batches, expected, "{filter}");
+            assert_eq!(metrics.filter_mode, Some(FilterMode::Prefilter), "{filter}");
+            assert_eq!(metrics.fragments_skipped, skipped, "{filter}");
+            assert_eq!(plain_metrics.fragments_skipped, 0);
+            assert_eq!(metrics.rows_scanned, rows, "{filter}");
+        }
+    }
+
+    #[test]
+    fn test_required_values() {
+        let values = |filter: &str| {
+            let expr = Parser::new(&GenericDialect {})
+                .try_with_sql(filter)
+                .unwrap()
+                .parse_expr()
+                .unwrap();
+            required_values(&expr, "t")
+        };
+        assert_eq!(values("t = 1"), Some(vec![1.into()]));
+        assert_eq!(values("-2 = t AND x > 1"), Some(vec![(-2).into()]));
+        assert_eq!(
+            values("t IN ('a', 'b') AND (t = 'b')"),
+            Some(vec!["b".into()])
+        );
+        assert_eq!(values("t = 1 AND t = 2"), Some(vec![]));
+        assert_eq!(values("t = 1 OR t = 2"), None);
+        assert_eq!(values("t NOT IN (1, 2)"), None);
+        assert_eq!(values("t = x"), None);
+        assert_eq!(values("t = 1.5"), None);
+    }
+}
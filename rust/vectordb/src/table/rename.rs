@@ -0,0 +1,153 @@
+// Copyright 2023 LanceDB Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Renaming the columns of a table, see [NativeTable::rename_column].
+//!
+//! lance reads the columns of the data files and the indices by the ids of
+//! the fields, so a rename only commits a manifest with the new name. The
+//! settings kept beside the manifest, which name the column, follow it.
+
+use arrow_schema::Schema as ArrowSchema;
+
+use super::keys::TableProperties;
+use super::{NativeTable, CONTENT_HASH_COLUMN, LAST_MODIFIED_VERSION_COLUMN};
+use crate::embeddings::{read_configs, write_configs};
+use crate::error::{Error, Result};
+use crate::io::constraints::constraint_columns;
+
+impl NativeTable {
+    /// Rename the top-level column `from` of this table to `to`.
+    ///
+    /// The indices of the column, the embeddings computing it or computed
+    /// from it, the primary key, the auto ids and the partitioning follow the
+    /// column, with the same commit lock as the new version. Searches of the
+    /// column then name it `to`, and the index keeps its name.
+    ///
+    /// Fails with an [Error::InvalidInput] if the table has no column `from`,
+    /// already has a column `to`, or if the constraints of the table read the
+    /// column, naming them: their expressions are not rewritten, replace them
+    /// with [NativeTable::set_constraints] first. The columns the table
+    /// maintains itself are not renamed.
+    pub async fn rename_column(&self, from: &str, to: &str) -> Result<()> {
+        let invalid = |message: String| Error::InvalidInput { message };
+        if to.is_empty() || to.contains('.') {
+            return Err(invalid(format!(
+                "cannot rename the column '{from}' to '{to}'"
+            )));
+        }
+        if [CONTENT_HASH_COLUMN, LAST_MODIFIED_VERSION_COLUMN].contains(&from) {
+            return Err(invalid(format!(
+                "the column '{from}' is maintained by the table and cannot be renamed"
+            )));
+        }
+        self.dataset
+            .write(self.max_commit_retries, |latest| async move {
+                let schema = ArrowSchema::from(latest.schema());
+                if schema.field_with_name(from).is_err() {
+                    return Err(invalid(format!("the table has no column '{from}'")));
+                }
+                if schema.field_with_name(to).is_ok() {
+                    return Err(invalid(format!("the table already has a column '{to}'")));
+                }
+                let properties = self.properties().await?;
+                let mut dependents = Vec::new();
+                for constraint in &properties.constraints {
+                    if constraint_columns(constraint, &schema)?
+                        .iter()
+                        .any(|c| c == from)
+                    {
+                        dependents.push(format!("'{}'", constraint.name));
+                    }
+                }
+                if !dependents.is_empty() {
+                    return Err(invalid(format!(
+                        "cannot rename the column '{from}', the constraints {} read it",
+                        dependents.join(", ")
+                    )));
+                }
+                let indices = latest.load_indices().await?;
+                let renamed = self
+                    .dataset
+                    .commit_manifest(&latest, indices, |manifest| {
+                        for field in manifest.schema.fields.iter_mut() {
+                            if field.name == from {
+                                field.name = to.to_string();
+                            }
+                        }
+                    })
+                    .await?;
+                let version = renamed.version().version;
+                self.rename_settings(properties, version, from, to).await?;
+                Ok(renamed)
+            })
+            .await?;
+        for embedding in self.embeddings.write().unwrap().iter_mut() {
+            embedding.rename_column(from, to);
+        }
+        Ok(())
+    }
+
+    /// Rename the column `from` to `to` in `properties` and in the index
+    /// configurations of the table, and in its embeddings from `version`, the
+    /// version of the rename.
+    async fn rename_settings(
+        &self,
+        mut properties: TableProperties,
+        version: u64,
+        from: &str,
+        to: &str,
+    ) -> Result<()> {
+        let mut changed = false;
+        let columns = [
+            properties.primary_key.as_mut(),
+            properties.auto_id.as_mut().map(|a| &mut a.column),
+            properties.partition_by.as_mut().map(|p| &mut p.column),
+        ];
+        for column in columns.into_iter().flatten() {
+            if *column == from {
+                *column = to.to_string();
+                changed = true;
+            }
+        }
+        if let Some(norms) = properties.norm_columns.remove(from) {
+            properties.norm_columns.insert(to.to_string(), norms);
+            changed = true;
+        }
+        if let Some(metadata) = properties.field_metadata.remove(from) {
+            properties.field_metadata.insert(to.to_string(), metadata);
+            changed = true;
+        }
+        if changed {
+            self.set_properties(properties).await?;
+        }
+
+        let mut configs = self.index_configs().await?;
+        if configs.iter().any(|c| c.column == from) {
+            for config in configs.iter_mut().filter(|c| c.column == from) {
+                config.column = to.to_string();
+            }
+            self.write_index_configs(&configs).await?;
+        }
+
+        let (store, base) = self.dataset.object_store().await?;
+        let mut configs = read_configs(&store, &base, version).await?;
+        if !configs.is_empty() {
+            for config in configs.iter_mut() {
+                config.rename_column(from, to);
+            }
+            write_configs(&store, &base, version, &configs).await?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,258 @@
+// Copyright 2023 LanceDB Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Deleting the rows of a table older than a retention period, see
+//! [NativeTable::apply_retention], on demand or as a step of
+//! [NativeTable::optimize].
+
+use std::time::Duration;
+
+use arrow_array::cast::as_primitive_array;
+use arrow_array::types::Int64Type;
+use arrow_schema::{DataType, Schema as ArrowSchema, TimeUnit};
+use chrono::{SecondsFormat, TimeZone, Utc};
+use datafusion::arrow::compute::cast;
+use futures::TryStreamExt;
+
+use super::NativeTable;
+use crate::error::{Error, Result};
+use crate::query::filter::lance_filter;
+
+/// The rows a maintenance run deletes by their age, see
+/// [NativeTable::apply_retention].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    /// The timestamp or date column holding the time of each row.
+    pub column: String,
+    /// The rows whose time is older than this are deleted.
+    pub older_than: Duration,
+    /// The most rows deleted by each delete, `None` deletes them all at once.
+    pub batch: Option<usize>,
+}
+
+/// The nanoseconds of a value of a temporal column of `data_type`.
+fn nanos_per_value(data_type: &DataType) -> Option<i128> {
+    Some(match data_type {
+        DataType::Date32 => 86_400_000_000_000,
+        DataType::Date64 | DataType::Timestamp(TimeUnit::Millisecond, _) => 1_000_000,
+        DataType::Timestamp(TimeUnit::Second, _) => 1_000_000_000,
+        DataType::Timestamp(TimeUnit::Microsecond, _) => 1_000,
+        DataType::Timestamp(TimeUnit::Nanosecond, _) => 1,
+        _ => return None,
+    })
+}
+
+/// The instant of the value `value` of a column whose values are `nanos`
+/// nanoseconds, as a filter string, `None` if it is out of range.
+fn instant(value: i64, nanos: i128) -> Option<String> {
+    let nanos = value as i128 * nanos;
+    let secs = i64::try_from(nanos.div_euclid(1_000_000_000)).ok()?;
+    let subsec = nanos.rem_euclid(1_000_000_000) as u32;
+    let instant = Utc.timestamp_opt(secs, subsec).single()?;
+    Some(instant.to_rfc3339_opts(SecondsFormat::Nanos, true))
+}
+
+impl NativeTable {
+    /// Delete the rows whose timestamp or date `column` is older than
+    /// `older_than` before now, returning the rows removed by each delete.
+    ///
+    /// Each delete commits a version. With `batch`, the oldest rows are
+    /// deleted first, at most `batch` of them per delete, so that a large
+    /// backlog is removed in bounded steps. The rows of the same time are
+    /// deleted together, more than `batch` of them if that many share the
+    /// oldest time. The rows whose `column` is null are kept.
+    ///
+    /// Fails with an [Error::InvalidInput] if `column` is not a timestamp or
+    /// date column, or `batch` is 0.
+    pub async fn apply_retention(
+        &self,
+        column: &str,
+        older_than: Duration,
+        batch: Option<usize>,
+    ) -> Result<Vec<usize>> {
+        let invalid = |message: String| Error::InvalidInput { message };
+        if batch == Some(0) {
+            return Err(invalid(
+                "apply_retention needs a batch of at least 1".to_string(),
+            ));
+        }
+        let dataset = self.dataset.get().await?;
+        let schema = ArrowSchema::from(dataset.schema());
+        let nanos = match schema.field_with_name(column) {
+            Ok(field) => nanos_per_value(field.data_type()).ok_or_else(|| {
+                invalid(format!(
+                    "the column '{column}' is not a timestamp or date column"
+                ))
+            })?,
+            Err(_) => return Err(invalid(format!("the table has no column '{column}'"))),
+        };
+        let cutoff = chrono::Duration::from_std(older_than)
+            .ok()
+            .and_then(|older_than| Utc::now().checked_sub_signed(older_than))
+            .ok_or_else(|| invalid(format!("the retention of {older_than:?} is out of range")))?;
+        let expired = format!(
+            "{column} < '{}'",
+            cutoff.to_rfc3339_opts(SecondsFormat::Nanos, true)
+        );
+
+        let mut deleted = Vec::new();
+        loop {
+            let dataset = self.dataset.get().await?;
+            let mut scanner = dataset.scan();
+            scanner.project(&[column])?;
+            scanner.filter(&lance_filter(&expired, &schema)?)?;
+            let batches = scanner
+                .try_into_stream()
+                .await?
+                .try_collect::<Vec<_>>()
+                .await?;
+            let mut times = Vec::new();
+            for batch in &batches {
+                let values =
+                    cast(batch[column].as_ref(), &DataType::Int64).map_err(lance::Error::from)?;
+                times.extend(
+                    as_primitive_array::<Int64Type>(values.as_ref())
+                        .iter()
+                        .flatten(),
+                );
+            }
+            if times.is_empty() {
+                return Ok(deleted);
+            }
+            let predicate = match batch {
+                Some(batch) if times.len() > batch => {
+                    times.sort_unstable();
+                    // The rows before the first one left, or all the rows of the
+                    // oldest time if that is more than a batch.
+                    let (op, time) = if times[batch] > times[0] {
+                        ("<", times[batch])
+                    } else {
+                        ("<=", times[0])
+                    };
+                    let time = instant(time, nanos).ok_or_else(|| {
+                        invalid(format!("a time of the column '{column}' is out of range"))
+                    })?;
+                    format!("{column} {op} '{time}'")
+                }
+                _ => expired.clone(),
+            };
+            let report = self
+                .delete_returning(&predicate, Some(vec![column.to_string()]), None)
+                .await?;
+            deleted.push(report.rows);
+            if predicate == expired {
+                return Ok(deleted);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_array::{Int32Array, RecordBatchReader, TimestampMicrosecondArray};
+    use lance::arrow::RecordBatchBuffer;
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::arrow::RecordBatchBuilder;
+    use crate::table::MaintenanceConfig;
+
+    const DAY: Duration = Duration::from_secs(86_400);
+
+    /// The rows `ids`, the row `i` of the time `i` days before now.
+    fn rows(ids: std::ops::Range<i32>) -> Box<dyn RecordBatchReader> {
+        let now = Utc::now();
+        let ts = ids
+            .clone()
+            .map(|i| (now - chrono::Duration::days(i as i64)).timestamp_micros());
+        let batch = RecordBatchBuilder::new()
+            .column("id", Arc::new(Int32Array::from_iter_values(ids)))
+            .column(
+                "ts",
+                Arc::new(TimestampMicrosecondArray::from_iter_values(ts).with_timezone("UTC")),
+            )
+            .build()
+            .unwrap();
+        Box::new(RecordBatchBuffer::new(vec![batch]))
+    }
+
+    async fn ids(table: &NativeTable) -> Vec<i32> {
+        let dataset = table.dataset.get().await.unwrap();
+        let batches = dataset.scan().try_into_stream().await.unwrap();
+        let batches = batches.try_collect::<Vec<_>>().await.unwrap();
+        let mut ids = batches
+            .iter()
+            .flat_map(|b| {
+                let ids = b["id"].as_any().downcast_ref::<Int32Array>().unwrap();
+                ids.values().to_vec()
+            })
+            .collect::<Vec<_>>();
+        ids.sort();
+        ids
+    }
+
+    #[tokio::test]
+    async fn test_apply_retention() {
+        let tmp_dir = tempdir().unwrap();
+        let older_than = DAY * 9 / 2;
+
+        let uri = tmp_dir.path().join("all");
+        let table = NativeTable::create(uri.to_str().unwrap(), "all", rows(0..10), None)
+            .await
+            .unwrap();
+        let deleted = table.apply_retention("ts", older_than, None).await.unwrap();
+        assert_eq!(deleted, vec![5]);
+        assert_eq!(ids(&table).await, vec![0, 1, 2, 3, 4]);
+        let version = table.dataset.current().version().version;
+        assert!(table
+            .apply_retention("ts", older_than, None)
+            .await
+            .unwrap()
+            .is_empty());
+        assert_eq!(table.dataset.current().version().version, version);
+
+        let uri = tmp_dir.path().join("batched");
+        let table = NativeTable::create(uri.to_str().unwrap(), "batched", rows(0..10), None)
+            .await
+            .unwrap();
+        let deleted = table.apply_retention("ts", older_than, Some(2));
+        assert_eq!(deleted.await.unwrap(), vec![2, 2, 1]);
+        assert_eq!(ids(&table).await, vec![0, 1, 2, 3, 4]);
+
+        // Retention runs with the other maintenance actions.
+        table.add(rows(20..23), None).await.unwrap();
+        let config = MaintenanceConfig {
+            retention: Some(RetentionPolicy {
+                column: "ts".to_string(),
+                older_than,
+                batch: None,
+            }),
+            optimize_indices: false,
+            ..Default::default()
+        };
+        let stats = table.optimize(&config).await.unwrap();
+        assert_eq!(stats.rows_expired, 3);
+        assert_eq!(ids(&table).await, vec![0, 1, 2, 3, 4]);
+
+        for (column, batch) in [("id", None), ("missing", None), ("ts", Some(0))] {
+            let err = table
+                .apply_retention(column, older_than, batch)
+                .await
+                .unwrap_err();
+            assert!(matches!(err, Error::InvalidInput { .. }), "{err}");
+        }
+    }
+}
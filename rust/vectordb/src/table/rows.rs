@@ -0,0 +1,230 @@
+// Copyright 2023 LanceDB Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Every row of a table as a Rust value, see [NativeTable::iter_rows].
+//!
+//! The rows are read in the order of the fragments of the table and of the
+//! rows in each fragment, the order of the offsets. The fragments before the
+//! offset are skipped by their number of rows, without reading them.
+
+use std::marker::PhantomData;
+
+use futures::stream::{self, BoxStream};
+use futures::{StreamExt, TryStreamExt};
+use serde::de::DeserializeOwned;
+
+use super::NativeTable;
+use crate::arrow::batch_to_rows;
+use crate::error::{Error, Result};
+
+/// A builder for the stream of the rows of a table, see
+/// [NativeTable::iter_rows].
+pub struct IterRowsBuilder<'a, T> {
+    table: &'a NativeTable,
+    batch_size: usize,
+    offset: usize,
+    columns: Option<Vec<String>>,
+    row: PhantomData<fn() -> T>,
+}
+
+impl<'a, T: DeserializeOwned + Send + 'static> IterRowsBuilder<'a, T> {
+    pub(crate) fn new(table: &'a NativeTable, batch_size: usize) -> Self {
+        Self {
+            table,
+            batch_size,
+            offset: 0,
+            columns: None,
+            row: PhantomData,
+        }
+    }
+
+    /// Start at the row `offset`, the number of rows read before, so that an
+    /// interrupted iteration resumes where it stopped. Default: 0.
+    ///
+    /// The offsets of the rows change with the writes to the table, iterate
+    /// over a pinned version, see [NativeTable::checkout], to resume.
+    pub fn offset(mut self, offset: usize) -> Self {
+        self.offset = offset;
+        self
+    }
+
+    /// Only read `columns`, all of them if `None`. Default: `None`.
+    pub fn select(mut self, columns: Option<Vec<String>>) -> Self {
+        self.columns = columns;
+        self
+    }
+
+    /// The rows of the table, read [NativeTable::iter_rows]'s `batch_size` at
+    /// a time.
+    ///
+    /// Fails with an [Error::InvalidInput] if `batch_size` is 0, and the
+    /// stream with one if a row does not fit `T`, see [batch_to_rows].
+    pub async fn execute(self) -> Result<BoxStream<'static, Result<T>>> {
+        if self.batch_size == 0 {
+            return Err(Error::InvalidInput {
+                message: "iter_rows needs a batch_size of at least 1".to_string(),
+            });
+        }
+        let latest = self.table.dataset.get().await?;
+        let dataset = self.table.dataset.tuned(latest, &self.table.scan_params);
+        let dataset = dataset.await?;
+        let mut skip = self.offset;
+        let mut fragments = Vec::new();
+        for fragment in dataset.get_fragments() {
+            if fragments.is_empty() {
+                let rows = fragment.count_rows().await?;
+                if rows <= skip {
+                    skip -= rows;
+                    continue;
+                }
+            }
+            fragments.push(fragment.metadata().clone());
+        }
+        if fragments.is_empty() {
+            return Ok(stream::empty().boxed());
+        }
+
+        let mut scanner = dataset.scan();
+        self.table.scan_params.apply(&mut scanner);
+        if let Some(columns) = self.columns.as_ref() {
+            scanner.project(columns)?;
+        }
+        scanner
+            .with_fragments(fragments)
+            .scan_in_order(true)
+            .batch_size(self.batch_size);
+        let batches = scanner.try_into_stream().await?;
+        Ok(batches
+            .map(move |batch| {
+                let batch = batch?;
+                let skipped = batch.num_rows().min(skip);
+                skip -= skipped;
+                let rows = batch_to_rows::<T>(&batch.slice(skipped, batch.num_rows() - skipped))?;
+                Ok::<_, Error>(stream::iter(rows.into_iter().map(Ok)))
+            })
+            .try_flatten()
+            .boxed())
+    }
+}
+
+impl NativeTable {
+    /// A stream of every row of this table as a `T`, see [batch_to_rows],
+    /// reading `batch_size` rows at a time from the table.
+    ///
+    /// The deleted rows are left out. The stream holds one batch at a time,
+    /// tables larger than memory can be exported with it.
+    pub fn iter_rows<T: DeserializeOwned + Send + 'static>(
+        &self,
+        batch_size: usize,
+    ) -> IterRowsBuilder<'_, T> {
+        IterRowsBuilder::new(self, batch_size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_array::{Int32Array, RecordBatchReader};
+    use lance::arrow::RecordBatchBuffer;
+    use serde::Deserialize;
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::arrow::RecordBatchBuilder;
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct Row {
+        id: i32,
+        vector: Vec<f32>,
+    }
+
+    fn rows(ids: std::ops::Range<i32>) -> Box<dyn RecordBatchReader> {
+        let vectors = ids.clone().map(|i| [i as f32, 1.0]).collect::<Vec<_>>();
+        let batch = RecordBatchBuilder::new()
+            .column("id", Arc::new(Int32Array::from_iter_values(ids)))
+            .vector_column("vector", 2, vectors)
+            .build()
+            .unwrap();
+        Box::new(RecordBatchBuffer::new(vec![batch]))
+    }
+
+    #[tokio::test]
+    async fn test_iter_rows_resume() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        let table = NativeTable::create(uri, "test", rows(0..10), None)
+            .await
+            .unwrap();
+        table.add(rows(10..20), None).await.unwrap();
+        table.add(rows(20..30), None).await.unwrap();
+        table.delete("id >= 5 AND id < 12").await.unwrap();
+        let expected = (0..5).chain(12..30).collect::<Vec<_>>();
+
+        let table = table.checkout(table.version()).await.unwrap();
+        let all = table
+            .iter_rows::<Row>(4)
+            .execute()
+            .await
+            .unwrap()
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        assert_eq!(all.iter().map(|r| r.id).collect::<Vec<_>>(), expected);
+        assert_eq!(all[1].vector, vec![1.0, 1.0]);
+
+        // Interrupted after 6 rows, in the middle of the second fragment.
+        let mut read = table
+            .iter_rows::<Row>(4)
+            .execute()
+            .await
+            .unwrap()
+            .take(6)
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        let resumed = table
+            .iter_rows::<Row>(4)
+            .offset(read.len())
+            .execute()
+            .await
+            .unwrap()
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        read.extend(resumed);
+        assert_eq!(read, all);
+
+        #[derive(Debug, Deserialize)]
+        struct Id {
+            id: i32,
+        }
+        let ids = table
+            .iter_rows::<Id>(100)
+            .offset(20)
+            .select(Some(vec!["id".to_string()]))
+            .execute()
+            .await
+            .unwrap()
+            .map_ok(|r| r.id)
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        assert_eq!(ids, vec![27, 28, 29]);
+        let past_end = table.iter_rows::<Id>(100).offset(100).execute().await;
+        assert_eq!(past_end.unwrap().count().await, 0);
+        let err = table.iter_rows::<Id>(0).execute().await.err().unwrap();
+        assert!(matches!(err, Error::InvalidInput { .. }), "{err}");
+    }
+}
@@ -0,0 +1,327 @@
+// Copyright 2023 LanceDB Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Comparison of the schemas of added data with the schema of a table, and
+//! the columns added to evolve the schema.
+
+use std::sync::Arc;
+
+use arrow_array::{new_null_array, RecordBatch};
+use arrow_schema::{DataType, Field, Schema as ArrowSchema};
+use lance::dataset::{Dataset, WriteMode};
+
+use super::{write, NativeTable};
+use crate::error::Result;
+
+/// A column of added data of another type than the column of the table, cast
+/// to the table's type when it is written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ColumnCast {
+    pub column: String,
+    pub from: DataType,
+    pub to: DataType,
+}
+
+/// How the schema of added data differs from the schema of a table, see
+/// [NativeTable::check_compatible].
+///
+/// Data of an exact schema is written as it is. Data of a compatible schema is
+/// cast and completed with nulls for the missing columns. New columns are only
+/// written with [crate::WriteOptions::schema_evolution].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SchemaDelta {
+    /// The columns that are cast to the types of the table.
+    pub casts: Vec<ColumnCast>,
+    /// The nullable columns of the table that the data does not have, written
+    /// as nulls.
+    pub missing_nullable: Vec<String>,
+    /// The nullable columns of the data that the table does not have.
+    pub new_nullable: Vec<Field>,
+    /// Why the data cannot be written to the table, one reason per column.
+    pub incompatible: Vec<String>,
+}
+
+impl SchemaDelta {
+    /// Whether the data has the columns and types of the table.
+    pub fn is_exact(&self) -> bool {
+        self.casts.is_empty()
+            && self.missing_nullable.is_empty()
+            && self.new_nullable.is_empty()
+            && self.incompatible.is_empty()
+    }
+
+    /// Whether the data can be added to the table, with schema evolution if
+    /// `schema_evolution` is set.
+    pub fn is_compatible(&self, schema_evolution: bool) -> bool {
+        self.incompatible.is_empty() && (schema_evolution || self.new_nullable.is_empty())
+    }
+}
+
+/// Whether the values of `from` can be cast to `to` without loss, except for
+/// the float vectors, which can be cast between precisions. Dictionary-encoded
+/// columns are decoded to their values.
+pub(crate) fn can_cast(from: &DataType, to: &DataType) -> bool {
+    use DataType::*;
+    match (from, to) {
+        (from, to) if from == to => true,
+        (Null, _) => true,
+        (FixedSizeList(from, from_width), FixedSizeList(to, to_width)) => {
+            from_width == to_width
+                && (from.data_type() == to.data_type()
+                    || from.data_type().is_floating() && to.data_type().is_floating())
+        }
+        (List(from), List(to)) => can_cast(from.data_type(), to.data_type()),
+        (Int8, Int16 | Int32 | Int64 | Float32 | Float64) => true,
+        (Int16, Int32 | Int64 | Float32 | Float64) => true,
+        (Int32, Int64 | Float64) => true,
+        (UInt8, UInt16 | UInt32 | UInt64 | Int16 | Int32 | Int64 | Float32 | Float64) => true,
+        (UInt16, UInt32 | UInt64 | Int32 | Int64 | Float32 | Float64) => true,
+        (UInt32, UInt64 | Int64 | Float64) => true,
+        (Float16, Float32 | Float64) => true,
+        (Float32, Float64) => true,
+        (Utf8, LargeUtf8) => true,
+        (Binary, LargeBinary) => true,
+        (Dictionary(_, from), to) => can_cast(from, to),
+        _ => false,
+    }
+}
+
+/// How `incoming` differs from the schema `table` of a table.
+pub(crate) fn schema_delta(table: &ArrowSchema, incoming: &ArrowSchema) -> SchemaDelta {
+    let mut delta = SchemaDelta::default();
+    for field in table.fields() {
+        // lance compares the stored dictionaries with the written ones.
+        if let DataType::Dictionary(_, _) = field.data_type() {
+            delta.incompatible.push(format!(
+                "the column '{}' is dictionary-encoded, which cannot be appended to",
+                field.name()
+            ));
+            continue;
+        }
+        let Ok(other) = incoming.field_with_name(field.name()) else {
+            if field.is_nullable() {
+                delta.missing_nullable.push(field.name().clone());
+            } else {
+                delta
+                    .incompatible
+                    .push(format!("the column '{}' is missing", field.name()));
+            }
+            continue;
+        };
+        if other.is_nullable() && !field.is_nullable() {
+            delta.incompatible.push(format!(
+                "the column '{}' is nullable, the table's is not",
+                field.name()
+            ));
+        } else if !can_cast(other.data_type(), field.data_type()) {
+            delta.incompatible.push(format!(
+                "the column '{}' is {}, which cannot be cast to the table's {}",
+                field.name(),
+                other.data_type(),
+                field.data_type()
+            ));
+        } else if other.data_type() != field.data_type() {
+            delta.casts.push(ColumnCast {
+                column: field.name().clone(),
+                from: other.data_type().clone(),
+                to: field.data_type().clone(),
+            });
+        }
+    }
+    for field in incoming.fields() {
+        if table.field_with_name(field.name()).is_ok() {
+            continue;
+        }
+        if field.is_nullable() {
+            delta.new_nullable.push(field.as_ref().clone());
+        } else {
+            delta.incompatible.push(format!(
+                "the table has no column '{}', and it is not nullable",
+                field.name()
+            ));
+        }
+    }
+    delta
+}
+
+impl NativeTable {
+    /// Compare `schema`, the schema of data to add, with the schema of the
+    /// current version of this table, without writing anything.
+    pub async fn check_compatible(&self, schema: &ArrowSchema) -> Result<SchemaDelta> {
+        let current = self.dataset.get().await?;
+        Ok(schema_delta(&ArrowSchema::from(current.schema()), schema))
+    }
+
+    /// Add the nullable columns `fields` to the table, null in its rows.
+    ///
+    /// Each fragment of the table gets a data file of the new columns, so the
+    /// existing data files and the indices are kept. The metadata of `fields`
+    /// is recorded with the settings of the table.
+    pub(crate) async fn add_null_columns(&self, fields: &[Field]) -> Result<Arc<Dataset>> {
+        let columns = &Arc::new(ArrowSchema::new(fields.to_vec()));
+        let nulls = |rows: usize| {
+            let arrays = fields
+                .iter()
+                .map(|f| new_null_array(f.data_type(), rows))
+                .collect();
+            RecordBatch::try_new(columns.clone(), arrays)
+        };
+        let dataset = self
+            .dataset
+            .write(self.max_commit_retries, |current| async move {
+                let key = [current.schema().fields[0].name.as_str()];
+                let mut fragments = Vec::new();
+                for fragment in current.get_fragments() {
+                    let mut updater = fragment.updater(Some(&key)).await?;
+                    while let Some(batch) = updater.next().await? {
+                        let rows = batch.num_rows();
+                        updater
+                            .update(nulls(rows).map_err(lance::Error::from)?)
+                            .await?;
+                    }
+                    fragments.push(updater.finish().await?);
+                }
+                let schema = current.schema().merge(columns.as_ref())?;
+                let read_params = self.dataset.read_params();
+                let committed = write::commit_fragments(
+                    &self.uri,
+                    &read_params,
+                    &schema,
+                    &fragments,
+                    WriteMode::Append,
+                )
+                .await?;
+                Ok(committed)
+            })
+            .await?;
+        self.record_field_metadata(fields).await?;
+        Ok(dataset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schema(fields: Vec<Field>) -> ArrowSchema {
+        ArrowSchema::new(fields)
+    }
+
+    fn vector(item: DataType, width: i32) -> DataType {
+        DataType::FixedSizeList(Arc::new(Field::new("item", item, true)), width)
+    }
+
+    #[test]
+    fn test_schema_delta() {
+        let table = schema(vec![
+            Field::new("id", DataType::Int64, false),
+            Field::new("name", DataType::Utf8, true),
+            Field::new("vector", vector(DataType::Float32, 2), true),
+        ]);
+
+        // Exact, in any order.
+        let incoming = schema(vec![
+            Field::new("vector", vector(DataType::Float32, 2), true),
+            Field::new("id", DataType::Int64, false),
+            Field::new("name", DataType::Utf8, true),
+        ]);
+        let delta = schema_delta(&table, &incoming);
+        assert!(delta.is_exact());
+
+        // Compatible with casts, and without a nullable column.
+        let incoming = schema(vec![
+            Field::new("id", DataType::Int32, false),
+            Field::new("vector", vector(DataType::Float64, 2), true),
+        ]);
+        let delta = schema_delta(&table, &incoming);
+        assert!(!delta.is_exact());
+        assert!(delta.is_compatible(false));
+        let casts = vec![
+            ColumnCast {
+                column: "id".to_string(),
+                from: DataType::Int32,
+                to: DataType::Int64,
+            },
+            ColumnCast {
+                column: "vector".to_string(),
+                from: vector(DataType::Float64, 2),
+                to: vector(DataType::Float32, 2),
+            },
+        ];
+        assert_eq!(delta.casts, casts);
+        assert_eq!(delta.missing_nullable, vec!["name".to_string()]);
+
+        // New nullable columns need schema evolution.
+        let mut fields = table
+            .fields()
+            .iter()
+            .map(|f| f.as_ref().clone())
+            .collect::<Vec<_>>();
+        fields.push(Field::new("tag", DataType::Utf8, true));
+        let delta = schema_delta(&table, &schema(fields));
+        assert_eq!(
+            delta.new_nullable,
+            vec![Field::new("tag", DataType::Utf8, true)]
+        );
+        assert!(!delta.is_compatible(false));
+        assert!(delta.is_compatible(true));
+
+        // Incompatible.
+        let incoming = schema(vec![
+            Field::new("id", DataType::Utf8, false),
+            Field::new("vector", vector(DataType::Float32, 3), true),
+            Field::new("other", DataType::Int32, false),
+        ]);
+        let delta = schema_delta(&table, &incoming);
+        assert!(!delta.is_compatible(true));
+        assert_eq!(
+            delta.incompatible,
+            vec![
+                "the column 'id' is Utf8, which cannot be cast to the table's Int64".to_string(),
+                format!(
+                    "the column 'vector' is {}, which cannot be cast to the table's {}",
+                    vector(DataType::Float32, 3),
+                    vector(DataType::Float32, 2)
+                ),
+                "the table has no column 'other', and it is not nullable".to_string(),
+            ]
+        );
+        let incoming = schema(vec![Field::new("id", DataType::Int64, true)]);
+        let delta = schema_delta(&table, &incoming);
+        assert_eq!(
+            delta.incompatible,
+            vec!["the column 'id' is nullable, the table's is not".to_string()]
+        );
+        assert_eq!(delta.missing_nullable, vec!["name", "vector"]);
+
+        // Dictionaries are decoded, but cannot be appended to.
+        let dictionary = DataType::Dictionary(Box::new(DataType::Int8), Box::new(DataType::Utf8));
+        let incoming = schema(vec![
+            Field::new("id", DataType::Int64, false),
+            Field::new("name", dictionary.clone(), true),
+        ]);
+        let delta = schema_delta(&table, &incoming);
+        assert!(delta.is_compatible(false));
+        assert_eq!(delta.casts[0].from, dictionary);
+        let encoded = schema(vec![Field::new("name", dictionary, true)]);
+        let delta = schema_delta(
+            &encoded,
+            &schema(vec![Field::new("name", DataType::Utf8, true)]),
+        );
+        assert_eq!(
+            delta.incompatible,
+            vec!["the column 'name' is dictionary-encoded, which cannot be appended to"]
+        );
+    }
+}
@@ -0,0 +1,276 @@
+// Copyright 2023 LanceDB Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Views of the rows of a table matching a filter, see [NativeTable::scoped].
+//!
+//! The filter of a scope is joined with the filters of each search, count and
+//! delete of the view, parsed whole so that no filter reaches out of its
+//! parentheses. The rows written through the view are checked like the rows
+//! of a [super::Constraint]. The table has no update, so the rows of a scope
+//! only change by the writes and deletes of the table.
+
+use arrow_array::RecordBatchReader;
+use arrow_schema::Schema as ArrowSchema;
+use futures::TryStreamExt;
+use lance::dataset::WriteMode;
+
+use super::{Constraint, DeleteReport, NativeTable, OnConstraintViolation};
+use crate::error::{Error, Result};
+use crate::io::constraints::check_constraints;
+use crate::query::filter::{and_filters, lance_filter};
+use crate::query::{Query, QueryVector};
+
+/// The rows of a [NativeTable] matching the filters of its scopes, see
+/// [NativeTable::scoped].
+#[derive(Debug, Clone)]
+pub struct ScopedTable {
+    table: NativeTable,
+    /// The filters of the nested scopes, outermost first.
+    scopes: Vec<String>,
+}
+
+impl NativeTable {
+    /// A view of the rows of this table matching `filter`, a SQL WHERE clause,
+    /// for example `tenant_id = 'a'`.
+    ///
+    /// The filter is joined with the filters of the searches, counts and
+    /// deletes of the view, which cannot read or delete other rows whatever
+    /// their own filters, and the rows added through the view have to match
+    /// it. An invalid filter fails each of them with an
+    /// [Error::InvalidInput].
+    pub fn scoped(&self, filter: &str) -> ScopedTable {
+        ScopedTable {
+            table: self.clone(),
+            scopes: vec![filter.to_string()],
+        }
+    }
+}
+
+impl ScopedTable {
+    /// A view of the rows of this view matching `filter` too.
+    pub fn scoped(&self, filter: &str) -> ScopedTable {
+        let mut scoped = self.clone();
+        scoped.scopes.push(filter.to_string());
+        scoped
+    }
+
+    /// The filter of the rows of this view, the filters of its scopes joined
+    /// by `AND`.
+    pub fn filter(&self) -> Result<String> {
+        and_filters(self.scopes.iter().map(String::as_str))
+    }
+
+    /// The filter of the rows of this view matching `predicate`.
+    fn within(&self, predicate: &str) -> Result<String> {
+        let scopes = self.scopes.iter().map(String::as_str);
+        and_filters(scopes.chain(std::iter::once(predicate)))
+    }
+
+    /// A search of the rows of this view, see [NativeTable::search]. Its
+    /// filter, set by [Query::filter] or otherwise, only narrows the rows of
+    /// the view.
+    pub fn search(&self, query: impl Into<QueryVector>) -> Query {
+        let mut query = self.table.search(query);
+        query.scopes = self.scopes.clone();
+        query
+    }
+
+    /// The number of rows of this view.
+    pub async fn count_rows(&self) -> Result<usize> {
+        let filter = self.filter()?;
+        let dataset = self.table.dataset.get().await?;
+        let schema = ArrowSchema::from(dataset.schema());
+        let mut scanner = dataset.scan();
+        scanner.project::<&str>(&[])?;
+        scanner.filter(&lance_filter(&filter, &schema)?)?;
+        scanner.with_row_id();
+        let mut stream = scanner.try_into_stream().await?;
+        let mut rows = 0;
+        while let Some(batch) = stream.try_next().await? {
+            rows += batch.num_rows();
+        }
+        Ok(rows)
+    }
+
+    /// Add `batches` to the table, see [NativeTable::add], failing with an
+    /// [Error::InvalidInput] without writing any row if one of them does not
+    /// match the filter of this view, null included.
+    ///
+    /// An overwrite would remove the rows outside the view, and fails with an
+    /// [Error::InvalidInput].
+    pub async fn add(
+        &self,
+        batches: Box<dyn RecordBatchReader>,
+        write_mode: Option<WriteMode>,
+    ) -> Result<usize> {
+        if matches!(write_mode, Some(WriteMode::Overwrite)) {
+            return Err(Error::InvalidInput {
+                message: "a scoped table cannot overwrite its table".to_string(),
+            });
+        }
+        let scope = Constraint::new("scope", format!("({}) IS TRUE", self.filter()?));
+        let (batches, violations) =
+            check_constraints(batches, &[scope], OnConstraintViolation::Error)?;
+        self.table
+            .add(batches, write_mode)
+            .await
+            .map_err(|e| violations.take_error(e))
+    }
+
+    /// Delete the rows of this view matching `predicate`, see
+    /// [NativeTable::delete].
+    pub async fn delete(&self, predicate: &str) -> Result<()> {
+        self.table.delete(&self.within(predicate)?).await
+    }
+
+    /// Delete the rows of this view matching `predicate`, returning them, see
+    /// [NativeTable::delete_returning].
+    pub async fn delete_returning(
+        &self,
+        predicate: &str,
+        projection: Option<Vec<String>>,
+        max_rows: Option<usize>,
+    ) -> Result<DeleteReport> {
+        let predicate = self.within(predicate)?;
+        self.table
+            .delete_returning(&predicate, projection, max_rows)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_array::{Int32Array, RecordBatch, StringArray};
+    use lance::arrow::RecordBatchBuffer;
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::arrow::RecordBatchBuilder;
+
+    fn rows(tenants: &[Option<&str>], ids: std::ops::Range<i32>) -> Box<dyn RecordBatchReader> {
+        let vectors = ids.clone().map(|i| [i as f32, 1.0]).collect::<Vec<_>>();
+        let tenants = ids.clone().map(|i| tenants[i as usize % tenants.len()]);
+        let batch = RecordBatchBuilder::new()
+            .column("id", Arc::new(Int32Array::from_iter_values(ids)))
+            .column("tenant", Arc::new(StringArray::from_iter(tenants)))
+            .vector_column("vector", 2, vectors)
+            .build()
+            .unwrap();
+        Box::new(RecordBatchBuffer::new(vec![batch]))
+    }
+
+    /// The tenants and ids of the rows of `batches`, sorted.
+    fn tenant_ids(batches: &[RecordBatch]) -> Vec<(String, i32)> {
+        let mut rows = batches
+            .iter()
+            .flat_map(|b| {
+                let ids = b["id"].as_any().downcast_ref::<Int32Array>().unwrap();
+                let tenants = b["tenant"].as_any().downcast_ref::<StringArray>().unwrap();
+                (0..b.num_rows())
+                    .map(|row| (tenants.value(row).to_string(), ids.value(row)))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        rows.sort();
+        rows
+    }
+
+    async fn search(query: Query) -> Result<Vec<(String, i32)>> {
+        let batches = query
+            .limit(100)
+            .execute()
+            .await?
+            .try_collect::<Vec<_>>()
+            .await?;
+        Ok(tenant_ids(&batches))
+    }
+
+    #[tokio::test]
+    async fn test_scoped_table() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        let table = NativeTable::create(uri, "test", rows(&[Some("a"), Some("b")], 0..20), None)
+            .await
+            .unwrap();
+        let scoped = table.scoped("tenant = 'a'");
+        assert_eq!(scoped.count_rows().await.unwrap(), 10);
+
+        // The filters of the searches only narrow the rows of the scope.
+        let all = search(scoped.search(vec![0.0, 1.0])).await.unwrap();
+        assert_eq!(all.len(), 10);
+        assert!(all.iter().all(|(tenant, _)| tenant == "a"));
+        for (filter, expected) in [
+            ("tenant = 'b'", vec![]),
+            ("tenant = 'b' OR id >= 0", all.clone()),
+            ("NOT (tenant = 'a')", vec![]),
+        ] {
+            let query = scoped
+                .search(vec![0.0, 1.0])
+                .filter(Some(filter.to_string()));
+            assert_eq!(search(query).await.unwrap(), expected, "{filter}");
+        }
+        let mut query = scoped.search(vec![0.0, 1.0]);
+        query.filter = Some("id < 4".to_string());
+        let expected = vec![("a".to_string(), 0), ("a".to_string(), 2)];
+        assert_eq!(search(query.clone()).await.unwrap(), expected);
+        let (batches, _) = query.execute_with_metrics().await.unwrap();
+        assert_eq!(tenant_ids(&batches), expected);
+        let query = scoped
+            .search(vec![0.0, 1.0])
+            .filter(Some("1 = 1) OR (1 = 1".to_string()));
+        let err = search(query).await.unwrap_err();
+        assert!(matches!(err, Error::InvalidInput { .. }), "{err}");
+
+        // Scopes nest.
+        let nested = scoped.scoped("id < 6");
+        assert_eq!(nested.filter().unwrap(), "(tenant = 'a') AND (id < 6)");
+        assert_eq!(nested.count_rows().await.unwrap(), 3);
+        let query = nested
+            .search(vec![0.0, 1.0])
+            .filter(Some("id >= 2".to_string()));
+        let expected = vec![("a".to_string(), 2), ("a".to_string(), 4)];
+        assert_eq!(search(query).await.unwrap(), expected);
+
+        // The rows added through a scope have to match it.
+        for tenants in [[Some("b")], [None]] {
+            let err = scoped.add(rows(&tenants, 20..22), None).await.unwrap_err();
+            assert!(matches!(err, Error::InvalidInput { .. }), "{err}");
+        }
+        let err = scoped
+            .add(rows(&[Some("a")], 20..22), Some(WriteMode::Overwrite))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::InvalidInput { .. }), "{err}");
+        assert_eq!(table.count_rows().await.unwrap(), 20);
+        let added = scoped.add(rows(&[Some("a")], 20..22), None).await.unwrap();
+        assert_eq!(added, 2);
+        assert_eq!(scoped.count_rows().await.unwrap(), 12);
+
+        // Deletes leave the rows outside the scope.
+        scoped.delete("tenant = 'b' OR id >= 0").await.unwrap();
+        assert_eq!(scoped.count_rows().await.unwrap(), 0);
+        assert_eq!(table.count_rows().await.unwrap(), 10);
+        let report = table
+            .scoped("tenant = 'b'")
+            .delete_returning("id < 5", None, None)
+            .await
+            .unwrap();
+        assert_eq!(report.rows, 2);
+        assert_eq!(table.count_rows().await.unwrap(), 8);
+        let err = scoped.delete("1 = 1) OR (1 = 1").await.unwrap_err();
+        assert!(matches!(err, Error::InvalidInput { .. }), "{err}");
+    }
+}
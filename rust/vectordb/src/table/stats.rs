@@ -0,0 +1,937 @@
+// Copyright 2023 LanceDB Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Table and column statistics, the filters they prove to match no row, the
+//! fraction of the rows they estimate a filter to match, and the fragments
+//! with rows matching a filter.
+
+use std::cmp::Ordering;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex};
+
+use arrow_array::{Array, RecordBatch, UInt64Array};
+use arrow_schema::{DataType, Schema as ArrowSchema, SortOptions};
+use datafusion::arrow::row::{RowConverter, SortField};
+use datafusion::logical_expr::Accumulator;
+use datafusion::physical_expr::expressions::{MaxAccumulator, MinAccumulator};
+use datafusion::scalar::ScalarValue;
+use datafusion::sql::sqlparser::ast::{BinaryOperator, Expr, UnaryOperator, Value};
+use datafusion::sql::sqlparser::dialect::GenericDialect;
+use datafusion::sql::sqlparser::parser::Parser;
+use futures::TryStreamExt;
+use lance::dataset::{Dataset, ROW_ID};
+use lance::format::Fragment;
+
+use super::{DatasetRef, NativeTable};
+use crate::error::{Error, Result};
+use crate::io::bad_vectors::{mark_null_vectors, marker_column_of};
+use crate::query::JsonFilter;
+
+/// The number of hashes kept to estimate the distinct values of a column.
+/// Columns with fewer distinct values are counted exactly.
+const DISTINCT_SKETCH_SIZE: usize = 1024;
+
+/// The number of rows scanned by [NativeTable::estimate_selectivity] for the
+/// filters the column statistics do not estimate.
+pub const DEFAULT_SELECTIVITY_SAMPLE: usize = 1024;
+
+/// Statistics of a column of a version of a table, see
+/// [crate::NativeTable::column_stats].
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColumnStats {
+    /// The rows of the table.
+    pub rows: usize,
+    /// The rows where the column is null. For vector columns, the rows whose
+    /// vector is null, which searches leave out: the other rows are the
+    /// searchable ones.
+    pub null_count: usize,
+    /// The smallest value of a column of numbers, strings or booleans.
+    pub min: Option<ScalarValue>,
+    /// The largest value of a column of numbers, strings or booleans.
+    pub max: Option<ScalarValue>,
+    /// An estimate of the distinct values other than null, exact up to 1024
+    /// values. `None` for the types that cannot be compared, such as vectors.
+    pub distinct_estimate: Option<usize>,
+}
+
+/// Statistics of the current version of a table, see
+/// [crate::NativeTable::stats].
+#[derive(Debug, Clone, PartialEq)]
+pub struct TableStats {
+    pub version: u64,
+    /// The rows of the table, without the deleted ones.
+    pub rows: usize,
+    pub fragments: usize,
+    /// The data files of the fragments, without their deletion files.
+    pub data_files: usize,
+    /// The size of the data files as stored, without the manifests, indices
+    /// and deletion files. `None` for a dataset searched without its table.
+    pub bytes_on_disk: Option<u64>,
+}
+
+/// The filters whose matching fragments are kept for a version of a table,
+/// the cache is emptied when it holds more.
+const MAX_CACHED_FILTERS: usize = 256;
+
+/// The ids of the fragments with rows matching a filter.
+type FragmentIds = Arc<HashSet<u64>>;
+
+/// The statistics computed for the columns of a version of a table, and the
+/// fragments found to have rows matching filters.
+#[derive(Debug, Default)]
+pub(crate) struct StatsCache {
+    columns: Mutex<(u64, HashMap<String, ColumnStats>)>,
+    fragments: Mutex<(u64, HashMap<String, FragmentIds>)>,
+}
+
+impl StatsCache {
+    fn get(&self, version: u64, column: &str) -> Option<ColumnStats> {
+        let columns = self.columns.lock().unwrap();
+        (columns.0 == version)
+            .then(|| columns.1.get(column).cloned())
+            .flatten()
+    }
+
+    fn insert(&self, version: u64, column: &str, stats: ColumnStats) {
+        let mut columns = self.columns.lock().unwrap();
+        if columns.0 != version {
+            *columns = (version, HashMap::new());
+        }
+        columns.1.insert(column.to_string(), stats);
+    }
+
+    fn get_fragments(&self, version: u64, filter: &str) -> Option<FragmentIds> {
+        let fragments = self.fragments.lock().unwrap();
+        (fragments.0 == version)
+            .then(|| fragments.1.get(filter).cloned())
+            .flatten()
+    }
+
+    fn insert_fragments(&self, version: u64, filter: &str, ids: FragmentIds) {
+        let mut fragments = self.fragments.lock().unwrap();
+        if fragments.0 != version || fragments.1.len() >= MAX_CACHED_FILTERS {
+            *fragments = (version, HashMap::new());
+        }
+        fragments.1.insert(filter.to_string(), ids);
+    }
+}
+
+impl DatasetRef {
+    /// The [TableStats] of `dataset`, reading the size of each of its data
+    /// files from the store.
+    pub(crate) async fn table_stats(&self, dataset: &Dataset) -> Result<TableStats> {
+        let fragments = dataset.get_fragments();
+        let mut rows = 0;
+        for fragment in fragments.iter() {
+            rows += fragment.count_rows().await?;
+        }
+        let files = fragments
+            .iter()
+            .flat_map(|f| f.metadata().files.iter())
+            .collect::<Vec<_>>();
+        let bytes_on_disk = if self.is_detached() {
+            None
+        } else {
+            let (store, base) = self.object_store().await?;
+            let mut bytes = 0;
+            for file in &files {
+                let path = base.child("data").child(file.path.as_str());
+                bytes += store.inner.head(&path).await?.size as u64;
+            }
+            Some(bytes)
+        };
+        Ok(TableStats {
+            version: dataset.version().version,
+            rows,
+            fragments: fragments.len(),
+            data_files: files.len(),
+            bytes_on_disk,
+        })
+    }
+
+    /// The statistics of `column` in `dataset`, computed on first use for each
+    /// version of the table.
+    pub(crate) async fn column_stats(
+        &self,
+        dataset: &Dataset,
+        column: &str,
+    ) -> Result<ColumnStats> {
+        let version = dataset.version().version;
+        if let Some(stats) = self.stats().get(version, column) {
+            return Ok(stats);
+        }
+        let stats = compute(dataset, column).await?;
+        self.stats().insert(version, column, stats.clone());
+        Ok(stats)
+    }
+
+    /// Whether the statistics of `dataset` prove that no row matches `filter`.
+    ///
+    /// Only comparisons of a column with a literal, `BETWEEN`, `IS NULL` and
+    /// `IS NOT NULL`, combined with `AND` and `OR`, are checked; any other
+    /// filter may match.
+    pub(crate) async fn filter_is_empty(&self, dataset: &Dataset, filter: &str) -> Result<bool> {
+        let Some(expr) = parse(filter) else {
+            // lance reports the errors of the filter when it plans it.
+            return Ok(false);
+        };
+        let stats = self.compared_stats(dataset, &expr).await?;
+        Ok(is_empty(&expr, &stats))
+    }
+
+    /// An estimate of the fraction of the rows of `dataset` matching `filter`.
+    ///
+    /// The comparisons checked by [Self::filter_is_empty], `!=` and `NOT` are
+    /// estimated from the column statistics, assuming the values are evenly
+    /// spread between the smallest and the largest one. Other filters are
+    /// evaluated on about `sample_size` rows, the first rows of each fragment.
+    pub(crate) async fn estimate_selectivity(
+        &self,
+        dataset: &Dataset,
+        filter: &str,
+        sample_size: usize,
+    ) -> Result<f64> {
+        if sample_size == 0 {
+            return Err(Error::InvalidInput {
+                message: "the selectivity of a filter needs a sample of at least 1 row".to_string(),
+            });
+        }
+        if let Some(expr) = parse(filter) {
+            let stats = self.compared_stats(dataset, &expr).await?;
+            if let Some(selectivity) = estimate(&expr, &stats) {
+                return Ok(selectivity.clamp(0.0, 1.0));
+            }
+        }
+        let schema = ArrowSchema::from(dataset.schema());
+        let planned = JsonFilter::plan_any(filter, &schema)?;
+        let batches = sample(dataset, &planned.columns, sample_size).await?;
+        let rows = batches.iter().map(|b| b.num_rows()).sum::<usize>();
+        if rows == 0 {
+            return Ok(0.0);
+        }
+        let matched = planned.apply(batches).await?;
+        Ok(matched.iter().map(|b| b.num_rows()).sum::<usize>() as f64 / rows as f64)
+    }
+
+    /// The statistics of the columns of the comparisons of `expr`.
+    async fn compared_stats(
+        &self,
+        dataset: &Dataset,
+        expr: &Expr,
+    ) -> Result<HashMap<String, ColumnStats>> {
+        let schema = ArrowSchema::from(dataset.schema());
+        let mut columns = Vec::new();
+        compared_columns(expr, &mut columns);
+        let mut stats = HashMap::new();
+        for column in columns {
+            if schema.field_with_name(&column).is_ok() && !stats.contains_key(&column) {
+                let column_stats = self.column_stats(dataset, &column).await?;
+                stats.insert(column, column_stats);
+            }
+        }
+        Ok(stats)
+    }
+
+    /// The fragments of `dataset` with rows matching `filter`, a lance filter,
+    /// and the number of the others. `None` if every fragment has such rows,
+    /// or the filter cannot be evaluated on its own.
+    ///
+    /// The filter is evaluated on its own columns, with the ids of the rows,
+    /// whose upper 32 bits are the id of the fragment of the row. The ids of
+    /// the fragments found are kept for the version of the table.
+    pub(crate) async fn matching_fragments(
+        &self,
+        dataset: &Dataset,
+        filter: &str,
+    ) -> Result<Option<(Vec<Fragment>, usize)>> {
+        let version = dataset.version().version;
+        let ids = match self.stats().get_fragments(version, filter) {
+            Some(ids) => ids,
+            None => {
+                // lance reports the errors of the filter when it plans the
+                // search, and fails to scan a filter of no column.
+                let Ok(ids) = matching_fragment_ids(dataset, filter).await else {
+                    return Ok(None);
+                };
+                let ids = Arc::new(ids);
+                self.stats().insert_fragments(version, filter, ids.clone());
+                ids
+            }
+        };
+        let fragments = dataset.get_fragments();
+        if ids.len() == fragments.len() {
+            return Ok(None);
+        }
+        let matching = fragments
+            .iter()
+            .filter(|f| ids.contains(&(f.id() as u64)))
+            .map(|f| f.metadata().clone())
+            .collect::<Vec<_>>();
+        let skipped = fragments.len() - matching.len();
+        Ok(Some((matching, skipped)))
+    }
+}
+
+/// The ids of the fragments of `dataset` with rows matching `filter`.
+async fn matching_fragment_ids(dataset: &Dataset, filter: &str) -> Result<HashSet<u64>> {
+    let mut scanner = dataset.scan();
+    scanner.project::<&str>(&[])?;
+    scanner.filter(filter)?;
+    scanner.with_row_id();
+    let mut ids = HashSet::new();
+    let mut stream = scanner.try_into_stream().await?;
+    while let Some(batch) = stream.try_next().await? {
+        let row_ids = batch[ROW_ID].as_any().downcast_ref::<UInt64Array>();
+        let row_ids = row_ids.expect("row ids are u64");
+        ids.extend(row_ids.values().iter().map(|row_id| row_id >> 32));
+    }
+    Ok(ids)
+}
+
+impl NativeTable {
+    /// An estimate of the fraction of the rows of this table matching
+    /// `filter`, a SQL WHERE clause, between 0 and 1.
+    ///
+    /// Comparisons of columns with literals are estimated from the
+    /// [Self::column_stats] of the columns, lance has no scalar index to
+    /// estimate them from. Other filters are evaluated on
+    /// [DEFAULT_SELECTIVITY_SAMPLE] rows, see
+    /// [Self::estimate_selectivity_with_sample]. Queries with
+    /// [crate::query::FilterMode::Auto] choose how to filter by this estimate.
+    pub async fn estimate_selectivity(&self, filter: &str) -> Result<f64> {
+        self.estimate_selectivity_with_sample(filter, DEFAULT_SELECTIVITY_SAMPLE)
+            .await
+    }
+
+    /// Like [Self::estimate_selectivity], evaluating the filters the column
+    /// statistics do not estimate on about `sample_size` rows.
+    pub async fn estimate_selectivity_with_sample(
+        &self,
+        filter: &str,
+        sample_size: usize,
+    ) -> Result<f64> {
+        let dataset = self.dataset.get().await?;
+        self.dataset
+            .estimate_selectivity(&dataset, filter, sample_size)
+            .await
+    }
+}
+
+fn parse(filter: &str) -> Option<Expr> {
+    Parser::new(&GenericDialect {})
+        .try_with_sql(filter)
+        .and_then(|mut parser| parser.parse_expr())
+        .ok()
+}
+
+/// `columns` of about `sample_size` rows of `dataset`, the first rows of each
+/// fragment in proportion to its rows.
+async fn sample(
+    dataset: &Dataset,
+    columns: &[String],
+    sample_size: usize,
+) -> Result<Vec<RecordBatch>> {
+    let mut columns = columns.to_vec();
+    if columns.is_empty() {
+        // A constant filter, evaluated on rows of any column.
+        columns.extend(dataset.schema().fields.first().map(|f| f.name.clone()));
+    }
+    let fragments = dataset.get_fragments();
+    let mut counts = Vec::with_capacity(fragments.len());
+    for fragment in fragments.iter() {
+        counts.push(fragment.count_rows().await?);
+    }
+    let total = counts.iter().sum::<usize>();
+    let mut batches = Vec::new();
+    for (fragment, rows) in fragments.iter().zip(counts) {
+        let sampled = if total <= sample_size {
+            rows
+        } else {
+            (rows * sample_size).div_ceil(total)
+        };
+        if sampled == 0 {
+            continue;
+        }
+        let mut scanner = fragment.scan();
+        scanner.project(&columns)?;
+        scanner.limit(Some(sampled as i64), None)?;
+        batches.extend(
+            scanner
+                .try_into_stream()
+                .await?
+                .try_collect::<Vec<_>>()
+                .await?,
+        );
+    }
+    Ok(batches)
+}
+
+/// Scan `column` of `dataset` for its statistics.
+async fn compute(dataset: &Dataset, column: &str) -> Result<ColumnStats> {
+    let schema = ArrowSchema::from(dataset.schema());
+    let field = schema
+        .field_with_name(column)
+        .map_err(|_| Error::InvalidInput {
+            message: format!("the column '{column}' is not in the table"),
+        })?;
+    let ordered = field.data_type().is_primitive()
+        || matches!(
+            field.data_type(),
+            DataType::Boolean | DataType::Utf8 | DataType::LargeUtf8
+        );
+    // The types datafusion does not aggregate have no min and max.
+    let mut min_max = ordered
+        .then(|| {
+            let min = MinAccumulator::try_new(field.data_type()).ok()?;
+            let max = MaxAccumulator::try_new(field.data_type()).ok()?;
+            Some((min, max))
+        })
+        .flatten();
+    let sort_field = SortField::new_with_options(field.data_type().clone(), SortOptions::default());
+    let mut converter = RowConverter::new(vec![sort_field]).ok();
+    let mut sketch = BTreeSet::new();
+
+    let mut stats = ColumnStats {
+        rows: 0,
+        null_count: 0,
+        min: None,
+        max: None,
+        distinct_estimate: None,
+    };
+    let mut columns = vec![column.to_string()];
+    columns.extend(marker_column_of(&schema, column));
+    let mut scanner = dataset.scan();
+    scanner.project(&columns)?;
+    let mut batches = scanner.try_into_stream().await?;
+    while let Some(batch) = batches.try_next().await? {
+        let batch = mark_null_vectors(&batch, column, false).map_err(lance::Error::from)?;
+        let array = batch.column(0);
+        stats.rows += array.len();
+        stats.null_count += array.null_count();
+        if let Some((min, max)) = min_max.as_mut() {
+            let arrays = std::slice::from_ref(array);
+            if min.update_batch(arrays).is_err() || max.update_batch(arrays).is_err() {
+                min_max = None;
+            }
+        }
+        let Some(rows) = converter
+            .as_mut()
+            .and_then(|c| c.convert_columns(std::slice::from_ref(array)).ok())
+        else {
+            converter = None;
+            continue;
+        };
+        for (i, row) in rows.iter().enumerate() {
+            if array.is_null(i) {
+                continue;
+            }
+            let mut hasher = DefaultHasher::new();
+            row.as_ref().hash(&mut hasher);
+            sketch.insert(hasher.finish());
+            if sketch.len() > DISTINCT_SKETCH_SIZE {
+                sketch.pop_last();
+            }
+        }
+    }
+    if let Some((min, max)) = min_max.as_ref() {
+        stats.min = min.evaluate().ok().filter(|v| !v.is_null());
+        stats.max = max.evaluate().ok().filter(|v| !v.is_null());
+    }
+    if converter.is_some() {
+        stats.distinct_estimate = Some(estimate_distinct(&sketch));
+    }
+    Ok(stats)
+}
+
+/// The distinct values estimated from the smallest hashes of the values, the
+/// k minimum values sketch.
+fn estimate_distinct(sketch: &BTreeSet<u64>) -> usize {
+    match sketch.last() {
+        Some(&largest) if sketch.len() == DISTINCT_SKETCH_SIZE => {
+            let fraction = (largest as f64 + 1.0) / (u64::MAX as f64 + 1.0);
+            ((DISTINCT_SKETCH_SIZE - 1) as f64 / fraction).round() as usize
+        }
+        _ => sketch.len(),
+    }
+}
+
+/// Add the columns of the comparisons of `expr` to `columns`.
+fn compared_columns(expr: &Expr, columns: &mut Vec<String>) {
+    match expr {
+        Expr::BinaryOp { left, op, right } => match op {
+            BinaryOperator::And | BinaryOperator::Or => {
+                compared_columns(left, columns);
+                compared_columns(right, columns);
+            }
+            _ => columns.extend(column(left).or(column(right))),
+        },
+        Expr::Nested(expr)
+        | Expr::IsNull(expr)
+        | Expr::IsNotNull(expr)
+        | Expr::UnaryOp {
+            op: UnaryOperator::Not,
+            expr,
+        } => compared_columns(expr, columns),
+        Expr::Between { expr, .. } => columns.extend(column(expr)),
+        _ => {}
+    }
+}
+
+fn column(expr: &Expr) -> Option<String> {
+    match expr {
+        Expr::Identifier(ident) => Some(ident.value.clone()),
+        Expr::Nested(expr) => column(expr),
+        _ => None,
+    }
+}
+
+/// A literal number or string, as the value it is compared with.
+fn literal(expr: &Expr) -> Option<ScalarValue> {
+    match expr {
+        Expr::Value(Value::Number(number, _)) => {
+            number.parse().ok().map(|n| ScalarValue::Float64(Some(n)))
+        }
+        Expr::Value(Value::SingleQuotedString(s)) => Some(ScalarValue::Utf8(Some(s.clone()))),
+        Expr::UnaryOp {
+            op: UnaryOperator::Minus,
+            expr,
+        } => match literal(expr)? {
+            ScalarValue::Float64(Some(number)) => Some(ScalarValue::Float64(Some(-number))),
+            _ => None,
+        },
+        Expr::Nested(expr) => literal(expr),
+        _ => None,
+    }
+}
+
+/// A value of a column of numbers, as a float.
+fn number(value: &ScalarValue) -> Option<f64> {
+    Some(match value {
+        ScalarValue::Int8(Some(v)) => *v as f64,
+        ScalarValue::Int16(Some(v)) => *v as f64,
+        ScalarValue::Int32(Some(v)) => *v as f64,
+        ScalarValue::Int64(Some(v)) => *v as f64,
+        ScalarValue::UInt8(Some(v)) => *v as f64,
+        ScalarValue::UInt16(Some(v)) => *v as f64,
+        ScalarValue::UInt32(Some(v)) => *v as f64,
+        ScalarValue::UInt64(Some(v)) => *v as f64,
+        ScalarValue::Float32(Some(v)) => *v as f64,
+        ScalarValue::Float64(Some(v)) => *v,
+        _ => return None,
+    })
+}
+
+/// Compare a value of a column with a literal, `None` if they do not compare.
+fn compare(value: &ScalarValue, literal: &ScalarValue) -> Option<Ordering> {
+    match literal {
+        ScalarValue::Utf8(Some(literal)) => match value {
+            ScalarValue::Utf8(Some(value)) | ScalarValue::LargeUtf8(Some(value)) => {
+                Some(value.as_str().cmp(literal.as_str()))
+            }
+            _ => None,
+        },
+        ScalarValue::Float64(Some(literal)) => number(value)?.partial_cmp(literal),
+        _ => None,
+    }
+}
+
+/// Whether no row with a value of the column of `stats` matches `column op value`.
+fn comparison_is_empty(stats: &ColumnStats, op: &BinaryOperator, value: &ScalarValue) -> bool {
+    if stats.null_count == stats.rows {
+        // Comparisons with null are never true.
+        return true;
+    }
+    let (Some(min), Some(max)) = (stats.min.as_ref(), stats.max.as_ref()) else {
+        return false;
+    };
+    let (Some(min), Some(max)) = (compare(min, value), compare(max, value)) else {
+        return false;
+    };
+    match op {
+        BinaryOperator::Gt => max.is_le(),
+        BinaryOperator::GtEq => max.is_lt(),
+        BinaryOperator::Lt => min.is_ge(),
+        BinaryOperator::LtEq => min.is_gt(),
+        BinaryOperator::Eq => min.is_gt() || max.is_lt(),
+        _ => false,
+    }
+}
+
+/// `op` with its operands swapped, `a op b` being `b flipped a`.
+fn flipped(op: &BinaryOperator) -> BinaryOperator {
+    match op {
+        BinaryOperator::Gt => BinaryOperator::Lt,
+        BinaryOperator::GtEq => BinaryOperator::LtEq,
+        BinaryOperator::Lt => BinaryOperator::Gt,
+        BinaryOperator::LtEq => BinaryOperator::GtEq,
+        op => op.clone(),
+    }
+}
+
+/// Whether the columns of `stats` prove that no row matches `expr`.
+fn is_empty(expr: &Expr, stats: &HashMap<String, ColumnStats>) -> bool {
+    let stats_of = |expr: &Expr| column(expr).and_then(|c| stats.get(&c));
+    match expr {
+        Expr::Nested(expr) => is_empty(expr, stats),
+        Expr::BinaryOp {
+            left,
+            op: BinaryOperator::And,
+            right,
+        } => is_empty(left, stats) || is_empty(right, stats),
+        Expr::BinaryOp {
+            left,
+            op: BinaryOperator::Or,
+            right,
+        } => is_empty(left, stats) && is_empty(right, stats),
+        Expr::BinaryOp { left, op, right } => {
+            match (
+                stats_of(left),
+                literal(right),
+                stats_of(right),
+                literal(left),
+            ) {
+                (Some(stats), Some(value), _, _) => comparison_is_empty(stats, op, &value),
+                (_, _, Some(stats), Some(value)) => {
+                    comparison_is_empty(stats, &flipped(op), &value)
+                }
+                _ => false,
+            }
+        }
+        Expr::Between {
+            expr,
+            negated: false,
+            low,
+            high,
+        } => match (stats_of(expr), literal(low), literal(high)) {
+            (Some(stats), Some(low), Some(high)) => {
+                comparison_is_empty(stats, &BinaryOperator::GtEq, &low)
+                    || comparison_is_empty(stats, &BinaryOperator::LtEq, &high)
+            }
+            _ => false,
+        },
+        Expr::IsNull(expr) => stats_of(expr).is_some_and(|s| s.null_count == 0 && s.rows > 0),
+        Expr::IsNotNull(expr) => stats_of(expr).is_some_and(|s| s.null_count == s.rows),
+        _ => false,
+    }
+}
+
+/// The fraction of the rows with a value of the column of `stats` between the
+/// numbers `low` and `high`, `None` if the column is not of numbers.
+fn range_fraction(stats: &ColumnStats, low: f64, high: f64) -> Option<f64> {
+    let min = number(stats.min.as_ref()?)?;
+    let max = number(stats.max.as_ref()?)?;
+    if max <= min {
+        return Some(if low <= min && min <= high { 1.0 } else { 0.0 });
+    }
+    Some(((high.min(max) - low.max(min)) / (max - min)).clamp(0.0, 1.0))
+}
+
+/// An estimate of the fraction of the rows of the column of `stats` matching
+/// `column op value`, `None` if the statistics do not tell.
+fn comparison_selectivity(
+    stats: &ColumnStats,
+    op: &BinaryOperator,
+    value: &ScalarValue,
+) -> Option<f64> {
+    if stats.rows == 0 || comparison_is_empty(stats, op, value) {
+        return Some(0.0);
+    }
+    let non_null = (stats.rows - stats.null_count) as f64 / stats.rows as f64;
+    let equal = || Some(1.0 / stats.distinct_estimate?.max(1) as f64);
+    let fraction = match op {
+        BinaryOperator::Eq => equal()?,
+        BinaryOperator::NotEq => 1.0 - equal()?,
+        BinaryOperator::Lt | BinaryOperator::LtEq => {
+            range_fraction(stats, f64::NEG_INFINITY, number(value)?)?
+        }
+        BinaryOperator::Gt | BinaryOperator::GtEq => {
+            range_fraction(stats, number(value)?, f64::INFINITY)?
+        }
+        _ => return None,
+    };
+    Some(non_null * fraction)
+}
+
+/// An estimate of the fraction of the rows matching `expr` by the columns of
+/// `stats`, `None` for the filters they do not estimate.
+fn estimate(expr: &Expr, stats: &HashMap<String, ColumnStats>) -> Option<f64> {
+    if is_empty(expr, stats) {
+        return Some(0.0);
+    }
+    let stats_of = |expr: &Expr| column(expr).and_then(|c| stats.get(&c));
+    match expr {
+        Expr::Nested(expr) => estimate(expr, stats),
+        Expr::BinaryOp {
+            left,
+            op: BinaryOperator::And,
+            right,
+        } => Some(estimate(left, stats)? * estimate(right, stats)?),
+        Expr::BinaryOp {
+            left,
+            op: BinaryOperator::Or,
+            right,
+        } => {
+            let (left, right) = (estimate(left, stats)?, estimate(right, stats)?);
+            Some(left + right - left * right)
+        }
+        Expr::UnaryOp {
+            op: UnaryOperator::Not,
+            expr,
+        } => Some(1.0 - estimate(expr, stats)?),
+        Expr::BinaryOp { left, op, right } => match (
+            stats_of(left),
+            literal(right),
+            stats_of(right),
+            literal(left),
+        ) {
+            (Some(stats), Some(value), _, _) => comparison_selectivity(stats, op, &value),
+            (_, _, Some(stats), Some(value)) => comparison_selectivity(stats, &flipped(op), &value),
+            _ => None,
+        },
+        Expr::Between {
+            expr,
+            negated,
+            low,
+            high,
+        } => {
+            let stats = stats_of(expr)?;
+            let (low, high) = (number(&literal(low)?)?, number(&literal(high)?)?);
+            let non_null = (stats.rows - stats.null_count) as f64 / stats.rows as f64;
+            let between = non_null * range_fraction(stats, low, high)?;
+            Some(if *negated {
+                non_null - between
+            } else {
+                between
+            })
+        }
+        Expr::IsNull(expr) => {
+            let stats = stats_of(expr).filter(|s| s.rows > 0)?;
+            Some(stats.null_count as f64 / stats.rows as f64)
+        }
+        Expr::IsNotNull(expr) => {
+            let stats = stats_of(expr).filter(|s| s.rows > 0)?;
+            Some(1.0 - stats.null_count as f64 / stats.rows as f64)
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_array::{Int32Array, RecordBatch, RecordBatchReader, StringArray};
+    use arrow_schema::{Field, Schema};
+    use lance::arrow::RecordBatchBuffer;
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_column_stats() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().join("test.lance");
+        let uri = uri.to_str().unwrap();
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("i", DataType::Int32, false),
+            Field::new("s", DataType::Utf8, true),
+        ]));
+        // lance keeps the nulls of strings, not those of numbers.
+        let ints = (0..5000).map(|i| i % 3000 + 1);
+        let strings = (0..5000).map(|i| (i < 10).then(|| format!("s{i}")));
+        let batch = RecordBatch::try_new(
+            schema,
+            vec![
+                Arc::new(Int32Array::from_iter_values(ints)),
+                Arc::new(StringArray::from_iter(strings)),
+            ],
+        )
+        .unwrap();
+        let mut reader: Box<dyn RecordBatchReader> = Box::new(RecordBatchBuffer::new(vec![batch]));
+        let dataset = Dataset::write(&mut reader, uri, None).await.unwrap();
+
+        let i = compute(&dataset, "i").await.unwrap();
+        assert_eq!(i.rows, 5000);
+        assert_eq!(i.null_count, 0);
+        assert_eq!(i.min, Some(ScalarValue::Int32(Some(1))));
+        assert_eq!(i.max, Some(ScalarValue::Int32(Some(3000))));
+        // 3000 distinct values, estimated.
+        let distinct = i.distinct_estimate.unwrap() as f64;
+        assert!((distinct - 3000.0).abs() / 3000.0 < 0.2, "{distinct}");
+
+        let s = compute(&dataset, "s").await.unwrap();
+        assert_eq!(s.null_count, 4990);
+        assert_eq!(s.min, Some(ScalarValue::Utf8(Some("s0".to_string()))));
+        assert_eq!(s.distinct_estimate, Some(10));
+
+        assert!(compute(&dataset, "nope").await.is_err());
+    }
+
+    #[test]
+    fn test_filter_is_empty() {
+        let stats = HashMap::from([
+            (
+                "x".to_string(),
+                ColumnStats {
+                    rows: 10,
+                    null_count: 2,
+                    min: Some(ScalarValue::Int64(Some(0))),
+                    max: Some(ScalarValue::Int64(Some(50))),
+                    distinct_estimate: Some(8),
+                },
+            ),
+            (
+                "n".to_string(),
+                ColumnStats {
+                    rows: 10,
+                    null_count: 10,
+                    min: None,
+                    max: None,
+                    distinct_estimate: Some(0),
+                },
+            ),
+        ]);
+        let empty = |filter: &str| {
+            let expr = Parser::new(&GenericDialect {})
+                .try_with_sql(filter)
+                .unwrap()
+                .parse_expr()
+                .unwrap();
+            is_empty(&expr, &stats)
+        };
+        assert!(empty("x > 100"));
+        assert!(empty("x >= 51"));
+        assert!(!empty("x >= 50"));
+        assert!(empty("100 < x"));
+        assert!(empty("x < -1"));
+        assert!(empty("x = 60 OR x < 0"));
+        assert!(!empty("x = 60 OR x = 10"));
+        assert!(empty("x < 10 AND (n = 3)"));
+        assert!(empty("x BETWEEN 51 AND 60"));
+        assert!(!empty("x BETWEEN 40 AND 60"));
+        assert!(empty("n IS NOT NULL"));
+        assert!(!empty("x IS NULL"));
+        assert!(!empty("x != 100"));
+        assert!(!empty("y > 100"));
+        assert!(!empty("x > '100'"));
+    }
+
+    #[test]
+    fn test_estimate() {
+        let stats = HashMap::from([(
+            "x".to_string(),
+            ColumnStats {
+                rows: 100,
+                null_count: 20,
+                min: Some(ScalarValue::Int64(Some(0))),
+                max: Some(ScalarValue::Int64(Some(100))),
+                distinct_estimate: Some(40),
+            },
+        )]);
+        let estimated = |filter: &str| estimate(&parse(filter).unwrap(), &stats);
+        let close = |filter: &str, expected: f64| {
+            let got = estimated(filter).unwrap();
+            assert!((got - expected).abs() < 1e-9, "{filter}: {got}");
+        };
+        close("x < 25", 0.8 * 0.25);
+        close("75 < x", 0.8 * 0.25);
+        close("x = 3", 0.8 / 40.0);
+        close("x != 3", 0.8 * 39.0 / 40.0);
+        close("x BETWEEN 10 AND 60", 0.8 * 0.5);
+        close("x NOT BETWEEN 10 AND 60", 0.8 * 0.5);
+        close("x IS NULL", 0.2);
+        close("NOT (x IS NULL)", 0.8);
+        close("x < 25 AND x > 75", 0.04);
+        close("x < 25 OR x > 75", 0.2 + 0.2 - 0.04);
+        close("x > 200", 0.0);
+        assert_eq!(estimated("x % 2 = 0"), None);
+        assert_eq!(estimated("y < 3"), None);
+    }
+
+    #[tokio::test]
+    async fn test_auto_filter_mode() {
+        use crate::arrow::RecordBatchBuilder;
+        use crate::query::{FilterMode, Query};
+
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        let rows = |ids: std::ops::Range<i32>| -> Box<dyn RecordBatchReader> {
+            let vectors = ids.clone().map(|i| [i as f32, 0.0]).collect::<Vec<_>>();
+            let batch = RecordBatchBuilder::new()
+                .column("id", Arc::new(Int32Array::from_iter_values(ids)))
+                .vector_column("vector", 2, vectors)
+                .build()
+                .unwrap();
+            Box::new(RecordBatchBuffer::new(vec![batch]))
+        };
+        let table = NativeTable::create(uri, "test", rows(0..500), None)
+            .await
+            .unwrap();
+        table.add(rows(500..1000), None).await.unwrap();
+
+        let selectivity = table.estimate_selectivity("id >= 910").await.unwrap();
+        assert!((selectivity - 89.0 / 999.0).abs() < 1e-9, "{selectivity}");
+        // Not estimated by the statistics, the sample is the whole table.
+        let sampled = table.estimate_selectivity("id % 20 = 0").await.unwrap();
+        assert_eq!(sampled, 0.05);
+        let sampled = table
+            .estimate_selectivity_with_sample("id % 20 = 0", 100)
+            .await
+            .unwrap();
+        assert!(sampled > 0.0 && sampled < 0.2, "{sampled}");
+        assert!(table.estimate_selectivity("id >").await.is_err());
+
+        let search = |query: Query| async move {
+            let (batches, metrics) = query.limit(5).execute_with_metrics().await.unwrap();
+            let ids = batches
+                .iter()
+                .flat_map(|b| {
+                    let ids = b["id"].as_any().downcast_ref::<Int32Array>().unwrap();
+                    ids.values().to_vec()
+                })
+                .collect::<Vec<_>>();
+            (ids, metrics.filter_mode.unwrap())
+        };
+        let auto = |filter: &str| {
+            table
+                .search(vec![0.0, 0.0])
+                .filter(Some(filter.to_string()))
+                .filter_mode(FilterMode::Auto)
+                .prefilter_threshold(0.1)
+        };
+        // Below the threshold the matching rows are searched.
+        assert_eq!(
+            search(auto("id >= 910")).await,
+            (vec![910, 911, 912, 913, 914], FilterMode::Prefilter)
+        );
+        // Above it the nearest neighbors, none of them matching, are filtered.
+        assert_eq!(
+            search(auto("id >= 890")).await,
+            (vec![], FilterMode::Postfilter)
+        );
+        assert_eq!(
+            search(auto("id % 20 = 0")).await,
+            (vec![0, 20, 40, 60, 80], FilterMode::Prefilter)
+        );
+        // A mode set on the query is kept.
+        let forced = auto("id >= 890").filter_mode(FilterMode::Prefilter);
+        assert_eq!(search(forced).await.1, FilterMode::Prefilter);
+        let forced = auto("id >= 910").filter_mode(FilterMode::Postfilter);
+        assert_eq!(search(forced).await, (vec![], FilterMode::Postfilter));
+        let no_filter = table.search(vec![0.0, 0.0]).execute_with_metrics().await;
+        assert_eq!(no_filter.unwrap().1.filter_mode, None);
+    }
+}
@@ -0,0 +1,235 @@
+// Copyright 2023 LanceDB Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The truncated vector columns of [NativeTable::add_truncated_vector_column],
+//! searched by [crate::Query::two_stage].
+//!
+//! Embeddings trained as Matryoshka representations keep most of their meaning
+//! in their first dimensions, so a few of them, normalized, rank the rows
+//! nearly as the full vectors do, at a fraction of the cost.
+
+use std::sync::Arc;
+
+use arrow_array::cast::as_primitive_array;
+use arrow_array::types::Float32Type;
+use arrow_array::{Array, ArrayRef, BooleanArray, FixedSizeListArray, Float32Array, RecordBatch};
+use arrow_schema::{DataType, Field, Schema as ArrowSchema};
+use datafusion::arrow::compute::cast;
+use lance::arrow::FixedSizeListArrayExt;
+
+use super::NativeTable;
+use crate::error::{Error, Result};
+use crate::io::bad_vectors::{mark_null_vectors, marker_column_of, null_vector_field};
+
+/// The first `dims` values of `vector`, scaled to a unit L2 norm. A vector of
+/// zeros stays one.
+pub(crate) fn truncate_vector(vector: &[f32], dims: usize) -> Vec<f32> {
+    let prefix = &vector[..dims.min(vector.len())];
+    let norm = prefix.iter().map(|v| v * v).sum::<f32>().sqrt();
+    if norm == 0.0 {
+        return prefix.to_vec();
+    }
+    prefix.iter().map(|v| v / norm).collect()
+}
+
+/// The vectors of `vectors` truncated to `dims` by [truncate_vector], with
+/// vectors of zeros for null vectors, as lance stores them.
+fn truncate_vectors(vectors: &FixedSizeListArray, dims: usize) -> Result<FixedSizeListArray> {
+    let values = cast(vectors.values(), &DataType::Float32).map_err(lance::Error::from)?;
+    let values = as_primitive_array::<Float32Type>(values.as_ref()).values();
+    let width = vectors.value_length() as usize;
+    let offset = vectors.value_offset(0) as usize;
+    let mut truncated = Vec::with_capacity(vectors.len() * dims);
+    for row in 0..vectors.len() {
+        if vectors.is_null(row) {
+            truncated.extend(std::iter::repeat_n(0.0, dims));
+            continue;
+        }
+        let start = offset + row * width;
+        truncated.extend(truncate_vector(&values[start..start + width], dims));
+    }
+    let values = Float32Array::from(truncated);
+    Ok(FixedSizeListArray::try_new(values, dims as i32)?)
+}
+
+impl NativeTable {
+    /// Add the vector column `dst` of the first `dims` values of the vectors
+    /// of the column `src`, normalized to a unit L2 norm, to search it before
+    /// `src` with [crate::Query::two_stage].
+    ///
+    /// The column is computed from the rows of the table when it is added, the
+    /// rows added later need a value for it, and the data files and indices of
+    /// the other columns are kept. Fails with an [Error::InvalidInput] if `src`
+    /// is not a vector column of more than `dims` dimensions, or if the table
+    /// already has a column `dst`.
+    pub async fn add_truncated_vector_column(
+        &self,
+        src: &str,
+        dst: &str,
+        dims: usize,
+    ) -> Result<()> {
+        let invalid = |message: String| Error::InvalidInput { message };
+        self.dataset
+            .write(self.max_commit_retries, |latest| async move {
+                let schema = ArrowSchema::from(latest.schema());
+                let width = match schema.field_with_name(src).map(|f| f.data_type()) {
+                    Ok(DataType::FixedSizeList(item, width)) if item.data_type().is_floating() => {
+                        *width as usize
+                    }
+                    Ok(_) => {
+                        return Err(invalid(format!(
+                            "the column '{src}' is not a vector column"
+                        )))
+                    }
+                    Err(_) => return Err(invalid(format!("the table has no column '{src}'"))),
+                };
+                if dims == 0 || dims >= width {
+                    return Err(invalid(format!(
+                        "cannot truncate the vectors of '{src}' of {width} dimensions to {dims}"
+                    )));
+                }
+                if schema.field_with_name(dst).is_ok() {
+                    return Err(invalid(format!("the table already has a column '{dst}'")));
+                }
+                let item = Arc::new(Field::new("item", DataType::Float32, true));
+                let field = Field::new(dst, DataType::FixedSizeList(item, dims as i32), true);
+                // The null vectors of `src` are null in `dst` too.
+                let marker = marker_column_of(&schema, src);
+                let mut fields = vec![field];
+                fields.extend(marker.as_ref().map(|_| null_vector_field(dst)));
+                let columns = Arc::new(ArrowSchema::new(fields));
+                let read = std::iter::once(src)
+                    .chain(marker.as_deref())
+                    .collect::<Vec<_>>();
+
+                let mut fragments = Vec::new();
+                for fragment in latest.get_fragments() {
+                    let mut updater = fragment.updater(Some(&read)).await?;
+                    while let Some(batch) = updater.next().await? {
+                        let batch =
+                            mark_null_vectors(batch, src, false).map_err(lance::Error::from)?;
+                        let vectors = batch[src]
+                            .as_any()
+                            .downcast_ref::<FixedSizeListArray>()
+                            .unwrap();
+                        let mut arrays: Vec<ArrayRef> =
+                            vec![Arc::new(truncate_vectors(vectors, dims)?)];
+                        if marker.is_some() {
+                            let marks = (0..vectors.len()).map(|row| Some(vectors.is_null(row)));
+                            arrays.push(Arc::new(marks.collect::<BooleanArray>()));
+                        }
+                        let batch = RecordBatch::try_new(columns.clone(), arrays)
+                            .map_err(lance::Error::from)?;
+                        updater.update(batch).await?;
+                    }
+                    fragments.push(updater.finish().await?);
+                }
+                let schema = latest.schema().merge(columns.as_ref())?;
+                let indices = latest.load_indices().await?;
+                self.dataset
+                    .commit_manifest(&latest, indices, |manifest| {
+                        manifest.schema = schema;
+                        manifest.fragments = Arc::new(fragments);
+                    })
+                    .await
+            })
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow_array::Int32Array;
+    use futures::TryStreamExt;
+    use lance::arrow::RecordBatchBuffer;
+    use lance::dataset::Dataset;
+    use rand::Rng;
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::arrow::RecordBatchBuilder;
+
+    #[test]
+    fn test_truncate_vector() {
+        assert_eq!(truncate_vector(&[3.0, 4.0, 12.0], 2), vec![0.6, 0.8]);
+        assert_eq!(truncate_vector(&[0.0, 0.0, 1.0], 2), vec![0.0, 0.0]);
+    }
+
+    #[tokio::test]
+    async fn test_add_truncated_vector_column() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        let mut rng = rand::thread_rng();
+        let vectors = (0..100)
+            .map(|_| {
+                (0..16)
+                    .map(|_| rng.gen_range(-1.0..1.0))
+                    .collect::<Vec<f32>>()
+            })
+            .collect::<Vec<_>>();
+        let batch = RecordBatchBuilder::new()
+            .column("id", Arc::new(Int32Array::from_iter_values(0..100)))
+            .vector_column("vector", 16, vectors.clone())
+            .build()
+            .unwrap();
+        let reader = Box::new(RecordBatchBuffer::new(vec![batch]));
+        let table = NativeTable::create(uri, "test", reader, None)
+            .await
+            .unwrap();
+        table.delete("id = 3").await.unwrap();
+        table
+            .add_truncated_vector_column("vector", "fast", 4)
+            .await
+            .unwrap();
+
+        let dataset = Dataset::open(table.uri()).await.unwrap();
+        let mut scanner = dataset.scan();
+        scanner.project(&["id", "fast"]).unwrap();
+        let batches = scanner.try_into_stream().await.unwrap();
+        let batches = batches.try_collect::<Vec<_>>().await.unwrap();
+        let mut rows = 0;
+        for batch in &batches {
+            let ids = batch["id"].as_any().downcast_ref::<Int32Array>().unwrap();
+            let fast = batch["fast"]
+                .as_any()
+                .downcast_ref::<FixedSizeListArray>()
+                .unwrap();
+            assert_eq!(fast.value_length(), 4);
+            for row in 0..batch.num_rows() {
+                let id = ids.value(row) as usize;
+                assert_ne!(id, 3);
+                let value = fast.value(row);
+                let value = as_primitive_array::<Float32Type>(value.as_ref());
+                assert_eq!(value.values().to_vec(), truncate_vector(&vectors[id], 4));
+                rows += 1;
+            }
+        }
+        assert_eq!(rows, 99);
+
+        for (src, dst, dims) in [
+            ("vector", "fast", 2),
+            ("vector", "other", 16),
+            ("vector", "other", 0),
+            ("id", "other", 1),
+            ("missing", "other", 1),
+        ] {
+            let err = table
+                .add_truncated_vector_column(src, dst, dims)
+                .await
+                .unwrap_err();
+            assert!(matches!(err, Error::InvalidInput { .. }), "{err}");
+        }
+    }
+}
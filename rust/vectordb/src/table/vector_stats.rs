@@ -0,0 +1,375 @@
+// Copyright 2023 LanceDB Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Statistics of the vectors of a column: their norms, the bad vectors, and
+//! how far the rows appended since the column was indexed are from the
+//! partitions of its IVF index.
+
+use std::collections::HashSet;
+
+use arrow_array::{Array, FixedSizeListArray, Float32Array, RecordBatch};
+use arrow_schema::{DataType, Schema as ArrowSchema};
+use datafusion::arrow::compute::cast;
+use futures::TryStreamExt;
+use lance::dataset::Dataset;
+
+use super::NativeTable;
+use crate::error::{Error, Result};
+use crate::io::bad_vectors::{mark_null_vectors, marker_column_of};
+use crate::query::flat::is_multivector;
+
+/// The rows read by [NativeTable::vector_stats] when no sample size is given.
+pub const DEFAULT_VECTOR_STATS_SAMPLE: usize = 100_000;
+
+/// Statistics of the vectors of a column, see [NativeTable::vector_stats].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct VectorStats {
+    /// The rows read, all rows of the table or a sample of them.
+    pub rows_sampled: usize,
+    /// The smallest L2 norm of the vectors that are neither null, all zeros nor
+    /// NaN. `None` if there are none.
+    pub min_norm: Option<f32>,
+    /// The mean L2 norm of the same vectors.
+    pub mean_norm: Option<f32>,
+    /// The largest L2 norm of the same vectors.
+    pub max_norm: Option<f32>,
+    /// The fraction of the rows read whose vector is null or all zeros.
+    pub zero_fraction: f64,
+    /// The fraction of the rows read whose vector has a NaN.
+    pub nan_fraction: f64,
+    /// The mean L2 distance of the vectors indexed by the IVF index of the
+    /// column to their nearest partition centroid. `None` if the column has no
+    /// IVF index.
+    pub indexed_centroid_distance: Option<f32>,
+    /// The same distance for the vectors appended since the index was built,
+    /// `None` if there are none. Much larger than the distance of the indexed
+    /// vectors, the data drifted from the partitions and searches of the
+    /// index are likely to lose recall.
+    pub unindexed_centroid_distance: Option<f32>,
+    /// Whether the norms of the vectors are stored, see
+    /// [NativeTable::store_vector_norms].
+    pub norms_materialized: bool,
+}
+
+/// The partition centroids of an IVF index, and the fragments it covers.
+struct Centroids {
+    dims: usize,
+    values: Vec<f32>,
+    fragments: HashSet<usize>,
+}
+
+impl Centroids {
+    /// The L2 distance of `vector` to its nearest centroid.
+    fn distance(&self, vector: &[f32]) -> f32 {
+        self.values
+            .chunks_exact(self.dims)
+            .map(|centroid| {
+                centroid
+                    .iter()
+                    .zip(vector)
+                    .map(|(c, v)| (c - v) * (c - v))
+                    .sum::<f32>()
+            })
+            .fold(f32::INFINITY, f32::min)
+            .sqrt()
+    }
+}
+
+/// The sums the statistics of the rows read are computed from.
+#[derive(Debug, Default)]
+struct StatsAccumulator {
+    rows: usize,
+    zeros: usize,
+    nans: usize,
+    norms: (usize, f64),
+    min_norm: Option<f32>,
+    max_norm: Option<f32>,
+    /// The count and sum of the centroid distances of indexed and unindexed vectors.
+    distances: [(usize, f64); 2],
+}
+
+impl StatsAccumulator {
+    /// Add the vectors of `column` in `batch`, from a fragment the index of
+    /// `centroids` covers if `indexed`.
+    fn add(
+        &mut self,
+        batch: &RecordBatch,
+        column: &str,
+        centroids: Option<&Centroids>,
+        indexed: bool,
+    ) -> Result<()> {
+        let batch = mark_null_vectors(batch, column, false).map_err(lance::Error::from)?;
+        let vectors = batch
+            .column(0)
+            .as_any()
+            .downcast_ref::<FixedSizeListArray>()
+            .expect("vector columns are checked to be fixed size lists");
+        let dims = vectors.value_length() as usize;
+        let values = cast(vectors.values(), &DataType::Float32).map_err(lance::Error::from)?;
+        let values = values.as_any().downcast_ref::<Float32Array>().unwrap();
+        for row in 0..vectors.len() {
+            self.rows += 1;
+            let start = vectors.value_offset(row) as usize;
+            let vector = &values.values()[start..start + dims];
+            if vectors.is_null(row) || vector.iter().all(|v| *v == 0.0) {
+                self.zeros += 1;
+                continue;
+            }
+            if vector.iter().any(|v| v.is_nan()) {
+                self.nans += 1;
+                continue;
+            }
+            let norm = vector.iter().map(|v| v * v).sum::<f32>().sqrt();
+            self.norms.0 += 1;
+            self.norms.1 += norm as f64;
+            self.min_norm = Some(self.min_norm.map_or(norm, |m| m.min(norm)));
+            self.max_norm = Some(self.max_norm.map_or(norm, |m| m.max(norm)));
+            if let Some(centroids) = centroids.filter(|c| c.dims == dims) {
+                let distances = &mut self.distances[usize::from(!indexed)];
+                distances.0 += 1;
+                distances.1 += centroids.distance(vector) as f64;
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(self, has_centroids: bool) -> VectorStats {
+        let fraction = |count: usize| match self.rows {
+            0 => 0.0,
+            rows => count as f64 / rows as f64,
+        };
+        let mean = |(count, sum): (usize, f64)| (count > 0).then(|| (sum / count as f64) as f32);
+        let distance = |i: usize| has_centroids.then(|| mean(self.distances[i])).flatten();
+        VectorStats {
+            rows_sampled: self.rows,
+            min_norm: self.min_norm,
+            mean_norm: mean(self.norms),
+            max_norm: self.max_norm,
+            zero_fraction: fraction(self.zeros),
+            nan_fraction: fraction(self.nans),
+            indexed_centroid_distance: distance(0),
+            unindexed_centroid_distance: distance(1),
+            norms_materialized: false,
+        }
+    }
+}
+
+/// The evenly spaced offsets of the rows sampled from a fragment of `length`
+/// rows, for a sample of `sample_size` of the `total` rows of a table.
+pub(super) fn sample_offsets(length: usize, total: usize, sample_size: usize) -> Vec<u32> {
+    let rows = (length * sample_size).div_ceil(total);
+    (0..rows).map(|i| (i * length / rows) as u32).collect()
+}
+
+impl NativeTable {
+    /// The centroids of the latest IVF index of `column` in `dataset`.
+    ///
+    /// Indices whose vectors are transformed before partitioning, such as
+    /// OPQ, have centroids in another space and are left out.
+    async fn ivf_centroids(&self, dataset: &Dataset, column: &str) -> Result<Option<Centroids>> {
+        let Some(field_id) = dataset.schema().field(column).map(|f| f.id) else {
+            return Ok(None);
+        };
+        let indices = dataset.load_indices().await?;
+        let Some(index) = indices
+            .iter()
+            .filter(|i| i.fields.contains(&field_id))
+            .max_by_key(|i| i.dataset_version)
+        else {
+            return Ok(None);
+        };
+        let Some(ivf) = self.dataset.read_ivf_index(index).await? else {
+            return Ok(None);
+        };
+
+        let indexed = dataset.checkout_version(index.dataset_version).await?;
+        Ok(Some(Centroids {
+            dims: ivf.dims,
+            values: ivf.ivf.centroids,
+            fragments: indexed.get_fragments().iter().map(|f| f.id()).collect(),
+        }))
+    }
+
+    /// Statistics of the vectors of `column` in the current version of this
+    /// table: their norms, the fraction of bad vectors, and how far they are
+    /// from the partitions of the IVF index of the column.
+    ///
+    /// Tables of more than `sample_size` rows, [DEFAULT_VECTOR_STATS_SAMPLE] if
+    /// `None`, are sampled: evenly spaced rows are read from each fragment, in
+    /// proportion to its rows. The rows of a sample may include deleted ones.
+    pub async fn vector_stats(
+        &self,
+        column: &str,
+        sample_size: Option<usize>,
+    ) -> Result<VectorStats> {
+        let dataset = self.dataset.get().await?;
+        let mut columns = vec![column.to_string()];
+        columns.extend(marker_column_of(
+            &ArrowSchema::from(dataset.schema()),
+            column,
+        ));
+        let schema = dataset.schema().project(&columns)?;
+        let field = arrow_schema::Field::from(&schema.fields[0]);
+        if is_multivector(&field) || !matches!(field.data_type(), DataType::FixedSizeList(..)) {
+            return Err(Error::InvalidInput {
+                message: format!(
+                    "vector_stats reads columns of one vector per row, '{column}' is {}",
+                    field.data_type()
+                ),
+            });
+        }
+        let centroids = self.ivf_centroids(&dataset, column).await?;
+
+        let fragments = dataset.get_fragments();
+        let mut lengths = Vec::with_capacity(fragments.len());
+        for fragment in &fragments {
+            lengths.push(fragment.fragment_length().await?);
+        }
+        let total = lengths.iter().sum::<usize>();
+        let sample_size = sample_size.unwrap_or(DEFAULT_VECTOR_STATS_SAMPLE).max(1);
+        let mut stats = StatsAccumulator::default();
+        for (fragment, length) in fragments.iter().zip(lengths) {
+            let indexed = centroids
+                .as_ref()
+                .is_some_and(|c| c.fragments.contains(&fragment.id()));
+            if total <= sample_size {
+                let mut scanner = fragment.scan();
+                scanner.project(&columns)?;
+                let mut stream = scanner.try_into_stream().await?;
+                while let Some(batch) = stream.try_next().await? {
+                    stats.add(&batch, column, centroids.as_ref(), indexed)?;
+                }
+                continue;
+            }
+            let offsets = sample_offsets(length, total, sample_size);
+            let batch = fragment.take(&offsets, &schema).await?;
+            stats.add(&batch, column, centroids.as_ref(), indexed)?;
+        }
+        Ok(VectorStats {
+            norms_materialized: self.dataset.stored_norms(&dataset, column).await?.is_some(),
+            ..stats.finish(centroids.is_some())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_array::RecordBatchReader;
+    use lance::arrow::RecordBatchBuffer;
+    use lance::index::vector::ivf::IvfBuildParams;
+    use lance::index::vector::pq::PQBuildParams;
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::arrow::{vec_to_fixed_size_list, RecordBatchBuilder};
+    use crate::index::vector::IvfPQIndexBuilder;
+
+    fn vectors(vectors: Vec<[f32; 3]>) -> Box<dyn RecordBatchReader> {
+        let batch = RecordBatchBuilder::new()
+            .vector_column("vector", 3, vectors)
+            .build()
+            .unwrap();
+        Box::new(RecordBatchBuffer::new(vec![batch]))
+    }
+
+    #[tokio::test]
+    async fn test_vector_stats() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        // Norms of 5 and 10, then 10 zero and 5 NaN vectors.
+        let mut rows = (0..100)
+            .map(|i| [3.0, 4.0, 0.0].map(|v| v * (1 + i % 2) as f32))
+            .collect::<Vec<_>>();
+        rows.extend([[0.0; 3]; 10]);
+        rows.extend([[f32::NAN, 1.0, 1.0]; 5]);
+        let mut reader = vectors(rows);
+        Dataset::write(&mut reader, &format!("{uri}/test.lance"), None)
+            .await
+            .unwrap();
+        let table = NativeTable::open(uri, "test").await.unwrap();
+
+        let expected = VectorStats {
+            rows_sampled: 115,
+            min_norm: Some(5.0),
+            mean_norm: Some(7.5),
+            max_norm: Some(10.0),
+            zero_fraction: 10.0 / 115.0,
+            nan_fraction: 5.0 / 115.0,
+            indexed_centroid_distance: None,
+            unindexed_centroid_distance: None,
+            norms_materialized: false,
+        };
+        assert_eq!(table.vector_stats("vector", None).await.unwrap(), expected);
+
+        // Every fifth row.
+        let sampled = table.vector_stats("vector", Some(23)).await.unwrap();
+        let expected = VectorStats {
+            rows_sampled: 23,
+            zero_fraction: 2.0 / 23.0,
+            nan_fraction: 1.0 / 23.0,
+            ..expected
+        };
+        assert_eq!(sampled, expected);
+    }
+
+    #[tokio::test]
+    async fn test_centroid_drift() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        // Two clusters, indexed with a partition each. The centroids are given,
+        // the trained ones could both fall in one cluster.
+        let clusters = (0..512)
+            .map(|i| {
+                let center = if i % 2 == 0 { 1.0 } else { 10.0 };
+                let jitter = (i % 7) as f32 * 0.01;
+                [center + jitter, center, center - jitter]
+            })
+            .collect::<Vec<_>>();
+        let table = NativeTable::create(uri, "test", vectors(clusters), None)
+            .await
+            .unwrap();
+        let centroids = vec_to_fixed_size_list(3, [[1.0; 3], [10.0; 3]]).unwrap();
+        let ivf_params = IvfBuildParams::try_with_centroids(2, Arc::new(centroids)).unwrap();
+        let builder = IvfPQIndexBuilder::new()
+            .column("vector")
+            .ivf_params(ivf_params)
+            .pq_params(PQBuildParams {
+                num_sub_vectors: 1,
+                ..Default::default()
+            });
+        table.create_index(&builder).await.unwrap();
+
+        let stats = table.vector_stats("vector", None).await.unwrap();
+        assert!(stats.indexed_centroid_distance.unwrap() < 0.1, "{stats:?}");
+        assert_eq!(stats.unindexed_centroid_distance, None);
+
+        // Rows between the clusters, far from both centroids.
+        table
+            .add(vectors(vec![[5.5, 5.5, 5.5]; 64]), None)
+            .await
+            .unwrap();
+        let stats = table.vector_stats("vector", None).await.unwrap();
+        assert!(stats.indexed_centroid_distance.unwrap() < 0.1, "{stats:?}");
+        let drift = stats.unindexed_centroid_distance.unwrap();
+        assert!((drift - 4.5 * 3f32.sqrt()).abs() < 0.1, "{stats:?}");
+
+        let err = table.vector_stats("missing", None).await.unwrap_err();
+        assert!(
+            matches!(err, Error::Lance { .. } | Error::Schema { .. }),
+            "{err}"
+        );
+    }
+}
@@ -0,0 +1,308 @@
+// Copyright 2023 LanceDB Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The writes of the tables with the object store of their storage options.
+//!
+//! lance's [Dataset::write] and [Dataset::commit] open the object store of a
+//! dataset from its URI, configured from the environment only. The tables
+//! whose read params have an object store wrapper, those of a connection
+//! with storage options or a mirror, see
+//! [crate::database::ConnectBuilder::storage_option], write their data files
+//! and manifests through the wrapped store instead, the way lance writes
+//! them. The other tables are written by lance.
+
+use std::sync::Arc;
+
+use arrow_array::{RecordBatch, RecordBatchReader};
+use lance::dataset::{Dataset, ReadParams, WriteMode, WriteParams};
+use lance::datatypes::Schema;
+use lance::format::{Fragment, Index, Manifest};
+use lance::io::object_store::{ObjectStore, ObjectStoreParams};
+use lance::io::{write_manifest, FileWriter};
+use object_store::path::Path;
+use uuid::Uuid;
+
+use super::indices::{LATEST_MANIFEST, VERSIONS_DIR};
+use super::maintenance;
+
+/// The directory of the data files of a dataset.
+pub(super) const DATA_DIR: &str = "data";
+
+/// The feature flag of the manifests with fragments that have deletion files,
+/// as lance sets it.
+const FLAG_DELETION_FILES: u64 = 1;
+
+/// The object store params of `read_params`, if they wrap the store lance
+/// builds from the URI.
+fn configured(read_params: &ReadParams) -> Option<&ObjectStoreParams> {
+    read_params
+        .store_options
+        .as_ref()
+        .filter(|params| params.object_store_wrapper.is_some())
+}
+
+/// Write `reader` to the dataset at `uri` as [Dataset::write] does, through
+/// the object store of `read_params`.
+///
+/// The written dataset is opened with `read_params`.
+pub(crate) async fn write_dataset(
+    reader: &mut Box<dyn RecordBatchReader>,
+    uri: &str,
+    params: Option<WriteParams>,
+    read_params: &ReadParams,
+) -> lance::Result<Dataset> {
+    let Some(store_params) = configured(read_params) else {
+        return Dataset::write(reader, uri, params).await;
+    };
+    let (store, base) = ObjectStore::from_uri_and_params(uri, store_params.clone()).await?;
+    let mut params = params.unwrap_or_default();
+    let exists = store.exists(&base.child(LATEST_MANIFEST)).await?;
+    let mut batches = reader.peekable();
+    let schema = match batches.peek() {
+        Some(Ok(batch)) => Schema::try_from(batch.schema().as_ref())?,
+        Some(Err(_)) => {
+            let e = batches.next().unwrap().unwrap_err();
+            return Err(lance::Error::from(e));
+        }
+        None => return Err(lance::Error::EmptyDataset),
+    };
+    if exists && matches!(params.mode, WriteMode::Create) {
+        return Err(lance::Error::DatasetAlreadyExists {
+            uri: uri.to_string(),
+        });
+    }
+    if !exists {
+        params.mode = WriteMode::Create;
+    }
+    let current = match params.mode {
+        WriteMode::Create => None,
+        _ => Some(Dataset::open_with_params(uri, read_params).await?),
+    };
+    let append = matches!(params.mode, WriteMode::Append);
+    let mut fragments = Vec::new();
+    if let (Some(current), true) = (current.as_ref(), append) {
+        if current.schema() != &schema {
+            return Err(lance::Error::SchemaMismatch {
+                original: current.schema().clone(),
+                new: schema,
+            });
+        }
+        fragments = maintenance::fragments(current);
+    }
+    let mut next_id = fragments.iter().map(|f| f.id + 1).max().unwrap_or(0);
+    let mut rows = Vec::new();
+    let mut file_rows = 0;
+    for batch in batches {
+        let batch = batch?;
+        file_rows += batch.num_rows();
+        rows.push(batch);
+        if file_rows >= params.max_rows_per_file {
+            let batches = std::mem::take(&mut rows);
+            fragments
+                .push(write_fragment(&store, &base, next_id, &schema, batches, &params).await?);
+            next_id += 1;
+            file_rows = 0;
+        }
+    }
+    if !rows.is_empty() {
+        fragments.push(write_fragment(&store, &base, next_id, &schema, rows, &params).await?);
+    }
+    let mut manifest = Manifest::new(&schema, Arc::new(fragments));
+    manifest.version = current.as_ref().map_or(1, |d| d.version().version + 1);
+    let indices = match (current.as_ref(), append) {
+        (Some(current), true) => Some(current.load_indices().await?),
+        _ => None,
+    };
+    write_manifests(&store, &base, &mut manifest, indices).await?;
+    checkout(uri, manifest.version, read_params).await
+}
+
+/// Commit `fragments` as a new version of the dataset at `uri` as
+/// [Dataset::commit] does, through the object store of `read_params`.
+///
+/// The committed dataset is opened with `read_params`.
+pub(crate) async fn commit_fragments(
+    uri: &str,
+    read_params: &ReadParams,
+    schema: &Schema,
+    fragments: &[Fragment],
+    mode: WriteMode,
+) -> lance::Result<Dataset> {
+    let Some(store_params) = configured(read_params) else {
+        return Dataset::commit(uri, schema, fragments, mode).await;
+    };
+    let (store, base) = ObjectStore::from_uri_and_params(uri, store_params.clone()).await?;
+    let mut version = 1;
+    let mut indices = Vec::new();
+    let schema = if store.exists(&base.child(LATEST_MANIFEST)).await? {
+        let current = Dataset::open_with_params(uri, read_params).await?;
+        version = current.version().version + 1;
+        if matches!(mode, WriteMode::Append) {
+            indices = current.load_indices().await?;
+        }
+        let added = schema.exclude(current.schema())?;
+        current.schema().merge(&added)?
+    } else {
+        schema.clone()
+    };
+    let mut manifest = Manifest::new(&schema, Arc::new(fragments.to_vec()));
+    manifest.version = version;
+    write_manifests(&store, &base, &mut manifest, Some(indices)).await?;
+    checkout(uri, version, read_params).await
+}
+
+/// The version `version` of the dataset at `uri`, opened with `read_params`.
+///
+/// lance checks a version out through the store of the URI, without the
+/// store options of the params, so the latest version is opened first.
+async fn checkout(uri: &str, version: u64, read_params: &ReadParams) -> lance::Result<Dataset> {
+    Dataset::open_with_params(uri, read_params)
+        .await?
+        .checkout_version(version)
+        .await
+}
+
+/// Write `batches` to a new data file of the dataset at `base`, as the
+/// fragment `id`, in row groups of `max_rows_per_group` rows.
+pub(super) async fn write_fragment(
+    store: &ObjectStore,
+    base: &Path,
+    id: u64,
+    schema: &Schema,
+    batches: Vec<RecordBatch>,
+    params: &WriteParams,
+) -> lance::Result<Fragment> {
+    let filename = format!("{}.lance", Uuid::new_v4());
+    let path = base.child(DATA_DIR).child(filename.as_str());
+    let mut writer = FileWriter::try_new(store, &path, schema.clone()).await?;
+    let mut row_group = Vec::new();
+    let mut rows = 0;
+    for batch in batches {
+        rows += batch.num_rows();
+        row_group.push(batch);
+        if rows >= params.max_rows_per_group {
+            writer.write(&row_group).await?;
+            row_group.clear();
+            rows = 0;
+        }
+    }
+    if !row_group.is_empty() {
+        writer.write(&row_group).await?;
+    }
+    writer.finish().await?;
+    Ok(Fragment::with_file(id, &filename, schema))
+}
+
+/// Write `manifest` as the manifest of its version and the latest one of the
+/// dataset at `base`, with the feature flags and the timestamp lance sets.
+pub(super) async fn write_manifests(
+    store: &ObjectStore,
+    base: &Path,
+    manifest: &mut Manifest,
+    indices: Option<Vec<Index>>,
+) -> lance::Result<()> {
+    let deletions = manifest.fragments.iter().any(|f| f.deletion_file.is_some());
+    let flags = if deletions { FLAG_DELETION_FILES } else { 0 };
+    manifest.reader_feature_flags = flags;
+    manifest.writer_feature_flags = flags;
+    manifest.set_timestamp(None);
+    for path in [
+        base.child(VERSIONS_DIR)
+            .child(format!("{}.manifest", manifest.version)),
+        base.child(LATEST_MANIFEST),
+    ] {
+        let mut writer = store.create(&path).await?;
+        let position = write_manifest(&mut writer, manifest, indices.clone()).await?;
+        writer.write_magics(position).await?;
+        writer.shutdown().await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow_array::{ArrayRef, Int32Array, RecordBatchIterator};
+    use arrow_schema::{DataType, Field, Schema as ArrowSchema};
+    use object_store::memory::InMemory;
+    use object_store::ObjectStore as OSObjectStore;
+
+    use super::*;
+    use crate::io::object_store::ConfiguredObjectStore;
+
+    fn rows(ids: std::ops::Range<i32>) -> Box<dyn RecordBatchReader> {
+        let schema = Arc::new(ArrowSchema::new(vec![Field::new(
+            "id",
+            DataType::Int32,
+            false,
+        )]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![Arc::new(Int32Array::from_iter_values(ids)) as ArrayRef],
+        );
+        Box::new(RecordBatchIterator::new(vec![batch], schema))
+    }
+
+    #[tokio::test]
+    async fn test_write_through_configured_store() {
+        // lance opens a new, empty store for every `memory://` URI, so the
+        // dataset is only found again through the mock store.
+        let store = Arc::new(InMemory::new());
+        let read_params = ReadParams {
+            store_options: Some(ObjectStoreParams {
+                object_store_wrapper: Some(Arc::new(ConfiguredObjectStore::new(store.clone()))),
+            }),
+            ..Default::default()
+        };
+        let uri = "memory://db/table";
+        let dataset = write_dataset(&mut rows(0..10), uri, None, &read_params)
+            .await
+            .unwrap();
+        assert_eq!(dataset.version().version, 1);
+        assert!(matches!(
+            write_dataset(&mut rows(0..10), uri, None, &read_params).await,
+            Err(lance::Error::DatasetAlreadyExists { .. })
+        ));
+
+        let append = WriteParams {
+            mode: WriteMode::Append,
+            ..Default::default()
+        };
+        let dataset = write_dataset(&mut rows(10..25), uri, Some(append), &read_params)
+            .await
+            .unwrap();
+        assert_eq!(dataset.version().version, 2);
+        assert_eq!(dataset.count_rows().await.unwrap(), 25);
+        let files = store
+            .list_with_delimiter(Some(&Path::from("table/data")))
+            .await;
+        assert_eq!(files.unwrap().objects.len(), 2);
+
+        let fragments = maintenance::fragments(&dataset);
+        let dataset = commit_fragments(
+            uri,
+            &read_params,
+            dataset.schema(),
+            &fragments[..1],
+            WriteMode::Append,
+        )
+        .await
+        .unwrap();
+        assert_eq!(dataset.version().version, 3);
+        assert_eq!(dataset.count_rows().await.unwrap(), 10);
+
+        let reopened = Dataset::open_with_params(uri, &read_params).await.unwrap();
+        assert_eq!(reopened.version().version, 3);
+        assert!(Dataset::open(uri).await.is_err());
+    }
+}
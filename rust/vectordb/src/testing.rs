@@ -0,0 +1,398 @@
+// Copyright 2023 Lance Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Tables of generated rows for tests, with the `test-utils` feature.
+//!
+//! A [TestTableBuilder] generates the same rows for the same seed: an
+//! [ID_COLUMN] of the row numbers, a vector column of random values between
+//! 0 and 1, and the nullable and categorical columns asked for, written to as
+//! many fragments as asked for. [ground_truth] finds the exact nearest
+//! neighbors of a query vector in the rows, to check the recall of the index
+//! searches of the table.
+//!
+//! ```
+//! # use vectordb::testing::TestTableBuilder;
+//! let builder = TestTableBuilder::new(100)
+//!     .dims(4)
+//!     .fragments(2)
+//!     .categorical_column("color", 3)
+//!     .seed(7);
+//! let batches = builder.batches().unwrap();
+//! assert_eq!(batches.len(), 2);
+//! assert_eq!(batches, builder.batches().unwrap());
+//! ```
+
+use std::sync::Arc;
+
+use arrow_array::{
+    Array, FixedSizeListArray, Float32Array, Int32Array, RecordBatch, RecordBatchReader,
+    StringArray,
+};
+use lance::arrow::RecordBatchBuffer;
+use lance::index::vector::MetricType;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::arrow::RecordBatchBuilder;
+use crate::error::{Error, Result};
+use crate::io::bad_vectors::mark_null_vectors;
+use crate::table::{NativeTable, VECTOR_COLUMN_NAME};
+
+/// The column of the row numbers of a generated table, from 0.
+pub const ID_COLUMN: &str = "id";
+
+/// The dimension of the vectors of a [TestTableBuilder] by default.
+pub const DEFAULT_TEST_DIMS: usize = 16;
+
+/// Generates the rows of a test table, see the [module docs](self).
+#[derive(Debug, Clone)]
+pub struct TestTableBuilder {
+    rows: usize,
+    dims: usize,
+    fragments: usize,
+    vector_column: String,
+    nullable_columns: Vec<(String, f64)>,
+    categorical_columns: Vec<(String, usize)>,
+    seed: u64,
+}
+
+impl TestTableBuilder {
+    /// Generate `rows` rows, of [DEFAULT_TEST_DIMS] vectors in a fragment.
+    pub fn new(rows: usize) -> Self {
+        Self {
+            rows,
+            dims: DEFAULT_TEST_DIMS,
+            fragments: 1,
+            vector_column: VECTOR_COLUMN_NAME.to_string(),
+            nullable_columns: Vec::new(),
+            categorical_columns: Vec::new(),
+            seed: 0,
+        }
+    }
+
+    /// Set the dimension of the vectors.
+    pub fn dims(mut self, dims: usize) -> Self {
+        self.dims = dims;
+        self
+    }
+
+    /// Set the number of fragments the rows are written to, each of about as
+    /// many rows and of consecutive ids.
+    pub fn fragments(mut self, fragments: usize) -> Self {
+        self.fragments = fragments;
+        self
+    }
+
+    /// Set the name of the vector column, `vector` by default.
+    pub fn vector_column(mut self, name: &str) -> Self {
+        self.vector_column = name.to_string();
+        self
+    }
+
+    /// Add the `Float32` column `name` of random values between 0 and 1, null
+    /// in about `null_fraction` of the rows.
+    pub fn nullable_column(mut self, name: &str, null_fraction: f64) -> Self {
+        self.nullable_columns
+            .push((name.to_string(), null_fraction));
+        self
+    }
+
+    /// Add the string column `name` of `cardinality` values, `{name}-0`,
+    /// `{name}-1`..., each row of one of them at random.
+    pub fn categorical_column(mut self, name: &str, cardinality: usize) -> Self {
+        self.categorical_columns
+            .push((name.to_string(), cardinality));
+        self
+    }
+
+    /// Set the seed of the random values, 0 by default.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// The rows, a batch for each fragment.
+    ///
+    /// Fails with an [Error::InvalidInput] without a row or a dimension, with
+    /// more fragments than rows, or with a null fraction that is not between
+    /// 0 and 1 or a categorical column without values.
+    pub fn batches(&self) -> Result<Vec<RecordBatch>> {
+        let invalid = |message: String| Err(Error::InvalidInput { message });
+        if self.rows == 0 || self.dims == 0 {
+            return invalid("a test table needs at least a row and a dimension".to_string());
+        }
+        if self.fragments == 0 || self.fragments > self.rows {
+            return invalid(format!(
+                "cannot write {} rows to {} fragments",
+                self.rows, self.fragments
+            ));
+        }
+        if let Some((name, _)) = self
+            .nullable_columns
+            .iter()
+            .find(|(_, fraction)| !(0.0..=1.0).contains(fraction))
+        {
+            return invalid(format!(
+                "the null fraction of '{name}' is not between 0 and 1"
+            ));
+        }
+        if let Some((name, _)) = self.categorical_columns.iter().find(|(_, n)| *n == 0) {
+            return invalid(format!("the categorical column '{name}' has no values"));
+        }
+
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        let mut batches = Vec::with_capacity(self.fragments);
+        let mut start = 0;
+        for fragment in 0..self.fragments {
+            let rows =
+                self.rows / self.fragments + usize::from(fragment < self.rows % self.fragments);
+            let ids = start as i32..(start + rows) as i32;
+            start += rows;
+            let vectors = (0..rows)
+                .map(|_| (0..self.dims).map(|_| rng.gen()).collect::<Vec<f32>>())
+                .collect::<Vec<_>>();
+            let mut builder = RecordBatchBuilder::new()
+                .column(ID_COLUMN, Arc::new(Int32Array::from_iter_values(ids)))
+                .vector_column(&self.vector_column, self.dims as i32, vectors);
+            for (name, null_fraction) in &self.nullable_columns {
+                let values = (0..rows)
+                    .map(|_| (!rng.gen_bool(*null_fraction)).then(|| rng.gen::<f32>()))
+                    .collect::<Float32Array>();
+                builder = builder.column(name, Arc::new(values));
+            }
+            for (name, cardinality) in &self.categorical_columns {
+                let values = (0..rows)
+                    .map(|_| format!("{name}-{}", rng.gen_range(0..*cardinality)))
+                    .collect::<Vec<_>>();
+                builder = builder.column(name, Arc::new(StringArray::from(values)));
+            }
+            batches.push(builder.build()?);
+        }
+        Ok(batches)
+    }
+
+    /// A random query vector of the dimension of the table, the same for the
+    /// same seed and `n`.
+    pub fn query_vector(&self, n: u64) -> Vec<f32> {
+        // Seeded apart from the rows, so that it is not one of their vectors.
+        let seed = self.seed ^ (n + 1).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+        let mut rng = StdRng::seed_from_u64(seed);
+        (0..self.dims).map(|_| rng.gen()).collect()
+    }
+
+    /// A reader of [Self::batches], written to a table at once.
+    pub fn reader(&self) -> Result<Box<dyn RecordBatchReader + Send>> {
+        Ok(Box::new(RecordBatchBuffer::new(self.batches()?)))
+    }
+
+    /// Create the table `name` in the database at `uri`, writing each
+    /// fragment of [Self::batches] on its own.
+    pub async fn create(&self, uri: &str, name: &str) -> Result<NativeTable> {
+        let mut batches = self.batches()?.into_iter();
+        let reader = |batch: RecordBatch| -> Box<dyn RecordBatchReader> {
+            Box::new(RecordBatchBuffer::new(vec![batch]))
+        };
+        let first = batches.next().expect("a test table has a fragment");
+        let table = NativeTable::create(uri, name, reader(first), None).await?;
+        for batch in batches {
+            table.add(reader(batch), None).await?;
+        }
+        Ok(table)
+    }
+
+    /// The ids of the `k` rows nearest to `query` by `metric`, see
+    /// [ground_truth].
+    pub fn ground_truth(&self, query: &[f32], k: usize, metric: MetricType) -> Result<Vec<i32>> {
+        ground_truth(&self.batches()?, &self.vector_column, query, k, metric)
+    }
+}
+
+/// The [ID_COLUMN] of the `k` rows of `batches` nearest to `query` by
+/// `metric`, nearest first, computed exactly as by a flat search. The rows of
+/// null vectors are left out, and rows at the same distance are in the order
+/// of `batches`.
+pub fn ground_truth(
+    batches: &[RecordBatch],
+    column: &str,
+    query: &[f32],
+    k: usize,
+    metric: MetricType,
+) -> Result<Vec<i32>> {
+    let distance = metric.batch_func();
+    let mut rows = Vec::new();
+    for batch in batches {
+        let batch = &mark_null_vectors(batch, column, false).map_err(lance::Error::from)?;
+        let ids = id_column(batch)?;
+        let vectors = batch
+            .column_by_name(column)
+            .and_then(|c| c.as_any().downcast_ref::<FixedSizeListArray>())
+            .ok_or_else(|| Error::InvalidInput {
+                message: format!("the column '{column}' is not a vector column"),
+            })?;
+        if vectors.value_length() as usize != query.len() {
+            return Err(Error::EmbeddingDimensionMismatch {
+                column: column.to_string(),
+                expected: vectors.value_length() as usize,
+                got: query.len(),
+            });
+        }
+        let values = vectors
+            .values()
+            .as_any()
+            .downcast_ref::<Float32Array>()
+            .ok_or_else(|| Error::InvalidInput {
+                message: format!("the column '{column}' is not of float32 vectors"),
+            })?;
+        let distances = distance(query, values.values(), query.len());
+        rows.extend(
+            (0..batch.num_rows())
+                .filter(|row| vectors.is_valid(*row))
+                .map(|row| (distances.value(row), ids.value(row))),
+        );
+    }
+    rows.sort_by(|a, b| a.0.total_cmp(&b.0));
+    Ok(rows.into_iter().take(k).map(|(_, id)| id).collect())
+}
+
+/// The [ID_COLUMN] of the results `batches` of a search, in their order.
+pub fn result_ids(batches: &[RecordBatch]) -> Result<Vec<i32>> {
+    let mut ids = Vec::new();
+    for batch in batches {
+        ids.extend(id_column(batch)?.values().iter().copied());
+    }
+    Ok(ids)
+}
+
+/// The fraction of the ids `expected` in `found`, 1 if none is expected.
+pub fn recall(expected: &[i32], found: &[i32]) -> f64 {
+    if expected.is_empty() {
+        return 1.0;
+    }
+    let found = expected.iter().filter(|id| found.contains(id)).count();
+    found as f64 / expected.len() as f64
+}
+
+fn id_column(batch: &RecordBatch) -> Result<&Int32Array> {
+    batch
+        .column_by_name(ID_COLUMN)
+        .and_then(|c| c.as_any().downcast_ref::<Int32Array>())
+        .ok_or_else(|| Error::InvalidInput {
+            message: format!("the batch has no int32 column '{ID_COLUMN}'"),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use futures::TryStreamExt;
+    use lance::index::vector::ivf::IvfBuildParams;
+    use lance::index::vector::pq::PQBuildParams;
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::index::vector::IvfPQIndexBuilder;
+
+    #[test]
+    fn test_batches() {
+        let builder = TestTableBuilder::new(1000)
+            .dims(8)
+            .fragments(3)
+            .nullable_column("score", 0.25)
+            .categorical_column("color", 5)
+            .seed(42);
+        let batches = builder.batches().unwrap();
+        assert_eq!(
+            batches
+                .iter()
+                .map(RecordBatch::num_rows)
+                .collect::<Vec<_>>(),
+            vec![334, 333, 333]
+        );
+        assert_eq!(result_ids(&batches).unwrap(), (0..1000).collect::<Vec<_>>());
+        assert_eq!(batches, builder.batches().unwrap());
+        assert_ne!(batches, builder.clone().seed(43).batches().unwrap());
+
+        let nulls = batches
+            .iter()
+            .map(|b| b["score"].null_count())
+            .sum::<usize>();
+        assert!((150..350).contains(&nulls), "{nulls}");
+        let colors = batches
+            .iter()
+            .flat_map(|b| {
+                let colors = b["color"].as_any().downcast_ref::<StringArray>().unwrap();
+                colors
+                    .iter()
+                    .flatten()
+                    .map(str::to_string)
+                    .collect::<Vec<_>>()
+            })
+            .collect::<HashSet<_>>();
+        assert_eq!(colors.len(), 5);
+        assert!(colors.contains("color-4"));
+
+        assert_eq!(builder.query_vector(0), builder.query_vector(0));
+        assert_ne!(builder.query_vector(0), builder.query_vector(1));
+        for invalid in [
+            TestTableBuilder::new(0),
+            TestTableBuilder::new(2).fragments(3),
+            TestTableBuilder::new(2).nullable_column("score", 1.5),
+            TestTableBuilder::new(2).categorical_column("color", 0),
+        ] {
+            let err = invalid.batches().unwrap_err();
+            assert!(matches!(err, Error::InvalidInput { .. }), "{err}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_recall() {
+        let tmp_dir = tempdir().unwrap();
+        let uri = tmp_dir.path().to_str().unwrap();
+        let builder = TestTableBuilder::new(2000).fragments(4);
+        let table = builder.create(uri, "test").await.unwrap();
+        assert_eq!(table.count_rows().await.unwrap(), 2000);
+        assert_eq!(table.list_fragments().await.unwrap().len(), 4);
+
+        let query = builder.query_vector(0);
+        let expected = builder.ground_truth(&query, 10, MetricType::L2).unwrap();
+        let search = || table.search(query.clone()).limit(10);
+        let batches: Vec<RecordBatch> = search()
+            .execute()
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(result_ids(&batches).unwrap(), expected);
+
+        let index = IvfPQIndexBuilder::new()
+            .ivf_params(IvfBuildParams::new(4))
+            .pq_params(PQBuildParams {
+                num_sub_vectors: 4,
+                ..Default::default()
+            });
+        table.create_index(&index).await.unwrap();
+        let (batches, _) = search()
+            .use_index(true)
+            .nprobes(4)
+            .refine_factor(Some(20))
+            .execute_with_metrics()
+            .await
+            .unwrap();
+        let found = result_ids(&batches).unwrap();
+        assert!(recall(&expected, &found) >= 0.8, "{expected:?} {found:?}");
+        assert_eq!(recall(&[], &found), 1.0);
+    }
+}